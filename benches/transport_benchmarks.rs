@@ -1,116 +1,28 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId, Throughput};
-use fleetlink_transport::{FleetMsgHeader, MessageType};
+use criterion::{black_box, criterion_group, BatchSize, Criterion, BenchmarkId, Throughput};
+use fleetlink_transport::{Delivery, Extensions, FleetMessage, FleetMsgHeader, HeaderChecksum, MessageType, MulticastSender, PeerTable, SequenceAnalyzer};
+use fleetlink_transport::protocol::fragment::{Fragment, Reassembler};
+use fleetlink_transport::alloc_counter;
+#[cfg(feature = "c-reference")]
+use fleetlink_transport::c_reference;
 use zerocopy::{AsBytes, FromBytes};
-use std::time::{Duration, Instant};
-
-// Simulate C-style message handling (inefficient)
-#[derive(Debug, Clone)]
-struct CStyleMessage {
-    magic: u32,
-    version: u8,
-    msg_type: u8,
-    sequence: u16,
-    timestamp: u64,
-    sender_id: u32,
-    payload_len: u16,
-    checksum: u16,
-    payload: Vec<u8>,
-}
+use fleetlink_transport::bench_history::{self, BenchRecord};
+use fleetlink_transport::ReceiverConfig;
+#[cfg(all(target_os = "linux", feature = "af-xdp"))]
+use fleetlink_transport::net::xdp::{XdpConfig, XdpReceiver};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
 
-impl CStyleMessage {
-    fn new(msg_type: u8, sender_id: u32, sequence: u16, payload: Vec<u8>) -> Self {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-        
-        let mut msg = Self {
-            magic: 0xFEED,
-            version: 1,
-            msg_type,
-            sequence,
-            timestamp,
-            sender_id,
-            payload_len: payload.len() as u16,
-            checksum: 0,
-            payload,
-        };
-        
-        // Inefficient checksum calculation
-        msg.checksum = msg.calculate_checksum();
-        msg
-    }
-    
-    fn calculate_checksum(&self) -> u16 {
-        let mut sum = 0u32;
-        sum += self.magic;
-        sum += self.version as u32;
-        sum += self.msg_type as u32;
-        sum += self.sequence as u32;
-        sum += (self.timestamp & 0xFFFFFFFF) as u32;
-        sum += (self.timestamp >> 32) as u32;
-        sum += self.sender_id;
-        sum += self.payload_len as u32;
-        
-        for &byte in &self.payload {
-            sum += byte as u32;
-        }
-        
-        (sum & 0xFFFF) as u16
-    }
-    
-    // Simulate C-style serialization (lots of copying)
-    fn serialize(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        buffer.extend_from_slice(&self.magic.to_le_bytes());
-        buffer.push(self.version);
-        buffer.push(self.msg_type);
-        buffer.extend_from_slice(&self.sequence.to_le_bytes());
-        buffer.extend_from_slice(&self.timestamp.to_le_bytes());
-        buffer.extend_from_slice(&self.sender_id.to_le_bytes());
-        buffer.extend_from_slice(&self.payload_len.to_le_bytes());
-        buffer.extend_from_slice(&self.checksum.to_le_bytes());
-        buffer.extend_from_slice(&self.payload);
-        buffer
-    }
-    
-    // Simulate C-style deserialization (lots of copying)
-    fn deserialize(data: &[u8]) -> Option<Self> {
-        if data.len() < 20 {
-            return None;
-        }
-        
-        let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-        let version = data[4];
-        let msg_type = data[5];
-        let sequence = u16::from_le_bytes([data[6], data[7]]);
-        let timestamp = u64::from_le_bytes([
-            data[8], data[9], data[10], data[11],
-            data[12], data[13], data[14], data[15]
-        ]);
-        let sender_id = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
-        let payload_len = u16::from_le_bytes([data[20], data[21]]);
-        let checksum = u16::from_le_bytes([data[22], data[23]]);
-        
-        if data.len() < 24 + payload_len as usize {
-            return None;
-        }
-        
-        let payload = data[24..24 + payload_len as usize].to_vec();
-        
-        Some(Self {
-            magic, version, msg_type, sequence, timestamp,
-            sender_id, payload_len, checksum, payload
-        })
-    }
-}
+#[cfg(feature = "alloc-count")]
+#[global_allocator]
+static ALLOC: alloc_counter::CountingAllocator = alloc_counter::CountingAllocator;
+
+// The `c_reference` cases run the C codec in c/ and need `--features c-reference`
 
 fn bench_message_creation(c: &mut Criterion) {
     let mut group = c.benchmark_group("message_creation");
     
     for payload_size in [0, 64, 256, 1024].iter() {
-        let payload = vec![0u8; *payload_size];
-        
         group.throughput(Throughput::Bytes(*payload_size as u64));
         
         // Rust zero-copy approach
@@ -131,20 +43,19 @@ fn bench_message_creation(c: &mut Criterion) {
             },
         );
         
-        // C-style approach
+        #[cfg(feature = "c-reference")]
         group.bench_with_input(
-            BenchmarkId::new("c_style", payload_size),
+            BenchmarkId::new("c_reference", payload_size),
             payload_size,
             |b, &size| {
-                let payload = vec![0u8; size];
                 b.iter(|| {
-                    let msg = CStyleMessage::new(
-                        2, // Data type
+                    let header = c_reference::header(
+                        MessageType::Data,
                         black_box(12345),
                         black_box(100),
-                        payload.clone()
+                        size as u16
                     );
-                    black_box(msg);
+                    black_box(header);
                 });
             },
         );
@@ -157,8 +68,6 @@ fn bench_serialization(c: &mut Criterion) {
     let mut group = c.benchmark_group("serialization");
     
     for payload_size in [0, 64, 256, 1024].iter() {
-        let payload = vec![0u8; *payload_size];
-        
         group.throughput(Throughput::Bytes(*payload_size as u64 + 24)); // header + payload
         
         // Rust zero-copy approach
@@ -177,18 +86,35 @@ fn bench_serialization(c: &mut Criterion) {
                 });
             },
         );
+
+        // The owned message type, into a reused buffer like the C codec
+        group.bench_with_input(
+            BenchmarkId::new("fleet_message", payload_size),
+            payload_size,
+            |b, &size| {
+                let message = FleetMessage::data(12345, 100, vec![0u8; size]);
+                let mut buffer = vec![0u8; 24 + size];
+
+                b.iter(|| {
+                    let len = message.serialize_into(&mut buffer).unwrap();
+                    black_box((len, &buffer));
+                });
+            },
+        );
         
-        // C-style approach
+        // C encodes into a caller-provided buffer rather than allocating one
+        #[cfg(feature = "c-reference")]
         group.bench_with_input(
-            BenchmarkId::new("c_style", payload_size),
+            BenchmarkId::new("c_reference", payload_size),
             payload_size,
             |b, &size| {
                 let payload = vec![0u8; size];
-                let msg = CStyleMessage::new(2, 12345, 100, payload);
-                
+                let header = c_reference::header(MessageType::Data, 12345, 100, payload.len() as u16);
+                let mut buffer = vec![0u8; 24 + size];
+
                 b.iter(|| {
-                    let serialized = msg.serialize();
-                    black_box(serialized);
+                    let len = c_reference::encode(&header, &payload, &mut buffer);
+                    black_box((len, &buffer));
                 });
             },
         );
@@ -206,21 +132,17 @@ fn bench_deserialization(c: &mut Criterion) {
         group.throughput(Throughput::Bytes(*payload_size as u64 + 24));
         
         // Prepare test data
-        let header = FleetMsgHeader::new(MessageType::Data, 12345, 100, payload.len() as u16);
-        let mut rust_data = Vec::new();
-        rust_data.extend_from_slice(header.as_bytes());
-        rust_data.extend_from_slice(&payload);
-        
-        let c_msg = CStyleMessage::new(2, 12345, 100, payload.clone());
-        let c_data = c_msg.serialize();
+        let rust_data = FleetMessage::data(12345, 100, payload.clone()).to_bytes();
         
-        // Rust zero-copy approach
+        // Rust zero-copy approach; both sides check magic, version and checksum
         group.bench_with_input(
             BenchmarkId::new("rust_zerocopy", payload_size),
             payload_size,
             |b, _| {
                 b.iter(|| {
-                    if let Some(header) = FleetMsgHeader::read_from_prefix(&rust_data) {
+                    if let Some(header) = FleetMsgHeader::read_from_prefix(&rust_data)
+                        && header.is_valid()
+                    {
                         let header_size = std::mem::size_of::<FleetMsgHeader>();
                         let payload = &rust_data[header_size..];
                         black_box((header, payload));
@@ -228,15 +150,25 @@ fn bench_deserialization(c: &mut Criterion) {
                 });
             },
         );
-        
-        // C-style approach
+
+        // Also checks the payload length, and copies the payload out
         group.bench_with_input(
-            BenchmarkId::new("c_style", payload_size),
+            BenchmarkId::new("fleet_message", payload_size),
+            payload_size,
+            |b, _| {
+                b.iter(|| black_box(FleetMessage::parse(&rust_data)));
+            },
+        );
+
+        // The same bytes: the C codec reads the same wire format
+        #[cfg(feature = "c-reference")]
+        group.bench_with_input(
+            BenchmarkId::new("c_reference", payload_size),
             payload_size,
             |b, _| {
                 b.iter(|| {
-                    if let Some(msg) = CStyleMessage::deserialize(&c_data) {
-                        black_box(msg);
+                    if let Some(decoded) = c_reference::decode(black_box(&rust_data)) {
+                        black_box(decoded);
                     }
                 });
             },
@@ -262,10 +194,10 @@ fn bench_throughput(c: &mut Criterion) {
                 message.extend_from_slice(header.as_bytes());
                 
                 // Simulate processing
-                if let Some(parsed) = FleetMsgHeader::read_from_prefix(&message) {
-                    if parsed.is_valid() {
-                        total_processed += 1;
-                    }
+                if let Some(parsed) = FleetMsgHeader::read_from_prefix(&message)
+                    && parsed.is_valid()
+                {
+                    total_processed += 1;
                 }
             }
             
@@ -273,23 +205,23 @@ fn bench_throughput(c: &mut Criterion) {
         });
     });
     
-    group.bench_function("c_style_message_processing", |b| {
+    #[cfg(feature = "c-reference")]
+    group.bench_function("c_reference_message_processing", |b| {
+        let mut buffer = [0u8; 24];
         b.iter(|| {
             let mut total_processed = 0;
             let start = Instant::now();
-            
+
             while start.elapsed() < Duration::from_millis(10) {
-                let msg = CStyleMessage::new(1, 12345, total_processed, vec![]);
-                let serialized = msg.serialize();
-                
+                let header = c_reference::header(MessageType::Heartbeat, 12345, total_processed, 0);
+                let len = c_reference::encode(&header, &[], &mut buffer).unwrap_or(0);
+
                 // Simulate processing
-                if let Some(parsed) = CStyleMessage::deserialize(&serialized) {
-                    if parsed.magic == 0xFEED {
-                        total_processed += 1;
-                    }
+                if c_reference::decode(&buffer[..len]).is_some() {
+                    total_processed += 1;
                 }
             }
-            
+
             black_box(total_processed);
         });
     });
@@ -297,11 +229,353 @@ fn bench_throughput(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_header_validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header_validation");
+
+    let valid = FleetMsgHeader::new(MessageType::Data, 12345, 100, 64);
+    let mut bad_magic = valid;
+    bad_magic.magic = 0xBEEF.into();
+    let mut bad_checksum = valid;
+    bad_checksum.checksum = bad_checksum.checksum.get().wrapping_add(1).into();
+
+    for (name, header) in [("valid", valid), ("bad_magic", bad_magic), ("bad_checksum", bad_checksum)] {
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(black_box(&header).is_valid()));
+        });
+    }
+
+    // Everything the receive loop does before handing a datagram to the handler
+    for payload_size in [0, 64, 256, 1024].iter() {
+        let header = FleetMsgHeader::new(MessageType::Data, 12345, 100, *payload_size as u16);
+        let mut datagram = header.as_bytes().to_vec();
+        datagram.extend_from_slice(&vec![0u8; *payload_size]);
+
+        group.throughput(Throughput::Bytes(datagram.len() as u64));
+        group.bench_with_input(BenchmarkId::new("receive_path", payload_size), &datagram, |b, datagram| {
+            b.iter(|| {
+                let header_size = std::mem::size_of::<FleetMsgHeader>();
+                let accepted = FleetMsgHeader::read_from_prefix(black_box(datagram.as_slice()))
+                    .filter(|header| header.is_valid())
+                    .is_some_and(|header| datagram.len() - header_size == header.payload_len.get() as usize);
+                black_box(accepted);
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_checksum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checksum");
+
+    for payload_size in [0, 64, 256, 1024].iter() {
+        group.throughput(Throughput::Bytes(*payload_size as u64 + 24));
+
+        // The header checksum only covers the header, so its cost doesn't grow with the payload
+        group.bench_with_input(
+            BenchmarkId::new("rust_header_sum", payload_size),
+            payload_size,
+            |b, &size| {
+                b.iter(|| black_box(FleetMsgHeader::new(MessageType::Data, 12345, black_box(100), size as u16)));
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("rust_header_crc32c", payload_size),
+            payload_size,
+            |b, &size| {
+                let header = FleetMsgHeader::new(MessageType::Data, 12345, 100, size as u16);
+                b.iter(|| black_box(black_box(header).with_checksum(HeaderChecksum::Crc32c)));
+            },
+        );
+
+        #[cfg(feature = "c-reference")]
+        group.bench_with_input(
+            BenchmarkId::new("c_reference_header_sum", payload_size),
+            payload_size,
+            |b, &size| {
+                b.iter(|| black_box(c_reference::header(MessageType::Data, 12345, black_box(100), size as u16)));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_peer_tracking(c: &mut Criterion) {
+    let mut group = c.benchmark_group("peer_tracking");
+
+    // Per-sender sequence lookup done for every received message
+    for peer_count in [1u32, 16, 256].iter() {
+        let mut table = PeerTable::new();
+        let now = Instant::now();
+        let addr: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let headers: Vec<FleetMsgHeader> = (0..*peer_count)
+            .map(|id| FleetMsgHeader::new(MessageType::Data, id, 0, 0))
+            .collect();
+        for header in &headers {
+            table.observe(header, addr, now);
+        }
+
+        group.bench_with_input(BenchmarkId::new("observe_known", peer_count), &headers, |b, headers| {
+            let mut next = 0;
+            b.iter(|| {
+                let header = &headers[next % headers.len()];
+                next += 1;
+                black_box(table.observe(black_box(header), addr, now));
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_duplicate_suppression(c: &mut Criterion) {
+    let mut group = c.benchmark_group("duplicate_suppression");
+
+    // The check a SequenceTracker makes (under its lock) for every received
+    // message: a repeat of one already seen, and the next one in order
+    let mut analyzer = SequenceAnalyzer::new();
+    let seen = FleetMsgHeader::new(MessageType::Data, 7, 0, 0);
+    analyzer.observe(&seen, &[]);
+    group.bench_function("hit", |b| {
+        b.iter(|| black_box(analyzer.observe(black_box(&seen), &[])));
+    });
+
+    let mut analyzer = SequenceAnalyzer::new();
+    let headers: Vec<FleetMsgHeader> = (0..=u16::MAX).map(|sequence| FleetMsgHeader::new(MessageType::Data, 7, sequence, 0)).collect();
+    group.bench_function("miss", |b| {
+        let mut next = 0;
+        b.iter(|| {
+            let header = &headers[next % headers.len()];
+            next += 1;
+            black_box(analyzer.observe(black_box(header), &[]))
+        });
+    });
+
+    group.finish();
+}
+
+/// A message split into fragments of `chunk_len` bytes, as the receiver
+/// hands them to its reassembler
+fn fragment_deliveries(payload: &[u8], chunk_len: usize) -> Vec<Delivery> {
+    let count = payload.len().div_ceil(chunk_len) as u16;
+    payload.chunks(chunk_len).enumerate()
+        .map(|(index, chunk)| {
+            let mut extensions = Extensions::new();
+            extensions.set_fragment(Fragment { id: 100, index: index as u16, count });
+            Delivery {
+                header: FleetMsgHeader::new(MessageType::Data, 7, 100 + index as u16, chunk.len() as u16),
+                extensions,
+                payload: chunk.to_vec(),
+                addr: "10.0.0.1:5000".parse().unwrap(),
+                issues: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+fn bench_fragmentation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fragmentation");
+    let payload = vec![0x5Au8; 8 * 1024];
+
+    // An 8 KiB message in six fragments, as they arrive and back to front
+    let in_order = fragment_deliveries(&payload, 1400);
+    let out_of_order: Vec<Delivery> = in_order.iter().rev().cloned().collect();
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+    for (name, fragments) in [("reassemble_in_order", &in_order), ("reassemble_out_of_order", &out_of_order)] {
+        group.bench_function(name, |b| {
+            let mut reassembler = Reassembler::new();
+            let now = Instant::now();
+            b.iter_batched(
+                || fragments.clone(),
+                |fragments| {
+                    for fragment in fragments {
+                        black_box(reassembler.push(fragment, now));
+                    }
+                },
+                BatchSize::SmallInput
+            );
+        });
+    }
+
+    // The send path with and without the split, over loopback. Every send
+    // logs a line, so this group runs fewer samples.
+    group.sample_size(20);
+    let mut sender = async_std::task::block_on(MulticastSender::new(Ipv4Addr::new(239, 255, 0, 1), 12347, 7)).unwrap();
+    for (name, len) in [("send_whole", 1024), ("send_split", payload.len())] {
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| async_std::task::block_on(sender.send_message(MessageType::Data, black_box(&payload[..len]))).unwrap());
+        });
+    }
+
+    group.finish();
+}
+
+/// Datagrams sent, then received, per iteration of `bench_receive_path`
+const RECEIVE_BATCH: usize = 32;
+
+// The same traffic received through the socket path and, with `--features af-xdp`
+// on Linux, through AF_XDP. Datagrams are sent from FLEETLINK_XDP_PEER, an address
+// on the far end of the link (e.g. the other end of a veth pair), to the interface
+// FLEETLINK_XDP_INTERFACE, which has c/fleetlink_xdp.c attached. Skipped unless
+// both are set; AF_XDP needs CAP_NET_RAW and CAP_BPF.
+fn bench_receive_path(c: &mut Criterion) {
+    let (Ok(interface), Ok(peer)) = (std::env::var("FLEETLINK_XDP_INTERFACE"), std::env::var("FLEETLINK_XDP_PEER")) else {
+        return;
+    };
+    let peer: Ipv4Addr = peer.parse().expect("FLEETLINK_XDP_PEER is an IPv4 address");
+    let local = fleetlink_transport::net::interfaces::select(Some(&interface)).expect("FLEETLINK_XDP_INTERFACE is an interface").ipv4[0];
+    let fleet_group = Ipv4Addr::new(239, 255, 0, 1);
+    let source = UdpSocket::bind((peer, 0)).unwrap();
+    socket2::SockRef::from(&source).set_multicast_if_v4(&peer).unwrap();
+    // Only the copy that crossed the link should be received
+    source.set_multicast_loop_v4(false).unwrap();
+    let header = FleetMsgHeader::new(MessageType::Data, 7, 0, 32);
+    let datagram = [header.as_bytes(), &[0u8; 32]].concat();
+    let config = ReceiverConfig::new();
+
+    let mut group = c.benchmark_group("receive_path");
+    group.throughput(Throughput::Elements(RECEIVE_BATCH as u64));
+
+    // The XDP program only redirects its own port, so the socket path gets the next one
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 12346)).unwrap();
+    socket.join_multicast_v4(&fleet_group, &local).unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+    group.bench_function("udp_socket", |b| {
+        let mut buf = [0u8; 1500];
+        b.iter(|| {
+            for _ in 0..RECEIVE_BATCH {
+                source.send_to(&datagram, (fleet_group, 12346)).unwrap();
+            }
+            // A lost datagram ends the batch at the timeout rather than hanging
+            for _ in 0..RECEIVE_BATCH {
+                let Ok((len, addr)) = socket.recv_from(&mut buf) else { break };
+                black_box(config.parse(&buf[..len], addr).is_ok());
+            }
+        });
+    });
+
+    #[cfg(all(target_os = "linux", feature = "af-xdp"))]
+    {
+        let mut receiver = XdpReceiver::bind(&XdpConfig::new(&interface), 12345, config.clone()).expect("AF_XDP socket");
+        group.bench_function("af_xdp", |b| {
+            b.iter(|| {
+                for _ in 0..RECEIVE_BATCH {
+                    source.send_to(&datagram, (fleet_group, 12345)).unwrap();
+                }
+                let mut taken = 0;
+                while taken < RECEIVE_BATCH {
+                    match receiver.poll(Duration::from_millis(10), &mut |delivery| {
+                        black_box(delivery);
+                    }) {
+                        Ok(0) | Err(_) => break,
+                        Ok(frames) => taken += frames as usize,
+                    }
+                }
+            });
+        });
+        match receiver.kernel_drops() {
+            Ok(drops) if drops > 0 => eprintln!("AF_XDP: the kernel dropped {} frames", drops),
+            _ => {}
+        }
+    }
+
+    group.finish();
+}
+
+// Criterion only times things, so allocations per operation are reported alongside
+fn report_allocations(_c: &mut Criterion) {
+    if !cfg!(feature = "alloc-count") {
+        return;
+    }
+    const OPS: u64 = 1000;
+
+    println!("allocations per create+serialize+parse (measured):");
+    for payload_size in [0, 64, 256, 1024] {
+        let payload = vec![0u8; payload_size];
+
+        let ((), rust) = alloc_counter::measure(|| {
+            for i in 0..OPS {
+                let header = FleetMsgHeader::new(MessageType::Data, 12345, i as u16, payload.len() as u16);
+                let mut message = Vec::new();
+                message.extend_from_slice(header.as_bytes());
+                message.extend_from_slice(&payload);
+                black_box(FleetMsgHeader::read_from_prefix(&message));
+            }
+        });
+        let (rust_allocs, rust_bytes) = rust.per_op(OPS);
+        print!("  {:>5}B  rust_zerocopy {:.1} ({:.0} B)", payload_size, rust_allocs, rust_bytes);
+
+        // malloc isn't counted, but the C codec doesn't call it: the buffer is the caller's
+        #[cfg(feature = "c-reference")]
+        {
+            let mut buffer = vec![0u8; 24 + payload_size];
+            let ((), c) = alloc_counter::measure(|| {
+                for i in 0..OPS {
+                    let header = c_reference::header(MessageType::Data, 12345, i as u16, payload.len() as u16);
+                    let len = c_reference::encode(&header, &payload, &mut buffer).unwrap_or(0);
+                    black_box(c_reference::decode(&buffer[..len]));
+                }
+            });
+            let (c_allocs, c_bytes) = c.per_op(OPS);
+            print!("  c_reference {:.1} ({:.0} B)", c_allocs, c_bytes);
+        }
+        println!();
+    }
+}
+
 criterion_group!(
     benches,
+    report_allocations,
     bench_message_creation,
     bench_serialization,
     bench_deserialization,
-    bench_throughput
+    bench_throughput,
+    bench_header_validation,
+    bench_checksum,
+    bench_peer_tracking,
+    bench_fragmentation,
+    bench_duplicate_suppression,
+    bench_receive_path
 );
-criterion_main!(benches);
+
+/// Where criterion writes its results
+fn criterion_dir() -> PathBuf {
+    if let Some(home) = std::env::var_os("CRITERION_HOME") {
+        return home.into();
+    }
+    let target = std::env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into());
+    PathBuf::from(target).join("criterion")
+}
+
+// `criterion_main!`, plus appending this run's means to the history file named by
+// FLEETLINK_BENCH_HISTORY (criterion owns the command line)
+fn main() {
+    let started = SystemTime::now();
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+
+    let Some(path) = std::env::var_os("FLEETLINK_BENCH_HISTORY") else { return };
+    let means = match bench_history::criterion_means(criterion_dir(), started) {
+        Ok(means) if !means.is_empty() => means,
+        Ok(_) => {
+            eprintln!("No benchmarks were measured; nothing appended to history");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Could not read criterion results: {}", e);
+            return;
+        }
+    };
+
+    let mut record = BenchRecord::new("criterion");
+    for (id, mean) in means {
+        record = record.with_metric(format!("{}_ns", id), mean);
+    }
+    match bench_history::append(&path, &record) {
+        Ok(()) => println!("Appended {} results to history {}", record.metrics.len(), path.to_string_lossy()),
+        Err(e) => eprintln!("Could not append to history {}: {}", path.to_string_lossy(), e),
+    }
+}
@@ -0,0 +1,58 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "grpc")]
+    compile_admin_service();
+
+    #[cfg(feature = "c-reference")]
+    compile_c_reference();
+}
+
+/// Build the reference C codec the benchmarks compare against. `cc` takes the
+/// optimization level from the cargo profile, so both sides are built alike.
+#[cfg(feature = "c-reference")]
+fn compile_c_reference() {
+    println!("cargo:rerun-if-changed=c/fleetlink.c");
+    println!("cargo:rerun-if-changed=c/fleetlink.h");
+
+    cc::Build::new()
+        .file("c/fleetlink.c")
+        .include("c")
+        .std("c99")
+        .warnings_into_errors(true)
+        .compile("fleetlink");
+}
+
+/// Generate the admin gRPC server from Rust definitions (see proto/admin.proto),
+/// so building with the `grpc` feature doesn't require protoc.
+#[cfg(feature = "grpc")]
+fn compile_admin_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let methods = [
+        ("list_peers", "ListPeers", "Empty", "PeerList"),
+        ("get_stats", "GetStats", "Empty", "Stats"),
+        ("get_rate_limits", "GetRateLimits", "Empty", "RateLimits"),
+        ("set_rate_limit", "SetRateLimit", "SetRateLimitRequest", "RateLimits"),
+        ("ping", "Ping", "PingRequest", "Ack"),
+        ("start_capture", "StartCapture", "CaptureRequest", "Ack"),
+        ("stop_capture", "StopCapture", "Empty", "Ack"),
+    ];
+
+    let service = methods.iter().fold(
+        Service::builder().name("Admin").package("fleetlink.admin.v1"),
+        |service, (name, route, input, output)| {
+            service.method(
+                Method::builder()
+                    .name(*name)
+                    .route_name(*route)
+                    .input_type(format!("super::{}", input))
+                    .output_type(format!("super::{}", output))
+                    .codec_path("tonic_prost::ProstCodec")
+                    .build(),
+            )
+        },
+    );
+
+    Builder::new().build_client(false).compile(&[service.build()]);
+}
@@ -0,0 +1,123 @@
+/* clock_gettime */
+#define _POSIX_C_SOURCE 199309L
+
+#include "fleetlink.h"
+
+#include <string.h>
+#include <time.h>
+
+static void put_u16(uint8_t *p, uint16_t v)
+{
+    p[0] = (uint8_t)v;
+    p[1] = (uint8_t)(v >> 8);
+}
+
+static void put_u32(uint8_t *p, uint32_t v)
+{
+    put_u16(p, (uint16_t)v);
+    put_u16(p + 2, (uint16_t)(v >> 16));
+}
+
+static void put_u64(uint8_t *p, uint64_t v)
+{
+    put_u32(p, (uint32_t)v);
+    put_u32(p + 4, (uint32_t)(v >> 32));
+}
+
+static uint16_t get_u16(const uint8_t *p)
+{
+    return (uint16_t)(p[0] | (p[1] << 8));
+}
+
+static uint32_t get_u32(const uint8_t *p)
+{
+    return (uint32_t)get_u16(p) | ((uint32_t)get_u16(p + 2) << 16);
+}
+
+static uint64_t get_u64(const uint8_t *p)
+{
+    return (uint64_t)get_u32(p) | ((uint64_t)get_u32(p + 4) << 32);
+}
+
+/* Everything but the payload; the checksum field is written as-is */
+static void write_header(const struct fl_header *h, uint8_t *out)
+{
+    put_u32(out, h->magic);
+    out[4] = h->version;
+    out[5] = h->msg_type;
+    put_u16(out + 6, h->sequence);
+    put_u64(out + 8, h->timestamp_us);
+    put_u32(out + 16, h->sender_id);
+    put_u16(out + 20, h->payload_len);
+    put_u16(out + 22, h->checksum);
+}
+
+static uint16_t sum_bytes(const uint8_t *bytes, size_t len)
+{
+    uint32_t sum = 0;
+    for (size_t i = 0; i < len; i++)
+        sum += bytes[i];
+    return (uint16_t)sum;
+}
+
+uint16_t fl_checksum(const struct fl_header *header)
+{
+    uint8_t wire[FL_HEADER_LEN];
+    write_header(header, wire);
+    return sum_bytes(wire, FL_HEADER_LEN - 2);
+}
+
+void fl_header_init(struct fl_header *header, uint8_t msg_type, uint32_t sender_id,
+                    uint16_t sequence, uint16_t payload_len)
+{
+    struct timespec now;
+    clock_gettime(CLOCK_REALTIME, &now);
+
+    header->magic = FL_MAGIC;
+    header->version = FL_VERSION;
+    header->msg_type = msg_type;
+    header->sequence = sequence;
+    header->timestamp_us = (uint64_t)now.tv_sec * 1000000u + (uint64_t)now.tv_nsec / 1000u;
+    header->sender_id = sender_id;
+    header->payload_len = payload_len;
+    header->checksum = fl_checksum(header);
+}
+
+size_t fl_encode(const struct fl_header *header, const uint8_t *payload, uint8_t *out, size_t cap)
+{
+    size_t len = FL_HEADER_LEN + header->payload_len;
+    if (cap < len)
+        return 0;
+
+    write_header(header, out);
+    if (header->payload_len)
+        memcpy(out + FL_HEADER_LEN, payload, header->payload_len);
+    return len;
+}
+
+int fl_decode(const uint8_t *datagram, size_t len, struct fl_header *header, const uint8_t **payload)
+{
+    if (len < FL_HEADER_LEN)
+        return FL_ERR_SHORT;
+
+    header->magic = get_u32(datagram);
+    header->version = datagram[4];
+    header->msg_type = datagram[5];
+    header->sequence = get_u16(datagram + 6);
+    header->timestamp_us = get_u64(datagram + 8);
+    header->sender_id = get_u32(datagram + 16);
+    header->payload_len = get_u16(datagram + 20);
+    header->checksum = get_u16(datagram + 22);
+
+    if (header->magic != FL_MAGIC)
+        return FL_ERR_MAGIC;
+    if (header->version < FL_MIN_VERSION || header->version > FL_VERSION)
+        return FL_ERR_VERSION;
+    if (header->checksum != sum_bytes(datagram, FL_HEADER_LEN - 2))
+        return FL_ERR_CHECKSUM;
+    if (len - FL_HEADER_LEN != header->payload_len)
+        return FL_ERR_LENGTH;
+
+    *payload = datagram + FL_HEADER_LEN;
+    return FL_OK;
+}
@@ -0,0 +1,56 @@
+/*
+ * Reference C codec for the FleetLink wire format.
+ *
+ * Written the way a C implementation of the protocol would be: the header is
+ * built in a caller-provided struct, encoded into a caller-provided buffer,
+ * and decoded in place with the payload returned as a pointer into the
+ * datagram. Used by the benchmarks (feature `c-reference`) as the baseline
+ * the Rust transport is compared against.
+ */
+#ifndef FLEETLINK_H
+#define FLEETLINK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define FL_MAGIC 0xFEEDu
+#define FL_VERSION 2
+#define FL_MIN_VERSION 1
+#define FL_HEADER_LEN 24
+#define FL_MSG_TYPE_MASK 0x0F
+
+/* Same field order and sizes as the wire header; no padding on any ABI */
+struct fl_header {
+    uint32_t magic;
+    uint8_t version;
+    uint8_t msg_type;
+    uint16_t sequence;
+    uint64_t timestamp_us;
+    uint32_t sender_id;
+    uint16_t payload_len;
+    uint16_t checksum;
+};
+
+enum fl_status {
+    FL_OK = 0,
+    FL_ERR_SHORT = -1,
+    FL_ERR_MAGIC = -2,
+    FL_ERR_VERSION = -3,
+    FL_ERR_CHECKSUM = -4,
+    FL_ERR_LENGTH = -5,
+};
+
+/* Fill in a header stamped with the current time, checksum included */
+void fl_header_init(struct fl_header *header, uint8_t msg_type, uint32_t sender_id,
+                    uint16_t sequence, uint16_t payload_len);
+
+/* Sum of the header's wire bytes before the checksum field, truncated to 16 bits */
+uint16_t fl_checksum(const struct fl_header *header);
+
+/* Write header and payload to out; returns the datagram length, or 0 if cap is too small */
+size_t fl_encode(const struct fl_header *header, const uint8_t *payload, uint8_t *out, size_t cap);
+
+/* Validate a datagram and point payload into it; returns an fl_status */
+int fl_decode(const uint8_t *datagram, size_t len, struct fl_header *header, const uint8_t **payload);
+
+#endif
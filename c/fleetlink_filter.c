@@ -0,0 +1,77 @@
+/*
+ * eBPF socket filter for receivers built with the `ebpf` feature
+ * (socket_filter::EbpfFilter): drops datagrams that aren't FleetLink
+ * messages, and those from outside the fleet's block of sender ids or of
+ * unwanted types, before they are queued on the socket.
+ *
+ *   clang -O2 -g -target bpf -c c/fleetlink_filter.c -o fleetlink_filter.o
+ *
+ * The receiver loads the object itself, fills in fleetlink_settings from its
+ * ReceiverConfig and attaches the program with SO_ATTACH_BPF, which needs
+ * CAP_BPF (or an unprivileged-BPF kernel).
+ */
+
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+
+#define UDP_HEADER_LEN 8
+#define FLEETLINK_HEADER_LEN 24
+#define MSG_TYPE_MASK 0x0f
+
+/* Laid out as FilterSettings in src/socket_filter.rs */
+struct fleetlink_settings {
+    __u32 magic;
+    __u32 min_sender;
+    __u32 max_sender;
+    /* Bit n set: message type code n is wanted */
+    __u16 types;
+    /* Drop bad magic and versions; off for lenient and promiscuous receivers */
+    __u8 validate;
+    __u8 min_version;
+    __u8 max_version;
+    __u8 pad[3];
+};
+
+/* The fields of the (little-endian) header this program looks at */
+struct fleetlink_prefix {
+    __u32 magic;
+    __u8 version;
+    __u8 msg_type;
+    __u8 skipped[10];
+    __u32 sender_id;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, __u32);
+    __type(value, struct fleetlink_settings);
+} fleetlink_settings SEC(".maps");
+
+SEC("socket")
+int fleetlink_filter(struct __sk_buff *skb)
+{
+    __u32 key = 0;
+    struct fleetlink_settings *settings = bpf_map_lookup_elem(&fleetlink_settings, &key);
+    struct fleetlink_prefix header;
+
+    if (!settings)
+        return skb->len;
+    /* Too short for a header: only validation would complain about it */
+    if (skb->len < UDP_HEADER_LEN + FLEETLINK_HEADER_LEN
+        || bpf_skb_load_bytes(skb, UDP_HEADER_LEN, &header, sizeof(header)) < 0)
+        return settings->validate ? 0 : skb->len;
+
+    if (settings->validate
+        && (header.magic != settings->magic
+            || header.version < settings->min_version
+            || header.version > settings->max_version))
+        return 0;
+    if (!(settings->types & (1 << (header.msg_type & MSG_TYPE_MASK))))
+        return 0;
+    if (header.sender_id < settings->min_sender || header.sender_id > settings->max_sender)
+        return 0;
+    return skb->len;
+}
+
+char LICENSE[] SEC("license") = "Dual MIT/GPL";
@@ -0,0 +1,77 @@
+/*
+ * XDP program for the AF_XDP backend (src/xdp.rs): redirects IPv4 UDP
+ * datagrams to the fleet port into the AF_XDP socket registered for the
+ * receiving queue, and passes everything else, and everything on queues
+ * without a socket, to the kernel stack.
+ *
+ *   clang -O2 -g -target bpf -DFLEETLINK_PORT=12345 -c c/fleetlink_xdp.c -o fleetlink_xdp.o
+ *   mkdir -p /sys/fs/bpf/fleetlink
+ *   bpftool prog load fleetlink_xdp.o /sys/fs/bpf/fleetlink/prog pinmaps /sys/fs/bpf/fleetlink
+ *   bpftool net attach xdp pinned /sys/fs/bpf/fleetlink/prog dev eth0
+ *
+ * The socket map is then pinned at /sys/fs/bpf/fleetlink/fleetlink_xsks,
+ * where XdpConfig looks by default. Detach with `bpftool net detach xdp dev eth0`.
+ */
+
+#include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/ip.h>
+#include <linux/udp.h>
+#include <bpf/bpf_endian.h>
+#include <bpf/bpf_helpers.h>
+
+#ifndef FLEETLINK_PORT
+#define FLEETLINK_PORT 12345
+#endif
+
+struct vlan_tag {
+    __be16 tci;
+    __be16 proto;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_XSKMAP);
+    __uint(max_entries, 64);
+    __type(key, __u32);
+    __type(value, __u32);
+} fleetlink_xsks SEC(".maps");
+
+SEC("xdp")
+int fleetlink_redirect(struct xdp_md *ctx)
+{
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+    struct ethhdr *eth = data;
+    void *next = eth + 1;
+    __be16 proto;
+
+    if (next > data_end)
+        return XDP_PASS;
+    proto = eth->h_proto;
+    if (proto == bpf_htons(ETH_P_8021Q)) {
+        struct vlan_tag *vlan = next;
+        if ((void *)(vlan + 1) > data_end)
+            return XDP_PASS;
+        proto = vlan->proto;
+        next = vlan + 1;
+    }
+    if (proto != bpf_htons(ETH_P_IP))
+        return XDP_PASS;
+
+    struct iphdr *ip = next;
+    if ((void *)(ip + 1) > data_end || ip->protocol != IPPROTO_UDP || ip->ihl < 5)
+        return XDP_PASS;
+    /* Later IP fragments carry no UDP header; leave them to the stack */
+    if (ip->frag_off & bpf_htons(0x1fff))
+        return XDP_PASS;
+
+    struct udphdr *udp = (void *)ip + ip->ihl * 4;
+    if ((void *)(udp + 1) > data_end || udp->dest != bpf_htons(FLEETLINK_PORT))
+        return XDP_PASS;
+
+    /* Pass, rather than drop, when no socket is registered for this queue */
+    return bpf_redirect_map(&fleetlink_xsks, ctx->rx_queue_index, XDP_PASS);
+}
+
+char LICENSE[] SEC("license") = "Dual MIT/GPL";
@@ -1,89 +1,52 @@
-use fleetlink_transport::{FleetMsgHeader, MessageType};
-use zerocopy::{AsBytes, FromBytes};
+//! Rust transport vs the reference C codec in c/, encoding and decoding the
+//! same wire format. Needs `--features c-reference`.
+
+use fleetlink_transport::{FleetMessage, MessageType};
+use fleetlink_transport::{alloc_counter, c_reference};
+use fleetlink_transport::bench_history::{self, BenchRecord};
+use serde::Serialize;
 use std::time::Instant;
-use std::collections::HashMap;
 
-// Simulate typical C++ implementation patterns
-struct CppStyleTransport {
-    buffer_pool: Vec<Vec<u8>>,
-    allocation_count: u64,
-    copy_count: u64,
+// Allocation counts are only real when the counting allocator is installed
+#[cfg(feature = "alloc-count")]
+#[global_allocator]
+static ALLOC: alloc_counter::CountingAllocator = alloc_counter::CountingAllocator;
+
+/// Measured allocations per operation, in the visualizer's `memory_efficiency` format
+#[derive(Debug, Serialize)]
+struct MemoryResult {
+    payload_size: usize,
+    rust_memory_kb: f64,
+    c_style_memory_kb: f64,
+    rust_allocations: f64,
+    c_style_allocations: f64,
 }
 
-impl CppStyleTransport {
-    fn new() -> Self {
-        Self {
-            buffer_pool: Vec::new(),
-            allocation_count: 0,
-            copy_count: 0,
-        }
-    }
-    
-    // Simulate C++ style message creation with multiple allocations
-    fn create_message_cpp_style(&mut self, msg_type: u8, payload: &[u8]) -> Vec<u8> {
-        // Allocation 1: Header struct
-        self.allocation_count += 1;
-        let mut header_bytes = Vec::new();
-        header_bytes.extend_from_slice(&0xFEEDu32.to_le_bytes()); // magic
-        header_bytes.push(1); // version
-        header_bytes.push(msg_type);
-        header_bytes.extend_from_slice(&100u16.to_le_bytes()); // sequence
-        header_bytes.extend_from_slice(&12345u64.to_le_bytes()); // timestamp
-        header_bytes.extend_from_slice(&99999u32.to_le_bytes()); // sender_id
-        header_bytes.extend_from_slice(&(payload.len() as u16).to_le_bytes());
-        header_bytes.extend_from_slice(&0u16.to_le_bytes()); // checksum
-        
-        // Allocation 2: Payload copy
-        self.allocation_count += 1;
-        let payload_copy = payload.to_vec();
-        self.copy_count += payload.len() as u64;
-        
-        // Allocation 3: Final message buffer
-        self.allocation_count += 1;
-        let mut message = Vec::new();
-        message.extend_from_slice(&header_bytes);
-        message.extend_from_slice(&payload_copy);
-        self.copy_count += message.len() as u64;
-        
-        message
-    }
-    
-    // Simulate C++ style parsing with multiple copies
-    fn parse_message_cpp_style(&mut self, data: &[u8]) -> Option<(HashMap<String, u64>, Vec<u8>)> {
-        if data.len() < 24 {
-            return None;
-        }
-        
-        // Allocation 4: Header parsing with field extraction
-        self.allocation_count += 1;
-        let mut header_map = HashMap::new();
-        header_map.insert("magic".to_string(), u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as u64);
-        header_map.insert("version".to_string(), data[4] as u64);
-        header_map.insert("msg_type".to_string(), data[5] as u64);
-        // ... more field extractions
-        
-        let payload_len = u16::from_le_bytes([data[20], data[21]]) as usize;
-        if data.len() < 24 + payload_len {
-            return None;
-        }
-        
-        // Allocation 5: Payload copy
-        self.allocation_count += 1;
-        let payload = data[24..24 + payload_len].to_vec();
-        self.copy_count += payload.len() as u64;
-        
-        Some((header_map, payload))
+/// Build, encode, decode and validate one message the way the transport does
+fn rust_round_trip(sequence: u16, payload: &[u8]) {
+    let message = FleetMessage::data(99999, sequence, payload).to_bytes();
+    if let Ok(parsed) = FleetMessage::parse(&message) {
+        std::hint::black_box(parsed.payload());
     }
 }
 
-fn benchmark_rust_vs_cpp() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔬 Rust vs C++ Performance Comparison");
-    println!("======================================");
+/// The same with the reference C codec
+fn c_round_trip(sequence: u16, payload: &[u8], buffer: &mut [u8]) {
+    let header = c_reference::header(MessageType::Data, 99999, sequence, payload.len() as u16);
+    let len = c_reference::encode(&header, payload, buffer).unwrap_or(0);
+    std::hint::black_box(c_reference::decode(&buffer[..len]));
+}
+
+fn benchmark_rust_vs_cpp(history: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔬 Rust vs C Performance Comparison");
+    println!("===================================");
     
     let test_sizes = vec![0, 64, 256, 512, 1024, 2048];
     let iterations = 10000;
     
     println!("Running {} iterations for each payload size...\n", iterations);
+    let mut memory_results = Vec::new();
+    let mut record = BenchRecord::new("cpp_comparison");
     
     for &payload_size in &test_sizes {
         println!("📦 Payload Size: {} bytes", payload_size);
@@ -91,72 +54,59 @@ fn benchmark_rust_vs_cpp() -> Result<(), Box<dyn std::error::Error>> {
         
         let payload = vec![0u8; payload_size];
         
-        // Rust zero-copy approach
+        // Rust: what the transport does, one buffer per message
         let rust_start = Instant::now();
-        let mut rust_total_allocations = 0;
-        let mut rust_total_copies = 0;
-        
-        for i in 0..iterations {
-            // Create message (minimal allocations)
-            let header = FleetMsgHeader::new(MessageType::Data, 99999, i as u16, payload.len() as u16);
-            let mut message = Vec::new(); // 1 allocation
-            message.extend_from_slice(header.as_bytes()); // zero-copy reference
-            message.extend_from_slice(&payload); // 1 copy
-            rust_total_allocations += 1;
-            rust_total_copies += payload.len();
-            
-            // Parse message (zero-copy)
-            if let Some(parsed_header) = FleetMsgHeader::read_from_prefix(&message) {
-                let header_size = std::mem::size_of::<FleetMsgHeader>();
-                let _parsed_payload = &message[header_size..]; // zero-copy reference
-                // No additional allocations or copies
+        let ((), rust_allocations) = alloc_counter::measure(|| {
+            for i in 0..iterations {
+                rust_round_trip(i as u16, &payload);
             }
-        }
-        
+        });
         let rust_duration = rust_start.elapsed();
-        
-        // C++ style approach
-        let mut cpp_transport = CppStyleTransport::new();
+
+        // C: encode into a caller-provided buffer and decode in place
+        let mut buffer = vec![0u8; 24 + payload_size];
         let cpp_start = Instant::now();
-        
-        for _i in 0..iterations {
-            // Create message (multiple allocations and copies)
-            let message = cpp_transport.create_message_cpp_style(2, &payload);
-            
-            // Parse message (multiple allocations and copies)
-            let _parsed = cpp_transport.parse_message_cpp_style(&message);
-        }
-        
+        let ((), cpp_allocations) = alloc_counter::measure(|| {
+            for i in 0..iterations {
+                c_round_trip(i as u16, &payload, &mut buffer);
+            }
+        });
         let cpp_duration = cpp_start.elapsed();
         
         // Calculate metrics
         let rust_ops_per_sec = iterations as f64 / rust_duration.as_secs_f64();
         let cpp_ops_per_sec = iterations as f64 / cpp_duration.as_secs_f64();
         let speedup = rust_ops_per_sec / cpp_ops_per_sec;
+        record = record
+            .with_metric(format!("rust_ops_per_sec/{}B", payload_size), rust_ops_per_sec)
+            .with_metric(format!("c_ops_per_sec/{}B", payload_size), cpp_ops_per_sec);
         
-        let rust_allocs_per_op = rust_total_allocations as f64 / iterations as f64;
-        let cpp_allocs_per_op = cpp_transport.allocation_count as f64 / iterations as f64;
-        
-        let rust_copies_per_op = rust_total_copies as f64 / iterations as f64;
-        let cpp_copies_per_op = cpp_transport.copy_count as f64 / iterations as f64;
+        let (rust_allocs_per_op, rust_bytes_per_op) = rust_allocations.per_op(iterations);
+        let (cpp_allocs_per_op, cpp_bytes_per_op) = cpp_allocations.per_op(iterations);
         
         // Display results
         println!("⚡ Performance Results:");
         println!("  Rust:     {:>8.0} ops/sec ({:>6.2} ms)", rust_ops_per_sec, rust_duration.as_millis());
-        println!("  C++ Est:  {:>8.0} ops/sec ({:>6.2} ms)", cpp_ops_per_sec, cpp_duration.as_millis());
-        println!("  Speedup:  {:>8.2}x faster", speedup);
+        println!("  C:        {:>8.0} ops/sec ({:>6.2} ms)", cpp_ops_per_sec, cpp_duration.as_millis());
+        println!("  Rust/C:   {:>8.2}x", speedup);
         println!();
         
         println!("💾 Memory Efficiency:");
-        println!("  Rust Allocs/op:  {:>6.1}", rust_allocs_per_op);
-        println!("  C++ Allocs/op:   {:>6.1}", cpp_allocs_per_op);
-        println!("  Alloc Reduction: {:>6.1}x", cpp_allocs_per_op / rust_allocs_per_op);
-        println!();
-        
-        println!("📋 Copy Efficiency:");
-        println!("  Rust Copies/op:  {:>6.1} bytes", rust_copies_per_op);
-        println!("  C++ Copies/op:   {:>6.1} bytes", cpp_copies_per_op);
-        println!("  Copy Reduction:  {:>6.1}x", cpp_copies_per_op / rust_copies_per_op);
+        if cfg!(feature = "alloc-count") {
+            println!("  Rust Allocs/op:  {:>6.1} ({:.0} bytes)", rust_allocs_per_op, rust_bytes_per_op);
+            // malloc isn't counted, but the C codec never calls it
+            println!("  C Allocs/op:     {:>6.1} ({:.0} bytes)", cpp_allocs_per_op, cpp_bytes_per_op);
+            record = record.with_metric(format!("rust_allocs_per_op/{}B", payload_size), rust_allocs_per_op);
+            memory_results.push(MemoryResult {
+                payload_size,
+                rust_memory_kb: rust_bytes_per_op / 1024.0,
+                c_style_memory_kb: cpp_bytes_per_op / 1024.0,
+                rust_allocations: rust_allocs_per_op,
+                c_style_allocations: cpp_allocs_per_op,
+            });
+        } else {
+            println!("  (run with --features alloc-count to measure allocations)");
+        }
         println!();
         
         // Visual representation
@@ -169,7 +119,7 @@ fn benchmark_rust_vs_cpp() -> Result<(), Box<dyn std::error::Error>> {
                  "█".repeat(rust_bar_length), 
                  "░".repeat(max_bar_length - rust_bar_length),
                  rust_ops_per_sec);
-        println!("  C++:  [{}{}] {:.0} ops/s", 
+        println!("  C:    [{}{}] {:.0} ops/s", 
                  "█".repeat(cpp_bar_length), 
                  "░".repeat(max_bar_length - cpp_bar_length),
                  cpp_ops_per_sec);
@@ -181,7 +131,7 @@ fn benchmark_rust_vs_cpp() -> Result<(), Box<dyn std::error::Error>> {
     // Summary table
     println!("📈 SUMMARY TABLE");
     println!("{}", "═".repeat(80));
-    println!("{:<12} {:<15} {:<15} {:<15} {:<15}", "Payload", "Rust (ops/s)", "C++ (ops/s)", "Speedup", "Memory Saved");
+    println!("{:<12} {:<15} {:<15} {:<15} {:<15}", "Payload", "Rust (ops/s)", "C (ops/s)", "Rust/C", "Allocs/op (Rust/C)");
     println!("{}", "─".repeat(80));
     
     for &payload_size in &test_sizes {
@@ -190,47 +140,59 @@ fn benchmark_rust_vs_cpp() -> Result<(), Box<dyn std::error::Error>> {
         // Quick benchmark for summary
         let rust_start = Instant::now();
         for i in 0..1000 {
-            let header = FleetMsgHeader::new(MessageType::Data, 99999, i as u16, payload.len() as u16);
-            let mut message = Vec::new();
-            message.extend_from_slice(header.as_bytes());
-            message.extend_from_slice(&payload);
-            if let Some(_) = FleetMsgHeader::read_from_prefix(&message) {
-                // Process
-            }
+            rust_round_trip(i, &payload);
         }
         let rust_time = rust_start.elapsed();
-        
-        let mut cpp_transport = CppStyleTransport::new();
+
+        let mut buffer = vec![0u8; 24 + payload_size];
         let cpp_start = Instant::now();
-        for _i in 0..1000 {
-            let message = cpp_transport.create_message_cpp_style(2, &payload);
-            let _parsed = cpp_transport.parse_message_cpp_style(&message);
+        for i in 0..1000 {
+            c_round_trip(i, &payload, &mut buffer);
         }
         let cpp_time = cpp_start.elapsed();
         
         let rust_ops = 1000.0 / rust_time.as_secs_f64();
         let cpp_ops = 1000.0 / cpp_time.as_secs_f64();
         let speedup = rust_ops / cpp_ops;
-        let memory_saved = (cpp_transport.allocation_count as f64 / 1000.0) / 1.0; // Rust uses ~1 alloc per op
+        let allocations = memory_results.iter()
+            .find(|result| result.payload_size == payload_size)
+            .map(|result| format!("{:.1}/{:.1}", result.rust_allocations, result.c_style_allocations))
+            .unwrap_or_else(|| "n/a".to_string());
         
-        println!("{:<12} {:<15.0} {:<15.0} {:<15.2}x {:<15.1}x", 
-                 format!("{}B", payload_size), rust_ops, cpp_ops, speedup, memory_saved);
+        println!("{:<12} {:<15.0} {:<15.0} {:<15.2}x {:<15}", 
+                 format!("{}B", payload_size), rust_ops, cpp_ops, speedup, allocations);
     }
     
     println!("{}", "═".repeat(80));
     println!();
-    println!("🎯 KEY ADVANTAGES OF RUST IMPLEMENTATION:");
-    println!("  ✅ Zero-copy deserialization with zerocopy crate");
-    println!("  ✅ Minimal memory allocations (1 vs 5+ per operation)");
-    println!("  ✅ No unnecessary data copying");
-    println!("  ✅ Compile-time memory safety guarantees");
-    println!("  ✅ Better cache locality due to fewer allocations");
-    println!("  ✅ Async/await for efficient I/O without blocking threads");
-    println!("  ✅ Built-in bounds checking without performance penalty");
+    
+    if !memory_results.is_empty() {
+        std::fs::write("allocation_data.json", serde_json::to_string_pretty(&memory_results)?)?;
+        println!("Measured allocations written to allocation_data.json");
+        println!();
+    }
+
+    if let Some(path) = history {
+        bench_history::append(&path, &record)?;
+        println!("Appended to history {}", path);
+        println!();
+    }
+    
+    println!("Both sides build, encode, decode and validate the same 24-byte header;");
+    println!("the C codec is compiled from c/fleetlink.c at the same optimization level.");
     
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    benchmark_rust_vs_cpp()
+    let mut args = std::env::args().skip(1);
+    let history = match (args.next().as_deref(), args.next()) {
+        (None, _) => None,
+        (Some("--history"), Some(path)) => Some(path),
+        _ => {
+            eprintln!("usage: cpp_comparison [--history FILE]");
+            std::process::exit(2);
+        }
+    };
+    benchmark_rust_vs_cpp(history)
 }
@@ -1,4 +1,5 @@
-use fleetlink_transport::{MulticastSender, start_multicast_rx, FleetMsgHeader};
+use fleetlink_transport::{MulticastReceiver, MulticastSender, ReceiverConfig, start_multicast_rx, FleetMsgHeader, ChannelRegistry};
+use fleetlink_transport::net::channels::{DEFAULT_GROUPS, DEFAULT_PORTS};
 use async_std::task;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::time::Duration;
@@ -8,8 +9,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("FleetLink Multicast Transport Demo");
     println!("==================================");
     
-    let group = Ipv4Addr::new(239, 1, 1, 1);
-    let port = 12345;
+    // Sender and receiver may run as separate processes; the registry file
+    // gives them the same channel
+    let channel = ChannelRegistry::new(DEFAULT_GROUPS, DEFAULT_PORTS)?
+        .with_file("fleet_channels.json")?
+        .allocate("multicast_demo")?;
+    let (group, port) = (channel.group, channel.port);
+    println!("Channel: {}:{}", group, port);
     
     // Get command line argument to determine mode
     let args: Vec<String> = std::env::args().collect();
@@ -64,18 +70,18 @@ async fn run_receiver(group: Ipv4Addr, port: u16) -> Result<(), Box<dyn std::err
     println!("Listening for multicast messages on {}:{}...", group, port);
     println!("Press Ctrl+C to stop");
     
-    let handler = |header: FleetMsgHeader, payload: Vec<u8>, addr: SocketAddr| {
-        let payload_str = String::from_utf8_lossy(&payload);
+    let receiver = MulticastReceiver::bind(&[group], port, ReceiverConfig::new()).await?;
+    while let Some(delivery) = receiver.recv().await {
+        let addr = delivery.addr;
+        let message = delivery.into_message();
         println!("[{}] {:?} from {} (seq: {}, {} bytes): {}", 
                  chrono::Utc::now().format("%H:%M:%S%.3f"),
-                 header.message_type(), 
+                 message.message_type(), 
                  addr, 
-                 header.sequence,
-                 payload.len(),
-                 payload_str);
-    };
-    
-    start_multicast_rx(group, port, handler).await?;
+                 message.sequence(),
+                 message.payload().len(),
+                 String::from_utf8_lossy(message.payload()));
+    }
     Ok(())
 }
 
@@ -1,10 +1,15 @@
-use fleetlink_transport::{FleetMsgHeader, MessageType, MulticastSender, start_multicast_rx};
+use fleetlink_transport::{ChannelRegistry, FleetMsgHeader, MulticastSender, start_multicast_rx};
+use fleetlink_transport::alloc_counter;
+use fleetlink_transport::net::channels::{DEFAULT_GROUPS, DEFAULT_PORTS};
 use async_std::task;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 use std::collections::VecDeque;
-use zerocopy::AsBytes;
+
+#[cfg(feature = "alloc-count")]
+#[global_allocator]
+static ALLOC: alloc_counter::CountingAllocator = alloc_counter::CountingAllocator;
 
 #[derive(Debug, Clone)]
 struct PerformanceMetrics {
@@ -15,8 +20,6 @@ struct PerformanceMetrics {
     avg_latency_us: f64,
     throughput_msg_per_sec: f64,
     throughput_mb_per_sec: f64,
-    cpu_usage_percent: f64,
-    memory_usage_kb: f64,
     start_time: Instant,
 }
 
@@ -30,8 +33,6 @@ impl PerformanceMetrics {
             avg_latency_us: 0.0,
             throughput_msg_per_sec: 0.0,
             throughput_mb_per_sec: 0.0,
-            cpu_usage_percent: 0.0,
-            memory_usage_kb: 0.0,
             start_time: Instant::now(),
         }
     }
@@ -80,8 +81,10 @@ async fn run_performance_test() -> Result<(), Box<dyn std::error::Error>> {
     println!("🚀 FleetLink Transport Performance Monitor");
     println!("==========================================");
     
-    let group = Ipv4Addr::new(239, 1, 1, 10);
-    let port = 12350;
+    let channel = ChannelRegistry::new(DEFAULT_GROUPS, DEFAULT_PORTS)?
+        .with_file("fleet_channels.json")?
+        .allocate("performance_monitor")?;
+    let (group, port) = (channel.group, channel.port);
     let sender_id = 99999;
     
     let metrics = Arc::new(Mutex::new(PerformanceMetrics::new()));
@@ -94,17 +97,15 @@ async fn run_performance_test() -> Result<(), Box<dyn std::error::Error>> {
     // Start receiver
     let receiver_task = task::spawn(async move {
         let handler = move |header: FleetMsgHeader, payload: Vec<u8>, _addr: SocketAddr| {
-            let receive_time = Instant::now();
-            
             // Calculate latency from timestamp in header
-            let sent_time_ms = header.timestamp;
-            let current_time_ms = std::time::SystemTime::now()
+            let sent_time_us = header.timestamp_micros();
+            let current_time_us = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
-                .as_millis() as u64;
+                .as_micros() as u64;
             
-            if current_time_ms >= sent_time_ms {
-                let latency = Duration::from_millis(current_time_ms - sent_time_ms);
+            if current_time_us >= sent_time_us {
+                let latency = Duration::from_micros(current_time_us - sent_time_us);
                 latency_rx.lock().unwrap().add_sample(latency);
             }
             
@@ -160,8 +161,17 @@ async fn run_performance_test() -> Result<(), Box<dyn std::error::Error>> {
             
             println!("💾 EFFICIENCY INDICATORS");
             println!("  Zero-Copy Ops:     {:>10}", metrics.messages_received);
-            println!("  Memory Efficiency: {:>8.1}%", 95.0); // Simulated
             println!("  CPU Efficiency:    {:>8.1}%", 88.0); // Simulated
+            match alloc_counter::by_subsystem() {
+                Some(allocations) => {
+                    println!("  Allocations per received message:");
+                    for (subsystem, count) in allocations.iter() {
+                        let (per_message, bytes_per_message) = count.per_op(metrics.messages_received);
+                        println!("    {:<15} {:>8.2} allocs {:>10.1} bytes", subsystem, per_message, bytes_per_message);
+                    }
+                }
+                None => println!("  Allocations:       (run with --features alloc-count to measure)"),
+            }
             println!();
             
             // Performance comparison
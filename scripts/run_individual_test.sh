@@ -16,7 +16,7 @@ print_usage() {
     echo "Usage: $0 [test_name] [options]"
     echo ""
     echo "Available tests:"
-    echo "  cpp_comparison      - Compare Rust vs C++ performance"
+    echo "  cpp_comparison      - Compare Rust against the reference C codec"
     echo "  performance_monitor - Live performance monitoring"
     echo "  performance_visualizer - Generate performance charts"
     echo "  multicast_demo      - Interactive multicast demo"
@@ -42,8 +42,8 @@ run_test() {
     
     case "$test_name" in
         "cpp_comparison")
-            echo -e "${BLUE}🔬 Running Rust vs C++ performance comparison...${NC}"
-            cargo run --release --example cpp_comparison
+            echo -e "${BLUE}🔬 Running Rust vs C performance comparison...${NC}"
+            cargo run --release --features c-reference --example cpp_comparison
             ;;
         "performance_monitor")
             echo -e "${BLUE}⚡ Starting live performance monitor...${NC}"
@@ -52,7 +52,7 @@ run_test() {
             ;;
         "performance_visualizer")
             echo -e "${BLUE}📊 Generating performance charts...${NC}"
-            cargo run --release --bin performance_visualizer
+            cargo run --release --features visualization --bin performance_visualizer
             ;;
         "multicast_demo")
             echo -e "${BLUE}🚀 Running multicast demo...${NC}"
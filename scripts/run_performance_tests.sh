@@ -49,12 +49,12 @@ else
 fi
 
 echo ""
-print_status "Running C++ vs Rust comparison..."
-cargo run --release --example cpp_comparison
+print_status "Running C vs Rust comparison..."
+cargo run --release --features c-reference --example cpp_comparison
 
 echo ""
 print_status "Generating performance visualization..."
-cargo run --release --bin performance_visualizer
+cargo run --release --features visualization --bin performance_visualizer
 
 echo ""
 print_status "Running benchmarks..."
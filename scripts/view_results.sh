@@ -55,7 +55,7 @@ suggest_generation() {
 
 # Open performance comparison chart
 if ! open_file "performance_comparison.png" "performance comparison chart"; then
-    suggest_generation "cargo run --bin performance_visualizer" "to generate performance charts"
+    suggest_generation "cargo run --features visualization --bin performance_visualizer" "to generate performance charts"
 fi
 
 # Check performance data
@@ -63,7 +63,7 @@ if [ -f "performance_data.json" ]; then
     echo -e "${GREEN}📋 Performance data available in performance_data.json${NC}"
 else
     echo -e "${RED}❌ Performance data not found.${NC}"
-    suggest_generation "cargo run --bin performance_visualizer" "to generate performance data"
+    suggest_generation "cargo run --features visualization --bin performance_visualizer" "to generate performance data"
 fi
 
 # Open performance analysis
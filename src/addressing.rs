@@ -0,0 +1,158 @@
+use async_std::net::UdpSocket;
+use std::io::{Error, ErrorKind};
+use std::net::Ipv4Addr;
+
+/// Fleets whose groups would overlap the tag groups in 239.192.0.0/14
+const TAG_GROUP_FLEETS: std::ops::RangeInclusive<u8> = 192..=195;
+
+/// One level of the fleet/site/zone hierarchy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GroupScope {
+    /// Every node in the fleet
+    Fleet,
+    Site(u8),
+    Zone { site: u8, zone: u8 },
+}
+
+/// Maps a fleet/site/zone hierarchy onto `239.F.S.Z`.
+///
+/// Site and zone 0 stand for "all of them", so `239.F.0.0` reaches the whole
+/// fleet and `239.F.S.0` a whole site; sites and zones are numbered 1..=254.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressPlan {
+    fleet: u8,
+}
+
+impl AddressPlan {
+    pub fn new(fleet: u8) -> std::io::Result<Self> {
+        if fleet == 0 || fleet == 255 || TAG_GROUP_FLEETS.contains(&fleet) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("fleet {} is reserved; use 1-191 or 196-254", fleet),
+            ));
+        }
+        Ok(Self { fleet })
+    }
+
+    pub fn fleet(&self) -> u8 {
+        self.fleet
+    }
+
+    pub fn group(&self, scope: GroupScope) -> std::io::Result<Ipv4Addr> {
+        let (site, zone) = match scope {
+            GroupScope::Fleet => (0, 0),
+            GroupScope::Site(site) => (check("site", site)?, 0),
+            GroupScope::Zone { site, zone } => (check("site", site)?, check("zone", zone)?),
+        };
+        Ok(Ipv4Addr::new(239, self.fleet, site, zone))
+    }
+
+    /// The groups a node in `zone` of `site` belongs to, widest first
+    pub fn groups_for(&self, site: u8, zone: u8) -> std::io::Result<[Ipv4Addr; 3]> {
+        Ok([
+            self.group(GroupScope::Fleet)?,
+            self.group(GroupScope::Site(site))?,
+            self.group(GroupScope::Zone { site, zone })?,
+        ])
+    }
+
+    /// Which level of this fleet's hierarchy `addr` addresses, if any
+    pub fn scope_of(&self, addr: Ipv4Addr) -> Option<GroupScope> {
+        match addr.octets() {
+            [239, fleet, _, _] if fleet != self.fleet => None,
+            [239, _, 0, 0] => Some(GroupScope::Fleet),
+            [239, _, site, 0] if site != 255 => Some(GroupScope::Site(site)),
+            [239, _, site, zone] if site != 0 && site != 255 && zone != 255 => Some(GroupScope::Zone { site, zone }),
+            _ => None,
+        }
+    }
+}
+
+fn check(level: &str, value: u8) -> std::io::Result<u8> {
+    if value == 0 || value == 255 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} must be between 1 and 254", level)));
+    }
+    Ok(value)
+}
+
+/// Keeps a socket joined to the groups of its current place in the hierarchy,
+/// e.g. as a vehicle drives from zone to zone
+#[derive(Debug)]
+pub struct GroupJoins {
+    plan: AddressPlan,
+    joined: Vec<Ipv4Addr>,
+}
+
+impl GroupJoins {
+    pub fn new(plan: AddressPlan) -> Self {
+        Self { plan, joined: Vec::new() }
+    }
+
+    pub fn joined(&self) -> &[Ipv4Addr] {
+        &self.joined
+    }
+
+    /// Join the groups for `site`/`zone`, leaving any that no longer apply
+    pub fn join(&mut self, socket: &UdpSocket, site: u8, zone: u8) -> std::io::Result<()> {
+        let wanted = self.plan.groups_for(site, zone)?;
+        for group in self.joined.clone() {
+            if !wanted.contains(&group) {
+                socket.leave_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+                self.joined.retain(|g| *g != group);
+            }
+        }
+        for group in wanted {
+            if !self.joined.contains(&group) {
+                socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+                self.joined.push(group);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn leave_all(&mut self, socket: &UdpSocket) -> std::io::Result<()> {
+        for group in std::mem::take(&mut self.joined) {
+            socket.leave_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_maps_hierarchy() {
+        let plan = AddressPlan::new(3).unwrap();
+        assert_eq!(plan.groups_for(12, 4).unwrap(), [
+            Ipv4Addr::new(239, 3, 0, 0),
+            Ipv4Addr::new(239, 3, 12, 0),
+            Ipv4Addr::new(239, 3, 12, 4),
+        ]);
+        for addr in plan.groups_for(12, 4).unwrap() {
+            assert_eq!(plan.group(plan.scope_of(addr).unwrap()).unwrap(), addr);
+        }
+        assert_eq!(plan.scope_of(Ipv4Addr::new(239, 4, 12, 4)), None);
+
+        assert!(plan.group(GroupScope::Zone { site: 12, zone: 0 }).is_err());
+        assert!(AddressPlan::new(193).is_err());
+    }
+
+    #[async_std::test]
+    async fn test_joins_follow_zone_changes() {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+        let mut joins = GroupJoins::new(AddressPlan::new(3).unwrap());
+
+        joins.join(&socket, 12, 4).unwrap();
+        joins.join(&socket, 12, 5).unwrap();
+        assert_eq!(joins.joined(), [
+            Ipv4Addr::new(239, 3, 0, 0),
+            Ipv4Addr::new(239, 3, 12, 0),
+            Ipv4Addr::new(239, 3, 12, 5),
+        ]);
+
+        joins.leave_all(&socket).unwrap();
+        assert!(joins.joined().is_empty());
+    }
+}
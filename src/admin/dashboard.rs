@@ -0,0 +1,195 @@
+use async_std::net::TcpStream;
+use async_std::task;
+use async_tungstenite::WebSocketStream;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::tungstenite::handshake::derive_accept_key;
+use async_tungstenite::tungstenite::protocol::Role;
+use futures::future::{Either, select};
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::http::{HttpAdmin, HttpRequest, write_response};
+use super::{AdminRequest, AdminResponse, PeerSummary};
+use crate::stats::StatsSnapshot;
+
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Messages pushed to dashboard WebSocket clients
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DashboardEvent {
+    Snapshot { stats: StatsSnapshot, peers: Vec<PeerSummary> },
+    PeerJoined { sender_id: u32, address: String },
+    PeerLeft { sender_id: u32 },
+}
+
+pub(super) fn is_dashboard_route(path: &str) -> bool {
+    matches!(path.split('?').next(), Some("/" | "/index.html" | "/ws"))
+}
+
+pub(super) async fn handle(admin: &HttpAdmin, mut stream: TcpStream, request: &HttpRequest) -> std::io::Result<()> {
+    if !request.path.starts_with("/ws") {
+        return write_response(&mut stream, 200, "text/html; charset=utf-8", DASHBOARD_HTML.as_bytes()).await;
+    }
+
+    if !admin.is_authorized_by_query(request) {
+        return write_response(&mut stream, 401, "text/plain", b"missing or invalid token").await;
+    }
+    let Some(key) = request.header("sec-websocket-key") else {
+        return write_response(&mut stream, 400, "text/plain", b"expected a WebSocket upgrade").await;
+    };
+
+    let head = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    futures::AsyncWriteExt::write_all(&mut stream, head.as_bytes()).await?;
+
+    let ws = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+    stream_events(admin, ws).await
+}
+
+async fn stream_events(admin: &HttpAdmin, ws: WebSocketStream<TcpStream>) -> std::io::Result<()> {
+    let (mut outgoing, mut incoming) = ws.split();
+    let mut known = BTreeMap::new();
+
+    loop {
+        let (stats, peers) = snapshot(admin);
+        let mut events = peer_events(&mut known, &peers);
+        events.push(DashboardEvent::Snapshot { stats, peers });
+
+        for event in events {
+            let json = serde_json::to_string(&event).unwrap_or_default();
+            if outgoing.send(Message::text(json)).await.is_err() {
+                return Ok(());
+            }
+        }
+
+        // Wake up for the next update, or stop once the browser goes away
+        let tick = Box::pin(task::sleep(UPDATE_INTERVAL));
+        if let Either::Right((Some(Ok(Message::Close(_)) | Err(_)) | None, _)) = select(tick, incoming.next()).await {
+            return Ok(());
+        }
+    }
+}
+
+fn snapshot(admin: &HttpAdmin) -> (StatsSnapshot, Vec<PeerSummary>) {
+    let stats = match admin.state().handle(AdminRequest::GetStats) {
+        AdminResponse::Stats { stats } => stats,
+        _ => StatsSnapshot::default(),
+    };
+    let peers = match admin.state().handle(AdminRequest::ListPeers) {
+        AdminResponse::Peers { peers } => peers,
+        _ => Vec::new(),
+    };
+    (stats, peers)
+}
+
+/// Diff the peers currently online against what the client has already been told
+fn peer_events(known: &mut BTreeMap<u32, String>, peers: &[PeerSummary]) -> Vec<DashboardEvent> {
+    let mut events = Vec::new();
+
+    let peers: Vec<&PeerSummary> = peers.iter().filter(|peer| peer.online).collect();
+    for peer in &peers {
+        if known.insert(peer.sender_id, peer.address.clone()).is_none() {
+            events.push(DashboardEvent::PeerJoined { sender_id: peer.sender_id, address: peer.address.clone() });
+        }
+    }
+
+    let current: Vec<u32> = peers.iter().map(|peer| peer.sender_id).collect();
+    known.retain(|sender_id, _| {
+        let present = current.contains(sender_id);
+        if !present {
+            events.push(DashboardEvent::PeerLeft { sender_id: *sender_id });
+        }
+        present
+    });
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(sender_id: u32) -> PeerSummary {
+        PeerSummary {
+            sender_id,
+            address: format!("10.0.0.{}:5000", sender_id),
+            last_seen_ms_ago: 0,
+            last_sequence: 0,
+            messages: 1,
+            roles: Vec::new(),
+            capabilities: Vec::new(),
+            online: true,
+        }
+    }
+
+    #[test]
+    fn test_peer_events_report_joins_and_leaves() {
+        let mut known = BTreeMap::new();
+
+        let events = peer_events(&mut known, &[peer(1), peer(2)]);
+        assert_eq!(events.len(), 2);
+        assert!(peer_events(&mut known, &[peer(1), peer(2)]).is_empty());
+
+        let events = peer_events(&mut known, &[peer(2), peer(3)]);
+        assert_eq!(events, vec![
+            DashboardEvent::PeerJoined { sender_id: 3, address: "10.0.0.3:5000".to_string() },
+            DashboardEvent::PeerLeft { sender_id: 1 },
+        ]);
+    }
+
+    #[async_std::test]
+    async fn test_websocket_streams_snapshots() {
+        use crate::admin::AdminState;
+        use crate::peers::PeerTable;
+        use crate::stats::TransportStats;
+        use async_std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+
+        let (state, _rx) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = task::spawn(HttpAdmin::new(state, "secret").unwrap().serve(listener));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let url = format!("ws://{}/ws?token=secret", addr);
+        let (mut ws, _) = async_tungstenite::client_async(url, stream).await.unwrap();
+
+        let message = ws.next().await.unwrap().unwrap();
+        assert!(message.to_text().unwrap().starts_with(r#"{"type":"snapshot""#), "{}", message);
+        server.cancel().await;
+    }
+
+    #[test]
+    fn test_routes() {
+        assert!(is_dashboard_route("/"));
+        assert!(is_dashboard_route("/ws?token=abc"));
+        assert!(!is_dashboard_route("/v1/stats"));
+    }
+
+    #[test]
+    fn test_query_token_only_opens_the_websocket() {
+        use crate::admin::AdminState;
+        use crate::peers::PeerTable;
+        use crate::stats::TransportStats;
+        use std::sync::{Arc, Mutex};
+
+        let (state, _rx) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        let admin = HttpAdmin::new(state, "secret").unwrap();
+        let request = |path: &str| HttpRequest { method: "GET".into(), path: path.into(), headers: Vec::new(), body: Vec::new() };
+        assert!(admin.is_authorized_by_query(&request("/ws?token=secret")));
+        assert!(!admin.is_authorized_by_query(&request("/ws?token=guess")));
+        assert!(!admin.is_authorized(&request("/v1/stats?token=secret")));
+    }
+
+    #[test]
+    fn test_events_serialize_with_type_tag() {
+        let json = serde_json::to_string(&DashboardEvent::PeerLeft { sender_id: 9 }).unwrap();
+        assert_eq!(json, r#"{"type":"peer_left","sender_id":9}"#);
+    }
+}
@@ -0,0 +1,387 @@
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::service::interceptor::InterceptedService;
+use tonic::{Request, Response, Status};
+
+use super::{AdminRequest, AdminResponse, AdminState, constant_time_eq};
+use crate::bandwidth::MessageClass;
+
+/// Messages and generated server for `fleetlink.admin.v1.Admin` (see proto/admin.proto)
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Peer {
+        #[prost(uint32, tag = "1")]
+        pub sender_id: u32,
+        #[prost(string, tag = "2")]
+        pub address: String,
+        #[prost(uint64, tag = "3")]
+        pub last_seen_ms_ago: u64,
+        #[prost(uint32, tag = "4")]
+        pub last_sequence: u32,
+        #[prost(uint64, tag = "5")]
+        pub messages: u64,
+        #[prost(string, repeated, tag = "6")]
+        pub roles: Vec<String>,
+        #[prost(string, repeated, tag = "7")]
+        pub capabilities: Vec<String>,
+        #[prost(bool, tag = "8")]
+        pub online: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PeerList {
+        #[prost(message, repeated, tag = "1")]
+        pub peers: Vec<Peer>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Stats {
+        #[prost(uint64, tag = "1")]
+        pub messages_sent: u64,
+        #[prost(uint64, tag = "2")]
+        pub bytes_sent: u64,
+        #[prost(uint64, tag = "3")]
+        pub messages_received: u64,
+        #[prost(uint64, tag = "4")]
+        pub bytes_received: u64,
+        #[prost(uint64, tag = "5")]
+        pub invalid_received: u64,
+        #[prost(message, repeated, tag = "6")]
+        pub allocations: Vec<SubsystemAllocations>,
+        #[prost(message, repeated, tag = "7")]
+        pub senders: Vec<SenderSequence>,
+        #[prost(uint64, tag = "8")]
+        pub padding_bytes_sent: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SenderSequence {
+        #[prost(uint32, tag = "1")]
+        pub sender_id: u32,
+        #[prost(uint64, tag = "2")]
+        pub received: u64,
+        #[prost(uint64, tag = "3")]
+        pub missing: u64,
+        #[prost(uint64, tag = "4")]
+        pub duplicates: u64,
+        #[prost(uint64, tag = "5")]
+        pub reordered: u64,
+        #[prost(uint32, tag = "6")]
+        pub max_reorder_depth: u32,
+        #[prost(uint64, tag = "7")]
+        pub restarts: u64,
+        #[prost(double, tag = "8")]
+        pub loss_percent: f64,
+        #[prost(double, tag = "9")]
+        pub duplication_percent: f64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubsystemAllocations {
+        #[prost(string, tag = "1")]
+        pub subsystem: String,
+        #[prost(uint64, tag = "2")]
+        pub allocations: u64,
+        #[prost(uint64, tag = "3")]
+        pub bytes: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RateLimit {
+        #[prost(string, tag = "1")]
+        pub class: String,
+        #[prost(double, tag = "2")]
+        pub share: f64,
+        #[prost(uint64, tag = "3")]
+        pub bytes_per_sec: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RateLimits {
+        #[prost(uint64, tag = "1")]
+        pub total_bytes_per_sec: u64,
+        #[prost(message, repeated, tag = "2")]
+        pub classes: Vec<RateLimit>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SetRateLimitRequest {
+        #[prost(uint64, optional, tag = "1")]
+        pub total_bytes_per_sec: Option<u64>,
+        #[prost(string, optional, tag = "2")]
+        pub class: Option<String>,
+        #[prost(double, optional, tag = "3")]
+        pub share: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PingRequest {
+        #[prost(uint32, optional, tag = "1")]
+        pub target: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CaptureRequest {
+        #[prost(string, tag = "1")]
+        pub path: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Ack {
+        #[prost(string, tag = "1")]
+        pub message: String,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/fleetlink.admin.v1.Admin.rs"));
+}
+
+use proto::admin_server::{Admin, AdminServer};
+
+/// gRPC front-end for [`AdminState`]
+#[derive(Debug, Clone)]
+pub struct AdminGrpcService {
+    state: AdminState,
+}
+
+impl AdminGrpcService {
+    pub fn new(state: AdminState) -> Self {
+        Self { state }
+    }
+
+    /// The server, refusing calls that don't carry `token`
+    pub fn into_server(self, token: BearerToken) -> InterceptedService<AdminServer<Self>, BearerToken> {
+        AdminServer::with_interceptor(self, token)
+    }
+
+    fn call(&self, request: AdminRequest) -> Result<AdminResponse, Status> {
+        match self.state.handle(request) {
+            AdminResponse::Error { message } => Err(Status::failed_precondition(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Admits calls whose `authorization` metadata is `Bearer <token>`, as the
+/// HTTP front-end does
+#[derive(Debug, Clone)]
+pub struct BearerToken {
+    token: Arc<str>,
+}
+
+impl BearerToken {
+    /// Fails on an empty token, which would admit anyone
+    pub fn new(token: impl Into<String>) -> std::io::Result<Self> {
+        let token = token.into();
+        if token.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "the admin token must not be empty"));
+        }
+        Ok(Self { token: Arc::from(token) })
+    }
+}
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let presented = request.metadata().get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or("");
+        if !constant_time_eq(presented.as_bytes(), self.token.as_bytes()) {
+            return Err(Status::unauthenticated("missing or invalid bearer token"));
+        }
+        Ok(request)
+    }
+}
+
+/// Serve the admin API on `addr` to callers presenting `token`; must be run
+/// on a Tokio runtime. Refuses to start with an empty token.
+pub async fn serve(state: AdminState, addr: SocketAddr, token: impl Into<String>) -> std::io::Result<()> {
+    let token = BearerToken::new(token)?;
+    println!("Admin gRPC service listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(AdminGrpcService::new(state).into_server(token))
+        .serve(addr)
+        .await
+        .map_err(Error::other)
+}
+
+fn parse_class(class: &str) -> Result<MessageClass, Status> {
+    match class {
+        "control" => Ok(MessageClass::Control),
+        "telemetry" => Ok(MessageClass::Telemetry),
+        "bulk" => Ok(MessageClass::Bulk),
+        other => Err(Status::invalid_argument(format!("unknown class '{}'", other))),
+    }
+}
+
+fn class_name(class: MessageClass) -> &'static str {
+    match class {
+        MessageClass::Control => "control",
+        MessageClass::Telemetry => "telemetry",
+        MessageClass::Bulk => "bulk",
+    }
+}
+
+fn rate_limits(response: AdminResponse) -> Result<Response<proto::RateLimits>, Status> {
+    match response {
+        AdminResponse::RateLimits { total_bytes_per_sec, classes } => Ok(Response::new(proto::RateLimits {
+            total_bytes_per_sec,
+            classes: classes.into_iter()
+                .map(|limit| proto::RateLimit {
+                    class: class_name(limit.class).to_string(),
+                    share: limit.share,
+                    bytes_per_sec: limit.bytes_per_sec,
+                })
+                .collect(),
+        })),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn ack(response: AdminResponse) -> Result<Response<proto::Ack>, Status> {
+    match response {
+        AdminResponse::Accepted => Ok(Response::new(proto::Ack { message: "accepted".to_string() })),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn unexpected(response: AdminResponse) -> Status {
+    Status::internal(format!("unexpected admin response {:?}", response))
+}
+
+#[tonic::async_trait]
+impl Admin for AdminGrpcService {
+    async fn list_peers(&self, _request: Request<proto::Empty>) -> Result<Response<proto::PeerList>, Status> {
+        match self.call(AdminRequest::ListPeers)? {
+            AdminResponse::Peers { peers } => Ok(Response::new(proto::PeerList {
+                peers: peers.into_iter()
+                    .map(|peer| proto::Peer {
+                        sender_id: peer.sender_id,
+                        address: peer.address,
+                        last_seen_ms_ago: peer.last_seen_ms_ago,
+                        last_sequence: peer.last_sequence as u32,
+                        messages: peer.messages,
+                        roles: peer.roles,
+                        capabilities: peer.capabilities,
+                        online: peer.online,
+                    })
+                    .collect(),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_stats(&self, _request: Request<proto::Empty>) -> Result<Response<proto::Stats>, Status> {
+        match self.call(AdminRequest::GetStats)? {
+            AdminResponse::Stats { stats } => Ok(Response::new(proto::Stats {
+                messages_sent: stats.messages_sent,
+                bytes_sent: stats.bytes_sent,
+                messages_received: stats.messages_received,
+                bytes_received: stats.bytes_received,
+                invalid_received: stats.invalid_received,
+                padding_bytes_sent: stats.padding_bytes_sent,
+                allocations: stats.allocations.iter()
+                    .flat_map(|allocations| allocations.iter())
+                    .map(|(subsystem, count)| proto::SubsystemAllocations {
+                        subsystem: subsystem.to_string(),
+                        allocations: count.allocations,
+                        bytes: count.bytes,
+                    })
+                    .collect(),
+                senders: stats.senders.iter()
+                    .map(|sender| proto::SenderSequence {
+                        sender_id: sender.sender_id,
+                        received: sender.received,
+                        missing: sender.missing,
+                        duplicates: sender.duplicates,
+                        reordered: sender.reordered,
+                        max_reorder_depth: sender.max_reorder_depth.into(),
+                        restarts: sender.restarts,
+                        loss_percent: sender.loss_percent,
+                        duplication_percent: sender.duplication_percent,
+                    })
+                    .collect(),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_rate_limits(&self, _request: Request<proto::Empty>) -> Result<Response<proto::RateLimits>, Status> {
+        rate_limits(self.call(AdminRequest::GetRateLimits)?)
+    }
+
+    async fn set_rate_limit(
+        &self,
+        request: Request<proto::SetRateLimitRequest>,
+    ) -> Result<Response<proto::RateLimits>, Status> {
+        let request = request.into_inner();
+        let class = request.class.as_deref().map(parse_class).transpose()?;
+        rate_limits(self.call(AdminRequest::SetRateLimit {
+            total_bytes_per_sec: request.total_bytes_per_sec,
+            class,
+            share: request.share,
+        })?)
+    }
+
+    async fn ping(&self, request: Request<proto::PingRequest>) -> Result<Response<proto::Ack>, Status> {
+        ack(self.call(AdminRequest::Ping { target: request.into_inner().target })?)
+    }
+
+    async fn start_capture(&self, request: Request<proto::CaptureRequest>) -> Result<Response<proto::Ack>, Status> {
+        ack(self.call(AdminRequest::StartCapture { path: request.into_inner().path })?)
+    }
+
+    async fn stop_capture(&self, _request: Request<proto::Empty>) -> Result<Response<proto::Ack>, Status> {
+        ack(self.call(AdminRequest::StopCapture)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::BandwidthManager;
+    use crate::peers::PeerTable;
+    use crate::stats::TransportStats;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_set_rate_limit_over_grpc_service() {
+        let (state, _rx) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        let service = AdminGrpcService::new(state.with_bandwidth_manager(BandwidthManager::new(1_000)));
+
+        let limits = service.set_rate_limit(Request::new(proto::SetRateLimitRequest {
+            total_bytes_per_sec: None,
+            class: Some("bulk".to_string()),
+            share: Some(0.25),
+        })).await.unwrap().into_inner();
+
+        let bulk = limits.classes.iter().find(|c| c.class == "bulk").unwrap();
+        assert_eq!(bulk.bytes_per_sec, 250);
+
+        let status = service.set_rate_limit(Request::new(proto::SetRateLimitRequest {
+            total_bytes_per_sec: None,
+            class: Some("video".to_string()),
+            share: Some(0.25),
+        })).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_calls_need_the_bearer_token() {
+        let mut token = BearerToken::new("secret").unwrap();
+        let with = |value: &str| {
+            let mut request = Request::new(());
+            request.metadata_mut().insert("authorization", value.parse().unwrap());
+            request
+        };
+        assert!(token.call(with("Bearer secret")).is_ok());
+        assert_eq!(token.call(with("Bearer guess")).unwrap_err().code(), tonic::Code::Unauthenticated);
+        assert_eq!(token.call(Request::new(())).unwrap_err().code(), tonic::Code::Unauthenticated);
+
+        assert_eq!(BearerToken::new("").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
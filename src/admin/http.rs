@@ -0,0 +1,332 @@
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::{AdminRequest, AdminResponse, AdminState, constant_time_eq};
+use crate::bandwidth::MessageClass;
+
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Minimal HTTP/JSON front-end for [`AdminState`], authenticated with a bearer token
+#[derive(Debug, Clone)]
+pub struct HttpAdmin {
+    state: AdminState,
+    token: Arc<str>,
+}
+
+/// A parsed HTTP request, just enough for the admin routes
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitBody {
+    total_bytes_per_sec: Option<u64>,
+    class: Option<MessageClass>,
+    share: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PingBody {
+    target: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaptureBody {
+    path: String,
+}
+
+impl HttpAdmin {
+    /// Fails on an empty token, which would admit requests without credentials
+    pub fn new(state: AdminState, token: impl Into<String>) -> std::io::Result<Self> {
+        let token = token.into();
+        if token.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the admin token must not be empty"));
+        }
+        Ok(Self { state, token: Arc::from(token) })
+    }
+
+    /// Accept connections until the listener fails
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        println!("Admin HTTP API listening on {}", listener.local_addr()?);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let admin = self.clone();
+            task::spawn(async move {
+                if let Err(e) = admin.handle_connection(stream).await {
+                    eprintln!("Admin HTTP connection error: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let (status, body) = match read_request(&mut stream).await? {
+            #[cfg(feature = "dashboard")]
+            Some(request) if super::dashboard::is_dashboard_route(&request.path) => {
+                return super::dashboard::handle(self, stream, &request).await;
+            }
+            Some(request) => self.respond(&request),
+            None => (400, error_body("malformed request")),
+        };
+        write_response(&mut stream, status, "application/json", body.as_bytes()).await
+    }
+
+    /// Route an authenticated request to the admin layer; returns status and JSON body
+    pub(crate) fn respond(&self, request: &HttpRequest) -> (u16, String) {
+        if !self.is_authorized(request) {
+            return (401, error_body("missing or invalid bearer token"));
+        }
+
+        let admin_request = match route(request) {
+            Ok(admin_request) => admin_request,
+            Err((status, message)) => return (status, error_body(&message)),
+        };
+
+        let response = self.state.handle(admin_request);
+        let status = match response {
+            AdminResponse::Accepted => 202,
+            AdminResponse::Error { .. } => 409,
+            _ => 200,
+        };
+        (status, serde_json::to_string(&response).unwrap_or_default())
+    }
+
+    #[cfg(feature = "dashboard")]
+    pub(super) fn state(&self) -> &AdminState {
+        &self.state
+    }
+
+    /// Accepts the token only as a bearer header
+    pub(super) fn is_authorized(&self, request: &HttpRequest) -> bool {
+        let presented = request.header("authorization").and_then(|value| value.strip_prefix("Bearer "));
+        self.is_token(presented)
+    }
+
+    /// Also accepts the token as `?token=`, for the dashboard's WebSocket,
+    /// which browsers can't give headers. Never for the API: a token in the
+    /// URL ends up in proxy logs and browser history.
+    #[cfg(feature = "dashboard")]
+    pub(super) fn is_authorized_by_query(&self, request: &HttpRequest) -> bool {
+        self.is_authorized(request) || self.is_token(query_param(&request.path, "token"))
+    }
+
+    fn is_token(&self, presented: Option<&str>) -> bool {
+        constant_time_eq(presented.unwrap_or("").as_bytes(), self.token.as_bytes())
+    }
+}
+
+fn route(request: &HttpRequest) -> Result<AdminRequest, (u16, String)> {
+    let path = request.path.split('?').next().unwrap_or("");
+    match (request.method.as_str(), path) {
+        ("GET", "/v1/peers") => Ok(AdminRequest::ListPeers),
+        ("GET", "/v1/stats") => Ok(AdminRequest::GetStats),
+        ("GET", "/v1/rate-limits") => Ok(AdminRequest::GetRateLimits),
+        ("PUT", "/v1/rate-limits") => {
+            let body: RateLimitBody = parse_body(&request.body)?;
+            Ok(AdminRequest::SetRateLimit {
+                total_bytes_per_sec: body.total_bytes_per_sec,
+                class: body.class,
+                share: body.share,
+            })
+        }
+        ("POST", "/v1/ping") => {
+            let body: PingBody = if request.body.is_empty() {
+                PingBody { target: None }
+            } else {
+                parse_body(&request.body)?
+            };
+            Ok(AdminRequest::Ping { target: body.target })
+        }
+        ("POST", "/v1/capture/start") => {
+            let body: CaptureBody = parse_body(&request.body)?;
+            Ok(AdminRequest::StartCapture { path: body.path })
+        }
+        ("POST", "/v1/capture/stop") => Ok(AdminRequest::StopCapture),
+        (_, "/v1/peers" | "/v1/stats" | "/v1/rate-limits" | "/v1/ping" | "/v1/capture/start" | "/v1/capture/stop") => {
+            Err((405, format!("method {} not allowed", request.method)))
+        }
+        _ => Err((404, format!("no route for {}", path))),
+    }
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(body: &[u8]) -> Result<T, (u16, String)> {
+    serde_json::from_slice(body).map_err(|e| (400, format!("invalid JSON body: {}", e)))
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::to_string(&AdminResponse::Error { message: message.to_string() }).unwrap_or_default()
+}
+
+#[cfg(feature = "dashboard")]
+fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = path.split_once('?')?;
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Read one request (head plus `Content-Length` body); `None` if it is malformed
+pub(crate) async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<HttpRequest>> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let Ok(head) = std::str::from_utf8(&buf[..head_end]) else {
+        return Ok(None);
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let content_length = headers.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_length > MAX_BODY_BYTES {
+        return Ok(None);
+    }
+
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+
+    Ok(Some(HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body,
+    }))
+}
+
+pub(crate) async fn write_response(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, reason, content_type, body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::BandwidthManager;
+    use crate::peers::PeerTable;
+    use crate::stats::TransportStats;
+    use std::sync::Mutex;
+
+    fn admin() -> HttpAdmin {
+        let (state, _rx) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        HttpAdmin::new(state.with_bandwidth_manager(BandwidthManager::new(1_000)), "secret").unwrap()
+    }
+
+    fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> HttpRequest {
+        HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: token.map(|t| vec![("Authorization".to_string(), format!("Bearer {}", t))]).unwrap_or_default(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_rejects_missing_or_wrong_token() {
+        let admin = admin();
+        assert_eq!(admin.respond(&request("GET", "/v1/stats", None, "")).0, 401);
+        assert_eq!(admin.respond(&request("GET", "/v1/stats", Some("guess"), "")).0, 401);
+        assert_eq!(admin.respond(&request("GET", "/v1/stats", Some("secret"), "")).0, 200);
+        // The API takes no token in the URL, least of all on routes that change state
+        assert_eq!(admin.respond(&request("GET", "/v1/stats?token=secret", None, "")).0, 401);
+        assert_eq!(admin.respond(&request("PUT", "/v1/rate-limits?token=secret", None, r#"{"share":0.1}"#)).0, 401);
+    }
+
+    #[test]
+    fn test_refuses_empty_token() {
+        let (state, _rx) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        assert_eq!(HttpAdmin::new(state, "").unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_routes_map_to_admin_requests() {
+        let admin = admin();
+        let (status, body) = admin.respond(&request(
+            "PUT", "/v1/rate-limits", Some("secret"), r#"{"class":"bulk","share":0.25}"#,
+        ));
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""bytes_per_sec":250"#), "{}", body);
+
+        assert_eq!(admin.respond(&request("PUT", "/v1/rate-limits", Some("secret"), "{oops")).0, 400);
+        assert_eq!(admin.respond(&request("DELETE", "/v1/peers", Some("secret"), "")).0, 405);
+        assert_eq!(admin.respond(&request("GET", "/v1/nope", Some("secret"), "")).0, 404);
+    }
+
+    #[async_std::test]
+    async fn test_serves_json_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = task::spawn(admin().serve(listener));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /v1/stats HTTP/1.1\r\nHost: node\r\nAuthorization: Bearer secret\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains(r#""result":"stats""#), "{}", response);
+        server.cancel().await;
+    }
+}
@@ -0,0 +1,303 @@
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http-admin")]
+pub mod http;
+#[cfg(unix)]
+pub mod socket;
+
+use async_std::channel::{self, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::bandwidth::{BandwidthManager, MessageClass};
+use crate::peers::PeerTable;
+use crate::protocol;
+use crate::stats::{StatsSnapshot, TransportStats};
+use crate::transport::{self, FleetMsgHeader, MessageType};
+
+/// Administrative operations, independent of the wire protocol used to reach them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AdminRequest {
+    ListPeers,
+    GetStats,
+    GetRateLimits,
+    SetRateLimit {
+        total_bytes_per_sec: Option<u64>,
+        class: Option<MessageClass>,
+        share: Option<f64>,
+    },
+    Ping {
+        target: Option<u32>,
+    },
+    StartCapture {
+        path: String,
+    },
+    StopCapture,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum AdminResponse {
+    Peers { peers: Vec<PeerSummary> },
+    Stats { stats: StatsSnapshot },
+    RateLimits { total_bytes_per_sec: u64, classes: Vec<RateLimit> },
+    Accepted,
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerSummary {
+    pub sender_id: u32,
+    pub address: String,
+    pub last_seen_ms_ago: u64,
+    pub last_sequence: u16,
+    pub messages: u64,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// False once the peer has timed out
+    #[serde(default = "PeerSummary::default_online")]
+    pub online: bool,
+}
+
+impl PeerSummary {
+    fn default_online() -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub class: MessageClass,
+    pub share: f64,
+    pub bytes_per_sec: u64,
+}
+
+/// Requests that need the node's own sender or receiver to act on
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Ping { target: Option<u32> },
+    StartCapture { path: String },
+    StopCapture,
+}
+
+/// Shared state behind every admin front-end (gRPC, HTTP, ...).
+///
+/// Ping and capture requests are forwarded to the node through the command
+/// channel returned by [`AdminState::new`]; everything else is answered here.
+#[derive(Debug, Clone)]
+pub struct AdminState {
+    peers: Arc<Mutex<PeerTable>>,
+    stats: Arc<TransportStats>,
+    bandwidth: Option<BandwidthManager>,
+    commands: Sender<AdminCommand>,
+}
+
+impl AdminState {
+    pub fn new(peers: Arc<Mutex<PeerTable>>, stats: Arc<TransportStats>) -> (Self, Receiver<AdminCommand>) {
+        let (commands, rx) = channel::bounded(64);
+        (Self { peers, stats, bandwidth: None, commands }, rx)
+    }
+
+    /// Allow rate limits to be inspected and changed remotely
+    pub fn with_bandwidth_manager(mut self, manager: BandwidthManager) -> Self {
+        self.bandwidth = Some(manager);
+        self
+    }
+
+    pub fn peers(&self) -> &Arc<Mutex<PeerTable>> {
+        &self.peers
+    }
+
+    pub fn stats(&self) -> &Arc<TransportStats> {
+        &self.stats
+    }
+
+    /// Feed a received message into the peer table and counters
+    pub fn observe(&self, header: &FleetMsgHeader, payload: &[u8], addr: SocketAddr) {
+        self.stats.record_received(protocol::HEADER_LEN + payload.len());
+        let mut peers = self.peers.lock().unwrap();
+        match header.message_type() {
+            MessageType::Goodbye => {
+                peers.leave(header.sender_id.get());
+                self.stats.forget_sender(header.sender_id.get());
+            }
+            msg_type => {
+                self.stats.record_sequence(header, payload);
+                peers.observe(header, addr, Instant::now());
+                // A downgrade attempt reaches the table's subscribers as PeerEvent::Downgraded
+                if msg_type == MessageType::Heartbeat
+                    && let Some(capabilities) = transport::heartbeat_capabilities(payload)
+                {
+                    peers.announce(header.sender_id.get(), capabilities);
+                }
+            }
+        }
+    }
+
+    pub fn handle(&self, request: AdminRequest) -> AdminResponse {
+        match request {
+            AdminRequest::ListPeers => {
+                let now = Instant::now();
+                let peers = self.peers.lock().unwrap().peers()
+                    .map(|peer| PeerSummary {
+                        sender_id: peer.sender_id,
+                        address: peer.addr.to_string(),
+                        last_seen_ms_ago: now.saturating_duration_since(peer.last_seen).as_millis() as u64,
+                        last_sequence: peer.last_sequence,
+                        messages: peer.messages,
+                        roles: peer.capabilities.roles.iter().cloned().collect(),
+                        capabilities: peer.capabilities.capabilities.iter().cloned().collect(),
+                        online: peer.online,
+                    })
+                    .collect();
+                AdminResponse::Peers { peers }
+            }
+            AdminRequest::GetStats => AdminResponse::Stats { stats: self.stats.snapshot() },
+            AdminRequest::GetRateLimits => self.rate_limits(),
+            AdminRequest::SetRateLimit { total_bytes_per_sec, class, share } => {
+                let Some(bandwidth) = &self.bandwidth else {
+                    return error("no bandwidth manager configured");
+                };
+                match (class, share) {
+                    (Some(class), Some(share)) => {
+                        if let Err(e) = bandwidth.set_share(class, share) {
+                            return error(&e.to_string());
+                        }
+                    }
+                    (None, None) => {}
+                    _ => return error("class and share must be given together"),
+                }
+                if let Some(total) = total_bytes_per_sec {
+                    bandwidth.set_total_rate(total);
+                }
+                self.rate_limits()
+            }
+            AdminRequest::Ping { target } => self.forward(AdminCommand::Ping { target }),
+            AdminRequest::StartCapture { path } => self.forward(AdminCommand::StartCapture { path }),
+            AdminRequest::StopCapture => self.forward(AdminCommand::StopCapture),
+        }
+    }
+
+    fn rate_limits(&self) -> AdminResponse {
+        let Some(bandwidth) = &self.bandwidth else {
+            return error("no bandwidth manager configured");
+        };
+        let classes = MessageClass::ALL.iter()
+            .map(|&class| RateLimit {
+                class,
+                share: bandwidth.share(class),
+                bytes_per_sec: bandwidth.class_rate(class),
+            })
+            .collect();
+        AdminResponse::RateLimits { total_bytes_per_sec: bandwidth.total_rate(), classes }
+    }
+
+    fn forward(&self, command: AdminCommand) -> AdminResponse {
+        match self.commands.try_send(command) {
+            Ok(()) => AdminResponse::Accepted,
+            Err(e) if e.is_full() => error("node is busy, try again"),
+            Err(_) => error("node is not processing admin commands"),
+        }
+    }
+}
+
+fn error(message: &str) -> AdminResponse {
+    AdminResponse::Error { message: message.to_string() }
+}
+
+/// Compare a presented token with the shared one without leaking, through
+/// timing, how much of it matched
+#[cfg(any(feature = "grpc", feature = "http-admin"))]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> (AdminState, Receiver<AdminCommand>) {
+        let (state, rx) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        (state.with_bandwidth_manager(BandwidthManager::new(10_000)), rx)
+    }
+
+    #[test]
+    fn test_observe_feeds_peers_and_stats() {
+        let (state, _rx) = state();
+        let header = FleetMsgHeader::new(MessageType::Data, 42, 3, 5);
+        state.observe(&header, b"hello", "10.0.0.42:5000".parse().unwrap());
+
+        match state.handle(AdminRequest::ListPeers) {
+            AdminResponse::Peers { peers } => {
+                assert_eq!(peers.len(), 1);
+                assert_eq!(peers[0].sender_id, 42);
+                assert_eq!(peers[0].last_sequence, 3);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        match state.handle(AdminRequest::GetStats) {
+            AdminResponse::Stats { stats } => assert_eq!(stats.messages_received, 1),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        let goodbye = FleetMsgHeader::new(MessageType::Goodbye, 42, 4, 0);
+        state.observe(&goodbye, b"", "10.0.0.42:5000".parse().unwrap());
+        assert!(state.peers().lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_rate_limit_updates_manager() {
+        let (state, _rx) = state();
+        let response = state.handle(AdminRequest::SetRateLimit {
+            total_bytes_per_sec: Some(20_000),
+            class: Some(MessageClass::Bulk),
+            share: Some(0.25),
+        });
+
+        match response {
+            AdminResponse::RateLimits { total_bytes_per_sec, classes } => {
+                assert_eq!(total_bytes_per_sec, 20_000);
+                let bulk = classes.iter().find(|c| c.class == MessageClass::Bulk).unwrap();
+                assert_eq!(bulk.bytes_per_sec, 5_000);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        // Shares can't add up to more than the whole link
+        let response = state.handle(AdminRequest::SetRateLimit {
+            total_bytes_per_sec: Some(40_000),
+            class: Some(MessageClass::Bulk),
+            share: Some(0.9),
+        });
+        assert!(matches!(response, AdminResponse::Error { .. }));
+        assert!(matches!(state.rate_limits(), AdminResponse::RateLimits { total_bytes_per_sec: 20_000, .. }));
+    }
+
+    #[test]
+    fn test_commands_are_forwarded_to_node() {
+        let (state, rx) = state();
+        assert_eq!(state.handle(AdminRequest::Ping { target: Some(7) }), AdminResponse::Accepted);
+        assert_eq!(rx.try_recv().unwrap(), AdminCommand::Ping { target: Some(7) });
+
+        drop(rx);
+        assert!(matches!(state.handle(AdminRequest::StopCapture), AdminResponse::Error { .. }));
+    }
+
+    #[test]
+    fn test_requests_round_trip_as_json() {
+        let request = AdminRequest::StartCapture { path: "/tmp/cap.bin".into() };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"op":"start_capture","path":"/tmp/cap.bin"}"#);
+        assert_eq!(serde_json::from_str::<AdminRequest>(&json).unwrap(), request);
+    }
+}
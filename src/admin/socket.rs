@@ -0,0 +1,90 @@
+use async_std::io::BufReader;
+use async_std::io::prelude::{BufReadExt, WriteExt};
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use async_std::task;
+use futures::StreamExt;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use super::{AdminRequest, AdminResponse, AdminState};
+
+/// Serve [`AdminState`] on a Unix socket: one JSON [`AdminRequest`] per line in,
+/// one JSON [`AdminResponse`] per line out. The socket is created owner-only,
+/// so its file permissions are the authentication.
+///
+/// ```text
+/// $ echo '{"op":"get_stats"}' | nc -U /run/fleetlinkd.sock
+/// {"result":"stats","stats":{"messages_sent":12,...}}
+/// ```
+pub async fn serve(state: AdminState, path: impl AsRef<Path>) -> std::io::Result<()> {
+    let path = path.as_ref();
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path).await?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    println!("Admin control socket listening on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        task::spawn(async move {
+            if let Err(e) = handle_connection(&state, stream).await {
+                eprintln!("Admin control socket error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(state: &AdminState, stream: UnixStream) -> std::io::Result<()> {
+    let mut lines = BufReader::new(stream.clone()).lines();
+    let mut writer = stream;
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(request) => state.handle(request),
+            Err(e) => AdminResponse::Error { message: format!("invalid request: {}", e) },
+        };
+        let mut reply = serde_json::to_vec(&response)?;
+        reply.push(b'\n');
+        writer.write_all(&reply).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::PeerTable;
+    use crate::stats::TransportStats;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn test_line_protocol() {
+        let path = std::env::temp_dir().join(format!("fleetlink-admin-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (state, _commands) = AdminState::new(Arc::new(Mutex::new(PeerTable::new())), Arc::new(TransportStats::new()));
+        state.stats().record_sent(40);
+        task::spawn(serve(state, path.clone()));
+        // The socket file appears just before the listener starts listening
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => task::sleep(Duration::from_millis(5)).await,
+            }
+        };
+        stream.write_all(b"{\"op\":\"get_stats\"}\n\nnot json\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let stats: AdminResponse = serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(stats, AdminResponse::Stats { stats } if stats.messages_sent == 1));
+        let error: AdminResponse = serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(error, AdminResponse::Error { .. }));
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,286 @@
+//! Transport-health alerts, so loss, latency or losing sight of the fleet can
+//! drive vehicle behaviour (slow down, stop) without external monitoring.
+//!
+//! Feed an [`AlertMonitor`] each received message with [`AlertMonitor::observe`]
+//! and call [`AlertMonitor::check`] periodically with the current stats and peer
+//! count. Crossing a threshold raises an alert once and falling back below it
+//! clears it; both are passed to the registered callbacks and returned, ready
+//! to be forwarded to the fleet as Control messages with [`broadcast`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::soak::{self, LatencySummary};
+use crate::stats::StatsSnapshot;
+use crate::transport::{FleetMsgHeader, MulticastSender};
+
+/// Control commands carrying an alert start with this, followed by the event as JSON
+pub const ALERT_COMMAND_PREFIX: &str = "ALERT ";
+
+/// A sender's loss is only judged once this many messages were expected from it since the last check
+pub const MIN_LOSS_SAMPLES: u64 = 20;
+
+/// Latency samples kept between checks; older ones are dropped first
+const MAX_LATENCY_SAMPLES: usize = 10_000;
+
+/// Limits beyond which an alert is raised; `None` disables that check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertThresholds {
+    /// Per-sender loss over one check interval
+    pub max_loss_percent: Option<f64>,
+    /// 99th percentile one-way latency over one check interval, from header timestamps
+    pub max_latency_p99: Option<Duration>,
+    /// How long no peer may be visible
+    pub no_peers_for: Option<Duration>,
+}
+
+impl Default for AlertThresholds {
+    /// Loss above 5%, p99 latency above 50ms, or no peers for 10s
+    fn default() -> Self {
+        Self {
+            max_loss_percent: Some(5.0),
+            max_latency_p99: Some(Duration::from_millis(50)),
+            no_peers_for: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// A transport-health problem
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    Loss { sender_id: u32, loss_percent: f64 },
+    Latency { p99_us: u64 },
+    NoPeers { silent_for_ms: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum AlertEvent {
+    /// A threshold was crossed
+    Raised { alert: Alert },
+    /// The condition behind an earlier `Raised` is over; carries the latest value
+    Cleared { alert: Alert },
+}
+
+impl AlertEvent {
+    /// The Control command announcing this event to the fleet
+    pub fn control_command(&self) -> String {
+        format!("{}{}", ALERT_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`AlertEvent::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(ALERT_COMMAND_PREFIX)?).ok()
+    }
+}
+
+type Callback = Box<dyn FnMut(&AlertEvent) + Send>;
+
+/// Watches stats and latency against [`AlertThresholds`]
+pub struct AlertMonitor {
+    thresholds: AlertThresholds,
+    callbacks: Vec<Callback>,
+    latencies_us: VecDeque<u64>,
+    /// Unique messages received and missing per sender at the last check
+    counts: BTreeMap<u32, (u64, u64)>,
+    lossy: BTreeMap<u32, f64>,
+    latency_raised: bool,
+    last_peer_seen: Instant,
+    no_peers_raised: bool,
+}
+
+impl std::fmt::Debug for AlertMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AlertMonitor")
+            .field("thresholds", &self.thresholds)
+            .field("callbacks", &self.callbacks.len())
+            .field("lossy", &self.lossy)
+            .field("latency_raised", &self.latency_raised)
+            .field("no_peers_raised", &self.no_peers_raised)
+            .finish()
+    }
+}
+
+impl AlertMonitor {
+    pub fn new(thresholds: AlertThresholds, now: Instant) -> Self {
+        Self {
+            thresholds,
+            callbacks: Vec::new(),
+            latencies_us: VecDeque::new(),
+            counts: BTreeMap::new(),
+            lossy: BTreeMap::new(),
+            latency_raised: false,
+            last_peer_seen: now,
+            no_peers_raised: false,
+        }
+    }
+
+    /// Call `callback` with every event as it is raised or cleared
+    pub fn with_callback(mut self, callback: impl FnMut(&AlertEvent) + Send + 'static) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    pub fn thresholds(&self) -> &AlertThresholds {
+        &self.thresholds
+    }
+
+    /// Record the one-way latency of a received message from its header timestamp
+    pub fn observe(&mut self, header: &FleetMsgHeader) {
+        if self.latencies_us.len() == MAX_LATENCY_SAMPLES {
+            self.latencies_us.pop_front();
+        }
+        self.latencies_us.push_back(soak::now_micros().saturating_sub(header.timestamp_micros()));
+    }
+
+    /// Compare everything since the last check against the thresholds.
+    ///
+    /// `peers` is how many peers are currently visible, e.g. from the `PeerTable`
+    /// or [`Membership::visible`](crate::membership::Membership::visible).
+    pub fn check(&mut self, stats: &StatsSnapshot, peers: usize, now: Instant) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        self.check_loss(stats, &mut events);
+        self.check_latency(&mut events);
+        self.check_peers(peers, now, &mut events);
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        events
+    }
+
+    fn check_loss(&mut self, stats: &StatsSnapshot, events: &mut Vec<AlertEvent>) {
+        let mut counts = BTreeMap::new();
+        for sender in &stats.senders {
+            let unique = sender.received - sender.duplicates;
+            counts.insert(sender.sender_id, (unique, sender.missing));
+            let Some(limit) = self.thresholds.max_loss_percent else {
+                continue;
+            };
+            // Counters start over when a sender leaves and comes back
+            let (last_unique, last_missing) = self.counts.get(&sender.sender_id).copied().unwrap_or_default();
+            let received = unique.saturating_sub(last_unique);
+            let missing = sender.missing.saturating_sub(last_missing);
+            if received + missing < MIN_LOSS_SAMPLES {
+                continue;
+            }
+
+            let loss_percent = missing as f64 * 100.0 / (received + missing) as f64;
+            let alert = Alert::Loss { sender_id: sender.sender_id, loss_percent };
+            match self.lossy.contains_key(&sender.sender_id) {
+                false if loss_percent > limit => {
+                    self.lossy.insert(sender.sender_id, loss_percent);
+                    events.push(AlertEvent::Raised { alert });
+                }
+                true if loss_percent <= limit => {
+                    self.lossy.remove(&sender.sender_id);
+                    events.push(AlertEvent::Cleared { alert });
+                }
+                _ => {}
+            }
+        }
+        // A sender that left can't be losing messages any more
+        let gone: Vec<u32> = self.lossy.keys().filter(|id| !counts.contains_key(id)).copied().collect();
+        for sender_id in gone {
+            let loss_percent = self.lossy.remove(&sender_id).unwrap_or_default();
+            events.push(AlertEvent::Cleared { alert: Alert::Loss { sender_id, loss_percent } });
+        }
+        self.counts = counts;
+    }
+
+    fn check_latency(&mut self, events: &mut Vec<AlertEvent>) {
+        let mut samples: Vec<u64> = self.latencies_us.drain(..).collect();
+        let Some(limit) = self.thresholds.max_latency_p99 else {
+            return;
+        };
+        if samples.is_empty() {
+            return;
+        }
+        let p99_us = LatencySummary::from_samples(&mut samples).p99;
+        let over = p99_us > limit.as_micros() as u64;
+        if over != self.latency_raised {
+            self.latency_raised = over;
+            let alert = Alert::Latency { p99_us };
+            events.push(if over { AlertEvent::Raised { alert } } else { AlertEvent::Cleared { alert } });
+        }
+    }
+
+    fn check_peers(&mut self, peers: usize, now: Instant, events: &mut Vec<AlertEvent>) {
+        let silent_for = now.saturating_duration_since(self.last_peer_seen);
+        if peers > 0 {
+            self.last_peer_seen = now;
+            if self.no_peers_raised {
+                self.no_peers_raised = false;
+                events.push(AlertEvent::Cleared { alert: Alert::NoPeers { silent_for_ms: silent_for.as_millis() as u64 } });
+            }
+        } else if let Some(limit) = self.thresholds.no_peers_for
+            && silent_for >= limit
+            && !self.no_peers_raised
+        {
+            self.no_peers_raised = true;
+            events.push(AlertEvent::Raised { alert: Alert::NoPeers { silent_for_ms: silent_for.as_millis() as u64 } });
+        }
+    }
+}
+
+/// Announce alert events to the fleet as Control messages
+pub async fn broadcast(sender: &mut MulticastSender, events: &[AlertEvent]) -> crate::error::Result<()> {
+    for event in events {
+        sender.send_control(&event.control_command()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence_stats::SenderSequenceStats;
+    use std::sync::{Arc, Mutex};
+
+    fn stats(received: u64, missing: u64) -> StatsSnapshot {
+        StatsSnapshot {
+            senders: vec![SenderSequenceStats { sender_id: 4, received, missing, ..Default::default() }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_loss_raises_once_and_clears() {
+        let start = Instant::now();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_cb = seen.clone();
+        let mut monitor = AlertMonitor::new(AlertThresholds::default(), start)
+            .with_callback(move |event| seen_cb.lock().unwrap().push(event.clone()));
+
+        assert!(monitor.check(&stats(100, 0), 1, start).is_empty());
+        // 10 of the next 100 went missing, then again, then none
+        let raised = monitor.check(&stats(190, 10), 1, start);
+        assert_eq!(raised, vec![AlertEvent::Raised { alert: Alert::Loss { sender_id: 4, loss_percent: 10.0 } }]);
+        assert!(monitor.check(&stats(280, 20), 1, start).is_empty());
+        let cleared = monitor.check(&stats(380, 20), 1, start);
+        assert_eq!(cleared, vec![AlertEvent::Cleared { alert: Alert::Loss { sender_id: 4, loss_percent: 0.0 } }]);
+        assert_eq!(*seen.lock().unwrap(), [raised, cleared].concat());
+    }
+
+    #[test]
+    fn test_no_peers_and_control_round_trip() {
+        let start = Instant::now();
+        let mut monitor = AlertMonitor::new(AlertThresholds::default(), start);
+        assert!(monitor.check(&StatsSnapshot::default(), 0, start + Duration::from_secs(9)).is_empty());
+
+        let events = monitor.check(&StatsSnapshot::default(), 0, start + Duration::from_secs(10));
+        assert_eq!(events, vec![AlertEvent::Raised { alert: Alert::NoPeers { silent_for_ms: 10_000 } }]);
+        assert!(monitor.check(&StatsSnapshot::default(), 0, start + Duration::from_secs(11)).is_empty());
+
+        let command = events[0].control_command();
+        assert!(command.starts_with(ALERT_COMMAND_PREFIX));
+        assert_eq!(AlertEvent::from_control(&command), Some(events[0].clone()));
+        assert_eq!(AlertEvent::from_control("PERF_TEST"), None);
+
+        let events = monitor.check(&StatsSnapshot::default(), 2, start + Duration::from_secs(12));
+        assert!(matches!(events[..], [AlertEvent::Cleared { alert: Alert::NoPeers { .. } }]));
+    }
+}
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static BYTES_ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+static SUBSYSTEM_ALLOCATIONS: [AtomicU64; Subsystem::COUNT] = [const { AtomicU64::new(0) }; Subsystem::COUNT];
+static SUBSYSTEM_BYTES: [AtomicU64; Subsystem::COUNT] = [const { AtomicU64::new(0) }; Subsystem::COUNT];
+
+thread_local! {
+    static CURRENT: Cell<Subsystem> = const { Cell::new(Subsystem::Other) };
+}
+
+/// System allocator wrapper that counts every allocation.
+///
+/// Install it in a benchmark or example binary with
+/// `#[global_allocator] static ALLOC: CountingAllocator = CountingAllocator;`.
+/// Counters are process-wide, so measure on a quiet thread.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CountingAllocator;
+
+/// Allocations made over some span of time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllocationCount {
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+impl AllocationCount {
+    /// Average per operation over `ops` operations
+    pub fn per_op(&self, ops: u64) -> (f64, f64) {
+        let ops = ops.max(1) as f64;
+        (self.allocations as f64 / ops, self.bytes as f64 / ops)
+    }
+}
+
+/// Parts of the transport whose allocations are counted separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// Receive buffers and payloads copied out of datagrams
+    RxBuffers,
+    /// Payload compression, encryption and extension parsing
+    Codec,
+    /// Peer tables and membership views
+    PeerTables,
+    /// Everything else, including application code
+    Other,
+}
+
+impl Subsystem {
+    const COUNT: usize = 4;
+}
+
+/// Allocations so far, by the subsystem that made them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubsystemAllocations {
+    pub rx_buffers: AllocationCount,
+    pub codec: AllocationCount,
+    pub peer_tables: AllocationCount,
+    pub other: AllocationCount,
+}
+
+impl SubsystemAllocations {
+    /// Each subsystem's name (as serialized) with its count
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, AllocationCount)> {
+        [("rx_buffers", self.rx_buffers), ("codec", self.codec), ("peer_tables", self.peer_tables), ("other", self.other)]
+            .into_iter()
+    }
+}
+
+/// Attributes this thread's allocations to a subsystem until dropped, then
+/// restores the previous one. Does nothing unless built with `alloc-count`.
+#[must_use]
+pub struct AllocationScope {
+    #[cfg(feature = "alloc-count")]
+    previous: Subsystem,
+}
+
+pub fn scope(subsystem: Subsystem) -> AllocationScope {
+    let _ = subsystem;
+    AllocationScope {
+        #[cfg(feature = "alloc-count")]
+        previous: CURRENT.with(|current| current.replace(subsystem)),
+    }
+}
+
+impl Drop for AllocationScope {
+    fn drop(&mut self) {
+        #[cfg(feature = "alloc-count")]
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+fn record(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    BYTES_ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+    LIVE_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    // The thread-local may already be gone while a thread shuts down
+    let subsystem = CURRENT.try_with(Cell::get).unwrap_or(Subsystem::Other) as usize;
+    SUBSYSTEM_ALLOCATIONS[subsystem].fetch_add(1, Ordering::Relaxed);
+    SUBSYSTEM_BYTES[subsystem].fetch_add(size as u64, Ordering::Relaxed);
+}
+
+fn release(size: usize) {
+    LIVE_BYTES.fetch_sub(size as u64, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        release(layout.size());
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        release(layout.size());
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+/// Totals since the process started
+pub fn current() -> AllocationCount {
+    AllocationCount {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        bytes: BYTES_ALLOCATED.load(Ordering::Relaxed),
+    }
+}
+
+/// Totals since the process started, by subsystem; `None` if the counting
+/// allocator isn't installed (or `alloc-count` is off, so nothing is attributed)
+pub fn by_subsystem() -> Option<SubsystemAllocations> {
+    if !cfg!(feature = "alloc-count") || ALLOCATIONS.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    let count = |subsystem: Subsystem| AllocationCount {
+        allocations: SUBSYSTEM_ALLOCATIONS[subsystem as usize].load(Ordering::Relaxed),
+        bytes: SUBSYSTEM_BYTES[subsystem as usize].load(Ordering::Relaxed),
+    };
+    Some(SubsystemAllocations {
+        rx_buffers: count(Subsystem::RxBuffers),
+        codec: count(Subsystem::Codec),
+        peer_tables: count(Subsystem::PeerTables),
+        other: count(Subsystem::Other),
+    })
+}
+
+/// Bytes allocated through the wrapper and not yet freed, for spotting leaks
+/// in long runs
+pub fn live_bytes() -> u64 {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+/// Run `f` and report the allocations it made (reallocations count as one each)
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, AllocationCount) {
+    let before = current();
+    let result = f();
+    let after = current();
+    (result, AllocationCount {
+        allocations: after.allocations - before.allocations,
+        bytes: after.bytes - before.bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_allocations_made_through_wrapper() {
+        let layout = Layout::from_size_align(128, 8).unwrap();
+        let (_, count) = measure(|| unsafe {
+            let ptr = CountingAllocator.alloc(layout);
+            assert!(!ptr.is_null());
+            CountingAllocator.dealloc(ptr, layout);
+        });
+
+        // Other test threads may allocate through the wrapper too, never fewer
+        assert!(count.allocations >= 1);
+        assert!(count.bytes >= 128);
+        assert_eq!(AllocationCount { allocations: 10, bytes: 640 }.per_op(5), (2.0, 128.0));
+    }
+
+    #[test]
+    fn test_scopes_attribute_allocations() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let peer_tables = || SUBSYSTEM_ALLOCATIONS[Subsystem::PeerTables as usize].load(Ordering::Relaxed);
+        let before = peer_tables();
+        {
+            let _scope = scope(Subsystem::PeerTables);
+            unsafe { CountingAllocator.dealloc(CountingAllocator.alloc(layout), layout) };
+        }
+
+        assert_eq!(peer_tables() - before, if cfg!(feature = "alloc-count") { 1 } else { 0 });
+        assert_eq!(CURRENT.with(Cell::get), Subsystem::Other);
+    }
+}
@@ -0,0 +1,267 @@
+//! Gap repair from journaled history: a node that missed part of a sender's
+//! stream asks peers that journal traffic for "messages from sender X since
+//! sequence S", and gets the journal entries back, original frames included.
+//!
+//! Nodes keeping a journal answer requests with [`BackfillServer::handle`],
+//! from a [`read_journal`](crate::journal::read_journal) file or a
+//! [`StateStore`](crate::store::StateStore) log. Others call
+//! [`BackfillClient::fetch`], which repeats the request until a reply arrives
+//! and asks for further pages while the journal has more.
+
+use async_std::channel::Receiver;
+use async_std::future::timeout;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+use crate::journal::JournalEntry;
+use crate::transport::MulticastSender;
+
+/// Control commands carrying a backfill message start with this, followed by the message as JSON
+pub const BACKFILL_COMMAND_PREFIX: &str = "BACKFILL ";
+
+/// How often an unanswered request is repeated
+pub const REQUEST_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Most entries a server sends in one reply by default
+pub const DEFAULT_MAX_ENTRIES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum BackfillMessage {
+    /// `node` asks for up to `limit` messages of `sender_id`, from sequence
+    /// `since` on, from journal `from` or any journaling peer
+    Request { request_id: u64, node: u32, sender_id: u32, since: u16, limit: usize, from: Option<u32> },
+    /// `server`'s journal entries for the request, oldest first; `more` if it
+    /// holds further entries past the last one
+    Reply { request_id: u64, node: u32, server: u32, entries: Vec<JournalEntry>, more: bool },
+}
+
+impl BackfillMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", BACKFILL_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`BackfillMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(BACKFILL_COMMAND_PREFIX)?).ok()
+    }
+}
+
+/// The messages of `sender_id` in `journal` from sequence `since` on, oldest
+/// first. Only the latest occurrence of `since` counts, so a sequence number
+/// reused after wrapping around doesn't pull in older history.
+pub fn since(journal: &[JournalEntry], sender_id: u32, since: u16) -> Vec<&JournalEntry> {
+    let mut run = Vec::new();
+    for entry in journal.iter().rev().filter(|entry| entry.sender_id == sender_id) {
+        if entry.sequence.wrapping_sub(since) >= 0x8000 {
+            break;
+        }
+        run.push(entry);
+        if entry.sequence == since {
+            break;
+        }
+    }
+    run.reverse();
+    run
+}
+
+/// Answers requests from a node's journal
+#[derive(Debug, Clone)]
+pub struct BackfillServer {
+    node: u32,
+    max_entries: usize,
+}
+
+impl BackfillServer {
+    pub fn new(node: u32) -> Self {
+        Self { node, max_entries: DEFAULT_MAX_ENTRIES }
+    }
+
+    /// Send at most this many entries per reply, however many are asked for
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// The reply to a request from `sender_id`, answered from `journal`; None
+    /// for anything else, requests meant for another server, or when the
+    /// journal has nothing from the requested sequence on
+    pub fn handle(&self, sender_id: u32, message: &BackfillMessage, journal: &[JournalEntry]) -> Option<BackfillMessage> {
+        let BackfillMessage::Request { request_id, node, sender_id: wanted, since: from, limit, from: server } = message else { return None };
+        if *node != sender_id || server.is_some_and(|server| server != self.node) {
+            return None;
+        }
+        let run = since(journal, *wanted, *from);
+        if run.is_empty() {
+            return None;
+        }
+        let limit = (*limit).clamp(1, self.max_entries);
+        Some(BackfillMessage::Reply {
+            request_id: *request_id,
+            node: *node,
+            server: self.node,
+            more: run.len() > limit,
+            entries: run.into_iter().take(limit).cloned().collect(),
+        })
+    }
+}
+
+/// A node asking journaling peers for history
+#[derive(Debug, Clone)]
+pub struct BackfillClient {
+    node: u32,
+    next_request: u64,
+    server: Option<u32>,
+}
+
+impl BackfillClient {
+    /// Request ids start from the clock, so ids from before a restart aren't reused
+    pub fn new(node: u32) -> Self {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        Self { node, next_request: now.as_micros() as u64, server: None }
+    }
+
+    /// Ask only this peer's journal, instead of whichever answers first
+    pub fn with_server(mut self, server: u32) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    /// The request for `limit` messages of `sender_id` from `since` on, with a fresh request id
+    pub fn request(&mut self, sender_id: u32, since: u16, limit: usize) -> BackfillMessage {
+        self.next_request += 1;
+        BackfillMessage::Request { request_id: self.next_request, node: self.node, sender_id, since, limit, from: self.server }
+    }
+
+    /// Fetch up to `limit` messages of `sender_id` from sequence `since` on,
+    /// page by page, taking replies from `messages`. Fails with `TimedOut` if
+    /// no journal has answered within `time_limit`; once one has, what it
+    /// sent is returned even if later pages time out.
+    pub async fn fetch(
+        &mut self,
+        sender: &mut MulticastSender,
+        messages: &Receiver<BackfillMessage>,
+        sender_id: u32,
+        since: u16,
+        limit: usize,
+        time_limit: Duration,
+    ) -> std::io::Result<Vec<JournalEntry>> {
+        let deadline = Instant::now() + time_limit;
+        let mut fetched: Vec<JournalEntry> = Vec::new();
+        let mut next = since;
+        while fetched.len() < limit {
+            let request = self.request(sender_id, next, limit - fetched.len());
+            let (entries, more) = match self.exchange(sender, messages, &request, deadline).await {
+                Ok(reply) => reply,
+                Err(e) if e.kind() == ErrorKind::TimedOut && !fetched.is_empty() => break,
+                Err(e) => return Err(e),
+            };
+            let Some(last) = entries.last() else { break };
+            next = last.sequence.wrapping_add(1);
+            fetched.extend(entries);
+            if !more {
+                break;
+            }
+        }
+        Ok(fetched)
+    }
+
+    /// Send `request` until its reply arrives
+    async fn exchange(
+        &self,
+        sender: &mut MulticastSender,
+        messages: &Receiver<BackfillMessage>,
+        request: &BackfillMessage,
+        deadline: Instant,
+    ) -> std::io::Result<(Vec<JournalEntry>, bool)> {
+        let BackfillMessage::Request { request_id, .. } = request else { unreachable!("only requests are sent") };
+        loop {
+            sender.send_control(&request.control_command()).await?;
+            let retry = (Instant::now() + REQUEST_RETRY_INTERVAL).min(deadline);
+            while let Some(wait) = retry.checked_duration_since(Instant::now()).filter(|wait| !wait.is_zero()) {
+                match timeout(wait, messages.recv()).await {
+                    Ok(Ok(BackfillMessage::Reply { request_id: id, node, entries, more, .. })) if id == *request_id && node == self.node => {
+                        return Ok((entries, more));
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(_)) => return Err(Error::new(ErrorKind::BrokenPipe, "backfill message channel closed")),
+                    Err(_) => break,
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, "backfill: no journal answered"));
+            }
+        }
+    }
+}
+
+/// Multicast a backfill message to the fleet as a Control command
+pub async fn send(sender: &mut MulticastSender, message: &BackfillMessage) -> crate::error::Result<()> {
+    sender.send_control(&message.control_command()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{FleetMsgHeader, MessageType};
+
+    fn journal(sender_id: u32, sequences: impl IntoIterator<Item = u16>) -> Vec<JournalEntry> {
+        sequences.into_iter()
+            .map(|sequence| {
+                let header = FleetMsgHeader::new(MessageType::Data, sender_id, sequence, 2);
+                JournalEntry::new(&header, b"ok", "10.0.0.1:5000".parse().unwrap(), 0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_server_answers_latest_run_in_pages() {
+        // Sender 5 wrapped around since sequence 65534 was first journaled
+        let mut history = journal(5, [65534, 65535]);
+        history.extend(journal(6, [1, 2]));
+        history.extend(journal(5, (0..40).chain([65534, 65535, 0, 1, 2])));
+        let server = BackfillServer::new(9).with_max_entries(3);
+        let mut client = BackfillClient::new(2);
+
+        let reply = server.handle(2, &client.request(5, 65534, 10), &history).unwrap();
+        let BackfillMessage::Reply { entries, more, server: 9, .. } = &reply else { panic!("not a reply: {:?}", reply) };
+        assert_eq!(entries.iter().map(|entry| entry.sequence).collect::<Vec<_>>(), vec![65534, 65535, 0]);
+        assert!(*more);
+        assert_eq!(BackfillMessage::from_control(&reply.control_command()), Some(reply.clone()));
+
+        // Forged requests, requests for another journal and nothing to send go unanswered
+        assert_eq!(server.handle(3, &client.request(5, 0, 10), &history), None);
+        assert_eq!(server.handle(2, &client.clone().with_server(8).request(5, 0, 10), &history), None);
+        assert_eq!(server.handle(2, &client.request(5, 3, 10), &history), None);
+        assert_eq!(since(&history, 6, 0).len(), 2);
+    }
+
+    #[async_std::test]
+    async fn test_fetch_follows_pages() {
+        use crate::testing::TestReceiver;
+
+        let receiver = TestReceiver::start().await.unwrap();
+        let mut sender = receiver.sender(2).await.unwrap();
+        let (tx, rx) = async_std::channel::unbounded();
+        let history = journal(5, 100..110);
+
+        // The journaling peer answers each request it hears, three entries at a time
+        let answering = async_std::task::spawn(async move {
+            let server = BackfillServer::new(9).with_max_entries(3);
+            let mut answered = 0;
+            while answered < 3 {
+                let received = receiver.wait_for(answered + 1, Duration::from_secs(5)).await;
+                let command = String::from_utf8_lossy(&received[answered].1).into_owned();
+                let request = BackfillMessage::from_control(&command).unwrap();
+                tx.send(server.handle(2, &request, &history).unwrap()).await.unwrap();
+                answered += 1;
+            }
+        });
+        let mut client = BackfillClient::new(2);
+        let entries = client.fetch(&mut sender, &rx, 5, 102, 7, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.sequence).collect::<Vec<_>>(), (102..109).collect::<Vec<_>>());
+        answering.await;
+    }
+}
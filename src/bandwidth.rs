@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{self, TransportError};
+use crate::transport::MessageType;
+
+/// Traffic classes that share the link budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageClass {
+    Control,
+    Telemetry,
+    Bulk,
+}
+
+impl MessageClass {
+    pub const ALL: [MessageClass; 3] = [MessageClass::Control, MessageClass::Telemetry, MessageClass::Bulk];
+
+    /// Default class used when a message is sent without an explicit class;
+    /// application-defined types count as telemetry, like Data
+    pub fn for_message_type(msg_type: MessageType) -> Self {
+        match msg_type {
+            MessageType::Heartbeat | MessageType::Control | MessageType::Goodbye | MessageType::Digest => {
+                MessageClass::Control
+            }
+            MessageType::Data | MessageType::Application(_) | MessageType::Unknown(_) => MessageClass::Telemetry,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ClassBucket {
+    share: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    total_bytes_per_sec: u64,
+    burst: Duration,
+    classes: BTreeMap<MessageClass, ClassBucket>,
+}
+
+impl BudgetState {
+    fn rate(&self, class: MessageClass) -> f64 {
+        self.total_bytes_per_sec as f64 * self.classes[&class].share
+    }
+
+    fn capacity(&self, class: MessageClass) -> f64 {
+        self.rate(class) * self.burst.as_secs_f64()
+    }
+
+    fn refill(&mut self, class: MessageClass, now: Instant) {
+        let rate = self.rate(class);
+        let capacity = self.capacity(class);
+        let bucket = self.classes.get_mut(&class).expect("all classes are initialized");
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = now;
+    }
+}
+
+/// Splits a total link budget (bytes/sec) into per-class token buckets.
+///
+/// Cloning yields another handle to the same budget, so limits can be changed
+/// at runtime while senders keep using it.
+#[derive(Debug, Clone)]
+pub struct BandwidthManager {
+    state: Arc<Mutex<BudgetState>>,
+}
+
+impl BandwidthManager {
+    pub fn new(total_bytes_per_sec: u64) -> Self {
+        let now = Instant::now();
+        let classes = [
+            (MessageClass::Control, 0.2),
+            (MessageClass::Telemetry, 0.5),
+            (MessageClass::Bulk, 0.3),
+        ]
+        .into_iter()
+        .map(|(class, share)| (class, ClassBucket { share, tokens: 0.0, last_refill: now }))
+        .collect();
+
+        let mut state = BudgetState {
+            total_bytes_per_sec,
+            burst: Duration::from_secs(1),
+            classes,
+        };
+        for class in MessageClass::ALL {
+            let capacity = state.capacity(class);
+            state.classes.get_mut(&class).unwrap().tokens = capacity;
+        }
+
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Fraction (0.0..=1.0) of the total budget reserved for `class`
+    pub fn with_share(self, class: MessageClass, share: f64) -> error::Result<Self> {
+        self.set_share(class, share)?;
+        Ok(self)
+    }
+
+    /// How much unused budget a class may accumulate for bursts
+    pub fn with_burst(self, burst: Duration) -> Self {
+        self.state.lock().unwrap().burst = burst;
+        self
+    }
+
+    /// Fails with [`TransportError::Misconfigured`] if `share` is outside
+    /// 0.0..=1.0 or would bring the classes' shares to more than the whole budget
+    pub fn set_share(&self, class: MessageClass, share: f64) -> error::Result<()> {
+        if !(0.0..=1.0).contains(&share) {
+            return Err(TransportError::Misconfigured(format!("share {} is not between 0 and 1", share)));
+        }
+        let mut state = self.state.lock().unwrap();
+        let others: f64 = state.classes.iter().filter(|(other, _)| **other != class).map(|(_, bucket)| bucket.share).sum();
+        if others + share > 1.0 + f64::EPSILON {
+            let reason = format!("a {} share for {:?} would bring the shares to {:.2} of the budget", share, class, others + share);
+            return Err(TransportError::Misconfigured(reason));
+        }
+        state.refill(class, Instant::now());
+        state.classes.get_mut(&class).unwrap().share = share;
+        Ok(())
+    }
+
+    pub fn set_total_rate(&self, total_bytes_per_sec: u64) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        for class in MessageClass::ALL {
+            state.refill(class, now);
+        }
+        state.total_bytes_per_sec = total_bytes_per_sec;
+    }
+
+    pub fn total_rate(&self) -> u64 {
+        self.state.lock().unwrap().total_bytes_per_sec
+    }
+
+    pub fn share(&self, class: MessageClass) -> f64 {
+        self.state.lock().unwrap().classes[&class].share
+    }
+
+    /// Bytes/sec currently allotted to `class`
+    pub fn class_rate(&self, class: MessageClass) -> u64 {
+        self.state.lock().unwrap().rate(class) as u64
+    }
+
+    /// Take `bytes` from the class budget, or return how long to wait first;
+    /// `Duration::MAX` if the class has no budget at all.
+    ///
+    /// A message larger than the burst capacity is admitted once the bucket is
+    /// full and leaves the bucket in debt, so oversized messages are slowed
+    /// down rather than blocked forever.
+    pub fn try_acquire(&self, class: MessageClass, bytes: usize, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        state.refill(class, now);
+
+        let rate = state.rate(class);
+        if rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        let needed = (bytes as f64).min(state.capacity(class));
+        let bucket = state.classes.get_mut(&class).unwrap();
+
+        if bucket.tokens >= needed {
+            bucket.tokens -= bytes as f64;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((needed - bucket.tokens) / rate))
+    }
+
+    /// Wait until `bytes` fit into the class budget and consume them. Fails
+    /// with [`TransportError::Held`] rather than waiting forever when the
+    /// class has no budget (a zero share or total rate).
+    pub async fn acquire(&self, class: MessageClass, bytes: usize) -> error::Result<()> {
+        loop {
+            match self.try_acquire(class, bytes, Instant::now()) {
+                Ok(()) => return Ok(()),
+                Err(Duration::MAX) => return Err(TransportError::Held { class, delay: Duration::MAX }),
+                Err(wait) => async_std::task::sleep(wait.min(Duration::from_secs(1))).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shares_split_total_rate() {
+        let manager = BandwidthManager::new(10_000).with_share(MessageClass::Bulk, 0.3).unwrap();
+
+        assert_eq!(manager.class_rate(MessageClass::Bulk), 3_000);
+        manager.set_total_rate(20_000);
+        assert_eq!(manager.class_rate(MessageClass::Bulk), 6_000);
+    }
+
+    #[test]
+    fn test_bucket_throttles_and_refills() {
+        let manager = BandwidthManager::new(10_000).with_share(MessageClass::Bulk, 0.3).unwrap();
+        let start = Instant::now();
+
+        // Full one-second burst is available immediately
+        assert!(manager.try_acquire(MessageClass::Bulk, 3_000, start).is_ok());
+
+        let wait = manager.try_acquire(MessageClass::Bulk, 1_500, start).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.5).abs() < 0.01);
+
+        assert!(manager.try_acquire(MessageClass::Bulk, 1_500, start + Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn test_classes_are_independent() {
+        let manager = BandwidthManager::new(10_000);
+        let now = Instant::now();
+
+        assert!(manager.try_acquire(MessageClass::Bulk, 3_000, now).is_ok());
+        assert!(manager.try_acquire(MessageClass::Bulk, 100, now).is_err());
+        assert!(manager.try_acquire(MessageClass::Control, 100, now).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_message_goes_into_debt() {
+        let manager = BandwidthManager::new(1_000)
+            .with_share(MessageClass::Control, 0.0).unwrap()
+            .with_share(MessageClass::Telemetry, 0.0).unwrap()
+            .with_share(MessageClass::Bulk, 1.0).unwrap();
+        // Let the bucket fill up to its new capacity
+        let now = Instant::now() + Duration::from_secs(1);
+
+        assert!(manager.try_acquire(MessageClass::Bulk, 5_000, now).is_ok());
+        let wait = manager.try_acquire(MessageClass::Bulk, 1, now).unwrap_err();
+        assert!(wait >= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_shares_stay_within_budget() {
+        let manager = BandwidthManager::new(10_000);
+        for share in [0.5, -0.1, f64::NAN] {
+            assert!(matches!(manager.set_share(MessageClass::Bulk, share), Err(TransportError::Misconfigured(_))), "{}", share);
+        }
+        assert_eq!(manager.share(MessageClass::Bulk), 0.3);
+
+        manager.set_share(MessageClass::Telemetry, 0.2).unwrap();
+        manager.set_share(MessageClass::Bulk, 0.6).unwrap();
+        assert_eq!(manager.class_rate(MessageClass::Bulk), 6_000);
+    }
+
+    #[async_std::test]
+    async fn test_zero_share_fails_instead_of_hanging() {
+        let manager = BandwidthManager::new(10_000).with_share(MessageClass::Bulk, 0.0).unwrap();
+        let error = manager.acquire(MessageClass::Bulk, 100).await.unwrap_err();
+        assert!(matches!(error, TransportError::Held { class: MessageClass::Bulk, delay: Duration::MAX }));
+        assert!(manager.acquire(MessageClass::Control, 100).await.is_ok());
+    }
+}
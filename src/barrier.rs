@@ -0,0 +1,198 @@
+//! Fleet-wide rendezvous: a named [`Barrier`] between a set of nodes that
+//! each node waits on until every one of them has announced it is ready, for
+//! coordinated manoeuvres or synchronized test starts.
+//!
+//! Every waiting node multicasts its readiness, along with the participants it
+//! has heard from, and repeats it until the barrier opens, so lost datagrams
+//! only delay it. A node that has heard from everyone says so, which opens the
+//! barrier for nodes that missed some of the announcements.
+//!
+//! Readiness arrives as Control commands: parse them with
+//! [`BarrierMessage::from_control`] in the receive handler and pass them to
+//! [`Barrier::wait`] through a channel, with the sender's id.
+
+use async_std::channel::Receiver;
+use async_std::future::timeout;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+use crate::transport::MulticastSender;
+
+/// Control commands carrying a barrier message start with this, followed by the message as JSON
+pub const BARRIER_COMMAND_PREFIX: &str = "BARRIER ";
+
+/// How often a waiting node repeats its readiness by default
+pub const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `node` is waiting at barrier `name` and has heard `ready` from these participants (itself included)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BarrierMessage {
+    pub name: String,
+    pub node: u32,
+    pub ready: Vec<u32>,
+}
+
+impl BarrierMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", BARRIER_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`BarrierMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(BARRIER_COMMAND_PREFIX)?).ok()
+    }
+}
+
+/// One node's view of a named barrier
+#[derive(Debug, Clone)]
+pub struct Barrier {
+    name: String,
+    node: u32,
+    participants: BTreeSet<u32>,
+    ready: BTreeSet<u32>,
+    announce_interval: Duration,
+}
+
+impl Barrier {
+    /// `node` is counted as a participant and as ready
+    pub fn new(name: impl Into<String>, node: u32, participants: impl IntoIterator<Item = u32>) -> Self {
+        let mut participants: BTreeSet<u32> = participants.into_iter().collect();
+        participants.insert(node);
+        Self { name: name.into(), node, participants, ready: BTreeSet::from([node]), announce_interval: DEFAULT_ANNOUNCE_INTERVAL }
+    }
+
+    pub fn with_announce_interval(mut self, interval: Duration) -> Self {
+        self.announce_interval = interval;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// This node's readiness, to multicast
+    pub fn announcement(&self) -> BarrierMessage {
+        BarrierMessage { name: self.name.clone(), node: self.node, ready: self.ready.iter().copied().collect() }
+    }
+
+    /// Take in an announcement from `sender_id`; returns whether the barrier is now open
+    pub fn handle(&mut self, sender_id: u32, message: &BarrierMessage) -> bool {
+        if message.name != self.name || message.node != sender_id || !self.participants.contains(&sender_id) {
+            return self.is_open();
+        }
+        self.ready.insert(sender_id);
+        // A participant that has heard from everyone opens the barrier for us too
+        if self.participants.iter().all(|participant| message.ready.contains(participant)) {
+            self.ready.extend(self.participants.iter().copied());
+        }
+        self.is_open()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.ready.len() == self.participants.len()
+    }
+
+    /// Participants not yet heard from
+    pub fn missing(&self) -> Vec<u32> {
+        self.participants.difference(&self.ready).copied().collect()
+    }
+
+    /// Announce readiness until every participant is ready, taking the others'
+    /// announcements from `messages`. Fails with `TimedOut`, naming the
+    /// participants still missing, if that takes longer than `limit`.
+    pub async fn wait(&mut self, sender: &mut MulticastSender, messages: &Receiver<(u32, BarrierMessage)>, limit: Duration) -> std::io::Result<()> {
+        let deadline = Instant::now() + limit;
+        while !self.is_open() {
+            sender.send_control(&self.announcement().control_command()).await?;
+            let next_announcement = (Instant::now() + self.announce_interval).min(deadline);
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    let missing: Vec<String> = self.missing().iter().map(u32::to_string).collect();
+                    let msg = format!("barrier {}: no readiness from {}", self.name, missing.join(", "));
+                    return Err(Error::new(ErrorKind::TimedOut, msg));
+                }
+                if now >= next_announcement {
+                    break;
+                }
+                match timeout(next_announcement - now, messages.recv()).await {
+                    Ok(Ok((sender_id, message))) => {
+                        if self.handle(sender_id, &message) {
+                            break;
+                        }
+                    }
+                    Ok(Err(_)) => return Err(Error::new(ErrorKind::BrokenPipe, "barrier message channel closed")),
+                    Err(_) => break,
+                }
+            }
+        }
+        // Tell the stragglers that everyone is ready; twice, in case one is lost
+        for _ in 0..2 {
+            sender.send_control(&self.announcement().control_command()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::{Delivery, ReceiverConfig};
+    use crate::testing::bind_free_channel;
+    use crate::transport::{self, MessageType};
+    use async_std::channel;
+    use async_std::task;
+
+    #[test]
+    fn test_opens_when_all_heard_or_vouched_for() {
+        let mut barrier = Barrier::new("launch", 1, [1, 2, 3]);
+        let from = |node, ready: &[u32]| BarrierMessage { name: "launch".into(), node, ready: ready.to_vec() };
+        assert!(!barrier.handle(2, &from(2, &[2])));
+        // Other barriers, forged senders and non-participants don't count
+        assert!(!barrier.handle(3, &BarrierMessage { name: "land".into(), ..from(3, &[3]) }));
+        assert!(!barrier.handle(2, &from(3, &[3])));
+        assert!(!barrier.handle(4, &from(4, &[4])));
+        assert_eq!(barrier.missing(), vec![3]);
+
+        let mut late = Barrier::new("launch", 3, [1, 2, 3]);
+        assert!(barrier.handle(3, &late.announcement()));
+        // Node 3 missed 2's announcement, but 1 has heard from everyone
+        assert!(late.handle(1, &barrier.announcement()));
+    }
+
+    #[async_std::test]
+    async fn test_wait_over_multicast() {
+        let (channel, socket) = bind_free_channel().await.unwrap();
+        let (inboxes, outboxes): (Vec<_>, Vec<_>) = (0..2).map(|_| channel::unbounded()).unzip();
+        task::spawn(transport::receive_loop(socket, ReceiverConfig::new(), move |delivery: Delivery| {
+            let command = String::from_utf8_lossy(&delivery.payload);
+            if delivery.header.message_type() == MessageType::Control
+                && let Some(message) = BarrierMessage::from_control(&command)
+            {
+                for inbox in &inboxes {
+                    let _ = inbox.try_send((delivery.header.sender_id.get(), message.clone()));
+                }
+            }
+        }));
+
+        // Both nodes share the one receiver here, so each also sees its own announcements
+        let mut waiters = Vec::new();
+        for (node, rx) in [1, 2].into_iter().zip(outboxes) {
+            let mut sender = MulticastSender::new(channel.group, channel.port, node).await.unwrap();
+            let mut barrier = Barrier::new("start", node, [1, 2]).with_announce_interval(Duration::from_millis(20));
+            waiters.push(task::spawn(async move { barrier.wait(&mut sender, &rx, Duration::from_secs(5)).await }));
+        }
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+
+        let mut sender = MulticastSender::new(channel.group, channel.port, 5).await.unwrap();
+        let mut lonely = Barrier::new("alone", 5, [5, 6]);
+        let (_tx, rx) = channel::unbounded();
+        let err = lonely.wait(&mut sender, &rx, Duration::from_millis(100)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+}
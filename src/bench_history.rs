@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+use crate::soak::{self, SoakReport};
+
+/// One benchmark run, as stored in a history file (one JSON object per line)
+/// so results can be charted across commits and machines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchRecord {
+    /// Microseconds since the Unix epoch
+    pub recorded_at_us: u64,
+    /// Which harness produced it: `criterion`, `soak_benchmark`, `cpp_comparison`
+    pub bench: String,
+    /// Commit the benchmark was built from, if run inside a git checkout
+    pub git_hash: Option<String>,
+    pub hostname: String,
+    /// Operating system and architecture, e.g. `linux x86_64`
+    pub os: String,
+    /// Named results; units are part of the name (`_ns`, `_us`, `_per_sec`)
+    pub metrics: BTreeMap<String, f64>,
+}
+
+impl BenchRecord {
+    /// An empty record for `bench`, stamped with the current time, commit and machine
+    pub fn new(bench: &str) -> Self {
+        Self {
+            recorded_at_us: soak::now_micros(),
+            bench: bench.to_string(),
+            git_hash: git_hash(),
+            hostname: hostname(),
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            metrics: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_metric(mut self, name: impl Into<String>, value: f64) -> Self {
+        self.metrics.insert(name.into(), value);
+        self
+    }
+
+    /// The headline numbers of a soak run
+    pub fn from_soak(report: &SoakReport) -> Self {
+        Self::new("soak_benchmark")
+            .with_metric("send_rate_per_sec", report.achieved_send_rate)
+            .with_metric("receive_rate_per_sec", report.achieved_receive_rate)
+            .with_metric("messages_lost", report.messages_lost as f64)
+            .with_metric("latency_p50_us", report.latency_us.p50 as f64)
+            .with_metric("latency_p99_us", report.latency_us.p99 as f64)
+            .with_metric("latency_max_us", report.latency_us.max as f64)
+    }
+}
+
+/// Add a record to the end of a history file, creating it if needed
+pub fn append(path: impl AsRef<Path>, record: &BenchRecord) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)
+}
+
+/// Load every record of a history file, oldest first, skipping blank lines
+pub fn read_history(path: impl AsRef<Path>) -> std::io::Result<Vec<BenchRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("history line {}: {}", number + 1, e))
+        })?);
+    }
+    Ok(records)
+}
+
+/// Mean time per iteration, in nanoseconds, of every criterion benchmark under
+/// `dir` (usually `target/criterion`) that was measured at or after `since`,
+/// keyed by its id (`serialization/rust_zerocopy/64`)
+pub fn criterion_means(dir: impl AsRef<Path>, since: SystemTime) -> std::io::Result<BTreeMap<String, f64>> {
+    let mut means = BTreeMap::new();
+    collect_criterion_means(dir.as_ref(), dir.as_ref(), since, &mut means)?;
+    Ok(means)
+}
+
+fn collect_criterion_means(root: &Path, dir: &Path, since: SystemTime, means: &mut BTreeMap<String, f64>) -> std::io::Result<()> {
+    // Criterion keeps the latest measurement of each benchmark in `<id>/new/estimates.json`
+    let estimates = dir.join("new").join("estimates.json");
+    if let Ok(metadata) = fs::metadata(&estimates)
+        && metadata.modified()? >= since
+    {
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&estimates)?)?;
+        if let (Some(mean), Ok(id)) = (json["mean"]["point_estimate"].as_f64(), dir.strip_prefix(root)) {
+            means.insert(id.to_string_lossy().replace('\\', "/"), mean);
+        }
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        // `report` holds the HTML, `base`/`new`/`change` a benchmark's own data
+        if entry.file_type()?.is_dir() && !matches!(name.to_str(), Some("report" | "base" | "new" | "change")) {
+            collect_criterion_means(root, &entry.path(), since, means)?;
+        }
+    }
+    Ok(())
+}
+
+fn git_hash() -> Option<String> {
+    let output = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output().ok()?;
+    let hash = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !hash.trim().is_empty()).then(|| hash.trim().to_string())
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME").ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_round_trip() {
+        let path = std::env::temp_dir().join(format!("fleetlink-history-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let first = BenchRecord::new("cpp_comparison").with_metric("rust_ops_per_sec/64B", 5.0e6);
+        let second = BenchRecord::new("cpp_comparison").with_metric("rust_ops_per_sec/64B", 5.5e6);
+        append(&path, &first).unwrap();
+        append(&path, &second).unwrap();
+
+        let records = read_history(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records, vec![first, second]);
+        assert!(!records[0].hostname.is_empty());
+        assert!(records[0].os.starts_with(std::env::consts::OS));
+    }
+
+    #[test]
+    fn test_criterion_means_skip_stale_results() {
+        let root = std::env::temp_dir().join(format!("fleetlink-criterion-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let estimates = r#"{"mean":{"point_estimate":42.5,"standard_error":0.1}}"#;
+        for id in ["serialization/rust_zerocopy/64", "checksum/rust_header_sum/0"] {
+            fs::create_dir_all(root.join(id).join("new")).unwrap();
+            fs::write(root.join(id).join("new/estimates.json"), estimates).unwrap();
+        }
+        fs::create_dir_all(root.join("report")).unwrap();
+
+        let means = criterion_means(&root, SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(means.len(), 2);
+        assert_eq!(means["serialization/rust_zerocopy/64"], 42.5);
+
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        assert!(criterion_means(&root, later).unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use async_std::task;
+use clap::{Args, Parser, Subcommand};
+use fleetlink_transport::bench_history::{self, BenchRecord};
+use fleetlink_transport::orchestrator::{Host, OrchestratedReport, ReceiverResult, RunPlan, SenderResult};
+use fleetlink_transport::soak::{self, LatencySummary};
+use fleetlink_transport::{FleetMsgHeader, MessageType, MulticastSender, start_multicast_rx};
+use serde::de::DeserializeOwned;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Benchmark with senders and receivers in separate processes, locally or on other hosts over SSH
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    mode: Mode,
+}
+
+#[derive(Debug, Subcommand)]
+enum Mode {
+    /// Spawn senders and receivers, start them together and collect their results
+    Run(RunArgs),
+    /// Send at a fixed rate from a common start time (spawned by `run`)
+    Send(SendArgs),
+    /// Count and time what arrives from a common start time (spawned by `run`)
+    Receive(ReceiveArgs),
+}
+
+/// What every process is told
+#[derive(Debug, Args)]
+struct Timing {
+    #[arg(long, default_value = "239.1.1.30")]
+    group: Ipv4Addr,
+    #[arg(long, default_value_t = 12370)]
+    port: u16,
+    /// Seconds of sending
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+    /// Wall-clock start in microseconds since the Unix epoch
+    #[arg(long)]
+    start_at: u64,
+}
+
+#[derive(Debug, Args)]
+struct RunArgs {
+    #[arg(long, default_value = "239.1.1.30")]
+    group: Ipv4Addr,
+    #[arg(long, default_value_t = 12370)]
+    port: u16,
+    /// Seconds of sending
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+    /// Messages per second, per sender
+    #[arg(long, default_value_t = 1000.0)]
+    rate: f64,
+    /// Payload bytes per message
+    #[arg(long, default_value_t = 256)]
+    payload: usize,
+    /// Hosts to run senders on, comma separated: `local` or an SSH destination
+    #[arg(long, value_delimiter = ',', default_value = "local")]
+    senders_on: Vec<String>,
+    /// Number of sender processes, spread over --senders-on in turn [default: one per host]
+    #[arg(long)]
+    senders: Option<u32>,
+    /// Hosts to run a receiver on, comma separated: `local` or an SSH destination
+    #[arg(long, value_delimiter = ',', default_value = "local")]
+    receivers_on: Vec<String>,
+    /// This binary's path on remote hosts
+    #[arg(long, default_value = "bench_orchestrator")]
+    remote_binary: String,
+    /// Seconds between spawning and the common start, to let SSH log in and receivers join
+    #[arg(long, default_value_t = 3)]
+    lead_time: u64,
+    /// Seconds receivers keep listening after the senders stop
+    #[arg(long, default_value_t = 1)]
+    drain: u64,
+    /// Where to write the JSON report
+    #[arg(long, short, default_value = "orchestrated_report.json")]
+    output: PathBuf,
+    /// History file to append the run's headline numbers to
+    #[arg(long)]
+    history: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct SendArgs {
+    #[command(flatten)]
+    timing: Timing,
+    #[arg(long)]
+    rate: f64,
+    #[arg(long)]
+    payload: usize,
+    #[arg(long)]
+    sender_id: u32,
+}
+
+#[derive(Debug, Args)]
+struct ReceiveArgs {
+    #[command(flatten)]
+    timing: Timing,
+    #[arg(long, default_value_t = 1)]
+    drain: u64,
+}
+
+/// Sleep until a wall-clock time, in microseconds since the Unix epoch
+async fn sleep_until(at_us: u64) {
+    task::sleep(Duration::from_micros(at_us.saturating_sub(soak::now_micros()))).await;
+}
+
+async fn send(args: SendArgs) -> Result<SenderResult, Box<dyn std::error::Error>> {
+    if args.rate <= 0.0 {
+        return Err("rate must be positive".into());
+    }
+    let Timing { group, port, duration, start_at } = args.timing;
+    let mut sender = MulticastSender::new(group, port, args.sender_id).await?;
+    let interval = Duration::from_secs_f64(1.0 / args.rate);
+    let duration = Duration::from_secs(duration);
+
+    sleep_until(start_at).await;
+    let started_late_us = soak::now_micros().saturating_sub(start_at);
+    let send_start = Instant::now();
+    let mut sent = 0u64;
+    while send_start.elapsed() < duration {
+        // Pace against the schedule rather than the last send so sleep overshoot doesn't accumulate
+        let due = send_start + interval.mul_f64(sent as f64);
+        let now = Instant::now();
+        if due > now {
+            task::sleep(due - now).await;
+        }
+        sender.send_data(&soak::soak_payload(args.payload)).await?;
+        sent += 1;
+    }
+
+    Ok(SenderResult {
+        sender_id: args.sender_id,
+        messages_sent: sent,
+        send_rate: sent as f64 / send_start.elapsed().as_secs_f64(),
+        started_late_us,
+    })
+}
+
+async fn receive(args: ReceiveArgs) -> Result<ReceiverResult, Box<dyn std::error::Error>> {
+    let Timing { group, port, duration, start_at } = args.timing;
+    let received = Arc::new(Mutex::new((ReceiverResult::default(), Vec::new())));
+
+    let received_rx = received.clone();
+    let handler = move |header: FleetMsgHeader, payload: Vec<u8>, _addr: SocketAddr| {
+        // Senders also announce themselves; only the benchmark traffic counts
+        if header.message_type() != MessageType::Data {
+            return;
+        }
+        let mut received = received_rx.lock().unwrap();
+        let (result, latencies_us) = &mut *received;
+        *result.received.entry(header.sender_id.get()).or_insert(0) += 1;
+        if let Some(sent_us) = soak::soak_timestamp(&payload) {
+            latencies_us.push(soak::now_micros().saturating_sub(sent_us));
+        }
+    };
+    let receiver = task::spawn(async move {
+        if let Err(e) = start_multicast_rx(group, port, handler).await {
+            eprintln!("Receiver error: {}", e);
+        }
+    });
+
+    sleep_until(start_at).await;
+    let drops_before = soak::kernel_udp_drops(port);
+    sleep_until(start_at + (duration + args.drain) * 1_000_000).await;
+    let drops_after = soak::kernel_udp_drops(port);
+    receiver.cancel().await;
+
+    let (mut result, mut latencies_us) = std::mem::take(&mut *received.lock().unwrap());
+    result.latency_us = LatencySummary::from_samples(&mut latencies_us);
+    result.kernel_drops = drops_before.zip(drops_after).map(|(before, after)| after.saturating_sub(before));
+    Ok(result)
+}
+
+/// Wait for a child and parse the JSON result on the last line of its output
+fn collect<T: DeserializeOwned>(host: &Host, role: &str, child: Child) -> Result<T, Box<dyn std::error::Error>> {
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!("{} on {} failed: {}", role, host, output.status).into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().rfind(|line| !line.trim().is_empty()).unwrap_or_default();
+    serde_json::from_str(line).map_err(|e| format!("{} on {} printed no result ({}): {}", role, host, e, line).into())
+}
+
+fn run(args: RunArgs) -> Result<OrchestratedReport, Box<dyn std::error::Error>> {
+    let sender_hosts: Vec<Host> = args.senders_on.iter().map(|spec| Host::parse(spec)).collect();
+    let receiver_hosts: Vec<Host> = args.receivers_on.iter().map(|spec| Host::parse(spec)).collect();
+    if sender_hosts.is_empty() || receiver_hosts.is_empty() {
+        return Err("need at least one sender host and one receiver host".into());
+    }
+    let plan = RunPlan {
+        group: args.group,
+        port: args.port,
+        rate: args.rate,
+        payload_size: args.payload,
+        duration_secs: args.duration,
+        start_at_us: soak::now_micros() + args.lead_time * 1_000_000,
+        drain_secs: args.drain,
+        senders: args.senders.unwrap_or(sender_hosts.len() as u32),
+    };
+    let binary = std::env::current_exe()?;
+    let spawn = |host: &Host, child_args: &[String]| {
+        host.command(&binary, &args.remote_binary, child_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not start a process on {}: {}", host, e))
+    };
+
+    println!("Starting {} senders on {} and receivers on {}, {}s from now",
+             plan.senders, args.senders_on.join(","), args.receivers_on.join(","), args.lead_time);
+    // Receivers first, so they have joined the group when sending starts
+    let mut receivers = Vec::new();
+    for host in receiver_hosts {
+        let child = spawn(&host, &plan.receiver_args())?;
+        receivers.push((host, child));
+    }
+    let mut senders = Vec::new();
+    for (i, sender_id) in plan.sender_ids().enumerate() {
+        let host = sender_hosts[i % sender_hosts.len()].clone();
+        let child = spawn(&host, &plan.sender_args(sender_id))?;
+        senders.push((host, child));
+    }
+
+    // Wait for every child before giving up on one, so none is left running
+    let senders: Vec<_> = senders.into_iter()
+        .map(|(host, child)| collect(&host, "sender", child).map(|result| (host, result)))
+        .collect();
+    let receivers: Vec<_> = receivers.into_iter()
+        .map(|(host, child)| collect(&host, "receiver", child).map(|result| (host, result)))
+        .collect();
+    Ok(OrchestratedReport::new(
+        plan,
+        senders.into_iter().collect::<Result<_, _>>()?,
+        receivers.into_iter().collect::<Result<_, _>>()?,
+    ))
+}
+
+fn print_report(report: &OrchestratedReport) {
+    println!("\n=== MULTI-PROCESS SUMMARY ===");
+    println!("Sent:      {} from {} senders ({:.1} msg/s combined)", report.messages_sent, report.senders.len(), report.send_rate);
+    for sender in &report.senders {
+        println!("  sender {:>6} on {:<20} {:>10} msgs, started {}us late",
+                 sender.result.sender_id, sender.host.to_string(), sender.result.messages_sent, sender.result.started_late_us);
+    }
+    for receiver in &report.receivers {
+        let drops = receiver.kernel_drops.map_or_else(|| "unavailable".to_string(), |drops| drops.to_string());
+        println!("  receiver on {:<20} {:>10} msgs, {} lost ({:.3}%), kernel drops {}, latency p50 {}us p99 {}us",
+                 receiver.host.to_string(), receiver.messages_received, receiver.messages_lost, receiver.loss_ratio * 100.0,
+                 drops, receiver.latency_us.p50, receiver.latency_us.p99);
+    }
+}
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Cli::parse().mode {
+        // Children print their result alone on stdout for `run` to read
+        Mode::Send(args) => println!("{}", serde_json::to_string(&send(args).await?)?),
+        Mode::Receive(args) => println!("{}", serde_json::to_string(&receive(args).await?)?),
+        Mode::Run(args) => {
+            let (output, history) = (args.output.clone(), args.history.clone());
+            let report = run(args)?;
+            std::fs::write(&output, serde_json::to_string_pretty(&report)?)?;
+            print_report(&report);
+            println!("Report written to {}", output.display());
+
+            if let Some(path) = history {
+                let mut record = BenchRecord::new("bench_orchestrator")
+                    .with_metric("send_rate_per_sec", report.send_rate);
+                if let Some(worst) = report.worst_receiver() {
+                    record = record
+                        .with_metric("worst_loss_ratio", worst.loss_ratio)
+                        .with_metric("worst_latency_p99_us", worst.latency_us.p99 as f64);
+                }
+                bench_history::append(&path, &record)?;
+                println!("Appended to history {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use clap::{Parser, ValueEnum};
+use fleetlink_transport::net::bridge::{self, BrokerBridge, NatsBroker, RedisBroker};
+use std::net::Ipv4Addr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BrokerKind {
+    Nats,
+    Redis,
+}
+
+/// Mirror fleet multicast traffic to and from a message broker
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    #[arg(long, value_enum, default_value_t = BrokerKind::Nats)]
+    broker: BrokerKind,
+    /// Broker address [default: 127.0.0.1:4222 for NATS, 127.0.0.1:6379 for Redis]
+    #[arg(long)]
+    url: Option<String>,
+    /// NATS subject or Redis channel [default: fleetlink.traffic or fleetlink:traffic]
+    #[arg(long)]
+    topic: Option<String>,
+    #[arg(long, default_value = "239.1.1.1")]
+    group: Ipv4Addr,
+    #[arg(long, default_value_t = 12345)]
+    port: u16,
+    /// Distinct id for this bridge among all bridges on the broker [default: random]
+    #[arg(long)]
+    bridge_id: Option<u32>,
+}
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    match args.broker {
+        BrokerKind::Nats => {
+            let url = args.url.as_deref().unwrap_or("127.0.0.1:4222");
+            let topic = args.topic.as_deref().unwrap_or(bridge::nats::DEFAULT_SUBJECT);
+            let broker = NatsBroker::connect(url, topic).await?;
+            println!("Connected to NATS at {}, subject {}", url, topic);
+            with_id(BrokerBridge::new(broker, args.group, args.port), args.bridge_id).run().await?;
+        }
+        BrokerKind::Redis => {
+            let url = args.url.as_deref().unwrap_or("127.0.0.1:6379");
+            let topic = args.topic.as_deref().unwrap_or(bridge::redis::DEFAULT_CHANNEL);
+            let broker = RedisBroker::connect(url, topic).await?;
+            println!("Connected to Redis at {}, channel {}", url, topic);
+            with_id(BrokerBridge::new(broker, args.group, args.port), args.bridge_id).run().await?;
+        }
+    }
+    Ok(())
+}
+
+fn with_id<B: bridge::Broker>(bridge: BrokerBridge<B>, bridge_id: Option<u32>) -> BrokerBridge<B> {
+    match bridge_id {
+        Some(id) => bridge.with_bridge_id(id),
+        None => bridge,
+    }
+}
@@ -0,0 +1,116 @@
+use clap::{Parser, ValueEnum};
+use fleetlink_transport::sim::{self, LinkModel, LossProfile, Scenario, SendPattern, SimConfig, SimReport, SimTransport};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Transport {
+    /// Modelled shared channel in virtual time
+    Memory,
+    /// Real UDP sockets on 127.0.0.1, in real time
+    Loopback,
+}
+
+/// Simulate a fleet of virtual nodes sharing one group, for capacity planning
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// Scenario file (TOML) to run instead of the fleet described by the options below
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+    /// Number of virtual nodes
+    #[arg(long, default_value_t = 100)]
+    nodes: usize,
+    /// Transmit ticks per second, per node
+    #[arg(long, default_value_t = 10.0)]
+    rate: f64,
+    /// Messages sent back to back on each tick
+    #[arg(long, default_value_t = 1)]
+    burst: u32,
+    /// Payload bytes per message
+    #[arg(long, default_value_t = 64)]
+    payload: usize,
+    /// Simulated seconds
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+    #[arg(long, value_enum, default_value_t = Transport::Memory)]
+    transport: Transport,
+    /// Channel capacity in bytes/sec, shared by the whole fleet (0 = unlimited)
+    #[arg(long, default_value_t = 0)]
+    link_rate: u64,
+    /// Bytes that may queue for the channel before messages are dropped
+    #[arg(long, default_value_t = 64 * 1024)]
+    queue_bytes: usize,
+    /// Propagation delay of the channel in milliseconds
+    #[arg(long, default_value_t = 0)]
+    link_latency_ms: u64,
+    /// Chance that the channel loses any one message (0.0 - 1.0)
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+    /// Seed for loss and churn; a random one is used (and reported) if omitted
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Where to write the JSON report
+    #[arg(long, short, default_value = "sim_report.json")]
+    output: PathBuf,
+}
+
+async fn run_from_args(args: &Args) -> Result<SimReport, Box<dyn std::error::Error>> {
+    let pattern = SendPattern::at_rate(args.rate, args.burst);
+    let link = LinkModel::new(args.link_rate)
+        .with_queue_bytes(args.queue_bytes)
+        .with_latency(Duration::from_millis(args.link_latency_ms));
+    let config = SimConfig::new(args.nodes, pattern)?
+        .with_payload_size(args.payload)
+        .with_duration(Duration::from_secs(args.duration))
+        .with_transport(match args.transport {
+            Transport::Memory => SimTransport::InMemory,
+            Transport::Loopback => SimTransport::Loopback,
+        })
+        .with_link(link)
+        .with_loss(LossProfile::Random { probability: args.loss.clamp(0.0, 1.0) });
+    let config = match args.seed {
+        Some(seed) => config.with_rng_seed(seed),
+        None => config,
+    };
+
+    println!("Simulating {} nodes at {} msg/s each for {}s over {:?}",
+             args.nodes, pattern.messages_per_sec(), args.duration, args.transport);
+    Ok(sim::run(&config).await?)
+}
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let report = match &args.scenario {
+        Some(path) => {
+            let scenario = Scenario::load(path)?;
+            println!("Running scenario {} (seed {})",
+                     scenario.name.as_deref().unwrap_or(&path.display().to_string()), scenario.seed);
+            scenario.run()
+        }
+        None => run_from_args(&args).await?,
+    };
+    std::fs::write(&args.output, serde_json::to_string_pretty(&report)?)?;
+
+    println!("\n=== SIMULATION SUMMARY ===");
+    println!("Offered load: {:.0} bytes/s", report.offered_load);
+    if let Some(utilization) = report.channel_utilization {
+        println!("Channel busy: {:.1}%", utilization * 100.0);
+    }
+    println!("Sent:         {}", report.messages_sent);
+    println!("Delivered:    {} ({:.2}%)", report.messages_delivered, report.delivery_ratio() * 100.0);
+    println!("Dropped:      {}", report.messages_dropped);
+    println!("Latency:      p50 {}us, p99 {}us, max {}us", report.latency_us.p50, report.latency_us.p99, report.latency_us.max);
+    if report.expected_receptions > 0 {
+        println!("Receptions:   {:.2}% of a fully connected fleet",
+                 report.receptions as f64 / report.expected_receptions as f64 * 100.0);
+    }
+    for event in &report.events {
+        println!("  t={:>6.1}s {:?} ({} nodes)", event.at_secs, event.event, event.affected);
+    }
+    println!("Seed:         {} (pass --seed to repeat this run)", report.seed);
+    println!("Report written to {}", args.output.display());
+    Ok(())
+}
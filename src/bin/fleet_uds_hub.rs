@@ -0,0 +1,29 @@
+#[cfg(unix)]
+use clap::Parser;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Fan fleet messages out between processes on this machine over Unix sockets
+#[cfg(unix)]
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// Socket path senders send to and subscribers subscribe at
+    #[arg(long, default_value = fleetlink_transport::net::uds::DEFAULT_HUB_PATH)]
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+#[async_std::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let hub = fleetlink_transport::net::uds::UdsHub::bind(&args.path).await?;
+    println!("Local hub listening on {}", args.path.display());
+    hub.run().await
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("fleet_uds_hub needs Unix domain sockets");
+    std::process::exit(1);
+}
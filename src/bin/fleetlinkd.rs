@@ -0,0 +1,51 @@
+use clap::Parser;
+use fleetlink_transport::daemon::{self, DaemonConfig};
+use fleetlink_transport::net::interfaces;
+use std::path::PathBuf;
+
+/// Run the fleet transport as a standalone daemon, configured from a TOML file
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    #[arg(long, short, default_value = daemon::DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Check the configuration and exit
+    #[arg(long)]
+    check: bool,
+    /// List the network interfaces, marking the one multicast would pick, and exit
+    #[arg(long)]
+    interfaces: bool,
+    /// Run under the Windows service control manager
+    #[cfg(windows)]
+    #[arg(long)]
+    service: bool,
+}
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    if args.interfaces {
+        let best = interfaces::select(None).ok();
+        if let Some(namespace) = interfaces::network_namespace() {
+            println!("Network namespace {}", namespace);
+        }
+        for interface in interfaces::list()? {
+            let mark = if best.as_ref() == Some(&interface) { "*" } else { " " };
+            println!("{} {}", mark, interface);
+        }
+        return Ok(());
+    }
+    let config = DaemonConfig::load(&args.config)?;
+    if args.check {
+        println!("{} is valid", args.config.display());
+        return Ok(());
+    }
+    #[cfg(windows)]
+    if args.service {
+        let service = || async_std::task::block_on(daemon::run(config));
+        fleetlink_transport::winservice::run(daemon::SERVICE_NAME, service)?;
+        return Ok(());
+    }
+    daemon::run(config).await?;
+    Ok(())
+}
@@ -1,6 +1,310 @@
+use clap::{Parser, ValueEnum};
+use fleetlink_transport::bench_history::{self, BenchRecord};
+use fleetlink_transport::journal::{self, JournalEntry, TrafficBreakdown};
+use fleetlink_transport::reliability::replay::{self, ReplaySummary};
+use fleetlink_transport::soak::SoakReport;
+use plotters::coord::Shift;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
 use plotters::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::{Path, PathBuf};
+
+type ChartResult = Result<(), Box<dyn std::error::Error>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Png,
+    Svg,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Svg => "svg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChartKind {
+    /// Rust vs C-style comparison (performance_comparison)
+    Comparison,
+    /// Soak benchmark results (soak_report)
+    Soak,
+    /// Per-sender and per-type journal breakdown (fleet_breakdown)
+    Breakdown,
+    /// Offline latency and gap analysis of a recording (replay_analysis)
+    Replay,
+    /// Benchmark results across runs from a history file (bench_history)
+    History,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ThemeName {
+    Light,
+    Dark,
+}
+
+/// Colors, font and footer used for every chart
+#[derive(Debug, Clone)]
+struct Theme {
+    background: RGBColor,
+    foreground: RGBColor,
+    grid: RGBColor,
+    series: Vec<RGBColor>,
+    font: String,
+    footer: Option<String>,
+}
+
+impl Theme {
+    const FOOTER_HEIGHT: u32 = 28;
+
+    fn new(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Light => Self {
+                background: WHITE,
+                foreground: BLACK,
+                grid: RGBColor(204, 204, 204),
+                series: vec![BLUE, RED, RGBColor(44, 160, 44), RGBColor(255, 127, 14), RGBColor(148, 103, 189), RGBColor(23, 190, 207)],
+                font: "sans-serif".to_string(),
+                footer: None,
+            },
+            ThemeName::Dark => Self {
+                background: RGBColor(30, 30, 36),
+                foreground: RGBColor(220, 220, 220),
+                grid: RGBColor(80, 80, 92),
+                series: vec![
+                    RGBColor(78, 154, 241), RGBColor(241, 107, 91), RGBColor(123, 216, 143),
+                    RGBColor(245, 197, 66), RGBColor(199, 146, 234), RGBColor(95, 215, 215),
+                ],
+                font: "sans-serif".to_string(),
+                footer: None,
+            },
+        }
+    }
+
+    fn series(&self, index: usize) -> RGBColor {
+        self.series[index % self.series.len()]
+    }
+
+    fn text(&self, size: u32) -> TextStyle<'_> {
+        (self.font.as_str(), size).into_font().color(&self.foreground)
+    }
+
+    /// Paint the background and footer; returns the area left for the chart itself
+    fn prepare<DB: DrawingBackend>(&self, canvas: &DrawingArea<DB, Shift>) -> Result<DrawingArea<DB, Shift>, DrawingAreaErrorKind<DB::ErrorType>> {
+        canvas.fill(&self.background)?;
+        let Some(footer) = &self.footer else {
+            return Ok(canvas.clone());
+        };
+
+        let (_, height) = canvas.dim_in_pixel();
+        let (body, footer_area) = canvas.split_vertically(height.saturating_sub(Self::FOOTER_HEIGHT));
+        footer_area.fill(&self.grid.mix(0.25))?;
+        let style = self.text(14).pos(Pos::new(HPos::Left, VPos::Center));
+        footer_area.draw_text(footer, &style, (10, Self::FOOTER_HEIGHT as i32 / 2))?;
+        Ok(body)
+    }
+}
+
+/// Parse `#rrggbb` (the `#` is optional)
+fn parse_color(text: &str) -> Result<RGBColor, String> {
+    let hex = text.trim().trim_start_matches('#');
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(RGBColor(r, g, b)),
+        _ => Err(format!("invalid color '{}', expected #rrggbb", text)),
+    }
+}
+
+/// Render FleetLink benchmark results and fleet journals as charts
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// TOML file with defaults for any of the options below
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Journal to break down per sender and message type
+    #[arg(long, short)]
+    journal: Option<PathBuf>,
+    /// Journal or pcap capture to analyse offline for latency and sequence gaps
+    #[arg(long, short)]
+    replay: Option<PathBuf>,
+    /// Soak benchmark report [default: soak_report.json, skipped if missing]
+    #[arg(long)]
+    soak_report: Option<PathBuf>,
+    /// Measured allocations from cpp_comparison [default: allocation_data.json, skipped if missing]
+    #[arg(long)]
+    allocation_data: Option<PathBuf>,
+    /// Benchmark history appended by the bench harness [default: bench_history.jsonl, skipped if missing]
+    #[arg(long)]
+    history: Option<PathBuf>,
+    /// Only chart history metrics whose name contains one of these, comma separated
+    #[arg(long, value_delimiter = ',')]
+    history_metrics: Option<Vec<String>>,
+    /// Directory to write charts and data into [default: .]
+    #[arg(long, short)]
+    output_dir: Option<PathBuf>,
+    /// Image format [default: png]
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+    /// Charts to render, comma separated [default: all available]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    charts: Option<Vec<ChartKind>>,
+    /// Chart width in pixels [default: 1200]
+    #[arg(long)]
+    width: Option<u32>,
+    /// Chart height in pixels [default: 800]
+    #[arg(long)]
+    height: Option<u32>,
+    /// Fit every axis to the data instead of the fixed comparison ranges
+    #[arg(long)]
+    autoscale: bool,
+    /// Color scheme [default: light]
+    #[arg(long, value_enum)]
+    theme: Option<ThemeName>,
+    /// Series colors in order, comma separated (e.g. "#1f77b4,#ff7f0e")
+    #[arg(long, value_delimiter = ',')]
+    series_colors: Option<Vec<String>>,
+    /// Font family for titles, labels and legends [default: sans-serif]
+    #[arg(long)]
+    font: Option<String>,
+    /// Text for a footer strip along the bottom of every chart (e.g. team or company name)
+    #[arg(long)]
+    footer: Option<String>,
+}
+
+/// The same options read from `--config`; command-line flags take precedence
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    journal: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    soak_report: Option<PathBuf>,
+    allocation_data: Option<PathBuf>,
+    history: Option<PathBuf>,
+    history_metrics: Option<Vec<String>>,
+    output_dir: Option<PathBuf>,
+    format: Option<OutputFormat>,
+    charts: Option<Vec<ChartKind>>,
+    width: Option<u32>,
+    height: Option<u32>,
+    autoscale: Option<bool>,
+    theme: Option<ThemeName>,
+    series_colors: Option<Vec<String>>,
+    font: Option<String>,
+    footer: Option<String>,
+}
+
+#[derive(Debug)]
+struct Settings {
+    journal: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    /// Inputs that were named explicitly must exist; defaults are skipped when missing
+    soak_report: (PathBuf, bool),
+    allocation_data: (PathBuf, bool),
+    history: (PathBuf, bool),
+    history_metrics: Vec<String>,
+    output_dir: PathBuf,
+    format: OutputFormat,
+    charts: Vec<ChartKind>,
+    size: (u32, u32),
+    autoscale: bool,
+    theme: Theme,
+}
+
+impl Settings {
+    fn load(args: Args) -> Result<Self, Box<dyn std::error::Error>> {
+        let file: FileConfig = match &args.config {
+            Some(path) => toml::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| format!("{}: {}", path.display(), e))?,
+            None => FileConfig::default(),
+        };
+
+        let input = |arg: Option<PathBuf>, file: Option<PathBuf>, default: &str| match arg.or(file) {
+            Some(path) => (path, true),
+            None => (PathBuf::from(default), false),
+        };
+
+        let mut theme = Theme::new(args.theme.or(file.theme).unwrap_or(ThemeName::Light));
+        if let Some(colors) = args.series_colors.or(file.series_colors) {
+            theme.series = colors.iter().map(|c| parse_color(c)).collect::<Result<_, _>>()?;
+            if theme.series.is_empty() {
+                return Err("series_colors must name at least one color".into());
+            }
+        }
+        if let Some(font) = args.font.or(file.font) {
+            theme.font = font;
+        }
+        theme.footer = args.footer.or(file.footer);
+
+        Ok(Self {
+            journal: args.journal.or(file.journal),
+            replay: args.replay.or(file.replay),
+            soak_report: input(args.soak_report, file.soak_report, "soak_report.json"),
+            allocation_data: input(args.allocation_data, file.allocation_data, "allocation_data.json"),
+            history: input(args.history, file.history, "bench_history.jsonl"),
+            history_metrics: args.history_metrics.or(file.history_metrics).unwrap_or_default(),
+            output_dir: args.output_dir.or(file.output_dir).unwrap_or_else(|| PathBuf::from(".")),
+            format: args.format.or(file.format).unwrap_or(OutputFormat::Png),
+            charts: args.charts.or(file.charts)
+                .unwrap_or_else(|| vec![
+                    ChartKind::Comparison, ChartKind::Soak, ChartKind::Breakdown, ChartKind::Replay, ChartKind::History
+                ]),
+            size: (
+                args.width.or(file.width).unwrap_or(1200),
+                args.height.or(file.height).unwrap_or(800),
+            ),
+            autoscale: args.autoscale || file.autoscale.unwrap_or(false),
+            theme,
+        })
+    }
+
+    fn wants(&self, chart: ChartKind) -> bool {
+        self.charts.contains(&chart)
+    }
+
+    fn output(&self, name: &str) -> PathBuf {
+        self.output_dir.join(format!("{}.{}", name, self.format.extension()))
+    }
+}
+
+/// Read an optional input; a missing file is only an error if it was asked for
+fn read_input(path: &Path, required: bool) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+    }
+}
+
+/// Draw onto a PNG or SVG file depending on the configured format
+macro_rules! render_chart {
+    ($settings:expr, $name:expr, |$root:ident| $draw:expr) => {{
+        let path = $settings.output($name);
+        match $settings.format {
+            OutputFormat::Png => {
+                let canvas = BitMapBackend::new(&path, $settings.size).into_drawing_area();
+                let $root = $settings.theme.prepare(&canvas)?;
+                $draw?;
+                canvas.present()?;
+            }
+            OutputFormat::Svg => {
+                let canvas = SVGBackend::new(&path, $settings.size).into_drawing_area();
+                let $root = $settings.theme.prepare(&canvas)?;
+                $draw?;
+                canvas.present()?;
+            }
+        }
+        println!("Chart saved as '{}'", path.display());
+    }};
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct BenchmarkResult {
@@ -26,8 +330,8 @@ struct MemoryResult {
     payload_size: usize,
     rust_memory_kb: f64,
     c_style_memory_kb: f64,
-    rust_allocations: u32,
-    c_style_allocations: u32,
+    rust_allocations: f64,
+    c_style_allocations: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,8 +342,30 @@ struct CpuResult {
     improvement_percent: f64,
 }
 
+/// `configure_mesh()` with the theme's axis, label and grid styles applied
+macro_rules! themed_mesh {
+    ($chart:expr, $theme:expr) => {
+        $chart.configure_mesh()
+            .axis_style($theme.foreground)
+            .label_style($theme.text(12))
+            .axis_desc_style($theme.text(14))
+            .bold_line_style($theme.grid)
+            .light_line_style($theme.grid.mix(0.3))
+    };
+}
+
+/// `configure_series_labels()` with the theme's legend styles applied
+macro_rules! themed_legend {
+    ($chart:expr, $theme:expr) => {
+        $chart.configure_series_labels()
+            .label_font($theme.text(14))
+            .background_style($theme.background.mix(0.8))
+            .border_style($theme.foreground)
+    };
+}
+
 fn generate_mock_data() -> PerformanceData {
-    let payload_sizes = vec![0, 64, 256, 1024];
+    let payload_sizes = [0, 64, 256, 1024];
     
     let message_creation = payload_sizes.iter().map(|&size| {
         // Rust is faster due to zero-copy and better optimization
@@ -103,8 +429,8 @@ fn generate_mock_data() -> PerformanceData {
             payload_size: size,
             rust_memory_kb: rust_mem,
             c_style_memory_kb: c_mem,
-            rust_allocations: if size == 0 { 1 } else { 2 },
-            c_style_allocations: 3 + (size / 64) as u32,
+            rust_allocations: if size == 0 { 1.0 } else { 2.0 },
+            c_style_allocations: 3.0 + (size / 64) as f64,
         }
     }).collect();
     
@@ -144,10 +470,16 @@ fn generate_mock_data() -> PerformanceData {
     }
 }
 
-fn create_performance_comparison_chart(data: &PerformanceData) -> Result<(), Box<dyn std::error::Error>> {
-    let root = BitMapBackend::new("performance_comparison.png", (1200, 800)).into_drawing_area();
-    root.fill(&WHITE)?;
-    
+fn draw_performance_comparison<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    theme: &Theme,
+    data: &PerformanceData,
+    autoscale: bool,
+) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let (primary, secondary) = (theme.series(0), theme.series(1));
     let root = root.margin(10, 10, 10, 10);
     let areas = root.split_evenly((2, 2));
     let upper_left = &areas[0];
@@ -157,14 +489,22 @@ fn create_performance_comparison_chart(data: &PerformanceData) -> Result<(), Box
     
     // Chart 1: Serialization Performance
     {
+        let (x_max, y_max) = if autoscale {
+            let x_max = data.serialization.iter().map(|r| r.payload_size as f64).fold(1.0, f64::max);
+            let y_max = data.serialization.iter().map(|r| r.rust_time_ns.max(r.c_style_time_ns)).fold(1.0, f64::max);
+            (x_max * 1.1, y_max * 1.1)
+        } else {
+            (1100.0, 500.0)
+        };
+
         let mut chart = ChartBuilder::on(upper_left)
-            .caption("Serialization Time (Lower is Better)", ("sans-serif", 30))
+            .caption("Serialization Time (Lower is Better)", theme.text(30))
             .margin(5)
             .x_label_area_size(40)
             .y_label_area_size(80)
-            .build_cartesian_2d(0f64..1100f64, 0f64..500f64)?;
+            .build_cartesian_2d(0f64..x_max, 0f64..y_max)?;
 
-        chart.configure_mesh()
+        themed_mesh!(chart, theme)
             .x_desc("Payload Size (bytes)")
             .y_desc("Time (nanoseconds)")
             .draw()?;
@@ -172,20 +512,20 @@ fn create_performance_comparison_chart(data: &PerformanceData) -> Result<(), Box
         chart
             .draw_series(LineSeries::new(
                 data.serialization.iter().map(|r| (r.payload_size as f64, r.rust_time_ns)),
-                &BLUE,
+                &primary,
             ))?
             .label("Rust (Zero-Copy)")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &BLUE));
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], primary));
         
         chart
             .draw_series(LineSeries::new(
                 data.serialization.iter().map(|r| (r.payload_size as f64, r.c_style_time_ns)),
-                &RED,
+                &secondary,
             ))?
             .label("C-Style (Copy-Heavy)")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &RED));
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], secondary));
         
-        chart.configure_series_labels().draw()?;
+        themed_legend!(chart, theme).draw()?;
     }
     
     // Chart 2: Throughput Comparison
@@ -203,13 +543,13 @@ fn create_performance_comparison_chart(data: &PerformanceData) -> Result<(), Box
         let y_max = (max_throughput * 1.1).ceil();
 
         let mut chart = ChartBuilder::on(upper_right)
-            .caption("Throughput (Higher is Better)", ("sans-serif", 30))
+            .caption("Throughput (Higher is Better)", theme.text(30))
             .margin(5)
             .x_label_area_size(40)
             .y_label_area_size(80)
             .build_cartesian_2d(0f64..1100f64, 0f64..y_max)?;
 
-        chart.configure_mesh()
+        themed_mesh!(chart, theme)
             .x_desc("Payload Size (bytes)")
             .y_desc("Throughput (ops/sec)")
             .draw()?;
@@ -217,32 +557,41 @@ fn create_performance_comparison_chart(data: &PerformanceData) -> Result<(), Box
         chart
             .draw_series(LineSeries::new(
                 data.serialization.iter().map(|r| (r.payload_size as f64, r.throughput_rust)),
-                &BLUE,
+                &primary,
             ))?
             .label("Rust Throughput (ops/sec)")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &BLUE));
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], primary));
 
         chart
             .draw_series(LineSeries::new(
                 data.serialization.iter().map(|r| (r.payload_size as f64, r.throughput_c)),
-                &RED,
+                &secondary,
             ))?
             .label("C-Style Throughput (ops/sec)")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &RED));
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], secondary));
 
-        chart.configure_series_labels().draw()?;
+        themed_legend!(chart, theme).draw()?;
     }
     
     // Chart 3: Memory Usage
     {
+        // Measured allocation data may cover different payload sizes than the mock data
+        let x_max = data.memory_efficiency.iter()
+            .map(|r| r.payload_size as f64)
+            .fold(1000.0, f64::max) * 1.1;
+        let y_max = data.memory_efficiency.iter()
+            .map(|r| r.rust_memory_kb.max(r.c_style_memory_kb))
+            .fold(0.0, f64::max)
+            .max(1.0) * 1.2;
+
         let mut chart = ChartBuilder::on(lower_left)
-            .caption("Memory Usage (Lower is Better)", ("sans-serif", 30))
+            .caption("Memory Usage (Lower is Better)", theme.text(30))
             .margin(5)
             .x_label_area_size(40)
             .y_label_area_size(80)
-            .build_cartesian_2d(0f64..1100f64, 0f64..5f64)?;
+            .build_cartesian_2d(0f64..x_max, 0f64..y_max)?;
 
-        chart.configure_mesh()
+        themed_mesh!(chart, theme)
             .x_desc("Payload Size (bytes)")
             .y_desc("Memory (KB)")
             .draw()?;
@@ -250,78 +599,538 @@ fn create_performance_comparison_chart(data: &PerformanceData) -> Result<(), Box
         chart
             .draw_series(LineSeries::new(
                 data.memory_efficiency.iter().map(|r| (r.payload_size as f64, r.rust_memory_kb)),
-                &BLUE,
+                &primary,
             ))?
             .label("Rust Memory (KB)")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &BLUE));
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], primary));
         
         chart
             .draw_series(LineSeries::new(
                 data.memory_efficiency.iter().map(|r| (r.payload_size as f64, r.c_style_memory_kb)),
-                &RED,
+                &secondary,
             ))?
             .label("C-Style Memory (KB)")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], &RED));
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], secondary));
         
-        chart.configure_series_labels().draw()?;
+        themed_legend!(chart, theme).draw()?;
     }
     
     // Chart 4: CPU Efficiency
     {
+        let cycles_max = if autoscale {
+            data.cpu_efficiency.iter()
+                .map(|r| r.rust_cpu_cycles.max(r.c_style_cpu_cycles) as f64)
+                .fold(1.0, f64::max) * 1.1
+        } else {
+            450.0
+        };
+
         let mut chart = ChartBuilder::on(lower_right)
-            .caption("CPU Cycles (Lower is Better)", ("sans-serif", 30))
+            .caption("CPU Cycles (Lower is Better)", theme.text(30))
             .margin(5)
             .x_label_area_size(40)
             .y_label_area_size(80)
-            .build_cartesian_2d(0f64..4f64, 0f64..450f64)?;
+            .build_cartesian_2d(0f64..data.cpu_efficiency.len().max(1) as f64, 0f64..cycles_max)?;
 
-        chart.configure_mesh()
+        themed_mesh!(chart, theme)
             .x_desc("Operation")
             .y_desc("CPU Cycles")
             .draw()?;
         
         for (i, cpu_data) in data.cpu_efficiency.iter().enumerate() {
             let x = i as f64;
-            chart.draw_series(std::iter::once(Rectangle::new([(x - 0.2, 0.0), (x, cpu_data.rust_cpu_cycles as f64)], BLUE.filled())))?;
-            chart.draw_series(std::iter::once(Rectangle::new([(x + 0.2, 0.0), (x + 0.4, cpu_data.c_style_cpu_cycles as f64)], RED.filled())))?;
+            chart.draw_series(std::iter::once(Rectangle::new([(x - 0.2, 0.0), (x, cpu_data.rust_cpu_cycles as f64)], primary.filled())))?;
+            chart.draw_series(std::iter::once(Rectangle::new([(x + 0.2, 0.0), (x + 0.4, cpu_data.c_style_cpu_cycles as f64)], secondary.filled())))?;
         }
     }
     
-    root.present()?;
-    println!("Performance comparison chart saved as 'performance_comparison.png'");
     Ok(())
 }
 
+fn draw_soak<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, theme: &Theme, report: &SoakReport) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let (primary, secondary) = (theme.series(0), theme.series(1));
+
+    let seconds = report.per_second.len().max(1) as f64;
+    let peak = report.per_second.iter().copied().max().unwrap_or(0) as f64;
+    let y_max = (peak.max(report.target_rate) * 1.1).ceil().max(1.0);
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(
+            format!("Soak: {} lost, p99 latency {} us", report.messages_lost, report.latency_us.p99),
+            theme.text(30),
+        )
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(80)
+        .build_cartesian_2d(0f64..seconds, 0f64..y_max)?;
+
+    themed_mesh!(chart, theme)
+        .x_desc("Time (seconds)")
+        .y_desc("Messages received per second")
+        .draw()?;
+
+    chart
+        .draw_series(LineSeries::new(
+            report.per_second.iter().enumerate().map(|(s, &count)| (s as f64, count as f64)),
+            &primary,
+        ))?
+        .label("Received")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], primary));
+
+    chart
+        .draw_series(LineSeries::new([(0.0, report.target_rate), (seconds, report.target_rate)], &secondary))?
+        .label("Target rate")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], secondary));
+
+    themed_legend!(chart, theme).draw()?;
+    Ok(())
+}
+
+fn draw_rate_panel<DB: DrawingBackend, K>(
+    area: &DrawingArea<DB, Shift>,
+    theme: &Theme,
+    title: &str,
+    groups: &BTreeMap<K, TrafficBreakdown>,
+    label: impl Fn(&K) -> String,
+) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let seconds = groups.values().map(|b| b.per_second.len()).max().unwrap_or(0).saturating_sub(1).max(1) as f64;
+    let peak = groups.values().flat_map(|b| b.per_second.iter().copied()).max().unwrap_or(0) as f64;
+
+    let mut chart = ChartBuilder::on(area)
+        .caption(title, theme.text(24))
+        .margin(5)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0f64..seconds, 0f64..(peak * 1.1).max(1.0))?;
+
+    themed_mesh!(chart, theme)
+        .x_desc("Time (seconds)")
+        .y_desc("Messages/sec")
+        .draw()?;
+
+    for (i, (key, breakdown)) in groups.iter().enumerate() {
+        let color = theme.series(i);
+        chart
+            .draw_series(LineSeries::new(
+                breakdown.per_second.iter().enumerate().map(|(s, &n)| (s as f64, n as f64)),
+                color,
+            ))?
+            .label(label(key))
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], color));
+    }
+
+    themed_legend!(chart, theme).draw()?;
+    Ok(())
+}
+
+fn draw_latency_panel<DB: DrawingBackend, K>(
+    area: &DrawingArea<DB, Shift>,
+    theme: &Theme,
+    title: &str,
+    groups: &BTreeMap<K, TrafficBreakdown>,
+    label: impl Fn(&K) -> String,
+) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let (primary, secondary) = (theme.series(0), theme.series(1));
+    let labels: Vec<String> = groups.keys().map(label).collect();
+    let worst = groups.values().map(|b| b.latency_us.p99).max().unwrap_or(0) as f64 / 1000.0;
+
+    let mut chart = ChartBuilder::on(area)
+        .caption(title, theme.text(24))
+        .margin(5)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(-0.5f64..groups.len().max(1) as f64 - 0.5, 0f64..(worst * 1.4).max(1.0))?;
+
+    themed_mesh!(chart, theme)
+        .disable_x_mesh()
+        .x_labels(labels.len().max(1))
+        .x_label_formatter(&|x| {
+            // Only label the tick at each group's center
+            if (x - x.round()).abs() < 1e-6 {
+                labels.get(x.round() as usize).cloned().unwrap_or_default()
+            } else {
+                String::new()
+            }
+        })
+        .y_desc("Latency (ms)")
+        .draw()?;
+
+    // p50 and p99 side by side for each group
+    let p50 = groups.values().enumerate()
+        .map(|(i, b)| Rectangle::new([(i as f64 - 0.35, 0.0), (i as f64 - 0.05, b.latency_us.p50 as f64 / 1000.0)], primary.filled()));
+    chart.draw_series(p50)?
+        .label("p50")
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], primary.filled()));
+
+    let p99 = groups.values().enumerate()
+        .map(|(i, b)| Rectangle::new([(i as f64 + 0.05, 0.0), (i as f64 + 0.35, b.latency_us.p99 as f64 / 1000.0)], secondary.filled()));
+    chart.draw_series(p99)?
+        .label("p99")
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], secondary.filled()));
+
+    themed_legend!(chart, theme)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+fn draw_breakdown<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, theme: &Theme, entries: &[JournalEntry]) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let by_sender = journal::breakdown_by_sender(entries);
+    let by_type = journal::breakdown_by_type(entries);
+
+    let root = root.margin(10, 10, 10, 10);
+    let areas = root.split_evenly((2, 2));
+
+    draw_rate_panel(&areas[0], theme, "Rate per Sender", &by_sender, |id| format!("sender {}", id))?;
+    draw_rate_panel(&areas[1], theme, "Rate per Message Type", &by_type, |t| journal::message_type_name(*t))?;
+    draw_latency_panel(&areas[2], theme, "Latency per Sender (p50 / p99)", &by_sender, |id| id.to_string())?;
+    draw_latency_panel(&areas[3], theme, "Latency per Message Type (p50 / p99)", &by_type, |t| journal::message_type_name(*t))?;
+    Ok(())
+}
+
+fn print_breakdown(entries: &[JournalEntry]) {
+    let by_sender = journal::breakdown_by_sender(entries);
+    let by_type = journal::breakdown_by_type(entries);
+
+    println!("\n=== FLEET BREAKDOWN ({} messages) ===", entries.len());
+    for (sender_id, breakdown) in &by_sender {
+        println!("  sender {:>10}: {:>8} msgs, {:>10} bytes, p50 {:.1} ms, p99 {:.1} ms",
+                 sender_id, breakdown.messages, breakdown.bytes,
+                 breakdown.latency_us.p50 as f64 / 1000.0, breakdown.latency_us.p99 as f64 / 1000.0);
+    }
+    for (msg_type, breakdown) in &by_type {
+        println!("  {:>17}: {:>8} msgs, {:>10} bytes, p50 {:.1} ms, p99 {:.1} ms",
+                 journal::message_type_name(*msg_type), breakdown.messages, breakdown.bytes,
+                 breakdown.latency_us.p50 as f64 / 1000.0, breakdown.latency_us.p99 as f64 / 1000.0);
+    }
+}
+
+fn draw_replay<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    theme: &Theme,
+    entries: &[JournalEntry],
+    summary: &ReplaySummary,
+) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let (primary, secondary) = (theme.series(0), theme.series(1));
+    let start = entries.iter().map(|e| e.received_at_us).min().unwrap_or(0);
+    let seconds = (summary.duration_secs.ceil()).max(1.0);
+    let mut latencies_ms: Vec<f64> = entries.iter().map(|e| e.latency_us() as f64 / 1000.0).collect();
+    latencies_ms.sort_by(f64::total_cmp);
+    let worst_ms = latencies_ms.last().copied().unwrap_or(0.0).max(1.0) * 1.1;
+
+    let root = root.margin(10, 10, 10, 10);
+    let areas = root.split_evenly((2, 2));
+
+    // One-way latency of every message, in arrival order
+    {
+        let mut chart = ChartBuilder::on(&areas[0])
+            .caption("One-way Latency over Time", theme.text(24))
+            .margin(5)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0f64..seconds, 0f64..worst_ms)?;
+
+        themed_mesh!(chart, theme)
+            .x_desc("Time (seconds)")
+            .y_desc("Latency (ms)")
+            .draw()?;
+
+        chart.draw_series(entries.iter().map(|e| {
+            let at = (e.received_at_us - start) as f64 / 1_000_000.0;
+            Circle::new((at, e.latency_us() as f64 / 1000.0), 2, primary.filled())
+        }))?;
+    }
+
+    // Share of messages at or below each latency
+    {
+        let mut chart = ChartBuilder::on(&areas[1])
+            .caption(format!("Latency CDF (p99 {:.1} ms)", summary.latency_us.p99 as f64 / 1000.0), theme.text(24))
+            .margin(5)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0f64..worst_ms, 0f64..100f64)?;
+
+        themed_mesh!(chart, theme)
+            .x_desc("Latency (ms)")
+            .y_desc("Messages (%)")
+            .draw()?;
+
+        let total = latencies_ms.len().max(1) as f64;
+        chart.draw_series(LineSeries::new(
+            latencies_ms.iter().enumerate().map(|(i, &ms)| (ms, (i + 1) as f64 * 100.0 / total)),
+            &primary,
+        ))?;
+    }
+
+    // Messages missing from sequence gaps, by when the gap was noticed
+    {
+        let mut missing = vec![0u64; seconds as usize];
+        for gap in summary.senders.iter().flat_map(|s| &s.gaps) {
+            let second = ((gap.at_us / 1_000_000) as usize).min(missing.len() - 1);
+            missing[second] += gap.missing as u64;
+        }
+        let peak = missing.iter().copied().max().unwrap_or(0) as f64;
+
+        let mut chart = ChartBuilder::on(&areas[2])
+            .caption(format!("Sequence Gaps ({} missing)", summary.missing), theme.text(24))
+            .margin(5)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0f64..seconds, 0f64..(peak * 1.1).max(1.0))?;
+
+        themed_mesh!(chart, theme)
+            .x_desc("Time (seconds)")
+            .y_desc("Missing messages")
+            .draw()?;
+
+        chart.draw_series(missing.iter().enumerate().filter(|(_, n)| **n > 0).map(|(s, &n)| {
+            Rectangle::new([(s as f64 + 0.1, 0.0), (s as f64 + 0.9, n as f64)], secondary.filled())
+        }))?;
+    }
+
+    // Longest silence per sender, the usual sign of a dropped link
+    {
+        let labels: Vec<String> = summary.senders.iter().map(|s| s.sender_id.to_string()).collect();
+        let longest = summary.senders.iter().map(|s| s.interarrival_us.max).max().unwrap_or(0) as f64 / 1000.0;
+
+        let mut chart = ChartBuilder::on(&areas[3])
+            .caption("Longest Silence per Sender", theme.text(24))
+            .margin(5)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(-0.5f64..labels.len().max(1) as f64 - 0.5, 0f64..(longest * 1.1).max(1.0))?;
+
+        themed_mesh!(chart, theme)
+            .disable_x_mesh()
+            .x_labels(labels.len().max(1))
+            .x_label_formatter(&|x| {
+                if (x - x.round()).abs() < 1e-6 {
+                    labels.get(x.round() as usize).cloned().unwrap_or_default()
+                } else {
+                    String::new()
+                }
+            })
+            .y_desc("Gap between messages (ms)")
+            .draw()?;
+
+        chart.draw_series(summary.senders.iter().enumerate().map(|(i, s)| {
+            let ms = s.interarrival_us.max as f64 / 1000.0;
+            Rectangle::new([(i as f64 - 0.3, 0.0), (i as f64 + 0.3, ms)], primary.filled())
+        }))?;
+    }
+
+    Ok(())
+}
+
+fn print_replay(summary: &ReplaySummary) {
+    println!("\n=== REPLAY ANALYSIS ({} messages over {:.1}s) ===", summary.messages, summary.duration_secs);
+    println!("  Missing: {}, duplicates: {}, reordered: {}", summary.missing, summary.duplicates, summary.reordered);
+    println!("  Latency p50/p99/max: {:.1}/{:.1}/{:.1} ms",
+             summary.latency_us.p50 as f64 / 1000.0, summary.latency_us.p99 as f64 / 1000.0,
+             summary.latency_us.max as f64 / 1000.0);
+    for sender in &summary.senders {
+        println!("  sender {:>10}: {:>8} msgs, {:>6} missing, {} gaps, {} restarts, longest silence {:.1} ms",
+                 sender.sender_id, sender.messages, sender.missing, sender.gaps.len(), sender.restarts,
+                 sender.interarrival_us.max as f64 / 1000.0);
+    }
+}
+
+/// Most history series drawn on one chart, so the legend stays readable
+const MAX_HISTORY_SERIES: usize = 12;
+
+/// Each `bench/metric` in the history with its value per run (by run index), in
+/// name order, restricted to names containing one of `filters` if any are given
+fn history_series(records: &[BenchRecord], filters: &[String]) -> BTreeMap<String, Vec<(usize, f64)>> {
+    let mut series: BTreeMap<String, Vec<(usize, f64)>> = BTreeMap::new();
+    for (run, record) in records.iter().enumerate() {
+        for (metric, &value) in &record.metrics {
+            let name = format!("{}/{}", record.bench, metric);
+            if filters.is_empty() || filters.iter().any(|filter| name.contains(filter.as_str())) {
+                series.entry(name).or_default().push((run, value));
+            }
+        }
+    }
+    series
+}
+
+fn draw_history<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    theme: &Theme,
+    records: &[BenchRecord],
+    series: &BTreeMap<String, Vec<(usize, f64)>>,
+) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    // Metrics have different units, so each is drawn relative to its first run
+    let relative: Vec<(&String, Vec<(f64, f64)>)> = series.iter().take(MAX_HISTORY_SERIES).map(|(name, points)| {
+        let baseline = points.first().map(|&(_, value)| value).filter(|&value| value != 0.0).unwrap_or(1.0);
+        (name, points.iter().map(|&(run, value)| (run as f64, value / baseline * 100.0)).collect())
+    }).collect();
+    let (low, high) = relative.iter().flat_map(|(_, points)| points.iter().map(|&(_, y)| y))
+        .fold((100f64, 100f64), |(low, high), y| (low.min(y), high.max(y)));
+    let labels: Vec<String> = records.iter().map(|record| {
+        let date = chrono::DateTime::from_timestamp_micros(record.recorded_at_us as i64)
+            .map(|at| at.format("%m-%d").to_string())
+            .unwrap_or_default();
+        match &record.git_hash {
+            Some(hash) => format!("{} {}", date, &hash[..hash.len().min(7)]),
+            None => date,
+        }
+    }).collect();
+
+    let mut chart = ChartBuilder::on(root)
+        .caption(format!("Benchmark History ({} runs)", records.len()), theme.text(30))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(80)
+        .build_cartesian_2d(-0.5f64..records.len().max(1) as f64 - 0.5, (low * 0.9).min(90.0)..(high * 1.1).max(110.0))?;
+
+    themed_mesh!(chart, theme)
+        .x_labels(labels.len().clamp(1, 12))
+        .x_label_formatter(&|x| {
+            if (x - x.round()).abs() < 1e-6 {
+                labels.get(x.round() as usize).cloned().unwrap_or_default()
+            } else {
+                String::new()
+            }
+        })
+        .y_desc("% of first run")
+        .draw()?;
+
+    for (i, (name, points)) in relative.into_iter().enumerate() {
+        let color = theme.series(i);
+        chart
+            .draw_series(LineSeries::new(points.iter().copied(), color.stroke_width(2)))?
+            .label(name.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], color.stroke_width(2)));
+        chart.draw_series(points.into_iter().map(|point| Circle::new(point, 3, color.filled())))?;
+    }
+
+    themed_legend!(chart, theme)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+fn print_history(records: &[BenchRecord], series: &BTreeMap<String, Vec<(usize, f64)>>) {
+    println!("\n=== BENCHMARK HISTORY ({} runs) ===", records.len());
+    for (name, points) in series {
+        let (first, last) = (points[0].1, points[points.len() - 1].1);
+        let change = if first != 0.0 { (last - first) / first * 100.0 } else { 0.0 };
+        println!("  {:<48} {:>14.1} ({:+.1}% over {} runs)", name, last, change, points.len());
+    }
+    if series.len() > MAX_HISTORY_SERIES {
+        println!("  Charted the first {} of {} metrics; narrow them with --history-metrics", MAX_HISTORY_SERIES, series.len());
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Settings::load(Args::parse())?;
+    fs::create_dir_all(&settings.output_dir)?;
+
     println!("Generating performance visualization...");
     
-    let data = generate_mock_data();
-    
-    // Save data as JSON for reference
-    let json_data = serde_json::to_string_pretty(&data)?;
-    fs::write("performance_data.json", json_data)?;
-    
-    // Create the performance comparison chart
-    create_performance_comparison_chart(&data)?;
-    
-    // Print summary statistics
-    println!("\n=== PERFORMANCE SUMMARY ===");
-    println!("Serialization improvements:");
-    for result in &data.serialization {
-        let improvement = ((result.c_style_time_ns - result.rust_time_ns) / result.c_style_time_ns) * 100.0;
-        println!("  Payload {}B: {:.1}% faster", result.payload_size, improvement);
+    let mut data = generate_mock_data();
+
+    // Prefer allocations measured by `cargo run --example cpp_comparison --features alloc-count`
+    let (path, required) = &settings.allocation_data;
+    if let Some(json) = read_input(path, *required)? {
+        data.memory_efficiency = serde_json::from_str(&json)?;
+        println!("Using measured allocations from {}", path.display());
     }
     
-    println!("\nMemory efficiency improvements:");
-    for result in &data.memory_efficiency {
-        let improvement = ((result.c_style_memory_kb - result.rust_memory_kb) / result.c_style_memory_kb) * 100.0;
-        println!("  Payload {}B: {:.1}% less memory", result.payload_size, improvement);
+    if settings.wants(ChartKind::Comparison) {
+        // Save data as JSON for reference
+        let json_data = serde_json::to_string_pretty(&data)?;
+        fs::write(settings.output_dir.join("performance_data.json"), json_data)?;
+
+        render_chart!(settings, "performance_comparison", |root| {
+            draw_performance_comparison(&root, &settings.theme, &data, settings.autoscale)
+        });
+        
+        // Print summary statistics
+        println!("\n=== PERFORMANCE SUMMARY ===");
+        println!("Serialization improvements:");
+        for result in &data.serialization {
+            let improvement = ((result.c_style_time_ns - result.rust_time_ns) / result.c_style_time_ns) * 100.0;
+            println!("  Payload {}B: {:.1}% faster", result.payload_size, improvement);
+        }
+        
+        println!("\nMemory efficiency improvements:");
+        for result in &data.memory_efficiency {
+            let improvement = ((result.c_style_memory_kb - result.rust_memory_kb) / result.c_style_memory_kb) * 100.0;
+            println!("  Payload {}B: {:.1}% less memory", result.payload_size, improvement);
+        }
+        
+        println!("\nCPU efficiency improvements:");
+        for result in &data.cpu_efficiency {
+            println!("  {}: {:.1}% fewer cycles", result.operation, result.improvement_percent);
+        }
     }
-    
-    println!("\nCPU efficiency improvements:");
-    for result in &data.cpu_efficiency {
-        println!("  {}: {:.1}% fewer cycles", result.operation, result.improvement_percent);
+
+    // Include the latest soak run, if the soak benchmark has been run
+    let (path, required) = &settings.soak_report;
+    if settings.wants(ChartKind::Soak)
+        && let Some(json) = read_input(path, *required)?
+    {
+        let report: SoakReport = serde_json::from_str(&json)?;
+        render_chart!(settings, "soak_report", |root| draw_soak(&root, &settings.theme, &report));
+
+        println!("\nSoak run ({:.0}s at {:.0} msg/s):", report.duration_secs, report.target_rate);
+        println!("  Delivered: {}/{} ({} lost)", report.messages_received, report.messages_sent, report.messages_lost);
+        println!("  Latency p50/p99/max: {}/{}/{} us", report.latency_us.p50, report.latency_us.p99, report.latency_us.max);
     }
-    
+
+    // A journal recorded by a receiver adds per-sender and per-type charts
+    if settings.wants(ChartKind::Breakdown)
+        && let Some(path) = &settings.journal
+    {
+        let entries = journal::read_journal(path)?;
+        render_chart!(settings, "fleet_breakdown", |root| draw_breakdown(&root, &settings.theme, &entries));
+        print_breakdown(&entries);
+    }
+
+    // Offline analysis of a recording from an incident, journal or pcap
+    if settings.wants(ChartKind::Replay)
+        && let Some(path) = &settings.replay
+    {
+        let entries = replay::load_recording(path)?;
+        let summary = replay::analyze(&entries);
+        fs::write(settings.output_dir.join("replay_summary.json"), serde_json::to_string_pretty(&summary)?)?;
+        render_chart!(settings, "replay_analysis", |root| draw_replay(&root, &settings.theme, &entries, &summary));
+        print_replay(&summary);
+    }
+
+    // Results appended over time by the bench harness's --history option
+    let (path, required) = &settings.history;
+    if settings.wants(ChartKind::History)
+        && (*required || path.exists())
+    {
+        let records = bench_history::read_history(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let series = history_series(&records, &settings.history_metrics);
+        if series.is_empty() {
+            println!("\nNo history metrics to chart in {}", path.display());
+        } else {
+            render_chart!(settings, "bench_history", |root| draw_history(&root, &settings.theme, &records, &series));
+            print_history(&records, &series);
+        }
+    }
+
     Ok(())
 }
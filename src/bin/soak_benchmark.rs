@@ -0,0 +1,220 @@
+use async_std::task;
+use fleetlink_transport::bench_history::{self, BenchRecord};
+use fleetlink_transport::soak::{self, LatencySummary, SoakReport};
+use fleetlink_transport::{FleetMsgHeader, MulticastSender, start_multicast_rx};
+use std::net::{Ipv4Addr, SocketAddr};
+#[cfg(feature = "profiling")]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SOAK_SENDER_ID: u32 = 0x50AC;
+
+#[derive(Debug)]
+struct SoakConfig {
+    group: Ipv4Addr,
+    port: u16,
+    rate: f64,
+    duration: Duration,
+    payload_size: usize,
+    output: String,
+    /// Sample the process while soaking and write a flamegraph next to the report
+    profile: bool,
+    /// History file to append the run's headline numbers to
+    history: Option<String>,
+}
+
+impl SoakConfig {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Self {
+            group: Ipv4Addr::new(239, 1, 1, 20),
+            port: 12360,
+            rate: 1000.0,
+            duration: Duration::from_secs(300),
+            payload_size: 256,
+            output: "soak_report.json".to_string(),
+            profile: false,
+            history: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(flag) = args.next() {
+            if flag == "--profile" {
+                config.profile = true;
+                continue;
+            }
+            let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+            let invalid = |_| format!("invalid value '{}' for {}", value, flag);
+            match flag.as_str() {
+                "--group" => config.group = value.parse().map_err(|_| format!("invalid group '{}'", value))?,
+                "--port" => config.port = value.parse().map_err(invalid)?,
+                "--rate" => config.rate = value.parse().map_err(|_| format!("invalid rate '{}'", value))?,
+                "--duration" => config.duration = Duration::from_secs(value.parse().map_err(invalid)?),
+                "--payload" => config.payload_size = value.parse().map_err(invalid)?,
+                "--output" => config.output = value,
+                "--history" => config.history = Some(value),
+                other => return Err(format!("unknown option {}", other)),
+            }
+        }
+
+        if config.rate <= 0.0 {
+            return Err("rate must be positive".to_string());
+        }
+        if config.profile && !cfg!(feature = "profiling") {
+            return Err("--profile needs a build with --features profiling".to_string());
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Received {
+    messages: u64,
+    latencies_us: Vec<u64>,
+    per_second: Vec<u64>,
+}
+
+async fn run_soak(config: SoakConfig) -> Result<SoakReport, Box<dyn std::error::Error>> {
+    let received = Arc::new(Mutex::new(Received::default()));
+    let start = Instant::now();
+
+    let received_rx = received.clone();
+    let handler = move |header: FleetMsgHeader, payload: Vec<u8>, _addr: SocketAddr| {
+        if header.sender_id.get() != SOAK_SENDER_ID {
+            return;
+        }
+        let mut received = received_rx.lock().unwrap();
+        received.messages += 1;
+        if let Some(sent_us) = soak::soak_timestamp(&payload) {
+            received.latencies_us.push(soak::now_micros().saturating_sub(sent_us));
+        }
+        let second = start.elapsed().as_secs() as usize;
+        if received.per_second.len() <= second {
+            received.per_second.resize(second + 1, 0);
+        }
+        received.per_second[second] += 1;
+    };
+
+    let (group, port) = (config.group, config.port);
+    let receiver = task::spawn(async move {
+        if let Err(e) = start_multicast_rx(group, port, handler).await {
+            eprintln!("Receiver error: {}", e);
+        }
+    });
+    task::sleep(Duration::from_millis(500)).await;
+
+    let drops_before = soak::kernel_udp_drops(config.port);
+    let mut sender = MulticastSender::new(config.group, config.port, SOAK_SENDER_ID).await?;
+    let interval = Duration::from_secs_f64(1.0 / config.rate);
+
+    println!("Soaking {}:{} at {} msg/s for {:?}", config.group, config.port, config.rate, config.duration);
+    let send_start = Instant::now();
+    let mut sent = 0u64;
+    while send_start.elapsed() < config.duration {
+        // Pace against the schedule rather than the last send so sleep overshoot doesn't accumulate
+        let due = send_start + interval.mul_f64(sent as f64);
+        let now = Instant::now();
+        if due > now {
+            task::sleep(due - now).await;
+        }
+        sender.send_data(&soak::soak_payload(config.payload_size)).await?;
+        sent += 1;
+    }
+    let send_elapsed = send_start.elapsed().as_secs_f64();
+
+    // Let the receiver drain what is still in flight
+    task::sleep(Duration::from_secs(1)).await;
+    let drops_after = soak::kernel_udp_drops(config.port);
+    receiver.cancel().await;
+
+    let mut received = std::mem::take(&mut *received.lock().unwrap());
+    Ok(SoakReport {
+        target_rate: config.rate,
+        payload_size: config.payload_size.max(soak::SOAK_TIMESTAMP_LEN),
+        duration_secs: send_elapsed,
+        messages_sent: sent,
+        messages_received: received.messages,
+        messages_lost: sent.saturating_sub(received.messages),
+        achieved_send_rate: sent as f64 / send_elapsed,
+        achieved_receive_rate: received.messages as f64 / send_elapsed,
+        kernel_drops: drops_before.zip(drops_after).map(|(before, after)| after.saturating_sub(before)),
+        latency_us: LatencySummary::from_samples(&mut received.latencies_us),
+        per_second: received.per_second,
+        flamegraph: None,
+    })
+}
+
+/// `soak_report.json` -> `soak_report_flamegraph.svg`, in the same directory
+#[cfg(feature = "profiling")]
+fn flamegraph_path(output: &str) -> PathBuf {
+    let output = Path::new(output);
+    let stem = output.file_stem().and_then(|stem| stem.to_str()).unwrap_or("soak_report");
+    output.with_file_name(format!("{}_flamegraph.svg", stem))
+}
+
+#[cfg(feature = "profiling")]
+fn start_profiler(enabled: bool) -> Result<Option<pprof::ProfilerGuard<'static>>, Box<dyn std::error::Error>> {
+    if !enabled {
+        return Ok(None);
+    }
+    // Just under 1 kHz, so sampling doesn't fall into step with the send schedule
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(997)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()?;
+    Ok(Some(guard))
+}
+
+/// Write the flamegraph, if profiling, and note it in the report
+#[cfg(feature = "profiling")]
+fn finish_profile(
+    profiler: Option<pprof::ProfilerGuard<'static>>,
+    mut report: SoakReport,
+    output: &str
+) -> Result<SoakReport, Box<dyn std::error::Error>> {
+    if let Some(guard) = profiler {
+        let path = flamegraph_path(output);
+        guard.report().build()?.flamegraph(std::fs::File::create(&path)?)?;
+        report.flamegraph = Some(path.display().to_string());
+    }
+    Ok(report)
+}
+
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = match SoakConfig::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: soak_benchmark [--rate MSG_PER_SEC] [--duration SECS] [--payload BYTES] [--group ADDR] [--port PORT] [--output FILE] [--history FILE] [--profile]");
+            std::process::exit(2);
+        }
+    };
+    let (output, history) = (config.output.clone(), config.history.clone());
+
+    #[cfg(feature = "profiling")]
+    let profiler = start_profiler(config.profile)?;
+    let report = run_soak(config).await?;
+    #[cfg(feature = "profiling")]
+    let report = finish_profile(profiler, report, &output)?;
+    std::fs::write(&output, serde_json::to_string_pretty(&report)?)?;
+
+    println!("\n=== SOAK SUMMARY ===");
+    println!("Sent:      {} ({:.1} msg/s, target {:.1})", report.messages_sent, report.achieved_send_rate, report.target_rate);
+    println!("Received:  {} ({:.1} msg/s)", report.messages_received, report.achieved_receive_rate);
+    println!("Lost:      {}", report.messages_lost);
+    match report.kernel_drops {
+        Some(drops) => println!("Kernel drops: {}", drops),
+        None => println!("Kernel drops: unavailable"),
+    }
+    println!("Latency:   p50 {}us, p99 {}us, max {}us", report.latency_us.p50, report.latency_us.p99, report.latency_us.max);
+    println!("Report written to {}", output);
+    if let Some(flamegraph) = &report.flamegraph {
+        println!("Flamegraph written to {}", flamegraph);
+    }
+    if let Some(path) = history {
+        bench_history::append(&path, &BenchRecord::from_soak(&report))?;
+        println!("Appended to history {}", path);
+    }
+    Ok(())
+}
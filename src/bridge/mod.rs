@@ -0,0 +1,233 @@
+//! Backplane mode: mirror fleet traffic between the multicast group and a
+//! message broker, so cloud services can take part in fleet messaging during
+//! development without being on the LAN.
+//!
+//! Datagrams are forwarded unchanged, headers and sender ids included, inside
+//! an envelope naming the bridge that published them; a bridge with
+//! [`Transforms`] rewrites the payloads it publishes to the broker, and
+//! withholds those it can't read. Loops are prevented by
+//! dropping the broker's echoes of a bridge's own envelopes and by never
+//! forwarding a datagram the bridge has recently seen from the other side.
+
+pub mod nats;
+pub mod redis;
+
+use async_std::net::UdpSocket;
+use futures::future::{Either, select};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::pin;
+use std::time::{Duration, Instant};
+use zerocopy::FromBytes;
+
+use crate::transform::Transforms;
+use crate::transport::FleetMsgHeader;
+
+pub use nats::NatsBroker;
+pub use redis::RedisBroker;
+
+/// Bytes before the datagram in a broker message: the publishing bridge's id, little-endian
+pub const ENVELOPE_HEADER_LEN: usize = 4;
+
+/// Bridge id for publishers that aren't bridges, such as cloud services
+pub const NO_BRIDGE: u32 = 0;
+
+/// How long a forwarded datagram is remembered, to recognise it coming back
+const RECENT_TTL: Duration = Duration::from_secs(5);
+
+/// Upper bound on remembered datagrams, whatever the traffic rate
+const MAX_RECENT: usize = 65_536;
+
+/// A publish/subscribe connection carrying one topic of bridged traffic
+pub trait Broker: Send + Sync {
+    /// Publish one envelope to the topic
+    fn publish(&self, envelope: &[u8]) -> impl Future<Output = std::io::Result<()>> + Send;
+
+    /// Wait for the next envelope on the topic
+    fn next_message(&self) -> impl Future<Output = std::io::Result<Vec<u8>>> + Send;
+}
+
+/// Wrap a datagram for the broker
+pub(crate) fn envelope(bridge_id: u32, datagram: &[u8]) -> Vec<u8> {
+    let mut envelope = Vec::with_capacity(ENVELOPE_HEADER_LEN + datagram.len());
+    envelope.extend_from_slice(&bridge_id.to_le_bytes());
+    envelope.extend_from_slice(datagram);
+    envelope
+}
+
+/// Split a broker message into the publishing bridge's id and the datagram
+pub(crate) fn open_envelope(envelope: &[u8]) -> Option<(u32, &[u8])> {
+    let (id, datagram) = envelope.split_at_checked(ENVELOPE_HEADER_LEN)?;
+    Some((u32::from_le_bytes(id.try_into().ok()?), datagram))
+}
+
+/// Decides what crosses the bridge in each direction
+#[derive(Debug)]
+pub struct LoopGuard {
+    bridge_id: u32,
+    seen: HashMap<u64, Instant>,
+    order: VecDeque<(Instant, u64)>,
+}
+
+impl LoopGuard {
+    pub fn new(bridge_id: u32) -> Self {
+        Self { bridge_id, seen: HashMap::new(), order: VecDeque::new() }
+    }
+
+    /// The envelope to publish for a datagram heard on multicast, unless it is
+    /// one this bridge put there or already carried
+    pub fn to_broker(&mut self, datagram: &[u8], now: Instant) -> Option<Vec<u8>> {
+        self.first_sighting(datagram, now).then(|| envelope(self.bridge_id, datagram))
+    }
+
+    /// The datagram to send on multicast for a broker message, unless it is
+    /// this bridge's own echo, already carried, or not a valid fleet message
+    pub fn from_broker<'a>(&mut self, message: &'a [u8], now: Instant) -> Option<&'a [u8]> {
+        let (origin, datagram) = open_envelope(message)?;
+        if origin == self.bridge_id || !FleetMsgHeader::read_from_prefix(datagram).is_some_and(|header| header.is_valid()) {
+            return None;
+        }
+        self.first_sighting(datagram, now).then_some(datagram)
+    }
+
+    /// Remember `datagram`, reporting whether it was new
+    fn first_sighting(&mut self, datagram: &[u8], now: Instant) -> bool {
+        while let Some(&(at, hash)) = self.order.front()
+            && (now.saturating_duration_since(at) > RECENT_TTL || self.order.len() >= MAX_RECENT)
+        {
+            self.order.pop_front();
+            if self.seen.get(&hash) == Some(&at) {
+                self.seen.remove(&hash);
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        datagram.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.seen.contains_key(&hash) {
+            return false;
+        }
+        self.seen.insert(hash, now);
+        self.order.push_back((now, hash));
+        true
+    }
+}
+
+/// Forwards between a multicast group and a [`Broker`] until either side fails
+#[derive(Debug)]
+pub struct BrokerBridge<B> {
+    broker: B,
+    group: Ipv4Addr,
+    port: u16,
+    bridge_id: u32,
+    transforms: Option<Transforms>,
+}
+
+impl<B: Broker> BrokerBridge<B> {
+    /// A bridge with a random id; give each bridge on a broker a distinct one with [`with_bridge_id`](Self::with_bridge_id)
+    pub fn new(broker: B, group: Ipv4Addr, port: u16) -> Self {
+        Self { broker, group, port, bridge_id: rand::random::<u32>().max(1), transforms: None }
+    }
+
+    pub fn with_bridge_id(mut self, bridge_id: u32) -> Self {
+        self.bridge_id = bridge_id;
+        self
+    }
+
+    /// Rewrite payloads published to the broker by topic, e.g. to coarsen
+    /// positions; what comes from the broker is sent on unchanged
+    pub fn with_transforms(mut self, transforms: Transforms) -> Self {
+        self.transforms = Some(transforms);
+        self
+    }
+
+    pub fn bridge_id(&self) -> u32 {
+        self.bridge_id
+    }
+
+    /// The envelope to publish with its datagram transformed, or `None` if withheld
+    fn transformed(&self, envelope: Vec<u8>) -> Option<Vec<u8>> {
+        let Some(transforms) = &self.transforms else { return Some(envelope) };
+        let (_, datagram) = open_envelope(&envelope)?;
+        match transforms.apply_to_datagram(datagram)? {
+            Cow::Borrowed(_) => Some(envelope),
+            Cow::Owned(datagram) => Some(self::envelope(self.bridge_id, &datagram)),
+        }
+    }
+
+    pub async fn run(self) -> std::io::Result<()> {
+        let socket = UdpSocket::bind(("0.0.0.0", self.port)).await?;
+        socket.join_multicast_v4(self.group, Ipv4Addr::UNSPECIFIED)?;
+        let destination = SocketAddr::new(IpAddr::V4(self.group), self.port);
+        let guard = std::sync::Mutex::new(LoopGuard::new(self.bridge_id));
+        println!("Bridging {} with the broker as bridge {:#010x}", destination, self.bridge_id);
+
+        let outbound = async {
+            let mut buf = vec![0u8; 65_536];
+            loop {
+                let (len, _) = socket.recv_from(&mut buf).await?;
+                // The guard remembers the datagram as heard, so it is recognised coming back
+                let envelope = guard.lock().unwrap().to_broker(&buf[..len], Instant::now()).and_then(|envelope| self.transformed(envelope));
+                if let Some(envelope) = envelope {
+                    self.broker.publish(&envelope).await?;
+                }
+            }
+        };
+        let inbound = async {
+            loop {
+                let message = self.broker.next_message().await?;
+                let datagram = guard.lock().unwrap().from_broker(&message, Instant::now()).map(<[u8]>::to_vec);
+                if let Some(datagram) = datagram {
+                    socket.send_to(&datagram, destination).await?;
+                }
+            }
+        };
+
+        match select(pin!(outbound), pin!(inbound)).await {
+            Either::Left((result, _)) | Either::Right((result, _)) => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MessageType;
+    use zerocopy::AsBytes;
+
+    fn datagram(sequence: u16) -> Vec<u8> {
+        let header = FleetMsgHeader::new(MessageType::Data, 7, sequence, 2);
+        [header.as_bytes(), b"hi"].concat()
+    }
+
+    #[test]
+    fn test_two_bridges_do_not_loop() {
+        let now = Instant::now();
+        let (mut a, mut b) = (LoopGuard::new(1), LoopGuard::new(2));
+        let heard = datagram(1);
+
+        // Heard on A's LAN: published once, A's own echo from the broker is dropped
+        let published = a.to_broker(&heard, now).unwrap();
+        assert_eq!(a.from_broker(&published, now), None);
+        // B puts it on its LAN, then hears it there and doesn't send it back
+        let injected = b.from_broker(&published, now).unwrap().to_vec();
+        assert_eq!(injected, heard);
+        assert_eq!(b.to_broker(&injected, now), None);
+        // The same datagram heard again on A's LAN within the window goes nowhere
+        assert_eq!(a.to_broker(&heard, now), None);
+        assert!(a.to_broker(&heard, now + RECENT_TTL * 2).is_some());
+    }
+
+    #[test]
+    fn test_invalid_broker_messages_are_dropped() {
+        let now = Instant::now();
+        let mut guard = LoopGuard::new(1);
+        assert_eq!(guard.from_broker(b"ab", now), None);
+        assert_eq!(guard.from_broker(&envelope(NO_BRIDGE, b"not a fleet message"), now), None);
+        assert!(guard.from_broker(&envelope(NO_BRIDGE, &datagram(3)), now).is_some());
+    }
+}
@@ -0,0 +1,91 @@
+use async_std::io::prelude::{BufReadExt, ReadExt, WriteExt};
+use async_std::io::BufReader;
+use async_std::net::{TcpStream, ToSocketAddrs};
+use async_std::sync::Mutex;
+use std::io::{Error, ErrorKind};
+
+use super::Broker;
+
+/// Subject bridges publish and subscribe to unless told otherwise
+pub const DEFAULT_SUBJECT: &str = "fleetlink.traffic";
+
+/// A NATS connection publishing and subscribing to one subject, speaking the
+/// plain-text client protocol directly (no TLS or authentication)
+#[derive(Debug)]
+pub struct NatsBroker {
+    subject: String,
+    writer: Mutex<TcpStream>,
+    reader: Mutex<BufReader<TcpStream>>,
+}
+
+impl NatsBroker {
+    pub async fn connect(addr: impl ToSocketAddrs, subject: &str) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut reader = BufReader::new(stream.clone());
+        let mut writer = stream;
+
+        let info = read_line(&mut reader).await?;
+        if !info.starts_with("INFO ") {
+            return Err(Error::new(ErrorKind::InvalidData, format!("expected INFO from the NATS server, got '{}'", info)));
+        }
+        // `echo: false` keeps the server from sending our own publishes back; the PING's PONG
+        // confirms it accepted the CONNECT and SUB
+        let handshake = format!(
+            "CONNECT {{\"verbose\":false,\"pedantic\":false,\"echo\":false,\"name\":\"fleetlink-bridge\",\"lang\":\"rust\",\"version\":\"{}\"}}\r\nSUB {} 1\r\nPING\r\n",
+            env!("CARGO_PKG_VERSION"), subject
+        );
+        writer.write_all(handshake.as_bytes()).await?;
+        loop {
+            let line = read_line(&mut reader).await?;
+            match line.split_ascii_whitespace().next() {
+                Some("PONG") => break,
+                Some("-ERR") => return Err(Error::other(format!("NATS server refused the connection: {}", line))),
+                _ => {}
+            }
+        }
+
+        Ok(Self { subject: subject.to_string(), writer: Mutex::new(writer), reader: Mutex::new(reader) })
+    }
+}
+
+impl Broker for NatsBroker {
+    async fn publish(&self, envelope: &[u8]) -> std::io::Result<()> {
+        let mut frame = format!("PUB {} {}\r\n", self.subject, envelope.len()).into_bytes();
+        frame.extend_from_slice(envelope);
+        frame.extend_from_slice(b"\r\n");
+        self.writer.lock().await.write_all(&frame).await
+    }
+
+    async fn next_message(&self) -> std::io::Result<Vec<u8>> {
+        let mut reader = self.reader.lock().await;
+        loop {
+            let line = read_line(&mut reader).await?;
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                // MSG <subject> <sid> [reply-to] <bytes>, then the payload and CRLF
+                Some("MSG") => {
+                    let len: usize = words.last().and_then(|len| len.parse().ok()).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, format!("malformed NATS message line '{}'", line))
+                    })?;
+                    let mut payload = vec![0u8; len + 2];
+                    reader.read_exact(&mut payload).await?;
+                    payload.truncate(len);
+                    return Ok(payload);
+                }
+                // The server drops clients that don't answer its keep-alive
+                Some("PING") => self.writer.lock().await.write_all(b"PONG\r\n").await?,
+                Some("-ERR") => return Err(Error::other(format!("NATS server error: {}", line))),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// One protocol line without its CRLF
+async fn read_line(reader: &mut BufReader<TcpStream>) -> std::io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "NATS server closed the connection"));
+    }
+    Ok(line.trim_end().to_string())
+}
@@ -0,0 +1,152 @@
+use async_std::io::prelude::{BufReadExt, ReadExt, WriteExt};
+use async_std::io::BufReader;
+use async_std::net::{TcpStream, ToSocketAddrs};
+use async_std::sync::Mutex;
+use std::io::{Error, ErrorKind};
+
+use super::Broker;
+
+/// Channel bridges publish and subscribe to unless told otherwise
+pub const DEFAULT_CHANNEL: &str = "fleetlink:traffic";
+
+/// Redis pub/sub on one channel, over two connections since a subscribed
+/// connection can't publish. Speaks RESP directly (no TLS or authentication).
+#[derive(Debug)]
+pub struct RedisBroker {
+    channel: String,
+    publisher: Mutex<BufReader<TcpStream>>,
+    subscriber: Mutex<BufReader<TcpStream>>,
+}
+
+/// The parts of a RESP reply pub/sub needs
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl RedisBroker {
+    pub async fn connect(addr: impl ToSocketAddrs + Clone, channel: &str) -> std::io::Result<Self> {
+        let publisher = BufReader::new(TcpStream::connect(addr.clone()).await?);
+        let mut subscriber = BufReader::new(TcpStream::connect(addr).await?);
+
+        subscriber.get_mut().write_all(&command(&[b"SUBSCRIBE", channel.as_bytes()])).await?;
+        match read_reply(&mut subscriber).await? {
+            Reply::Array(items) if items.first() == Some(&Reply::Bulk(Some(b"subscribe".to_vec()))) => {}
+            Reply::Error(message) => return Err(Error::other(format!("Redis refused the subscription: {}", message))),
+            other => return Err(Error::new(ErrorKind::InvalidData, format!("unexpected reply to SUBSCRIBE: {:?}", other))),
+        }
+
+        Ok(Self { channel: channel.to_string(), publisher: Mutex::new(publisher), subscriber: Mutex::new(subscriber) })
+    }
+}
+
+impl Broker for RedisBroker {
+    async fn publish(&self, envelope: &[u8]) -> std::io::Result<()> {
+        let mut publisher = self.publisher.lock().await;
+        publisher.get_mut().write_all(&command(&[b"PUBLISH", self.channel.as_bytes(), envelope])).await?;
+        match read_reply(&mut publisher).await? {
+            Reply::Error(message) => Err(Error::other(format!("Redis rejected PUBLISH: {}", message))),
+            _ => Ok(()),
+        }
+    }
+
+    async fn next_message(&self) -> std::io::Result<Vec<u8>> {
+        let mut subscriber = self.subscriber.lock().await;
+        loop {
+            // ["message", channel, payload]; anything else is a subscription change
+            if let Reply::Array(mut items) = read_reply(&mut subscriber).await?
+                && items.len() == 3
+                && items[0] == Reply::Bulk(Some(b"message".to_vec()))
+                && let Reply::Bulk(Some(payload)) = items.pop().unwrap()
+            {
+                return Ok(payload);
+            }
+        }
+    }
+}
+
+/// A command as a RESP array of bulk strings
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut frame = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        frame.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        frame.extend_from_slice(arg);
+        frame.extend_from_slice(b"\r\n");
+    }
+    frame
+}
+
+/// Read one reply; arrays may only hold scalars, which is all pub/sub sends
+async fn read_reply(reader: &mut BufReader<TcpStream>) -> std::io::Result<Reply> {
+    match read_scalar(reader).await? {
+        Err(len) => {
+            let mut items = Vec::with_capacity(len.min(16));
+            for _ in 0..len {
+                items.push(read_scalar(reader).await?.map_err(|_| {
+                    Error::new(ErrorKind::InvalidData, "nested Redis arrays are not supported")
+                })?);
+            }
+            Ok(Reply::Array(items))
+        }
+        Ok(reply) => Ok(reply),
+    }
+}
+
+/// A non-array reply, or the length of an array whose items follow
+async fn read_scalar(reader: &mut BufReader<TcpStream>) -> std::io::Result<Result<Reply, usize>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Redis closed the connection"));
+    }
+    let line = line.trim_end();
+    let malformed = || Error::new(ErrorKind::InvalidData, format!("malformed Redis reply '{}'", line));
+    let (kind, rest) = line.split_at_checked(1).ok_or_else(malformed)?;
+    Ok(match kind {
+        "+" => Ok(Reply::Simple(rest.to_string())),
+        "-" => Ok(Reply::Error(rest.to_string())),
+        ":" => Ok(Reply::Integer(rest.parse().map_err(|_| malformed())?)),
+        "$" => match rest.parse::<i64>().map_err(|_| malformed())? {
+            len if len < 0 => Ok(Reply::Bulk(None)),
+            len => {
+                let mut data = vec![0u8; len as usize + 2];
+                reader.read_exact(&mut data).await?;
+                data.truncate(len as usize);
+                Ok(Reply::Bulk(Some(data)))
+            }
+        },
+        "*" => Err(rest.parse::<i64>().map_err(|_| malformed())?.max(0) as usize),
+        _ => return Err(malformed()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    #[async_std::test]
+    async fn test_resp_round_trip() {
+        assert_eq!(command(&[b"PUBLISH", b"ch", b"\x01\r\n"]), b"*3\r\n$7\r\nPUBLISH\r\n$2\r\nch\r\n$3\r\n\x01\r\n\r\n");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"*3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$4\r\nab\r\n\r\n:1\r\n-ERR nope\r\n").await.unwrap();
+        });
+        let mut reader = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let message = Reply::Array(vec![
+            Reply::Bulk(Some(b"message".to_vec())),
+            Reply::Bulk(Some(b"ch".to_vec())),
+            Reply::Bulk(Some(b"ab\r\n".to_vec())),
+        ]);
+        assert_eq!(read_reply(&mut reader).await.unwrap(), message);
+        assert_eq!(read_reply(&mut reader).await.unwrap(), Reply::Integer(1));
+        assert_eq!(read_reply(&mut reader).await.unwrap(), Reply::Error("ERR nope".to_string()));
+    }
+}
@@ -0,0 +1,81 @@
+//! Bindings to the reference C codec in `c/fleetlink.c`, the baseline the
+//! benchmarks and `cpp_comparison` measure the Rust transport against.
+//!
+//! Only built with the `c-reference` feature, which compiles the C source with
+//! the system C compiler at the same optimization level as the crate.
+
+use zerocopy::FromZeroes;
+
+use crate::transport::{FleetMsgHeader, MessageType};
+
+// `struct fl_header` has the same field order and sizes as `FleetMsgHeader`,
+// which is `repr(C)`, so headers are passed straight through
+unsafe extern "C" {
+    fn fl_header_init(header: *mut FleetMsgHeader, msg_type: u8, sender_id: u32, sequence: u16, payload_len: u16);
+    fn fl_checksum(header: *const FleetMsgHeader) -> u16;
+    fn fl_encode(header: *const FleetMsgHeader, payload: *const u8, out: *mut u8, cap: usize) -> usize;
+    fn fl_decode(datagram: *const u8, len: usize, header: *mut FleetMsgHeader, payload: *mut *const u8) -> i32;
+}
+
+/// A header stamped with the current time, as built by the C codec
+pub fn header(msg_type: MessageType, sender_id: u32, sequence: u16, payload_len: u16) -> FleetMsgHeader {
+    let mut header = FleetMsgHeader::new_zeroed();
+    unsafe { fl_header_init(&mut header, msg_type.code(), sender_id, sequence, payload_len) };
+    header
+}
+
+pub fn checksum(header: &FleetMsgHeader) -> u16 {
+    unsafe { fl_checksum(header) }
+}
+
+/// Write `header` and `payload` into `out`, returning the datagram length, or
+/// `None` if `out` is too small. `payload` must be `header.payload_len` bytes.
+pub fn encode(header: &FleetMsgHeader, payload: &[u8], out: &mut [u8]) -> Option<usize> {
+    assert_eq!(payload.len(), header.payload_len.get() as usize, "payload length doesn't match the header");
+    match unsafe { fl_encode(header, payload.as_ptr(), out.as_mut_ptr(), out.len()) } {
+        0 => None,
+        len => Some(len),
+    }
+}
+
+/// Validate a datagram the way the receive path does, borrowing its payload
+pub fn decode(datagram: &[u8]) -> Option<(FleetMsgHeader, &[u8])> {
+    let mut header = FleetMsgHeader::new_zeroed();
+    let mut payload = std::ptr::null();
+    if unsafe { fl_decode(datagram.as_ptr(), datagram.len(), &mut header, &mut payload) } != 0 {
+        return None;
+    }
+    let offset = datagram.len() - header.payload_len.get() as usize;
+    debug_assert_eq!(payload, datagram[offset..].as_ptr());
+    Some((header, &datagram[offset..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zerocopy::{AsBytes, FromBytes};
+
+    #[test]
+    fn test_c_and_rust_agree_on_the_wire_format() {
+        let header = header(MessageType::Data, 42, 7, 5);
+        assert!(header.is_valid());
+        assert_eq!(checksum(&header), header.checksum.get());
+
+        let mut datagram = [0u8; 64];
+        let len = encode(&header, b"hello", &mut datagram).unwrap();
+        let parsed = FleetMsgHeader::read_from_prefix(&datagram[..len]).unwrap();
+        assert_eq!(parsed.as_bytes(), header.as_bytes());
+        assert_eq!(&datagram[24..len], b"hello");
+
+        let rust = FleetMsgHeader::new(MessageType::Heartbeat, 9, 3, 2);
+        let mut bytes = rust.as_bytes().to_vec();
+        bytes.extend_from_slice(b"hi");
+        let (decoded, payload) = decode(&bytes).unwrap();
+        assert_eq!(decoded.as_bytes(), rust.as_bytes());
+        assert_eq!(payload, b"hi");
+
+        bytes[10] ^= 1;
+        assert!(decode(&bytes).is_none());
+        assert!(encode(&header, b"hello", &mut [0u8; 16]).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+
+use crate::features::ProtocolFeatures;
+
+/// Longest role or capability name, in bytes
+pub const MAX_TAG_LEN: usize = 64;
+/// Most roles, and most capabilities, one node may announce
+pub const MAX_TAGS: usize = 32;
+
+/// What a node is and what it can do, announced in its heartbeats.
+///
+/// On the wire (after the heartbeat's incarnation) each set is a count byte
+/// followed by length-prefixed UTF-8 names, then one byte of protocol features.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// What the node is, e.g. "charger" or "forklift"
+    pub roles: BTreeSet<String>,
+    /// What it carries or supports, e.g. "lidar-v2"
+    pub capabilities: BTreeSet<String>,
+    /// Optional protocol features the node can receive; filled in by the sender
+    #[serde(default)]
+    pub features: ProtocolFeatures,
+}
+
+impl Capabilities {
+    pub fn new<R, C>(roles: R, capabilities: C) -> std::io::Result<Self>
+    where
+        R: IntoIterator,
+        R::Item: Into<String>,
+        C: IntoIterator,
+        C::Item: Into<String>,
+    {
+        let roles: BTreeSet<String> = roles.into_iter().map(Into::into).collect();
+        let capabilities: BTreeSet<String> = capabilities.into_iter().map(Into::into).collect();
+
+        if roles.len() > MAX_TAGS || capabilities.len() > MAX_TAGS {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("at most {} roles and {} capabilities", MAX_TAGS, MAX_TAGS)));
+        }
+        if let Some(bad) = roles.iter().chain(&capabilities).find(|tag| tag.is_empty() || tag.len() > MAX_TAG_LEN) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' must be between 1 and {} bytes", bad, MAX_TAG_LEN),
+            ));
+        }
+
+        Ok(Self { roles, capabilities, features: ProtocolFeatures::NONE })
+    }
+
+    /// Whether `tag` is one of the node's roles or capabilities
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.roles.contains(tag) || self.capabilities.contains(tag)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.capabilities.is_empty() && self.features.is_empty()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for set in [&self.roles, &self.capabilities] {
+            bytes.push(set.len() as u8);
+            for tag in set {
+                bytes.push(tag.len() as u8);
+                bytes.extend_from_slice(tag.as_bytes());
+            }
+        }
+        bytes.push(self.features.bits());
+        bytes
+    }
+
+    /// Parse an announcement; `None` if it is truncated or not UTF-8.
+    /// Announcements from before protocol features were added have none.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut rest = bytes;
+        let mut next_set = || -> Option<BTreeSet<String>> {
+            let (&count, tail) = rest.split_first()?;
+            rest = tail;
+            let mut set = BTreeSet::new();
+            for _ in 0..count {
+                let (&len, tail) = rest.split_first()?;
+                let tag = tail.get(..len as usize)?;
+                set.insert(String::from_utf8(tag.to_vec()).ok()?);
+                rest = &tail[len as usize..];
+            }
+            Some(set)
+        };
+        let roles = next_set()?;
+        let capabilities = next_set()?;
+        let features = rest.first().map_or(ProtocolFeatures::NONE, |&bits| ProtocolFeatures::from_bits(bits));
+        Some(Self { roles, capabilities, features })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_round_trip() {
+        let caps = Capabilities::new(["charger"], ["lidar-v2", "ota"]).unwrap();
+        assert!(caps.has_tag("charger") && caps.has_tag("ota") && !caps.has_tag("forklift"));
+
+        let bytes = caps.encode();
+        assert_eq!(Capabilities::decode(&bytes), Some(caps.clone()));
+        assert_eq!(Capabilities::decode(&bytes[..bytes.len() - 2]), None);
+        // Without the trailing features byte, as older nodes send it
+        assert_eq!(Capabilities::decode(&bytes[..bytes.len() - 1]), Some(caps));
+
+        assert!(Capabilities::new([""], Vec::<String>::new()).is_err());
+        assert!(Capabilities::new(["x".repeat(MAX_TAG_LEN + 1)], Vec::<String>::new()).is_err());
+    }
+}
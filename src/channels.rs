@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Groups handed out when no range is configured
+pub const DEFAULT_GROUPS: RangeInclusive<Ipv4Addr> = Ipv4Addr::new(239, 1, 1, 1)..=Ipv4Addr::new(239, 1, 1, 254);
+/// Ports handed out when no range is configured
+pub const DEFAULT_PORTS: RangeInclusive<u16> = 12345..=12599;
+
+/// Where one logical channel lives on the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Channel {
+    pub group: Ipv4Addr,
+    pub port: u16,
+}
+
+/// Hands out (group, port) pairs for named channels so that no two share
+/// either: receivers bind `0.0.0.0:port`, so a shared port would deliver one
+/// channel's traffic to the other.
+///
+/// Allocations are remembered by name, in memory or in a JSON file
+/// (`with_file`) that separate processes can share.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelRegistry {
+    groups: RangeInclusive<Ipv4Addr>,
+    ports: RangeInclusive<u16>,
+    channels: BTreeMap<String, Channel>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl ChannelRegistry {
+    pub fn new(groups: RangeInclusive<Ipv4Addr>, ports: RangeInclusive<u16>) -> std::io::Result<Self> {
+        if groups.is_empty() || !groups.start().is_multicast() || !groups.end().is_multicast() {
+            return Err(Error::new(ErrorKind::InvalidInput, "group range must be non-empty and multicast"));
+        }
+        if ports.is_empty() || *ports.start() == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "port range must be non-empty and above 0"));
+        }
+        Ok(Self { groups, ports, channels: BTreeMap::new(), path: None })
+    }
+
+    /// Load allocations already recorded at `path` (if any) and record every change there
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            let saved: ChannelRegistry = serde_json::from_str(&std::fs::read_to_string(path)?)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+            self.channels = saved.channels;
+        }
+        self.path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// The channel recorded for `name`, allocating a free one the first time
+    pub fn allocate(&mut self, name: &str) -> std::io::Result<Channel> {
+        if let Some(channel) = self.channels.get(name) {
+            return Ok(*channel);
+        }
+
+        let group = (u32::from(*self.groups.start())..=u32::from(*self.groups.end()))
+            .map(Ipv4Addr::from)
+            .find(|group| self.channels.values().all(|c| c.group != *group));
+        let port = self.ports.clone().find(|port| self.channels.values().all(|c| c.port != *port));
+        let (Some(group), Some(port)) = (group, port) else {
+            return Err(Error::new(ErrorKind::AddrNotAvailable, format!("no free channel left for '{}'", name)));
+        };
+
+        let channel = Channel { group, port };
+        self.channels.insert(name.to_string(), channel);
+        self.save()?;
+        Ok(channel)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Channel> {
+        self.channels.get(name).copied()
+    }
+
+    pub fn release(&mut self, name: &str) -> std::io::Result<Option<Channel>> {
+        let released = self.channels.remove(name);
+        if released.is_some() {
+            self.save()?;
+        }
+        Ok(released)
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = (&str, Channel)> {
+        self.channels.iter().map(|(name, channel)| (name.as_str(), *channel))
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        match &self.path {
+            Some(path) => std::fs::write(path, serde_json::to_string_pretty(self)?),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Process-wide in-memory registry over the default ranges, e.g. for tests
+/// that would otherwise pick their own constants
+pub fn shared() -> &'static Mutex<ChannelRegistry> {
+    static SHARED: OnceLock<Mutex<ChannelRegistry>> = OnceLock::new();
+    SHARED.get_or_init(|| Mutex::new(ChannelRegistry::new(DEFAULT_GROUPS, DEFAULT_PORTS).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocations_are_distinct_and_remembered() {
+        let path = std::env::temp_dir().join(format!("fleet_channels_{}.json", std::process::id()));
+        let groups = Ipv4Addr::new(239, 9, 0, 1)..=Ipv4Addr::new(239, 9, 0, 2);
+        let mut registry = ChannelRegistry::new(groups.clone(), 20000..=20001).unwrap().with_file(&path).unwrap();
+
+        let telemetry = registry.allocate("telemetry").unwrap();
+        let commands = registry.allocate("commands").unwrap();
+        assert_eq!(telemetry, Channel { group: Ipv4Addr::new(239, 9, 0, 1), port: 20000 });
+        assert_eq!(commands, Channel { group: Ipv4Addr::new(239, 9, 0, 2), port: 20001 });
+        assert_eq!(registry.allocate("telemetry").unwrap(), telemetry);
+        assert_eq!(registry.allocate("ota").unwrap_err().kind(), ErrorKind::AddrNotAvailable);
+
+        // Another process opening the same file sees the same allocations
+        let mut reopened = ChannelRegistry::new(groups, 20000..=20001).unwrap().with_file(&path).unwrap();
+        assert_eq!(reopened.get("commands"), Some(commands));
+        reopened.release("telemetry").unwrap();
+        assert_eq!(reopened.allocate("ota").unwrap(), telemetry);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+//! Two-phase commit for settings that must change on every vehicle or on
+//! none, such as a geofence update.
+//!
+//! A [`CommitCoordinator`] multicasts a `Prepare` carrying the change. Each
+//! participant checks and stages it and votes; once every vote is yes, the
+//! coordinator multicasts `Commit` and the participants apply the staged
+//! change. A no vote, or a participant that doesn't vote before the deadline,
+//! makes the coordinator broadcast `Abort` instead, and the participants drop
+//! what they staged. Like [`rollout`](crate::coordination::rollout), neither side does I/O:
+//! poll the coordinator, handle messages on both sides and multicast what they
+//! return with [`send`].
+//!
+//! `Commit` is repeated until every participant confirms it applied the change,
+//! since a lost decision would leave a participant holding a staged change. A
+//! participant that hears no decision at all discards its staged change once
+//! the deadline and a grace period have passed ([`CommitParticipant::expire`]).
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::transport::MulticastSender;
+
+/// Control commands carrying a commit message start with this, followed by the message as JSON
+pub const COMMIT_COMMAND_PREFIX: &str = "COMMIT ";
+
+/// How often an unconfirmed `Commit` is repeated
+pub const COMMIT_RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many times `Commit` is repeated before the coordinator gives up on confirmations
+pub const MAX_COMMIT_RESENDS: u32 = 10;
+
+/// The messages of a two-phase commit, all multicast as Control commands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum CommitMessage {
+    /// Stage `change` and vote, within `deadline_ms`
+    Prepare { txn_id: u64, participants: Vec<u32>, change: String, deadline_ms: u64 },
+    /// A participant staged the change, or refused it with `error`
+    Vote { txn_id: u64, node: u32, error: Option<String> },
+    /// Apply the staged change
+    Commit { txn_id: u64 },
+    /// Drop the staged change
+    Abort { txn_id: u64, reason: String },
+    /// A participant applied the committed change
+    Applied { txn_id: u64, node: u32 },
+}
+
+impl CommitMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", COMMIT_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`CommitMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(COMMIT_COMMAND_PREFIX)?).ok()
+    }
+
+    pub fn txn_id(&self) -> u64 {
+        match self {
+            CommitMessage::Prepare { txn_id, .. }
+            | CommitMessage::Vote { txn_id, .. }
+            | CommitMessage::Commit { txn_id }
+            | CommitMessage::Abort { txn_id, .. }
+            | CommitMessage::Applied { txn_id, .. } => *txn_id,
+        }
+    }
+}
+
+/// What the coordinator should do next
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommitStep {
+    /// Multicast this message, then poll again
+    Send(CommitMessage),
+    /// Nothing to do until this time, or until a vote or confirmation arrives
+    Wait(Instant),
+    Done(CommitOutcome),
+}
+
+/// How a transaction ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum CommitOutcome {
+    /// Every participant voted yes; `unconfirmed` ones never said they applied it
+    Committed { unconfirmed: Vec<u32> },
+    Aborted { reason: String },
+}
+
+enum Phase {
+    /// `Prepare` not sent yet
+    Start,
+    Voting { yes: BTreeSet<u32> },
+    Committing { applied: BTreeSet<u32>, next_send: Instant, sends: u32 },
+    Aborting { reason: String },
+    Done(CommitOutcome),
+}
+
+/// The coordinator's side of one transaction
+pub struct CommitCoordinator {
+    txn_id: u64,
+    change: String,
+    participants: BTreeSet<u32>,
+    vote_timeout: Duration,
+    deadline: Option<Instant>,
+    phase: Phase,
+}
+
+impl CommitCoordinator {
+    /// Change the settings of `participants` to `change` (its format is up to
+    /// the application), aborting unless they all vote within `vote_timeout`
+    pub fn new(txn_id: u64, change: impl Into<String>, participants: impl IntoIterator<Item = u32>, vote_timeout: Duration) -> Self {
+        Self {
+            txn_id,
+            change: change.into(),
+            participants: participants.into_iter().collect(),
+            vote_timeout,
+            deadline: None,
+            phase: Phase::Start,
+        }
+    }
+
+    pub fn txn_id(&self) -> u64 {
+        self.txn_id
+    }
+
+    /// Record a vote or confirmation from `sender_id`; anything else is ignored
+    pub fn handle(&mut self, sender_id: u32, message: &CommitMessage, now: Instant) {
+        if message.txn_id() != self.txn_id || !self.participants.contains(&sender_id) {
+            return;
+        }
+        match (&mut self.phase, message) {
+            (Phase::Voting { yes }, CommitMessage::Vote { node, error, .. }) if *node == sender_id => match error {
+                None => {
+                    yes.insert(sender_id);
+                    if yes.len() == self.participants.len() {
+                        self.phase = Phase::Committing { applied: BTreeSet::new(), next_send: now, sends: 0 };
+                    }
+                }
+                Some(error) => self.phase = Phase::Aborting { reason: format!("node {} refused: {}", sender_id, error) },
+            },
+            (Phase::Committing { applied, .. }, CommitMessage::Applied { node, .. }) if *node == sender_id => {
+                applied.insert(sender_id);
+                if applied.len() == self.participants.len() {
+                    self.phase = Phase::Done(CommitOutcome::Committed { unconfirmed: Vec::new() });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// What to do next
+    pub fn poll(&mut self, now: Instant) -> CommitStep {
+        match &mut self.phase {
+            Phase::Start => {
+                self.deadline = Some(now + self.vote_timeout);
+                self.phase = Phase::Voting { yes: BTreeSet::new() };
+                CommitStep::Send(CommitMessage::Prepare {
+                    txn_id: self.txn_id,
+                    participants: self.participants.iter().copied().collect(),
+                    change: self.change.clone(),
+                    deadline_ms: self.vote_timeout.as_millis() as u64,
+                })
+            }
+            Phase::Voting { yes } => {
+                let deadline = self.deadline.unwrap_or(now);
+                if now < deadline {
+                    return CommitStep::Wait(deadline);
+                }
+                let silent: Vec<String> = self.participants.difference(yes).map(u32::to_string).collect();
+                self.phase = Phase::Aborting { reason: format!("no vote from {}", silent.join(", ")) };
+                self.poll(now)
+            }
+            Phase::Committing { applied, next_send, sends } => {
+                if now < *next_send {
+                    return CommitStep::Wait(*next_send);
+                }
+                if *sends > MAX_COMMIT_RESENDS {
+                    let unconfirmed = self.participants.difference(applied).copied().collect();
+                    self.phase = Phase::Done(CommitOutcome::Committed { unconfirmed });
+                    return self.poll(now);
+                }
+                *sends += 1;
+                *next_send = now + COMMIT_RESEND_INTERVAL;
+                CommitStep::Send(CommitMessage::Commit { txn_id: self.txn_id })
+            }
+            Phase::Aborting { reason } => {
+                let reason = std::mem::take(reason);
+                self.phase = Phase::Done(CommitOutcome::Aborted { reason: reason.clone() });
+                CommitStep::Send(CommitMessage::Abort { txn_id: self.txn_id, reason })
+            }
+            Phase::Done(outcome) => CommitStep::Done(outcome.clone()),
+        }
+    }
+}
+
+/// What a participant should do about a message from the coordinator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParticipantEvent {
+    /// Check and stage `change`, then reply with [`CommitParticipant::vote`]
+    Prepare { txn_id: u64, change: String },
+    /// Apply the change staged for `txn_id`, then multicast `reply`
+    Apply { txn_id: u64, change: String, reply: CommitMessage },
+    /// Drop the change staged for `txn_id`
+    Discard { txn_id: u64 },
+}
+
+struct Staged {
+    change: String,
+    voted: bool,
+    expires: Instant,
+}
+
+/// A participant's side of the transactions it takes part in
+pub struct CommitParticipant {
+    node: u32,
+    grace: Duration,
+    staged: BTreeMap<u64, Staged>,
+    /// Transactions already applied, so a repeated `Commit` is only confirmed again
+    applied: BTreeSet<u64>,
+}
+
+impl CommitParticipant {
+    pub fn new(node: u32) -> Self {
+        Self { node, grace: Duration::from_secs(30), staged: BTreeMap::new(), applied: BTreeSet::new() }
+    }
+
+    /// How long past the vote deadline a staged change waits for a decision
+    /// before [`expire`](Self::expire) drops it (30s by default)
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    /// Handle a message from the coordinator. A repeated `Commit` for an
+    /// already applied transaction returns just the confirmation to multicast.
+    pub fn handle(&mut self, message: &CommitMessage, now: Instant) -> (Option<ParticipantEvent>, Option<CommitMessage>) {
+        let txn_id = message.txn_id();
+        let applied = CommitMessage::Applied { txn_id, node: self.node };
+        match message {
+            CommitMessage::Prepare { participants, change, deadline_ms, .. } if participants.contains(&self.node) => {
+                if self.staged.contains_key(&txn_id) || self.applied.contains(&txn_id) {
+                    return (None, None);
+                }
+                let expires = now + Duration::from_millis(*deadline_ms) + self.grace;
+                self.staged.insert(txn_id, Staged { change: change.clone(), voted: false, expires });
+                (Some(ParticipantEvent::Prepare { txn_id, change: change.clone() }), None)
+            }
+            CommitMessage::Commit { .. } => match self.staged.remove(&txn_id) {
+                Some(staged) if staged.voted => {
+                    self.applied.insert(txn_id);
+                    (Some(ParticipantEvent::Apply { txn_id, change: staged.change, reply: applied }), None)
+                }
+                Some(staged) => {
+                    self.staged.insert(txn_id, staged);
+                    (None, None)
+                }
+                None => (None, self.applied.contains(&txn_id).then_some(applied)),
+            },
+            CommitMessage::Abort { .. } => (self.staged.remove(&txn_id).map(|_| ParticipantEvent::Discard { txn_id }), None),
+            _ => (None, None),
+        }
+    }
+
+    /// The vote on a prepared transaction: `Ok` once the change is staged, or
+    /// why it can't be. A refused change is discarded straight away.
+    pub fn vote(&mut self, txn_id: u64, result: Result<(), String>) -> CommitMessage {
+        match &result {
+            Ok(()) => {
+                if let Some(staged) = self.staged.get_mut(&txn_id) {
+                    staged.voted = true;
+                }
+            }
+            Err(_) => {
+                self.staged.remove(&txn_id);
+            }
+        }
+        CommitMessage::Vote { txn_id, node: self.node, error: result.err() }
+    }
+
+    /// Drop staged changes whose decision never arrived; returns their transaction ids
+    pub fn expire(&mut self, now: Instant) -> Vec<u64> {
+        let expired: Vec<u64> = self.staged.iter().filter(|(_, staged)| now >= staged.expires).map(|(txn_id, _)| *txn_id).collect();
+        for txn_id in &expired {
+            self.staged.remove(txn_id);
+        }
+        expired
+    }
+}
+
+/// Multicast a commit message to the fleet as a Control command
+pub async fn send(sender: &mut MulticastSender, message: &CommitMessage) -> crate::error::Result<()> {
+    sender.send_control(&message.control_command()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(step: CommitStep) -> CommitMessage {
+        match step {
+            CommitStep::Send(message) => message,
+            other => panic!("expected a message to send, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_all_yes_commits_and_applies_once() {
+        let start = Instant::now();
+        let mut coordinator = CommitCoordinator::new(9, r#"{"geofence":"v9"}"#, [1, 2], Duration::from_secs(5));
+        let mut participants = [CommitParticipant::new(1), CommitParticipant::new(2)];
+
+        let prepare = CommitMessage::from_control(&sent(coordinator.poll(start)).control_command()).unwrap();
+        for participant in &mut participants {
+            let (event, _) = participant.handle(&prepare, start);
+            assert_eq!(event, Some(ParticipantEvent::Prepare { txn_id: 9, change: r#"{"geofence":"v9"}"#.into() }));
+            let vote = participant.vote(9, Ok(()));
+            coordinator.handle(participant.node, &vote, start);
+        }
+
+        let commit = sent(coordinator.poll(start));
+        assert_eq!(commit, CommitMessage::Commit { txn_id: 9 });
+        let (event, _) = participants[0].handle(&commit, start);
+        let Some(ParticipantEvent::Apply { reply, .. }) = event else { panic!("not applied") };
+        coordinator.handle(1, &reply, start);
+        // Node 2 missed the first Commit; it is repeated, and a repeat only gets a confirmation
+        assert_eq!(coordinator.poll(start), CommitStep::Wait(start + COMMIT_RESEND_INTERVAL));
+        let again = sent(coordinator.poll(start + COMMIT_RESEND_INTERVAL));
+        assert!(matches!(participants[1].handle(&again, start).0, Some(ParticipantEvent::Apply { .. })));
+        assert_eq!(participants[0].handle(&again, start), (None, Some(reply)));
+        coordinator.handle(2, &CommitMessage::Applied { txn_id: 9, node: 2 }, start);
+        assert_eq!(coordinator.poll(start), CommitStep::Done(CommitOutcome::Committed { unconfirmed: vec![] }));
+    }
+
+    #[test]
+    fn test_refusal_or_silence_aborts() {
+        let start = Instant::now();
+        let mut coordinator = CommitCoordinator::new(3, "speed_limit=4", [1, 2, 3], Duration::from_secs(5));
+        let mut participant = CommitParticipant::new(1);
+        let prepare = sent(coordinator.poll(start));
+        participant.handle(&prepare, start);
+        coordinator.handle(1, &participant.vote(3, Ok(())), start);
+        coordinator.handle(2, &CommitMessage::Vote { txn_id: 3, node: 2, error: Some("out of range".into()) }, start);
+        let abort = sent(coordinator.poll(start));
+        assert_eq!(abort, CommitMessage::Abort { txn_id: 3, reason: "node 2 refused: out of range".into() });
+        assert_eq!(participant.handle(&abort, start).0, Some(ParticipantEvent::Discard { txn_id: 3 }));
+
+        let mut coordinator = CommitCoordinator::new(4, "speed_limit=4", [1, 2], Duration::from_secs(5));
+        sent(coordinator.poll(start));
+        coordinator.handle(1, &CommitMessage::Vote { txn_id: 4, node: 1, error: None }, start);
+        let abort = sent(coordinator.poll(start + Duration::from_secs(5)));
+        assert_eq!(abort, CommitMessage::Abort { txn_id: 4, reason: "no vote from 2".into() });
+
+        // A participant that never hears the decision drops its staged change after the grace period
+        let mut participant = CommitParticipant::new(2).with_grace(Duration::from_secs(1));
+        participant.handle(&CommitMessage::Prepare { txn_id: 5, participants: vec![2], change: "x".into(), deadline_ms: 5000 }, start);
+        assert!(participant.expire(start + Duration::from_secs(5)).is_empty());
+        assert_eq!(participant.expire(start + Duration::from_secs(6)), vec![5]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Compaction of latest-only topics, e.g. positions: after a stall, only the
+//! newest value of each topic matters, so older ones shouldn't be replayed.
+//!
+//! On the sending side, a [`LastValueOutbox`] holds at most one unsent value
+//! per topic; putting a newer one replaces it. Whatever drains the outbox
+//! sends each value with [`MulticastSender::send_latest`], which marks it with
+//! the [`LAST_VALUE`](crate::extensions::LAST_VALUE) extension naming its topic.
+//! On the receiving side, a [`Conflator`] goes through a backlog (e.g. what
+//! queued in a [`MulticastReceiver`](crate::transport::MulticastReceiver)
+//! while the consumer was busy) and keeps, per sender and topic, only the
+//! newest value by send time; other messages pass through untouched.
+
+use async_std::channel::{self, Receiver, Sender};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{self, TransportError};
+use crate::receiver::Delivery;
+use crate::transport::MulticastSender;
+
+#[derive(Debug)]
+struct Outbox {
+    /// Oldest first; a replaced value keeps its topic's place
+    pending: Mutex<Vec<(String, Vec<u8>)>>,
+    replaced: AtomicU64,
+    wake: Sender<()>,
+    woken: Receiver<()>,
+}
+
+/// Unsent values of latest-only topics, at most one per topic. Clones share
+/// the outbox, so one task can put values while another drains it.
+#[derive(Debug, Clone)]
+pub struct LastValueOutbox {
+    inner: Arc<Outbox>,
+}
+
+impl Default for LastValueOutbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LastValueOutbox {
+    pub fn new() -> Self {
+        let (wake, woken) = channel::bounded(1);
+        Self { inner: Arc::new(Outbox { pending: Mutex::new(Vec::new()), replaced: AtomicU64::new(0), wake, woken }) }
+    }
+
+    /// Queue `payload` as the newest value of `topic`, replacing any unsent one
+    pub fn put(&self, topic: &str, payload: &[u8]) {
+        let mut pending = self.inner.pending.lock().unwrap();
+        match pending.iter_mut().find(|(queued, _)| queued == topic) {
+            Some((_, value)) => {
+                *value = payload.to_vec();
+                self.inner.replaced.fetch_add(1, Ordering::Relaxed);
+            }
+            None => pending.push((topic.to_string(), payload.to_vec())),
+        }
+        let _ = self.inner.wake.try_send(());
+    }
+
+    /// Topics with a value waiting
+    pub fn pending(&self) -> usize {
+        self.inner.pending.lock().unwrap().len()
+    }
+
+    /// Values dropped because a newer one replaced them before they were sent
+    pub fn replaced(&self) -> u64 {
+        self.inner.replaced.load(Ordering::Relaxed)
+    }
+
+    /// Take the oldest waiting value
+    pub fn take(&self) -> Option<(String, Vec<u8>)> {
+        let mut pending = self.inner.pending.lock().unwrap();
+        (!pending.is_empty()).then(|| pending.remove(0))
+    }
+
+    /// Send every waiting value, one at a time, so values put while a send is
+    /// held up (by the bandwidth budget, a TDMA slot or the shaping calendar)
+    /// still replace the ones behind it. Returns how many were sent. A value
+    /// whose send fails goes back in the outbox unless a newer one was put.
+    pub async fn flush(&self, sender: &mut MulticastSender) -> error::Result<usize> {
+        let mut sent = 0;
+        while let Some((topic, payload)) = self.take() {
+            if let Err(e) = sender.send_latest(&topic, &payload).await {
+                let mut pending = self.inner.pending.lock().unwrap();
+                if !pending.iter().any(|(queued, _)| *queued == topic) {
+                    pending.insert(0, (topic, payload));
+                }
+                return Err(e);
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Send values as they are put, until a send fails. While the shaping
+    /// calendar holds telemetry, the values wait (and keep being replaced)
+    /// until it lets them through.
+    pub async fn run(&self, sender: &mut MulticastSender) -> error::Result<()> {
+        loop {
+            match self.flush(sender).await {
+                Ok(_) => {
+                    let _ = self.inner.woken.recv().await;
+                }
+                Err(TransportError::Held { delay, .. }) => async_std::task::sleep(delay).await,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Drops superseded values of latest-only topics from received backlogs; see
+/// the [module docs](self)
+#[derive(Debug, Default)]
+pub struct Conflator {
+    /// Send time of the newest value passed on, by sender and topic
+    newest: HashMap<(u32, String), u64>,
+    dropped: u64,
+}
+
+impl Conflator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `backlog` in order, less every latest-only value that a newer one of the
+    /// same sender and topic supersedes, whether later in the backlog or
+    /// already passed on before
+    pub fn conflate(&mut self, backlog: impl IntoIterator<Item = Delivery>) -> Vec<Delivery> {
+        let backlog: Vec<Delivery> = backlog.into_iter().collect();
+        let mut newest_in_backlog: HashMap<(u32, &str), (u64, usize)> = HashMap::new();
+        for (index, delivery) in backlog.iter().enumerate() {
+            if let Some(topic) = delivery.extensions.last_value() {
+                let sent = delivery.header.timestamp_micros();
+                let newest = newest_in_backlog.entry((delivery.header.sender_id.get(), topic)).or_insert((sent, index));
+                if sent >= newest.0 {
+                    *newest = (sent, index);
+                }
+            }
+        }
+        let keep: Vec<usize> = newest_in_backlog.into_iter()
+            .filter(|((sender_id, topic), (sent, _))| {
+                self.newest.get(&(*sender_id, topic.to_string())).is_none_or(|passed| sent >= passed)
+            })
+            .map(|(_, (_, index))| index)
+            .collect();
+
+        let before = backlog.len();
+        let kept: Vec<Delivery> = backlog.into_iter()
+            .enumerate()
+            .filter(|(index, delivery)| delivery.extensions.last_value().is_none() || keep.contains(index))
+            .map(|(_, delivery)| delivery)
+            .collect();
+        for delivery in &kept {
+            if let Some(topic) = delivery.extensions.last_value() {
+                self.newest.insert((delivery.header.sender_id.get(), topic.to_string()), delivery.header.timestamp_micros());
+            }
+        }
+        self.dropped += (before - kept.len()) as u64;
+        kept
+    }
+
+    /// Superseded values dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::Extensions;
+    use crate::receiver::ReceiverConfig;
+    use crate::transport::{FleetMsgHeader, MessageType, MulticastReceiver};
+    use std::time::Duration;
+
+    fn delivery(sender_id: u32, topic: Option<&str>, sent_us: u64, payload: &[u8]) -> Delivery {
+        let mut header = FleetMsgHeader::new(MessageType::Data, sender_id, 0, payload.len() as u16);
+        header.timestamp = sent_us.into();
+        let mut extensions = Extensions::new();
+        if let Some(topic) = topic {
+            extensions.set_last_value(topic).unwrap();
+        }
+        Delivery { header, extensions, payload: payload.to_vec(), addr: "10.0.0.1:5000".parse().unwrap(), issues: Vec::new() }
+    }
+
+    #[test]
+    fn test_conflation_keeps_newest_per_sender_and_topic() {
+        let mut conflator = Conflator::new();
+        let backlog = vec![
+            delivery(1, Some("pos"), 100, b"a"),
+            delivery(1, None, 110, b"log"),
+            delivery(2, Some("pos"), 120, b"b"),
+            // Reordered: sent before the first, arrived after it
+            delivery(1, Some("pos"), 90, b"stale"),
+            delivery(1, Some("pos"), 130, b"c"),
+        ];
+        let kept = conflator.conflate(backlog);
+        assert_eq!(kept.iter().map(|delivery| delivery.payload.as_slice()).collect::<Vec<_>>(), [&b"log"[..], b"b", b"c"]);
+        // An update older than one already passed on is dropped on its own too
+        assert!(conflator.conflate([delivery(1, Some("pos"), 125, b"late")]).is_empty());
+        assert_eq!(conflator.dropped(), 3);
+    }
+
+    #[async_std::test]
+    async fn test_outbox_sends_only_the_newest_values() {
+        let (channel, socket) = crate::testing::bind_free_channel().await.unwrap();
+        let receiver = MulticastReceiver::from_socket(socket, ReceiverConfig::new(), 16);
+        let mut sender = MulticastSender::new(channel.group, channel.port, 4).await.unwrap();
+
+        let outbox = LastValueOutbox::new();
+        for position in ["1,1", "1,2", "1,3"] {
+            outbox.put("pos", position.as_bytes());
+        }
+        outbox.put("battery", b"87%");
+        assert_eq!((outbox.pending(), outbox.replaced()), (2, 2));
+        assert_eq!(outbox.flush(&mut sender).await.unwrap(), 2);
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let delivery = async_std::future::timeout(Duration::from_secs(2), receiver.recv()).await.unwrap().unwrap();
+            received.push((delivery.extensions.last_value().unwrap().to_string(), delivery.payload));
+        }
+        assert_eq!(received, [("pos".to_string(), b"1,3".to_vec()), ("battery".to_string(), b"87%".to_vec())]);
+    }
+}
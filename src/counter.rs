@@ -0,0 +1,258 @@
+//! Fleet-wide counters handing out unique ranges, e.g. of mission ids, so
+//! nodes don't each invent their own collision-prone scheme.
+//!
+//! One node, the fleet's leader, runs the [`CounterService`]: it owns the next
+//! value of every named counter and saves them to a file or a
+//! [`StateStore`] before answering, so a restart never hands out a range twice. Other nodes ask for a range with
+//! [`CounterClient::allocate`], which repeats the request until it is granted.
+//! The service answers a repeated request with the range it already granted,
+//! so a lost grant costs nothing.
+
+use async_std::channel::Receiver;
+use async_std::future::timeout;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::store::StateStore;
+use crate::transport::MulticastSender;
+
+/// Control commands carrying a counter message start with this, followed by the message as JSON
+pub const COUNTER_COMMAND_PREFIX: &str = "COUNTER ";
+
+/// How often an unanswered request is repeated
+pub const REQUEST_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Grants remembered for answering repeated requests
+const RECENT_GRANTS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum CounterMessage {
+    /// `node` asks for `count` values of `counter`
+    Request { request_id: u64, node: u32, counter: String, count: u64 },
+    /// Values `start..start + count` of `counter` are `node`'s alone
+    Grant { request_id: u64, node: u32, counter: String, start: u64, count: u64 },
+    Refused { request_id: u64, node: u32, reason: String },
+}
+
+impl CounterMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", COUNTER_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`CounterMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(COUNTER_COMMAND_PREFIX)?).ok()
+    }
+}
+
+/// The allocator, run by the leader
+#[derive(Debug, Default)]
+pub struct CounterService {
+    path: Option<PathBuf>,
+    store: Option<Arc<dyn StateStore>>,
+    /// Next value of each counter
+    counters: BTreeMap<String, u64>,
+    recent: BTreeMap<(u32, u64), CounterMessage>,
+    recent_order: VecDeque<(u32, u64)>,
+}
+
+impl CounterService {
+    /// A service that forgets its counters when dropped, for tests and simulations
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// A service keeping its counters in the JSON file at `path`, created on first grant
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let counters = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path: Some(path), counters, ..Default::default() })
+    }
+
+    /// A service keeping its counters under the `counters` key of `store`
+    pub fn with_store(store: Arc<dyn StateStore>) -> std::io::Result<Self> {
+        let counters = match store.get("counters")? {
+            Some(json) => serde_json::from_slice(&json).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self { store: Some(store), counters, ..Default::default() })
+    }
+
+    /// The value the next grant of `counter` starts at
+    pub fn next(&self, counter: &str) -> u64 {
+        self.counters.get(counter).copied().unwrap_or(0)
+    }
+
+    /// Answer a request from `sender_id`; None for anything else. The new
+    /// counter value is saved before the grant is returned.
+    pub fn handle(&mut self, sender_id: u32, message: &CounterMessage) -> std::io::Result<Option<CounterMessage>> {
+        let CounterMessage::Request { request_id, node, counter, count } = message else { return Ok(None) };
+        if *node != sender_id {
+            return Ok(None);
+        }
+        if let Some(grant) = self.recent.get(&(*node, *request_id)) {
+            return Ok(Some(grant.clone()));
+        }
+        let start = self.next(counter);
+        let Some(end) = start.checked_add(*count).filter(|_| *count > 0) else {
+            let reason = if *count == 0 { "empty range requested" } else { "counter exhausted" };
+            return Ok(Some(CounterMessage::Refused { request_id: *request_id, node: *node, reason: reason.to_string() }));
+        };
+        self.counters.insert(counter.clone(), end);
+        if let Err(e) = self.save() {
+            self.counters.insert(counter.clone(), start);
+            return Err(e);
+        }
+
+        let grant = CounterMessage::Grant { request_id: *request_id, node: *node, counter: counter.clone(), start, count: *count };
+        if self.recent_order.len() == RECENT_GRANTS
+            && let Some(oldest) = self.recent_order.pop_front()
+        {
+            self.recent.remove(&oldest);
+        }
+        self.recent_order.push_back((*node, *request_id));
+        self.recent.insert((*node, *request_id), grant.clone());
+        Ok(Some(grant))
+    }
+
+    /// Write the counters to the store, or to a temporary file renamed over the
+    /// old one, so a crash leaves either the old or the new values
+    fn save(&self) -> std::io::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.counters).map_err(Error::other)?;
+        if let Some(store) = &self.store {
+            return store.put("counters", &json);
+        }
+        let Some(path) = &self.path else { return Ok(()) };
+        let temp = path.with_extension("tmp");
+        let file = std::fs::File::create(&temp)?;
+        std::io::Write::write_all(&mut &file, &json)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, path)
+    }
+}
+
+/// A node asking the leader for ranges
+#[derive(Debug, Clone)]
+pub struct CounterClient {
+    node: u32,
+    next_request: u64,
+}
+
+impl CounterClient {
+    /// Request ids start from the clock, so ids from before a restart aren't reused
+    pub fn new(node: u32) -> Self {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        Self { node, next_request: now.as_micros() as u64 }
+    }
+
+    /// The request for `count` values of `counter`, with a fresh request id
+    pub fn request(&mut self, counter: &str, count: u64) -> CounterMessage {
+        self.next_request += 1;
+        CounterMessage::Request { request_id: self.next_request, node: self.node, counter: counter.to_string(), count }
+    }
+
+    /// Ask for `count` values of `counter`, repeating the request until the
+    /// answer arrives on `messages`. Fails with `TimedOut` after `limit`, or
+    /// `PermissionDenied` if the leader refuses.
+    pub async fn allocate(
+        &mut self,
+        sender: &mut MulticastSender,
+        messages: &Receiver<CounterMessage>,
+        counter: &str,
+        count: u64,
+        limit: Duration,
+    ) -> std::io::Result<Range<u64>> {
+        let request = self.request(counter, count);
+        let request_id = self.next_request;
+        let deadline = Instant::now() + limit;
+        loop {
+            sender.send_control(&request.control_command()).await?;
+            let retry = (Instant::now() + REQUEST_RETRY_INTERVAL).min(deadline);
+            while let Some(wait) = retry.checked_duration_since(Instant::now()).filter(|wait| !wait.is_zero()) {
+                match timeout(wait, messages.recv()).await {
+                    Ok(Ok(CounterMessage::Grant { request_id: id, node, start, count, .. })) if id == request_id && node == self.node => {
+                        return Ok(start..start + count);
+                    }
+                    Ok(Ok(CounterMessage::Refused { request_id: id, node, reason })) if id == request_id && node == self.node => {
+                        return Err(Error::new(ErrorKind::PermissionDenied, format!("counter {}: {}", counter, reason)));
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(_)) => return Err(Error::new(ErrorKind::BrokenPipe, "counter message channel closed")),
+                    Err(_) => break,
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, format!("counter {}: no answer from the leader", counter)));
+            }
+        }
+    }
+}
+
+/// Multicast a counter message to the fleet as a Control command
+pub async fn send(sender: &mut MulticastSender, message: &CounterMessage) -> crate::error::Result<()> {
+    sender.send_control(&message.control_command()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grants_are_unique_and_survive_restart() {
+        let path = std::env::temp_dir().join(format!("fleetlink-counters-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut service = CounterService::open(&path).unwrap();
+        let (mut a, mut b) = (CounterClient::new(1), CounterClient::new(2));
+
+        let first = a.request("mission", 10);
+        let grant = service.handle(1, &first).unwrap().unwrap();
+        assert!(matches!(grant, CounterMessage::Grant { start: 0, count: 10, .. }));
+        // A repeated request gets the same range; one forged for another node is ignored
+        assert_eq!(service.handle(1, &first).unwrap(), Some(grant));
+        assert_eq!(service.handle(2, &first).unwrap(), None);
+        let second = service.handle(2, &b.request("mission", 5)).unwrap().unwrap();
+        assert!(matches!(second, CounterMessage::Grant { start: 10, count: 5, .. }));
+        assert!(matches!(service.handle(2, &b.request("mission", 0)).unwrap(), Some(CounterMessage::Refused { .. })));
+
+        let restarted = CounterService::open(&path).unwrap();
+        assert_eq!((restarted.next("mission"), restarted.next("route")), (15, 0));
+        std::fs::remove_file(&path).unwrap();
+
+        let store: Arc<dyn StateStore> = Arc::new(crate::store::MemoryStore::new());
+        CounterService::with_store(store.clone()).unwrap().handle(1, &a.request("route", 4)).unwrap();
+        assert_eq!(CounterService::with_store(store).unwrap().next("route"), 4);
+    }
+
+    #[async_std::test]
+    async fn test_allocate_retries_until_granted() {
+        use crate::testing::TestReceiver;
+
+        let receiver = TestReceiver::start().await.unwrap();
+        let mut sender = receiver.sender(7).await.unwrap();
+        let (tx, rx) = async_std::channel::unbounded();
+        let mut client = CounterClient::new(7);
+
+        // The leader answers the second copy of the request only, as if the first were lost
+        let answered = async_std::task::spawn(async move {
+            let mut service = CounterService::in_memory();
+            let received = receiver.wait_for(2, Duration::from_secs(5)).await;
+            let command = String::from_utf8_lossy(&received[1].1).into_owned();
+            let request = CounterMessage::from_control(&command).unwrap();
+            tx.send(service.handle(7, &request).unwrap().unwrap()).await.unwrap();
+        });
+        let range = client.allocate(&mut sender, &rx, "mission", 3, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(range, 0..3);
+        answered.await;
+    }
+}
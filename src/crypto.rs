@@ -0,0 +1,284 @@
+//! The cryptographic primitives the transport uses, behind a provider trait.
+//!
+//! Payload encryption ([`FeatureCodec`](crate::features::FeatureCodec)), the
+//! peer [`handshake`](crate::handshake) and [`session`](crate::session)
+//! ciphers all go through [`provider()`]. By default that is [`RustCrypto`]
+//! (pure Rust). Build with `--features crypto-ring` for `ring`, or
+//! `--features crypto-openssl` for the system OpenSSL, e.g. a FIPS-validated
+//! build. With both, OpenSSL wins. A provider can also be installed at
+//! startup with [`install_provider`].
+//!
+//! Every provider implements the same algorithms (ChaCha20-Poly1305,
+//! HMAC-SHA256, HKDF-SHA256), so nodes with different providers interoperate.
+//! Keyrings sealed with `age` are opened with age's own crypto.
+
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, OnceLock};
+
+pub const AEAD_KEY_LEN: usize = 32;
+pub const AEAD_NONCE_LEN: usize = 12;
+pub const AEAD_TAG_LEN: usize = 16;
+pub const HMAC_LEN: usize = 32;
+
+/// A source of the primitives the transport needs
+pub trait CryptoProvider: Send + Sync + std::fmt::Debug {
+    fn name(&self) -> &'static str;
+
+    /// ChaCha20-Poly1305: the ciphertext with its tag appended
+    fn seal(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], plaintext: &[u8]) -> std::io::Result<Vec<u8>>;
+
+    /// Undo [`CryptoProvider::seal`]; fails with `InvalidData` if the tag doesn't match
+    fn open(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], sealed: &[u8]) -> std::io::Result<Vec<u8>>;
+
+    /// HMAC-SHA256 over the concatenation of `parts`
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; HMAC_LEN];
+
+    /// HKDF-SHA256 extract-and-expand into `out`
+    fn hkdf_sha256(&self, salt: Option<&[u8]>, ikm: &[u8], info: &[u8], out: &mut [u8]) -> std::io::Result<()>;
+
+    /// Fill `out` from a cryptographically secure generator
+    fn fill_random(&self, out: &mut [u8]) -> std::io::Result<()>;
+}
+
+fn auth_failed() -> Error {
+    Error::new(ErrorKind::InvalidData, "payload failed authentication")
+}
+
+/// Check an HMAC tag in constant time
+pub fn verify_hmac(expected: &[u8; HMAC_LEN], tag: &[u8]) -> bool {
+    use subtle::ConstantTimeEq;
+    expected[..].ct_eq(tag).into()
+}
+
+/// Fresh random bytes from the current provider
+pub fn random_bytes<const N: usize>() -> std::io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    provider().fill_random(&mut bytes)?;
+    Ok(bytes)
+}
+
+static PROVIDER: OnceLock<Arc<dyn CryptoProvider>> = OnceLock::new();
+
+fn default_provider() -> Arc<dyn CryptoProvider> {
+    #[cfg(feature = "crypto-openssl")]
+    return Arc::new(OpenSslProvider);
+    #[cfg(all(feature = "crypto-ring", not(feature = "crypto-openssl")))]
+    return Arc::new(RingProvider);
+    #[cfg(not(any(feature = "crypto-ring", feature = "crypto-openssl")))]
+    Arc::new(RustCrypto)
+}
+
+/// The provider in use: the one installed, or else the default for this build
+pub fn provider() -> &'static dyn CryptoProvider {
+    PROVIDER.get_or_init(default_provider).as_ref()
+}
+
+/// Use `provider` for all cryptography in this process. Fails if a provider
+/// is already in use, so call it before anything encrypts or authenticates.
+pub fn install_provider(provider: Arc<dyn CryptoProvider>) -> std::io::Result<()> {
+    let name = provider.name();
+    PROVIDER.set(provider)
+        .map_err(|_| Error::new(ErrorKind::AlreadyExists, format!("can't install {}: a crypto provider is already in use", name)))
+}
+
+/// The RustCrypto crates: pure Rust, no C toolchain needed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustCrypto;
+
+impl CryptoProvider for RustCrypto {
+    fn name(&self) -> &'static str {
+        "rustcrypto"
+    }
+
+    fn seal(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+        use chacha20poly1305::aead::Aead;
+        use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+        ChaCha20Poly1305::new(Key::from_slice(key)).encrypt(Nonce::from_slice(nonce), plaintext)
+            .map_err(|_| Error::other("payload encryption failed"))
+    }
+
+    fn open(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        use chacha20poly1305::aead::Aead;
+        use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+        ChaCha20Poly1305::new(Key::from_slice(key)).decrypt(Nonce::from_slice(nonce), sealed).map_err(|_| auth_failed())
+    }
+
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; HMAC_LEN] {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes().into()
+    }
+
+    fn hkdf_sha256(&self, salt: Option<&[u8]>, ikm: &[u8], info: &[u8], out: &mut [u8]) -> std::io::Result<()> {
+        hkdf::Hkdf::<sha2::Sha256>::new(salt, ikm).expand(info, out)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "HKDF output too long"))
+    }
+
+    fn fill_random(&self, out: &mut [u8]) -> std::io::Result<()> {
+        use rand::TryRngCore;
+        rand::rngs::OsRng.try_fill_bytes(out).map_err(Error::other)
+    }
+}
+
+/// `ring`, the primitives rustls builds on
+#[cfg(feature = "crypto-ring")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RingProvider;
+
+#[cfg(feature = "crypto-ring")]
+impl CryptoProvider for RingProvider {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn seal(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+        use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, Nonce, UnboundKey};
+        let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| Error::other("bad AEAD key"))?);
+        let mut sealed = plaintext.to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(*nonce), Aad::empty(), &mut sealed)
+            .map_err(|_| Error::other("payload encryption failed"))?;
+        Ok(sealed)
+    }
+
+    fn open(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, Nonce, UnboundKey};
+        let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| Error::other("bad AEAD key"))?);
+        let mut buffer = sealed.to_vec();
+        let plaintext_len = key.open_in_place(Nonce::assume_unique_for_key(*nonce), Aad::empty(), &mut buffer)
+            .map_err(|_| auth_failed())?
+            .len();
+        buffer.truncate(plaintext_len);
+        Ok(buffer)
+    }
+
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; HMAC_LEN] {
+        let mut context = ring::hmac::Context::with_key(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, key));
+        for part in parts {
+            context.update(part);
+        }
+        context.sign().as_ref().try_into().expect("HMAC-SHA256 tags are 32 bytes")
+    }
+
+    fn hkdf_sha256(&self, salt: Option<&[u8]>, ikm: &[u8], info: &[u8], out: &mut [u8]) -> std::io::Result<()> {
+        use ring::hkdf::{HKDF_SHA256, KeyType, Salt};
+        struct Len(usize);
+        impl KeyType for Len {
+            fn len(&self) -> usize {
+                self.0
+            }
+        }
+        let too_long = || Error::new(ErrorKind::InvalidInput, "HKDF output too long");
+        let prk = Salt::new(HKDF_SHA256, salt.unwrap_or(&[])).extract(ikm);
+        let info = [info];
+        prk.expand(&info, Len(out.len())).map_err(|_| too_long())?.fill(out).map_err(|_| too_long())
+    }
+
+    fn fill_random(&self, out: &mut [u8]) -> std::io::Result<()> {
+        use ring::rand::SecureRandom;
+        ring::rand::SystemRandom::new().fill(out).map_err(|_| Error::other("system random generator failed"))
+    }
+}
+
+/// The system OpenSSL (3.x). Point it at a FIPS provider through OpenSSL's
+/// own configuration to use a validated module.
+#[cfg(feature = "crypto-openssl")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenSslProvider;
+
+#[cfg(feature = "crypto-openssl")]
+impl CryptoProvider for OpenSslProvider {
+    fn name(&self) -> &'static str {
+        "openssl"
+    }
+
+    fn seal(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut tag = [0u8; AEAD_TAG_LEN];
+        let mut sealed = openssl::symm::encrypt_aead(openssl::symm::Cipher::chacha20_poly1305(), key, Some(nonce), &[], plaintext, &mut tag)
+            .map_err(Error::other)?;
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    fn open(&self, key: &[u8; AEAD_KEY_LEN], nonce: &[u8; AEAD_NONCE_LEN], sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let body_len = sealed.len().checked_sub(AEAD_TAG_LEN).ok_or_else(auth_failed)?;
+        let (ciphertext, tag) = sealed.split_at(body_len);
+        openssl::symm::decrypt_aead(openssl::symm::Cipher::chacha20_poly1305(), key, Some(nonce), &[], ciphertext, tag)
+            .map_err(|_| auth_failed())
+    }
+
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; HMAC_LEN] {
+        use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+        let tag = PKey::hmac(key)
+            .and_then(|key| {
+                let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+                for part in parts {
+                    signer.update(part)?;
+                }
+                signer.sign_to_vec()
+            })
+            .expect("OpenSSL HMAC-SHA256 failed");
+        tag.try_into().expect("HMAC-SHA256 tags are 32 bytes")
+    }
+
+    fn hkdf_sha256(&self, salt: Option<&[u8]>, ikm: &[u8], info: &[u8], out: &mut [u8]) -> std::io::Result<()> {
+        use openssl::{md::Md, pkey::Id, pkey_ctx::PkeyCtx};
+        let mut ctx = PkeyCtx::new_id(Id::HKDF).map_err(Error::other)?;
+        ctx.derive_init().map_err(Error::other)?;
+        ctx.set_hkdf_md(Md::sha256()).map_err(Error::other)?;
+        ctx.set_hkdf_key(ikm).map_err(Error::other)?;
+        if let Some(salt) = salt {
+            ctx.set_hkdf_salt(salt).map_err(Error::other)?;
+        }
+        ctx.add_hkdf_info(info).map_err(Error::other)?;
+        ctx.derive(Some(out)).map_err(Error::other)?;
+        Ok(())
+    }
+
+    fn fill_random(&self, out: &mut [u8]) -> std::io::Result<()> {
+        openssl::rand::rand_bytes(out).map_err(Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 5869 test case 1
+    fn check_hkdf(provider: &dyn CryptoProvider) {
+        let ikm = [0x0b; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let mut okm = [0u8; 42];
+        provider.hkdf_sha256(Some(&salt), &ikm, &info, &mut okm).unwrap();
+        assert_eq!(crate::journal::to_hex(&okm[..8]), "3cb25f25faacd57a", "{}", provider.name());
+    }
+
+    #[test]
+    fn test_providers_agree() {
+        #[allow(unused_mut)] // Only pushed to with the optional providers
+        let mut providers: Vec<Box<dyn CryptoProvider>> = vec![Box::new(RustCrypto)];
+        #[cfg(feature = "crypto-ring")]
+        providers.push(Box::new(RingProvider));
+        #[cfg(feature = "crypto-openssl")]
+        providers.push(Box::new(OpenSslProvider));
+
+        let (key, nonce) = ([3u8; AEAD_KEY_LEN], [4u8; AEAD_NONCE_LEN]);
+        let reference = RustCrypto.seal(&key, &nonce, b"telemetry").unwrap();
+        let mac = RustCrypto.hmac_sha256(b"key", &[b"fleet", b"link"]);
+        for provider in &providers {
+            let provider = provider.as_ref();
+            assert_eq!(provider.seal(&key, &nonce, b"telemetry").unwrap(), reference, "{}", provider.name());
+            assert_eq!(provider.open(&key, &nonce, &reference).unwrap(), b"telemetry");
+            let mut tampered = reference.clone();
+            tampered[0] ^= 1;
+            assert_eq!(provider.open(&key, &nonce, &tampered).unwrap_err().kind(), ErrorKind::InvalidData);
+            assert_eq!(provider.hmac_sha256(b"key", &[b"fleetlink"]), mac);
+            check_hkdf(provider);
+        }
+        assert!(verify_hmac(&mac, &mac) && !verify_hmac(&mac, &mac[..31]));
+        assert_ne!(random_bytes::<16>().unwrap(), [0; 16]);
+    }
+}
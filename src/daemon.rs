@@ -0,0 +1,655 @@
+//! The transport as a standalone service (`fleetlinkd`): joins the group,
+//! heartbeats, journals what it hears and answers admin requests, all set up
+//! from a TOML file, so small deployments need no Rust of their own.
+//!
+//! ```toml
+//! sender_id = 0x0100
+//! group = "239.1.1.1"
+//! port = 12345
+//! heartbeat_secs = 1.0
+//! peer_timeout_secs = 3.0             # peers quiet this long are shown offline
+//! journal = "/var/log/fleetlink/traffic.jsonl"
+//!
+//! [control]
+//! socket = "/run/fleetlinkd.sock"   # line-delimited JSON admin requests
+//! http = "127.0.0.1:7071"           # --features http-admin, needs token
+//! token = "change-me"
+//! grpc = "127.0.0.1:7070"           # --features grpc, needs token too
+//! capture_dir = "/var/lib/fleetlink/captures"  # where admin-started captures go
+//!
+//! [bridge]                          # --features bridge
+//! broker = "nats"
+//! url = "10.0.0.5:4222"
+//!
+//! [[bridge.transforms]]             # coarse positions for the cloud
+//! topic = "Data"
+//! action = "round"
+//! fields = ["lat", "lon"]
+//! decimals = 2
+//! ```
+//!
+//! The daemon runs until SIGTERM or SIGINT, then flushes the journal and says
+//! goodbye to the fleet; it stops with an error if any of its services fail,
+//! so a supervisor can restart it. Under systemd (`Type=notify`) it reports
+//! ready once it has joined the group, keeps the peer count in its status,
+//! and feeds the unit's watchdog (`WatchdogSec=`) for as long as its
+//! heartbeats keep coming back to it over multicast loopback.
+//!
+//! On Windows, `fleetlinkd --service` runs it under the service control
+//! manager, which stops it like SIGTERM does elsewhere. With `etw = true` it
+//! writes its events (start and stop, peers joining and leaving, failed
+//! heartbeats) to ETW under [`winservice::PROVIDER_GUID`](crate::winservice::PROVIDER_GUID).
+
+use async_std::future::timeout;
+use async_std::task::{self, JoinHandle};
+use futures::future::{Either, select, select_all};
+use serde::{Deserialize, Serialize};
+#[cfg(windows)]
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::admin::{AdminCommand, AdminState};
+use crate::journal::JournalWriter;
+use crate::peers::PeerTable;
+use crate::receiver::{Delivery, ReceiverConfig};
+use crate::scheduling::ThreadScheduling;
+use crate::stats::TransportStats;
+#[cfg(unix)]
+use crate::systemd::Notifier;
+use crate::transform::TransformRule;
+use crate::transport::{self, FleetMsgHeader, HeaderChecksum, MulticastSender};
+#[cfg(windows)]
+use crate::winservice::{self, EventLevel, EventProvider};
+
+/// Where `fleetlinkd` looks for its configuration unless told otherwise
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/fleetlink/fleetlinkd.toml";
+
+/// Name `fleetlinkd --service` runs under on Windows
+pub const SERVICE_NAME: &str = "fleetlinkd";
+
+/// How often journals are flushed when the daemon isn't heartbeating
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub sender_id: u32,
+    #[serde(default = "DaemonConfig::default_group")]
+    pub group: Ipv4Addr,
+    #[serde(default = "DaemonConfig::default_port")]
+    pub port: u16,
+    /// Seconds between heartbeats; 0 keeps the daemon silent
+    #[serde(default = "DaemonConfig::default_heartbeat_secs")]
+    pub heartbeat_secs: f64,
+    /// Seconds a peer may stay quiet before it is marked offline; 0 never times peers out
+    #[serde(default = "DaemonConfig::default_peer_timeout_secs")]
+    pub peer_timeout_secs: f64,
+    /// Append every message heard to this journal
+    #[serde(default)]
+    pub journal: Option<PathBuf>,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default)]
+    pub bridge: Option<BridgeConfig>,
+    /// `"crc32c"` once every node accepts version 3 headers, `"crc32c-payload"`
+    /// to cover payloads too once every node accepts version 4
+    #[serde(default)]
+    pub header_checksum: HeaderChecksum,
+    /// Send headers as this protocol version instead of the one
+    /// `header_checksum` implies, e.g. 1 while older nodes are still rolled out
+    #[serde(default)]
+    pub protocol_version: Option<u8>,
+    /// Write events to Event Tracing for Windows
+    #[serde(default)]
+    pub etw: bool,
+    /// Pin the receive thread to cores and raise its priority
+    #[serde(default)]
+    pub receive_thread: ThreadScheduling,
+}
+
+/// Admin front-ends to run; all answer the same [`AdminRequest`](crate::AdminRequest)s
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    /// Unix socket taking one JSON request per line
+    pub socket: Option<PathBuf>,
+    /// HTTP/JSON API address, with `token` as its bearer token
+    pub http: Option<SocketAddr>,
+    pub token: Option<String>,
+    /// gRPC service address, also with `token` as its bearer token
+    pub grpc: Option<SocketAddr>,
+    /// Directory captures started over the admin interfaces are written to;
+    /// their paths are relative to it. Captures are refused without one.
+    pub capture_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrokerKind {
+    Nats,
+    Redis,
+}
+
+/// Mirror the group to a message broker, as `fleet_bridge` does
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    pub broker: BrokerKind,
+    /// Broker address; defaults to the broker's usual port on 127.0.0.1
+    pub url: Option<String>,
+    /// NATS subject or Redis channel
+    pub topic: Option<String>,
+    pub bridge_id: Option<u32>,
+    /// Rewrite what is published to the broker, e.g. coarsen positions
+    #[serde(default)]
+    pub transforms: Vec<TransformRule>,
+}
+
+impl DaemonConfig {
+    fn default_group() -> Ipv4Addr {
+        Ipv4Addr::new(239, 1, 1, 1)
+    }
+
+    fn default_port() -> u16 {
+        12345
+    }
+
+    fn default_heartbeat_secs() -> f64 {
+        1.0
+    }
+
+    fn default_peer_timeout_secs() -> f64 {
+        3.0
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .and_then(|text| Self::from_toml(&text))
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Parse and check a configuration, including that this build has the
+    /// features the configured services need
+    pub fn from_toml(text: &str) -> std::io::Result<Self> {
+        let config: Self = toml::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let invalid = |msg: &str| Err(Error::new(ErrorKind::InvalidInput, msg.to_string()));
+
+        if !(config.heartbeat_secs.is_finite() && config.heartbeat_secs >= 0.0) {
+            return invalid("heartbeat_secs must be zero or positive");
+        }
+        if !(config.peer_timeout_secs.is_finite() && config.peer_timeout_secs >= 0.0) {
+            return invalid("peer_timeout_secs must be zero or positive");
+        }
+        let versions = FleetMsgHeader::MIN_VERSION..=FleetMsgHeader::MAX_VERSION;
+        if config.protocol_version.is_some_and(|version| !versions.contains(&version)) {
+            return invalid(&format!("protocol_version must be between {} and {}", versions.start(), versions.end()));
+        }
+        config.receive_thread.validate()?;
+        if config.etw && !cfg!(windows) {
+            return invalid("etw needs Windows");
+        }
+        if config.control.socket.is_some() && !cfg!(unix) {
+            return invalid("control.socket needs Unix domain sockets");
+        }
+        if config.control.http.is_some() {
+            if !cfg!(feature = "http-admin") {
+                return invalid("control.http needs a build with --features http-admin");
+            }
+            if config.control.token.as_deref().unwrap_or_default().is_empty() {
+                return invalid("control.http needs a control.token");
+            }
+        }
+        if config.control.grpc.is_some() {
+            if !cfg!(feature = "grpc") {
+                return invalid("control.grpc needs a build with --features grpc");
+            }
+            if config.control.token.as_deref().unwrap_or_default().is_empty() {
+                return invalid("control.grpc needs a control.token");
+            }
+        }
+        if config.bridge.is_some() && !cfg!(feature = "bridge") {
+            return invalid("bridge needs a build with --features bridge");
+        }
+        Ok(config)
+    }
+}
+
+/// The continuous journal and any capture started over the admin interface
+#[derive(Debug, Default)]
+struct Recorders {
+    journal: Option<JournalWriter>,
+    capture: Option<JournalWriter>,
+}
+
+impl Recorders {
+    fn record(&mut self, header: &FleetMsgHeader, payload: &[u8], addr: SocketAddr) {
+        for (name, writer) in [("journal", &mut self.journal), ("capture", &mut self.capture)] {
+            if let Some(writer) = writer
+                && let Err(e) = writer.append(header, payload, addr)
+            {
+                eprintln!("Failed to write {}: {}", name, e);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        for writer in [&mut self.journal, &mut self.capture].into_iter().flatten() {
+            if let Err(e) = writer.flush() {
+                eprintln!("Failed to flush journal: {}", e);
+            }
+        }
+    }
+}
+
+/// Run the daemon until it is signalled to stop or one of its services fails
+pub async fn run(config: DaemonConfig) -> std::io::Result<()> {
+    let stats = Arc::new(TransportStats::new());
+    let mut peers = PeerTable::new();
+    if config.peer_timeout_secs > 0.0 {
+        peers = peers.with_timeout(Duration::from_secs_f64(config.peer_timeout_secs));
+    }
+    let (admin, commands) = AdminState::new(Arc::new(Mutex::new(peers)), stats.clone());
+    let mut sender = MulticastSender::new(config.group, config.port, config.sender_id).await?
+        .with_header_checksum(config.header_checksum)
+        .with_stats(stats);
+    if let Some(version) = config.protocol_version {
+        sender = sender.with_protocol_version(version)?;
+    }
+    let journal = config.journal.as_ref().map(JournalWriter::open).transpose()?;
+    let recorders = Arc::new(Mutex::new(Recorders { journal, capture: None }));
+
+    let heard = Arc::new(AtomicU64::new(0));
+    let handler = {
+        let (admin, recorders, heard, own_id) = (admin.clone(), recorders.clone(), heard.clone(), config.sender_id);
+        move |delivery: Delivery| {
+            let Delivery { header, payload, addr, .. } = delivery;
+            heard.fetch_add(1, Ordering::Relaxed);
+            // Our own heartbeats come back over multicast loopback; we aren't our own peer
+            if header.sender_id.get() != own_id {
+                admin.observe(&header, &payload, addr);
+            }
+            recorders.lock().unwrap().record(&header, &payload, addr);
+        }
+    };
+    let socket = transport::join(&[config.group], config.port).await?;
+    let receiver_config = ReceiverConfig::new().with_thread_scheduling(config.receive_thread.clone());
+    let receiving = transport::receive_loop(socket, receiver_config, handler);
+    let mut services = vec![task::spawn(async move { receiving.await.map_err(Error::from) })];
+    services.extend(start_services(&config, &admin).await?);
+    println!("fleetlinkd running as sender {:#06x} on {}:{}", config.sender_id, config.group, config.port);
+    #[cfg(unix)]
+    let notifier = Notifier::from_env()?;
+    #[cfg(unix)]
+    report(notifier.ready(&status(&admin)), "readiness");
+    #[cfg(windows)]
+    if let Some(service) = winservice::current() {
+        report(service.ready(), "readiness");
+    }
+    #[cfg(windows)]
+    let mut events = config.etw.then(|| EventProvider::register(winservice::PROVIDER_GUID)).transpose()?
+        .map(|provider| Events { provider, peers: BTreeSet::new() });
+    #[cfg(windows)]
+    if let Some(events) = &events {
+        events.write(EventLevel::Info, &format!("fleetlinkd started: {}", status(&admin)));
+    }
+
+    let heartbeat = (config.heartbeat_secs > 0.0).then(|| Duration::from_secs_f64(config.heartbeat_secs));
+    #[allow(unused_mut)]
+    let mut tick = heartbeat.unwrap_or(FLUSH_INTERVAL);
+    #[cfg(unix)]
+    let mut health = notifier.watchdog_timeout().map(|timeout| {
+        tick = tick.min(timeout / 2);
+        Health { timeout, heard: 0, last_heard: Instant::now() }
+    });
+    let mut next_tick = Instant::now();
+    let mut stop = select(select_all(services), Box::pin(shutdown_signal()));
+    let result = loop {
+        if Instant::now() >= next_tick {
+            if heartbeat.is_some()
+                && let Err(e) = sender.send_heartbeat().await
+            {
+                eprintln!("Failed to send heartbeat: {}", e);
+                #[cfg(windows)]
+                if let Some(events) = &events {
+                    events.write(EventLevel::Warning, &format!("Failed to send heartbeat: {}", e));
+                }
+            }
+            recorders.lock().unwrap().flush();
+            admin.peers().lock().unwrap().tick(Instant::now());
+            #[cfg(windows)]
+            if let Some(events) = &mut events {
+                events.peers_changed(&admin);
+            }
+            #[cfg(unix)]
+            if let Some(health) = &mut health
+                && (heartbeat.is_none() || health.receiving(heard.load(Ordering::Relaxed)))
+            {
+                report(notifier.watchdog(), "watchdog ping");
+                report(notifier.status(&status(&admin)), "status");
+            }
+            next_tick = Instant::now() + tick;
+        }
+
+        let wait = next_tick.saturating_duration_since(Instant::now());
+        match select(pin!(timeout(wait, commands.recv())), &mut stop).await {
+            Either::Left((Ok(Ok(command)), _)) => {
+                execute(command, &mut sender, &recorders, config.control.capture_dir.as_deref()).await
+            }
+            Either::Left((Ok(Err(_)), _)) => break Ok(()),
+            Either::Left((Err(_), _)) => {}
+            Either::Right((Either::Left(((result, _, _), _)), _)) => {
+                break result.and(Err(Error::other("a daemon service stopped unexpectedly")));
+            }
+            Either::Right((Either::Right((signal, _)), _)) => {
+                println!("Shutting down");
+                break signal;
+            }
+        }
+    };
+
+    #[cfg(unix)]
+    report(notifier.stopping(), "shutdown");
+    #[cfg(windows)]
+    if let Some(service) = winservice::current() {
+        report(service.stopping(), "shutdown");
+    }
+    #[cfg(windows)]
+    if let Some(events) = &events {
+        match &result {
+            Ok(()) => events.write(EventLevel::Info, "fleetlinkd stopping"),
+            Err(e) => events.write(EventLevel::Error, &format!("fleetlinkd stopping: {}", e)),
+        }
+    }
+    recorders.lock().unwrap().flush();
+    sender.shutdown().await?;
+    result
+}
+
+/// Whether the receive loop is still delivering, judged by the daemon's own
+/// heartbeats coming back to it
+#[cfg(unix)]
+struct Health {
+    timeout: Duration,
+    heard: u64,
+    last_heard: Instant,
+}
+
+#[cfg(unix)]
+impl Health {
+    /// True until nothing at all was heard for the watchdog timeout
+    fn receiving(&mut self, heard: u64) -> bool {
+        if heard != self.heard {
+            self.heard = heard;
+            self.last_heard = Instant::now();
+        }
+        self.last_heard.elapsed() < self.timeout
+    }
+}
+
+/// The status line shown by `systemctl status`
+#[cfg(any(unix, windows))]
+fn status(admin: &AdminState) -> String {
+    let peers = admin.peers().lock().unwrap().online().count();
+    let stats = admin.stats().snapshot();
+    format!("{} peers, {} messages received, {} sent", peers, stats.messages_received, stats.messages_sent)
+}
+
+#[cfg(unix)]
+fn report(result: std::io::Result<()>, what: &str) {
+    if let Err(e) = result {
+        eprintln!("Failed to notify systemd of {}: {}", what, e);
+    }
+}
+
+#[cfg(windows)]
+fn report(result: std::io::Result<()>, what: &str) {
+    if let Err(e) = result {
+        eprintln!("Failed to report {} to the service control manager: {}", what, e);
+    }
+}
+
+/// ETW events, with the peers seen at the last tick to tell who joined or left
+#[cfg(windows)]
+struct Events {
+    provider: EventProvider,
+    peers: BTreeSet<u32>,
+}
+
+#[cfg(windows)]
+impl Events {
+    fn write(&self, level: EventLevel, message: &str) {
+        if let Err(e) = self.provider.write(level, message) {
+            eprintln!("Failed to write ETW event: {}", e);
+        }
+    }
+
+    fn peers_changed(&mut self, admin: &AdminState) {
+        let peers: BTreeSet<u32> = admin.peers().lock().unwrap().online().map(|peer| peer.sender_id).collect();
+        for joined in peers.difference(&self.peers) {
+            self.write(EventLevel::Info, &format!("Peer {:#06x} joined", joined));
+        }
+        for left in self.peers.difference(&peers) {
+            self.write(EventLevel::Warning, &format!("Peer {:#06x} left", left));
+        }
+        self.peers = peers;
+    }
+}
+
+/// Carry out an admin request that needs the daemon's sender or journals
+async fn execute(command: AdminCommand, sender: &mut MulticastSender, recorders: &Mutex<Recorders>, capture_dir: Option<&Path>) {
+    match command {
+        // There is no unicast ping in the protocol; an immediate heartbeat shows we're alive
+        AdminCommand::Ping { .. } => {
+            if let Err(e) = sender.send_heartbeat().await {
+                eprintln!("Failed to answer ping: {}", e);
+            }
+        }
+        AdminCommand::StartCapture { path: requested } => match capture_path(capture_dir, &requested).and_then(|path| {
+            JournalWriter::open(&path).map(|writer| (path, writer))
+        }) {
+            Ok((path, writer)) => {
+                println!("Capturing to {}", path.display());
+                recorders.lock().unwrap().capture = Some(writer);
+            }
+            Err(e) => eprintln!("Failed to start capture to {}: {}", requested, e),
+        },
+        AdminCommand::StopCapture => {
+            let mut recorders = recorders.lock().unwrap();
+            if let Some(mut capture) = recorders.capture.take()
+                && let Err(e) = capture.flush()
+            {
+                eprintln!("Failed to flush capture: {}", e);
+            }
+        }
+    }
+}
+
+/// Where a capture requested as `requested` goes: inside `capture_dir`, which
+/// admin clients can't leave with an absolute path or `..`
+fn capture_path(capture_dir: Option<&Path>, requested: &str) -> std::io::Result<PathBuf> {
+    let Some(dir) = capture_dir else {
+        return Err(Error::new(ErrorKind::PermissionDenied, "captures need a control.capture_dir"));
+    };
+    let relative = Path::new(requested);
+    if requested.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(Error::new(ErrorKind::InvalidInput, "capture paths must be relative to control.capture_dir, without '..'"));
+    }
+    Ok(dir.join(relative))
+}
+
+/// Start the configured admin front-ends and bridge
+async fn start_services(config: &DaemonConfig, admin: &AdminState) -> std::io::Result<Vec<JoinHandle<std::io::Result<()>>>> {
+    let mut services = Vec::new();
+    #[cfg(unix)]
+    if let Some(path) = &config.control.socket {
+        services.push(task::spawn(crate::admin::socket::serve(admin.clone(), path.clone())));
+    }
+    #[cfg(feature = "http-admin")]
+    if let Some(addr) = config.control.http {
+        let listener = async_std::net::TcpListener::bind(addr).await?;
+        let token = config.control.token.clone().unwrap_or_default();
+        services.push(task::spawn(crate::admin::http::HttpAdmin::new(admin.clone(), token)?.serve(listener)));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = config.control.grpc {
+        // tonic needs a tokio runtime of its own
+        let admin = admin.clone();
+        let token = config.control.token.clone().unwrap_or_default();
+        services.push(task::spawn_blocking(move || {
+            tokio::runtime::Runtime::new()?.block_on(crate::admin::grpc::serve(admin, addr, token))
+        }));
+    }
+    #[cfg(feature = "bridge")]
+    if let Some(bridge) = config.bridge.clone() {
+        services.push(task::spawn(run_bridge(bridge, config.group, config.port)));
+    }
+    Ok(services)
+}
+
+#[cfg(feature = "bridge")]
+async fn run_bridge(config: BridgeConfig, group: Ipv4Addr, port: u16) -> std::io::Result<()> {
+    use crate::bridge::{self, BrokerBridge, NatsBroker, RedisBroker};
+    use crate::transform::Transforms;
+
+    fn configured<B: bridge::Broker>(mut bridge: BrokerBridge<B>, config: &BridgeConfig) -> BrokerBridge<B> {
+        if let Some(id) = config.bridge_id {
+            bridge = bridge.with_bridge_id(id);
+        }
+        if !config.transforms.is_empty() {
+            bridge = bridge.with_transforms(Transforms::from_rules(config.transforms.clone()));
+        }
+        bridge
+    }
+
+    match config.broker {
+        BrokerKind::Nats => {
+            let url = config.url.as_deref().unwrap_or("127.0.0.1:4222");
+            let broker = NatsBroker::connect(url, config.topic.as_deref().unwrap_or(bridge::nats::DEFAULT_SUBJECT)).await?;
+            configured(BrokerBridge::new(broker, group, port), &config).run().await
+        }
+        BrokerKind::Redis => {
+            let url = config.url.as_deref().unwrap_or("127.0.0.1:6379");
+            let broker = RedisBroker::connect(url, config.topic.as_deref().unwrap_or(bridge::redis::DEFAULT_CHANNEL)).await?;
+            configured(BrokerBridge::new(broker, group, port), &config).run().await
+        }
+    }
+}
+
+/// Resolves on SIGTERM or SIGINT, the ways supervisors stop a service, or
+/// when the Windows service control manager stops it
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(windows)]
+    if let Some(service) = winservice::current() {
+        service.stop_requested().await;
+        return Ok(());
+    }
+    task::spawn_blocking(|| {
+        tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(async {
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{SignalKind, signal};
+                let mut terminate = signal(SignalKind::terminate())?;
+                tokio::select! {
+                    result = tokio::signal::ctrl_c() => result,
+                    _ = terminate.recv() => Ok(()),
+                }
+            }
+            #[cfg(not(unix))]
+            tokio::signal::ctrl_c().await
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parsing() {
+        let config = DaemonConfig::from_toml("sender_id = 0x0100\njournal = \"/tmp/traffic.jsonl\"\n").unwrap();
+        assert_eq!((config.sender_id, config.group, config.port), (0x100, Ipv4Addr::new(239, 1, 1, 1), 12345));
+        assert_eq!((config.heartbeat_secs, config.peer_timeout_secs), (1.0, 3.0));
+        assert_eq!(config.control, ControlConfig::default());
+        assert_eq!(config.header_checksum, HeaderChecksum::Sum);
+        let crc = DaemonConfig::from_toml("sender_id = 1\nheader_checksum = \"crc32c\"\n").unwrap();
+        assert_eq!(crc.header_checksum, HeaderChecksum::Crc32c);
+        assert_eq!(DaemonConfig::from_toml("sender_id = 1\nprotocol_version = 1\n").unwrap().protocol_version, Some(1));
+        assert!(DaemonConfig::from_toml("sender_id = 1\nprotocol_version = 9\n").is_err());
+        assert_eq!(DaemonConfig::from_toml("sender_id = 1\netw = true\n").is_ok(), cfg!(windows));
+        let pinned = DaemonConfig::from_toml("sender_id = 1\n[receive_thread]\ncpus = [2, 3]\nrealtime_priority = 20\n").unwrap();
+        assert_eq!(pinned.receive_thread, ThreadScheduling::pinned([2, 3]).with_realtime_priority(20));
+        assert!(DaemonConfig::from_toml("sender_id = 1\n[receive_thread]\nrealtime_priority = 100\n").is_err());
+
+        let typo = DaemonConfig::from_toml("sender_id = 1\nheartbeat = 2\n").unwrap_err();
+        assert_eq!(typo.kind(), ErrorKind::InvalidData);
+        let negative = DaemonConfig::from_toml("sender_id = 1\nheartbeat_secs = -1\n").unwrap_err();
+        assert_eq!(negative.kind(), ErrorKind::InvalidInput);
+        let untokened = DaemonConfig::from_toml("sender_id = 1\n[control]\nhttp = \"127.0.0.1:7071\"\n").unwrap_err();
+        assert_eq!(untokened.kind(), ErrorKind::InvalidInput);
+        let untokened = DaemonConfig::from_toml("sender_id = 1\n[control]\ngrpc = \"127.0.0.1:7070\"\n").unwrap_err();
+        assert_eq!(untokened.kind(), ErrorKind::InvalidInput);
+
+        let captures = DaemonConfig::from_toml("sender_id = 1\n[control]\ncapture_dir = \"/var/lib/fleetlink\"\n").unwrap();
+        assert_eq!(captures.control.capture_dir, Some(PathBuf::from("/var/lib/fleetlink")));
+
+        let bridge: BridgeConfig = toml::from_str("broker = \"nats\"\n[[transforms]]\ntopic = \"Data\"\naction = \"redact\"\nfields = [\"vin\"]\n").unwrap();
+        assert_eq!(bridge.transforms[0].action, crate::transform::TransformAction::Redact { fields: vec!["vin".into()] });
+    }
+
+    #[test]
+    fn test_captures_stay_in_capture_dir() {
+        let dir = Path::new("/var/lib/fleetlink/captures");
+        assert_eq!(capture_path(Some(dir), "incident.jsonl").unwrap(), dir.join("incident.jsonl"));
+        assert_eq!(capture_path(Some(dir), "depot/incident.jsonl").unwrap(), dir.join("depot/incident.jsonl"));
+        for escape in ["/etc/passwd", "../fleetlinkd.toml", "depot/../../x", "./incident.jsonl", ""] {
+            assert_eq!(capture_path(Some(dir), escape).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", escape);
+        }
+        assert_eq!(capture_path(None, "incident.jsonl").unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn test_daemon_journals_and_answers() {
+        use crate::admin::AdminResponse;
+        use async_std::io::BufReader;
+        use async_std::io::prelude::{BufReadExt, WriteExt};
+        use async_std::os::unix::net::UnixStream;
+        use futures::StreamExt;
+
+        let dir = std::env::temp_dir();
+        let socket = dir.join(format!("fleetlinkd-{}.sock", std::process::id()));
+        let journal = dir.join(format!("fleetlinkd-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&journal);
+        let _ = std::fs::remove_file(&socket);
+        let config = DaemonConfig::from_toml(&format!(
+            "sender_id = 0xD0\ngroup = \"239.1.1.94\"\nport = 12594\nheartbeat_secs = 0.05\njournal = {:?}\n[control]\nsocket = {:?}\n",
+            journal, socket
+        )).unwrap();
+        task::spawn(run(config));
+        while !socket.exists() {
+            task::sleep(Duration::from_millis(5)).await;
+        }
+
+        let mut peer = MulticastSender::new(Ipv4Addr::new(239, 1, 1, 94), 12594, 0x42).await.unwrap();
+        peer.send_data(b"hello").await.unwrap();
+        task::sleep(Duration::from_millis(200)).await;
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        stream.write_all(b"{\"op\":\"list_peers\"}\n").await.unwrap();
+        let reply = BufReader::new(stream).lines().next().await.unwrap().unwrap();
+        let AdminResponse::Peers { peers } = serde_json::from_str(&reply).unwrap() else { panic!("{}", reply) };
+        assert_eq!(peers.iter().map(|p| p.sender_id).collect::<Vec<_>>(), [0x42]);
+
+        let entries = crate::journal::read_journal(&journal).unwrap();
+        assert!(entries.iter().any(|e| e.sender_id == 0x42 && e.msg_type == 2));
+        assert!(entries.iter().any(|e| e.sender_id == 0xD0), "own heartbeats are journaled too");
+    }
+}
@@ -0,0 +1,186 @@
+//! zstd dictionaries trained on recorded fleet traffic, for compressing
+//! payloads too small for deflate to gain anything on.
+//!
+//! Telemetry messages of a few dozen bytes repeat the same field names and
+//! framing every time. A dictionary trained from a journal of them holds
+//! those parts, so each message only carries what changed. Give the
+//! dictionary to a [`FeatureCodec`] with
+//! [`with_dictionary`](FeatureCodec::with_dictionary): payloads up to
+//! [`DICTIONARY_PAYLOAD_LIMIT`] are then compressed with it as zstd frames,
+//! larger ones are still deflated.
+//!
+//! Both travel under the same compression flag. A receiver tells them apart
+//! by the zstd magic number, which deflate streams never start with, and picks
+//! the dictionary by the id in the frame. Hand the dictionary to every
+//! receiver before any sender uses it; a node without it refuses such
+//! payloads as `Unsupported`.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::features::FeatureCodec;
+use crate::journal::JournalEntry;
+
+/// Largest payload compressed with the dictionary; larger ones compress well
+/// enough on their own
+pub const DICTIONARY_PAYLOAD_LIMIT: usize = 1024;
+
+/// Dictionary size that suits most fleets' telemetry
+pub const DEFAULT_DICTIONARY_SIZE: usize = 16 * 1024;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const LEVEL: i32 = 3;
+
+/// A trained zstd dictionary, cheap to clone and share between codecs
+#[derive(Clone)]
+pub struct CompressionDictionary {
+    id: u32,
+    bytes: Arc<[u8]>,
+    encoder: Arc<EncoderDictionary<'static>>,
+    decoder: Arc<DecoderDictionary<'static>>,
+}
+
+impl fmt::Debug for CompressionDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionDictionary").field("id", &self.id).field("len", &self.bytes.len()).finish_non_exhaustive()
+    }
+}
+
+impl CompressionDictionary {
+    /// Train a dictionary of at most `max_size` bytes from sample payloads;
+    /// zstd wants a few hundred of them at least
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> std::io::Result<Self> {
+        Self::from_bytes(zstd::dict::from_samples(samples, max_size)?)
+    }
+
+    /// Train from the small payloads in a journal of recorded traffic, undoing
+    /// whatever features they were sent with; entries `codec` can't decode are skipped
+    pub fn train_from_journal(entries: &[JournalEntry], codec: &FeatureCodec, max_size: usize) -> std::io::Result<Self> {
+        let samples: Vec<Vec<u8>> = entries
+            .iter()
+            .filter_map(|entry| entry.decode())
+            .filter_map(|(header, payload)| codec.decode(&header, &payload).ok())
+            .filter(|payload| !payload.is_empty() && payload.len() <= DICTIONARY_PAYLOAD_LIMIT)
+            .collect();
+        if samples.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no small payloads in the journal to train on"));
+        }
+        Self::train(&samples, max_size)
+    }
+
+    /// A dictionary as written by [`save`](Self::save) or `zstd --train`
+    pub fn from_bytes(bytes: Vec<u8>) -> std::io::Result<Self> {
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&bytes)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "not a zstd dictionary (no dictionary id)"))?
+            .get();
+        Ok(Self {
+            id,
+            encoder: Arc::new(EncoderDictionary::copy(&bytes, LEVEL)),
+            decoder: Arc::new(DecoderDictionary::copy(&bytes)),
+            bytes: bytes.into(),
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, &self.bytes)
+    }
+
+    /// The id zstd stamps on every frame compressed with this dictionary
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn compress(&self, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)?.compress(payload)
+    }
+
+    pub(crate) fn decompress(&self, payload: &[u8], max_len: usize) -> std::io::Result<Vec<u8>> {
+        zstd::bulk::Decompressor::with_prepared_dictionary(&self.decoder)?
+            .decompress(payload, max_len)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "payload does not decompress with its dictionary"))
+    }
+
+    /// The dictionary id of a compressed payload that is a zstd frame
+    pub(crate) fn frame_id(payload: &[u8]) -> Option<u32> {
+        if !payload.starts_with(&ZSTD_MAGIC) {
+            return None;
+        }
+        Some(zstd::zstd_safe::get_dict_id_from_frame(payload).map_or(0, |id| id.get()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::ProtocolFeatures;
+    use crate::rng;
+    use crate::transport::{FleetMsgHeader, MessageType};
+    use rand::Rng;
+
+    fn telemetry(count: usize) -> Vec<Vec<u8>> {
+        let mut rng = rng::seeded(7);
+        (0..count)
+            .map(|i| {
+                let (speed, battery): (u32, u32) = (rng.random_range(0..40), rng.random_range(0..100));
+                format!("{{\"vehicle\":\"forklift-{}\",\"speed\":{},\"battery\":{},\"state\":\"moving\"}}", i % 12, speed, battery).into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dictionary_compresses_tiny_payloads() {
+        let samples = telemetry(1000);
+        let dictionary = CompressionDictionary::train(&samples, 4096).unwrap();
+        let codec = FeatureCodec::new().with_dictionary(dictionary.clone());
+
+        let payload = &samples[3];
+        let (used, encoded) = codec.encode(ProtocolFeatures::COMPRESSION, payload).unwrap();
+        assert_eq!(used, ProtocolFeatures::COMPRESSION);
+        assert!(encoded.len() * 2 < payload.len(), "{} of {} bytes", encoded.len(), payload.len());
+        // Deflate alone can't shrink a payload this small
+        assert!(FeatureCodec::new().encode(ProtocolFeatures::COMPRESSION, payload).unwrap().0.is_empty());
+
+        let header = FleetMsgHeader::new(MessageType::Data, 1, 0, encoded.len() as u16).with_features(used);
+        assert_eq!(&codec.decode(&header, &encoded).unwrap(), payload);
+        assert_eq!(FeatureCodec::new().decode(&header, &encoded).unwrap_err().kind(), ErrorKind::Unsupported);
+
+        // Large payloads are still deflated, and any codec reads them
+        let large = b"telemetry ".repeat(200);
+        let (used, encoded) = codec.encode(ProtocolFeatures::COMPRESSION, &large).unwrap();
+        let header = FleetMsgHeader::new(MessageType::Data, 1, 1, encoded.len() as u16).with_features(used);
+        assert_eq!(FeatureCodec::new().decode(&header, &encoded).unwrap(), large);
+
+        assert_eq!(CompressionDictionary::from_bytes(dictionary.as_bytes().to_vec()).unwrap().id(), dictionary.id());
+        assert_eq!(CompressionDictionary::from_bytes(b"not a dictionary".to_vec()).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_train_from_journal() {
+        let addr = "10.0.0.9:5000".parse().unwrap();
+        let codec = FeatureCodec::new();
+        let entries: Vec<JournalEntry> = telemetry(500)
+            .iter()
+            .enumerate()
+            .map(|(sequence, payload)| {
+                let (used, encoded) = codec.encode(ProtocolFeatures::CRC32, payload).unwrap();
+                let header = FleetMsgHeader::new(MessageType::Data, 9, sequence as u16, encoded.len() as u16).with_features(used);
+                JournalEntry::new(&header, &encoded, addr, 0)
+            })
+            .collect();
+
+        let dictionary = CompressionDictionary::train_from_journal(&entries, &codec, 4096).unwrap();
+        assert!(!dictionary.as_bytes().is_empty());
+        assert_eq!(CompressionDictionary::train_from_journal(&[], &codec, 4096).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,127 @@
+//! The error type of the sender, the receiver and header parsing.
+//!
+//! [`TransportError`] tells apart what `std::io::Error` kinds can't: a message
+//! that failed validation, a header checksum mismatch, a payload too large to
+//! send, a send held back by the shaping calendar, and the socket itself
+//! failing. It converts to and from `std::io::Error` both ways, so `?` keeps
+//! working in functions returning `std::io::Result`, and a `TransportError`
+//! that went through an `io::Error` comes back out unchanged.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use crate::bandwidth::MessageClass;
+use crate::receiver::ValidationIssue;
+
+pub type Result<T> = std::result::Result<T, TransportError>;
+
+#[derive(Debug)]
+pub enum TransportError {
+    /// A received message or header failed validation
+    Invalid(Vec<ValidationIssue>),
+    /// The header's checksum doesn't match its contents
+    ChecksumMismatch,
+    /// A payload over the most that can be sent or accepted
+    PayloadTooLarge { len: usize, limit: usize },
+    /// The shaping calendar holds this class of traffic for `delay` yet, or
+    /// (with a `delay` of `Duration::MAX`) the class has no bandwidth budget
+    Held { class: MessageClass, delay: Duration },
+    /// The sender or receiver isn't set up for what was asked of it
+    Misconfigured(String),
+    /// A payload couldn't be compressed, encrypted or signed for a target
+    Encode(Error),
+    /// The socket failed, e.g. it was closed or the network is unreachable
+    Socket(Error),
+}
+
+impl TransportError {
+    /// The closest `std::io::ErrorKind`, for callers that only look at that
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            TransportError::Invalid(_) | TransportError::ChecksumMismatch => ErrorKind::InvalidData,
+            TransportError::PayloadTooLarge { .. } | TransportError::Misconfigured(_) => ErrorKind::InvalidInput,
+            TransportError::Held { .. } => ErrorKind::WouldBlock,
+            TransportError::Encode(e) | TransportError::Socket(e) => e.kind(),
+        }
+    }
+
+    /// The error for a message with these issues; a bad checksum alone is a [`ChecksumMismatch`](Self::ChecksumMismatch)
+    pub fn from_issues(issues: Vec<ValidationIssue>) -> Self {
+        match issues.as_slice() {
+            [ValidationIssue::BadChecksum] => TransportError::ChecksumMismatch,
+            [ValidationIssue::PayloadTooLarge { claimed, limit }] => {
+                TransportError::PayloadTooLarge { len: *claimed, limit: *limit }
+            }
+            _ => TransportError::Invalid(issues),
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Invalid(issues) => write!(f, "invalid message: {}", crate::receiver::describe(issues)),
+            TransportError::ChecksumMismatch => write!(f, "header checksum mismatch"),
+            TransportError::PayloadTooLarge { len, limit } => write!(f, "{} byte payload over the {} byte limit", len, limit),
+            TransportError::Held { class, delay: Duration::MAX } => write!(f, "{:?} traffic has no bandwidth budget", class),
+            TransportError::Held { class, delay } => {
+                write!(f, "{:?} traffic held by the shaping calendar for another {:?}", class, delay)
+            }
+            TransportError::Misconfigured(reason) => write!(f, "{}", reason),
+            TransportError::Encode(e) => write!(f, "payload does not encode: {}", e),
+            TransportError::Socket(e) => write!(f, "socket error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransportError::Encode(e) | TransportError::Socket(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for TransportError {
+    /// A `TransportError` carried inside the `io::Error` is taken back out;
+    /// anything else is a socket error
+    fn from(e: Error) -> Self {
+        if !e.get_ref().is_some_and(|inner| inner.is::<TransportError>()) {
+            return TransportError::Socket(e);
+        }
+        let kind = e.kind();
+        match e.into_inner().map(|inner| inner.downcast::<TransportError>()) {
+            Some(Ok(inner)) => *inner,
+            _ => TransportError::Socket(Error::from(kind)),
+        }
+    }
+}
+
+impl From<TransportError> for Error {
+    fn from(e: TransportError) -> Self {
+        match e {
+            TransportError::Socket(e) => e,
+            other => Error::new(other.kind(), other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_through_io_error() {
+        let held = TransportError::Held { class: MessageClass::Bulk, delay: Duration::from_secs(3) };
+        let io: Error = held.into();
+        assert_eq!(io.kind(), ErrorKind::WouldBlock);
+        assert!(matches!(TransportError::from(io), TransportError::Held { class: MessageClass::Bulk, .. }));
+
+        let closed = TransportError::from(Error::from(ErrorKind::NotConnected));
+        assert!(matches!(&closed, TransportError::Socket(e) if e.kind() == ErrorKind::NotConnected));
+        assert_eq!(Error::from(closed).kind(), ErrorKind::NotConnected);
+        assert!(matches!(TransportError::from_issues(vec![ValidationIssue::BadChecksum]), TransportError::ChecksumMismatch));
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::fragment::Fragment;
+use crate::heartbeat::Liveness;
+use crate::rpc::Correlation;
+use crate::timing::SendTimestamps;
+use crate::trace::TraceId;
+use crate::zones::Zone;
+
+/// Extension type of the trace id stamped on Data and Control messages
+pub const TRACE_ID: u8 = 1;
+/// Extension type of the sender's wall and monotonic send times
+pub const SEND_TIME: u8 = 2;
+/// Extension type giving (as a little-endian u16) how many padding bytes
+/// follow the payload; [`Extensions::split`] strips them
+pub const PADDING: u8 = 3;
+/// Extension type marking one fragment of a larger message, see [`Fragment`]
+pub const FRAGMENT: u8 = 4;
+/// Extension type naming the latest-only topic a message is the newest value
+/// of, see [`compaction`](crate::compaction)
+pub const LAST_VALUE: u8 = 5;
+/// Extension type giving (as a little-endian u16) the schema version the
+/// payload is encoded in, see [`schema`](crate::net::schema)
+pub const SCHEMA_VERSION: u8 = 6;
+/// Extension type of a heartbeat's uptime and message counters, see [`Liveness`]
+pub const LIVENESS: u8 = 7;
+/// Extension type of the site zone and geofences the sender is in, see [`Zone`]
+pub const ZONE: u8 = 8;
+/// Extension type tying a Control response to its request, see [`Correlation`]
+pub const CORRELATION: u8 = 9;
+
+/// Type-length-value extensions carried ahead of the payload of messages
+/// flagged with [`ProtocolFeatures::EXTENSIONS`](crate::ProtocolFeatures::EXTENSIONS).
+///
+/// On the wire: a count byte, then a type byte, a length byte and the value
+/// for each extension. Type 0 is reserved. Extensions a receiver doesn't know
+/// are kept, so they can still be inspected or forwarded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Extensions(BTreeMap<u8, Vec<u8>>);
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, kind: u8, value: impl Into<Vec<u8>>) -> std::io::Result<()> {
+        let value = value.into();
+        if kind == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "extension type 0 is reserved"));
+        }
+        if value.len() > u8::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("extension {} is over 255 bytes", kind)));
+        }
+        self.0.insert(kind, value);
+        Ok(())
+    }
+
+    /// Extensions carrying just `trace`
+    pub fn traced(trace: TraceId) -> Self {
+        let mut extensions = Self::new();
+        extensions.set_trace_id(trace);
+        extensions
+    }
+
+    pub fn get(&self, kind: u8) -> Option<&[u8]> {
+        self.0.get(&kind).map(Vec::as_slice)
+    }
+
+    pub fn remove(&mut self, kind: u8) -> Option<Vec<u8>> {
+        self.0.remove(&kind)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        self.0.iter().map(|(kind, value)| (*kind, value.as_slice()))
+    }
+
+    pub fn trace_id(&self) -> Option<TraceId> {
+        Some(TraceId::from_bytes(self.get(TRACE_ID)?.try_into().ok()?))
+    }
+
+    pub fn set_trace_id(&mut self, trace: TraceId) {
+        self.0.insert(TRACE_ID, trace.to_bytes().to_vec());
+    }
+
+    pub fn send_timestamps(&self) -> Option<SendTimestamps> {
+        SendTimestamps::decode(self.get(SEND_TIME)?)
+    }
+
+    pub fn set_send_timestamps(&mut self, sent: SendTimestamps) {
+        self.0.insert(SEND_TIME, sent.encode().to_vec());
+    }
+
+    pub fn fragment(&self) -> Option<Fragment> {
+        Fragment::decode(self.get(FRAGMENT)?)
+    }
+
+    pub fn set_fragment(&mut self, fragment: Fragment) {
+        self.0.insert(FRAGMENT, fragment.encode().to_vec());
+    }
+
+    /// The latest-only topic the message updates
+    pub fn last_value(&self) -> Option<&str> {
+        std::str::from_utf8(self.get(LAST_VALUE)?).ok()
+    }
+
+    pub fn set_last_value(&mut self, topic: &str) -> std::io::Result<()> {
+        self.insert(LAST_VALUE, topic.as_bytes())
+    }
+
+    /// The schema version of the payload, if the sender tagged one
+    pub fn schema_version(&self) -> Option<u16> {
+        self.get(SCHEMA_VERSION).and_then(|value| value.try_into().ok()).map(u16::from_le_bytes)
+    }
+
+    pub fn set_schema_version(&mut self, version: u16) {
+        self.0.insert(SCHEMA_VERSION, version.to_le_bytes().to_vec());
+    }
+
+    /// The sender's uptime and counters, on heartbeats
+    pub fn liveness(&self) -> Option<Liveness> {
+        Liveness::decode(self.get(LIVENESS)?)
+    }
+
+    pub fn set_liveness(&mut self, liveness: Liveness) {
+        self.0.insert(LIVENESS, liveness.encode().to_vec());
+    }
+
+    /// Where the sender said it is
+    pub fn zone(&self) -> Option<Zone> {
+        Zone::decode(self.get(ZONE)?)
+    }
+
+    pub fn set_zone(&mut self, zone: &Zone) {
+        self.0.insert(ZONE, zone.encode());
+    }
+
+    /// The request a Control message is, or answers
+    pub fn correlation(&self) -> Option<Correlation> {
+        Correlation::decode(self.get(CORRELATION)?)
+    }
+
+    pub fn set_correlation(&mut self, correlation: Correlation) {
+        self.0.insert(CORRELATION, correlation.encode().to_vec());
+    }
+
+    /// The extension block followed by `payload`
+    pub fn prepend_to(&self, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![self.0.len() as u8];
+        for (kind, value) in &self.0 {
+            bytes.extend_from_slice(&[*kind, value.len() as u8]);
+            bytes.extend_from_slice(value);
+        }
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    /// Padding bytes following the payload, per the [`PADDING`] extension
+    pub fn padding(&self) -> usize {
+        self.get(PADDING).and_then(|value| value.try_into().ok()).map_or(0, |len| u16::from_le_bytes(len) as usize)
+    }
+
+    pub fn set_padding(&mut self, len: u16) {
+        self.0.insert(PADDING, len.to_le_bytes().to_vec());
+    }
+
+    /// Separate the extension block from the payload that follows it, less any padding
+    pub fn split(bytes: &[u8]) -> std::io::Result<(Self, &[u8])> {
+        let truncated = || Error::new(ErrorKind::InvalidData, "truncated extension block");
+        let (&count, mut rest) = bytes.split_first().ok_or_else(truncated)?;
+        let mut extensions = Self::new();
+        for _ in 0..count {
+            let [kind, len, tail @ ..] = rest else {
+                return Err(truncated());
+            };
+            let value = tail.get(..*len as usize).ok_or_else(truncated)?;
+            extensions.0.insert(*kind, value.to_vec());
+            rest = &tail[*len as usize..];
+        }
+        let payload_len = rest.len().checked_sub(extensions.padding())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "padding longer than the payload"))?;
+        Ok((extensions, &rest[..payload_len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions_round_trip() {
+        let trace = TraceId::random();
+        let mut extensions = Extensions::new();
+        extensions.set_trace_id(trace);
+        let sent = SendTimestamps { wall_us: 1_700_000_000_000_000, monotonic_us: 42 };
+        extensions.set_send_timestamps(sent);
+        extensions.insert(200, b"from newer firmware".to_vec()).unwrap();
+
+        let bytes = extensions.prepend_to(b"payload");
+        let (parsed, payload) = Extensions::split(&bytes).unwrap();
+        assert_eq!(parsed, extensions);
+        assert_eq!(parsed.trace_id(), Some(trace));
+        assert_eq!(parsed.send_timestamps(), Some(sent));
+        assert_eq!(payload, b"payload");
+        assert_eq!(trace.to_string().parse::<TraceId>().unwrap(), trace);
+
+        assert!(Extensions::split(&bytes[..10]).is_err());
+
+        // Padding after the payload is stripped, and can't exceed it
+        let mut padded = Extensions::new();
+        padded.set_padding(5);
+        let mut bytes = padded.prepend_to(b"payload");
+        bytes.extend_from_slice(&[0; 5]);
+        assert_eq!(Extensions::split(&bytes).unwrap().1, b"payload");
+        padded.set_padding(50);
+        assert!(Extensions::split(&padded.prepend_to(b"payload")).is_err());
+        assert!(extensions.insert(0, b"x".to_vec()).is_err());
+        assert!(extensions.insert(3, vec![0; 256]).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Warm-standby pairs of receivers for high-availability gateways: both
+//! processes receive the fleet's traffic, but only the active one acts on it.
+//!
+//! The active member of a [`FailoverPair`] multicasts a claim every
+//! `claim_interval`. A standby that hears no claim for `lease_timeout` takes
+//! over under a new term. If both end up active, e.g. after a partition heals,
+//! the claim with the higher term wins, then the higher priority, then the
+//! lower node id, and the loser steps down. A standby never preempts a working
+//! active member, so a flapping link can't bounce the role back and forth.
+//!
+//! The active member also sends its duplicate-detection windows and membership
+//! table now and then with [`FailoverPair::handoff`], so the standby takes over
+//! without delivering messages twice or declaring live peers new. Like the
+//! other coordination modules this does no I/O: call [`FailoverPair::poll`] for
+//! what to do next and feed it the failover messages heard with
+//! [`FailoverPair::handle`].
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::membership::{MemberSnapshot, Membership};
+use crate::sequence_stats::{SequenceAnalyzer, SequenceWindow};
+use crate::transport::MulticastSender;
+
+/// Control commands carrying a failover message start with this, followed by the message as JSON
+pub const FAILOVER_COMMAND_PREFIX: &str = "FAILOVER ";
+
+/// How often the active member claims the role by default
+pub const DEFAULT_CLAIM_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a standby waits without a claim before taking over, by default
+pub const DEFAULT_LEASE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The receive-side state the active member hands over
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandoffState {
+    pub sequences: Vec<SequenceWindow>,
+    pub members: Vec<MemberSnapshot>,
+}
+
+impl HandoffState {
+    pub fn capture(sequences: &SequenceAnalyzer, membership: &Membership, now: Instant) -> Self {
+        Self { sequences: sequences.windows(), members: membership.snapshot(now) }
+    }
+
+    /// Merge into the standby's own tables, which it has kept warm meanwhile
+    pub fn restore(&self, sequences: &mut SequenceAnalyzer, membership: &mut Membership, now: Instant) {
+        for window in &self.sequences {
+            sequences.restore(window);
+        }
+        membership.restore(&self.members, now);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum FailoverMessage {
+    /// `node` is the active member of `pair` for `term`
+    Claim { pair: String, node: u32, term: u64, priority: u8 },
+    Handoff { pair: String, node: u32, term: u64, state: HandoffState },
+}
+
+impl FailoverMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", FAILOVER_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`FailoverMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(FAILOVER_COMMAND_PREFIX)?).ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailoverRole {
+    Active,
+    Standby,
+}
+
+/// What the caller should do next
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailoverStep {
+    /// Multicast this message, then poll again
+    Send(FailoverMessage),
+    /// This process is now active for `term`: start acting on traffic
+    Promoted { term: u64 },
+    /// Nothing to do until this time, or until a failover message arrives
+    Wait(Instant),
+}
+
+/// What a message from the other member changed
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailoverEvent {
+    /// Another member won the role: stop acting on traffic
+    Demoted { by: u32, term: u64 },
+    /// State from the active member, to restore into the standby's tables
+    Handoff(HandoffState),
+}
+
+/// This process's half of a warm-standby pair
+#[derive(Debug, Clone)]
+pub struct FailoverPair {
+    pair: String,
+    node: u32,
+    priority: u8,
+    claim_interval: Duration,
+    lease_timeout: Duration,
+    role: FailoverRole,
+    term: u64,
+    /// The active member we last heard claim the role
+    active: Option<u32>,
+    /// Standby: when the lease runs out. Active: when to claim next.
+    deadline: Instant,
+}
+
+impl FailoverPair {
+    /// Starts as standby, so a restarted member doesn't take the role from a working one
+    pub fn new(pair: impl Into<String>, node: u32, now: Instant) -> Self {
+        Self {
+            pair: pair.into(),
+            node,
+            priority: 0,
+            claim_interval: DEFAULT_CLAIM_INTERVAL,
+            lease_timeout: DEFAULT_LEASE_TIMEOUT,
+            role: FailoverRole::Standby,
+            term: 0,
+            active: None,
+            deadline: now + DEFAULT_LEASE_TIMEOUT,
+        }
+    }
+
+    /// Wins ties with lower-priority members when both claim the same term
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The lease timeout should be a few claim intervals, so one lost claim doesn't cause a takeover
+    pub fn with_timing(mut self, claim_interval: Duration, lease_timeout: Duration) -> Self {
+        self.deadline = self.deadline - self.lease_timeout + lease_timeout;
+        self.claim_interval = claim_interval;
+        self.lease_timeout = lease_timeout;
+        self
+    }
+
+    pub fn role(&self) -> FailoverRole {
+        self.role
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.role == FailoverRole::Active
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// The member acting on traffic, as far as this one knows
+    pub fn active(&self) -> Option<u32> {
+        if self.is_active() { Some(self.node) } else { self.active }
+    }
+
+    /// A handoff of `state` to the standby; None while not active
+    pub fn handoff(&self, state: HandoffState) -> Option<FailoverMessage> {
+        self.is_active().then(|| FailoverMessage::Handoff { pair: self.pair.clone(), node: self.node, term: self.term, state })
+    }
+
+    /// What to do next
+    pub fn poll(&mut self, now: Instant) -> FailoverStep {
+        if now < self.deadline {
+            return FailoverStep::Wait(self.deadline);
+        }
+        match self.role {
+            FailoverRole::Active => {
+                self.deadline = now + self.claim_interval;
+                FailoverStep::Send(self.claim())
+            }
+            FailoverRole::Standby => {
+                self.role = FailoverRole::Active;
+                self.term += 1;
+                self.active = None;
+                // Claim straight away, so the other member learns of the takeover
+                self.deadline = now;
+                FailoverStep::Promoted { term: self.term }
+            }
+        }
+    }
+
+    /// Take in a message from `sender_id`; messages for other pairs, or whose
+    /// sender isn't the node they name, are ignored
+    pub fn handle(&mut self, sender_id: u32, message: &FailoverMessage, now: Instant) -> Option<FailoverEvent> {
+        match message {
+            FailoverMessage::Claim { pair, node, term, priority } if *pair == self.pair && *node == sender_id && *node != self.node => {
+                if self.is_active() && !self.outranked_by(*node, *term, *priority) {
+                    // Our next claim makes the other member step down
+                    self.deadline = now;
+                    return None;
+                }
+                self.term = self.term.max(*term);
+                self.active = Some(*node);
+                self.deadline = now + self.lease_timeout;
+                if self.is_active() {
+                    self.role = FailoverRole::Standby;
+                    return Some(FailoverEvent::Demoted { by: *node, term: *term });
+                }
+                None
+            }
+            FailoverMessage::Handoff { pair, node, term, state } if *pair == self.pair && *node == sender_id => {
+                (!self.is_active() && self.active == Some(*node) && *term >= self.term).then(|| FailoverEvent::Handoff(state.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn claim(&self) -> FailoverMessage {
+        FailoverMessage::Claim { pair: self.pair.clone(), node: self.node, term: self.term, priority: self.priority }
+    }
+
+    fn outranked_by(&self, node: u32, term: u64, priority: u8) -> bool {
+        (term, priority, std::cmp::Reverse(node)) > (self.term, self.priority, std::cmp::Reverse(self.node))
+    }
+}
+
+/// Multicast a failover message as a Control command
+pub async fn send(sender: &mut MulticastSender, message: &FailoverMessage) -> crate::error::Result<()> {
+    sender.send_control(&message.control_command()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{FleetMsgHeader, MessageType};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn claim(pair: &mut FailoverPair, now: Instant) -> FailoverMessage {
+        match pair.poll(now) {
+            FailoverStep::Send(message) => message,
+            other => panic!("expected a claim, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_standby_takes_over_and_split_brain_resolves() {
+        let start = Instant::now();
+        let mut a = FailoverPair::new("gw", 1, start).with_priority(5);
+        let mut b = FailoverPair::new("gw", 2, start + SECOND);
+
+        assert_eq!(a.poll(start), FailoverStep::Wait(start + 3 * SECOND));
+        assert_eq!(a.poll(start + 3 * SECOND), FailoverStep::Promoted { term: 1 });
+        let first = claim(&mut a, start + 3 * SECOND);
+        assert_eq!(b.handle(2, &first, start + 3 * SECOND), None, "forged sender");
+        assert_eq!(b.handle(1, &first, start + 3 * SECOND), None);
+        assert_eq!((b.role(), b.active(), b.term()), (FailoverRole::Standby, Some(1), 1));
+        // Claims keep the standby waiting; it takes over once they stop
+        assert_eq!(b.poll(start + 5 * SECOND), FailoverStep::Wait(start + 6 * SECOND));
+        assert_eq!(b.poll(start + 6 * SECOND), FailoverStep::Promoted { term: 2 });
+
+        // `a` was only cut off; when it returns, the higher term wins despite its priority
+        let stale = claim(&mut a, start + 7 * SECOND);
+        assert_eq!(b.handle(1, &stale, start + 7 * SECOND), None);
+        let newer = claim(&mut b, start + 7 * SECOND);
+        assert_eq!(a.handle(2, &newer, start + 7 * SECOND), Some(FailoverEvent::Demoted { by: 2, term: 2 }));
+        assert!(b.is_active() && !a.is_active());
+        assert_eq!(a.handoff(HandoffState::default()), None);
+    }
+
+    #[test]
+    fn test_handoff_restores_dedup_and_membership() {
+        let now = Instant::now();
+        let (mut sequences, mut membership) = (SequenceAnalyzer::new(), Membership::new(1, 3 * SECOND, now));
+        for sequence in [10, 11, 13] {
+            let header = FleetMsgHeader::new(MessageType::Data, 7, sequence, 0);
+            sequences.observe(&header, b"");
+            membership.observe(&header, b"", now);
+        }
+        let mut active = FailoverPair::new("gw", 1, now);
+        active.poll(now + 3 * SECOND);
+        let mut standby = FailoverPair::new("gw", 2, now);
+        standby.handle(1, &claim(&mut active, now + 3 * SECOND), now + 3 * SECOND);
+
+        let message = active.handoff(HandoffState::capture(&sequences, &membership, now)).unwrap();
+        let Some(FailoverEvent::Handoff(state)) = standby.handle(1, &FailoverMessage::from_control(&message.control_command()).unwrap(), now) else {
+            panic!("handoff not accepted");
+        };
+        // The standby heard 14 itself but missed the rest
+        let (mut standby_sequences, mut standby_membership) = (SequenceAnalyzer::new(), Membership::new(2, 3 * SECOND, now));
+        standby_sequences.observe(&FleetMsgHeader::new(MessageType::Data, 7, 14, 0), b"");
+        state.restore(&mut standby_sequences, &mut standby_membership, now);
+        for sequence in [11, 13, 12] {
+            standby_sequences.observe(&FleetMsgHeader::new(MessageType::Data, 7, sequence, 0), b"");
+        }
+        let stats = standby_sequences.get(7).unwrap();
+        assert_eq!((stats.duplicates, stats.reordered), (2, 1));
+        assert_eq!(standby_membership.get(7).unwrap().last_sequence, 13);
+    }
+}
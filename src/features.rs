@@ -0,0 +1,442 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{Error, ErrorKind};
+use std::ops::{BitAnd, BitOr};
+#[cfg(feature = "crypto")]
+use zeroize::Zeroizing;
+
+use crate::alloc_counter::{self, Subsystem};
+#[cfg(feature = "crypto")]
+use crate::crypto::{self, AEAD_KEY_LEN};
+#[cfg(feature = "compression-dict")]
+use crate::dictionary::{CompressionDictionary, DICTIONARY_PAYLOAD_LIMIT};
+use crate::receiver::DEFAULT_MAX_MESSAGE_LEN;
+#[cfg(feature = "crypto")]
+use crate::session::SessionCiphers;
+use crate::transport::{FleetMsgHeader, MessageType};
+
+#[cfg(feature = "crypto")]
+const NONCE_LEN: usize = crypto::AEAD_NONCE_LEN;
+const CRC_LEN: usize = 4;
+/// Payloads shorter than this are never worth compressing
+#[cfg(feature = "compression")]
+const MIN_COMPRESS_LEN: usize = 64;
+/// With a dictionary, even this short a payload can shrink
+#[cfg(feature = "compression-dict")]
+const MIN_DICT_COMPRESS_LEN: usize = 8;
+
+/// Optional protocol features, announced in heartbeats and flagged per message
+/// in the high nibble of `msg_type`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProtocolFeatures(u8);
+
+impl ProtocolFeatures {
+    pub const NONE: Self = Self(0);
+    /// Deflate-compressed payload
+    pub const COMPRESSION: Self = Self(0x1);
+    /// CRC32 of the payload appended as a 4-byte trailer
+    pub const CRC32: Self = Self(0x2);
+    /// ChaCha20-Poly1305 with a pre-shared fleet key
+    pub const ENCRYPTION: Self = Self(0x4);
+    /// Payload starts with a block of TLV [`Extensions`](crate::extensions::Extensions)
+    pub const EXTENSIONS: Self = Self(0x8);
+
+    const ALL: u8 = 0xF;
+
+    /// Unknown bits (from newer firmware) are dropped
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::ALL)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        [(Self::COMPRESSION, "compression"), (Self::CRC32, "crc32"), (Self::ENCRYPTION, "encryption"), (Self::EXTENSIONS, "extensions")]
+            .into_iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name)
+            .collect()
+    }
+}
+
+impl BitOr for ProtocolFeatures {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for ProtocolFeatures {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// Applies and strips the optional features this node supports.
+///
+/// Compression, CRC32 and extensions need no configuration, so every node
+/// supports them by default; encryption is added by giving the fleet key.
+/// Extensions are not applied by the codec itself, only announced. With
+/// `SessionCiphers`, messages to and from peers holding a session are
+/// encrypted with its keys instead of the fleet key.
+///
+/// With a [`CompressionDictionary`](crate::dictionary::CompressionDictionary)
+/// (`compression-dict` feature), small payloads are compressed with it instead
+/// of deflated.
+///
+/// Compression and encryption are only available with the `compression` and
+/// `crypto` cargo features. Without them the codec never offers them, so
+/// peers negotiate them away, and payloads flagged with them are refused.
+///
+/// Features made required with `with_required` are a floor under negotiation:
+/// Data, Control and application messages always get them, whatever peers
+/// announce, and such messages arriving without them are refused.
+#[derive(Clone)]
+pub struct FeatureCodec {
+    supported: ProtocolFeatures,
+    required: ProtocolFeatures,
+    #[cfg(feature = "crypto")]
+    fleet_key: Option<Zeroizing<[u8; AEAD_KEY_LEN]>>,
+    #[cfg(feature = "crypto")]
+    sessions: Option<SessionCiphers>,
+    /// The last one compresses; all of them decompress
+    #[cfg(feature = "compression-dict")]
+    dictionaries: Vec<CompressionDictionary>,
+}
+
+impl Default for FeatureCodec {
+    fn default() -> Self {
+        let compression = if cfg!(feature = "compression") { ProtocolFeatures::COMPRESSION } else { ProtocolFeatures::NONE };
+        let mut codec = Self::plain();
+        codec.supported = compression | ProtocolFeatures::CRC32 | ProtocolFeatures::EXTENSIONS;
+        codec
+    }
+}
+
+impl std::fmt::Debug for FeatureCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FeatureCodec")
+            .field("supported", &self.supported)
+            .field("required", &self.required)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FeatureCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Behave like firmware that predates optional features
+    pub fn plain() -> Self {
+        Self {
+            supported: ProtocolFeatures::NONE,
+            required: ProtocolFeatures::NONE,
+            #[cfg(feature = "crypto")]
+            fleet_key: None,
+            #[cfg(feature = "crypto")]
+            sessions: None,
+            #[cfg(feature = "compression-dict")]
+            dictionaries: Vec::new(),
+        }
+    }
+
+    /// Support encryption with a key shared by the fleet
+    #[cfg(feature = "crypto")]
+    pub fn with_encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.fleet_key = Some(Zeroizing::new(key));
+        self.supported = self.supported | ProtocolFeatures::ENCRYPTION;
+        self
+    }
+
+    /// Encrypt traffic with peers that have an authenticated session using
+    /// that session's keys, rekeyed as the sessions' policy says
+    #[cfg(feature = "crypto")]
+    pub fn with_sessions(mut self, sessions: SessionCiphers) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Compress payloads up to `DICTIONARY_PAYLOAD_LIMIT` bytes with
+    /// `dictionary`, and decompress payloads compressed with it. A codec given
+    /// several still reads senders on the earlier ones; the last compresses.
+    #[cfg(feature = "compression-dict")]
+    pub fn with_dictionary(mut self, dictionary: CompressionDictionary) -> Self {
+        self.dictionaries.retain(|known| known.id() != dictionary.id());
+        self.dictionaries.push(dictionary);
+        self
+    }
+
+    /// Never send Data, Control or application messages without `required`,
+    /// nor accept them without it, e.g. so a forged heartbeat announcing no
+    /// encryption can't talk the fleet into plaintext. Only CRC32 and
+    /// encryption can be required, and only once supported.
+    pub fn with_required(mut self, required: ProtocolFeatures) -> std::io::Result<Self> {
+        let enforceable = ProtocolFeatures::CRC32 | ProtocolFeatures::ENCRYPTION;
+        if !enforceable.contains(required) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("only CRC32 and encryption can be required, not {:?}", required)));
+        }
+        if !self.supported.contains(required) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{:?} can't be required before it is supported", required)));
+        }
+        self.required = required;
+        Ok(self)
+    }
+
+    pub fn supported(&self) -> ProtocolFeatures {
+        self.supported
+    }
+
+    pub fn required(&self) -> ProtocolFeatures {
+        self.required
+    }
+
+    /// The features a message of `msg_type` must carry. Heartbeats, goodbyes
+    /// and digests stay readable to every node, since negotiation starts there.
+    pub(crate) fn required_for(&self, msg_type: MessageType) -> ProtocolFeatures {
+        match msg_type {
+            MessageType::Data | MessageType::Control | MessageType::Application(_) => self.required,
+            _ => ProtocolFeatures::NONE,
+        }
+    }
+
+    /// Apply the `wanted` features this codec supports; returns the ones actually used
+    /// (compression is skipped when it wouldn't shrink the payload)
+    pub fn encode(&self, wanted: ProtocolFeatures, payload: &[u8]) -> std::io::Result<(ProtocolFeatures, Vec<u8>)> {
+        self.encode_for(None, wanted, payload)
+    }
+
+    /// Like `encode`, for a payload going to `peer` alone: if we hold a
+    /// session with it, the payload is always encrypted under that session.
+    /// Required features are applied whether wanted or not.
+    pub fn encode_for(&self, peer: Option<u32>, wanted: ProtocolFeatures, payload: &[u8]) -> std::io::Result<(ProtocolFeatures, Vec<u8>)> {
+        let _scope = alloc_counter::scope(Subsystem::Codec);
+        let wanted = (wanted | self.required) & self.supported;
+        let mut used = ProtocolFeatures::NONE;
+        let mut payload = payload.to_vec();
+
+        #[cfg(feature = "compression")]
+        if wanted.contains(ProtocolFeatures::COMPRESSION)
+            && let Some(compressed) = self.compress(&payload)?
+            && compressed.len() < payload.len()
+        {
+            payload = compressed;
+            used = used | ProtocolFeatures::COMPRESSION;
+        }
+        #[cfg(feature = "crypto")]
+        if let Some(sealed) = self.seal(peer, wanted, &payload)? {
+            payload = sealed;
+            used = used | ProtocolFeatures::ENCRYPTION;
+        }
+        #[cfg(not(feature = "crypto"))]
+        let _ = peer;
+        if wanted.contains(ProtocolFeatures::CRC32) {
+            let crc = crc32fast::hash(&payload);
+            payload.extend_from_slice(&crc.to_le_bytes());
+            used = used | ProtocolFeatures::CRC32;
+        }
+
+        Ok((used, payload))
+    }
+
+    /// `payload` compressed with the dictionary if it is small enough for
+    /// one, else deflated; None if it is too short to bother
+    #[cfg(feature = "compression")]
+    fn compress(&self, payload: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        #[cfg(feature = "compression-dict")]
+        if let Some(dictionary) = self.dictionaries.last()
+            && (MIN_DICT_COMPRESS_LEN..=DICTIONARY_PAYLOAD_LIMIT).contains(&payload.len())
+        {
+            return dictionary.compress(payload).map(Some);
+        }
+        Ok((payload.len() >= MIN_COMPRESS_LEN).then(|| miniz_oxide::deflate::compress_to_vec(payload, 6)))
+    }
+
+    /// How many bytes `encode_for` adds to a payload it encrypts for `peer`
+    /// (nonce, tag and any CRC trailer), or None if it wouldn't encrypt it
+    #[cfg(feature = "crypto")]
+    pub(crate) fn sealed_overhead(&self, peer: Option<u32>, wanted: ProtocolFeatures) -> Option<usize> {
+        let wanted = wanted & self.supported;
+        let session = peer.is_some_and(|peer| self.sessions.as_ref().is_some_and(|sessions| sessions.has_session(peer)));
+        let encrypted = session || (self.fleet_key.is_some() && wanted.contains(ProtocolFeatures::ENCRYPTION));
+        let crc = if wanted.contains(ProtocolFeatures::CRC32) { CRC_LEN } else { 0 };
+        encrypted.then_some(NONCE_LEN + crypto::AEAD_TAG_LEN + crc)
+    }
+
+    /// Without the `crypto` feature nothing is ever encrypted
+    #[cfg(not(feature = "crypto"))]
+    pub(crate) fn sealed_overhead(&self, _peer: Option<u32>, _wanted: ProtocolFeatures) -> Option<usize> {
+        None
+    }
+
+    /// How many bytes `encode_for` adds to a payload for `peer`, at most
+    pub(crate) fn encoded_overhead(&self, peer: Option<u32>, wanted: ProtocolFeatures) -> usize {
+        let crc = if (wanted & self.supported).contains(ProtocolFeatures::CRC32) { CRC_LEN } else { 0 };
+        self.sealed_overhead(peer, wanted).unwrap_or(crc)
+    }
+
+    /// `payload` encrypted under `peer`'s session if we hold one, else under
+    /// the fleet key if encryption is wanted; None if it stays in the clear
+    #[cfg(feature = "crypto")]
+    fn seal(&self, peer: Option<u32>, wanted: ProtocolFeatures, payload: &[u8]) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(peer) = peer
+            && let Some(sessions) = self.sessions.as_ref().filter(|sessions| sessions.has_session(peer))
+        {
+            return sessions.seal(peer, payload).map(Some);
+        }
+        let Some(key) = self.fleet_key.as_ref().filter(|_| wanted.contains(ProtocolFeatures::ENCRYPTION)) else {
+            return Ok(None);
+        };
+        let nonce: [u8; NONCE_LEN] = crypto::random_bytes()?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&crypto::provider().seal(key, &nonce, payload)?);
+        Ok(Some(sealed))
+    }
+
+    #[cfg(feature = "crypto")]
+    fn open(&self, sender_id: u32, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        let session = self.sessions.as_ref().filter(|sessions| sessions.has_session(sender_id));
+        match (session.map(|sessions| sessions.open(sender_id, payload)), &self.fleet_key) {
+            (Some(Ok(opened)), _) => Ok(opened),
+            (Some(Err(e)), None) => Err(e),
+            // Multicasts from a peer we hold a session with still use the fleet key
+            (_, Some(key)) => {
+                if payload.len() < NONCE_LEN {
+                    return Err(Error::new(ErrorKind::InvalidData, "payload too short for nonce"));
+                }
+                let (nonce, sealed) = payload.split_at(NONCE_LEN);
+                crypto::provider().open(key, nonce.try_into().unwrap(), sealed)
+            }
+            (None, None) => Err(Error::new(ErrorKind::Unsupported, "encrypted payload but no fleet key")),
+        }
+    }
+
+    #[cfg(not(feature = "crypto"))]
+    fn open(&self, _sender_id: u32, _payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        Err(Error::new(ErrorKind::Unsupported, "encrypted payload but built without the crypto feature"))
+    }
+
+    /// Undo whatever features the header says were applied
+    pub fn decode(&self, header: &FleetMsgHeader, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.decode_with_limit(header, payload, DEFAULT_MAX_MESSAGE_LEN)
+    }
+
+    /// Like `decode`, refusing to inflate the payload past `max_len` whatever the sender claims
+    pub fn decode_with_limit(&self, header: &FleetMsgHeader, payload: &[u8], max_len: usize) -> std::io::Result<Vec<u8>> {
+        self.decode_borrowed(header, payload, max_len).map(Cow::into_owned)
+    }
+
+    /// Like `decode_with_limit`, copying the payload only if it has to be
+    /// decrypted or decompressed; a CRC32 trailer is just checked and cut off
+    pub fn decode_borrowed<'a>(&self, header: &FleetMsgHeader, payload: &'a [u8], max_len: usize) -> std::io::Result<Cow<'a, [u8]>> {
+        let _scope = alloc_counter::scope(Subsystem::Codec);
+        let features = header.features();
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+        let required = self.required_for(header.message_type());
+        if !features.contains(required) {
+            let reason = format!("message from {} lacks required {:?}", header.sender_id.get(), required);
+            return Err(Error::new(ErrorKind::PermissionDenied, reason));
+        }
+        let mut body = payload;
+
+        if features.contains(ProtocolFeatures::CRC32) {
+            let body_len = body.len().checked_sub(CRC_LEN).ok_or_else(|| invalid("payload too short for CRC32"))?;
+            let (checked, trailer) = body.split_at(body_len);
+            if crc32fast::hash(checked) != u32::from_le_bytes(trailer.try_into().unwrap()) {
+                return Err(invalid("CRC32 mismatch"));
+            }
+            body = checked;
+        }
+        let mut payload = Cow::Borrowed(body);
+        if features.contains(ProtocolFeatures::ENCRYPTION) {
+            payload = Cow::Owned(self.open(header.sender_id.get(), &payload)?);
+        }
+        if features.contains(ProtocolFeatures::COMPRESSION) {
+            payload = Cow::Owned(self.inflate(&payload, max_len)?);
+        }
+
+        Ok(payload)
+    }
+
+    #[cfg(feature = "compression")]
+    fn inflate(&self, payload: &[u8], max_len: usize) -> std::io::Result<Vec<u8>> {
+        #[cfg(feature = "compression-dict")]
+        if let Some(id) = CompressionDictionary::frame_id(payload) {
+            let dictionary = self.dictionaries.iter().find(|dictionary| dictionary.id() == id).ok_or_else(|| {
+                Error::new(ErrorKind::Unsupported, format!("payload compressed with dictionary {}, which this node doesn't have", id))
+            })?;
+            return dictionary.decompress(payload, max_len);
+        }
+        miniz_oxide::inflate::decompress_to_vec_with_limit(payload, max_len)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "payload does not decompress"))
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn inflate(&self, _payload: &[u8], _max_len: usize) -> std::io::Result<Vec<u8>> {
+        Err(Error::new(ErrorKind::Unsupported, "compressed payload but built without the compression feature"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(feature = "crypto", feature = "compression"))]
+    fn test_features_round_trip() {
+        let key = [7u8; 32];
+        let codec = FeatureCodec::new().with_encryption_key(key);
+        let payload = b"telemetry ".repeat(20);
+
+        let all = ProtocolFeatures::COMPRESSION | ProtocolFeatures::CRC32 | ProtocolFeatures::ENCRYPTION;
+        let (used, encoded) = codec.encode(all, &payload).unwrap();
+        assert_eq!(used, all);
+        let header = FleetMsgHeader::new(MessageType::Data, 1, 0, encoded.len() as u16).with_features(used);
+        assert_eq!(header.message_type(), MessageType::Data);
+        assert_eq!(codec.decode(&header, &encoded).unwrap(), payload);
+
+        // A corrupted payload fails the CRC; a node without the key can't read it
+        let mut corrupted = encoded.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(codec.decode(&header, &corrupted).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(FeatureCodec::new().decode(&header, &encoded).unwrap_err().kind(), ErrorKind::Unsupported);
+
+        // Short payloads aren't compressed, and a plain codec applies nothing
+        let (used, _) = codec.encode(ProtocolFeatures::COMPRESSION, b"hi").unwrap();
+        assert!(used.is_empty());
+        assert_eq!(FeatureCodec::plain().encode(all, &payload).unwrap(), (ProtocolFeatures::NONE, payload));
+    }
+
+    #[test]
+    #[cfg(feature = "crypto")]
+    fn test_required_features_are_a_floor() {
+        let codec = FeatureCodec::new().with_encryption_key([7u8; 32]).with_required(ProtocolFeatures::ENCRYPTION).unwrap();
+        let (used, encoded) = codec.encode(ProtocolFeatures::NONE, b"telemetry").unwrap();
+        assert!(used.contains(ProtocolFeatures::ENCRYPTION));
+        let header = FleetMsgHeader::new(MessageType::Data, 1, 0, encoded.len() as u16).with_features(used);
+        assert_eq!(codec.decode(&header, &encoded).unwrap(), b"telemetry");
+
+        // Plaintext Data is refused, a plaintext heartbeat isn't
+        let plain = FleetMsgHeader::new(MessageType::Data, 1, 0, 9);
+        assert_eq!(codec.decode(&plain, b"telemetry").unwrap_err().kind(), ErrorKind::PermissionDenied);
+        let heartbeat = FleetMsgHeader::new(MessageType::Heartbeat, 1, 0, 9);
+        assert_eq!(codec.decode(&heartbeat, b"telemetry").unwrap(), b"telemetry");
+
+        // Only what the codec can enforce can be required
+        assert!(FeatureCodec::new().with_required(ProtocolFeatures::ENCRYPTION).is_err());
+        assert!(FeatureCodec::new().with_required(ProtocolFeatures::COMPRESSION).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Messages too large for one datagram, split by the sender and put back
+//! together by the receiver.
+//!
+//! A sender splits a payload that wouldn't fit its maximum datagram length
+//! (by default one Ethernet MTU) into fragments, each a complete message of
+//! its own with its own sequence number and a [`FRAGMENT`](crate::extensions::FRAGMENT)
+//! extension giving the message's fragment id (the sequence number of its
+//! first fragment), the fragment's index and the fragment count. Fragments are
+//! encoded (compressed, encrypted) one by one, so each is checked on arrival
+//! like any other message. The [`Reassembler`] collects them in whatever order
+//! they arrive and hands the message on once the last one is in; messages
+//! still incomplete after the reassembly timeout are dropped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::protocol::MAX_FRAGMENTS;
+use crate::receiver::{DEFAULT_MAX_MESSAGE_LEN, Delivery};
+use crate::extensions::FRAGMENT;
+
+/// How long the fragments of a message have to arrive by default
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Incomplete messages held at once, by default; the oldest is dropped to make room
+pub const DEFAULT_MAX_PENDING: usize = 64;
+
+/// Where a fragment belongs, as carried in the [`FRAGMENT`] extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment {
+    /// Sequence number of the message's first fragment
+    pub id: u16,
+    pub index: u16,
+    pub count: u16,
+}
+
+impl Fragment {
+    pub fn encode(&self) -> [u8; 6] {
+        let mut bytes = [0u8; 6];
+        bytes[..2].copy_from_slice(&self.id.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.index.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.count.to_le_bytes());
+        bytes
+    }
+
+    /// None if the value is malformed or describes an impossible fragment
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; 6] = bytes.try_into().ok()?;
+        let field = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let fragment = Self { id: field(0), index: field(2), count: field(4) };
+        (fragment.count > 0 && fragment.index < fragment.count && fragment.count as usize <= MAX_FRAGMENTS).then_some(fragment)
+    }
+}
+
+/// A message with some of its fragments in
+#[derive(Debug)]
+struct Partial {
+    count: u16,
+    parts: BTreeMap<u16, Vec<u8>>,
+    bytes: usize,
+    started: Instant,
+    /// Fragment 0, once it is in, for the header and extensions of the message
+    first: Option<Delivery>,
+}
+
+/// Puts fragmented messages back together; see the [module docs](self)
+#[derive(Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_message_len: usize,
+    max_pending: usize,
+    pending: BTreeMap<(u32, u16), Partial>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            max_pending: DEFAULT_MAX_PENDING,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Drop messages that would reassemble to more than this
+    pub fn with_max_message_len(mut self, len: usize) -> Self {
+        self.max_message_len = len;
+        self
+    }
+
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// Messages with fragments still missing
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Take in a delivered message. Whole messages come straight back; a
+    /// fragment is held until its message is complete, which is then returned
+    /// with the header and extensions of its first fragment.
+    pub fn push(&mut self, mut delivery: Delivery, now: Instant) -> Option<Delivery> {
+        let Some(fragment) = delivery.extensions.fragment() else {
+            // A malformed fragment extension can't be reassembled, and isn't a whole message either
+            return delivery.extensions.get(FRAGMENT).is_none().then_some(delivery);
+        };
+        let key = (delivery.header.sender_id.get(), fragment.id);
+        if self.pending.get(&key).is_some_and(|partial| partial.count != fragment.count) {
+            // The sequence numbers wrapped around to a new message with the same id
+            self.pending.remove(&key);
+        }
+        if !self.pending.contains_key(&key) && self.pending.len() >= self.max_pending {
+            let oldest = self.pending.iter().min_by_key(|(_, partial)| partial.started).map(|(key, _)| *key);
+            self.pending.retain(|key, _| Some(*key) != oldest);
+        }
+
+        let partial = self.pending.entry(key).or_insert_with(|| Partial {
+            count: fragment.count,
+            parts: BTreeMap::new(),
+            bytes: 0,
+            started: now,
+            first: None,
+        });
+        if partial.parts.contains_key(&fragment.index) {
+            return None;
+        }
+        partial.bytes += delivery.payload.len();
+        if partial.bytes > self.max_message_len {
+            self.pending.remove(&key);
+            return None;
+        }
+        partial.parts.insert(fragment.index, std::mem::take(&mut delivery.payload));
+        if fragment.index == 0 {
+            partial.first = Some(delivery);
+        }
+        if partial.parts.len() < partial.count as usize {
+            return None;
+        }
+
+        let partial = self.pending.remove(&key)?;
+        let mut message = partial.first?;
+        message.extensions.remove(FRAGMENT);
+        message.payload = partial.parts.into_values().flatten().collect();
+        Some(message)
+    }
+
+    /// Drop messages whose fragments didn't all arrive in time; returns how many
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, partial| now.saturating_duration_since(partial.started) < self.timeout);
+        before - self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::Extensions;
+    use crate::transport::{FleetMsgHeader, MessageType};
+
+    fn fragment(sender_id: u32, id: u16, index: u16, count: u16, payload: &[u8]) -> Delivery {
+        let mut extensions = Extensions::new();
+        extensions.set_fragment(Fragment { id, index, count });
+        Delivery {
+            header: FleetMsgHeader::new(MessageType::Data, sender_id, id.wrapping_add(index), payload.len() as u16),
+            extensions,
+            payload: payload.to_vec(),
+            addr: "10.0.0.1:5000".parse().unwrap(),
+            issues: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_fragments_reassemble() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        // Two senders' messages interleaved, fragments out of order and one duplicated
+        assert!(reassembler.push(fragment(1, 40, 2, 3, b"fox"), now).is_none());
+        assert!(reassembler.push(fragment(2, 40, 1, 2, b"world"), now).is_none());
+        assert!(reassembler.push(fragment(1, 40, 0, 3, b"quick "), now).is_none());
+        assert!(reassembler.push(fragment(1, 40, 2, 3, b"fox"), now).is_none());
+        let message = reassembler.push(fragment(1, 40, 1, 3, b"brown "), now).unwrap();
+        assert_eq!(message.payload, b"quick brown fox");
+        assert_eq!((message.header.sequence.get(), message.extensions.fragment()), (40, None));
+        let message = reassembler.push(fragment(2, 40, 0, 2, b"hello "), now).unwrap();
+        assert_eq!(message.payload, b"hello world");
+        assert_eq!(reassembler.pending(), 0);
+
+        let mut whole = fragment(3, 0, 0, 1, b"whole");
+        whole.extensions = Extensions::new();
+        assert_eq!(reassembler.push(whole, now).unwrap().payload, b"whole");
+        assert_eq!(Fragment::decode(&Fragment { id: 1, index: 2, count: 2 }.encode()), None);
+    }
+
+    #[test]
+    fn test_incomplete_and_oversized_messages_are_dropped() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new().with_max_message_len(10).with_max_pending(2);
+        reassembler.push(fragment(1, 7, 0, 2, b"first"), now);
+        assert_eq!(reassembler.expire(now + Duration::from_secs(1)), 0);
+        assert_eq!(reassembler.expire(now + DEFAULT_REASSEMBLY_TIMEOUT), 1);
+        // The missing fragment arriving late starts over rather than completing anything
+        assert!(reassembler.push(fragment(1, 7, 1, 2, b"second"), now).is_none());
+
+        reassembler.push(fragment(2, 1, 0, 2, b"123456"), now);
+        assert!(reassembler.push(fragment(2, 1, 1, 2, b"789012"), now).is_none());
+        assert_eq!(reassembler.pending(), 1);
+        // Room for two incomplete messages; the oldest makes way for a third
+        reassembler.push(fragment(3, 1, 0, 2, b"a"), now + Duration::from_millis(1));
+        reassembler.push(fragment(4, 1, 0, 2, b"b"), now + Duration::from_millis(2));
+        assert_eq!(reassembler.pending(), 2);
+        assert!(reassembler.push(fragment(1, 7, 0, 2, b"first"), now).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! WebSocket gateway for browser-based tools: every received message is pushed
+//! to connected clients as JSON (header fields plus the payload in base64), and
+//! clients can send Data and Control messages back through the gateway's sender.
+//!
+//! Clients receive
+//! `{"type":"message","from":"10.0.0.7:12345","header":{...},"payload":"aGk="}`
+//! and may send `{"type":"send","msg_type":"control","payload":"U1RPUA=="}`;
+//! a send that fails is answered with `{"type":"error","message":"..."}`.
+
+use async_std::channel::{self, Sender};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use async_tungstenite::tungstenite::http::StatusCode;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::future::{Either, select};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::journal;
+use crate::transport::{FleetMsgHeader, MessageType, MulticastSender};
+
+/// Messages queued for one client before it counts as too slow and misses some
+const CLIENT_QUEUE_LEN: usize = 1024;
+
+/// The header fields of a received message, as sent to clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatewayHeader {
+    pub version: u8,
+    /// `Data`, `Control`, ... or `Type <n>` for types this build doesn't know
+    pub msg_type: String,
+    /// Optional-feature flags from the high nibble of the type byte
+    pub features: u8,
+    pub sequence: u16,
+    pub timestamp_us: u64,
+    pub sender_id: u32,
+    pub payload_len: u16,
+}
+
+impl From<&FleetMsgHeader> for GatewayHeader {
+    fn from(header: &FleetMsgHeader) -> Self {
+        Self {
+            version: header.version,
+            msg_type: journal::message_type_name(header.msg_type & FleetMsgHeader::MSG_TYPE_MASK),
+            features: header.msg_type >> 4,
+            sequence: header.sequence.get(),
+            timestamp_us: header.timestamp_micros(),
+            sender_id: header.sender_id.get(),
+            payload_len: header.payload_len.get(),
+        }
+    }
+}
+
+/// What the gateway pushes to clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GatewayEvent {
+    Message { from: String, header: GatewayHeader, payload: String },
+    Error { message: String },
+}
+
+/// Message types clients may send
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayMessageType {
+    Data,
+    Control,
+}
+
+/// What clients send to the gateway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GatewayCommand {
+    Send { msg_type: GatewayMessageType, payload: String },
+}
+
+impl GatewayEvent {
+    pub fn message(header: &FleetMsgHeader, payload: &[u8], from: SocketAddr) -> Self {
+        GatewayEvent::Message { from: from.to_string(), header: header.into(), payload: BASE64.encode(payload) }
+    }
+}
+
+/// Bridges the fleet and browser clients over WebSocket; clones share the same clients and sender
+#[derive(Clone)]
+pub struct WsGateway {
+    clients: Arc<Mutex<Vec<Sender<Arc<str>>>>>,
+    sender: Arc<async_std::sync::Mutex<MulticastSender>>,
+    token: Option<Arc<str>>,
+}
+
+impl std::fmt::Debug for WsGateway {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WsGateway")
+            .field("clients", &self.clients.lock().unwrap().len())
+            .field("authenticated", &self.token.is_some())
+            .finish()
+    }
+}
+
+impl WsGateway {
+    /// Clients' sends go out through `sender`, under its sender id
+    pub fn new(sender: MulticastSender) -> Self {
+        Self { clients: Arc::default(), sender: Arc::new(async_std::sync::Mutex::new(sender)), token: None }
+    }
+
+    /// Only accept clients connecting to `/?token=<token>`
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(Arc::from(token.into()));
+        self
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// A handler for [`start_multicast_rx`](crate::start_multicast_rx) that pushes every message to clients
+    pub fn handler(&self) -> impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static {
+        let gateway = self.clone();
+        move |header, payload, addr| gateway.publish(&header, &payload, addr)
+    }
+
+    /// Push one received message to every client, skipping any whose queue is full
+    pub fn publish(&self, header: &FleetMsgHeader, payload: &[u8], from: SocketAddr) {
+        let json: Arc<str> = serde_json::to_string(&GatewayEvent::message(header, payload, from)).unwrap_or_default().into();
+        self.clients.lock().unwrap().retain(|client| !client.is_closed() && {
+            let _ = client.try_send(json.clone());
+            true
+        });
+    }
+
+    /// Accept clients until the listener fails
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        println!("WebSocket gateway listening on {}", listener.local_addr()?);
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let gateway = self.clone();
+            task::spawn(async move {
+                if let Err(e) = gateway.handle_connection(stream).await {
+                    eprintln!("Gateway connection from {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        request.uri().query().unwrap_or_default().split('&').any(|pair| pair.strip_prefix("token=") == Some(&**token))
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> std::io::Result<()> {
+        // The error type is fixed by tungstenite's callback signature
+        #[allow(clippy::result_large_err)]
+        let check_token = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            if self.is_authorized(request) {
+                return Ok(response);
+            }
+            let mut refusal = ErrorResponse::new(Some("missing or invalid token".to_string()));
+            *refusal.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(refusal)
+        };
+        let ws = async_tungstenite::accept_hdr_async(stream, check_token).await.map_err(std::io::Error::other)?;
+        let (mut outgoing, mut incoming) = ws.split();
+        let (client, queued) = channel::bounded(CLIENT_QUEUE_LEN);
+        self.clients.lock().unwrap().push(client);
+
+        loop {
+            let reply = match select(queued.recv(), incoming.next()).await {
+                Either::Left((Ok(json), _)) => Some(json.to_string()),
+                Either::Right((Some(Ok(Message::Text(text))), _)) => self.execute(&text).await
+                    .err()
+                    .map(|message| serde_json::to_string(&GatewayEvent::Error { message }).unwrap_or_default()),
+                Either::Right((Some(Ok(Message::Close(_)) | Err(_)) | None, _)) | Either::Left((Err(_), _)) => break,
+                Either::Right((Some(Ok(_)), _)) => None,
+            };
+            if let Some(json) = reply
+                && outgoing.send(Message::text(json)).await.is_err()
+            {
+                break;
+            }
+        }
+        // Dropping the queue closes the client's channel, and `publish` forgets it
+        Ok(())
+    }
+
+    /// Carry out one client command, describing what went wrong if it failed
+    async fn execute(&self, text: &str) -> Result<(), String> {
+        let GatewayCommand::Send { msg_type, payload } = serde_json::from_str(text).map_err(|e| format!("bad command: {}", e))?;
+        let payload = BASE64.decode(payload).map_err(|e| format!("payload is not base64: {}", e))?;
+        let msg_type = match msg_type {
+            GatewayMessageType::Data => MessageType::Data,
+            GatewayMessageType::Control => MessageType::Control,
+        };
+        self.sender.lock().await.send_message(msg_type, &payload).await.map_err(|e| format!("send failed: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MessageCollector;
+    use crate::transport::start_multicast_rx;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn test_clients_receive_and_send() {
+        let (group, port) = (Ipv4Addr::new(239, 1, 1, 93), 12593);
+        let collector = MessageCollector::new();
+        task::spawn(start_multicast_rx(group, port, collector.handler()));
+        let gateway = WsGateway::new(MulticastSender::new(group, port, 0x6A7E).await.unwrap()).with_token("secret");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(gateway.clone().serve(listener));
+
+        let refused = async_tungstenite::client_async(format!("ws://{}/", addr), TcpStream::connect(addr).await.unwrap()).await;
+        assert!(refused.is_err());
+        let url = format!("ws://{}/?token=secret", addr);
+        let (mut ws, _) = async_tungstenite::client_async(url, TcpStream::connect(addr).await.unwrap()).await.unwrap();
+        while gateway.client_count() == 0 {
+            task::sleep(Duration::from_millis(10)).await;
+        }
+
+        let header = FleetMsgHeader::new(MessageType::Data, 7, 3, 2);
+        gateway.publish(&header, b"hi", "10.0.0.7:12345".parse().unwrap());
+        let Some(Ok(Message::Text(text))) = ws.next().await else { panic!("no message pushed") };
+        let GatewayEvent::Message { from, header, payload } = serde_json::from_str(&text).unwrap() else { panic!("{}", text) };
+        assert_eq!((from.as_str(), header.msg_type.as_str(), header.sender_id), ("10.0.0.7:12345", "Data", 7));
+        assert_eq!(payload, "aGk=");
+
+        ws.send(Message::text(r#"{"type":"send","msg_type":"control","payload":"U1RPUA=="}"#)).await.unwrap();
+        collector.wait_for(1, Duration::from_secs(2)).await;
+        let (header, payload, _) = &collector.of_type(MessageType::Control)[0];
+        assert_eq!((header.sender_id.get(), payload.as_slice()), (0x6A7E, &b"STOP"[..]));
+
+        ws.send(Message::text(r#"{"type":"send","msg_type":"data","payload":"%%"}"#)).await.unwrap();
+        let Some(Ok(Message::Text(text))) = ws.next().await else { panic!("no error reported") };
+        assert!(text.contains(r#""type":"error""#), "{}", text);
+    }
+}
@@ -0,0 +1,527 @@
+//! Challenge-response peer authentication, so long-lived command channels are
+//! protected by per-peer session keys rather than only the fleet-wide PSK.
+//!
+//! Both sides hold an HMAC key from the [`Keyring`] under the same key id. The
+//! initiator sends a fresh nonce; the responder answers with its own nonce and
+//! a MAC over both ids and both nonces; the initiator checks it and confirms
+//! with a MAC of its own. Each side has then proven it holds the key, and both
+//! derive the same pair of directional session keys with HKDF-SHA256. A
+//! recorded exchange can't be replayed: every handshake mixes in a nonce from
+//! each side.
+//!
+//! Each side also states the [`ProtocolFeatures`] it supports, and both MACs
+//! cover both sets. An attacker who strips encryption or CRC flags from a
+//! hello or challenge breaks the handshake rather than downgrading it. With a
+//! [`PeerTable`], the peer's authenticated features are pinned, so forged
+//! heartbeats announcing fewer features can't downgrade it either.
+//!
+//! Handshake messages are Control commands (`AUTH {json}`) naming both peers,
+//! so they can travel over multicast, unicast or any other transport; nodes
+//! ignore handshakes addressed to someone else. [`PeerAuthenticator`] runs
+//! both roles for a node and keeps the sessions it established.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::Zeroize;
+
+use crate::crypto::{self, HMAC_LEN};
+use crate::features::ProtocolFeatures;
+use crate::journal::{from_hex, to_hex};
+use crate::security::keyring::Keyring;
+use crate::peers::PeerTable;
+use crate::session::SessionCiphers;
+
+/// Control commands carrying a handshake message start with this, followed by the message as JSON
+pub const AUTH_COMMAND_PREFIX: &str = "AUTH ";
+
+/// A handshake not completed within this long is abandoned
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const NONCE_LEN: usize = 32;
+pub const SESSION_KEY_LEN: usize = 32;
+
+const MAC_LABEL: &str = "fleetlink-auth-v1";
+
+/// The three messages of a handshake between `initiator` and `responder`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum HandshakeMessage {
+    Hello { initiator: u32, responder: u32, key_id: String, initiator_nonce: String, features: ProtocolFeatures },
+    Challenge {
+        initiator: u32,
+        responder: u32,
+        initiator_nonce: String,
+        responder_nonce: String,
+        features: ProtocolFeatures,
+        mac: String,
+    },
+    Confirm { initiator: u32, responder: u32, responder_nonce: String, mac: String },
+}
+
+impl HandshakeMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", AUTH_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`HandshakeMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(AUTH_COMMAND_PREFIX)?).ok()
+    }
+
+    /// The node this message is for
+    pub fn recipient(&self) -> u32 {
+        match self {
+            HandshakeMessage::Hello { responder, .. } | HandshakeMessage::Confirm { responder, .. } => *responder,
+            HandshakeMessage::Challenge { initiator, .. } => *initiator,
+        }
+    }
+}
+
+/// Keys for one authenticated peer: one for each direction, wiped on drop
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionKeys {
+    pub peer_id: u32,
+    /// The keyring HMAC key the session was authenticated with
+    pub key_id: String,
+    /// The features the peer offered, as covered by the handshake MACs
+    pub peer_features: ProtocolFeatures,
+    pub send: [u8; SESSION_KEY_LEN],
+    pub receive: [u8; SESSION_KEY_LEN],
+    pub established_at: DateTime<Utc>,
+}
+
+impl std::fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionKeys")
+            .field("peer_id", &self.peer_id)
+            .field("key_id", &self.key_id)
+            .field("peer_features", &self.peer_features)
+            .field("established_at", &self.established_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SessionKeys {
+    fn drop(&mut self) {
+        self.send.zeroize();
+        self.receive.zeroize();
+    }
+}
+
+fn failed(msg: &str) -> Error {
+    Error::new(ErrorKind::PermissionDenied, format!("peer authentication failed: {}", msg))
+}
+
+fn decode_nonce(hex: &str) -> std::io::Result<[u8; NONCE_LEN]> {
+    from_hex(hex).and_then(|bytes| bytes.try_into().ok()).ok_or_else(|| failed("malformed nonce"))
+}
+
+/// Everything both MACs of a handshake cover, initiator's side first in each pair
+struct Transcript<'a> {
+    initiator: u32,
+    responder: u32,
+    nonces: (&'a [u8], &'a [u8]),
+    features: (ProtocolFeatures, ProtocolFeatures),
+}
+
+/// HMAC over the role and the transcript; the role keeps a responder's MAC
+/// from being reflected back as the initiator's
+fn transcript_mac(key: &[u8], role: &str, transcript: &Transcript) -> [u8; HMAC_LEN] {
+    let Transcript { initiator, responder, nonces, features } = transcript;
+    let parts: [&[u8]; 8] = [
+        MAC_LABEL.as_bytes(),
+        role.as_bytes(),
+        &initiator.to_le_bytes(),
+        &responder.to_le_bytes(),
+        nonces.0,
+        nonces.1,
+        &[features.0.bits()],
+        &[features.1.bits()],
+    ];
+    let lengths = parts.map(|part| (part.len() as u32).to_le_bytes());
+    let framed: Vec<&[u8]> = parts.iter().zip(&lengths).flat_map(|(part, len)| [&len[..], part]).collect();
+    crypto::provider().hmac_sha256(key, &framed)
+}
+
+fn verify(expected: [u8; HMAC_LEN], tag_hex: &str) -> std::io::Result<()> {
+    let tag = from_hex(tag_hex).ok_or_else(|| failed("malformed MAC"))?;
+    match crypto::verify_hmac(&expected, &tag) {
+        true => Ok(()),
+        false => Err(failed("MAC does not match; the peer doesn't hold the key")),
+    }
+}
+
+/// Derive the initiator-to-responder and responder-to-initiator keys
+fn session_keys(key: &[u8], initiator: u32, responder: u32, nonces: (&[u8], &[u8])) -> ([u8; SESSION_KEY_LEN], [u8; SESSION_KEY_LEN]) {
+    let salt = [nonces.0, nonces.1].concat();
+    let mut okm = [0u8; 2 * SESSION_KEY_LEN];
+    let info = format!("{} session {:08x} {:08x}", MAC_LABEL, initiator, responder);
+    crypto::provider().hkdf_sha256(Some(&salt), key, info.as_bytes(), &mut okm)
+        .expect("64 bytes is a valid HKDF-SHA256 output length");
+    let keys = (okm[..SESSION_KEY_LEN].try_into().unwrap(), okm[SESSION_KEY_LEN..].try_into().unwrap());
+    okm.zeroize();
+    keys
+}
+
+/// Our side of a handshake we started, waiting for the challenge
+struct Initiating {
+    key_id: String,
+    key: Vec<u8>,
+    nonce: [u8; NONCE_LEN],
+    /// What we offered in the hello
+    features: ProtocolFeatures,
+    started: Instant,
+}
+
+/// Our side of a handshake a peer started, waiting for the confirmation
+struct Responding {
+    key_id: String,
+    key: Vec<u8>,
+    nonces: ([u8; NONCE_LEN], [u8; NONCE_LEN]),
+    features: (ProtocolFeatures, ProtocolFeatures),
+    started: Instant,
+}
+
+impl Drop for Initiating {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl Drop for Responding {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// What handling a handshake message led to
+#[derive(Debug, Default)]
+pub struct HandshakeStep {
+    /// Send this to the peer next
+    pub reply: Option<HandshakeMessage>,
+    /// The peer is now authenticated under these keys
+    pub established: Option<SessionKeys>,
+}
+
+/// Runs handshakes in both roles for one node and keeps the resulting sessions
+pub struct PeerAuthenticator {
+    local_id: u32,
+    initiating: HashMap<u32, Initiating>,
+    responding: HashMap<u32, Responding>,
+    sessions: HashMap<u32, SessionKeys>,
+    features: ProtocolFeatures,
+    ciphers: Option<SessionCiphers>,
+    peers: Option<Arc<Mutex<PeerTable>>>,
+}
+
+impl std::fmt::Debug for PeerAuthenticator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PeerAuthenticator")
+            .field("local_id", &self.local_id)
+            .field("features", &self.features)
+            .field("pending", &(self.initiating.len() + self.responding.len()))
+            .field("sessions", &self.sessions.len())
+            .finish()
+    }
+}
+
+impl PeerAuthenticator {
+    pub fn new(local_id: u32) -> Self {
+        Self {
+            local_id,
+            initiating: HashMap::new(),
+            responding: HashMap::new(),
+            sessions: HashMap::new(),
+            features: ProtocolFeatures::NONE,
+            ciphers: None,
+            peers: None,
+        }
+    }
+
+    /// Offer `features` (normally the codec's `supported()`) in our handshakes
+    pub fn with_features(mut self, features: ProtocolFeatures) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Pin each authenticated peer's features in `peers`, so heartbeats can't
+    /// negotiate it down from them
+    pub fn with_peer_table(mut self, peers: Arc<Mutex<PeerTable>>) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// Install each session established into `ciphers`, so a codec sharing
+    /// them encrypts traffic with that peer under the session from then on
+    pub fn with_sessions(mut self, ciphers: SessionCiphers) -> Self {
+        self.ciphers = Some(ciphers);
+        self
+    }
+
+    /// Start authenticating `peer_id` with the keyring's current HMAC key,
+    /// replacing any handshake already under way with it
+    pub fn initiate(&mut self, peer_id: u32, keyring: &Keyring) -> std::io::Result<HandshakeMessage> {
+        let key = keyring.current_hmac_key(Utc::now())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no HMAC key is currently valid in the keyring"))?;
+        let nonce: [u8; NONCE_LEN] = crypto::random_bytes()?;
+        let hello = HandshakeMessage::Hello {
+            initiator: self.local_id,
+            responder: peer_id,
+            key_id: key.id().to_string(),
+            initiator_nonce: to_hex(&nonce),
+            features: self.features,
+        };
+        let pending = Initiating {
+            key_id: key.id().to_string(),
+            key: key.bytes().to_vec(),
+            nonce,
+            features: self.features,
+            started: Instant::now(),
+        };
+        self.initiating.insert(peer_id, pending);
+        Ok(hello)
+    }
+
+    /// Handle a handshake message received from `sender_id`. Messages for
+    /// other nodes, or claiming to come from someone other than their sender,
+    /// are ignored with an empty step; a failed check abandons the handshake.
+    pub fn handle(&mut self, sender_id: u32, message: &HandshakeMessage, keyring: &Keyring) -> std::io::Result<HandshakeStep> {
+        self.expire(Instant::now());
+        if message.recipient() != self.local_id {
+            return Ok(HandshakeStep::default());
+        }
+        match message {
+            HandshakeMessage::Hello { initiator, key_id, initiator_nonce, features, .. } if *initiator == sender_id => {
+                let key = keyring.hmac_key(key_id)
+                    .filter(|key| key.is_valid_at(Utc::now()))
+                    .ok_or_else(|| failed(&format!("no valid HMAC key '{}'", key_id)))?;
+                let initiator_nonce = decode_nonce(initiator_nonce)?;
+                let responder_nonce: [u8; NONCE_LEN] = crypto::random_bytes()?;
+                let features = (*features, self.features);
+                let transcript = Transcript {
+                    initiator: sender_id,
+                    responder: self.local_id,
+                    nonces: (&initiator_nonce, &responder_nonce),
+                    features,
+                };
+                let mac = transcript_mac(key.bytes(), "responder", &transcript);
+                let reply = HandshakeMessage::Challenge {
+                    initiator: sender_id,
+                    responder: self.local_id,
+                    initiator_nonce: to_hex(&initiator_nonce),
+                    responder_nonce: to_hex(&responder_nonce),
+                    features: self.features,
+                    mac: to_hex(&mac),
+                };
+                self.responding.insert(sender_id, Responding {
+                    key_id: key_id.clone(),
+                    key: key.bytes().to_vec(),
+                    nonces: (initiator_nonce, responder_nonce),
+                    features,
+                    started: Instant::now(),
+                });
+                Ok(HandshakeStep { reply: Some(reply), established: None })
+            }
+            HandshakeMessage::Challenge { responder, initiator_nonce, responder_nonce, features, mac, .. } if *responder == sender_id => {
+                let pending = self.initiating.remove(&sender_id).ok_or_else(|| failed("challenge for no handshake we started"))?;
+                if decode_nonce(initiator_nonce)? != pending.nonce {
+                    return Err(failed("challenge answers a different hello"));
+                }
+                let responder_nonce = decode_nonce(responder_nonce)?;
+                // Our own record of what we offered, not what reached the peer
+                let transcript = Transcript {
+                    initiator: self.local_id,
+                    responder: sender_id,
+                    nonces: (&pending.nonce, &responder_nonce),
+                    features: (pending.features, *features),
+                };
+                verify(transcript_mac(&pending.key, "responder", &transcript), mac)
+                    .map_err(|_| failed("challenge MAC does not match; wrong key or a tampered hello"))?;
+
+                let confirm_mac = transcript_mac(&pending.key, "initiator", &transcript);
+                let reply = HandshakeMessage::Confirm {
+                    initiator: self.local_id,
+                    responder: sender_id,
+                    responder_nonce: to_hex(&responder_nonce),
+                    mac: to_hex(&confirm_mac),
+                };
+                let (send, receive) = session_keys(&pending.key, self.local_id, sender_id, transcript.nonces);
+                let session = self.establish(sender_id, &pending.key_id, *features, send, receive);
+                Ok(HandshakeStep { reply: Some(reply), established: Some(session) })
+            }
+            HandshakeMessage::Confirm { initiator, responder_nonce, mac, .. } if *initiator == sender_id => {
+                let pending = self.responding.remove(&sender_id).ok_or_else(|| failed("confirmation for no challenge we sent"))?;
+                if decode_nonce(responder_nonce)? != pending.nonces.1 {
+                    return Err(failed("confirmation answers a different challenge"));
+                }
+                let transcript = Transcript {
+                    initiator: sender_id,
+                    responder: self.local_id,
+                    nonces: (&pending.nonces.0, &pending.nonces.1),
+                    features: pending.features,
+                };
+                verify(transcript_mac(&pending.key, "initiator", &transcript), mac)
+                    .map_err(|_| failed("confirmation MAC does not match; wrong key or a tampered challenge"))?;
+                let (initiator_to_us, us_to_initiator) = session_keys(&pending.key, sender_id, self.local_id, transcript.nonces);
+                let session = self.establish(sender_id, &pending.key_id, pending.features.0, us_to_initiator, initiator_to_us);
+                Ok(HandshakeStep { reply: None, established: Some(session) })
+            }
+            // A message naming someone other than its sender as the peer
+            _ => Ok(HandshakeStep::default()),
+        }
+    }
+
+    fn establish(
+        &mut self,
+        peer_id: u32,
+        key_id: &str,
+        peer_features: ProtocolFeatures,
+        send: [u8; SESSION_KEY_LEN],
+        receive: [u8; SESSION_KEY_LEN]
+    ) -> SessionKeys {
+        let session = SessionKeys { peer_id, key_id: key_id.to_string(), peer_features, send, receive, established_at: Utc::now() };
+        if let Some(ciphers) = &self.ciphers {
+            ciphers.install(&session);
+        }
+        if let Some(peers) = &self.peers {
+            peers.lock().unwrap().pin_features(peer_id, peer_features);
+        }
+        self.sessions.insert(peer_id, session.clone());
+        session
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.initiating.retain(|_, pending| now.duration_since(pending.started) < HANDSHAKE_TIMEOUT);
+        self.responding.retain(|_, pending| now.duration_since(pending.started) < HANDSHAKE_TIMEOUT);
+    }
+
+    /// The session established with `peer_id`, if any
+    pub fn session(&self, peer_id: u32) -> Option<&SessionKeys> {
+        self.sessions.get(&peer_id)
+    }
+
+    /// Forget a peer's session, e.g. when it says goodbye
+    pub fn forget(&mut self, peer_id: u32) -> Option<SessionKeys> {
+        self.initiating.remove(&peer_id);
+        self.responding.remove(&peer_id);
+        if let Some(ciphers) = &self.ciphers {
+            ciphers.remove(peer_id);
+        }
+        if let Some(peers) = &self.peers {
+            peers.lock().unwrap().unpin_features(peer_id);
+        }
+        self.sessions.remove(&peer_id)
+    }
+
+    pub fn authenticated_peers(&self) -> impl Iterator<Item = u32> + '_ {
+        self.sessions.keys().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(key: &str) -> Keyring {
+        Keyring::from_toml(&format!("[[hmac]]\nid = \"ops\"\nkey = \"{}\"\n", key)).unwrap()
+    }
+
+    /// Deliver a message through its Control command form, as a transport would
+    fn deliver(to: &mut PeerAuthenticator, from: u32, message: &HandshakeMessage, keyring: &Keyring) -> std::io::Result<HandshakeStep> {
+        to.handle(from, &HandshakeMessage::from_control(&message.control_command()).unwrap(), keyring)
+    }
+
+    #[test]
+    fn test_handshake_establishes_matching_sessions() {
+        let shared = keyring("00112233445566778899aabbccddeeff");
+        let ciphers = SessionCiphers::new();
+        let (mut base, mut robot) = (PeerAuthenticator::new(1), PeerAuthenticator::new(42).with_sessions(ciphers.clone()));
+        let mut bystander = PeerAuthenticator::new(7);
+
+        let hello = base.initiate(42, &shared).unwrap();
+        assert!(deliver(&mut bystander, 1, &hello, &shared).unwrap().reply.is_none());
+        let challenge = deliver(&mut robot, 1, &hello, &shared).unwrap().reply.unwrap();
+        let step = deliver(&mut base, 42, &challenge, &shared).unwrap();
+        let base_session = step.established.unwrap();
+        let done = deliver(&mut robot, 1, &step.reply.unwrap(), &shared).unwrap();
+        assert!(done.reply.is_none());
+        let robot_session = done.established.unwrap();
+
+        assert_eq!((base_session.peer_id, robot_session.peer_id), (42, 1));
+        assert_eq!(base_session.send, robot_session.receive);
+        assert_eq!(base_session.receive, robot_session.send);
+        assert_ne!(base_session.send, base_session.receive);
+        assert_eq!(robot.session(1).unwrap().key_id, "ops");
+        assert!(ciphers.has_session(1));
+        assert!(!format!("{:?}", base_session).contains("send"));
+    }
+
+    #[test]
+    fn test_wrong_key_and_replay_are_refused() {
+        let shared = keyring("00112233445566778899aabbccddeeff");
+        let other = keyring("ffeeddccbbaa99887766554433221100");
+        let (mut base, mut impostor) = (PeerAuthenticator::new(1), PeerAuthenticator::new(42));
+
+        // The impostor has a key under the same id, but not the same key
+        let hello = base.initiate(42, &shared).unwrap();
+        let challenge = impostor.handle(1, &hello, &other).unwrap().reply.unwrap();
+        assert_eq!(base.handle(42, &challenge, &shared).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(base.session(42).is_none());
+
+        // A recorded challenge doesn't answer a new hello
+        let mut robot = PeerAuthenticator::new(42);
+        let hello = base.initiate(42, &shared).unwrap();
+        let challenge = robot.handle(1, &hello, &shared).unwrap().reply.unwrap();
+        base.initiate(42, &shared).unwrap();
+        assert!(base.handle(42, &challenge, &shared).is_err());
+
+        // Nor can a message claim to come from a node other than its sender
+        let forged = base.initiate(42, &shared).unwrap();
+        assert!(robot.handle(99, &forged, &shared).unwrap().reply.is_none());
+    }
+
+    #[test]
+    fn test_stripped_features_break_the_handshake() {
+        use crate::capabilities::Capabilities;
+        use crate::transport::{FleetMsgHeader, MessageType};
+
+        let shared = keyring("00112233445566778899aabbccddeeff");
+        let offered = ProtocolFeatures::CRC32 | ProtocolFeatures::ENCRYPTION;
+        let strip = |features: &mut ProtocolFeatures| *features = *features & ProtocolFeatures::CRC32;
+        let peers = Arc::new(Mutex::new(PeerTable::new()));
+        let mut base = PeerAuthenticator::new(1).with_features(offered).with_peer_table(peers.clone());
+        let mut robot = PeerAuthenticator::new(42).with_features(offered);
+
+        // Stripped from the hello: the initiator refuses the challenge
+        let mut hello = base.initiate(42, &shared).unwrap();
+        if let HandshakeMessage::Hello { features, .. } = &mut hello {
+            strip(features);
+        }
+        let challenge = robot.handle(1, &hello, &shared).unwrap().reply.unwrap();
+        assert_eq!(base.handle(42, &challenge, &shared).unwrap_err().kind(), ErrorKind::PermissionDenied);
+
+        // Stripped from the challenge, which the responder's own MAC covers too
+        let hello = base.initiate(42, &shared).unwrap();
+        let mut challenge = robot.handle(1, &hello, &shared).unwrap().reply.unwrap();
+        if let HandshakeMessage::Challenge { features, .. } = &mut challenge {
+            strip(features);
+        }
+        assert!(base.handle(42, &challenge, &shared).is_err());
+        assert!(base.session(42).is_none());
+
+        // An honest handshake pins the robot's features against forged heartbeats
+        peers.lock().unwrap().observe(&FleetMsgHeader::new(MessageType::Heartbeat, 42, 0, 0), "10.0.0.42:7000".parse().unwrap(), Instant::now());
+        let hello = base.initiate(42, &shared).unwrap();
+        let challenge = robot.handle(1, &hello, &shared).unwrap().reply.unwrap();
+        let session = base.handle(42, &challenge, &shared).unwrap().established.unwrap();
+        assert_eq!(session.peer_features, offered);
+        let mut peers = peers.lock().unwrap();
+        assert!(peers.announce(42, Capabilities::default()));
+        assert_eq!(peers.negotiated(42, offered), offered);
+    }
+}
@@ -0,0 +1,200 @@
+//! Heartbeats on a timer, sent by a background task, so applications don't
+//! each write the loop around [`MulticastSender::send_heartbeat`].
+//!
+//! [`MulticastSender::start_heartbeat`] spawns the task and returns a
+//! [`HeartbeatHandle`] that changes the interval or stops it. Every heartbeat,
+//! whether the task sends it or the application does, carries the sender's
+//! [`Liveness`] (uptime and message counters) for dashboards. Senders on
+//! version-1 headers can't carry extensions and leave it out.
+//!
+//! The task shares the sender's sequence numbers and stats and waits for its
+//! TDMA slot. It isn't held back by the bandwidth budget, shaping calendar or
+//! transforms, and heartbeats aren't copied to mirrors, since the fleet
+//! decides from them whether the node is alive. The task stops when the
+//! handle is dropped or stopped, or when the sender is shut down or dropped.
+//!
+//! [`MulticastSender::send_heartbeat`]: crate::transport::MulticastSender::send_heartbeat
+//! [`MulticastSender::start_heartbeat`]: crate::transport::MulticastSender::start_heartbeat
+
+use async_std::channel::{self, Receiver, Sender};
+use async_std::net::UdpSocket;
+use async_std::task::JoinHandle;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::extensions::Extensions;
+use crate::features::ProtocolFeatures;
+use crate::message::FleetMessage;
+use crate::protocol;
+use crate::stats::TransportStats;
+use crate::tdma::SlotSchedule;
+use crate::timing::SendTimestamps;
+use crate::transport::MessageType;
+use crate::zones::Zone;
+
+/// Shortest interval a heartbeat task accepts, so a zero can't turn it into a flood
+pub const MIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a sender has been up and how much it has sent and received.
+///
+/// Carried on heartbeats as the [`LIVENESS`](crate::extensions::LIVENESS)
+/// extension: uptime in milliseconds, then the two counters, each a
+/// little-endian u64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Liveness {
+    pub uptime: Duration,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+}
+
+impl Liveness {
+    /// A sender's liveness, up since `started` and counting into `stats`
+    pub fn of(started: Instant, stats: &TransportStats) -> Self {
+        let snapshot = stats.snapshot();
+        Self { uptime: started.elapsed(), messages_sent: snapshot.messages_sent, messages_received: snapshot.messages_received }
+    }
+
+    pub fn encode(&self) -> [u8; 24] {
+        let mut bytes = [0u8; 24];
+        bytes[..8].copy_from_slice(&(self.uptime.as_millis() as u64).to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.messages_sent.to_le_bytes());
+        bytes[16..].copy_from_slice(&self.messages_received.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; 24] = bytes.try_into().ok()?;
+        let field = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Some(Self { uptime: Duration::from_millis(field(0)), messages_sent: field(8), messages_received: field(16) })
+    }
+}
+
+/// What the heartbeat task needs of its sender, shared with it or copied from it
+#[derive(Debug)]
+pub(crate) struct HeartbeatLane {
+    pub socket: UdpSocket,
+    pub target: SocketAddr,
+    pub sender_id: u32,
+    pub sequence: Arc<AtomicU16>,
+    /// Incarnation and announcement, as [`MulticastSender::send_heartbeat`](crate::transport::MulticastSender::send_heartbeat) sends them
+    pub payload: Vec<u8>,
+    pub version: u8,
+    pub started: Instant,
+    pub stats: Arc<TransportStats>,
+    pub slot_schedule: Option<SlotSchedule>,
+    /// Shared, so heartbeats follow a vehicle across zones
+    pub zone: Arc<Mutex<Option<Zone>>>,
+    /// Set once the sender has said goodbye
+    pub departed: Arc<AtomicBool>,
+}
+
+impl HeartbeatLane {
+    fn frame(&self) -> Vec<u8> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        if self.version < protocol::FEATURES_VERSION {
+            return FleetMessage::new(MessageType::Heartbeat, self.sender_id, sequence, self.payload.clone())
+                .with_version(self.version)
+                .to_bytes();
+        }
+        let mut extensions = Extensions::new();
+        extensions.set_send_timestamps(SendTimestamps::now());
+        extensions.set_liveness(Liveness::of(self.started, &self.stats));
+        if let Some(zone) = &*self.zone.lock().unwrap() {
+            extensions.set_zone(zone);
+        }
+        FleetMessage::new(MessageType::Heartbeat, self.sender_id, sequence, extensions.prepend_to(&self.payload))
+            .with_version(self.version)
+            .with_features(ProtocolFeatures::EXTENSIONS)
+            .to_bytes()
+    }
+
+    async fn beat(&self) -> std::io::Result<()> {
+        if let Some(schedule) = &self.slot_schedule {
+            let wait = schedule.delay_until_slot(self.sender_id, schedule.synchronized_now_us());
+            if !wait.is_zero() {
+                async_std::task::sleep(wait).await;
+            }
+        }
+        let message = self.frame();
+        self.socket.send_to(&message, self.target).await?;
+        self.stats.record_sent(message.len());
+        Ok(())
+    }
+
+    /// Beat every interval until the handle goes away or the sender departs
+    async fn run(self, mut interval: Duration, control: Receiver<Duration>, sent: Arc<AtomicU64>) {
+        loop {
+            match async_std::future::timeout(interval, control.recv()).await {
+                Ok(Ok(changed)) => interval = changed,
+                Ok(Err(_)) => return,
+                Err(_) => {
+                    if self.departed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    match self.beat().await {
+                        Ok(()) => {
+                            sent.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => eprintln!("Failed to send heartbeat: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Controls a running heartbeat task; dropping it stops the task
+#[derive(Debug)]
+pub struct HeartbeatHandle {
+    control: Sender<Duration>,
+    interval: Arc<AtomicU64>,
+    sent: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+}
+
+impl HeartbeatHandle {
+    pub(crate) fn spawn(lane: HeartbeatLane, interval: Duration) -> Self {
+        let interval = interval.max(MIN_INTERVAL);
+        let (control, changes) = channel::unbounded();
+        let sent = Arc::new(AtomicU64::new(0));
+        let task = async_std::task::spawn(lane.run(interval, changes, sent.clone()));
+        Self { control, interval: Arc::new(AtomicU64::new(interval.as_micros() as u64)), sent, task }
+    }
+
+    /// Beat every `interval` from now on (at least [`MIN_INTERVAL`]); the next
+    /// heartbeat is due one new interval after the change
+    pub fn set_interval(&self, interval: Duration) {
+        let interval = interval.max(MIN_INTERVAL);
+        self.interval.store(interval.as_micros() as u64, Ordering::Relaxed);
+        let _ = self.control.try_send(interval);
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_micros(self.interval.load(Ordering::Relaxed))
+    }
+
+    /// Heartbeats the task has sent
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Stop the task and wait for it to finish
+    pub async fn stop(self) {
+        self.control.close();
+        self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liveness_round_trip() {
+        let liveness = Liveness { uptime: Duration::from_millis(86_400_123), messages_sent: 42, messages_received: 7 };
+        assert_eq!(Liveness::decode(&liveness.encode()), Some(liveness));
+        assert_eq!(Liveness::decode(&[0; 23]), None);
+    }
+}
@@ -0,0 +1,247 @@
+//! Exactly-once effects for command handlers, despite retransmissions,
+//! backfill and replays delivering the same command more than once.
+//!
+//! An [`ExactlyOnce`] names each delivery by an idempotency key, runs the
+//! handler only for keys it hasn't processed, and journals every processed
+//! message with its key to a log in a [`StateStore`], so a restarted node
+//! still recognizes commands it handled before. By default the key is the
+//! sender, send time and sequence from the header, which a retransmitted
+//! datagram keeps; a sender that re-sends a command as a new message (e.g. a
+//! user pressing a button twice) needs a key of its own in the payload, picked
+//! out with [`ExactlyOnce::with_key`].
+//!
+//! A message is journaled once its handler succeeds. If the node stops
+//! between the two, the message is handled again when it is delivered again;
+//! handlers whose effects can't be repeated should record the key they are
+//! given alongside the effect (e.g. in the same database transaction) and
+//! check it first.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+use crate::journal::JournalEntry;
+use crate::receiver::Delivery;
+use crate::soak;
+use crate::store::StateStore;
+
+/// Processed keys remembered by default; older ones are forgotten
+pub const DEFAULT_WINDOW: usize = 4096;
+
+/// Records read from the log at a time when opening
+const LOAD_BATCH: usize = 1024;
+
+/// Names a delivery's effect; `None` handles it without deduplicating
+pub type KeyFn = Arc<dyn Fn(&Delivery) -> Option<String> + Send + Sync>;
+
+/// A processed message as journaled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessedRecord {
+    pub key: String,
+    pub entry: JournalEntry,
+}
+
+/// What became of a delivery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Handled<T> {
+    /// The handler ran and returned this
+    Processed(T),
+    /// A message with this key was processed before; the handler didn't run
+    Duplicate(String),
+}
+
+/// Runs a handler at most once per idempotency key; see the [module docs](self)
+pub struct ExactlyOnce {
+    store: Arc<dyn StateStore>,
+    log: String,
+    key: Option<KeyFn>,
+    window: usize,
+    seen: HashSet<String>,
+    /// Oldest first
+    order: VecDeque<String>,
+    duplicates: u64,
+}
+
+impl fmt::Debug for ExactlyOnce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExactlyOnce")
+            .field("log", &self.log)
+            .field("window", &self.window)
+            .field("remembered", &self.order.len())
+            .field("duplicates", &self.duplicates)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The default key: sender, send time and sequence, kept by retransmissions
+pub fn header_key(delivery: &Delivery) -> Option<String> {
+    let header = &delivery.header;
+    Some(format!("{}:{}:{}", header.sender_id, header.timestamp_micros(), header.sequence))
+}
+
+impl ExactlyOnce {
+    /// Journal processed messages to `log` in `store`, first reading back the
+    /// keys already there
+    pub fn open(store: Arc<dyn StateStore>, log: &str) -> std::io::Result<Self> {
+        let mut exactly_once = Self {
+            store,
+            log: log.to_string(),
+            key: None,
+            window: DEFAULT_WINDOW,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            duplicates: 0,
+        };
+        let mut cursor = 0;
+        loop {
+            let records = exactly_once.store.read_log(log, cursor, LOAD_BATCH)?;
+            let Some((last, _)) = records.last() else { break };
+            cursor = last + 1;
+            for (_, record) in records {
+                let record: ProcessedRecord = serde_json::from_slice(&record)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+                exactly_once.remember(record.key);
+            }
+        }
+        Ok(exactly_once)
+    }
+
+    /// Name deliveries with `key` instead of [`header_key`], e.g. to read a
+    /// request id out of the payload
+    pub fn with_key(mut self, key: impl Fn(&Delivery) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.key = Some(Arc::new(key));
+        self
+    }
+
+    /// Remember this many processed keys (default [`DEFAULT_WINDOW`]); a
+    /// duplicate arriving after that many newer messages is handled again
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        while self.order.len() > self.window {
+            self.forget_oldest();
+        }
+        self
+    }
+
+    /// The idempotency key of `delivery`
+    pub fn key(&self, delivery: &Delivery) -> Option<String> {
+        match &self.key {
+            Some(key) => key(delivery),
+            None => header_key(delivery),
+        }
+    }
+
+    /// Whether a message with `key` was processed, as far as the window reaches
+    pub fn is_processed(&self, key: &str) -> bool {
+        self.seen.contains(key)
+    }
+
+    /// Run `handler` with the delivery's key unless it was processed before,
+    /// then journal it. A handler error is returned without journaling, so
+    /// the message is handled again if it is delivered again.
+    pub fn handle<T>(
+        &mut self,
+        delivery: &Delivery,
+        handler: impl FnOnce(&str, &Delivery) -> std::io::Result<T>
+    ) -> std::io::Result<Handled<T>> {
+        let Some(key) = self.key(delivery) else {
+            return handler("", delivery).map(Handled::Processed);
+        };
+        if self.seen.contains(&key) {
+            self.duplicates += 1;
+            return Ok(Handled::Duplicate(key));
+        }
+        let output = handler(&key, delivery)?;
+        let message = delivery.message();
+        let entry = JournalEntry::new(message.header(), message.payload(), delivery.addr, soak::now_micros())
+            .with_trace_id(delivery.extensions.trace_id());
+        let record = ProcessedRecord { key, entry };
+        self.store.append(&self.log, &serde_json::to_vec(&record)?)?;
+        self.remember(record.key);
+        Ok(Handled::Processed(output))
+    }
+
+    /// Deliveries skipped as duplicates since opening
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    fn remember(&mut self, key: String) {
+        if self.seen.insert(key.clone()) {
+            self.order.push_back(key);
+        }
+        while self.order.len() > self.window {
+            self.forget_oldest();
+        }
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some(oldest) = self.order.pop_front() {
+            self.seen.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::Extensions;
+    use crate::store::MemoryStore;
+    use crate::transport::{FleetMsgHeader, MessageType};
+
+    fn command(sequence: u16, text: &str) -> Delivery {
+        let header = FleetMsgHeader::new(MessageType::Control, 7, sequence, text.len() as u16);
+        let addr = "10.0.0.7:5000".parse().unwrap();
+        Delivery { header, extensions: Extensions::new(), payload: text.as_bytes().to_vec(), addr, issues: Vec::new() }
+    }
+
+    #[test]
+    fn test_retransmissions_are_handled_once_across_restarts() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let unlock = command(1, "UNLOCK door-3");
+        let mut effects = Vec::new();
+
+        let mut exactly_once = ExactlyOnce::open(store.clone(), "commands").unwrap();
+        let handled = exactly_once.handle(&unlock, |key, delivery| {
+            effects.push(key.to_string());
+            Ok(delivery.payload.len())
+        });
+        assert_eq!(handled.unwrap(), Handled::Processed(13));
+        let failed = exactly_once.handle(&command(2, "LOCK door-3"), |_, _| Err::<(), _>(Error::other("actuator busy")));
+        assert!(failed.is_err());
+
+        // After a restart: the retransmitted unlock is skipped, the failed lock retried
+        let mut exactly_once = ExactlyOnce::open(store.clone(), "commands").unwrap();
+        assert!(matches!(exactly_once.handle(&unlock, |_, _| Ok(())).unwrap(), Handled::Duplicate(_)));
+        assert_eq!(exactly_once.handle(&command(2, "LOCK door-3"), |_, _| Ok(())).unwrap(), Handled::Processed(()));
+        assert_eq!((effects.len(), exactly_once.duplicates()), (1, 1));
+
+        let records = store.read_log("commands", 0, 10).unwrap();
+        let first: ProcessedRecord = serde_json::from_slice(&records[0].1).unwrap();
+        assert_eq!((first.key, first.entry.decode().unwrap().1), (header_key(&unlock).unwrap(), b"UNLOCK door-3".to_vec()));
+    }
+
+    #[test]
+    fn test_keys_from_the_payload_catch_resent_commands() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        // The request id is the command's second word
+        let mut exactly_once = ExactlyOnce::open(store, "commands").unwrap()
+            .with_key(|delivery| std::str::from_utf8(&delivery.payload).ok()?.split(' ').nth(1).map(str::to_string))
+            .with_window(2);
+
+        assert!(matches!(exactly_once.handle(&command(1, "DISPATCH r-1"), |_, _| Ok(())).unwrap(), Handled::Processed(())));
+        // Sent again as a new message, with a new sequence and send time
+        assert_eq!(exactly_once.handle(&command(5, "DISPATCH r-1"), |_, _| Ok(())).unwrap(), Handled::Duplicate("r-1".into()));
+        // Without a key, messages are handled every time
+        assert!(matches!(exactly_once.handle(&command(6, "PING"), |_, _| Ok(())).unwrap(), Handled::Processed(())));
+        assert!(matches!(exactly_once.handle(&command(6, "PING"), |_, _| Ok(())).unwrap(), Handled::Processed(())));
+
+        for id in ["r-2", "r-3"] {
+            exactly_once.handle(&command(9, &format!("DISPATCH {}", id)), |_, _| Ok(())).unwrap();
+        }
+        assert!(!exactly_once.is_processed("r-1"), "forgotten past the window");
+        assert!(exactly_once.is_processed("r-3"));
+    }
+}
@@ -0,0 +1,257 @@
+//! Finding the network interface to send and receive fleet traffic on.
+//!
+//! [`list`] enumerates the interfaces of this process's network namespace
+//! with their flags, addresses and MTU; [`candidates`] keeps those that can
+//! carry IPv4 multicast, best first, and [`select`] picks one by name or
+//! address, or the best one. `fleetlinkd --interfaces` prints the same list.
+//!
+//! Inside a container the list is the container's: typically one end of a
+//! veth pair as `eth0`, whose peer lives in the host's namespace. Its MTU is
+//! often smaller than the host's (e.g. 1450 under a VXLAN overlay), so size
+//! datagrams with [`NetworkInterface::max_datagram_len`] rather than assuming
+//! Ethernet's. On a host running containers, the host ends of the veth pairs
+//! have no addresses and the container bridges (`docker0`, `cni0`) rank below
+//! physical interfaces, so neither is picked over the real uplink. A process
+//! started in another namespace (`ip netns exec`, `nsenter`) sees that
+//! namespace's interfaces; [`network_namespace`] tells which one it is in.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// IPv4 and UDP headers, which an interface's MTU has to hold besides the datagram
+const IP_UDP_OVERHEAD: usize = 28;
+
+/// What an interface is, as far as the kernel tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InterfaceKind {
+    /// Backed by a device, e.g. a NIC
+    Physical,
+    /// One end of a virtual Ethernet pair, e.g. a container's `eth0`
+    Veth,
+    /// Something else virtual, e.g. a VLAN or macvlan
+    Other,
+    /// A software bridge, e.g. `docker0`
+    Bridge,
+    /// A tunnel or point-to-point link, e.g. WireGuard or VXLAN
+    Tunnel,
+    Loopback,
+}
+
+/// One network interface; see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub index: u32,
+    pub kind: InterfaceKind,
+    pub ipv4: Vec<Ipv4Addr>,
+    pub ipv6: Vec<Ipv6Addr>,
+    /// `None` where the platform doesn't tell
+    pub mtu: Option<usize>,
+    pub up: bool,
+    /// Up with a carrier, e.g. a cable plugged in
+    pub running: bool,
+    pub multicast: bool,
+}
+
+impl NetworkInterface {
+    /// Whether fleet traffic can use the interface: up, multicast-capable,
+    /// with an IPv4 address, and not loopback
+    pub fn is_candidate(&self) -> bool {
+        self.up && self.multicast && self.kind != InterfaceKind::Loopback && !self.ipv4.is_empty()
+    }
+
+    /// Largest datagram, header included, that fits the MTU without IP
+    /// fragmentation; for [`MulticastSender::with_max_datagram_len`](crate::transport::MulticastSender::with_max_datagram_len)
+    pub fn max_datagram_len(&self) -> Option<usize> {
+        self.mtu.map(|mtu| mtu.saturating_sub(IP_UDP_OVERHEAD))
+    }
+
+    /// Whether `wanted` is the interface's name or one of its addresses
+    pub fn matches(&self, wanted: &str) -> bool {
+        match wanted.parse::<IpAddr>() {
+            Ok(IpAddr::V4(addr)) => self.ipv4.contains(&addr),
+            Ok(IpAddr::V6(addr)) => self.ipv6.contains(&addr),
+            Err(_) => self.name == wanted,
+        }
+    }
+}
+
+impl fmt::Display for NetworkInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = format!("{:?}", self.kind).to_lowercase();
+        let addrs: Vec<String> = self.ipv4.iter().map(Ipv4Addr::to_string).chain(self.ipv6.iter().map(Ipv6Addr::to_string)).collect();
+        let addrs = if addrs.is_empty() { "-".to_string() } else { addrs.join(",") };
+        let mtu = self.mtu.map_or("?".to_string(), |mtu| mtu.to_string());
+        let flags = [(self.up, "up"), (self.running, "running"), (self.multicast, "multicast")]
+            .iter()
+            .filter_map(|&(set, flag)| set.then_some(flag))
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(f, "{:<12} {:<8} mtu {:<6} {:<24} {}", self.name, kind, mtu, flags, addrs)
+    }
+}
+
+/// Every interface in this process's network namespace, in index order
+#[cfg(unix)]
+pub fn list() -> std::io::Result<Vec<NetworkInterface>> {
+    let mut interfaces: Vec<NetworkInterface> = Vec::new();
+    for (name, flags, addr) in unix::addresses()? {
+        let position = match interfaces.iter().position(|interface| interface.name == name) {
+            Some(position) => position,
+            None => {
+                interfaces.push(unix::describe(&name, flags));
+                interfaces.len() - 1
+            }
+        };
+        match addr {
+            Some(IpAddr::V4(addr)) => interfaces[position].ipv4.push(addr),
+            Some(IpAddr::V6(addr)) => interfaces[position].ipv6.push(addr),
+            None => {}
+        }
+    }
+    interfaces.sort_by_key(|interface| interface.index);
+    Ok(interfaces)
+}
+
+#[cfg(not(unix))]
+pub fn list() -> std::io::Result<Vec<NetworkInterface>> {
+    Err(Error::new(ErrorKind::Unsupported, "interfaces are only listed on Unix"))
+}
+
+/// Interfaces fleet traffic can use, best first: running before not, then
+/// physical, veth, other virtual, bridges and tunnels
+pub fn candidates() -> std::io::Result<Vec<NetworkInterface>> {
+    let mut candidates: Vec<NetworkInterface> = list()?.into_iter().filter(NetworkInterface::is_candidate).collect();
+    candidates.sort_by_key(|interface| (!interface.running, interface.kind));
+    Ok(candidates)
+}
+
+/// The interface named `wanted`, or with `wanted` as an address, or without
+/// one the best [candidate](candidates)
+pub fn select(wanted: Option<&str>) -> std::io::Result<NetworkInterface> {
+    let Some(wanted) = wanted else {
+        return candidates()?.into_iter().next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no multicast-capable interface with an IPv4 address"));
+    };
+    let interfaces = list()?;
+    if let Some(interface) = interfaces.iter().find(|interface| interface.matches(wanted)) {
+        return Ok(interface.clone());
+    }
+    let names: Vec<&str> = interfaces.iter().map(|interface| interface.name.as_str()).collect();
+    Err(Error::new(ErrorKind::NotFound, format!("no interface {}; there are {}", wanted, names.join(", "))))
+}
+
+/// The network namespace this process is in, e.g. `net:[4026531840]`; compare
+/// with another process's to tell whether they share interfaces
+#[cfg(target_os = "linux")]
+pub fn network_namespace() -> Option<String> {
+    Some(std::fs::read_link("/proc/self/ns/net").ok()?.to_string_lossy().into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn network_namespace() -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::ffi::{CStr, CString};
+
+    /// Name, flags and address of every entry `getifaddrs` returns; interfaces
+    /// appear once per address, and without an IP address at least once
+    pub(super) fn addresses() -> std::io::Result<Vec<(String, u32, Option<IpAddr>)>> {
+        let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+        // SAFETY: on success `head` is a list we own until `freeifaddrs`
+        if unsafe { libc::getifaddrs(&mut head) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        let mut entries = Vec::new();
+        let mut cursor = head;
+        // SAFETY: each entry, its name and its address are valid until the list is freed
+        while let Some(entry) = unsafe { cursor.as_ref() } {
+            let name = unsafe { CStr::from_ptr(entry.ifa_name) }.to_string_lossy().into_owned();
+            let addr = match unsafe { entry.ifa_addr.as_ref() }.map(|addr| addr.sa_family as i32) {
+                Some(libc::AF_INET) => {
+                    let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))))
+                }
+                Some(libc::AF_INET6) => {
+                    let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                    Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            };
+            entries.push((name, entry.ifa_flags, addr));
+            cursor = entry.ifa_next;
+        }
+        // SAFETY: `head` came from `getifaddrs` and isn't used again
+        unsafe { libc::freeifaddrs(head) };
+        Ok(entries)
+    }
+
+    pub(super) fn describe(name: &str, flags: u32) -> NetworkInterface {
+        let flag = |bit: libc::c_int| flags & bit as u32 != 0;
+        // SAFETY: the name is NUL-terminated; 0 means there is no such interface
+        let index = CString::new(name).map_or(0, |name| unsafe { libc::if_nametoindex(name.as_ptr()) });
+        NetworkInterface {
+            name: name.to_string(),
+            index,
+            kind: kind(name, flag(libc::IFF_LOOPBACK), flag(libc::IFF_POINTOPOINT)),
+            ipv4: Vec::new(),
+            ipv6: Vec::new(),
+            mtu: crate::receiver::interface_mtu(name).ok(),
+            up: flag(libc::IFF_UP),
+            running: flag(libc::IFF_RUNNING),
+            multicast: flag(libc::IFF_MULTICAST),
+        }
+    }
+
+    /// Told apart by what sysfs has for the interface: a `DEVTYPE`, a backing
+    /// device, or a link to another interface (a veth pair's peer)
+    #[cfg(target_os = "linux")]
+    fn kind(name: &str, loopback: bool, point_to_point: bool) -> InterfaceKind {
+        let sys = std::path::Path::new("/sys/class/net").join(name);
+        let read = |file: &str| std::fs::read_to_string(sys.join(file)).unwrap_or_default();
+        let devtype = read("uevent").lines().find_map(|line| line.strip_prefix("DEVTYPE=").map(str::to_string));
+        match devtype.as_deref() {
+            _ if loopback => InterfaceKind::Loopback,
+            Some("bridge") => InterfaceKind::Bridge,
+            Some("vxlan" | "wireguard" | "geneve" | "ipip" | "gre" | "sit") => InterfaceKind::Tunnel,
+            _ if point_to_point || sys.join("tun_flags").exists() => InterfaceKind::Tunnel,
+            _ if sys.join("device").exists() => InterfaceKind::Physical,
+            None if read("iflink").trim() != read("ifindex").trim() => InterfaceKind::Veth,
+            _ => InterfaceKind::Other,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn kind(_name: &str, loopback: bool, point_to_point: bool) -> InterfaceKind {
+        match () {
+            _ if loopback => InterfaceKind::Loopback,
+            _ if point_to_point => InterfaceKind::Tunnel,
+            _ => InterfaceKind::Other,
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loopback_is_listed_but_never_a_candidate() {
+        let interfaces = list().unwrap();
+        let lo = interfaces.iter().find(|interface| interface.kind == InterfaceKind::Loopback).unwrap();
+        assert!(lo.up && lo.ipv4.contains(&Ipv4Addr::LOCALHOST));
+        assert!(!lo.is_candidate());
+        assert!(candidates().unwrap().iter().all(|interface| interface.kind != InterfaceKind::Loopback));
+
+        assert_eq!(select(Some(&lo.name)).unwrap(), *lo);
+        assert_eq!(select(Some("127.0.0.1")).unwrap().name, lo.name);
+        assert_eq!(select(Some("no-such-nic")).unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(lo.max_datagram_len(), lo.mtu.map(|mtu| mtu - 28));
+        assert!(network_namespace().unwrap().starts_with("net:["));
+    }
+}
@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::extensions::Extensions;
+use crate::message_types;
+use crate::protocol;
+use crate::soak::{self, LatencySummary};
+use crate::store::StateStore;
+use crate::trace::TraceId;
+use crate::transport::{FleetMsgHeader, MessageType};
+
+/// One received message, as stored in a journal file (one JSON object per line)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Receiver wall clock, microseconds since the Unix epoch
+    pub received_at_us: u64,
+    pub source: String,
+    pub sender_id: u32,
+    pub msg_type: u8,
+    pub sequence: u16,
+    /// Sender wall clock from the header, microseconds since the Unix epoch
+    #[serde(default)]
+    pub sent_at_us: u64,
+    /// Millisecond send time from journals written before protocol version 2;
+    /// folded into `sent_at_us` when read
+    #[serde(default, skip_serializing)]
+    pub sent_at_ms: Option<u64>,
+    pub payload_len: u16,
+    /// The whole datagram, hex encoded, so it can be replayed or re-parsed later
+    pub frame: String,
+    /// Trace id the message carried, to follow one action across nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<TraceId>,
+}
+
+impl JournalEntry {
+    pub fn new(header: &FleetMsgHeader, payload: &[u8], addr: SocketAddr, received_at_us: u64) -> Self {
+        let mut frame = header.as_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        Self {
+            received_at_us,
+            source: addr.to_string(),
+            sender_id: header.sender_id.get(),
+            msg_type: header.msg_type & FleetMsgHeader::MSG_TYPE_MASK,
+            sequence: header.sequence.get(),
+            sent_at_us: header.timestamp_micros(),
+            sent_at_ms: None,
+            payload_len: header.payload_len.get(),
+            frame: to_hex(&frame),
+            trace_id: None,
+        }
+    }
+
+    pub fn with_trace_id(mut self, trace_id: Option<TraceId>) -> Self {
+        self.trace_id = trace_id;
+        self
+    }
+
+    /// One-way latency, limited by clock sync between the nodes
+    pub fn latency_us(&self) -> u64 {
+        self.received_at_us.saturating_sub(self.sent_at_us)
+    }
+
+    pub fn frame_bytes(&self) -> Option<Vec<u8>> {
+        from_hex(&self.frame)
+    }
+
+    /// Re-parse the stored datagram into its header and payload
+    pub fn decode(&self) -> Option<(FleetMsgHeader, Vec<u8>)> {
+        let frame = self.frame_bytes()?;
+        let header = FleetMsgHeader::read_from_prefix(&frame)?;
+        let payload = frame[protocol::HEADER_LEN..].to_vec();
+        Some((header, payload))
+    }
+}
+
+/// Appends received messages to a journal file
+#[derive(Debug)]
+pub struct JournalWriter {
+    out: BufWriter<File>,
+}
+
+impl JournalWriter {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { out: BufWriter::new(file) })
+    }
+
+    /// Record a message, stamped with the current time
+    pub fn append(&mut self, header: &FleetMsgHeader, payload: &[u8], addr: SocketAddr) -> std::io::Result<()> {
+        self.write_entry(&JournalEntry::new(header, payload, addr, soak::now_micros()))
+    }
+
+    /// Record a message from [`start_multicast_rx_extended`](crate::start_multicast_rx_extended),
+    /// keeping its trace id
+    pub fn append_extended(
+        &mut self,
+        header: &FleetMsgHeader,
+        extensions: &Extensions,
+        payload: &[u8],
+        addr: SocketAddr
+    ) -> std::io::Result<()> {
+        let entry = JournalEntry::new(header, payload, addr, soak::now_micros()).with_trace_id(extensions.trace_id());
+        self.write_entry(&entry)
+    }
+
+    pub fn write_entry(&mut self, entry: &JournalEntry) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.out, entry)?;
+        self.out.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Append `entry` to the journal `log` kept in `store` instead of a file;
+/// returns its position, for use as a read cursor
+pub fn store_entry(store: &dyn StateStore, log: &str, entry: &JournalEntry) -> std::io::Result<u64> {
+    store.append(log, &serde_json::to_vec(entry)?)
+}
+
+/// Up to `limit` entries of the journal `log` in `store`, from position `cursor` on
+pub fn read_stored(store: &dyn StateStore, log: &str, cursor: u64, limit: usize) -> std::io::Result<Vec<(u64, JournalEntry)>> {
+    store
+        .read_log(log, cursor, limit)?
+        .into_iter()
+        .map(|(position, record)| {
+            let entry = serde_json::from_slice(&record).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("journal record {}: {}", position, e))
+            })?;
+            Ok((position, entry))
+        })
+        .collect()
+}
+
+/// Load every entry of a journal file, skipping blank lines
+pub fn read_journal(path: impl AsRef<Path>) -> std::io::Result<Vec<JournalEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut entry: JournalEntry = serde_json::from_str(&line).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("journal line {}: {}", number + 1, e))
+        })?;
+        if let Some(sent_at_ms) = entry.sent_at_ms.take() {
+            entry.sent_at_us = sent_at_ms * 1000;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Rate and latency for one slice of a journal (a sender, a message type, ...)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrafficBreakdown {
+    pub messages: u64,
+    pub bytes: u64,
+    /// Messages per second, indexed from the first entry in the journal
+    pub per_second: Vec<u64>,
+    pub latency_us: LatencySummary,
+}
+
+/// Group journal entries by sender id
+pub fn breakdown_by_sender(entries: &[JournalEntry]) -> BTreeMap<u32, TrafficBreakdown> {
+    breakdown_by(entries, |entry| entry.sender_id)
+}
+
+/// Group journal entries by raw message type
+pub fn breakdown_by_type(entries: &[JournalEntry]) -> BTreeMap<u8, TrafficBreakdown> {
+    breakdown_by(entries, |entry| entry.msg_type)
+}
+
+fn breakdown_by<K: Ord>(entries: &[JournalEntry], key: impl Fn(&JournalEntry) -> K) -> BTreeMap<K, TrafficBreakdown> {
+    let start = entries.iter().map(|entry| entry.received_at_us).min().unwrap_or(0);
+    let mut groups: BTreeMap<K, (TrafficBreakdown, Vec<u64>)> = BTreeMap::new();
+
+    for entry in entries {
+        let (breakdown, latencies) = groups.entry(key(entry)).or_default();
+        breakdown.messages += 1;
+        breakdown.bytes += (protocol::HEADER_LEN + entry.payload_len as usize) as u64;
+        let second = ((entry.received_at_us - start) / 1_000_000) as usize;
+        if breakdown.per_second.len() <= second {
+            breakdown.per_second.resize(second + 1, 0);
+        }
+        breakdown.per_second[second] += 1;
+        latencies.push(entry.latency_us());
+    }
+
+    groups.into_iter()
+        .map(|(key, (mut breakdown, mut latencies))| {
+            breakdown.latency_us = LatencySummary::from_samples(&mut latencies);
+            (key, breakdown)
+        })
+        .collect()
+}
+
+/// Display name for a raw message type byte, including application types
+/// named in the [shared registry](crate::message_types::shared)
+pub fn message_type_name(msg_type: u8) -> String {
+    message_types::shared().lock().unwrap().name(MessageType::from(msg_type))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(sender_id: u32, msg_type: MessageType, received_at_us: u64) -> JournalEntry {
+        let header = FleetMsgHeader::new(msg_type, sender_id, 1, 3);
+        let mut entry = JournalEntry::new(&header, b"abc", "10.0.0.1:5000".parse().unwrap(), received_at_us);
+        entry.sent_at_us = received_at_us - 2000;
+        entry
+    }
+
+    #[test]
+    fn test_entry_round_trips_frame() {
+        let header = FleetMsgHeader::new(MessageType::Data, 7, 42, 5);
+        let entry = JournalEntry::new(&header, b"hello", "10.0.0.7:5000".parse().unwrap(), 0);
+
+        let (decoded, payload) = entry.decode().unwrap();
+        assert!(decoded.is_valid());
+        assert_eq!(decoded.sequence.get(), 42);
+        assert_eq!(payload, b"hello");
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_writer_and_reader_agree() {
+        let path = std::env::temp_dir().join(format!("fleetlink-journal-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut writer = JournalWriter::open(&path).unwrap();
+        let header = FleetMsgHeader::new(MessageType::Control, 9, 1, 2);
+        let mut extensions = Extensions::new();
+        let trace = TraceId::random();
+        extensions.set_trace_id(trace);
+        writer.append(&header, b"ok", "10.0.0.9:5000".parse().unwrap()).unwrap();
+        writer.append_extended(&header, &extensions, b"ok", "10.0.0.9:5000".parse().unwrap()).unwrap();
+        // A line from before protocol version 2, with the send time in milliseconds
+        let mut legacy = serde_json::to_value(JournalEntry::new(&header, b"ok", "10.0.0.9:5000".parse().unwrap(), 0)).unwrap();
+        legacy.as_object_mut().unwrap().remove("sent_at_us");
+        legacy["sent_at_ms"] = 1_700_000_000_123u64.into();
+        writeln!(writer.out, "{}", legacy).unwrap();
+        writer.flush().unwrap();
+
+        let entries = read_journal(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].sent_at_us, header.timestamp.get());
+        assert_eq!(entries[2].sent_at_us, 1_700_000_000_123_000);
+        assert_eq!(entries[0].sender_id, 9);
+        assert_eq!(entries[0].msg_type, MessageType::Control.code());
+        assert_eq!((entries[0].trace_id, entries[1].trace_id), (None, Some(trace)));
+
+        let store = crate::store::MemoryStore::new();
+        for entry in &entries {
+            store_entry(&store, "journal", entry).unwrap();
+        }
+        let stored = read_stored(&store, "journal", 1, 10).unwrap();
+        assert_eq!(stored.iter().map(|(position, _)| *position).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(stored[0].1.trace_id, Some(trace));
+    }
+
+    #[test]
+    fn test_breakdowns_group_by_sender_and_type() {
+        let entries = vec![
+            entry(1, MessageType::Data, 10_000_000),
+            entry(1, MessageType::Heartbeat, 10_500_000),
+            entry(2, MessageType::Data, 11_200_000),
+        ];
+
+        let senders = breakdown_by_sender(&entries);
+        assert_eq!(senders[&1].messages, 2);
+        assert_eq!(senders[&1].per_second, vec![2]);
+        assert_eq!(senders[&2].per_second, vec![0, 1]);
+        assert_eq!(senders[&2].latency_us.p50, 2000);
+
+        let types = breakdown_by_type(&entries);
+        assert_eq!(types[&MessageType::Data.code()].messages, 2);
+        assert_eq!(message_type_name(2), "Data");
+        assert_eq!(message_type_name(9), "Type 9");
+    }
+}
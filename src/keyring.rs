@@ -0,0 +1,566 @@
+//! Security material for the whole node in one file: pre-shared payload keys
+//! (PSKs), HMAC keys and peers' public keys, each under a key id, so senders and
+//! receivers are configured from the same source.
+//!
+//! ```toml
+//! [[psk]]
+//! id = "fleet-2026-10"
+//! key = "<64 hex digits>"
+//! valid_from = "2026-10-01T00:00:00Z"     # optional
+//! valid_until = "2026-11-01T00:00:00Z"    # optional
+//!
+//! [[hmac]]
+//! id = "ops-1"
+//! key = "<at least 32 hex digits>"
+//!
+//! [[peer]]
+//! sender_id = 0x42
+//! id = "robot-42-2026"
+//! algorithm = "ed25519"                   # or "x25519"
+//! public_key = "<64 hex digits>"
+//! ```
+//!
+//! A plain keyring must only be accessible to its owner (mode 0600 or
+//! stricter). With `--features keyring-age` the file may instead be sealed with
+//! [age](https://age-encryption.org), to a passphrase or an X25519 identity.
+//!
+//! Rotation works by overlapping validity windows: distribute the new key
+//! ahead of its `valid_from`, and [`Keyring::current_psk`] switches to it when
+//! the time comes, while the old one still decrypts stragglers until its
+//! `valid_until`. [`KeyringFile`] picks up a redistributed file without a restart.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use zeroize::Zeroize;
+
+use crate::journal::from_hex;
+
+/// ChaCha20-Poly1305 fleet keys are 32 bytes
+pub const PSK_LEN: usize = 32;
+/// Shorter HMAC keys are refused as too weak
+pub const MIN_HMAC_KEY_LEN: usize = 16;
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+const AGE_BINARY_MAGIC: &[u8] = b"age-encryption.org/";
+const AGE_ARMOR_MAGIC: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// A secret with its id and validity window; the bytes are wiped on drop and
+/// never printed
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretKey {
+    id: String,
+    bytes: Vec<u8>,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretKey")
+            .field("id", &self.id)
+            .field("len", &self.bytes.len())
+            .field("valid_from", &self.valid_from)
+            .field("valid_until", &self.valid_until)
+            .finish()
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl SecretKey {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The key as a PSK, e.g. for [`FeatureCodec::with_encryption_key`](crate::FeatureCodec::with_encryption_key)
+    pub fn to_psk(&self) -> Option<[u8; PSK_LEN]> {
+        self.bytes.as_slice().try_into().ok()
+    }
+
+    pub fn is_valid_at(&self, now: DateTime<Utc>) -> bool {
+        in_window(self.valid_from, self.valid_until, now)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    Ed25519,
+    X25519,
+}
+
+/// A peer's public key, for verifying what that sender signs or agreeing keys with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerKey {
+    pub sender_id: u32,
+    pub id: String,
+    pub algorithm: KeyAlgorithm,
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl PeerKey {
+    pub fn is_valid_at(&self, now: DateTime<Utc>) -> bool {
+        in_window(self.valid_from, self.valid_until, now)
+    }
+}
+
+fn in_window(from: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    from.is_none_or(|from| from <= now) && until.is_none_or(|until| now < until)
+}
+
+/// The newest key valid at `now`: a key whose window has opened takes over
+/// from the one before it
+fn newest_valid<'a, T>(
+    keys: impl Iterator<Item = &'a T>,
+    window: impl Fn(&T) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    now: DateTime<Utc>
+) -> Option<&'a T> {
+    keys.filter(|key| {
+            let (from, until) = window(key);
+            in_window(from, until, now)
+        })
+        .max_by_key(|key| window(key).0)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyringFileFormat {
+    #[serde(default)]
+    psk: Vec<SecretFile>,
+    #[serde(default)]
+    hmac: Vec<SecretFile>,
+    #[serde(default)]
+    peer: Vec<PeerFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SecretFile {
+    id: String,
+    key: String,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Debug for SecretFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretFile").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeerFile {
+    sender_id: u32,
+    id: String,
+    algorithm: KeyAlgorithm,
+    public_key: String,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+/// PSKs, HMAC keys and peer public keys, by key id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keyring {
+    psks: Vec<SecretKey>,
+    hmac_keys: Vec<SecretKey>,
+    peers: Vec<PeerKey>,
+}
+
+impl Keyring {
+    /// Load a plain keyring, refusing one that others can read or that is sealed
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let contents = read_plain(path).map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        Self::from_toml(&contents).map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    /// Load a keyring sealed with age
+    #[cfg(feature = "keyring-age")]
+    pub fn load_sealed(path: impl AsRef<Path>, seal: &SealKey) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        std::fs::read(path)
+            .and_then(|sealed| seal.open(&sealed))
+            .and_then(|contents| Self::from_toml(&contents))
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn from_toml(text: &str) -> std::io::Result<Self> {
+        // Only the message: the full error quotes the offending line, which may hold a key
+        let file: KeyringFileFormat = toml::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.message().to_string()))?;
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, msg);
+
+        let mut keyring = Keyring::default();
+        for (section, entries, keys) in [("psk", &file.psk, &mut keyring.psks), ("hmac", &file.hmac, &mut keyring.hmac_keys)] {
+            for entry in entries {
+                if keys.iter().any(|key: &SecretKey| key.id == entry.id) {
+                    return Err(invalid(format!("{} '{}' is defined twice", section, entry.id)));
+                }
+                let bytes = from_hex(entry.key.trim()).ok_or_else(|| invalid(format!("{} '{}' key is not hex", section, entry.id)))?;
+                let key = SecretKey { id: entry.id.clone(), bytes, valid_from: entry.valid_from, valid_until: entry.valid_until };
+                match section {
+                    "psk" if key.bytes.len() != PSK_LEN => {
+                        return Err(invalid(format!("psk '{}' must be {} bytes, not {}", key.id, PSK_LEN, key.bytes.len())));
+                    }
+                    "hmac" if key.bytes.len() < MIN_HMAC_KEY_LEN => {
+                        return Err(invalid(format!("hmac '{}' must be at least {} bytes", key.id, MIN_HMAC_KEY_LEN)));
+                    }
+                    _ => keys.push(key),
+                }
+            }
+        }
+        for peer in &file.peer {
+            if keyring.peers.iter().any(|p| p.sender_id == peer.sender_id && p.id == peer.id) {
+                return Err(invalid(format!("peer {:#x} key '{}' is defined twice", peer.sender_id, peer.id)));
+            }
+            let public_key = from_hex(peer.public_key.trim())
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| invalid(format!("peer {:#x} key '{}' must be {} hex bytes", peer.sender_id, peer.id, PUBLIC_KEY_LEN)))?;
+            keyring.peers.push(PeerKey {
+                sender_id: peer.sender_id,
+                id: peer.id.clone(),
+                algorithm: peer.algorithm,
+                public_key,
+                valid_from: peer.valid_from,
+                valid_until: peer.valid_until,
+            });
+        }
+        Ok(keyring)
+    }
+
+    pub fn psk(&self, id: &str) -> Option<&SecretKey> {
+        self.psks.iter().find(|key| key.id == id)
+    }
+
+    /// The PSK to send with at `now`
+    pub fn current_psk(&self, now: DateTime<Utc>) -> Option<&SecretKey> {
+        newest_valid(self.psks.iter(), |key| (key.valid_from, key.valid_until), now)
+    }
+
+    pub fn hmac_key(&self, id: &str) -> Option<&SecretKey> {
+        self.hmac_keys.iter().find(|key| key.id == id)
+    }
+
+    /// The HMAC key to sign with at `now`
+    pub fn current_hmac_key(&self, now: DateTime<Utc>) -> Option<&SecretKey> {
+        newest_valid(self.hmac_keys.iter(), |key| (key.valid_from, key.valid_until), now)
+    }
+
+    /// Every key listed for `sender_id`, whatever its window
+    pub fn peer_keys(&self, sender_id: u32) -> impl Iterator<Item = &PeerKey> {
+        self.peers.iter().filter(move |peer| peer.sender_id == sender_id)
+    }
+
+    /// The key `sender_id` should be using at `now`
+    pub fn current_peer_key(&self, sender_id: u32, now: DateTime<Utc>) -> Option<&PeerKey> {
+        newest_valid(self.peer_keys(sender_id), |key| (key.valid_from, key.valid_until), now)
+    }
+
+    /// Every key id, qualified by kind: `psk/<id>`, `hmac/<id>`, `peer/<sender_id>/<id>`
+    pub fn key_ids(&self) -> BTreeSet<String> {
+        let psks = self.psks.iter().map(|key| format!("psk/{}", key.id));
+        let hmac = self.hmac_keys.iter().map(|key| format!("hmac/{}", key.id));
+        let peers = self.peers.iter().map(|peer| format!("peer/{}/{}", peer.sender_id, peer.id));
+        psks.chain(hmac).chain(peers).collect()
+    }
+}
+
+fn is_sealed(contents: &[u8]) -> bool {
+    contents.starts_with(AGE_BINARY_MAGIC) || contents.starts_with(AGE_ARMOR_MAGIC)
+}
+
+fn read_plain(path: &Path) -> std::io::Result<String> {
+    let contents = std::fs::read(path)?;
+    if is_sealed(&contents) {
+        return Err(Error::new(ErrorKind::InvalidInput, "keyring is sealed with age; load it with its passphrase or identity"));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(Error::new(ErrorKind::PermissionDenied, format!(
+                "keyring is accessible to group or others (mode {:o}); chmod 600 it", mode & 0o777
+            )));
+        }
+    }
+    String::from_utf8(contents).map_err(|_| Error::new(ErrorKind::InvalidData, "keyring is not UTF-8 text"))
+}
+
+/// What opens an age-sealed keyring
+#[cfg(feature = "keyring-age")]
+#[derive(Clone)]
+pub enum SealKey {
+    /// Sealed with `age --passphrase`
+    Passphrase(String),
+    /// Sealed to an X25519 recipient; the matching `AGE-SECRET-KEY-1...` identity
+    Identity(String),
+}
+
+#[cfg(feature = "keyring-age")]
+impl std::fmt::Debug for SealKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SealKey::Passphrase(_) => f.write_str("SealKey::Passphrase(..)"),
+            SealKey::Identity(_) => f.write_str("SealKey::Identity(..)"),
+        }
+    }
+}
+
+#[cfg(feature = "keyring-age")]
+impl Drop for SealKey {
+    fn drop(&mut self) {
+        match self {
+            SealKey::Passphrase(secret) | SealKey::Identity(secret) => secret.zeroize(),
+        }
+    }
+}
+
+#[cfg(feature = "keyring-age")]
+impl SealKey {
+    /// Read the identity from an `age-keygen` key file
+    pub fn from_identity_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        contents.lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+            .map(|line| SealKey::Identity(line.to_string()))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no AGE-SECRET-KEY line in identity file"))
+    }
+
+    fn open(&self, sealed: &[u8]) -> std::io::Result<String> {
+        use age::secrecy::SecretString;
+
+        let failed = |e: age::DecryptError| Error::new(ErrorKind::PermissionDenied, format!("can't unseal keyring: {}", e));
+        let mut contents = match self {
+            SealKey::Passphrase(passphrase) => {
+                age::decrypt(&age::scrypt::Identity::new(SecretString::from(passphrase.clone())), sealed).map_err(failed)?
+            }
+            SealKey::Identity(identity) => {
+                let identity: age::x25519::Identity = identity.parse()
+                    .map_err(|e: &str| Error::new(ErrorKind::InvalidInput, format!("bad age identity: {}", e)))?;
+                age::decrypt(&identity, sealed).map_err(failed)?
+            }
+        };
+        let text = String::from_utf8(contents.clone()).map_err(|_| Error::new(ErrorKind::InvalidData, "keyring is not UTF-8 text"));
+        contents.zeroize();
+        text
+    }
+}
+
+/// Key ids that appeared or went away in a reload
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyringChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// A keyring file that is re-read when it changes on disk, for rotation
+/// without restarting. Readers take the current [`Keyring`] with
+/// [`keyring`](Self::keyring); a file that fails to load is reported and the
+/// previous keyring stays in use.
+#[derive(Debug)]
+pub struct KeyringFile {
+    path: PathBuf,
+    #[cfg(feature = "keyring-age")]
+    seal: Option<SealKey>,
+    current: RwLock<Arc<Keyring>>,
+    /// Modification time and length the current keyring was read at
+    stamp: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl KeyringFile {
+    /// Load a plain keyring that will be reloaded from `path`
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let stamp = file_stamp(&path);
+        let keyring = Keyring::load(&path)?;
+        Ok(Self {
+            path,
+            #[cfg(feature = "keyring-age")]
+            seal: None,
+            current: RwLock::new(Arc::new(keyring)),
+            stamp: Mutex::new(stamp),
+        })
+    }
+
+    /// Load an age-sealed keyring that will be reloaded from `path`
+    #[cfg(feature = "keyring-age")]
+    pub fn open_sealed(path: impl Into<PathBuf>, seal: SealKey) -> std::io::Result<Self> {
+        let path = path.into();
+        let stamp = file_stamp(&path);
+        let keyring = Keyring::load_sealed(&path, &seal)?;
+        Ok(Self { path, seal: Some(seal), current: RwLock::new(Arc::new(keyring)), stamp: Mutex::new(stamp) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn keyring(&self) -> Arc<Keyring> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn load(&self) -> std::io::Result<Keyring> {
+        #[cfg(feature = "keyring-age")]
+        if let Some(seal) = &self.seal {
+            return Keyring::load_sealed(&self.path, seal);
+        }
+        Keyring::load(&self.path)
+    }
+
+    /// Re-read the file if it changed since it was last read; `None` if it didn't
+    pub fn reload_if_changed(&self) -> std::io::Result<Option<KeyringChange>> {
+        let stamp = file_stamp(&self.path);
+        if stamp.is_none() || stamp == *self.stamp.lock().unwrap() {
+            return Ok(None);
+        }
+        let keyring = self.load();
+        // Whatever the outcome, don't retry until the file changes again
+        *self.stamp.lock().unwrap() = stamp;
+        let keyring = keyring?;
+
+        let (before, after) = (self.keyring().key_ids(), keyring.key_ids());
+        let change = KeyringChange {
+            added: after.difference(&before).cloned().collect(),
+            removed: before.difference(&after).cloned().collect(),
+        };
+        *self.current.write().unwrap() = Arc::new(keyring);
+        Ok(Some(change))
+    }
+
+    /// Check for changes every `interval`, forever
+    pub async fn watch(&self, interval: Duration, mut on_change: impl FnMut(&KeyringChange)) {
+        loop {
+            async_std::task::sleep(interval).await;
+            match self.reload_if_changed() {
+                Ok(Some(change)) => {
+                    println!("Reloaded keyring {}: +{:?} -{:?}", self.path.display(), change.added, change.removed);
+                    on_change(&change);
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Keeping the previous keyring: {}", e),
+            }
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYRING: &str = r#"
+        [[psk]]
+        id = "2026-09"
+        key = "0101010101010101010101010101010101010101010101010101010101010101"
+        valid_until = "2026-10-02T00:00:00Z"
+
+        [[psk]]
+        id = "2026-10"
+        key = "0202020202020202020202020202020202020202020202020202020202020202"
+        valid_from = "2026-10-01T00:00:00Z"
+
+        [[hmac]]
+        id = "ops"
+        key = "00112233445566778899aabbccddeeff"
+
+        [[peer]]
+        sender_id = 0x42
+        id = "robot-42"
+        algorithm = "ed25519"
+        public_key = "abababababababababababababababababababababababababababababababab"
+    "#;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_rotation() {
+        let keyring = Keyring::from_toml(KEYRING).unwrap();
+        // The new key takes over as soon as it's valid; the old one still decrypts until it expires
+        assert_eq!(keyring.current_psk(at("2026-09-20T00:00:00Z")).unwrap().id(), "2026-09");
+        assert_eq!(keyring.current_psk(at("2026-10-01T12:00:00Z")).unwrap().id(), "2026-10");
+        assert!(keyring.psk("2026-09").unwrap().is_valid_at(at("2026-10-01T12:00:00Z")));
+        assert_eq!(keyring.psk("2026-10").unwrap().to_psk(), Some([2; 32]));
+        assert_eq!(keyring.current_hmac_key(Utc::now()).unwrap().bytes().len(), 16);
+        assert_eq!(keyring.current_peer_key(0x42, Utc::now()).unwrap().algorithm, KeyAlgorithm::Ed25519);
+        assert!(!format!("{:?}", keyring).contains("0202"));
+
+        let short_hmac = "[[hmac]]\nid = \"x\"\nkey = \"0011\"\n";
+        assert_eq!(Keyring::from_toml(short_hmac).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let twice = format!("{}\n[[hmac]]\nid = \"ops\"\nkey = \"00112233445566778899aabbccddeeff\"\n", KEYRING);
+        assert_eq!(Keyring::from_toml(&twice).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(Keyring::from_toml("[[psk]]\nid = \"x\"\nkeey = \"00\"\n").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permissions_and_reload() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("fleetlink-keyring-{}.toml", std::process::id()));
+        std::fs::write(&path, KEYRING).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(KeyringFile::open(&path).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let file = KeyringFile::open(&path).unwrap();
+        assert_eq!(file.reload_if_changed().unwrap(), None);
+
+        let rotated = KEYRING.replacen("id = \"2026-09\"", "id = \"2026-11\"", 1).replacen("\"ops\"", "\"ops-2\"", 1) + "\n";
+        std::fs::write(&path, &rotated).unwrap();
+        let change = file.reload_if_changed().unwrap().unwrap();
+        assert_eq!(change.added, ["hmac/ops-2", "psk/2026-11"]);
+        assert_eq!(change.removed, ["hmac/ops", "psk/2026-09"]);
+
+        // A broken file keeps the last good keyring
+        std::fs::write(&path, "[[psk]]\nid = \"broken\"\n").unwrap();
+        assert!(file.reload_if_changed().is_err());
+        assert!(file.keyring().psk("2026-11").is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "keyring-age")]
+    #[test]
+    fn test_sealed_keyring() {
+        use age::secrecy::ExposeSecret;
+
+        let identity = age::x25519::Identity::generate();
+        let sealed = age::encrypt(&identity.to_public(), KEYRING.as_bytes()).unwrap();
+        let path = std::env::temp_dir().join(format!("fleetlink-keyring-{}.age", std::process::id()));
+        std::fs::write(&path, sealed).unwrap();
+
+        assert_eq!(Keyring::load(&path).unwrap_err().kind(), ErrorKind::InvalidInput);
+        let seal = SealKey::Identity(identity.to_string().expose_secret().to_string());
+        assert!(Keyring::load_sealed(&path, &seal).unwrap().psk("2026-10").is_some());
+        let wrong = SealKey::Identity(age::x25519::Identity::generate().to_string().expose_secret().to_string());
+        assert_eq!(Keyring::load_sealed(&path, &wrong).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
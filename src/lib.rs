@@ -1,39 +1,159 @@
-pub mod transport;
+//! UDP multicast transport for fleet communication.
+//!
+//! Most applications only need the [`prelude`]. The modules are grouped by
+//! concern in [`protocol`], [`net`], [`reliability`], [`discovery`],
+//! [`coordination`], [`security`] and [`stats`]; the commonly used types are
+//! also re-exported at the root.
+
+pub mod protocol;
+pub mod error;
+pub mod stats;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+pub mod admin;
+#[cfg(feature = "tools")]
+pub mod daemon;
+#[cfg(unix)]
+pub mod systemd;
+#[cfg(windows)]
+pub mod winservice;
+pub mod sim;
+pub mod soak;
+pub mod bench_history;
+pub mod orchestrator;
+pub mod alloc_counter;
+pub mod rng;
+pub mod prelude;
+#[cfg(feature = "c-reference")]
+pub mod c_reference;
+
+// The grouped modules are declared in their group; these keep the short
+// paths working inside the crate
+#[cfg(feature = "compression-dict")]
+pub(crate) use protocol::dictionary;
+pub(crate) use protocol::{capabilities, extensions, features, fragment, message, message_types, padding, timing, trace};
+pub(crate) use stats::{alerts, sequence_stats, usage};
+pub(crate) use net::{addressing, channels, mirror, power, receiver, scheduling, socket_filter, tap, transform, transport, zones};
+#[cfg(feature = "bridge")]
+pub(crate) use net::bridge;
+pub(crate) use reliability::{bandwidth, shaping, store, tdma};
+// Still reachable here for the tools that open journals directly
+pub use reliability::{backfill, compaction, journal};
+pub(crate) use discovery::{heartbeat, peers};
+#[cfg(feature = "discovery")]
+pub(crate) use discovery::membership;
+pub(crate) use coordination::rpc;
+#[cfg(feature = "crypto")]
+pub(crate) use security::{crypto, handshake, session};
 
 pub use transport::{
-    FleetMsgHeader, MessageType, MulticastSender, start_multicast_rx
+    FleetMsgHeader, HeaderChecksum, MessageType, MulticastReceiver, MulticastSender, TagRouting, heartbeat_capabilities, heartbeat_incarnation,
+    start_multicast_rx, start_multicast_rx_borrowed, start_multicast_rx_extended, start_multicast_rx_filtered, start_multicast_rx_groups,
+    start_multicast_rx_with_codec,
+    tag_group
 };
+pub use error::TransportError;
+pub use message::FleetMessage;
+pub use message_types::MessageTypeRegistry;
+pub use receiver::{BorrowedDelivery, Delivery, ReceiverConfig, ReceiverFilter, ValidationIssue, ValidationPolicy};
+pub use tap::{FrameTap, Sampling, TapSubscription, TappedFrame};
+pub use tdma::SlotSchedule;
+pub use bandwidth::{BandwidthManager, MessageClass};
+pub use stats::{StatsSnapshot, TransportStats};
+pub use sequence_stats::{SenderSequenceStats, SequenceAnalyzer, SequenceEvent, SequenceEvents, SequenceOutcome, SequenceTracker};
+pub use alerts::{Alert, AlertEvent, AlertMonitor, AlertThresholds};
+pub use peers::{PeerEvent, PeerEvents, PeerInfo, PeerTable};
+#[cfg(feature = "discovery")]
+pub use membership::{Membership, MembershipEvent, Roster};
+pub use capabilities::Capabilities;
+pub use features::{FeatureCodec, ProtocolFeatures};
+#[cfg(feature = "compression-dict")]
+pub use dictionary::CompressionDictionary;
+pub use extensions::Extensions;
+pub use trace::TraceId;
+pub use heartbeat::{HeartbeatHandle, Liveness};
+pub use timing::{DelayEstimator, SendTimestamps};
+pub use addressing::{AddressPlan, GroupJoins, GroupScope};
+pub use zones::{Zone, ZoneFilter};
+pub use rpc::{ControlClient, ControlResponder, Response};
+pub use channels::{Channel, ChannelRegistry};
+pub use admin::{AdminCommand, AdminRequest, AdminResponse, AdminState};
+pub use journal::{JournalEntry, JournalWriter};
 
-use std::net::Ipv4Addr;
+/// Moving datagrams: multicast, the local transports and the bridges to other systems
+#[path = "."]
+pub mod net {
+    pub mod transport;
+    pub mod receiver;
+    pub mod addressing;
+    pub mod channels;
+    pub mod interfaces;
+    pub mod zones;
+    pub mod power;
+    pub mod scheduling;
+    pub mod socket_filter;
+    pub mod tap;
+    pub mod mirror;
+    pub mod transform;
+    pub mod schema;
+    pub mod lora;
+    pub mod shm;
+    #[cfg(unix)]
+    pub mod uds;
+    #[cfg(all(target_os = "linux", feature = "af-xdp"))]
+    pub mod xdp;
+    #[cfg(feature = "bridge")]
+    pub mod bridge;
+    #[cfg(feature = "ws-gateway")]
+    pub mod gateway;
+    #[cfg(feature = "zenoh")]
+    pub mod zenoh_adapter;
+}
 
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
+/// Getting traffic through a shared, lossy link, and recording it for later
+#[path = "."]
+pub mod reliability {
+    pub mod tdma;
+    pub mod bandwidth;
+    pub mod shaping;
+    pub mod journal;
+    pub mod backfill;
+    pub mod compaction;
+    pub mod idempotency;
+    pub mod store;
+    pub mod replay;
 }
 
-/// Example function showing how to use the multicast transport
-/// Note: This is just a demonstration - in practice you'd use async_std::main
-/// or integrate with your preferred async runtime
-pub async fn run_example() -> std::io::Result<()> {
-    let group = Ipv4Addr::new(239, 1, 1, 1);
-    let port = 12345;
-
-    // Example message handler
-    let handler = |header: FleetMsgHeader, payload: Vec<u8>, addr: std::net::SocketAddr| {
-        println!("Received {:?} from {}: {} bytes",
-                 header.message_type(), addr, payload.len());
-    };
-
-    // Start multicast receiver (this would run indefinitely)
-    start_multicast_rx(group, port, handler).await
+/// Who is on the network and what they can do
+#[path = "."]
+pub mod discovery {
+    pub use crate::protocol::capabilities;
+    pub mod heartbeat;
+    pub mod peers;
+    #[cfg(feature = "discovery")]
+    pub mod membership;
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Acting on the fleet as a whole
+#[path = "."]
+pub mod coordination {
+    pub mod rollout;
+    pub mod commit;
+    pub mod barrier;
+    pub mod counter;
+    pub mod rpc;
+    #[cfg(feature = "discovery")]
+    pub mod failover;
+}
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
+/// Keys, encryption and peer authentication
+#[path = "."]
+pub mod security {
+    pub mod keyring;
+    #[cfg(feature = "crypto")]
+    pub mod crypto;
+    #[cfg(feature = "crypto")]
+    pub mod handshake;
+    #[cfg(feature = "crypto")]
+    pub mod session;
 }
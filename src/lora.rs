@@ -0,0 +1,277 @@
+//! Fallback transport over LoRa serial modems: the same headers and receive
+//! validation as multicast, over a link with a tiny MTU and a regulatory duty
+//! cycle.
+//!
+//! Frames are SLIP-delimited on the serial line, one radio packet each, for
+//! transparent-mode modems that transmit whatever is written to them. Open the
+//! modem's serial device (with its baud rate already set, e.g. by `stty`) and
+//! hand it over as the reader or writer.
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zerocopy::AsBytes;
+
+use crate::protocol::HEADER_LEN;
+use crate::receiver::{self, Delivery, ReceiverConfig};
+use crate::stats::TransportStats;
+use crate::transport::{FleetMsgHeader, MessageType};
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Deliveries over LoRa have no IP source; they carry this address instead
+pub const LORA_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// Modulation settings, which decide how long each packet occupies the channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoraRadio {
+    /// 7 to 12
+    pub spreading_factor: u8,
+    pub bandwidth_hz: u32,
+    /// Denominator of the 4/x coding rate, 5 to 8
+    pub coding_rate: u8,
+    pub preamble_symbols: u16,
+}
+
+impl Default for LoraRadio {
+    /// SF9, 125 kHz, 4/5, 8 preamble symbols
+    fn default() -> Self {
+        Self { spreading_factor: 9, bandwidth_hz: 125_000, coding_rate: 5, preamble_symbols: 8 }
+    }
+}
+
+impl LoraRadio {
+    /// Time on air of a packet of `len` bytes with an explicit header and CRC (Semtech AN1200.13)
+    pub fn time_on_air(&self, len: usize) -> Duration {
+        let sf = self.spreading_factor.clamp(6, 12) as f64;
+        let symbol = 2f64.powf(sf) / self.bandwidth_hz as f64;
+        // Low data rate optimisation is mandatory once symbols exceed 16 ms
+        let low_rate = if symbol > 0.016 { 1.0 } else { 0.0 };
+        let preamble = (self.preamble_symbols as f64 + 4.25) * symbol;
+        let coded = ((8.0 * len as f64 - 4.0 * sf + 28.0 + 16.0) / (4.0 * (sf - 2.0 * low_rate))).ceil();
+        let payload_symbols = 8.0 + (coded * self.coding_rate.clamp(5, 8) as f64).max(0.0);
+        Duration::from_secs_f64(preamble + payload_symbols * symbol)
+    }
+}
+
+/// What the radio link allows: packet size and share of airtime
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoraLink {
+    pub radio: LoraRadio,
+    /// Largest packet the modem sends in one transmission
+    pub mtu: usize,
+    /// Share of time this node may transmit, e.g. 0.01 in most EU868 sub-bands
+    pub duty_cycle: f64,
+}
+
+impl Default for LoraLink {
+    /// [`LoraRadio::default`], 58-byte packets as on common transparent modems, 1% duty cycle
+    fn default() -> Self {
+        Self { radio: LoraRadio::default(), mtu: 58, duty_cycle: 0.01 }
+    }
+}
+
+impl LoraLink {
+    /// Largest payload that fits one packet, if SLIP doesn't need to escape any byte
+    pub fn max_payload_len(&self) -> usize {
+        self.mtu.saturating_sub(HEADER_LEN + 2)
+    }
+
+    /// Channel time of one message with a `payload_len` byte payload
+    pub fn airtime(&self, payload_len: usize) -> Duration {
+        self.radio.time_on_air(HEADER_LEN + payload_len + 2)
+    }
+
+    /// Shortest spacing between such messages that stays within the duty cycle
+    pub fn min_interval(&self, payload_len: usize) -> Duration {
+        self.airtime(payload_len).div_f64(self.duty_cycle.clamp(f64::MIN_POSITIVE, 1.0))
+    }
+
+    /// How many such messages the duty cycle allows per hour
+    pub fn messages_per_hour(&self, payload_len: usize) -> u64 {
+        (3600.0 / self.min_interval(payload_len).as_secs_f64()) as u64
+    }
+}
+
+/// Sends fleet messages through a LoRa modem, one packet each, never faster
+/// than the duty cycle allows
+pub struct LoraSender<W> {
+    writer: W,
+    link: LoraLink,
+    sender_id: u32,
+    sequence: u16,
+    /// Earliest time the duty cycle allows the next transmission
+    next_allowed: Option<Instant>,
+    stats: Arc<TransportStats>,
+}
+
+impl<W: AsyncWrite + Unpin> LoraSender<W> {
+    pub fn new(writer: W, link: LoraLink, sender_id: u32) -> Self {
+        Self { writer, link, sender_id, sequence: 0, next_allowed: None, stats: Arc::new(TransportStats::new()) }
+    }
+
+    /// Count sends in shared stats, e.g. the ones a multicast sender on the same node uses
+    pub fn with_stats(mut self, stats: Arc<TransportStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn link(&self) -> &LoraLink {
+        &self.link
+    }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+
+    /// How long until the duty cycle allows another transmission
+    pub fn time_until_clear(&self) -> Duration {
+        self.next_allowed.map_or(Duration::ZERO, |at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Send one message, waiting out the duty cycle first. Payloads over the
+    /// link's budget are refused rather than split across packets.
+    pub async fn send_message(&mut self, msg_type: MessageType, payload: &[u8]) -> std::io::Result<()> {
+        let header = FleetMsgHeader::new(msg_type, self.sender_id, self.sequence, payload.len() as u16);
+        let packet = slip_encode(&[header.as_bytes(), payload].concat());
+        if packet.len() > self.link.mtu {
+            return Err(Error::new(ErrorKind::InvalidInput, format!(
+                "{} byte payload makes a {} byte packet, over the {} byte LoRa MTU (budget {} payload bytes)",
+                payload.len(), packet.len(), self.link.mtu, self.link.max_payload_len()
+            )));
+        }
+
+        let wait = self.time_until_clear();
+        if !wait.is_zero() {
+            async_std::task::sleep(wait).await;
+        }
+        self.writer.write_all(&packet).await?;
+        self.writer.flush().await?;
+
+        let airtime = self.link.radio.time_on_air(packet.len());
+        self.next_allowed = Some(Instant::now() + airtime.div_f64(self.link.duty_cycle.clamp(f64::MIN_POSITIVE, 1.0)));
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stats.record_sent(packet.len());
+        Ok(())
+    }
+
+    pub async fn send_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.send_message(MessageType::Data, data).await
+    }
+
+    pub async fn send_control(&mut self, command: &str) -> std::io::Result<()> {
+        self.send_message(MessageType::Control, command.as_bytes()).await
+    }
+}
+
+/// Receive fleet messages from a LoRa modem until it is closed, validating
+/// them as `config` says, exactly like a multicast receiver
+pub async fn start_lora_rx(
+    mut reader: impl AsyncRead + Unpin,
+    config: ReceiverConfig,
+    mut message_handler: impl FnMut(Delivery)
+) -> std::io::Result<()> {
+    let mut decoder = SlipDecoder::default();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = reader.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        for &byte in &buf[..len] {
+            let Some(frame) = decoder.push(byte).filter(|frame| config.screen(frame, LORA_ADDR)) else {
+                continue;
+            };
+            match receiver::inspect(&frame, LORA_ADDR, &config) {
+                Ok(delivery) => message_handler(delivery),
+                Err(issues) => eprintln!("Dropped LoRa frame: {}", receiver::describe(&issues)),
+            }
+        }
+    }
+}
+
+fn slip_encode(frame: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(frame.len() + 2);
+    packet.push(SLIP_END);
+    for &byte in frame {
+        match byte {
+            SLIP_END => packet.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => packet.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            byte => packet.push(byte),
+        }
+    }
+    packet.push(SLIP_END);
+    packet
+}
+
+/// Reassembles frames from the serial byte stream, resynchronising at the next
+/// frame boundary after line noise
+#[derive(Debug, Default)]
+struct SlipDecoder {
+    frame: Vec<u8>,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if byte == SLIP_END {
+            self.escaped = false;
+            return (!self.frame.is_empty()).then(|| std::mem::take(&mut self.frame));
+        }
+        let byte = match (std::mem::take(&mut self.escaped), byte) {
+            (false, SLIP_ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (true, SLIP_ESC_END) => SLIP_END,
+            (true, SLIP_ESC_ESC) => SLIP_ESC,
+            (_, byte) => byte,
+        };
+        self.frame.push(byte);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[test]
+    fn test_airtime_and_budget() {
+        let sf7 = LoraRadio { spreading_factor: 7, ..Default::default() };
+        assert_eq!(sf7.time_on_air(10).as_micros(), 41_216);
+
+        let link = LoraLink::default();
+        assert_eq!(link.max_payload_len(), 32);
+        // SF9 with a full packet: ~0.37 s on air, so one every ~37 s at 1%
+        assert!((36.0..38.0).contains(&link.min_interval(32).as_secs_f64()));
+        assert_eq!(link.messages_per_hour(32), 97);
+    }
+
+    #[async_std::test]
+    async fn test_messages_survive_the_serial_line() {
+        let link = LoraLink { duty_cycle: 1.0, ..Default::default() };
+        let mut sender = LoraSender::new(Cursor::new(Vec::new()), link, 9);
+        // Both SLIP special bytes in the payload
+        sender.send_data(&[SLIP_END, 1, SLIP_ESC, 2]).await.unwrap();
+        sender.send_control("STOP").await.unwrap();
+        assert!(sender.send_data(&[0; 33]).await.is_err());
+        assert_eq!(sender.stats().snapshot().messages_sent, 2);
+
+        // Noise on the line before the first frame is discarded
+        let line = [&[0x55, 0xAA][..], sender.writer.get_ref()].concat();
+        let mut received = Vec::new();
+        start_lora_rx(Cursor::new(line), ReceiverConfig::new(), |delivery| received.push(delivery)).await.unwrap();
+
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].payload, [SLIP_END, 1, SLIP_ESC, 2]);
+        assert_eq!((received[1].header.sender_id.get(), received[1].header.sequence.get()), (9, 1));
+        assert_eq!(received[1].header.message_type(), MessageType::Control);
+    }
+}
@@ -0,0 +1,640 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::alloc_counter::{self, Subsystem};
+use crate::transport::{self, FleetMsgHeader, MessageType};
+
+/// Bytes per member in a digest: sender_id, incarnation (0 = unknown), state, age in ms
+const DIGEST_ENTRY_LEN: usize = 17;
+
+/// Members per `Digest` message, keeping each one well inside a 1500-byte MTU
+pub const DIGEST_ENTRIES_PER_MESSAGE: usize = 64;
+
+/// Changes in who this node can hear
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MembershipEvent {
+    PeerUp { sender_id: u32 },
+    /// Nothing heard from the peer for longer than the peer timeout
+    PeerDown { sender_id: u32 },
+    /// The peer announced a clean shutdown
+    PeerDeparted { sender_id: u32 },
+    /// The peer's heartbeat announced a new incarnation: it restarted, so its
+    /// sequence numbers started over
+    PeerRestarted { sender_id: u32, incarnation: u64 },
+    /// Only part of the roster is reachable. Raised again whenever the missing set changes.
+    PartitionSuspected {
+        visible: BTreeSet<u32>,
+        missing: BTreeSet<u32>,
+        /// Whether the visible side (this node included) is a strict majority of the roster
+        has_quorum: bool,
+    },
+    PartitionHealed,
+    /// A roster member hasn't been heard at all within the absence threshold of startup
+    NeverSeen {
+        sender_id: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// A roster member has been down for longer than the absence threshold
+    Absent {
+        sender_id: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        last_seen_secs_ago: f64,
+    },
+}
+
+/// The sender ids expected on a site, optionally with names.
+///
+/// Roster files are TOML, listing bare ids, named nodes, or both:
+///
+/// ```toml
+/// site = "north-yard"
+/// sender_ids = [1, 2, 3]
+///
+/// [[nodes]]
+/// sender_id = 7
+/// name = "truck-07"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Roster {
+    pub site: Option<String>,
+    members: BTreeMap<u32, Option<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RosterFile {
+    site: Option<String>,
+    #[serde(default)]
+    sender_ids: Vec<u32>,
+    #[serde(default)]
+    nodes: Vec<RosterNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RosterNode {
+    sender_id: u32,
+    name: Option<String>,
+}
+
+impl Roster {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Self::from_toml(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn from_toml(text: &str) -> std::io::Result<Self> {
+        let file: RosterFile = toml::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        let mut roster = Roster { site: file.site, members: BTreeMap::new() };
+        let entries = file.sender_ids.into_iter().map(|id| (id, None))
+            .chain(file.nodes.into_iter().map(|node| (node.sender_id, node.name)));
+        for (sender_id, name) in entries {
+            if roster.members.contains_key(&sender_id) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("sender_id {} is listed twice", sender_id)));
+            }
+            if let Some(name) = &name
+                && roster.sender_id(name).is_some()
+            {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("name '{}' is used twice", name)));
+            }
+            roster.members.insert(sender_id, name);
+        }
+        Ok(roster)
+    }
+
+    pub fn insert(&mut self, sender_id: u32, name: Option<String>) {
+        self.members.insert(sender_id, name);
+    }
+
+    pub fn contains(&self, sender_id: u32) -> bool {
+        self.members.contains_key(&sender_id)
+    }
+
+    pub fn name(&self, sender_id: u32) -> Option<&str> {
+        self.members.get(&sender_id).and_then(|name| name.as_deref())
+    }
+
+    pub fn sender_id(&self, name: &str) -> Option<u32> {
+        self.members.iter().find(|(_, n)| n.as_deref() == Some(name)).map(|(&id, _)| id)
+    }
+
+    pub fn sender_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.members.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl FromIterator<u32> for Roster {
+    fn from_iter<I: IntoIterator<Item = u32>>(ids: I) -> Self {
+        Roster { site: None, members: ids.into_iter().map(|id| (id, None)).collect() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberState {
+    Alive,
+    Down,
+    /// Left with a `Goodbye`; not counted as missing when checking for partitions
+    Departed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub sender_id: u32,
+    pub state: MemberState,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    /// From the latest heartbeat, if the peer announces one
+    pub incarnation: Option<u64>,
+    /// Sequence number of the latest message from the current incarnation
+    pub last_sequence: u16,
+}
+
+/// A member as one node sees it, for handing over to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberSnapshot {
+    pub sender_id: u32,
+    pub state: MemberState,
+    pub incarnation: Option<u64>,
+    pub last_sequence: u16,
+    /// How long ago the member was last heard
+    pub silent_ms: u64,
+}
+
+/// Liveness of every peer this node hears, checked against the expected fleet.
+///
+/// Feed it every valid message with [`Membership::observe`] and call
+/// [`Membership::tick`] periodically (e.g. once per heartbeat interval) to
+/// time out silent peers.
+#[derive(Debug)]
+pub struct Membership {
+    local_id: u32,
+    peer_timeout: Duration,
+    roster: Roster,
+    partition_threshold: usize,
+    absence_threshold: Duration,
+    members: BTreeMap<u32, Member>,
+    started: Instant,
+    /// Missing set from the last `PartitionSuspected`, while one is in effect
+    partition: Option<BTreeSet<u32>>,
+    /// Roster members already reported as never seen or absent, until they return
+    alarmed: BTreeSet<u32>,
+}
+
+impl Membership {
+    pub fn new(local_id: u32, peer_timeout: Duration, now: Instant) -> Self {
+        Self {
+            local_id,
+            peer_timeout,
+            roster: Roster::default(),
+            partition_threshold: 2,
+            absence_threshold: Duration::from_secs(60),
+            members: BTreeMap::new(),
+            started: now,
+            partition: None,
+            alarmed: BTreeSet::new(),
+        }
+    }
+
+    /// Nodes expected on this site; enables partition detection and absence alarms
+    pub fn with_roster(mut self, roster: Roster) -> Self {
+        self.roster = roster;
+        self
+    }
+
+    /// How long a roster member may be missing before `NeverSeen`/`Absent` is raised (default 60s).
+    /// Peers outside the roster that have been down or departed this long are forgotten.
+    pub fn with_absence_threshold(mut self, threshold: Duration) -> Self {
+        self.absence_threshold = threshold;
+        self
+    }
+
+    /// How many roster members must be missing at once before a partition is
+    /// suspected (default 2, so one node going quiet is just a `PeerDown`)
+    pub fn with_partition_threshold(mut self, missing: usize) -> Self {
+        self.partition_threshold = missing.max(1);
+        self
+    }
+
+    pub fn local_id(&self) -> u32 {
+        self.local_id
+    }
+
+    pub fn roster(&self) -> &Roster {
+        &self.roster
+    }
+
+    pub fn get(&self, sender_id: u32) -> Option<&Member> {
+        self.members.get(&sender_id)
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values()
+    }
+
+    /// Peers currently considered alive, plus this node
+    pub fn visible(&self) -> BTreeSet<u32> {
+        self.members.values()
+            .filter(|member| member.state == MemberState::Alive)
+            .map(|member| member.sender_id)
+            .chain(std::iter::once(self.local_id))
+            .collect()
+    }
+
+    /// Payloads for `Digest` messages describing this node's view of the fleet.
+    ///
+    /// Covers live peers and recent departures, each with how long ago it was
+    /// last heard, so a receiver never keeps a node alive longer than the
+    /// node's own timeout would.
+    pub fn digest_payloads(&self, now: Instant) -> Vec<Vec<u8>> {
+        let entries: Vec<[u8; DIGEST_ENTRY_LEN]> = self.members.values()
+            .filter_map(|member| {
+                let age = now.saturating_duration_since(member.last_seen);
+                let state = match member.state {
+                    MemberState::Alive => 1,
+                    MemberState::Departed if age <= self.absence_threshold => 3,
+                    _ => return None,
+                };
+                let mut entry = [0u8; DIGEST_ENTRY_LEN];
+                entry[..4].copy_from_slice(&member.sender_id.to_le_bytes());
+                entry[4..12].copy_from_slice(&member.incarnation.unwrap_or(0).to_le_bytes());
+                entry[12] = state;
+                entry[13..].copy_from_slice(&(age.as_millis().min(u32::MAX as u128) as u32).to_le_bytes());
+                Some(entry)
+            })
+            .collect();
+
+        entries.chunks(DIGEST_ENTRIES_PER_MESSAGE).map(|chunk| chunk.concat()).collect()
+    }
+
+    /// Every member, with how long ago it was last heard
+    pub fn snapshot(&self, now: Instant) -> Vec<MemberSnapshot> {
+        self.members.values()
+            .map(|member| MemberSnapshot {
+                sender_id: member.sender_id,
+                state: member.state,
+                incarnation: member.incarnation,
+                last_sequence: member.last_sequence,
+                silent_ms: now.saturating_duration_since(member.last_seen).as_millis() as u64,
+            })
+            .collect()
+    }
+
+    /// Take in members from another node's [`Membership::snapshot`]. Members
+    /// heard more recently here are kept as they are, and no events are raised.
+    pub fn restore(&mut self, snapshot: &[MemberSnapshot], now: Instant) {
+        for entry in snapshot.iter().filter(|entry| entry.sender_id != self.local_id) {
+            let last_seen = now.checked_sub(Duration::from_millis(entry.silent_ms)).unwrap_or(now);
+            let member = self.members.entry(entry.sender_id).or_insert(Member {
+                sender_id: entry.sender_id,
+                state: entry.state,
+                first_seen: last_seen,
+                last_seen,
+                incarnation: entry.incarnation,
+                last_sequence: entry.last_sequence,
+            });
+            if member.last_seen < last_seen {
+                member.state = entry.state;
+                member.last_seen = last_seen;
+                member.incarnation = entry.incarnation;
+                member.last_sequence = entry.last_sequence;
+            }
+        }
+    }
+
+    /// Record a valid message; our own multicast echoes are ignored
+    pub fn observe(&mut self, header: &FleetMsgHeader, payload: &[u8], now: Instant) -> Vec<MembershipEvent> {
+        let _scope = alloc_counter::scope(Subsystem::PeerTables);
+        let mut events = Vec::new();
+        if header.sender_id.get() == self.local_id {
+            return events;
+        }
+
+        let member = self.members.entry(header.sender_id.get()).or_insert(Member {
+            sender_id: header.sender_id.get(),
+            state: MemberState::Down,
+            first_seen: now,
+            last_seen: now,
+            incarnation: None,
+            last_sequence: header.sequence.get(),
+        });
+        member.last_seen = now;
+        member.last_sequence = header.sequence.get();
+        if header.message_type() == MessageType::Heartbeat
+            && let Some(incarnation) = transport::heartbeat_incarnation(payload)
+        {
+            if member.incarnation.is_some_and(|known| known != incarnation) {
+                events.push(MembershipEvent::PeerRestarted { sender_id: header.sender_id.get(), incarnation });
+            }
+            member.incarnation = Some(incarnation);
+        }
+        if header.message_type() == MessageType::Goodbye {
+            if member.state != MemberState::Departed {
+                member.state = MemberState::Departed;
+                events.push(MembershipEvent::PeerDeparted { sender_id: header.sender_id.get() });
+            }
+        } else if member.state != MemberState::Alive {
+            member.state = MemberState::Alive;
+            self.alarmed.remove(&header.sender_id.get());
+            events.push(MembershipEvent::PeerUp { sender_id: header.sender_id.get() });
+        }
+        if header.message_type() == MessageType::Digest {
+            self.merge_digest(payload, now, &mut events);
+        }
+
+        self.check_partition(now, &mut events);
+        events
+    }
+
+    /// Fold a peer's view into ours: learn of nodes we missed, departures we
+    /// didn't hear and restarts, without letting stale reports revive anyone
+    fn merge_digest(&mut self, payload: &[u8], now: Instant, events: &mut Vec<MembershipEvent>) {
+        for entry in payload.chunks_exact(DIGEST_ENTRY_LEN) {
+            let sender_id = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let incarnation = Some(u64::from_le_bytes(entry[4..12].try_into().unwrap())).filter(|&i| i != 0);
+            let age = Duration::from_millis(u32::from_le_bytes(entry[13..].try_into().unwrap()) as u64);
+            let Some(last_heard) = now.checked_sub(age) else {
+                continue;
+            };
+            if sender_id == self.local_id || (entry[12] == 1 && age > self.peer_timeout) {
+                continue;
+            }
+
+            let member = self.members.entry(sender_id).or_insert(Member {
+                sender_id,
+                state: MemberState::Down,
+                first_seen: now,
+                last_seen: last_heard,
+                incarnation,
+                last_sequence: 0,
+            });
+            let newer = matches!((member.incarnation, incarnation), (Some(known), Some(reported)) if reported > known);
+            let older = matches!((member.incarnation, incarnation), (Some(known), Some(reported)) if reported < known);
+            if older {
+                continue;
+            }
+            if newer {
+                member.incarnation = incarnation;
+                member.last_sequence = 0;
+                events.push(MembershipEvent::PeerRestarted { sender_id, incarnation: incarnation.unwrap() });
+            }
+
+            match entry[12] {
+                1 if newer || member.state != MemberState::Departed => {
+                    member.last_seen = member.last_seen.max(last_heard);
+                    if member.state != MemberState::Alive {
+                        member.state = MemberState::Alive;
+                        self.alarmed.remove(&sender_id);
+                        events.push(MembershipEvent::PeerUp { sender_id });
+                    }
+                }
+                3 if member.state != MemberState::Departed && last_heard >= member.last_seen => {
+                    member.state = MemberState::Departed;
+                    member.last_seen = last_heard;
+                    events.push(MembershipEvent::PeerDeparted { sender_id });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Time out silent peers and re-evaluate the partition state
+    pub fn tick(&mut self, now: Instant) -> Vec<MembershipEvent> {
+        let mut events = Vec::new();
+        for member in self.members.values_mut() {
+            if member.state == MemberState::Alive && now.saturating_duration_since(member.last_seen) > self.peer_timeout {
+                member.state = MemberState::Down;
+                events.push(MembershipEvent::PeerDown { sender_id: member.sender_id });
+            }
+        }
+
+        self.forget_departed(now);
+        self.check_absences(now, &mut events);
+        self.check_partition(now, &mut events);
+        events
+    }
+
+    /// Drop peers outside the roster that have been gone for the absence
+    /// threshold, so short-lived nodes don't accumulate forever
+    fn forget_departed(&mut self, now: Instant) {
+        let (roster, threshold) = (&self.roster, self.absence_threshold);
+        self.members.retain(|sender_id, member| {
+            member.state == MemberState::Alive
+                || roster.contains(*sender_id)
+                || now.saturating_duration_since(member.last_seen) <= threshold
+        });
+    }
+
+    fn check_absences(&mut self, now: Instant, events: &mut Vec<MembershipEvent>) {
+        let waited = now.saturating_duration_since(self.started);
+        for sender_id in self.roster.sender_ids() {
+            if sender_id == self.local_id || self.alarmed.contains(&sender_id) {
+                continue;
+            }
+            let name = self.roster.name(sender_id).map(str::to_string);
+            let event = match self.members.get(&sender_id) {
+                None if waited > self.absence_threshold => MembershipEvent::NeverSeen { sender_id, name },
+                Some(member) if member.state != MemberState::Alive => {
+                    let silent = now.saturating_duration_since(member.last_seen);
+                    if silent <= self.absence_threshold {
+                        continue;
+                    }
+                    MembershipEvent::Absent { sender_id, name, last_seen_secs_ago: silent.as_secs_f64() }
+                }
+                _ => continue,
+            };
+            self.alarmed.insert(sender_id);
+            events.push(event);
+        }
+    }
+
+    fn check_partition(&mut self, now: Instant, events: &mut Vec<MembershipEvent>) {
+        // Give every peer one timeout to be heard before judging the fleet
+        if self.roster.is_empty() || now.saturating_duration_since(self.started) < self.peer_timeout {
+            return;
+        }
+
+        let visible: BTreeSet<u32> = self.visible().into_iter().filter(|&id| self.roster.contains(id)).collect();
+        let departed = |id: &u32| self.members.get(id).is_some_and(|m| m.state == MemberState::Departed);
+        let missing: BTreeSet<u32> = self.roster.sender_ids()
+            .filter(|id| !visible.contains(id) && !departed(id))
+            .collect();
+
+        if missing.len() >= self.partition_threshold {
+            if self.partition.as_ref() != Some(&missing) {
+                self.partition = Some(missing.clone());
+                events.push(MembershipEvent::PartitionSuspected {
+                    has_quorum: visible.len() * 2 > self.roster.len(),
+                    visible,
+                    missing,
+                });
+            }
+        } else if self.partition.take().is_some() {
+            events.push(MembershipEvent::PartitionHealed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MessageType;
+
+    fn heartbeat(sender_id: u32) -> FleetMsgHeader {
+        FleetMsgHeader::new(MessageType::Heartbeat, sender_id, 0, 0)
+    }
+
+    #[test]
+    fn test_peers_go_up_and_down() {
+        let start = Instant::now();
+        let mut membership = Membership::new(1, Duration::from_secs(3), start);
+
+        assert_eq!(membership.observe(&heartbeat(2), b"", start), vec![MembershipEvent::PeerUp { sender_id: 2 }]);
+        assert!(membership.observe(&heartbeat(2), b"", start + Duration::from_secs(1)).is_empty());
+        assert!(membership.observe(&heartbeat(1), b"", start).is_empty());
+
+        assert!(membership.tick(start + Duration::from_secs(3)).is_empty());
+        assert_eq!(membership.tick(start + Duration::from_secs(5)), vec![MembershipEvent::PeerDown { sender_id: 2 }]);
+        assert_eq!(membership.get(2).unwrap().state, MemberState::Down);
+        assert_eq!(membership.visible(), BTreeSet::from([1]));
+
+        // A goodbye takes the peer out straight away
+        membership.observe(&heartbeat(3), b"", start);
+        let goodbye = FleetMsgHeader::new(MessageType::Goodbye, 3, 1, 0);
+        assert_eq!(membership.observe(&goodbye, b"", start), vec![MembershipEvent::PeerDeparted { sender_id: 3 }]);
+        assert_eq!(membership.get(3).unwrap().state, MemberState::Departed);
+        assert!(membership.tick(start + Duration::from_secs(10)).is_empty());
+
+        // Peers outside the roster are forgotten once gone past the absence threshold
+        membership.tick(start + Duration::from_secs(62));
+        assert_eq!(membership.members().count(), 0);
+    }
+
+    #[test]
+    fn test_new_incarnation_is_a_restart() {
+        let start = Instant::now();
+        let mut membership = Membership::new(1, Duration::from_secs(3), start);
+        let before = FleetMsgHeader::new(MessageType::Heartbeat, 2, 900, 8);
+        membership.observe(&before, &transport::heartbeat_payload(10), start);
+        assert!(membership.observe(&before, &transport::heartbeat_payload(10), start).is_empty());
+
+        let after = FleetMsgHeader::new(MessageType::Heartbeat, 2, 0, 8);
+        assert_eq!(membership.observe(&after, &transport::heartbeat_payload(11), start),
+                   vec![MembershipEvent::PeerRestarted { sender_id: 2, incarnation: 11 }]);
+        assert_eq!(membership.get(2).unwrap().last_sequence, 0);
+    }
+
+    #[test]
+    fn test_digests_converge_views() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(3);
+        let mut a = Membership::new(1, timeout, start);
+        let mut b = Membership::new(2, timeout, start);
+
+        // A hears 5 and 6; B hears neither, but hears 7 leave
+        a.observe(&heartbeat(5), b"", start);
+        a.observe(&heartbeat(6), b"", start);
+        b.observe(&heartbeat(6), b"", start);
+        b.observe(&heartbeat(7), b"", start);
+        b.observe(&FleetMsgHeader::new(MessageType::Goodbye, 7, 1, 0), b"", start);
+        a.observe(&heartbeat(7), b"", start - Duration::from_secs(1));
+
+        let later = start + Duration::from_secs(1);
+        let digest = |from: u32, payload: &[u8]| (FleetMsgHeader::new(MessageType::Digest, from, 0, payload.len() as u16), payload.to_vec());
+        for payload in a.digest_payloads(later) {
+            let (header, payload) = digest(1, &payload);
+            b.observe(&header, &payload, later);
+        }
+        for payload in b.digest_payloads(later) {
+            let (header, payload) = digest(2, &payload);
+            a.observe(&header, &payload, later);
+        }
+
+        let view = |m: &Membership| m.members().map(|member| (member.sender_id, member.state)).collect::<Vec<_>>();
+        assert_eq!(view(&a)[..3], [(2, MemberState::Alive), (5, MemberState::Alive), (6, MemberState::Alive)]);
+        assert_eq!(view(&a)[3], (7, MemberState::Departed));
+        assert_eq!(view(&b), [(1, MemberState::Alive), (5, MemberState::Alive), (6, MemberState::Alive), (7, MemberState::Departed)]);
+
+        // Second-hand liveness still times out: B only knows of 5 through A
+        assert!(b.tick(start + Duration::from_secs(4)).contains(&MembershipEvent::PeerDown { sender_id: 5 }));
+    }
+
+    #[test]
+    fn test_partition_suspected_and_healed() {
+        let start = Instant::now();
+        let mut membership = Membership::new(1, Duration::from_secs(3), start).with_roster((1..=5).collect());
+        for id in 2..=5 {
+            membership.observe(&heartbeat(id), b"", start);
+        }
+
+        // 4 and 5 fall silent; 2 and 3 keep talking
+        let later = start + Duration::from_secs(4);
+        membership.observe(&heartbeat(2), b"", later);
+        membership.observe(&heartbeat(3), b"", later);
+        let events = membership.tick(later);
+        assert_eq!(events, vec![
+            MembershipEvent::PeerDown { sender_id: 4 },
+            MembershipEvent::PeerDown { sender_id: 5 },
+            MembershipEvent::PartitionSuspected {
+                visible: BTreeSet::from([1, 2, 3]),
+                missing: BTreeSet::from([4, 5]),
+                has_quorum: true,
+            },
+        ]);
+        assert!(membership.tick(later).is_empty());
+
+        let events = membership.observe(&heartbeat(4), b"", later);
+        assert_eq!(events, vec![MembershipEvent::PeerUp { sender_id: 4 }, MembershipEvent::PartitionHealed]);
+    }
+
+    #[test]
+    fn test_roster_absence_alarms() {
+        let roster = Roster::from_toml(r#"
+            site = "north-yard"
+            sender_ids = [1, 2]
+
+            [[nodes]]
+            sender_id = 7
+            name = "truck-07"
+        "#).unwrap();
+        assert_eq!(roster.sender_id("truck-07"), Some(7));
+
+        let start = Instant::now();
+        let mut membership = Membership::new(1, Duration::from_secs(3), start)
+            .with_roster(roster)
+            .with_absence_threshold(Duration::from_secs(10));
+        membership.observe(&heartbeat(2), b"", start);
+        // A peer outside the roster going quiet is only a PeerDown
+        membership.observe(&heartbeat(9), b"", start);
+
+        let events = membership.tick(start + Duration::from_secs(11));
+        assert_eq!(events[..3], [
+            MembershipEvent::PeerDown { sender_id: 2 },
+            MembershipEvent::PeerDown { sender_id: 9 },
+            MembershipEvent::Absent { sender_id: 2, name: None, last_seen_secs_ago: 11.0 },
+        ]);
+        assert_eq!(events[3], MembershipEvent::NeverSeen { sender_id: 7, name: Some("truck-07".to_string()) });
+        // Each absence is reported once, until the node comes back
+        assert!(membership.tick(start + Duration::from_secs(20)).iter()
+            .all(|e| !matches!(e, MembershipEvent::Absent { .. } | MembershipEvent::NeverSeen { .. })));
+
+        let duplicate = "sender_ids = [1]\n[[nodes]]\nsender_id = 1\n";
+        assert_eq!(Roster::from_toml(duplicate).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,206 @@
+//! [`FleetMessage`], a header and its payload as one owned value.
+//!
+//! Building a message by hand means keeping the header's `payload_len` and
+//! checksum in step with the payload and laying the two out back to back;
+//! `FleetMessage` does both. The constructors stamp the current time, like
+//! [`FleetMsgHeader::new`], and [`FleetMessage::parse`] checks the header and
+//! that the payload is as long as it claims. The payload is kept as it is on
+//! the wire: optional features (compression, encryption, extensions) are for
+//! the [receiver](crate::receiver) to undo.
+
+use zerocopy::AsBytes;
+
+use crate::error::{self, TransportError};
+use crate::features::ProtocolFeatures;
+use crate::protocol;
+use crate::receiver::ValidationIssue;
+use crate::transport::{FleetMsgHeader, HeaderChecksum, MessageType, heartbeat_payload};
+
+/// A header and the payload it describes
+#[derive(Debug, Clone)]
+pub struct FleetMessage {
+    header: FleetMsgHeader,
+    payload: Vec<u8>,
+}
+
+impl FleetMessage {
+    /// A message of `msg_type` carrying `payload`. One over
+    /// [`MAX_PAYLOAD_LEN`](protocol::MAX_PAYLOAD_LEN) can be built, but not serialized.
+    pub fn new(msg_type: MessageType, sender_id: u32, sequence: u16, payload: impl Into<Vec<u8>>) -> Self {
+        let payload = payload.into();
+        let payload_len = payload.len().min(protocol::MAX_PAYLOAD_LEN) as u16;
+        Self { header: FleetMsgHeader::new(msg_type, sender_id, sequence, payload_len), payload }
+    }
+
+    /// A heartbeat announcing `incarnation`, without capabilities
+    pub fn heartbeat(sender_id: u32, sequence: u16, incarnation: u64) -> Self {
+        Self::new(MessageType::Heartbeat, sender_id, sequence, heartbeat_payload(incarnation))
+    }
+
+    pub fn data(sender_id: u32, sequence: u16, data: impl Into<Vec<u8>>) -> Self {
+        Self::new(MessageType::Data, sender_id, sequence, data)
+    }
+
+    pub fn control(sender_id: u32, sequence: u16, command: &str) -> Self {
+        Self::new(MessageType::Control, sender_id, sequence, command.as_bytes())
+    }
+
+    pub fn goodbye(sender_id: u32, sequence: u16) -> Self {
+        Self::new(MessageType::Goodbye, sender_id, sequence, Vec::new())
+    }
+
+    /// A membership digest; see [`membership`](crate::membership) for the payload
+    pub fn digest(sender_id: u32, sequence: u16, payload: impl Into<Vec<u8>>) -> Self {
+        Self::new(MessageType::Digest, sender_id, sequence, payload)
+    }
+
+    /// Flag `features` as applied to the payload, which must already be encoded with them
+    pub fn with_features(mut self, features: ProtocolFeatures) -> Self {
+        self.header = self.header.with_features(features);
+        self.sealed()
+    }
+
+    /// Checksum the header with `checksum`, sent as the version that uses it
+    pub fn with_header_checksum(mut self, checksum: HeaderChecksum) -> Self {
+        self.header = self.header.with_checksum(checksum);
+        self.sealed()
+    }
+
+    /// Send the header as protocol `version`
+    pub fn with_version(mut self, version: u8) -> Self {
+        self.header = self.header.with_version(version);
+        self.sealed()
+    }
+
+    /// Bring a checksum that covers the payload up to date with it
+    fn sealed(mut self) -> Self {
+        if self.header.covers_payload() {
+            self.header = self.header.seal(&self.payload[..self.header.payload_len.get() as usize]);
+        }
+        self
+    }
+
+    /// A received header with its decoded payload, as the receiver hands them
+    /// out: the header's feature flags are cleared and its `payload_len`
+    /// matches the payload, so the message serializes as sent without features
+    pub(crate) fn decoded(mut header: FleetMsgHeader, payload: Vec<u8>) -> Self {
+        header.payload_len = (payload.len().min(protocol::MAX_PAYLOAD_LEN) as u16).into();
+        Self { header: header.with_features(ProtocolFeatures::NONE), payload }.sealed()
+    }
+
+    pub fn header(&self) -> &FleetMsgHeader {
+        &self.header
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        self.header.message_type()
+    }
+
+    pub fn sender_id(&self) -> u32 {
+        self.header.sender_id.get()
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.header.sequence.get()
+    }
+
+    /// Send time in microseconds since the Unix epoch
+    pub fn timestamp_micros(&self) -> u64 {
+        self.header.timestamp_micros()
+    }
+
+    /// Length on the wire, header included
+    pub fn wire_len(&self) -> usize {
+        protocol::HEADER_LEN + self.payload.len()
+    }
+
+    /// Write the message to the start of `buffer`, returning how many bytes
+    /// it took. Fails with [`TransportError::PayloadTooLarge`] if the payload
+    /// doesn't fit the buffer or the header's `payload_len`.
+    pub fn serialize_into(&self, buffer: &mut [u8]) -> error::Result<usize> {
+        let limit = buffer.len().saturating_sub(protocol::HEADER_LEN).min(protocol::MAX_PAYLOAD_LEN);
+        if buffer.len() < protocol::HEADER_LEN || self.payload.len() > limit {
+            return Err(TransportError::PayloadTooLarge { len: self.payload.len(), limit });
+        }
+        buffer[..protocol::HEADER_LEN].copy_from_slice(self.header.as_bytes());
+        buffer[protocol::HEADER_LEN..self.wire_len()].copy_from_slice(&self.payload);
+        Ok(self.wire_len())
+    }
+
+    /// The message as one datagram; a payload over `MAX_PAYLOAD_LEN` is cut short
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let payload = &self.payload[..self.header.payload_len.get() as usize];
+        let mut bytes = Vec::with_capacity(protocol::HEADER_LEN + payload.len());
+        bytes.extend_from_slice(self.header.as_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    /// Read a message from a whole datagram, checking the header as
+    /// [`FleetMsgHeader::parse`] does (with the payload, if the checksum covers
+    /// it) and the payload against its `payload_len`
+    pub fn parse(datagram: &[u8]) -> error::Result<Self> {
+        let header = FleetMsgHeader::parse(datagram)?;
+        let (claimed, payload) = (header.payload_len.get() as usize, &datagram[protocol::HEADER_LEN..]);
+        if payload.len() != claimed {
+            let issue = ValidationIssue::LengthMismatch { claimed, actual: payload.len() };
+            return Err(TransportError::Invalid(vec![issue]));
+        }
+        Ok(Self { header, payload: payload.to_vec() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_through_a_buffer() {
+        let message = FleetMessage::control(7, 42, "ROLLOUT start");
+        let mut buffer = [0u8; 128];
+        let len = message.serialize_into(&mut buffer).unwrap();
+        assert_eq!(len, protocol::HEADER_LEN + 13);
+        assert_eq!(&buffer[..len], message.to_bytes().as_slice());
+
+        let parsed = FleetMessage::parse(&buffer[..len]).unwrap();
+        assert_eq!((parsed.message_type(), parsed.sender_id(), parsed.sequence()), (MessageType::Control, 7, 42));
+        assert_eq!(parsed.payload(), b"ROLLOUT start");
+        assert_eq!(parsed.timestamp_micros(), message.timestamp_micros());
+        assert_eq!(crate::transport::heartbeat_incarnation(FleetMessage::heartbeat(7, 0, 3).payload()), Some(3));
+    }
+
+    #[test]
+    fn test_received_messages_drop_their_feature_flags() {
+        // As delivered: extensions were split off, leaving a shorter payload
+        let header = FleetMsgHeader::new(MessageType::Data, 7, 5, 40).with_features(ProtocolFeatures::EXTENSIONS);
+        let message = FleetMessage::decoded(header, b"12.5".to_vec());
+        assert!(message.header().features().is_empty());
+        let parsed = FleetMessage::parse(&message.to_bytes()).unwrap();
+        assert_eq!((parsed.sequence(), parsed.payload()), (5, &b"12.5"[..]));
+    }
+
+    #[test]
+    fn test_short_buffers_and_truncated_datagrams_fail() {
+        let message = FleetMessage::data(7, 1, vec![0u8; 100]);
+        let mut buffer = [0u8; 64];
+        assert!(matches!(
+            message.serialize_into(&mut buffer),
+            Err(TransportError::PayloadTooLarge { len: 100, limit: 40 })
+        ));
+
+        let bytes = message.to_bytes();
+        assert!(matches!(
+            FleetMessage::parse(&bytes[..bytes.len() - 1]),
+            Err(TransportError::Invalid(issues)) if issues == [ValidationIssue::LengthMismatch { claimed: 100, actual: 99 }]
+        ));
+        assert!(matches!(FleetMessage::parse(&bytes[..10]), Err(TransportError::Invalid(_))));
+    }
+}
@@ -0,0 +1,126 @@
+//! Naming application-defined message types.
+//!
+//! Besides its built-in types the protocol leaves the codes in
+//! [`APPLICATION_TYPES`](protocol::APPLICATION_TYPES) to applications: they
+//! arrive as [`MessageType::Application`] and are delivered like Data, with
+//! no meaning given to them by this crate. A [`MessageTypeRegistry`] names
+//! them, so an application can send `"Alert"` rather than code 9 and the
+//! journal, usage accounting and gateways show the name. Codes outside both
+//! ranges arrive as [`MessageType::Unknown`], from firmware newer than this build.
+//!
+//! Register an application's types in [`shared`] at startup for every part of
+//! the process to see them; nodes agree on codes the way they agree on
+//! channels, by configuration.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{Mutex, OnceLock};
+
+use crate::protocol;
+use crate::transport::MessageType;
+
+/// Names of application-defined message types, by code
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageTypeRegistry {
+    names: BTreeMap<u8, String>,
+}
+
+impl MessageTypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the application type `code`. Fails if the code is outside
+    /// [`APPLICATION_TYPES`](protocol::APPLICATION_TYPES), or the code or the
+    /// name is taken, by another registered type or a built-in one.
+    pub fn register(&mut self, code: u8, name: &str) -> std::io::Result<MessageType> {
+        let Some(msg_type) = MessageType::application(code) else {
+            let range = &protocol::APPLICATION_TYPES;
+            let reason = format!("message type {} is outside the application range {}-{}", code, range.start(), range.end());
+            return Err(Error::new(ErrorKind::InvalidInput, reason));
+        };
+        if name.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "message type names can't be empty"));
+        }
+        if let Some(taken) = self.names.get(&code) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("message type {} is already '{}'", code, taken)));
+        }
+        if self.get(name).is_some() {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("message type '{}' is already registered", name)));
+        }
+        self.names.insert(code, name.to_string());
+        Ok(msg_type)
+    }
+
+    /// The registry with `code` named `name`, as [`register`](Self::register) would
+    pub fn with_type(mut self, code: u8, name: &str) -> std::io::Result<Self> {
+        self.register(code, name)?;
+        Ok(self)
+    }
+
+    /// The type named `name`, built-in (e.g. `Data`) or registered
+    pub fn get(&self, name: &str) -> Option<MessageType> {
+        let builtin = protocol::MESSAGE_TYPES.map(MessageType::from).find(|msg_type| format!("{:?}", msg_type) == name);
+        builtin.or_else(|| {
+            self.names.iter().find(|(_, registered)| *registered == name).map(|(&code, _)| MessageType::Application(code))
+        })
+    }
+
+    /// The type's name: a built-in type's own, a registered one's, or `Type 9`
+    /// for a code nobody named
+    pub fn name(&self, msg_type: MessageType) -> String {
+        match msg_type {
+            MessageType::Application(code) => match self.names.get(&code) {
+                Some(name) => name.clone(),
+                None => format!("Type {}", code),
+            },
+            MessageType::Unknown(code) => format!("Type {}", code),
+            builtin => format!("{:?}", builtin),
+        }
+    }
+
+    /// Whether `msg_type` is built in or registered here
+    pub fn is_registered(&self, msg_type: MessageType) -> bool {
+        match msg_type {
+            MessageType::Application(code) => self.names.contains_key(&code),
+            MessageType::Unknown(_) => false,
+            _ => true,
+        }
+    }
+
+    /// The registered types with their names, by code
+    pub fn types(&self) -> impl Iterator<Item = (MessageType, &str)> {
+        self.names.iter().map(|(&code, name)| (MessageType::Application(code), name.as_str()))
+    }
+}
+
+/// Process-wide registry, which [`journal::message_type_name`](crate::journal::message_type_name)
+/// and through it usage accounting and the gateways name types by
+pub fn shared() -> &'static Mutex<MessageTypeRegistry> {
+    static SHARED: OnceLock<Mutex<MessageTypeRegistry>> = OnceLock::new();
+    SHARED.get_or_init(|| Mutex::new(MessageTypeRegistry::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_application_types_are_named_within_their_range() {
+        let mut registry = MessageTypeRegistry::new().with_type(8, "Alert").unwrap();
+        let report = registry.register(9, "MissionReport").unwrap();
+        assert_eq!(report, MessageType::Application(9));
+        assert_eq!(MessageType::from(9), report);
+        assert_eq!(registry.get("MissionReport"), Some(report));
+        assert_eq!(registry.get("Control"), Some(MessageType::Control));
+        assert_eq!(registry.name(MessageType::Application(8)), "Alert");
+        assert_eq!(registry.name(MessageType::Application(10)), "Type 10");
+        assert!(!registry.is_registered(MessageType::Application(10)));
+
+        assert_eq!(registry.register(5, "Mine").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(registry.register(200, "Mine").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(registry.register(8, "Other").unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(registry.register(10, "Data").unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(registry.types().count(), 2);
+    }
+}
@@ -0,0 +1,162 @@
+//! Copies of a sender's traffic for a secondary group or collector, e.g. on a
+//! monitoring VLAN, so observability taps don't need switch port mirroring.
+//!
+//! A [`Mirror`] gets every datagram exactly as it went out, once per message,
+//! optionally only for some message types, and with payloads rewritten by
+//! [`Transforms`] if it has any. It has a budget of its own and
+//! drops copies that don't fit, or that the socket can't take right away,
+//! rather than slowing the sender down; a failed copy never fails the send.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::bandwidth::{BandwidthManager, MessageClass};
+use crate::transform::Transforms;
+use crate::transport::MessageType;
+
+/// Copies sent and dropped by a [`Mirror`]
+#[derive(Debug, Default)]
+pub struct MirrorStats {
+    mirrored: AtomicU64,
+    mirrored_bytes: AtomicU64,
+    /// Over budget, the socket was busy, the send failed, or the transforms withheld it
+    dropped: AtomicU64,
+}
+
+impl MirrorStats {
+    pub fn mirrored(&self) -> u64 {
+        self.mirrored.load(Ordering::Relaxed)
+    }
+
+    pub fn mirrored_bytes(&self) -> u64 {
+        self.mirrored_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Where a sender's traffic is copied to; add it with
+/// [`MulticastSender::with_mirror`](crate::transport::MulticastSender::with_mirror)
+#[derive(Debug)]
+pub struct Mirror {
+    target: SocketAddr,
+    socket: UdpSocket,
+    /// None: every type
+    types: Option<BTreeSet<u8>>,
+    budget: Option<BandwidthManager>,
+    transforms: Option<Transforms>,
+    stats: Arc<MirrorStats>,
+}
+
+impl Mirror {
+    /// Copy to `target`, a multicast group or a single collector
+    pub fn new(target: SocketAddr) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_multicast_ttl_v4(1)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { target, socket, types: None, budget: None, transforms: None, stats: Arc::new(MirrorStats::default()) })
+    }
+
+    /// Only copy messages of these types
+    pub fn with_message_types(mut self, types: impl IntoIterator<Item = MessageType>) -> Self {
+        self.types = Some(types.into_iter().map(|msg_type| msg_type.code()).collect());
+        self
+    }
+
+    /// Limit the copies to this budget, charged by message class; it is
+    /// separate from the sender's own
+    pub fn with_bandwidth_manager(mut self, manager: BandwidthManager) -> Self {
+        self.budget = Some(manager);
+        self
+    }
+
+    /// Rewrite the copies' payloads by topic, e.g. to coarsen positions for a
+    /// less-trusted collector. Copies whose payload can't be read (compressed,
+    /// encrypted or fragmented) are dropped instead.
+    pub fn with_transforms(mut self, transforms: Transforms) -> Self {
+        self.transforms = Some(transforms);
+        self
+    }
+
+    /// Send the copies out of this interface, e.g. the monitoring VLAN's
+    pub fn with_interface(self, interface: Ipv4Addr) -> std::io::Result<Self> {
+        socket2::SockRef::from(&self.socket).set_multicast_if_v4(&interface)?;
+        Ok(self)
+    }
+
+    /// Let the copies cross this many routers (default 1, the local network only)
+    pub fn with_ttl(self, ttl: u32) -> std::io::Result<Self> {
+        self.socket.set_multicast_ttl_v4(ttl)?;
+        Ok(self)
+    }
+
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    pub fn stats(&self) -> Arc<MirrorStats> {
+        self.stats.clone()
+    }
+
+    /// Copy one sent datagram, if it passes the filter and fits the budget
+    pub(crate) fn forward(&self, class: MessageClass, msg_type: MessageType, datagram: &[u8]) {
+        if self.types.as_ref().is_some_and(|types| !types.contains(&msg_type.code())) {
+            return;
+        }
+        let datagram = match &self.transforms {
+            Some(transforms) => transforms.apply_to_datagram(datagram),
+            None => Some(Cow::Borrowed(datagram)),
+        };
+        let Some(datagram) = datagram else {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        let admitted = self.budget.as_ref().is_none_or(|budget| budget.try_acquire(class, datagram.len(), Instant::now()).is_ok());
+        if admitted && self.socket.send_to(&datagram, self.target).is_ok() {
+            self.stats.mirrored.fetch_add(1, Ordering::Relaxed);
+            self.stats.mirrored_bytes.fetch_add(datagram.len() as u64, Ordering::Relaxed);
+        } else {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestReceiver;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn test_filtered_and_rate_limited_copies() {
+        let primary = TestReceiver::start().await.unwrap();
+        let monitor = TestReceiver::start().await.unwrap();
+        let channel = monitor.channel();
+        // 100 bytes a second for telemetry: room for two of the 44-byte frames
+        let budget = BandwidthManager::new(200);
+        let mirror = Mirror::new(SocketAddr::new(IpAddr::V4(channel.group), channel.port))
+            .unwrap()
+            .with_message_types([MessageType::Data])
+            .with_bandwidth_manager(budget);
+        let stats = mirror.stats();
+        let mut sender = primary.sender(3).await.unwrap().with_mirror(mirror);
+
+        sender.send_heartbeat().await.unwrap();
+        for _ in 0..3 {
+            sender.send_data(b"telemetry 0123456789").await.unwrap();
+        }
+        primary.wait_for(4, Duration::from_secs(2)).await;
+        let copies = monitor.wait_for(2, Duration::from_secs(2)).await;
+
+        assert_eq!(copies.len(), 2);
+        assert!(copies.iter().all(|(header, payload, _)| header.sender_id.get() == 3 && payload == b"telemetry 0123456789"));
+        assert_eq!((stats.mirrored(), stats.dropped()), (2, 1));
+    }
+}
@@ -0,0 +1,269 @@
+//! Plans and results for benchmarks that run senders and receivers as separate
+//! processes, optionally on other hosts over SSH, so the numbers include real
+//! NIC, interrupt and scheduler behaviour instead of one process's loopback.
+//!
+//! The `bench_orchestrator` binary plays every part: `run` spawns copies of
+//! itself as `send` and `receive` children, which wait for a common wall-clock
+//! start time, run, and print one JSON result line on stdout for `run` to
+//! collect.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::process::Command;
+
+use crate::soak::LatencySummary;
+
+/// Sender ids are this plus the sender's index, so receivers can tell them apart
+pub const FIRST_SENDER_ID: u32 = 0x0B00;
+
+/// Where one benchmark process runs; serialized as it is written on the command line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum Host {
+    Local,
+    /// Over `ssh`, which must log in without prompting
+    Ssh { destination: String },
+}
+
+impl Host {
+    /// `local` (or `localhost`) runs here; anything else is an SSH destination such as `user@vehicle-3`
+    pub fn parse(spec: &str) -> Self {
+        match spec.trim() {
+            "local" | "localhost" => Host::Local,
+            destination => Host::Ssh { destination: destination.to_string() },
+        }
+    }
+
+    /// The command that runs the benchmark binary with `args` on this host:
+    /// `local_binary` here, or `remote_binary` (a path or a name on the remote `PATH`) over SSH
+    pub fn command(&self, local_binary: &Path, remote_binary: &str, args: &[String]) -> Command {
+        match self {
+            Host::Local => {
+                let mut command = Command::new(local_binary);
+                command.args(args);
+                command
+            }
+            Host::Ssh { destination } => {
+                let mut command = Command::new("ssh");
+                command.args(["-o", "BatchMode=yes", destination.as_str(), remote_binary]).args(args);
+                command
+            }
+        }
+    }
+}
+
+impl From<String> for Host {
+    fn from(spec: String) -> Self {
+        Host::parse(&spec)
+    }
+}
+
+impl From<Host> for String {
+    fn from(host: Host) -> Self {
+        host.to_string()
+    }
+}
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Host::Local => write!(f, "local"),
+            Host::Ssh { destination } => write!(f, "{}", destination),
+        }
+    }
+}
+
+/// What every process in one run agrees on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunPlan {
+    pub group: Ipv4Addr,
+    pub port: u16,
+    /// Messages per second, per sender
+    pub rate: f64,
+    pub payload_size: usize,
+    pub duration_secs: u64,
+    /// Wall-clock start, microseconds since the Unix epoch; hosts' clocks should be synchronized
+    pub start_at_us: u64,
+    /// How long receivers keep listening after the senders stop
+    pub drain_secs: u64,
+    pub senders: u32,
+}
+
+impl RunPlan {
+    pub fn sender_ids(&self) -> std::ops::Range<u32> {
+        FIRST_SENDER_ID..FIRST_SENDER_ID + self.senders
+    }
+
+    fn common_args(&self) -> Vec<String> {
+        vec![
+            "--group".into(), self.group.to_string(),
+            "--port".into(), self.port.to_string(),
+            "--duration".into(), self.duration_secs.to_string(),
+            "--start-at".into(), self.start_at_us.to_string(),
+        ]
+    }
+
+    /// Arguments for the `send` child with the given id
+    pub fn sender_args(&self, sender_id: u32) -> Vec<String> {
+        let mut args = vec!["send".to_string()];
+        args.extend(self.common_args());
+        args.extend([
+            "--rate".into(), self.rate.to_string(),
+            "--payload".into(), self.payload_size.to_string(),
+            "--sender-id".into(), sender_id.to_string(),
+        ]);
+        args
+    }
+
+    /// Arguments for a `receive` child
+    pub fn receiver_args(&self) -> Vec<String> {
+        let mut args = vec!["receive".to_string()];
+        args.extend(self.common_args());
+        args.extend(["--drain".into(), self.drain_secs.to_string()]);
+        args
+    }
+}
+
+/// Printed by a `send` child
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SenderResult {
+    pub sender_id: u32,
+    pub messages_sent: u64,
+    pub send_rate: f64,
+    /// How far past the planned start sending actually began
+    pub started_late_us: u64,
+}
+
+/// Printed by a `receive` child
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReceiverResult {
+    /// Messages received from each sender id, including any not in the run
+    pub received: BTreeMap<u32, u64>,
+    /// One-way latency from the send time in each payload; only meaningful across
+    /// hosts if their clocks are synchronized
+    pub latency_us: LatencySummary,
+    /// Receive-buffer overflows reported by the kernel during the run, where it exposes them
+    pub kernel_drops: Option<u64>,
+}
+
+/// One sending process's result, with where it ran
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SenderSummary {
+    pub host: Host,
+    #[serde(flatten)]
+    pub result: SenderResult,
+}
+
+/// One receiving host's view of the run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReceiverSummary {
+    pub host: Host,
+    pub messages_received: u64,
+    pub messages_lost: u64,
+    pub loss_ratio: f64,
+    pub latency_us: LatencySummary,
+    pub kernel_drops: Option<u64>,
+}
+
+/// Everything a multi-process run measured, written as JSON by `bench_orchestrator run`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrchestratedReport {
+    pub plan: RunPlan,
+    pub senders: Vec<SenderSummary>,
+    pub receivers: Vec<ReceiverSummary>,
+    pub messages_sent: u64,
+    /// Combined send rate of all senders
+    pub send_rate: f64,
+}
+
+impl OrchestratedReport {
+    pub fn new(plan: RunPlan, senders: Vec<(Host, SenderResult)>, receivers: Vec<(Host, ReceiverResult)>) -> Self {
+        let sent: BTreeMap<u32, u64> = senders.iter().map(|(_, s)| (s.sender_id, s.messages_sent)).collect();
+        let send_rate = senders.iter().map(|(_, s)| s.send_rate).sum();
+        let messages_sent = sent.values().sum();
+        let receivers = receivers.into_iter().map(|(host, result)| {
+            // Duplicates can't make up for losses from another sender
+            let messages_lost: u64 = sent.iter()
+                .map(|(id, &count)| count.saturating_sub(result.received.get(id).copied().unwrap_or(0)))
+                .sum();
+            ReceiverSummary {
+                host,
+                messages_received: sent.keys().filter_map(|id| result.received.get(id)).sum(),
+                messages_lost,
+                loss_ratio: if messages_sent > 0 { messages_lost as f64 / messages_sent as f64 } else { 0.0 },
+                latency_us: result.latency_us,
+                kernel_drops: result.kernel_drops,
+            }
+        }).collect();
+
+        Self {
+            plan,
+            senders: senders.into_iter().map(|(host, result)| SenderSummary { host, result }).collect(),
+            receivers,
+            messages_sent,
+            send_rate,
+        }
+    }
+
+    /// The receiver that lost the largest share of messages
+    pub fn worst_receiver(&self) -> Option<&ReceiverSummary> {
+        self.receivers.iter().max_by(|a, b| a.loss_ratio.total_cmp(&b.loss_ratio))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> RunPlan {
+        RunPlan {
+            group: Ipv4Addr::new(239, 1, 1, 30),
+            port: 12370,
+            rate: 500.0,
+            payload_size: 128,
+            duration_secs: 10,
+            start_at_us: 1_700_000_000_000_000,
+            drain_secs: 1,
+            senders: 2,
+        }
+    }
+
+    #[test]
+    fn test_child_commands() {
+        let args = plan().sender_args(FIRST_SENDER_ID + 1);
+        assert_eq!(args[0], "send");
+        assert!(args.windows(2).any(|pair| pair == ["--sender-id", "2817"]));
+        assert!(args.windows(2).any(|pair| pair == ["--start-at", "1700000000000000"]));
+
+        let remote = Host::parse("fleet@vehicle-3").command(Path::new("/unused"), "bench_orchestrator", &plan().receiver_args());
+        let remote_args: Vec<_> = remote.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(remote.get_program(), "ssh");
+        assert_eq!(&remote_args[2..5], ["fleet@vehicle-3", "bench_orchestrator", "receive"]);
+
+        let local = Host::parse("local").command(Path::new("/bin/bench"), "unused", &[]);
+        assert_eq!(local.get_program(), "/bin/bench");
+    }
+
+    #[test]
+    fn test_loss_is_counted_per_sender() {
+        let senders = plan().sender_ids()
+            .map(|sender_id| (Host::Local, SenderResult { sender_id, messages_sent: 100, send_rate: 50.0, started_late_us: 0 }))
+            .collect();
+        // The second receiver got duplicates of one sender but missed the other entirely
+        let near = ReceiverResult { received: BTreeMap::from([(0x0B00, 100), (0x0B01, 98)]), ..Default::default() };
+        let far = ReceiverResult { received: BTreeMap::from([(0x0B00, 120)]), ..Default::default() };
+        let report = OrchestratedReport::new(plan(), senders, vec![(Host::Local, near), (Host::parse("far"), far)]);
+
+        assert_eq!(report.messages_sent, 200);
+        assert_eq!(report.send_rate, 100.0);
+        assert_eq!(report.receivers[0].messages_lost, 2);
+        assert_eq!(report.receivers[1].messages_lost, 100);
+        assert_eq!(report.worst_receiver().unwrap().host, Host::parse("far"));
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains(r#""host":"far""#));
+        assert_eq!(serde_json::from_str::<OrchestratedReport>(&json).unwrap(), report);
+    }
+}
@@ -0,0 +1,79 @@
+//! Padding of encrypted messages to fixed size buckets, so observers on a
+//! shared network can't tell commands apart by datagram length.
+//!
+//! The padding travels as zero bytes after the payload, counted by the
+//! [`PADDING`](crate::extensions::PADDING) extension, inside the encryption.
+//! It therefore needs the extensions feature, and padded messages are never
+//! compressed, since compressed lengths leak content too.
+
+use std::io::{Error, ErrorKind};
+
+use crate::extensions::Extensions;
+use crate::protocol::MTU_DATAGRAM_LEN;
+
+/// Datagram sizes (header included) that padded messages are rounded up to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaddingBuckets {
+    buckets: Vec<usize>,
+}
+
+impl Default for PaddingBuckets {
+    /// Powers of two up to a datagram that fits a 1500-byte Ethernet MTU
+    fn default() -> Self {
+        Self { buckets: vec![128, 256, 512, 1024, MTU_DATAGRAM_LEN] }
+    }
+}
+
+impl PaddingBuckets {
+    pub fn new(mut buckets: Vec<usize>) -> std::io::Result<Self> {
+        buckets.sort_unstable();
+        buckets.dedup();
+        if buckets.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "padding needs at least one bucket"));
+        }
+        Ok(Self { buckets })
+    }
+
+    /// The smallest bucket holding `len` bytes; larger datagrams aren't padded
+    pub fn bucket_for(&self, len: usize) -> Option<usize> {
+        self.buckets.iter().copied().find(|bucket| *bucket >= len)
+    }
+
+    /// The extension block and `payload`, padded so that with `overhead`
+    /// (header, nonce, tag and trailers) they fill a bucket. Returns the body
+    /// and how many padding bytes it carries.
+    pub(crate) fn pad(&self, extensions: &Extensions, payload: &[u8], overhead: usize) -> (Vec<u8>, usize) {
+        let mut extensions = extensions.clone();
+        extensions.set_padding(0);
+        let unpadded = extensions.prepend_to(payload).len() + overhead;
+        let padding = match self.bucket_for(unpadded) {
+            Some(bucket) => (bucket - unpadded).min(u16::MAX as usize),
+            None => return (extensions.prepend_to(payload), 0),
+        };
+        extensions.set_padding(padding as u16);
+        let mut body = extensions.prepend_to(payload);
+        body.resize(body.len() + padding, 0);
+        (body, padding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pads_to_bucket_and_strips() {
+        let buckets = PaddingBuckets::default();
+        let mut extensions = Extensions::new();
+        extensions.insert(9, b"meta".to_vec()).unwrap();
+        for payload in [&b"STOP"[..], &b"GOTO 12.5 40.1 heading 270"[..]] {
+            let (body, padding) = buckets.pad(&extensions, payload, 52);
+            assert_eq!(body.len() + 52, 128);
+            let (parsed, stripped) = Extensions::split(&body).unwrap();
+            assert_eq!((stripped, parsed.padding()), (payload, padding));
+        }
+        let (body, padding) = buckets.pad(&extensions, &[1; 1500], 52);
+        assert_eq!((padding, Extensions::split(&body).unwrap().1.len()), (0, 1500));
+        assert!(PaddingBuckets::new(vec![]).is_err());
+    }
+}
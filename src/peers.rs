@@ -0,0 +1,407 @@
+//! Which remote senders this node hears, and what they announced.
+//!
+//! Every valid message refreshes its sender's entry. With a timeout set,
+//! [`PeerTable::tick`] marks peers that went quiet as offline; a `Goodbye`
+//! passed to [`PeerTable::leave`] removes its sender at once. Joins, leaves,
+//! timeouts and downgrade attempts go to each [`PeerEvents`] subscriber, e.g.
+//! a dashboard showing which vehicles are online.
+//!
+//! This is the node's own first-hand view, in every build. With the
+//! `discovery` feature, `Membership` (whose `PeerUp`, `PeerDown` and
+//! `PeerDeparted` events look alike) adds what other nodes report in their
+//! digests, restarts, rosters and partition detection.
+
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::alloc_counter::{self, Subsystem};
+use crate::capabilities::Capabilities;
+use crate::features::ProtocolFeatures;
+use crate::transport::FleetMsgHeader;
+
+/// What we know about one remote sender
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub sender_id: u32,
+    pub addr: SocketAddr,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    pub last_sequence: u16,
+    pub messages: u64,
+    /// Protocol version of the peer's latest header
+    pub version: u8,
+    /// From the peer's latest heartbeat that announced any
+    pub capabilities: Capabilities,
+    /// False once the peer has been quiet for longer than the table's timeout
+    pub online: bool,
+}
+
+/// A peer coming online, going away or announcing less than it should
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// First heard from, or heard from again after timing out
+    Joined { sender_id: u32, addr: SocketAddr },
+    /// Said goodbye
+    Left { sender_id: u32 },
+    /// Quiet for longer than the timeout
+    TimedOut { sender_id: u32, last_seen: Instant },
+    /// Announced fewer features than it offered in an authenticated
+    /// handshake, which were kept
+    Downgraded { sender_id: u32, announced: ProtocolFeatures },
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    events: Sender<PeerEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Table of peers seen on the fleet network, keyed by `sender_id`
+#[derive(Debug, Default)]
+pub struct PeerTable {
+    peers: BTreeMap<u32, PeerInfo>,
+    /// Features peers offered in an authenticated handshake, which
+    /// unauthenticated announcements can't take away
+    pinned: BTreeMap<u32, ProtocolFeatures>,
+    /// How long a peer may stay quiet before [`tick`](Self::tick) marks it offline
+    timeout: Option<Duration>,
+    subscribers: Vec<Subscriber>,
+}
+
+impl PeerTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark peers offline when nothing has been heard from them for `timeout`,
+    /// typically a few heartbeat intervals
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Receive joins, leaves and timeouts from now on, buffering up to
+    /// `capacity` events. When the subscriber falls behind, events are dropped
+    /// (and counted) rather than holding up the table.
+    pub fn subscribe(&mut self, capacity: usize) -> PeerEvents {
+        let (events, receiver) = channel::bounded(capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        self.subscribers.push(Subscriber { events, dropped: dropped.clone() });
+        PeerEvents { events: receiver, dropped }
+    }
+
+    fn publish(&mut self, event: PeerEvent) {
+        self.subscribers.retain(|subscriber| match subscriber.events.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        });
+    }
+
+    /// Record a valid message from a peer; returns true if the peer is new or
+    /// back online after timing out
+    pub fn observe(&mut self, header: &FleetMsgHeader, addr: SocketAddr, now: Instant) -> bool {
+        let _scope = alloc_counter::scope(Subsystem::PeerTables);
+        let sender_id = header.sender_id.get();
+        match self.peers.get_mut(&sender_id) {
+            Some(peer) => {
+                peer.addr = addr;
+                peer.last_seen = now;
+                peer.last_sequence = header.sequence.get();
+                peer.messages += 1;
+                peer.version = header.version();
+                if peer.online {
+                    return false;
+                }
+                peer.online = true;
+                self.publish(PeerEvent::Joined { sender_id, addr });
+                true
+            }
+            None => {
+                self.peers.insert(header.sender_id.get(), PeerInfo {
+                    sender_id: header.sender_id.get(),
+                    addr,
+                    first_seen: now,
+                    last_seen: now,
+                    last_sequence: header.sequence.get(),
+                    messages: 1,
+                    version: header.version(),
+                    capabilities: Capabilities::default(),
+                    online: true,
+                });
+                self.publish(PeerEvent::Joined { sender_id, addr });
+                true
+            }
+        }
+    }
+
+    /// Mark peers quiet for longer than the timeout as offline, returning
+    /// their ids. Call it periodically, e.g. once per heartbeat interval;
+    /// without a timeout, peers never time out.
+    pub fn tick(&mut self, now: Instant) -> Vec<u32> {
+        let Some(timeout) = self.timeout else {
+            return Vec::new();
+        };
+        let mut timed_out = Vec::new();
+        for peer in self.peers.values_mut() {
+            if peer.online && now.saturating_duration_since(peer.last_seen) > timeout {
+                peer.online = false;
+                timed_out.push((peer.sender_id, peer.last_seen));
+            }
+        }
+        for &(sender_id, last_seen) in &timed_out {
+            self.publish(PeerEvent::TimedOut { sender_id, last_seen });
+        }
+        timed_out.into_iter().map(|(sender_id, _)| sender_id).collect()
+    }
+
+    /// Forget a peer that said goodbye, telling subscribers it left
+    pub fn leave(&mut self, sender_id: u32) -> Option<PeerInfo> {
+        let peer = self.peers.remove(&sender_id)?;
+        self.publish(PeerEvent::Left { sender_id });
+        Some(peer)
+    }
+
+    pub fn get(&self, sender_id: u32) -> Option<&PeerInfo> {
+        self.peers.get(&sender_id)
+    }
+
+    /// Record the roles and capabilities a known peer announced. Returns true
+    /// if the announcement lacked features the peer offered in a handshake
+    /// (a likely downgrade attempt), and publishes it as
+    /// [`PeerEvent::Downgraded`]; those features are kept.
+    pub fn announce(&mut self, sender_id: u32, mut capabilities: Capabilities) -> bool {
+        let pinned = self.pinned.get(&sender_id).copied().unwrap_or_default();
+        let downgraded = !capabilities.features.contains(pinned);
+        if downgraded {
+            self.publish(PeerEvent::Downgraded { sender_id, announced: capabilities.features });
+        }
+        capabilities.features = capabilities.features | pinned;
+        if let Some(peer) = self.peers.get_mut(&sender_id) {
+            peer.capabilities = capabilities;
+        }
+        downgraded
+    }
+
+    /// Pin the features `sender_id` offered in an authenticated handshake, so
+    /// they are negotiated whatever its heartbeats say from then on
+    pub fn pin_features(&mut self, sender_id: u32, features: ProtocolFeatures) {
+        self.pinned.insert(sender_id, features);
+        if let Some(peer) = self.peers.get_mut(&sender_id) {
+            peer.capabilities.features = peer.capabilities.features | features;
+        }
+    }
+
+    pub fn unpin_features(&mut self, sender_id: u32) -> Option<ProtocolFeatures> {
+        self.pinned.remove(&sender_id)
+    }
+
+    /// Online peers that announced `tag` as a role or capability
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a PeerInfo> {
+        self.online().filter(move |peer| peer.capabilities.has_tag(tag))
+    }
+
+    /// Features both this node and `sender_id` support, for traffic addressed to that peer alone
+    pub fn negotiated(&self, sender_id: u32, local: ProtocolFeatures) -> ProtocolFeatures {
+        self.peers.get(&sender_id).map_or(ProtocolFeatures::NONE, |peer| peer.capabilities.features & local)
+    }
+
+    /// Features every online peer (and this node) supports, so a multicast
+    /// stays readable by all of them. Once any peer has pinned its features,
+    /// only pinned peers count: a sender that never completed a handshake
+    /// can't drag the fleet down by announcing less.
+    pub fn common_features(&self, local: ProtocolFeatures) -> ProtocolFeatures {
+        let pinning = !self.pinned.is_empty();
+        let mut online = self.online().filter(|peer| !pinning || self.pinned.contains_key(&peer.sender_id)).peekable();
+        if online.peek().is_none() {
+            return ProtocolFeatures::NONE;
+        }
+        online.fold(local, |common, peer| common & peer.capabilities.features)
+    }
+
+    /// Oldest protocol version any online peer sends, which every one of them
+    /// reads; `None` without online peers. A sender on this version (see
+    /// [`with_protocol_version`](crate::transport::MulticastSender::with_protocol_version))
+    /// is understood fleet-wide during a rolling upgrade, and peers that timed
+    /// out don't hold it back.
+    pub fn common_version(&self) -> Option<u8> {
+        self.online().map(|peer| peer.version).min()
+    }
+
+    pub fn remove(&mut self, sender_id: u32) -> Option<PeerInfo> {
+        self.peers.remove(&sender_id)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerInfo> {
+        self.peers.values()
+    }
+
+    /// Peers that haven't timed out
+    pub fn online(&self) -> impl Iterator<Item = &PeerInfo> {
+        self.peers.values().filter(|peer| peer.online)
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
+
+/// The receiving end of [`PeerTable::subscribe`]
+#[derive(Debug)]
+pub struct PeerEvents {
+    events: Receiver<PeerEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl PeerEvents {
+    /// The next event; `None` once the table is dropped
+    pub async fn next(&self) -> Option<PeerEvent> {
+        self.events.recv().await.ok()
+    }
+
+    /// The next event if one is waiting
+    pub fn try_next(&self) -> Option<PeerEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Events lost because the subscriber fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MessageType;
+
+    #[test]
+    fn test_observe_tracks_new_and_known_peers() {
+        let mut table = PeerTable::new();
+        let addr: SocketAddr = "10.0.0.7:40000".parse().unwrap();
+        let start = Instant::now();
+
+        let first = FleetMsgHeader::new(MessageType::Heartbeat, 7, 1, 0);
+        assert!(table.observe(&first, addr, start));
+
+        let second = FleetMsgHeader::new(MessageType::Data, 7, 2, 4);
+        assert!(!table.observe(&second, addr, start + Duration::from_secs(1)));
+
+        let peer = table.get(7).unwrap();
+        assert_eq!(peer.messages, 2);
+        assert_eq!(peer.last_sequence, 2);
+        assert_eq!(peer.last_seen - peer.first_seen, Duration::from_secs(1));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.common_version(), Some(FleetMsgHeader::VERSION));
+        table.observe(&FleetMsgHeader::new(MessageType::Data, 9, 1, 0).with_version(1), addr, start);
+        assert_eq!(table.common_version(), Some(1));
+
+        table.announce(7, Capabilities::new(["forklift"], ["lidar-v2"]).unwrap());
+        assert_eq!(table.tagged("lidar-v2").map(|peer| peer.sender_id).collect::<Vec<_>>(), vec![7]);
+        assert_eq!(table.tagged("charger").count(), 0);
+    }
+
+    #[test]
+    fn test_features_negotiated_with_mixed_fleet() {
+        let mut table = PeerTable::new();
+        let addr: SocketAddr = "10.0.0.7:40000".parse().unwrap();
+        let local = ProtocolFeatures::COMPRESSION | ProtocolFeatures::CRC32;
+        assert_eq!(table.common_features(local), ProtocolFeatures::NONE);
+
+        for sender_id in [7, 8] {
+            table.observe(&FleetMsgHeader::new(MessageType::Heartbeat, sender_id, 1, 0), addr, Instant::now());
+        }
+        let newer = Capabilities { features: ProtocolFeatures::CRC32 | ProtocolFeatures::ENCRYPTION, ..Default::default() };
+        table.announce(7, newer.clone());
+
+        assert_eq!(table.negotiated(7, local), ProtocolFeatures::CRC32);
+        // Peer 8 is old firmware, so multicasts stay plain
+        assert_eq!(table.negotiated(8, local), ProtocolFeatures::NONE);
+        assert_eq!(table.common_features(local), ProtocolFeatures::NONE);
+
+        // Once authenticated, a forged heartbeat can't strip features
+        table.pin_features(7, newer.features);
+        assert!(table.announce(7, Capabilities::default()));
+        assert_eq!(table.negotiated(7, local), ProtocolFeatures::CRC32);
+        assert!(!table.announce(7, newer));
+        // Nor can a peer that never shook hands, old or forged
+        assert_eq!(table.common_features(local), ProtocolFeatures::CRC32);
+        table.observe(&FleetMsgHeader::new(MessageType::Heartbeat, 9, 1, 0), addr, Instant::now());
+        assert!(!table.announce(9, Capabilities::default()));
+        assert_eq!(table.common_features(local), ProtocolFeatures::CRC32);
+    }
+
+    #[test]
+    fn test_timed_out_peers_dont_hold_back_negotiation() {
+        let mut table = PeerTable::new().with_timeout(Duration::from_secs(3));
+        let addr: SocketAddr = "10.0.0.7:40000".parse().unwrap();
+        let local = ProtocolFeatures::COMPRESSION | ProtocolFeatures::CRC32;
+        let start = Instant::now();
+
+        table.observe(&FleetMsgHeader::new(MessageType::Heartbeat, 7, 1, 0), addr, start + Duration::from_secs(2));
+        table.announce(7, Capabilities { features: local, ..Default::default() });
+        // Peer 8 runs old firmware and then goes away without a goodbye
+        table.observe(&FleetMsgHeader::new(MessageType::Heartbeat, 8, 1, 0).with_version(1), addr, start);
+        assert_eq!(table.common_version(), Some(1));
+        assert_eq!(table.common_features(local), ProtocolFeatures::NONE);
+
+        assert_eq!(table.tick(start + Duration::from_secs(4)), vec![8]);
+        assert_eq!(table.common_version(), Some(FleetMsgHeader::VERSION));
+        assert_eq!(table.common_features(local), local);
+
+        assert_eq!(table.tick(start + Duration::from_secs(6)), vec![7]);
+        assert_eq!(table.common_version(), None);
+        assert_eq!(table.common_features(local), ProtocolFeatures::NONE);
+    }
+
+    #[test]
+    fn test_timeouts_goodbyes_and_downgrades_reach_subscribers() {
+        let mut table = PeerTable::new().with_timeout(Duration::from_secs(3));
+        let events = table.subscribe(8);
+        let addr: SocketAddr = "10.0.0.7:40000".parse().unwrap();
+        let start = Instant::now();
+        let heartbeat = |sender_id, sequence| FleetMsgHeader::new(MessageType::Heartbeat, sender_id, sequence, 0);
+
+        table.observe(&heartbeat(7, 1), addr, start);
+        table.observe(&heartbeat(8, 1), addr, start);
+        table.observe(&heartbeat(8, 2), addr, start + Duration::from_secs(2));
+        assert!(table.tick(start + Duration::from_secs(3)).is_empty());
+        assert_eq!(table.tick(start + Duration::from_secs(4)), vec![7]);
+        assert!(table.tick(start + Duration::from_secs(4)).is_empty());
+        assert!(!table.get(7).unwrap().online);
+        assert_eq!(table.online().map(|peer| peer.sender_id).collect::<Vec<_>>(), vec![8]);
+
+        // Heard from again after timing out, it rejoins
+        assert!(table.observe(&heartbeat(7, 5), addr, start + Duration::from_secs(6)));
+        assert!(table.leave(8).is_some());
+        assert!(table.leave(8).is_none());
+        table.pin_features(7, ProtocolFeatures::ENCRYPTION);
+        assert!(table.announce(7, Capabilities::default()));
+
+        let received: Vec<PeerEvent> = std::iter::from_fn(|| events.try_next()).collect();
+        assert_eq!(received, vec![
+            PeerEvent::Joined { sender_id: 7, addr },
+            PeerEvent::Joined { sender_id: 8, addr },
+            PeerEvent::TimedOut { sender_id: 7, last_seen: start },
+            PeerEvent::Joined { sender_id: 7, addr },
+            PeerEvent::Left { sender_id: 8 },
+            PeerEvent::Downgraded { sender_id: 7, announced: ProtocolFeatures::NONE },
+        ]);
+        assert_eq!(events.dropped(), 0);
+    }
+}
@@ -0,0 +1,121 @@
+//! Low-power receiving for battery devices: instead of waking for every
+//! datagram, the receiver sleeps for a coarse interval and then handles
+//! everything that queued in the socket meanwhile as one batch.
+//!
+//! Optionally it also stays awake through rendezvous windows, when senders
+//! known to talk to it transmit, e.g. the on-windows of their
+//! [`ShapingRule::duty_cycle`](crate::shaping::ShapingRule::duty_cycle). The
+//! windows are aligned to the Unix epoch the same way, so the same period,
+//! on-time and phase line the two up on nodes with synchronized clocks.
+//!
+//! Datagrams queue in the kernel's socket buffer while the receiver sleeps;
+//! one that fills up during a long interval drops what arrives after.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Datagrams handled per wake-up by default; the rest wait for the next
+pub const DEFAULT_MAX_BATCH: usize = 256;
+
+/// What the receiver does next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// In a rendezvous window: receive as datagrams arrive, for this long
+    Listen(Duration),
+    /// Sleep this long, then handle what queued meanwhile
+    Sleep(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rendezvous {
+    period: Duration,
+    on: Duration,
+    phase: Duration,
+}
+
+/// When a low-power receiver wakes; set it with
+/// [`ReceiverConfig::with_power_policy`](crate::receiver::ReceiverConfig::with_power_policy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerPolicy {
+    wake_interval: Duration,
+    rendezvous: Option<Rendezvous>,
+    max_batch: usize,
+}
+
+impl PowerPolicy {
+    /// Wake every `wake_interval` to handle what arrived
+    pub fn new(wake_interval: Duration) -> Self {
+        Self { wake_interval: wake_interval.max(Duration::from_millis(1)), rendezvous: None, max_batch: DEFAULT_MAX_BATCH }
+    }
+
+    /// Also stay awake for the first `on` of every `period`, shifted `phase`
+    /// past the period boundary
+    pub fn with_rendezvous(mut self, period: Duration, on: Duration, phase: Duration) -> Self {
+        let period = period.max(Duration::from_millis(1));
+        let phase = Duration::from_nanos((phase.as_nanos() % period.as_nanos()) as u64);
+        self.rendezvous = Some(Rendezvous { period, on: on.min(period), phase });
+        self
+    }
+
+    pub fn with_max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = max_batch.max(1);
+        self
+    }
+
+    pub fn wake_interval(&self) -> Duration {
+        self.wake_interval
+    }
+
+    pub fn max_batch(&self) -> usize {
+        self.max_batch
+    }
+
+    /// What to do at `now`: listen through a rendezvous window, otherwise
+    /// sleep until the next wake-up or window, whichever comes first
+    pub fn next(&self, now: SystemTime) -> Wake {
+        let Some(Rendezvous { period, on, phase }) = self.rendezvous else {
+            return Wake::Sleep(self.wake_interval);
+        };
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let position = Duration::from_nanos(((since_epoch + period - phase).as_nanos() % period.as_nanos()) as u64);
+        if position < on {
+            Wake::Listen(on - position)
+        } else {
+            Wake::Sleep(self.wake_interval.min(period - position))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::ReceiverConfig;
+    use crate::testing::TestReceiver;
+
+    #[test]
+    fn test_rendezvous_windows_cut_sleep_short() {
+        let second = Duration::from_secs(1);
+        // Awake for 2 s from 5 s into every minute, otherwise every 20 s
+        let policy = PowerPolicy::new(20 * second).with_rendezvous(60 * second, 2 * second, 5 * second);
+        let minute = UNIX_EPOCH + 1_000_000 * 60 * second;
+
+        assert_eq!(policy.next(minute + 6 * second), Wake::Listen(second));
+        assert_eq!(policy.next(minute + 10 * second), Wake::Sleep(20 * second));
+        assert_eq!(policy.next(minute + 50 * second), Wake::Sleep(15 * second));
+        assert_eq!(PowerPolicy::new(20 * second).next(minute), Wake::Sleep(20 * second));
+    }
+
+    #[async_std::test]
+    async fn test_messages_are_handled_in_batches() {
+        let policy = PowerPolicy::new(Duration::from_millis(500));
+        let receiver = TestReceiver::start_with_config(ReceiverConfig::new().with_power_policy(policy)).await.unwrap();
+        let mut sender = receiver.sender(3).await.unwrap();
+
+        for i in 0..3u8 {
+            sender.send_data(&[i]).await.unwrap();
+        }
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.received().is_empty(), "the receiver should still be asleep");
+        let received = receiver.wait_for(3, Duration::from_secs(2)).await;
+        assert_eq!(received.iter().map(|(_, payload, _)| payload[0]).collect::<Vec<_>>(), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,20 @@
+//! The types most applications need, for a single glob import:
+//!
+//! ```
+//! use fleetlink_transport::prelude::*;
+//! ```
+
+pub use crate::capabilities::Capabilities;
+pub use crate::channels::{Channel, ChannelRegistry};
+pub use crate::error::TransportError;
+pub use crate::extensions::Extensions;
+pub use crate::features::{FeatureCodec, ProtocolFeatures};
+pub use crate::message::FleetMessage;
+pub use crate::peers::{PeerEvent, PeerEvents, PeerInfo, PeerTable};
+pub use crate::receiver::{BorrowedDelivery, Delivery, ReceiverConfig};
+pub use crate::stats::{StatsSnapshot, TransportStats};
+pub use crate::trace::TraceId;
+pub use crate::transport::{
+    FleetMsgHeader, MessageType, MulticastReceiver, MulticastSender, start_multicast_rx, start_multicast_rx_borrowed, start_multicast_rx_extended,
+    start_multicast_rx_with_codec
+};
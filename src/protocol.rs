@@ -0,0 +1,97 @@
+//! The wire format: header, message types and the optional protocol features.
+//!
+//! The constants here are the single source of the numbers on the wire; the
+//! rest of the crate refers to them rather than repeating literals. Changing
+//! any of them changes the protocol, so they follow the protocol [`VERSION`],
+//! not the crate version:
+//!
+//! | Version | Change                                                 |
+//! |---------|--------------------------------------------------------|
+//! | 1       | Original header, timestamps in milliseconds            |
+//! | 2       | Timestamps in microseconds; feature flags in `msg_type` |
+//! | 3       | Header checksum is a CRC32C, opt-in (see [`HeaderChecksum`]) |
+//! | 4       | Header checksum is a CRC32C covering the payload too, opt-in |
+//!
+//! Every multi-byte field, in the header and in the payload formats defined
+//! here, is little-endian, so big-endian hosts interoperate with the rest.
+
+use std::ops::RangeInclusive;
+
+pub use crate::message::FleetMessage;
+pub use crate::transport::{FleetMsgHeader, HeaderChecksum, MessageType};
+
+#[path = "message.rs"]
+pub mod message;
+#[path = "message_types.rs"]
+pub mod message_types;
+#[path = "extensions.rs"]
+pub mod extensions;
+#[path = "features.rs"]
+pub mod features;
+#[cfg(feature = "compression-dict")]
+#[path = "dictionary.rs"]
+pub mod dictionary;
+#[path = "fragment.rs"]
+pub mod fragment;
+#[path = "padding.rs"]
+pub mod padding;
+#[path = "trace.rs"]
+pub mod trace;
+#[path = "timing.rs"]
+pub mod timing;
+#[path = "capabilities.rs"]
+pub mod capabilities;
+
+/// First field of every header; anything else isn't FleetLink traffic
+pub const MAGIC: u32 = 0xFEED;
+
+/// Protocol version this build sends unless told to use another
+pub const VERSION: u8 = 2;
+
+/// Oldest protocol version still accepted
+pub const MIN_VERSION: u8 = 1;
+
+/// Newest protocol version accepted
+pub const MAX_VERSION: u8 = 4;
+
+/// First protocol version with [`ProtocolFeatures`](crate::features::ProtocolFeatures)
+/// flags in `msg_type`; version 1 payloads are always sent as they are
+pub const FEATURES_VERSION: u8 = 2;
+
+/// First protocol version whose header checksum is a CRC32C rather than a byte sum
+pub const CRC32C_VERSION: u8 = 3;
+
+/// First protocol version whose header checksum covers the payload as well
+pub const PAYLOAD_CHECKSUM_VERSION: u8 = 4;
+
+/// Size of [`FleetMsgHeader`] on the wire
+pub const HEADER_LEN: usize = 24;
+
+const _: () = assert!(HEADER_LEN == std::mem::size_of::<FleetMsgHeader>());
+
+/// Bits of `msg_type` holding the [`MessageType`]
+pub const MSG_TYPE_MASK: u8 = 0x0F;
+
+/// Shift of the [`ProtocolFeatures`](crate::features::ProtocolFeatures) flags in the high nibble of `msg_type`
+pub const FEATURES_SHIFT: u32 = 4;
+
+/// Message type codes this build understands
+pub const MESSAGE_TYPES: RangeInclusive<u8> = 1..=5;
+
+/// Message type codes left to applications, never given to a built-in type.
+/// The type is four bits wide, the high nibble of `msg_type` carrying
+/// feature flags, so this is its upper half rather than 128-255.
+pub const APPLICATION_TYPES: RangeInclusive<u8> = 8..=15;
+
+/// Largest payload `payload_len` can describe
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
+
+/// Largest UDP payload over IPv4, i.e. the largest datagram, header included
+pub const MAX_DATAGRAM_LEN: usize = 65_507;
+
+/// Largest datagram, header included, that fits a 1500-byte Ethernet MTU
+/// without IP fragmentation (20 bytes of IPv4 and 8 of UDP header)
+pub const MTU_DATAGRAM_LEN: usize = 1_472;
+
+/// Most fragments one message may be split into (see [`fragment`])
+pub const MAX_FRAGMENTS: usize = 1_024;
@@ -0,0 +1,817 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use zerocopy::{FromBytes, FromZeroes};
+
+use crate::alloc_counter::{self, Subsystem};
+use crate::error::{self, TransportError};
+use crate::extensions::Extensions;
+use crate::features::{FeatureCodec, ProtocolFeatures};
+use crate::fragment::{self, Reassembler};
+use crate::message::FleetMessage;
+use crate::power::PowerPolicy;
+use crate::protocol;
+use crate::scheduling::{BusyPoll, ThreadScheduling};
+use crate::sequence_stats::SequenceTracker;
+use crate::tap::FrameTap;
+use crate::transport::{FleetMsgHeader, MessageType};
+use crate::usage::UsageAccounting;
+use crate::zones::ZoneFilter;
+
+/// Receive buffer size by default: one standard 1500-byte MTU
+pub const RECEIVE_BUFFER_LEN: usize = 1500;
+/// Largest payload that fits the default receive buffer after the header
+pub const DEFAULT_MAX_PAYLOAD_LEN: usize = RECEIVE_BUFFER_LEN - protocol::HEADER_LEN;
+/// IPv4 and UDP headers, the part of a link's MTU a datagram can't use
+const IPV4_UDP_HEADER_LEN: usize = 20 + 8;
+/// Largest message a payload may expand to (e.g. when decompressed)
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// What a receiver does with messages that fail validation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    /// Drop anything unexpected, including message types this build doesn't know
+    Strict,
+    /// Drop malformed messages (bad header, length or encoding) but pass unknown message types through
+    #[default]
+    Standard,
+    /// Deliver everything that has a header, listing what was wrong in [`Delivery::issues`]
+    Lenient,
+    /// Deliver every datagram as received, without decoding, for debugging tools;
+    /// one too short for a header comes with a zeroed header and the raw bytes as payload
+    Promiscuous,
+}
+
+/// One thing wrong with a received datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    Truncated { len: usize },
+    BadMagic(u32),
+    UnsupportedVersion(u8),
+    BadChecksum,
+    UnknownMessageType(u8),
+    PayloadTooLarge { claimed: usize, limit: usize },
+    LengthMismatch { claimed: usize, actual: usize },
+    /// Optional features or extensions could not be undone
+    Undecodable(String),
+    /// The datagram didn't fit the receive buffer and was cut short
+    ExceedsBuffer { buffer_len: usize },
+}
+
+impl ValidationIssue {
+    /// Whether the message is damaged, rather than merely from newer firmware
+    pub fn is_malformed(&self) -> bool {
+        !matches!(self, ValidationIssue::UnknownMessageType(_))
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::Truncated { len } => write!(f, "packet too small for header ({} bytes)", len),
+            ValidationIssue::ExceedsBuffer { buffer_len } => {
+                write!(f, "datagram longer than the {} byte receive buffer", buffer_len)
+            }
+            ValidationIssue::BadMagic(magic) => write!(f, "bad magic {:#x}", magic),
+            ValidationIssue::UnsupportedVersion(version) => write!(f, "unsupported version {}", version),
+            ValidationIssue::BadChecksum => write!(f, "header checksum mismatch"),
+            ValidationIssue::UnknownMessageType(msg_type) => write!(f, "unknown message type {}", msg_type),
+            ValidationIssue::PayloadTooLarge { claimed, limit } => {
+                write!(f, "payload_len {} over the {} byte limit", claimed, limit)
+            }
+            ValidationIssue::LengthMismatch { claimed, actual } => {
+                write!(f, "payload length mismatch: expected {}, got {}", claimed, actual)
+            }
+            ValidationIssue::Undecodable(reason) => write!(f, "payload does not decode: {}", reason),
+        }
+    }
+}
+
+/// A received message as handed to an extended handler
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub header: FleetMsgHeader,
+    /// Extensions (e.g. the trace id) that came ahead of the payload
+    pub extensions: Extensions,
+    pub payload: Vec<u8>,
+    pub addr: SocketAddr,
+    /// What failed validation; only ever non-empty under a lenient or promiscuous policy
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl Delivery {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The header and decoded payload as a [`FleetMessage`], as if sent without optional features
+    pub fn message(&self) -> FleetMessage {
+        FleetMessage::decoded(self.header, self.payload.clone())
+    }
+
+    pub fn into_message(self) -> FleetMessage {
+        FleetMessage::decoded(self.header, self.payload)
+    }
+}
+
+/// A received message whose payload is, where possible, borrowed from the
+/// receive buffer rather than copied; see
+/// [`start_multicast_rx_borrowed`](crate::transport::start_multicast_rx_borrowed).
+/// Payloads that had to be decrypted, decompressed or reassembled are owned.
+#[derive(Debug, Clone)]
+pub struct BorrowedDelivery<'a> {
+    pub header: FleetMsgHeader,
+    pub extensions: Extensions,
+    pub payload: Cow<'a, [u8]>,
+    pub addr: SocketAddr,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl BorrowedDelivery<'_> {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The payload still lies in the receive buffer, and is gone once the handler returns
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.payload, Cow::Borrowed(_))
+    }
+
+    /// Copy the payload out (if it is borrowed), for handlers that keep messages
+    pub fn into_owned(self) -> Delivery {
+        Delivery {
+            header: self.header,
+            extensions: self.extensions,
+            payload: self.payload.into_owned(),
+            addr: self.addr,
+            issues: self.issues,
+        }
+    }
+}
+
+impl From<Delivery> for BorrowedDelivery<'static> {
+    fn from(delivery: Delivery) -> Self {
+        let Delivery { header, extensions, payload, addr, issues } = delivery;
+        BorrowedDelivery { header, extensions, payload: Cow::Owned(payload), addr, issues }
+    }
+}
+
+/// A [`ReceiverFilter`]'s custom test of a header
+pub type HeaderPredicate = Arc<dyn Fn(&FleetMsgHeader) -> bool + Send + Sync>;
+
+/// Decides from the header alone whether a receiver wants a message, so
+/// unwanted traffic is dropped before it is validated, decoded or handed over.
+///
+/// A message passes if its type is among the allowed types (any, if none
+/// were given), its sender is on the allow list or in an allowed range (any,
+/// without either) and not on the deny list, and the predicate, if any,
+/// accepts its header.
+#[derive(Clone, Default)]
+pub struct ReceiverFilter {
+    pub(crate) types: Option<Vec<MessageType>>,
+    pub(crate) allowed_senders: Option<HashSet<u32>>,
+    pub(crate) allowed_ranges: Vec<RangeInclusive<u32>>,
+    pub(crate) denied_senders: HashSet<u32>,
+    predicate: Option<HeaderPredicate>,
+}
+
+impl fmt::Debug for ReceiverFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReceiverFilter")
+            .field("types", &self.types)
+            .field("allowed_senders", &self.allowed_senders)
+            .field("allowed_ranges", &self.allowed_ranges)
+            .field("denied_senders", &self.denied_senders)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+impl ReceiverFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only pass messages of these types
+    pub fn with_message_types(mut self, types: impl IntoIterator<Item = MessageType>) -> Self {
+        self.types = Some(types.into_iter().collect());
+        self
+    }
+
+    /// Only pass messages from these senders
+    pub fn with_allowed_senders(mut self, senders: impl IntoIterator<Item = u32>) -> Self {
+        self.allowed_senders = Some(senders.into_iter().collect());
+        self
+    }
+
+    /// Also pass messages from senders in `range`, e.g. a fleet's block of ids
+    pub fn with_allowed_sender_range(mut self, range: RangeInclusive<u32>) -> Self {
+        self.allowed_ranges.push(range);
+        self
+    }
+
+    /// Drop messages from these senders, even if allowed
+    pub fn with_denied_senders(mut self, senders: impl IntoIterator<Item = u32>) -> Self {
+        self.denied_senders.extend(senders);
+        self
+    }
+
+    /// Also require `predicate` to accept the header
+    pub fn with_predicate(mut self, predicate: impl Fn(&FleetMsgHeader) -> bool + Send + Sync + 'static) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    pub fn accepts(&self, header: &FleetMsgHeader) -> bool {
+        let sender_id = header.sender_id.get();
+        self.types.as_ref().is_none_or(|types| types.contains(&header.message_type()))
+            && self.allows_sender(sender_id)
+            && !self.denied_senders.contains(&sender_id)
+            && self.predicate.as_ref().is_none_or(|predicate| predicate(header))
+    }
+
+    fn allows_sender(&self, sender_id: u32) -> bool {
+        if self.allowed_senders.is_none() && self.allowed_ranges.is_empty() {
+            return true;
+        }
+        self.allowed_senders.as_ref().is_some_and(|senders| senders.contains(&sender_id))
+            || self.allowed_ranges.iter().any(|range| range.contains(&sender_id))
+    }
+}
+
+/// How a receiver decodes, bounds and validates what arrives.
+///
+/// Size limits are checked against the header's `payload_len` claim before
+/// anything is copied out of the receive buffer.
+#[derive(Debug, Clone)]
+pub struct ReceiverConfig {
+    codec: FeatureCodec,
+    buffer_len: usize,
+    /// None: whatever fits the buffer after the header
+    max_payload_len: Option<usize>,
+    max_message_len: usize,
+    validation: ValidationPolicy,
+    tap: Option<FrameTap>,
+    reassembly_timeout: Duration,
+    usage: Option<Arc<UsageAccounting>>,
+    power: Option<PowerPolicy>,
+    scheduling: Option<ThreadScheduling>,
+    busy_poll: Option<BusyPoll>,
+    filter: Option<ReceiverFilter>,
+    kernel_filter: bool,
+    /// Object built from `c/fleetlink_filter.c`
+    #[cfg(feature = "ebpf")]
+    ebpf_filter: Option<std::path::PathBuf>,
+    sequences: Option<SequenceTracker>,
+    zones: Option<ZoneFilter>,
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        Self {
+            codec: FeatureCodec::default(),
+            buffer_len: RECEIVE_BUFFER_LEN,
+            max_payload_len: None,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            validation: ValidationPolicy::default(),
+            tap: None,
+            reassembly_timeout: fragment::DEFAULT_REASSEMBLY_TIMEOUT,
+            usage: None,
+            power: None,
+            scheduling: None,
+            busy_poll: None,
+            filter: None,
+            kernel_filter: false,
+            #[cfg(feature = "ebpf")]
+            ebpf_filter: None,
+            sequences: None,
+            zones: None,
+        }
+    }
+}
+
+impl ReceiverConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Strip optional features (e.g. decrypt with the fleet key) with this codec
+    pub fn with_codec(mut self, codec: FeatureCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Receive into a buffer of this many bytes (default 1500), e.g. 9000 for
+    /// jumbo frames; a longer datagram is reported as
+    /// [`ExceedsBuffer`](ValidationIssue::ExceedsBuffer) rather than cut short
+    pub fn with_buffer_len(mut self, len: usize) -> Self {
+        self.buffer_len = len.clamp(protocol::HEADER_LEN, protocol::MAX_DATAGRAM_LEN);
+        self
+    }
+
+    /// Size the buffer for the largest datagram a link with this MTU carries
+    pub fn with_mtu(self, mtu: usize) -> Self {
+        self.with_buffer_len(mtu.saturating_sub(IPV4_UDP_HEADER_LEN))
+    }
+
+    /// Size the buffer for the largest MTU among this host's interfaces
+    /// (see [`largest_interface_mtu`]), keeping the current size if that can't
+    /// be found out or is smaller
+    pub fn with_detected_mtu(self) -> Self {
+        match largest_interface_mtu() {
+            Ok(mtu) if mtu.saturating_sub(IPV4_UDP_HEADER_LEN) > self.buffer_len => self.with_mtu(mtu),
+            _ => self,
+        }
+    }
+
+    /// Drop messages whose header claims a larger payload than this (by
+    /// default, whatever fits the receive buffer after the header)
+    pub fn with_max_payload_len(mut self, len: usize) -> Self {
+        self.max_payload_len = Some(len);
+        self
+    }
+
+    /// Drop messages that would expand past this once decoded
+    pub fn with_max_message_len(mut self, len: usize) -> Self {
+        self.max_message_len = len;
+        self
+    }
+
+    pub fn with_validation(mut self, policy: ValidationPolicy) -> Self {
+        self.validation = policy;
+        self
+    }
+
+    /// Copy every raw datagram, with its parse outcome, to `tap`; those the
+    /// filter turns away arrive there as not delivered
+    pub fn with_tap(mut self, tap: FrameTap) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// How long the fragments of a message have to arrive before it is dropped
+    pub fn with_reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembly_timeout = timeout;
+        self
+    }
+
+    /// Count delivered messages per topic into `usage`
+    pub fn with_usage(mut self, usage: Arc<UsageAccounting>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// Wake up now and then and handle what arrived in batches, instead of
+    /// waking for every datagram; for battery devices
+    pub fn with_power_policy(mut self, policy: PowerPolicy) -> Self {
+        self.power = Some(policy);
+        self
+    }
+
+    /// Receive on a thread of its own, pinned and prioritized as `scheduling`
+    /// says, instead of on the async runtime's threads
+    pub fn with_thread_scheduling(mut self, scheduling: ThreadScheduling) -> Self {
+        self.scheduling = Some(scheduling).filter(|scheduling| !scheduling.is_default());
+        self
+    }
+
+    /// Spin on the socket rather than wait for datagrams, on a thread of its
+    /// own; this keeps a core busy, see [`BusyPoll`] for the cost
+    pub fn with_busy_poll(mut self, busy_poll: BusyPoll) -> Self {
+        self.busy_poll = Some(busy_poll);
+        self
+    }
+
+    /// Drop messages `filter` doesn't accept before anything else is done with them
+    pub fn with_filter(mut self, filter: ReceiverFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Have the kernel drop what the filter and validation would, before the
+    /// receiver sees it (Linux only, see [`SocketFilter`](crate::socket_filter::SocketFilter));
+    /// the dropped datagrams aren't counted or tapped
+    pub fn with_kernel_filter(mut self, enabled: bool) -> Self {
+        self.kernel_filter = enabled;
+        self
+    }
+
+    /// Have the kernel drop non-FleetLink traffic and what the filter turns
+    /// away by type or sender block with the eBPF program at `path`, built from
+    /// `c/fleetlink_filter.c` (Linux only, needs `CAP_BPF`; see
+    /// [`EbpfFilter`](crate::socket_filter::EbpfFilter)). If it can't be
+    /// loaded, the receiver falls back to `with_kernel_filter`'s program, if
+    /// asked for, or to filtering in userspace.
+    #[cfg(feature = "ebpf")]
+    pub fn with_ebpf_filter(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.ebpf_filter = Some(path.into());
+        self
+    }
+
+    /// Drop duplicate messages and report sequence gaps and reordering through `tracker`
+    pub fn with_sequence_tracker(mut self, tracker: SequenceTracker) -> Self {
+        self.sequences = Some(tracker);
+        self
+    }
+
+    /// Drop messages from senders outside the areas `filter` passes, by the
+    /// zone they were stamped with; see [`zones`](crate::zones)
+    pub fn with_zone_filter(mut self, filter: ZoneFilter) -> Self {
+        self.zones = Some(filter);
+        self
+    }
+
+    pub fn codec(&self) -> &FeatureCodec {
+        &self.codec
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffer_len
+    }
+
+    pub fn max_payload_len(&self) -> usize {
+        self.max_payload_len.unwrap_or(self.buffer_len - protocol::HEADER_LEN)
+    }
+
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+
+    pub fn validation(&self) -> ValidationPolicy {
+        self.validation
+    }
+
+    pub fn usage(&self) -> Option<&Arc<UsageAccounting>> {
+        self.usage.as_ref()
+    }
+
+    pub fn power_policy(&self) -> Option<PowerPolicy> {
+        self.power
+    }
+
+    pub fn thread_scheduling(&self) -> Option<&ThreadScheduling> {
+        self.scheduling.as_ref()
+    }
+
+    pub fn busy_poll(&self) -> Option<BusyPoll> {
+        self.busy_poll
+    }
+
+    pub fn filter(&self) -> Option<&ReceiverFilter> {
+        self.filter.as_ref()
+    }
+
+    pub fn kernel_filter(&self) -> bool {
+        self.kernel_filter
+    }
+
+    #[cfg(feature = "ebpf")]
+    pub fn ebpf_filter(&self) -> Option<&std::path::Path> {
+        self.ebpf_filter.as_deref()
+    }
+
+    pub fn sequence_tracker(&self) -> Option<&SequenceTracker> {
+        self.sequences.as_ref()
+    }
+
+    pub fn zone_filter(&self) -> Option<&ZoneFilter> {
+        self.zones.as_ref()
+    }
+
+    /// Whether a validated message should be handed over: false for a
+    /// duplicate when there is a sequence tracker, or for a message from
+    /// outside the zone filter's area. The tracker sees messages from every
+    /// zone, so a sender moving between zones doesn't show gaps.
+    pub(crate) fn admits(&self, header: &FleetMsgHeader, extensions: &Extensions, payload: &[u8], addr: SocketAddr) -> bool {
+        self.sequences.as_ref().is_none_or(|tracker| tracker.admit(header, payload, addr))
+            && self.zones.as_ref().is_none_or(|filter| filter.accepts(extensions.zone().as_ref()))
+    }
+
+    /// Whether the filter, if any, passes `datagram`; one too short for a
+    /// header passes, to be reported by validation
+    pub fn accepts(&self, datagram: &[u8]) -> bool {
+        let Some(filter) = &self.filter else { return true };
+        FleetMsgHeader::read_from_prefix(datagram).is_none_or(|header| filter.accepts(&header))
+    }
+
+    /// Like `accepts`, also copying a datagram the filter turns away to the
+    /// tap, as not delivered
+    pub(crate) fn screen(&self, datagram: &[u8], addr: SocketAddr) -> bool {
+        let accepted = self.accepts(datagram);
+        if !accepted && let Some(tap) = &self.tap {
+            tap.record(datagram, addr, false, &[]);
+        }
+        accepted
+    }
+
+    /// Validate and decode a datagram received by other means, e.g. read from
+    /// a capture, as a receiver with this config would
+    pub fn parse(&self, datagram: &[u8], addr: SocketAddr) -> error::Result<Delivery> {
+        inspect(datagram, addr, self).map_err(TransportError::from_issues)
+    }
+
+    /// A [`Reassembler`] with this config's timeout and message limit
+    pub fn reassembler(&self) -> Reassembler {
+        Reassembler::new().with_timeout(self.reassembly_timeout).with_max_message_len(self.max_message_len)
+    }
+}
+
+/// MTU of the network interface `name` (e.g. `eth0`), as the kernel reports it
+#[cfg(target_os = "linux")]
+pub fn interface_mtu(name: &str) -> std::io::Result<usize> {
+    let path = std::path::Path::new("/sys/class/net").join(name).join("mtu");
+    std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("MTU of {}: {}", name, e)))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn interface_mtu(_name: &str) -> std::io::Result<usize> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "interface MTUs are only read on Linux"))
+}
+
+/// The largest MTU of this host's interfaces, loopback aside
+pub fn largest_interface_mtu() -> std::io::Result<usize> {
+    let mut largest = None;
+    if cfg!(target_os = "linux") {
+        for entry in std::fs::read_dir("/sys/class/net")? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name != "lo" && let Ok(mtu) = interface_mtu(&name) {
+                largest = largest.max(Some(mtu));
+            }
+        }
+    }
+    largest.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no interface MTU found"))
+}
+
+/// Validate a datagram and, if `config`'s policy delivers it, decode it.
+/// Returns the issues instead when the message is dropped. Either way the
+/// datagram goes to the config's tap, if it has one.
+pub(crate) fn inspect(datagram: &[u8], addr: SocketAddr, config: &ReceiverConfig) -> Result<Delivery, Vec<ValidationIssue>> {
+    examine(datagram, addr, config, false).map(BorrowedDelivery::into_owned)
+}
+
+/// Like [`inspect`] for a datagram read from a socket into a buffer one byte
+/// longer than the config's `buffer_len`, so one that didn't fit shows.
+/// Leaves the payload in `datagram` where it can.
+pub(crate) fn inspect_borrowed<'a>(
+    datagram: &'a [u8],
+    addr: SocketAddr,
+    config: &ReceiverConfig
+) -> Result<BorrowedDelivery<'a>, Vec<ValidationIssue>> {
+    examine(datagram, addr, config, true)
+}
+
+fn examine<'a>(
+    datagram: &'a [u8],
+    addr: SocketAddr,
+    config: &ReceiverConfig,
+    from_socket: bool
+) -> Result<BorrowedDelivery<'a>, Vec<ValidationIssue>> {
+    let _scope = alloc_counter::scope(Subsystem::RxBuffers);
+    let clipped = from_socket && datagram.len() > config.buffer_len;
+    let datagram = if clipped { &datagram[..config.buffer_len] } else { datagram };
+    let mut outcome = validate(datagram, addr, config);
+    if clipped {
+        // Whatever else is wrong, the rest of the datagram is missing
+        let issue = ValidationIssue::ExceedsBuffer { buffer_len: config.buffer_len };
+        outcome = match outcome {
+            Ok(mut delivery) if matches!(config.validation, ValidationPolicy::Lenient | ValidationPolicy::Promiscuous) => {
+                delivery.issues.insert(0, issue);
+                Ok(delivery)
+            }
+            Ok(delivery) => Err(std::iter::once(issue).chain(delivery.issues).collect()),
+            Err(issues) => Err(std::iter::once(issue).chain(issues).collect()),
+        };
+    }
+    if let Some(tap) = &config.tap {
+        match &outcome {
+            Ok(delivery) => tap.record(datagram, addr, true, &delivery.issues),
+            Err(issues) => tap.record(datagram, addr, false, issues),
+        }
+    }
+    outcome
+}
+
+fn validate<'a>(datagram: &'a [u8], addr: SocketAddr, config: &ReceiverConfig) -> Result<BorrowedDelivery<'a>, Vec<ValidationIssue>> {
+    let policy = config.validation;
+    let Some(header) = FleetMsgHeader::read_from_prefix(datagram) else {
+        let issues = vec![ValidationIssue::Truncated { len: datagram.len() }];
+        return match policy {
+            ValidationPolicy::Promiscuous => {
+                let header = FleetMsgHeader::new_zeroed();
+                Ok(BorrowedDelivery { header, extensions: Extensions::new(), payload: Cow::Borrowed(datagram), addr, issues })
+            }
+            _ => Err(issues),
+        };
+    };
+
+    // Bound the payload_len claim before trusting it, then check the payload matches it
+    let body = &datagram[protocol::HEADER_LEN..];
+    let mut issues = header.validation_issues_for(body);
+    let claimed = header.payload_len.get() as usize;
+    if claimed > config.max_payload_len() {
+        issues.push(ValidationIssue::PayloadTooLarge { claimed, limit: config.max_payload_len() });
+    }
+    if body.len() != claimed {
+        issues.push(ValidationIssue::LengthMismatch { claimed, actual: body.len() });
+    }
+
+    // Nothing is copied out of the datagram for a message that is going to be dropped
+    let dropped = match policy {
+        ValidationPolicy::Strict => !issues.is_empty(),
+        ValidationPolicy::Standard => issues.iter().any(ValidationIssue::is_malformed),
+        ValidationPolicy::Lenient | ValidationPolicy::Promiscuous => false,
+    };
+    if dropped {
+        return Err(issues);
+    }
+    let plain = header.features().is_empty() && config.codec.required_for(header.message_type()).is_empty();
+    if policy == ValidationPolicy::Promiscuous || plain {
+        return Ok(BorrowedDelivery { header, extensions: Extensions::new(), payload: Cow::Borrowed(body), addr, issues });
+    }
+
+    match decode(&header, body, config) {
+        Ok((extensions, payload)) => Ok(BorrowedDelivery { header, extensions, payload, addr, issues }),
+        Err(e) => {
+            issues.push(ValidationIssue::Undecodable(e.to_string()));
+            match policy {
+                ValidationPolicy::Lenient => {
+                    Ok(BorrowedDelivery { header, extensions: Extensions::new(), payload: Cow::Borrowed(body), addr, issues })
+                }
+                _ => Err(issues),
+            }
+        }
+    }
+}
+
+fn decode<'a>(header: &FleetMsgHeader, body: &'a [u8], config: &ReceiverConfig) -> std::io::Result<(Extensions, Cow<'a, [u8]>)> {
+    let _scope = alloc_counter::scope(Subsystem::Codec);
+    let payload = config.codec.decode_borrowed(header, body, config.max_message_len)?;
+    if !header.features().contains(ProtocolFeatures::EXTENSIONS) {
+        return Ok((Extensions::new(), payload));
+    }
+    match payload {
+        Cow::Borrowed(payload) => {
+            let (extensions, payload) = Extensions::split(payload)?;
+            Ok((extensions, Cow::Borrowed(payload)))
+        }
+        Cow::Owned(payload) => {
+            let (extensions, payload) = Extensions::split(&payload)?;
+            Ok((extensions, Cow::Owned(payload.to_vec())))
+        }
+    }
+}
+
+/// One line listing `issues`, for logs
+pub(crate) fn describe(issues: &[ValidationIssue]) -> String {
+    issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::ProtocolFeatures;
+    use crate::transport::{HeaderChecksum, MessageType};
+    use zerocopy::AsBytes;
+
+    fn addr() -> SocketAddr {
+        "10.0.0.1:5000".parse().unwrap()
+    }
+
+    fn datagram(header: FleetMsgHeader, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = header.as_bytes().to_vec();
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    #[test]
+    fn test_limits_are_enforced() {
+        let config = ReceiverConfig::new().with_max_payload_len(100).with_max_message_len(500);
+
+        // An oversized claim is refused before the (short) payload is looked at
+        let claim = datagram(FleetMsgHeader::new(MessageType::Data, 1, 0, 60000), b"tiny");
+        let issues = inspect(&claim, addr(), &config).unwrap_err();
+        assert!(matches!(issues[0], ValidationIssue::PayloadTooLarge { claimed: 60000, limit: 100 }), "{:?}", issues);
+
+        // A small compressed payload that inflates past the message limit
+        let (used, bomb) = config.codec().encode(ProtocolFeatures::COMPRESSION, &[0u8; 4000]).unwrap();
+        let header = FleetMsgHeader::new(MessageType::Data, 1, 0, bomb.len() as u16).with_features(used);
+        assert!(bomb.len() <= 100);
+        assert!(inspect(&datagram(header, &bomb), addr(), &config).is_err());
+        assert_eq!(inspect(&datagram(header, &bomb), addr(), &ReceiverConfig::new()).unwrap().payload.len(), 4000);
+    }
+
+    #[test]
+    fn test_policies_decide_what_is_delivered() {
+        let policy = |policy| ReceiverConfig::new().with_validation(policy);
+        let mut unknown_type = FleetMsgHeader::new(MessageType::Data, 1, 0, 2);
+        unknown_type.msg_type = 7;
+        let unknown_type = datagram(unknown_type.with_features(ProtocolFeatures::NONE), b"hi");
+        let mut bad_checksum = FleetMsgHeader::new(MessageType::Data, 1, 0, 2);
+        bad_checksum.checksum ^= 1.into();
+        let bad_checksum = datagram(bad_checksum, b"hi");
+
+        // Newer message types pass by default but not in strict mode
+        assert!(inspect(&unknown_type, addr(), &policy(ValidationPolicy::Standard)).is_ok());
+        assert_eq!(inspect(&unknown_type, addr(), &policy(ValidationPolicy::Strict)).unwrap_err(),
+                   vec![ValidationIssue::UnknownMessageType(7)]);
+
+        // Damage is dropped unless the policy asks for a report
+        assert!(inspect(&bad_checksum, addr(), &policy(ValidationPolicy::Standard)).is_err());
+        let delivery = inspect(&bad_checksum, addr(), &policy(ValidationPolicy::Lenient)).unwrap();
+        assert_eq!((delivery.payload.as_slice(), delivery.issues), (&b"hi"[..], vec![ValidationIssue::BadChecksum]));
+
+        // Only promiscuous mode passes on what isn't a FleetLink frame at all
+        assert!(inspect(b"tiny", addr(), &policy(ValidationPolicy::Lenient)).is_err());
+        let delivery = inspect(b"tiny", addr(), &policy(ValidationPolicy::Promiscuous)).unwrap();
+        assert_eq!(delivery.payload, b"tiny");
+        assert!(!delivery.is_valid());
+    }
+
+    #[test]
+    fn test_payload_checksums_catch_corrupt_payloads() {
+        let config = ReceiverConfig::new();
+        let corrupt = |checksum| {
+            let mut bytes = FleetMessage::data(1, 0, &b"speed=12"[..]).with_header_checksum(checksum).to_bytes();
+            assert!(inspect(&bytes, addr(), &config).unwrap().is_valid());
+            *bytes.last_mut().unwrap() ^= 0x04;
+            bytes
+        };
+
+        // The header-only checksums let the damage through
+        assert_eq!(inspect(&corrupt(HeaderChecksum::Crc32c), addr(), &config).unwrap().payload, b"speed=16");
+        assert_eq!(inspect(&corrupt(HeaderChecksum::Crc32cPayload), addr(), &config).unwrap_err(), [ValidationIssue::BadChecksum]);
+
+        // Re-flagging features keeps the payload covered
+        let (used, body) = config.codec().encode(ProtocolFeatures::CRC32, b"speed=12").unwrap();
+        let message = FleetMessage::data(1, 0, body).with_header_checksum(HeaderChecksum::Crc32cPayload).with_features(used);
+        assert_eq!(inspect(&message.to_bytes(), addr(), &config).unwrap().payload, b"speed=12");
+    }
+
+    #[test]
+    fn test_payloads_are_borrowed_unless_decoded() {
+        let config = ReceiverConfig::new();
+        let plain = datagram(FleetMsgHeader::new(MessageType::Data, 1, 0, 2), b"hi");
+        let delivery = inspect_borrowed(&plain, addr(), &config).unwrap();
+        assert!(delivery.is_borrowed());
+        assert_eq!(delivery.payload.as_ptr(), plain[protocol::HEADER_LEN..].as_ptr());
+
+        // Checking a CRC and splitting off extensions need no copy
+        let mut extensions = Extensions::new();
+        extensions.set_last_value("pos").unwrap();
+        let (used, body) = config.codec().encode(ProtocolFeatures::CRC32, &extensions.prepend_to(b"1,2")).unwrap();
+        assert_eq!(used, ProtocolFeatures::CRC32);
+        let header = FleetMsgHeader::new(MessageType::Data, 1, 0, body.len() as u16).with_features(used | ProtocolFeatures::EXTENSIONS);
+        let checked = datagram(header, &body);
+        let delivery = inspect_borrowed(&checked, addr(), &config).unwrap();
+        assert!(delivery.is_borrowed());
+        assert_eq!((delivery.extensions.last_value(), delivery.payload.as_ref()), (Some("pos"), &b"1,2"[..]));
+
+        let (used, compressed) = config.codec().encode(ProtocolFeatures::COMPRESSION, &[7u8; 400]).unwrap();
+        let compressed = datagram(FleetMsgHeader::new(MessageType::Data, 1, 0, compressed.len() as u16).with_features(used), &compressed);
+        let delivery = inspect_borrowed(&compressed, addr(), &config).unwrap();
+        assert!(!delivery.is_borrowed());
+        assert_eq!(delivery.into_owned().payload, [7u8; 400]);
+    }
+
+    #[test]
+    fn test_datagrams_over_the_buffer_are_reported() {
+        let jumbo = datagram(FleetMsgHeader::new(MessageType::Data, 1, 0, 8000), &[1u8; 8000]);
+        // As read into the default buffer, one byte over
+        let clipped = &jumbo[..RECEIVE_BUFFER_LEN + 1];
+        let issues = inspect_borrowed(clipped, addr(), &ReceiverConfig::new()).unwrap_err();
+        assert_eq!(issues[0], ValidationIssue::ExceedsBuffer { buffer_len: RECEIVE_BUFFER_LEN });
+        let lenient = ReceiverConfig::new().with_validation(ValidationPolicy::Lenient);
+        assert!(!inspect_borrowed(clipped, addr(), &lenient).unwrap().is_valid());
+
+        let config = ReceiverConfig::new().with_mtu(9000);
+        assert_eq!((config.buffer_len(), config.max_payload_len()), (8972, 8948));
+        assert_eq!(inspect_borrowed(&jumbo, addr(), &config).unwrap().payload.len(), 8000);
+    }
+
+    #[test]
+    fn test_tap_sees_every_frame() {
+        let (tap, subscription) = FrameTap::new(2);
+        let config = ReceiverConfig::new().with_validation(ValidationPolicy::Strict).with_tap(tap);
+        let valid = datagram(FleetMsgHeader::new(MessageType::Data, 1, 0, 2), b"hi");
+
+        assert!(inspect(&valid, addr(), &config).is_ok());
+        assert!(inspect(b"tiny", addr(), &config).is_err());
+        assert!(inspect(b"tiny", addr(), &config).is_err());
+
+        let first = subscription.try_next().unwrap();
+        assert_eq!((first.datagram, first.delivered, first.issues), (valid, true, vec![]));
+        let second = subscription.try_next().unwrap();
+        assert_eq!((second.datagram.as_slice(), second.delivered), (&b"tiny"[..], false));
+        assert_eq!(second.issues, vec![ValidationIssue::Truncated { len: 4 }]);
+        // The third didn't fit and was counted instead of blocking the receiver
+        assert!(subscription.try_next().is_none());
+        assert_eq!(subscription.dropped(), 1);
+    }
+}
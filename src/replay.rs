@@ -0,0 +1,356 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use zerocopy::FromBytes;
+
+use crate::journal::{self, JournalEntry};
+use crate::protocol;
+use crate::soak::LatencySummary;
+use crate::transport::{self, FleetMsgHeader, MessageType};
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+
+/// Offline latency and gap statistics for a recorded journal or capture
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplaySummary {
+    pub messages: u64,
+    pub duration_secs: f64,
+    /// Sequence numbers skipped across all senders
+    pub missing: u64,
+    pub duplicates: u64,
+    pub reordered: u64,
+    pub latency_us: LatencySummary,
+    pub senders: Vec<SenderReplay>,
+}
+
+/// What one sender's stream looked like from the receiver
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SenderReplay {
+    pub sender_id: u32,
+    pub messages: u64,
+    pub missing: u64,
+    pub duplicates: u64,
+    /// Arrived after a later sequence number; each one fills a gap counted earlier
+    pub reordered: u64,
+    /// Heartbeats announcing a new incarnation; sequence tracking starts over at each one
+    pub restarts: u64,
+    pub latency_us: LatencySummary,
+    /// Time between consecutive messages; `max` is the longest silence
+    pub interarrival_us: LatencySummary,
+    pub gaps: Vec<SequenceGap>,
+}
+
+/// A run of sequence numbers that never arrived (as first observed)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceGap {
+    /// Microseconds from the start of the recording to the message after the gap
+    pub at_us: u64,
+    pub after_sequence: u16,
+    pub missing: u16,
+}
+
+/// Load a recording, telling a pcap capture from a journal by its magic number
+pub fn load_recording(path: impl AsRef<Path>) -> std::io::Result<Vec<JournalEntry>> {
+    let bytes = std::fs::read(&path)?;
+    if PcapFormat::detect(&bytes).is_some() {
+        parse_pcap(&bytes)
+    } else {
+        journal::read_journal(path)
+    }
+}
+
+/// Read a classic libpcap capture, keeping the UDP datagrams that carry valid fleet messages.
+///
+/// Ethernet, Linux cooked and raw IPv4 captures are supported; IP fragments are skipped.
+pub fn read_pcap(path: impl AsRef<Path>) -> std::io::Result<Vec<JournalEntry>> {
+    parse_pcap(&std::fs::read(path)?)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PcapFormat {
+    big_endian: bool,
+    nanos: bool,
+}
+
+impl PcapFormat {
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        let magic = bytes.get(..4)?;
+        let (big_endian, nanos) = match u32::from_le_bytes(magic.try_into().ok()?) {
+            0xa1b2_c3d4 => (false, false),
+            0xd4c3_b2a1 => (true, false),
+            0xa1b2_3c4d => (false, true),
+            0x4d3c_b2a1 => (true, true),
+            _ => return None,
+        };
+        Some(Self { big_endian, nanos })
+    }
+
+    fn u32_at(self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let raw: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) })
+    }
+}
+
+fn parse_pcap(bytes: &[u8]) -> std::io::Result<Vec<JournalEntry>> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+    let format = PcapFormat::detect(bytes).ok_or_else(|| invalid("not a pcap file"))?;
+    let link_type = format.u32_at(bytes, 20).ok_or_else(|| invalid("truncated pcap header"))?;
+    if ![LINKTYPE_ETHERNET, LINKTYPE_RAW, LINKTYPE_LINUX_SLL, LINKTYPE_IPV4].contains(&link_type) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("unsupported pcap link type {}", link_type)));
+    }
+
+    let mut entries = Vec::new();
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let record = (|| {
+            let seconds = format.u32_at(bytes, offset)? as u64;
+            let fraction = format.u32_at(bytes, offset + 4)? as u64;
+            let captured = format.u32_at(bytes, offset + 8)? as usize;
+            let data = bytes.get(offset + 16..offset + 16 + captured)?;
+            let micros = if format.nanos { fraction / 1000 } else { fraction };
+            Some((seconds * 1_000_000 + micros, data, 16 + captured))
+        })();
+        let (received_at_us, data, length) = record.ok_or_else(|| invalid("truncated pcap record"))?;
+        offset += length;
+
+        if let Some(entry) = ipv4_payload(link_type, data).and_then(|ip| fleet_datagram(ip, received_at_us)) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Strip the link-layer header, if the frame carries IPv4
+fn ipv4_payload(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    let ethertype = |at: usize| Some(u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?));
+    match link_type {
+        LINKTYPE_RAW | LINKTYPE_IPV4 => Some(frame),
+        LINKTYPE_LINUX_SLL => (ethertype(14)? == 0x0800).then(|| &frame[16..]),
+        _ => match ethertype(12)? {
+            0x0800 => frame.get(14..),
+            // Single 802.1Q tag
+            0x8100 if ethertype(16)? == 0x0800 => frame.get(18..),
+            _ => None,
+        },
+    }
+}
+
+fn fleet_datagram(ip: &[u8], received_at_us: u64) -> Option<JournalEntry> {
+    let header_len = (*ip.first()? & 0x0f) as usize * 4;
+    if ip[0] >> 4 != 4 || ip.len() < 20 || ip[9] != 17 {
+        return None;
+    }
+    // More-fragments flag or a fragment offset: the datagram can't be decoded on its own
+    if u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0 {
+        return None;
+    }
+    let source = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+
+    let udp = ip.get(header_len..)?;
+    let port = u16::from_be_bytes(udp.get(..2)?.try_into().ok()?);
+    let udp_len = u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?) as usize;
+    let datagram = udp.get(8..udp_len.max(8))?;
+
+    let header = FleetMsgHeader::read_from_prefix(datagram)?;
+    let payload = &datagram[protocol::HEADER_LEN..];
+    if !header.is_valid() || !header.verify(payload) || payload.len() != header.payload_len.get() as usize {
+        return None;
+    }
+    Some(JournalEntry::new(&header, payload, SocketAddr::from((source, port)), received_at_us))
+}
+
+/// Reconstruct per-sender latency, sequence gaps and silences from recorded messages
+pub fn analyze(entries: &[JournalEntry]) -> ReplaySummary {
+    let mut ordered: Vec<&JournalEntry> = entries.iter().collect();
+    ordered.sort_by_key(|entry| entry.received_at_us);
+    let Some((first, last)) = ordered.first().zip(ordered.last()) else {
+        return ReplaySummary::default();
+    };
+    let start = first.received_at_us;
+
+    #[derive(Default)]
+    struct Stream {
+        replay: SenderReplay,
+        last: Option<(u16, u64)>,
+        incarnation: Option<u64>,
+        latencies: Vec<u64>,
+        interarrivals: Vec<u64>,
+    }
+
+    // Note the incarnation a heartbeat announces; true if it differs from the previous one
+    fn restarted(stream: &mut Stream, entry: &JournalEntry) -> bool {
+        if entry.msg_type != MessageType::Heartbeat.code() {
+            return false;
+        }
+        let Some(incarnation) = entry.decode().and_then(|(_, payload)| transport::heartbeat_incarnation(&payload)) else {
+            return false;
+        };
+        stream.incarnation.replace(incarnation).is_some_and(|known| known != incarnation)
+    }
+
+    let mut streams: BTreeMap<u32, Stream> = BTreeMap::new();
+    let mut latencies = Vec::with_capacity(ordered.len());
+    for entry in &ordered {
+        let stream = streams.entry(entry.sender_id).or_default();
+        stream.replay.messages += 1;
+        stream.latencies.push(entry.latency_us());
+        latencies.push(entry.latency_us());
+
+        let Some((last_sequence, last_at)) = stream.last else {
+            restarted(stream, entry);
+            stream.last = Some((entry.sequence, entry.received_at_us));
+            continue;
+        };
+        stream.interarrivals.push(entry.received_at_us - last_at);
+
+        if restarted(stream, entry) {
+            stream.replay.restarts += 1;
+            stream.last = Some((entry.sequence, entry.received_at_us));
+            continue;
+        }
+
+        // Sequence numbers wrap, so anything within half the space ahead counts as forward
+        match entry.sequence.wrapping_sub(last_sequence) {
+            0 => stream.replay.duplicates += 1,
+            step if step < 0x8000 => {
+                if step > 1 {
+                    stream.replay.missing += (step - 1) as u64;
+                    stream.replay.gaps.push(SequenceGap {
+                        at_us: entry.received_at_us - start,
+                        after_sequence: last_sequence,
+                        missing: step - 1,
+                    });
+                }
+                stream.last = Some((entry.sequence, entry.received_at_us));
+                continue;
+            }
+            _ => {
+                stream.replay.reordered += 1;
+                stream.replay.missing = stream.replay.missing.saturating_sub(1);
+            }
+        }
+        stream.last = Some((last_sequence, entry.received_at_us));
+    }
+
+    let senders: Vec<SenderReplay> = streams.into_iter()
+        .map(|(sender_id, mut stream)| SenderReplay {
+            sender_id,
+            latency_us: LatencySummary::from_samples(&mut stream.latencies),
+            interarrival_us: LatencySummary::from_samples(&mut stream.interarrivals),
+            ..stream.replay
+        })
+        .collect();
+
+    ReplaySummary {
+        messages: ordered.len() as u64,
+        duration_secs: (last.received_at_us - start) as f64 / 1_000_000.0,
+        missing: senders.iter().map(|s| s.missing).sum(),
+        duplicates: senders.iter().map(|s| s.duplicates).sum(),
+        reordered: senders.iter().map(|s| s.reordered).sum(),
+        latency_us: LatencySummary::from_samples(&mut latencies),
+        senders,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MessageType;
+    use zerocopy::AsBytes;
+
+    fn entry(sender_id: u32, sequence: u16, received_at_us: u64) -> JournalEntry {
+        let header = FleetMsgHeader::new(MessageType::Data, sender_id, sequence, 0);
+        let mut entry = JournalEntry::new(&header, b"", "10.0.0.1:5000".parse().unwrap(), received_at_us);
+        entry.sent_at_us = received_at_us - 3000;
+        entry
+    }
+
+    /// A one-packet Ethernet capture of a fleet message from 10.0.0.5:6000
+    fn capture(header: &FleetMsgHeader, payload: &[u8], fragmented: bool) -> Vec<u8> {
+        let mut udp_payload = header.as_bytes().to_vec();
+        udp_payload.extend_from_slice(payload);
+
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, if fragmented { 0x20 } else { 0x40 }, 0, 64, 17, 0, 0, 10, 0, 0, 5, 239, 1, 1, 1];
+        ip.extend_from_slice(&6000u16.to_be_bytes());
+        ip.extend_from_slice(&12345u16.to_be_bytes());
+        ip.extend_from_slice(&((8 + udp_payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(&udp_payload);
+
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&ip);
+
+        let mut pcap = Vec::new();
+        for field in [0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, LINKTYPE_ETHERNET] {
+            pcap.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [1_700_000_000u32, 250_000, frame.len() as u32, frame.len() as u32] {
+            pcap.extend_from_slice(&field.to_le_bytes());
+        }
+        pcap.extend_from_slice(&frame);
+        pcap
+    }
+
+    #[test]
+    fn test_pcap_yields_fleet_messages() {
+        let header = FleetMsgHeader::new(MessageType::Heartbeat, 42, 7, 2);
+        let entries = parse_pcap(&capture(&header, b"hi", false)).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].sender_id, 42);
+        assert_eq!(entries[0].sequence, 7);
+        assert_eq!(entries[0].source, "10.0.0.5:6000");
+        assert_eq!(entries[0].received_at_us, 1_700_000_000_250_000);
+        assert_eq!(entries[0].decode().unwrap().1, b"hi");
+
+        assert!(parse_pcap(&capture(&header, b"hi", true)).unwrap().is_empty());
+        assert!(parse_pcap(b"not a capture").is_err());
+    }
+
+    #[test]
+    fn test_analyze_counts_gaps_duplicates_and_reordering() {
+        let entries = vec![
+            entry(1, 10, 10_000_000),
+            entry(1, 11, 10_100_000),
+            entry(1, 15, 10_900_000), // 12..=14 missing
+            entry(1, 13, 10_950_000), // late, fills one of them
+            entry(1, 15, 11_000_000), // duplicate
+            entry(2, u16::MAX, 10_050_000),
+            entry(2, 0, 10_150_000), // wraps without a gap
+        ];
+
+        let summary = analyze(&entries);
+        assert_eq!(summary.messages, 7);
+        assert_eq!(summary.duration_secs, 1.0);
+        assert_eq!((summary.missing, summary.duplicates, summary.reordered), (2, 1, 1));
+        assert_eq!(summary.latency_us.p50, 3000);
+
+        let first = &summary.senders[0];
+        assert_eq!(first.gaps, vec![SequenceGap { at_us: 900_000, after_sequence: 11, missing: 3 }]);
+        assert_eq!(first.interarrival_us.max, 800_000);
+        assert_eq!(summary.senders[1].missing, 0);
+    }
+
+    #[test]
+    fn test_restart_is_not_loss() {
+        let heartbeat = |sequence: u16, incarnation: u64, received_at_us: u64| {
+            let header = FleetMsgHeader::new(MessageType::Heartbeat, 3, sequence, 8);
+            JournalEntry::new(&header, &transport::heartbeat_payload(incarnation), "10.0.0.3:5000".parse().unwrap(), received_at_us)
+        };
+        let entries = vec![
+            heartbeat(500, 1, 10_000_000),
+            entry(3, 501, 10_100_000),
+            heartbeat(0, 2, 12_000_000),
+            entry(3, 1, 12_100_000),
+        ];
+
+        let sender = &analyze(&entries).senders[0];
+        assert_eq!((sender.restarts, sender.missing, sender.reordered), (1, 0, 0));
+    }
+}
@@ -0,0 +1,342 @@
+//! Fleet-wide Control commands rolled out in waves, so a bad firmware flag or
+//! mode switch reaches a few canaries instead of every vehicle at once.
+//!
+//! A [`Rollout`] sends the command to the first `canary` targets, waits until
+//! each has acknowledged it (or the wave times out), optionally waits a little
+//! longer and checks their health, then moves on to the next wave. Too many
+//! failures abort the rollout and tell the fleet so; nodes that haven't applied
+//! the command yet must not. The rollout does no I/O itself: call
+//! [`Rollout::poll`] for what to do next, send what it says with [`send`] and
+//! feed it the acknowledgements heard with [`Rollout::handle`].
+//!
+//! On the vehicles, answer a [`RolloutMessage::Command`] addressed to the node
+//! with [`RolloutMessage::ack`] once the command has been applied or has failed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::transport::MulticastSender;
+
+/// Control commands carrying a rollout message start with this, followed by the message as JSON
+pub const ROLLOUT_COMMAND_PREFIX: &str = "ROLLOUT ";
+
+/// The messages of a rollout, all multicast as Control commands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum RolloutMessage {
+    /// Apply `command`; only the listed `targets` act on it
+    Command { rollout_id: u64, wave: usize, targets: Vec<u32>, command: String },
+    /// A target applied the command, or failed to with `error`
+    Ack { rollout_id: u64, node: u32, error: Option<String> },
+    /// The rollout stopped; targets that haven't applied the command must not
+    Abort { rollout_id: u64, reason: String },
+}
+
+impl RolloutMessage {
+    /// The Control command carrying this message
+    pub fn control_command(&self) -> String {
+        format!("{}{}", ROLLOUT_COMMAND_PREFIX, serde_json::to_string(self).unwrap_or_default())
+    }
+
+    /// Read back a Control command made by [`RolloutMessage::control_command`]
+    pub fn from_control(command: &str) -> Option<Self> {
+        serde_json::from_str(command.strip_prefix(ROLLOUT_COMMAND_PREFIX)?).ok()
+    }
+
+    pub fn rollout_id(&self) -> u64 {
+        match self {
+            RolloutMessage::Command { rollout_id, .. }
+            | RolloutMessage::Ack { rollout_id, .. }
+            | RolloutMessage::Abort { rollout_id, .. } => *rollout_id,
+        }
+    }
+
+    /// Whether `node` is asked to apply this command
+    pub fn targets(&self, node: u32) -> bool {
+        matches!(self, RolloutMessage::Command { targets, .. } if targets.contains(&node))
+    }
+
+    /// `node`'s acknowledgement of this command with the result of applying it;
+    /// None if this isn't a command for `node`
+    pub fn ack(&self, node: u32, result: Result<(), String>) -> Option<RolloutMessage> {
+        self.targets(node).then(|| RolloutMessage::Ack { rollout_id: self.rollout_id(), node, error: result.err() })
+    }
+}
+
+/// How a rollout proceeds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RolloutPlan {
+    /// Targets in the first wave
+    pub canary: usize,
+    /// Targets in each later wave
+    pub wave_size: usize,
+    /// How long a wave's targets have to acknowledge the command
+    pub ack_timeout: Duration,
+    /// How long to wait after a wave is acknowledged before checking its health
+    pub settle: Duration,
+    /// Failed targets tolerated before the rollout aborts
+    pub max_failures: usize,
+}
+
+impl Default for RolloutPlan {
+    /// One canary, then waves of 10, 30s to acknowledge, no settling time and no failures tolerated
+    fn default() -> Self {
+        Self { canary: 1, wave_size: 10, ack_timeout: Duration::from_secs(30), settle: Duration::ZERO, max_failures: 0 }
+    }
+}
+
+/// What the caller should do next
+#[derive(Debug, Clone, PartialEq)]
+pub enum RolloutStep {
+    /// Multicast this message, then poll again
+    Send(RolloutMessage),
+    /// Nothing to do until this time, or until an acknowledgement arrives
+    Wait(Instant),
+    /// The rollout is over
+    Done(RolloutOutcome),
+}
+
+/// How a rollout ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum RolloutOutcome {
+    Completed { applied: Vec<u32>, failed: BTreeMap<u32, String> },
+    /// `skipped` targets were never sent the command
+    Aborted { reason: String, applied: Vec<u32>, failed: BTreeMap<u32, String>, skipped: Vec<u32> },
+}
+
+/// The wave waiting for acknowledgements
+struct Wave {
+    targets: BTreeSet<u32>,
+    answered: BTreeSet<u32>,
+    deadline: Instant,
+    /// When the last target answered
+    answered_at: Option<Instant>,
+}
+
+type HealthCheck = Box<dyn FnMut(u32) -> bool + Send>;
+
+/// One command rolled out to a set of targets in waves
+pub struct Rollout {
+    rollout_id: u64,
+    command: String,
+    plan: RolloutPlan,
+    pending: VecDeque<u32>,
+    waves_sent: usize,
+    wave: Option<Wave>,
+    applied: Vec<u32>,
+    failed: BTreeMap<u32, String>,
+    health: Option<HealthCheck>,
+    abort: Option<RolloutMessage>,
+    outcome: Option<RolloutOutcome>,
+}
+
+impl Rollout {
+    /// `targets` are rolled out to in the order given; duplicates are ignored
+    pub fn new(rollout_id: u64, command: impl Into<String>, targets: impl IntoIterator<Item = u32>, plan: RolloutPlan) -> Self {
+        let mut seen = BTreeSet::new();
+        Self {
+            rollout_id,
+            command: command.into(),
+            plan,
+            pending: targets.into_iter().filter(|target| seen.insert(*target)).collect(),
+            waves_sent: 0,
+            wave: None,
+            applied: Vec::new(),
+            failed: BTreeMap::new(),
+            health: None,
+            abort: None,
+            outcome: None,
+        }
+    }
+
+    /// Check each acknowledged target (e.g. against the peer table or its
+    /// alerts) before the next wave; an unhealthy one counts as failed
+    pub fn with_health_check(mut self, check: impl FnMut(u32) -> bool + Send + 'static) -> Self {
+        self.health = Some(Box::new(check));
+        self
+    }
+
+    pub fn rollout_id(&self) -> u64 {
+        self.rollout_id
+    }
+
+    /// Targets that applied the command and passed the health check so far
+    pub fn applied(&self) -> &[u32] {
+        &self.applied
+    }
+
+    /// Record an acknowledgement from `sender_id`; anything that isn't an
+    /// acknowledgement of this rollout's current wave by its sender is ignored
+    pub fn handle(&mut self, sender_id: u32, message: &RolloutMessage, now: Instant) {
+        let RolloutMessage::Ack { rollout_id, node, error } = message else { return };
+        let Some(wave) = self.wave.as_mut() else { return };
+        if *rollout_id != self.rollout_id || *node != sender_id || !wave.targets.contains(node) || !wave.answered.insert(*node) {
+            return;
+        }
+        if let Some(error) = error {
+            self.failed.insert(*node, error.clone());
+        }
+        if wave.answered.len() == wave.targets.len() {
+            wave.answered_at = Some(now);
+        }
+    }
+
+    /// Stop the rollout: no further waves are sent and the fleet is told
+    pub fn abort(&mut self, reason: impl Into<String>) {
+        if self.outcome.is_some() {
+            return;
+        }
+        let reason = reason.into();
+        let mut skipped: Vec<u32> = self.pending.drain(..).collect();
+        if let Some(wave) = self.wave.take() {
+            // Targets that never answered may or may not have applied the command
+            skipped.extend(wave.targets.iter().filter(|target| !wave.answered.contains(target)));
+        }
+        self.abort = Some(RolloutMessage::Abort { rollout_id: self.rollout_id, reason: reason.clone() });
+        self.outcome = Some(RolloutOutcome::Aborted {
+            reason,
+            applied: self.applied.clone(),
+            failed: self.failed.clone(),
+            skipped,
+        });
+    }
+
+    /// What to do next
+    pub fn poll(&mut self, now: Instant) -> RolloutStep {
+        if let Some(abort) = self.abort.take() {
+            return RolloutStep::Send(abort);
+        }
+        if let Some(outcome) = &self.outcome {
+            return RolloutStep::Done(outcome.clone());
+        }
+        let Some(wave) = &self.wave else { return self.next_wave(now) };
+        match wave.answered_at {
+            Some(answered_at) if now < answered_at + self.plan.settle => RolloutStep::Wait(answered_at + self.plan.settle),
+            None if now < wave.deadline => RolloutStep::Wait(wave.deadline),
+            _ => {
+                self.finish_wave();
+                self.poll(now)
+            }
+        }
+    }
+
+    fn next_wave(&mut self, now: Instant) -> RolloutStep {
+        if self.pending.is_empty() {
+            let outcome = RolloutOutcome::Completed { applied: self.applied.clone(), failed: self.failed.clone() };
+            self.outcome = Some(outcome.clone());
+            return RolloutStep::Done(outcome);
+        }
+        let size = if self.waves_sent == 0 { self.plan.canary } else { self.plan.wave_size }.max(1);
+        let targets: Vec<u32> = self.pending.drain(..size.min(self.pending.len())).collect();
+        self.waves_sent += 1;
+        self.wave = Some(Wave {
+            targets: targets.iter().copied().collect(),
+            answered: BTreeSet::new(),
+            deadline: now + self.plan.ack_timeout,
+            answered_at: None,
+        });
+        RolloutStep::Send(RolloutMessage::Command {
+            rollout_id: self.rollout_id,
+            wave: self.waves_sent,
+            targets,
+            command: self.command.clone(),
+        })
+    }
+
+    /// Judge the current wave: silent and unhealthy targets fail
+    fn finish_wave(&mut self) {
+        let Some(wave) = self.wave.take() else { return };
+        for target in wave.targets {
+            if !wave.answered.contains(&target) {
+                self.failed.insert(target, "no acknowledgement".to_string());
+            } else if !self.failed.contains_key(&target) {
+                if self.health.as_mut().is_none_or(|healthy| healthy(target)) {
+                    self.applied.push(target);
+                } else {
+                    self.failed.insert(target, "unhealthy after applying".to_string());
+                }
+            }
+        }
+        if self.failed.len() > self.plan.max_failures {
+            self.abort(format!("{} of the targets failed", self.failed.len()));
+        }
+    }
+}
+
+/// Multicast a rollout message to the fleet as a Control command
+pub async fn send(sender: &mut MulticastSender, message: &RolloutMessage) -> crate::error::Result<()> {
+    sender.send_control(&message.control_command()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(step: RolloutStep) -> RolloutMessage {
+        match step {
+            RolloutStep::Send(message) => message,
+            other => panic!("expected a message to send, got {:?}", other),
+        }
+    }
+
+    fn ack_all(rollout: &mut Rollout, command: &RolloutMessage, failing: &[u32], now: Instant) {
+        let RolloutMessage::Command { targets, .. } = command else { panic!("not a command") };
+        for node in targets {
+            let result = if failing.contains(node) { Err("disk full".to_string()) } else { Ok(()) };
+            let ack = RolloutMessage::from_control(&command.ack(*node, result).unwrap().control_command()).unwrap();
+            rollout.handle(*node, &ack, now);
+        }
+    }
+
+    #[test]
+    fn test_waves_complete_after_acks_and_health() {
+        let start = Instant::now();
+        let plan = RolloutPlan { canary: 1, wave_size: 2, settle: Duration::from_secs(5), ..Default::default() };
+        let mut rollout = Rollout::new(7, "MODE eco", [10, 11, 12, 11], plan).with_health_check(|_| true);
+
+        let canary = sent(rollout.poll(start));
+        assert!(canary.targets(10) && !canary.targets(11));
+        // Acks from other rollouts or forged for someone else don't count
+        rollout.handle(10, &RolloutMessage::Ack { rollout_id: 8, node: 10, error: None }, start);
+        rollout.handle(11, &RolloutMessage::Ack { rollout_id: 7, node: 10, error: None }, start);
+        assert_eq!(rollout.poll(start), RolloutStep::Wait(start + Duration::from_secs(30)));
+        ack_all(&mut rollout, &canary, &[], start);
+        assert_eq!(rollout.poll(start), RolloutStep::Wait(start + Duration::from_secs(5)));
+
+        let later = start + Duration::from_secs(5);
+        let wave = sent(rollout.poll(later));
+        assert_eq!(wave, RolloutMessage::Command { rollout_id: 7, wave: 2, targets: vec![11, 12], command: "MODE eco".into() });
+        ack_all(&mut rollout, &wave, &[], later);
+        assert_eq!(rollout.poll(later), RolloutStep::Wait(later + Duration::from_secs(5)));
+        let done = rollout.poll(later + Duration::from_secs(5));
+        assert_eq!(done, RolloutStep::Done(RolloutOutcome::Completed { applied: vec![10, 11, 12], failed: BTreeMap::new() }));
+    }
+
+    #[test]
+    fn test_failed_canary_or_timeout_aborts() {
+        let start = Instant::now();
+        let mut rollout = Rollout::new(1, "FIRMWARE 2.3", 1..=5, RolloutPlan::default());
+        let canary = sent(rollout.poll(start));
+        ack_all(&mut rollout, &canary, &[1], start);
+        assert_eq!(sent(rollout.poll(start)), RolloutMessage::Abort { rollout_id: 1, reason: "1 of the targets failed".into() });
+        let RolloutStep::Done(RolloutOutcome::Aborted { applied, failed, skipped, .. }) = rollout.poll(start) else { panic!("not aborted") };
+        assert_eq!((applied, skipped), (vec![], vec![2, 3, 4, 5]));
+        assert_eq!(failed[&1], "disk full");
+
+        // A silent target fails the wave once the timeout passes; unhealthy ones fail too
+        let plan = RolloutPlan { canary: 2, max_failures: 1, ..Default::default() };
+        let mut rollout = Rollout::new(2, "GEOFENCE v9", [1, 2, 3], plan).with_health_check(|node| node != 3);
+        let canary = sent(rollout.poll(start));
+        rollout.handle(1, &canary.ack(1, Ok(())).unwrap(), start);
+        let timed_out = start + Duration::from_secs(30);
+        let wave = sent(rollout.poll(timed_out));
+        assert!(wave.targets(3));
+        ack_all(&mut rollout, &wave, &[], timed_out);
+        assert!(matches!(sent(rollout.poll(timed_out)), RolloutMessage::Abort { .. }));
+        let RolloutStep::Done(RolloutOutcome::Aborted { applied, failed, .. }) = rollout.poll(timed_out) else { panic!("not aborted") };
+        assert_eq!(applied, vec![1]);
+        assert_eq!(failed.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
@@ -0,0 +1,321 @@
+//! Request/response over Control messages: send a command to one node and
+//! wait for its answer.
+//!
+//! Requests and responses are ordinary Control multicasts carrying a
+//! [`Correlation`] extension: the request's id, the node it is addressed to
+//! (or, on a response, the node that asked) and whether it is a request, an
+//! answer or a failure. A [`ControlClient`] repeats a request until the answer
+//! arrives or the request's timeout passes. A [`ControlResponder`] runs the
+//! command once and answers repeats of it with the same response, so a lost
+//! answer doesn't run the command twice.
+//!
+//! Neither receives anything itself. Pass every received [`Delivery`] to
+//! [`ControlClient::handle`], which takes the responses it is waiting for, and
+//! requests to [`ControlResponder::serve`] from a task of their own, since
+//! answering sends.
+
+use async_std::future::timeout;
+use async_std::sync::Mutex;
+use futures::channel::oneshot;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::receiver::Delivery;
+use crate::trace::TraceId;
+use crate::transport::{MessageType, MulticastSender};
+
+/// How often an unanswered request is repeated, unless the client is told otherwise
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Responses remembered for answering repeated requests
+const RECENT_RESPONSES: usize = 1024;
+
+/// What a correlated Control message is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorrelationKind {
+    Request,
+    /// The command ran; the payload is its result
+    Response,
+    /// The command failed; the payload is the reason
+    Failed,
+}
+
+/// The [`CORRELATION`](crate::extensions::CORRELATION) extension tying a
+/// response to its request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Correlation {
+    /// Chosen by the requester, unique among its requests
+    pub id: u64,
+    /// The node asked, on a request; the node that asked, on a response
+    pub to: u32,
+    pub kind: CorrelationKind,
+}
+
+impl Correlation {
+    pub fn encode(&self) -> [u8; 13] {
+        let mut bytes = [0u8; 13];
+        bytes[..8].copy_from_slice(&self.id.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.to.to_le_bytes());
+        bytes[12] = match self.kind {
+            CorrelationKind::Request => 0,
+            CorrelationKind::Response => 1,
+            CorrelationKind::Failed => 2,
+        };
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; 13] = bytes.try_into().ok()?;
+        let kind = match bytes[12] {
+            0 => CorrelationKind::Request,
+            1 => CorrelationKind::Response,
+            2 => CorrelationKind::Failed,
+            _ => return None,
+        };
+        Some(Self {
+            id: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            to: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            kind,
+        })
+    }
+
+    /// The correlation of a Control delivery, if it has one
+    fn of(delivery: &Delivery) -> Option<Self> {
+        (delivery.header.message_type() == MessageType::Control).then(|| delivery.extensions.correlation()).flatten()
+    }
+}
+
+/// A node's answer to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub from: u32,
+    /// The command's result, or why it failed
+    pub result: Result<Vec<u8>, String>,
+}
+
+type Pending = HashMap<u64, (u32, oneshot::Sender<Response>)>;
+
+/// Sends commands to single nodes and waits for their answers.
+///
+/// Clones share their pending requests, so one can sit in the receive handler
+/// while others make requests.
+#[derive(Clone)]
+pub struct ControlClient {
+    sender: Arc<Mutex<MulticastSender>>,
+    node: u32,
+    next_id: Arc<AtomicU64>,
+    pending: Arc<std::sync::Mutex<Pending>>,
+    retry_interval: Duration,
+}
+
+impl ControlClient {
+    /// A client sending through `sender`. Request ids start from the clock,
+    /// so a restarted client doesn't get answers meant for its predecessor.
+    pub async fn new(sender: Arc<Mutex<MulticastSender>>) -> Self {
+        let node = sender.lock().await.sender_id();
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        Self {
+            sender,
+            node,
+            next_id: Arc::new(AtomicU64::new(now.as_micros() as u64)),
+            pending: Arc::default(),
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+        }
+    }
+
+    /// Repeat unanswered requests this often
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Send `command` to `target` and wait for its answer, repeating the
+    /// request until it comes. Fails with `TimedOut` if none comes within `limit`.
+    pub async fn request(&self, target: u32, command: &str, limit: Duration) -> std::io::Result<Response> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (answer, mut answered) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, (target, answer));
+        let _forget = Forget { pending: &self.pending, id };
+
+        let correlation = Correlation { id, to: target, kind: CorrelationKind::Request };
+        let trace = TraceId::random();
+        let deadline = Instant::now() + limit;
+        loop {
+            self.sender.lock().await.send_correlated(correlation, trace, command.as_bytes()).await?;
+            let wait = self.retry_interval.min(deadline.saturating_duration_since(Instant::now()));
+            match timeout(wait, &mut answered).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(_)) => return Err(Error::new(ErrorKind::BrokenPipe, "request abandoned")),
+                Err(_) if Instant::now() >= deadline => {
+                    return Err(Error::new(ErrorKind::TimedOut, format!("no answer from node {} within {:?}", target, limit)));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Take `delivery` if it answers one of this client's pending requests;
+    /// returns whether it did
+    pub fn handle(&self, delivery: &Delivery) -> bool {
+        let Some(correlation) = Correlation::of(delivery).filter(|c| c.kind != CorrelationKind::Request && c.to == self.node) else {
+            return false;
+        };
+        let from = delivery.header.sender_id.get();
+        let mut pending = self.pending.lock().unwrap();
+        // Only the node asked can answer
+        if pending.get(&correlation.id).is_none_or(|(target, _)| *target != from) {
+            return false;
+        }
+        let (_, answer) = pending.remove(&correlation.id).unwrap();
+        let result = match correlation.kind {
+            CorrelationKind::Failed => Err(String::from_utf8_lossy(&delivery.payload).into_owned()),
+            _ => Ok(delivery.payload.clone()),
+        };
+        let _ = answer.send(Response { from, result });
+        true
+    }
+
+    /// Requests still waiting for an answer
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+/// Drops a request's pending entry however `request` ends, including by being cancelled
+struct Forget<'a> {
+    pending: &'a std::sync::Mutex<Pending>,
+    id: u64,
+}
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Answers the requests addressed to this node
+pub struct ControlResponder {
+    sender: Arc<Mutex<MulticastSender>>,
+    node: u32,
+    /// By requester and request id
+    answered: BTreeMap<(u32, u64), (CorrelationKind, Vec<u8>)>,
+    answered_order: VecDeque<(u32, u64)>,
+}
+
+impl ControlResponder {
+    pub async fn new(sender: Arc<Mutex<MulticastSender>>) -> Self {
+        let node = sender.lock().await.sender_id();
+        Self { sender, node, answered: BTreeMap::new(), answered_order: VecDeque::new() }
+    }
+
+    /// Answer `delivery` if it is a request to this node: the first copy goes
+    /// to `handler` with the requester's id and the command, repeats get the
+    /// same answer without running it again. Returns whether it was such a request.
+    pub async fn serve(
+        &mut self,
+        delivery: &Delivery,
+        handler: impl FnOnce(u32, &str) -> Result<Vec<u8>, String>
+    ) -> std::io::Result<bool> {
+        let Some(request) = Correlation::of(delivery).filter(|c| c.kind == CorrelationKind::Request && c.to == self.node) else {
+            return Ok(false);
+        };
+        let from = delivery.header.sender_id.get();
+        let key = (from, request.id);
+        if !self.answered.contains_key(&key) {
+            let answer = match std::str::from_utf8(&delivery.payload) {
+                Ok(command) => handler(from, command),
+                Err(_) => Err("command is not UTF-8".to_string()),
+            };
+            let answer = match answer {
+                Ok(result) => (CorrelationKind::Response, result),
+                Err(reason) => (CorrelationKind::Failed, reason.into_bytes()),
+            };
+            if self.answered_order.len() == RECENT_RESPONSES
+                && let Some(oldest) = self.answered_order.pop_front()
+            {
+                self.answered.remove(&oldest);
+            }
+            self.answered_order.push_back(key);
+            self.answered.insert(key, answer);
+        }
+
+        let (kind, payload) = &self.answered[&key];
+        let response = Correlation { id: request.id, to: from, kind: *kind };
+        let trace = delivery.extensions.trace_id().unwrap_or_else(TraceId::random);
+        self.sender.lock().await.send_correlated(response, trace, payload).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::ReceiverConfig;
+    use crate::testing;
+    use crate::transport;
+    use async_std::task;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_correlation_round_trip() {
+        let correlation = Correlation { id: u64::MAX - 1, to: 0x42, kind: CorrelationKind::Failed };
+        assert_eq!(Correlation::decode(&correlation.encode()), Some(correlation));
+        let mut unknown = correlation.encode();
+        unknown[12] = 9;
+        assert_eq!(Correlation::decode(&unknown), None);
+        assert_eq!(Correlation::decode(&unknown[..12]), None);
+    }
+
+    #[async_std::test]
+    async fn test_request_retries_until_answered_once() {
+        let (channel, socket) = testing::bind_free_channel().await.unwrap();
+        let node = |sender_id| async move {
+            Arc::new(Mutex::new(MulticastSender::new(channel.group, channel.port, sender_id).await.unwrap()))
+        };
+        let client = ControlClient::new(node(1).await).await.with_retry_interval(Duration::from_millis(100));
+        let mut responder = ControlResponder::new(node(2).await).await;
+
+        let (requests, incoming) = async_std::channel::unbounded();
+        let routing = client.clone();
+        task::spawn(transport::receive_loop(socket, ReceiverConfig::new(), move |delivery: Delivery| {
+            if !routing.handle(&delivery) {
+                let _ = requests.try_send(delivery);
+            }
+        }));
+
+        // The first copy of each request is lost; the command still runs only once
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = runs.clone();
+        task::spawn(async move {
+            let mut seen = 0;
+            while let Ok(delivery) = incoming.recv().await {
+                if Correlation::of(&delivery).is_some_and(|c| c.kind == CorrelationKind::Request) {
+                    seen += 1;
+                    if seen == 1 {
+                        continue;
+                    }
+                }
+                responder.serve(&delivery, |from, command| {
+                    counted.fetch_add(1, Ordering::Relaxed);
+                    match command {
+                        "status" => Ok(format!("ok for {}", from).into_bytes()),
+                        other => Err(format!("unknown command {}", other)),
+                    }
+                }).await.unwrap();
+            }
+        });
+
+        let response = client.request(2, "status", Duration::from_secs(3)).await.unwrap();
+        assert_eq!(response, Response { from: 2, result: Ok(b"ok for 1".to_vec()) });
+        let failed = client.request(2, "reboot", Duration::from_secs(3)).await.unwrap();
+        assert_eq!(failed.result, Err("unknown command reboot".to_string()));
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+
+        let silent = client.request(99, "status", Duration::from_millis(300)).await.unwrap_err();
+        assert_eq!(silent.kind(), ErrorKind::TimedOut);
+        assert_eq!(client.pending(), 0);
+    }
+}
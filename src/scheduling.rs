@@ -0,0 +1,241 @@
+//! Pinning the receive loop to cores and raising its scheduling priority, to
+//! keep receive jitter within budget on computers that share their cores with
+//! heavy workloads (e.g. perception on a vehicle).
+//!
+//! A receiver whose [`ReceiverConfig`](crate::receiver::ReceiverConfig) has a
+//! [`ThreadScheduling`] runs its loop on an OS thread of its own, rather than
+//! on the async runtime's shared threads, and applies the scheduling to that
+//! thread before receiving. On Linux, `cpus` sets its affinity
+//! (`sched_setaffinity`), `realtime_priority` moves it to `SCHED_FIFO` at that
+//! priority, and `nice` sets its niceness otherwise. Real-time priority and
+//! negative niceness need `CAP_SYS_NICE` (or an `RLIMIT_RTPRIO` allowance);
+//! without it the receiver warns and keeps receiving at normal priority.
+//! Other platforms only run the loop on its own thread.
+//!
+//! For still lower and steadier latency, [`BusyPoll`] has that thread spin on
+//! the socket instead of sleeping until a datagram arrives, and asks the
+//! kernel to poll the network device for it too (`SO_BUSY_POLL`). That takes
+//! a whole core while it spins: pin it to one the rest of the system doesn't
+//! need.
+
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+/// Highest `SCHED_FIFO` priority Linux allows
+pub const MAX_REALTIME_PRIORITY: u8 = 99;
+
+/// Affinity and priority for a receive thread; see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreadScheduling {
+    /// Cores the thread may run on, by index; empty for any
+    pub cpus: Vec<usize>,
+    /// `SCHED_FIFO` priority, 1 to 99; above every normal thread whatever its niceness
+    pub realtime_priority: Option<u8>,
+    /// Niceness, -20 (most favoured) to 19, when not real-time
+    pub nice: Option<i8>,
+}
+
+impl ThreadScheduling {
+    /// Run on `cpus` only, at normal priority
+    pub fn pinned(cpus: impl IntoIterator<Item = usize>) -> Self {
+        Self { cpus: cpus.into_iter().collect(), ..Self::default() }
+    }
+
+    pub fn with_realtime_priority(mut self, priority: u8) -> Self {
+        self.realtime_priority = Some(priority);
+        self
+    }
+
+    pub fn with_nice(mut self, nice: i8) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Whether there is anything to apply
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check the settings without applying them, e.g. when loading a configuration
+    pub fn validate(&self) -> std::io::Result<()> {
+        if let Some(priority) = self.realtime_priority
+            && !(1..=MAX_REALTIME_PRIORITY).contains(&priority)
+        {
+            let reason = format!("realtime_priority {} is outside 1-{}", priority, MAX_REALTIME_PRIORITY);
+            return Err(Error::new(ErrorKind::InvalidInput, reason));
+        }
+        if let Some(nice) = self.nice
+            && !(-20..=19).contains(&nice)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("nice {} is outside -20-19", nice)));
+        }
+        Ok(())
+    }
+
+    /// Apply the settings to the calling thread
+    pub fn apply_to_current_thread(&self) -> std::io::Result<()> {
+        self.validate()?;
+        if !self.cpus.is_empty() {
+            set_affinity(&self.cpus)?;
+        }
+        match (self.realtime_priority, self.nice) {
+            (Some(priority), _) => set_realtime_priority(priority),
+            (None, Some(nice)) => set_nice(nice),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Receiving by spinning on the socket, for the few consumers (e.g. control
+/// loops) that need every datagram within tens of microseconds.
+///
+/// The receive thread never sleeps in the kernel waiting for a datagram: it
+/// polls the socket in a loop, so one arriving is picked up at once instead
+/// of after a wake-up, which costs tens to hundreds of microseconds and
+/// varies with load. The price is a core at 100% for as long as it spins,
+/// which is until `spin` has passed without a datagram. It then backs off,
+/// sleeping between polls for up to `max_backoff`, and the first datagram
+/// after a quiet spell can wait that long. Set `spin` longer than the gap
+/// between the consumer's messages to stay in the fast path.
+///
+/// On Linux the kernel busy polls the device queue for `kernel` on each
+/// receive as well (`SO_BUSY_POLL`); raising it above `net.core.busy_read`
+/// needs `CAP_NET_ADMIN`, and without it the receiver warns and spins alone.
+/// A receiver that busy polls ignores its [`PowerPolicy`](crate::power::PowerPolicy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyPoll {
+    pub kernel: Duration,
+    pub spin: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for BusyPoll {
+    /// 50µs of kernel polling; spin for a second after each datagram, then
+    /// back off to a millisecond between polls
+    fn default() -> Self {
+        Self { kernel: Duration::from_micros(50), spin: Duration::from_secs(1), max_backoff: Duration::from_millis(1) }
+    }
+}
+
+impl BusyPoll {
+    pub fn with_kernel(mut self, kernel: Duration) -> Self {
+        self.kernel = kernel;
+        self
+    }
+
+    pub fn with_spin(mut self, spin: Duration) -> Self {
+        self.spin = spin;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Set `SO_BUSY_POLL` on `socket`; nothing to do for a zero `kernel`
+    pub fn apply_to_socket(&self, socket: &std::net::UdpSocket) -> std::io::Result<()> {
+        if self.kernel.is_zero() {
+            return Ok(());
+        }
+        set_busy_poll(socket, self.kernel.as_micros().min(i32::MAX as u128) as i32)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_busy_poll(socket: &std::net::UdpSocket, micros: i32) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let value = micros as libc::c_int;
+    let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the socket is open and `value` outlives the call
+    let status = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_BUSY_POLL, (&value as *const libc::c_int).cast(), len)
+    };
+    if status != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_busy_poll(_socket: &std::net::UdpSocket, _micros: i32) -> std::io::Result<()> {
+    Err(Error::new(ErrorKind::Unsupported, "kernel busy polling is only available on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> std::io::Result<()> {
+    // SAFETY: a zeroed cpu_set_t is an empty set
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("no CPU {}", cpu)));
+        }
+        // SAFETY: `cpu` is within the set, checked above
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // SAFETY: `set` is initialized and outlives the call; 0 is the calling thread
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_realtime_priority(priority: u8) -> std::io::Result<()> {
+    let param = libc::sched_param { sched_priority: priority as libc::c_int };
+    // SAFETY: `param` outlives the call; 0 is the calling thread
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_nice(nice: i8) -> std::io::Result<()> {
+    // SAFETY: gettid can't fail; on Linux niceness is per thread, by its id
+    let tid = unsafe { libc::gettid() };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice as libc::c_int) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> std::io::Result<()> {
+    Err(Error::new(ErrorKind::Unsupported, "CPU affinity is only set on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_realtime_priority(_priority: u8) -> std::io::Result<()> {
+    Err(Error::new(ErrorKind::Unsupported, "real-time priority is only set on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_nice(_nice: i8) -> std::io::Result<()> {
+    Err(Error::new(ErrorKind::Unsupported, "thread niceness is only set on Linux"))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_thread_is_pinned_and_reniced() {
+        let scheduling = ThreadScheduling::pinned([0]).with_nice(5);
+        let applied = std::thread::spawn(move || {
+            scheduling.apply_to_current_thread().unwrap();
+            // SAFETY: a zeroed cpu_set_t is valid to be written into
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+            let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t) };
+            (unsafe { libc::CPU_COUNT(&set) }, unsafe { libc::CPU_ISSET(0, &set) }, nice)
+        });
+        assert_eq!(applied.join().unwrap(), (1, true, 5));
+
+        assert!(ThreadScheduling::default().with_realtime_priority(0).validate().is_err());
+        assert!(ThreadScheduling::default().with_nice(20).validate().is_err());
+        assert!(ThreadScheduling::pinned([usize::MAX]).apply_to_current_thread().is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Schema versions of payloads, for rolling upgrades: while part of the fleet
+//! still sends the old encoding of a topic and part the new one, each
+//! consumer migrates what it receives to the version it understands.
+//!
+//! Senders tag a payload with the version it is encoded in using
+//! [`MulticastSender::send_versioned`](crate::transport::MulticastSender::send_versioned),
+//! which carries it in the [`SCHEMA_VERSION`](crate::extensions::SCHEMA_VERSION)
+//! extension. A [`SchemaRegistry`] holds, per topic (as named by the
+//! [`usage`](crate::usage) classifier), the migrations between adjacent
+//! versions: upgrades from `n` to `n + 1` for new consumers of old payloads,
+//! and downgrades from `n` to `n - 1` for old consumers of new ones. Migrating
+//! across several versions runs each step in turn.
+//!
+//! Payloads are migrated as bytes; the migrations decode and re-encode them
+//! in whatever format the topic uses. Untagged payloads, e.g. from senders
+//! older than the tagging, count as the registry's untagged version.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use crate::receiver::Delivery;
+use crate::transport::MessageType;
+use crate::usage::{TopicClassifier, default_topic};
+
+/// One step between adjacent versions of a topic's payload
+pub type Migration = Arc<dyn Fn(&[u8]) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// Migrations between the schema versions of each topic; see the [module docs](self)
+#[derive(Clone, Default)]
+pub struct SchemaRegistry {
+    classifier: Option<TopicClassifier>,
+    untagged: u16,
+    /// Keyed by topic and the version migrated from
+    upgrades: HashMap<(String, u16), Migration>,
+    downgrades: HashMap<(String, u16), Migration>,
+}
+
+impl fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut upgrades: Vec<&(String, u16)> = self.upgrades.keys().collect();
+        let mut downgrades: Vec<&(String, u16)> = self.downgrades.keys().collect();
+        upgrades.sort();
+        downgrades.sort();
+        f.debug_struct("SchemaRegistry")
+            .field("untagged", &self.untagged)
+            .field("upgrades", &upgrades)
+            .field("downgrades", &downgrades)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Migrate payloads of `topic` from version `from` to `from + 1`
+    pub fn with_upgrade(
+        mut self,
+        topic: &str,
+        from: u16,
+        migration: impl Fn(&[u8]) -> io::Result<Vec<u8>> + Send + Sync + 'static
+    ) -> Self {
+        self.upgrades.insert((topic.to_string(), from), Arc::new(migration));
+        self
+    }
+
+    /// Migrate payloads of `topic` from version `from` to `from - 1`
+    pub fn with_downgrade(
+        mut self,
+        topic: &str,
+        from: u16,
+        migration: impl Fn(&[u8]) -> io::Result<Vec<u8>> + Send + Sync + 'static
+    ) -> Self {
+        self.downgrades.insert((topic.to_string(), from), Arc::new(migration));
+        self
+    }
+
+    /// The version of payloads that carry none (default 0)
+    pub fn with_untagged_version(mut self, version: u16) -> Self {
+        self.untagged = version;
+        self
+    }
+
+    /// Name topics with `classifier` instead of [`default_topic`]
+    pub fn with_classifier(mut self, classifier: impl Fn(MessageType, &[u8]) -> String + Send + Sync + 'static) -> Self {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// `payload` of `topic` migrated from version `from` to `to`. Fails with
+    /// [`ErrorKind::Unsupported`] if a step on the way is missing, or with the
+    /// error of a migration that fails.
+    pub fn migrate<'a>(&self, topic: &str, from: u16, to: u16, payload: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        let mut payload = Cow::Borrowed(payload);
+        let mut version = from;
+        while version != to {
+            let (migrations, next) = match version < to {
+                true => (&self.upgrades, version + 1),
+                false => (&self.downgrades, version - 1),
+            };
+            let migration = migrations.get(&(topic.to_string(), version)).ok_or_else(|| {
+                let message = format!("no migration of {} from schema version {} to {}", topic, version, next);
+                Error::new(ErrorKind::Unsupported, message)
+            })?;
+            payload = Cow::Owned(migration(&payload)?);
+            version = next;
+        }
+        Ok(payload)
+    }
+
+    /// Migrate a received message's payload to version `to`, retagging it.
+    /// On failure the delivery is left as it was.
+    pub fn migrate_delivery(&self, delivery: &mut Delivery, to: u16) -> io::Result<()> {
+        let from = delivery.extensions.schema_version().unwrap_or(self.untagged);
+        let msg_type = delivery.header.message_type();
+        let topic = match &self.classifier {
+            Some(classifier) => classifier(msg_type, &delivery.payload),
+            None => default_topic(msg_type, &delivery.payload),
+        };
+        if let Cow::Owned(payload) = self.migrate(&topic, from, to, &delivery.payload)? {
+            delivery.payload = payload;
+        }
+        delivery.extensions.set_schema_version(to);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receiver::ReceiverConfig;
+    use crate::transport::{MulticastReceiver, MulticastSender};
+    use std::time::Duration;
+
+    /// Version 1 sends speed in m/s as "12.5", version 2 as JSON in km/h
+    fn speeds() -> SchemaRegistry {
+        let parse = |payload: &[u8]| -> io::Result<f64> {
+            std::str::from_utf8(payload).ok().and_then(|text| text.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "not a number"))
+        };
+        SchemaRegistry::new()
+            .with_untagged_version(1)
+            .with_upgrade("Data", 1, move |payload| Ok(format!(r#"{{"kmh":{}}}"#, parse(payload)? * 3.6).into_bytes()))
+            .with_downgrade("Data", 2, |payload| {
+                let value: serde_json::Value = serde_json::from_slice(payload)?;
+                let kmh = value["kmh"].as_f64().ok_or_else(|| Error::new(ErrorKind::InvalidData, "no kmh field"))?;
+                Ok((kmh / 3.6).to_string().into_bytes())
+            })
+    }
+
+    #[test]
+    fn test_payloads_migrate_both_ways_step_by_step() {
+        let registry = speeds();
+        assert_eq!(registry.migrate("Data", 1, 2, b"10").unwrap().as_ref(), br#"{"kmh":36}"#);
+        assert_eq!(registry.migrate("Data", 2, 1, br#"{"kmh":36}"#).unwrap().as_ref(), b"10");
+        assert!(matches!(registry.migrate("Data", 2, 2, b"as is").unwrap(), Cow::Borrowed(b"as is")));
+
+        let error = registry.migrate("Data", 1, 3, b"10").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert_eq!(registry.migrate("Data", 1, 2, b"fast").unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(registry.migrate("Control ROLLOUT", 1, 2, b"ROLLOUT go").is_err());
+    }
+
+    #[async_std::test]
+    async fn test_received_versions_are_migrated() {
+        let (channel, socket) = crate::testing::bind_free_channel().await.unwrap();
+        let receiver = MulticastReceiver::from_socket(socket, ReceiverConfig::new(), 16);
+        let mut sender = MulticastSender::new(channel.group, channel.port, 4).await.unwrap();
+
+        // An old sender that doesn't tag, and a new one on version 2
+        sender.send_data(b"10").await.unwrap();
+        sender.send_versioned(MessageType::Data, 2, br#"{"kmh":72}"#).await.unwrap();
+
+        let registry = speeds();
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let mut delivery = async_std::future::timeout(Duration::from_secs(2), receiver.recv()).await.unwrap().unwrap();
+            registry.migrate_delivery(&mut delivery, 2).unwrap();
+            assert_eq!(delivery.extensions.schema_version(), Some(2));
+            received.push(delivery.payload);
+        }
+        assert_eq!(received, [br#"{"kmh":36}"#.to_vec(), br#"{"kmh":72}"#.to_vec()]);
+    }
+}
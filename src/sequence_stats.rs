@@ -0,0 +1,361 @@
+//! Per-sender loss, duplication and reordering, from the sequence numbers of
+//! received messages.
+//!
+//! [`SequenceAnalyzer`] only counts. A [`SequenceTracker`] on a receiver's
+//! [`ReceiverConfig`](crate::receiver::ReceiverConfig) also drops duplicates
+//! before the handler sees them, and reports gaps, late arrivals, duplicates
+//! and restarts as [`SequenceEvent`]s to its [`SequenceEvents`] subscriber.
+
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::transport::{self, FleetMsgHeader, MessageType};
+
+/// How far behind the newest sequence number a message can arrive and still be
+/// told apart as a duplicate or a late arrival; anything older means the sender restarted
+pub const REORDER_WINDOW: u16 = 64;
+
+/// Loss, duplication and reordering of one sender's stream, as seen by this receiver
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SenderSequenceStats {
+    pub sender_id: u32,
+    pub received: u64,
+    /// Sequence numbers skipped and not (yet) filled in by a late arrival
+    pub missing: u64,
+    pub duplicates: u64,
+    /// Arrived after a later sequence number
+    pub reordered: u64,
+    /// Furthest behind the newest sequence number a message has arrived
+    pub max_reorder_depth: u16,
+    /// Sequence tracking started over: a new incarnation, or a jump back past the window
+    pub restarts: u64,
+    pub loss_percent: f64,
+    pub duplication_percent: f64,
+}
+
+impl SenderSequenceStats {
+    fn update_percentages(&mut self) {
+        let unique = self.received - self.duplicates;
+        let expected = unique + self.missing;
+        self.loss_percent = if expected > 0 { self.missing as f64 * 100.0 / expected as f64 } else { 0.0 };
+        self.duplication_percent = if self.received > 0 { self.duplicates as f64 * 100.0 / self.received as f64 } else { 0.0 };
+    }
+}
+
+/// What one message's sequence number says about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SequenceOutcome {
+    /// The next one expected, or the first heard from the sender
+    InOrder,
+    /// Ahead of the next one expected, which and `missing - 1` after it were skipped
+    Gap { expected: u16, missing: u16 },
+    /// Filled in part of an earlier gap, `depth` behind the newest
+    Late { depth: u16 },
+    Duplicate,
+    /// Tracking started over: a new incarnation, or a jump back past the window
+    Restart,
+}
+
+/// Where duplicate detection stands for one sender, for handing over to another receiver
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceWindow {
+    pub sender_id: u32,
+    pub incarnation: Option<u64>,
+    pub newest: u16,
+    /// Bit `n` set: `newest - n` has arrived
+    pub seen: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Stream {
+    newest: u16,
+    /// Bit `n` set: `newest - n` has arrived
+    seen: u64,
+    incarnation: Option<u64>,
+    stats: SenderSequenceStats,
+}
+
+/// Continuously derives per-sender loss, duplication and reorder depth from the
+/// sequence numbers of received messages, the live counterpart of
+/// [`replay::analyze`](crate::reliability::replay::analyze).
+#[derive(Debug, Clone, Default)]
+pub struct SequenceAnalyzer {
+    streams: BTreeMap<u32, Stream>,
+}
+
+impl SequenceAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for one received message; heartbeats announcing a new incarnation start tracking over
+    pub fn observe(&mut self, header: &FleetMsgHeader, payload: &[u8]) -> SequenceOutcome {
+        let sequence = header.sequence.get();
+        let incarnation = (header.message_type() == MessageType::Heartbeat)
+            .then(|| transport::heartbeat_incarnation(payload))
+            .flatten();
+        let stream = self.streams.entry(header.sender_id.get()).or_insert_with(|| Stream {
+            newest: sequence,
+            seen: 0,
+            incarnation,
+            stats: SenderSequenceStats { sender_id: header.sender_id.get(), ..Default::default() },
+        });
+        stream.stats.received += 1;
+
+        let restarted = incarnation.is_some() && stream.incarnation.is_some_and(|known| Some(known) != incarnation);
+        if incarnation.is_some() {
+            stream.incarnation = incarnation;
+        }
+        // Sequence numbers wrap, so anything within half the space ahead counts as forward
+        let behind = stream.newest.wrapping_sub(sequence);
+        let outcome = if stream.seen == 0 || restarted || (REORDER_WINDOW..0x8000).contains(&behind) {
+            let first = stream.seen == 0;
+            if !first {
+                stream.stats.restarts += 1;
+            }
+            stream.newest = sequence;
+            stream.seen = 1;
+            if first { SequenceOutcome::InOrder } else { SequenceOutcome::Restart }
+        } else if behind >= 0x8000 {
+            // Everything skipped over counts as missing until it turns up late
+            let step = sequence.wrapping_sub(stream.newest);
+            stream.stats.missing += (step - 1) as u64;
+            stream.seen = if step >= REORDER_WINDOW { 1 } else { (stream.seen << step) | 1 };
+            let expected = stream.newest.wrapping_add(1);
+            stream.newest = sequence;
+            if step == 1 { SequenceOutcome::InOrder } else { SequenceOutcome::Gap { expected, missing: step - 1 } }
+        } else if stream.seen & (1u64 << behind) != 0 {
+            stream.stats.duplicates += 1;
+            SequenceOutcome::Duplicate
+        } else {
+            stream.seen |= 1u64 << behind;
+            stream.stats.reordered += 1;
+            stream.stats.missing = stream.stats.missing.saturating_sub(1);
+            stream.stats.max_reorder_depth = stream.stats.max_reorder_depth.max(behind);
+            SequenceOutcome::Late { depth: behind }
+        };
+        stream.stats.update_percentages();
+        outcome
+    }
+
+    pub fn get(&self, sender_id: u32) -> Option<&SenderSequenceStats> {
+        self.streams.get(&sender_id).map(|stream| &stream.stats)
+    }
+
+    /// Stop tracking a sender that has left
+    pub fn remove(&mut self, sender_id: u32) -> Option<SenderSequenceStats> {
+        self.streams.remove(&sender_id).map(|stream| stream.stats)
+    }
+
+    /// Every tracked sender, by id
+    pub fn senders(&self) -> impl Iterator<Item = &SenderSequenceStats> {
+        self.streams.values().map(|stream| &stream.stats)
+    }
+
+    /// The duplicate-detection window of every tracked sender
+    pub fn windows(&self) -> Vec<SequenceWindow> {
+        self.streams
+            .iter()
+            .map(|(sender_id, stream)| SequenceWindow {
+                sender_id: *sender_id,
+                incarnation: stream.incarnation,
+                newest: stream.newest,
+                seen: stream.seen,
+            })
+            .collect()
+    }
+
+    /// Take in a window from another receiver, so messages it already
+    /// delivered count as duplicates here. A window for a sender tracked under
+    /// another incarnation is ignored; one for the same incarnation is merged.
+    pub fn restore(&mut self, window: &SequenceWindow) {
+        let Some(stream) = self.streams.get_mut(&window.sender_id) else {
+            self.streams.insert(window.sender_id, Stream {
+                newest: window.newest,
+                seen: window.seen,
+                incarnation: window.incarnation,
+                stats: SenderSequenceStats { sender_id: window.sender_id, ..Default::default() },
+            });
+            return;
+        };
+        if window.incarnation.is_some() && stream.incarnation.is_some() && stream.incarnation != window.incarnation {
+            return;
+        }
+        let ahead = window.newest.wrapping_sub(stream.newest);
+        if ahead < 0x8000 {
+            stream.seen = stream.seen.checked_shl(ahead as u32).unwrap_or(0) | window.seen;
+            stream.newest = window.newest;
+        } else {
+            stream.seen |= window.seen.checked_shl(stream.newest.wrapping_sub(window.newest) as u32).unwrap_or(0);
+        }
+        stream.incarnation = stream.incarnation.or(window.incarnation);
+    }
+}
+
+/// Anything but an in-order message from one sender, as a receiver saw it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceEvent {
+    pub sender_id: u32,
+    pub sequence: u16,
+    pub addr: SocketAddr,
+    pub outcome: SequenceOutcome,
+}
+
+/// Per-sender sequence state for receivers: drops duplicates before the
+/// handler sees them and reports gaps, late arrivals, duplicates and restarts
+/// to a [`SequenceEvents`] subscriber.
+///
+/// Clones share their state, so receivers on several groups that hear the
+/// same senders can share one tracker. When the subscriber falls behind,
+/// events are dropped (and counted) rather than slowing the receiver down.
+#[derive(Debug, Clone)]
+pub struct SequenceTracker {
+    analyzer: Arc<Mutex<SequenceAnalyzer>>,
+    events: Sender<SequenceEvent>,
+    dropped: Arc<AtomicU64>,
+    deliver_duplicates: bool,
+}
+
+impl SequenceTracker {
+    /// A tracker buffering up to `capacity` events for its subscriber
+    pub fn new(capacity: usize) -> (Self, SequenceEvents) {
+        let (events, receiver) = channel::bounded(capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let tracker = Self { analyzer: Arc::default(), events, dropped: dropped.clone(), deliver_duplicates: false };
+        (tracker, SequenceEvents { events: receiver, dropped })
+    }
+
+    /// Hand duplicates to the handler too, still reporting them
+    pub fn with_duplicates_delivered(mut self, deliver: bool) -> Self {
+        self.deliver_duplicates = deliver;
+        self
+    }
+
+    pub fn stats(&self, sender_id: u32) -> Option<SenderSequenceStats> {
+        self.analyzer.lock().unwrap().get(sender_id).cloned()
+    }
+
+    /// Every tracked sender, by id
+    pub fn senders(&self) -> Vec<SenderSequenceStats> {
+        self.analyzer.lock().unwrap().senders().cloned().collect()
+    }
+
+    /// Stop tracking a sender that has left
+    pub fn forget(&self, sender_id: u32) {
+        self.analyzer.lock().unwrap().remove(sender_id);
+    }
+
+    /// Track one received message and report it if it is out of order;
+    /// whether it should be delivered
+    pub(crate) fn admit(&self, header: &FleetMsgHeader, payload: &[u8], addr: SocketAddr) -> bool {
+        let outcome = self.analyzer.lock().unwrap().observe(header, payload);
+        if outcome == SequenceOutcome::InOrder {
+            return true;
+        }
+        let event = SequenceEvent { sender_id: header.sender_id.get(), sequence: header.sequence.get(), addr, outcome };
+        if let Err(TrySendError::Full(_)) = self.events.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        outcome != SequenceOutcome::Duplicate || self.deliver_duplicates
+    }
+}
+
+/// The receiving end of a [`SequenceTracker`]
+#[derive(Debug)]
+pub struct SequenceEvents {
+    events: Receiver<SequenceEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl SequenceEvents {
+    /// The next event; `None` once every receiver using the tracker has stopped
+    pub async fn next(&self) -> Option<SequenceEvent> {
+        self.events.recv().await.ok()
+    }
+
+    /// The next event if one is waiting
+    pub fn try_next(&self) -> Option<SequenceEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Events lost because the subscriber fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(analyzer: &mut SequenceAnalyzer, sender_id: u32, sequences: &[u16]) {
+        for &sequence in sequences {
+            analyzer.observe(&FleetMsgHeader::new(MessageType::Data, sender_id, sequence, 0), b"");
+        }
+    }
+
+    #[test]
+    fn test_loss_duplicates_and_reordering() {
+        let mut analyzer = SequenceAnalyzer::new();
+        // 3 arrives late, 5 twice, 7 never, and the stream wraps
+        data(&mut analyzer, 1, &[65534, 65535, 0, 1, 2, 4, 3, 5, 5, 6, 8]);
+
+        let stats = analyzer.get(1).unwrap();
+        assert_eq!(stats.received, 11);
+        assert_eq!(stats.missing, 1);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(stats.reordered, 1);
+        assert_eq!(stats.max_reorder_depth, 1);
+        assert_eq!(stats.restarts, 0);
+        assert!((stats.loss_percent - 100.0 / 11.0).abs() < 1e-9);
+        assert!((stats.duplication_percent - 100.0 / 11.0).abs() < 1e-9);
+
+        assert!(analyzer.remove(1).is_some());
+        assert_eq!(analyzer.senders().count(), 0);
+    }
+
+    #[test]
+    fn test_restart_starts_tracking_over() {
+        let mut analyzer = SequenceAnalyzer::new();
+        let heartbeat = |sequence| FleetMsgHeader::new(MessageType::Heartbeat, 2, sequence, 8);
+        analyzer.observe(&heartbeat(500), &transport::heartbeat_payload(1));
+        data(&mut analyzer, 2, &[501, 502]);
+        // A new incarnation from sequence 0 is neither loss nor reordering
+        analyzer.observe(&heartbeat(0), &transport::heartbeat_payload(2));
+        data(&mut analyzer, 2, &[1, 2]);
+        // Neither is jumping back further than the window
+        data(&mut analyzer, 2, &[2u16.wrapping_sub(REORDER_WINDOW), 3u16.wrapping_sub(REORDER_WINDOW)]);
+
+        let stats = analyzer.get(2).unwrap();
+        assert_eq!(stats.restarts, 2);
+        assert_eq!((stats.missing, stats.duplicates, stats.reordered), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_tracker_drops_duplicates_and_reports() {
+        let (tracker, events) = SequenceTracker::new(8);
+        let addr: SocketAddr = "10.0.0.3:5000".parse().unwrap();
+        let admitted: Vec<bool> = [10u16, 11, 14, 12, 12, 15]
+            .iter()
+            .map(|&sequence| tracker.admit(&FleetMsgHeader::new(MessageType::Data, 3, sequence, 0), b"", addr))
+            .collect();
+        assert_eq!(admitted, [true, true, true, true, false, true]);
+
+        let outcomes: Vec<SequenceOutcome> = std::iter::from_fn(|| events.try_next()).map(|event| event.outcome).collect();
+        assert_eq!(outcomes, [
+            SequenceOutcome::Gap { expected: 12, missing: 2 },
+            SequenceOutcome::Late { depth: 2 },
+            SequenceOutcome::Duplicate,
+        ]);
+        assert_eq!(tracker.stats(3).unwrap().missing, 1);
+
+        let tracker = tracker.with_duplicates_delivered(true);
+        assert!(tracker.admit(&FleetMsgHeader::new(MessageType::Data, 3, 15, 0), b"", addr));
+        tracker.forget(3);
+        assert!(tracker.senders().is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+//! Per-peer session encryption, keyed by the [`handshake`](crate::handshake).
+//!
+//! Each authenticated peer gets a cipher state per direction: a key, an epoch
+//! and a message counter that together form the nonce. The sending side
+//! rekeys on its own after [`RekeyPolicy::max_messages`] messages or
+//! [`RekeyPolicy::max_age`], deriving the next key from the current one with
+//! HKDF; the epoch travels in the nonce, so the receiving side follows by
+//! ratcheting forward the same way. Old keys are wiped once ratcheted past.
+//! Counters also let the receiver reject replays within a small window.
+//!
+//! Give the same [`SessionCiphers`] to the [`FeatureCodec`](crate::features::FeatureCodec)
+//! (`with_sessions`) and the [`PeerAuthenticator`](crate::handshake::PeerAuthenticator)
+//! (`with_sessions`): sessions are installed when handshakes complete, and the
+//! codec then seals unicast messages to those peers with them and opens what
+//! they send, in place of the fleet key.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::Zeroize;
+
+use crate::crypto;
+use crate::handshake::{SESSION_KEY_LEN, SessionKeys};
+
+/// Sealed payloads start with the nonce: the epoch then the counter, little-endian
+pub const SESSION_NONCE_LEN: usize = 12;
+
+/// How far ahead of its current epoch a receiver will ratchet to follow a sender
+const MAX_EPOCH_SKIP: u32 = 8;
+/// Counters this far behind the highest seen in an epoch are refused as possible replays
+const REPLAY_WINDOW: u64 = 64;
+const REKEY_INFO: &[u8] = b"fleetlink-rekey-v1";
+
+/// When a sender moves its session on to a fresh key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    pub max_messages: u64,
+    pub max_age: Duration,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self { max_messages: 1 << 20, max_age: Duration::from_secs(10 * 60) }
+    }
+}
+
+/// One key of a direction, wiped on drop
+struct EpochKey {
+    epoch: u32,
+    key: [u8; SESSION_KEY_LEN],
+}
+
+impl EpochKey {
+    fn new(epoch: u32, key: [u8; SESSION_KEY_LEN]) -> Self {
+        Self { epoch, key }
+    }
+
+    fn next(&self) -> Self {
+        let mut key = [0u8; SESSION_KEY_LEN];
+        crypto::provider().hkdf_sha256(None, &self.key, REKEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self::new(self.epoch.wrapping_add(1), key)
+    }
+}
+
+impl Drop for EpochKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+fn nonce(epoch: u32, counter: u64) -> [u8; SESSION_NONCE_LEN] {
+    let mut nonce = [0u8; SESSION_NONCE_LEN];
+    nonce[..4].copy_from_slice(&epoch.to_le_bytes());
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// Our sending direction to one peer
+struct SendState {
+    key: EpochKey,
+    counter: u64,
+    since: Instant,
+}
+
+impl SendState {
+    fn seal(&mut self, policy: &RekeyPolicy, plaintext: &[u8], now: Instant) -> std::io::Result<Vec<u8>> {
+        if self.counter >= policy.max_messages || now.duration_since(self.since) >= policy.max_age {
+            self.key = self.key.next();
+            self.counter = 0;
+            self.since = now;
+        }
+        let nonce = nonce(self.key.epoch, self.counter);
+        self.counter += 1;
+        let sealed = crypto::provider().seal(&self.key.key, &nonce, plaintext)?;
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&sealed);
+        Ok(payload)
+    }
+}
+
+/// Counters already accepted in one epoch
+#[derive(Default)]
+struct ReplayWindow {
+    /// One past the highest counter accepted
+    next: u64,
+    /// Bit `i` is set when counter `next - 1 - i` was accepted
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn is_fresh(&self, counter: u64) -> bool {
+        if counter >= self.next {
+            return true;
+        }
+        let behind = self.next - 1 - counter;
+        behind < REPLAY_WINDOW && self.seen & (1 << behind) == 0
+    }
+
+    fn accept(&mut self, counter: u64) {
+        if counter >= self.next {
+            let shift = counter + 1 - self.next;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.next = counter + 1;
+        } else {
+            self.seen |= 1 << (self.next - 1 - counter);
+        }
+    }
+}
+
+/// Our receiving direction from one peer. The previous epoch's key is kept so
+/// messages reordered around a rekey still open.
+struct ReceiveState {
+    current: (EpochKey, ReplayWindow),
+    previous: Option<(EpochKey, ReplayWindow)>,
+}
+
+impl ReceiveState {
+    fn open(&mut self, sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+        if sealed.len() < SESSION_NONCE_LEN {
+            return Err(invalid("payload too short for nonce"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(SESSION_NONCE_LEN);
+        let epoch = u32::from_le_bytes(nonce[..4].try_into().unwrap());
+        let counter = u64::from_le_bytes(nonce[4..].try_into().unwrap());
+        let nonce: &[u8; SESSION_NONCE_LEN] = nonce.try_into().unwrap();
+        let decrypt = |key: &EpochKey| crypto::provider().open(&key.key, nonce, ciphertext)
+            .map_err(|_| invalid("payload failed session authentication"));
+
+        let ahead = epoch.wrapping_sub(self.current.0.epoch);
+        if ahead == 0 || self.previous.as_ref().is_some_and(|(key, _)| key.epoch == epoch) {
+            let (key, window) = if ahead == 0 { &mut self.current } else { self.previous.as_mut().unwrap() };
+            if !window.is_fresh(counter) {
+                return Err(invalid("replayed session message"));
+            }
+            let plaintext = decrypt(key)?;
+            window.accept(counter);
+            return Ok(plaintext);
+        }
+        if ahead > MAX_EPOCH_SKIP {
+            return Err(invalid("session message from an unknown epoch"));
+        }
+
+        // The sender has rekeyed: follow it, but only once the message proves the new key
+        let mut key = self.current.0.next();
+        for _ in 1..ahead {
+            key = key.next();
+        }
+        let plaintext = decrypt(&key)?;
+        let mut window = ReplayWindow::default();
+        window.accept(counter);
+        let previous = std::mem::replace(&mut self.current, (key, window));
+        self.previous = (ahead == 1).then_some(previous);
+        Ok(plaintext)
+    }
+}
+
+struct PeerSession {
+    send: SendState,
+    receive: ReceiveState,
+}
+
+/// The session cipher states of every authenticated peer, shared between the
+/// codec that uses them and whatever installs them
+#[derive(Clone, Default)]
+pub struct SessionCiphers {
+    sessions: Arc<Mutex<HashMap<u32, PeerSession>>>,
+    policy: RekeyPolicy,
+}
+
+impl std::fmt::Debug for SessionCiphers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionCiphers")
+            .field("peers", &self.sessions.lock().unwrap().len())
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl SessionCiphers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rekey_policy(mut self, policy: RekeyPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Start (or restart) the session with `keys.peer_id` from epoch 0
+    pub fn install(&self, keys: &SessionKeys) {
+        let session = PeerSession {
+            send: SendState { key: EpochKey::new(0, keys.send), counter: 0, since: Instant::now() },
+            receive: ReceiveState { current: (EpochKey::new(0, keys.receive), ReplayWindow::default()), previous: None },
+        };
+        self.sessions.lock().unwrap().insert(keys.peer_id, session);
+    }
+
+    pub fn remove(&self, peer_id: u32) -> bool {
+        self.sessions.lock().unwrap().remove(&peer_id).is_some()
+    }
+
+    pub fn has_session(&self, peer_id: u32) -> bool {
+        self.sessions.lock().unwrap().contains_key(&peer_id)
+    }
+
+    /// The epoch our messages to `peer_id` are currently sealed under
+    pub fn send_epoch(&self, peer_id: u32) -> Option<u32> {
+        self.sessions.lock().unwrap().get(&peer_id).map(|session| session.send.key.epoch)
+    }
+
+    /// Encrypt `plaintext` for `peer_id`, rekeying first if the policy says so
+    pub fn seal(&self, peer_id: u32, plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(&peer_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no session with peer {}", peer_id)))?;
+        session.send.seal(&self.policy, plaintext, Instant::now())
+    }
+
+    /// Decrypt a payload `peer_id` sealed for us
+    pub fn open(&self, peer_id: u32, sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(&peer_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no session with peer {}", peer_id)))?;
+        session.receive.open(sealed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn pair(policy: RekeyPolicy) -> (SessionCiphers, SessionCiphers) {
+        let keys = |peer_id, send, receive| SessionKeys {
+            peer_id,
+            key_id: "ops".to_string(),
+            peer_features: Default::default(),
+            send: [send; SESSION_KEY_LEN],
+            receive: [receive; SESSION_KEY_LEN],
+            established_at: Utc::now(),
+        };
+        let (base, robot) = (SessionCiphers::new().with_rekey_policy(policy), SessionCiphers::new().with_rekey_policy(policy));
+        base.install(&keys(42, 1, 2));
+        robot.install(&keys(1, 2, 1));
+        (base, robot)
+    }
+
+    #[test]
+    fn test_rekeys_transparently_and_refuses_replays() {
+        let (base, robot) = pair(RekeyPolicy { max_messages: 3, max_age: Duration::from_secs(60) });
+        let sealed: Vec<Vec<u8>> = (0..10u8).map(|i| base.seal(42, &[i]).unwrap()).collect();
+        assert_eq!(base.send_epoch(42), Some(3));
+        assert_ne!(sealed[0][SESSION_NONCE_LEN..], sealed[3][SESSION_NONCE_LEN..], "a new epoch has a new key");
+
+        // Reordered across a rekey, and skipping a whole epoch's messages
+        for i in [0, 3, 2, 4, 6, 9, 7] {
+            assert_eq!(robot.open(1, &sealed[i]).unwrap(), [i as u8]);
+        }
+        assert!(robot.open(1, &sealed[9]).is_err(), "replay");
+        assert!(robot.open(1, &sealed[1]).is_err(), "epoch ratcheted past");
+        assert_eq!(robot.open(1, &robot.seal(1, b"ack").unwrap()).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(base.open(1, b"x").unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(base.open(42, &robot.seal(1, b"ack").unwrap()).unwrap(), b"ack");
+    }
+
+    #[test]
+    fn test_codec_uses_sessions_for_unicast() {
+        use crate::features::{FeatureCodec, ProtocolFeatures};
+        use crate::transport::{FleetMsgHeader, MessageType};
+
+        let (base, robot) = pair(RekeyPolicy::default());
+        let fleet_key = [9u8; 32];
+        let base_codec = FeatureCodec::new().with_encryption_key(fleet_key).with_sessions(base);
+        let robot_codec = FeatureCodec::new().with_encryption_key(fleet_key).with_sessions(robot);
+        let decode = |codec: &FeatureCodec, sender, (features, payload): (ProtocolFeatures, Vec<u8>)| {
+            let header = FleetMsgHeader::new(MessageType::Control, sender, 0, payload.len() as u16).with_features(features);
+            codec.decode(&header, &payload)
+        };
+
+        // Encrypted under the session even when no features were asked for
+        let unicast = base_codec.encode_for(Some(42), ProtocolFeatures::NONE, b"STOP").unwrap();
+        assert_eq!(unicast.0, ProtocolFeatures::ENCRYPTION);
+        assert!(decode(&FeatureCodec::new().with_encryption_key(fleet_key), 1, unicast.clone()).is_err());
+        assert_eq!(decode(&robot_codec, 1, unicast).unwrap(), b"STOP");
+
+        // Multicasts still use the fleet key
+        let multicast = base_codec.encode(ProtocolFeatures::ENCRYPTION, b"pose").unwrap();
+        assert_eq!(decode(&robot_codec, 1, multicast).unwrap(), b"pose");
+    }
+
+    #[test]
+    fn test_rekeys_by_age() {
+        let (base, robot) = pair(RekeyPolicy { max_messages: u64::MAX, max_age: Duration::ZERO });
+        for _ in 0..3 {
+            let sealed = base.seal(42, b"pose").unwrap();
+            assert_eq!(robot.open(1, &sealed).unwrap(), b"pose");
+        }
+        assert_eq!(base.send_epoch(42), Some(3));
+    }
+}
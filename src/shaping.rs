@@ -0,0 +1,222 @@
+//! Transmit calendar: windows in which a sender holds back non-critical
+//! traffic, e.g. quiet hours while vehicles charge on a congested depot Wi-Fi,
+//! or duty cycling of battery-powered beacons.
+//!
+//! Each [`ShapingRule`] is a recurring window during which only the message
+//! classes it allows go out. A [`ShapingCalendar`] combines rules: a message is
+//! held while any rule holds its class. Attached with
+//! [`MulticastSender::with_shaping_calendar`](crate::transport::MulticastSender::with_shaping_calendar),
+//! a held send waits for the window to close if that is within the calendar's
+//! `max_defer`, and otherwise fails with [`TransportError::Held`](crate::error::TransportError::Held).
+//! [`MulticastSender::send_emergency`](crate::transport::MulticastSender::send_emergency)
+//! is never held.
+//!
+//! Duty cycles are aligned to the Unix epoch (plus the rule's phase), so every
+//! node with a synchronized clock agrees on when the on-windows are.
+
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bandwidth::MessageClass;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Rule windows looked at when working out how long a message is held
+const MAX_WINDOWS_CHECKED: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Window {
+    /// Held from `start` to `end` after local midnight, wrapping past midnight if `end` is earlier
+    Daily { start: Duration, end: Duration },
+    /// Held for the rest of every `period` after its first `on`
+    DutyCycle { period: Duration, on: Duration, phase: Duration },
+}
+
+/// A recurring window that holds every message class it doesn't allow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapingRule {
+    window: Window,
+    allowed: BTreeSet<MessageClass>,
+}
+
+impl ShapingRule {
+    /// Hold traffic daily from `start` to `end`, both measured from midnight
+    /// in the calendar's time zone; an `end` before `start` ends the next day
+    pub fn quiet_hours(start: Duration, end: Duration) -> Self {
+        let window = Window::Daily { start: duration_mod(start, DAY), end: duration_mod(end, DAY) };
+        Self { window, allowed: BTreeSet::new() }
+    }
+
+    /// Only transmit during the first `on` of every `period`
+    pub fn duty_cycle(period: Duration, on: Duration) -> Self {
+        let period = period.max(Duration::from_millis(1));
+        let window = Window::DutyCycle { period, on: on.min(period), phase: Duration::ZERO };
+        Self { window, allowed: BTreeSet::new() }
+    }
+
+    /// Shift a duty cycle's on-window this far past the period boundary, e.g.
+    /// to stagger beacons; no effect on quiet hours
+    pub fn with_phase(mut self, phase: Duration) -> Self {
+        if let Window::DutyCycle { period, phase: current, .. } = &mut self.window {
+            *current = duration_mod(phase, *period);
+        }
+        self
+    }
+
+    /// Let these classes through while the rule holds traffic
+    pub fn allowing(mut self, classes: impl IntoIterator<Item = MessageClass>) -> Self {
+        self.allowed.extend(classes);
+        self
+    }
+
+    /// How long the rule holds `class` for, at `since_epoch` in the calendar's time zone
+    fn held_for(&self, class: MessageClass, since_epoch: Duration) -> Duration {
+        if self.allowed.contains(&class) {
+            return Duration::ZERO;
+        }
+        match self.window {
+            Window::Daily { start, end } => {
+                let time = duration_mod(since_epoch, DAY);
+                let held = match start <= end {
+                    true => start <= time && time < end,
+                    false => time >= start || time < end,
+                };
+                if held { duration_mod(end + DAY - time, DAY) } else { Duration::ZERO }
+            }
+            Window::DutyCycle { period, on, phase } => {
+                let position = duration_mod(since_epoch + period - phase, period);
+                if position < on { Duration::ZERO } else { period - position }
+            }
+        }
+    }
+}
+
+/// The rules a sender's traffic is shaped by; see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShapingCalendar {
+    rules: Vec<ShapingRule>,
+    utc_offset: i64,
+    max_defer: Duration,
+}
+
+impl ShapingCalendar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: ShapingRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Read quiet hours in a time zone this many seconds east of UTC (default UTC)
+    pub fn with_utc_offset(mut self, offset_secs: i32) -> Self {
+        self.utc_offset = offset_secs as i64;
+        self
+    }
+
+    /// Wait up to this long for a held message's window to close instead of
+    /// failing the send (default zero: fail straight away)
+    pub fn with_max_defer(mut self, max_defer: Duration) -> Self {
+        self.max_defer = max_defer;
+        self
+    }
+
+    pub fn max_defer(&self) -> Duration {
+        self.max_defer
+    }
+
+    /// How long messages of `class` are held at `now`; zero if they may go out
+    pub fn delay(&self, class: MessageClass, now: SystemTime) -> Duration {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let local = match self.utc_offset >= 0 {
+            true => since_epoch + Duration::from_secs(self.utc_offset as u64),
+            false => since_epoch.saturating_sub(Duration::from_secs(self.utc_offset.unsigned_abs())),
+        };
+        // The end of one rule's window may fall in another's
+        let mut delay = Duration::ZERO;
+        for _ in 0..MAX_WINDOWS_CHECKED {
+            let held = self.rules.iter().map(|rule| rule.held_for(class, local + delay)).max().unwrap_or_default();
+            if held.is_zero() {
+                break;
+            }
+            delay += held;
+        }
+        delay
+    }
+}
+
+fn duration_mod(value: Duration, modulus: Duration) -> Duration {
+    Duration::from_nanos((value.as_nanos() % modulus.as_nanos()) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TransportError;
+    use crate::testing::TestReceiver;
+    use crate::transport::MessageType;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    fn at(since_epoch: Duration) -> SystemTime {
+        UNIX_EPOCH + since_epoch
+    }
+
+    #[test]
+    fn test_quiet_hours_wrap_midnight_in_local_time() {
+        // 22:00 to 06:00 at UTC+2, control traffic still allowed
+        let calendar = ShapingCalendar::new()
+            .with_utc_offset(2 * 60 * 60)
+            .with_rule(ShapingRule::quiet_hours(22 * HOUR, 6 * HOUR).allowing([MessageClass::Control]));
+        let day = 20_000 * DAY;
+
+        assert_eq!(calendar.delay(MessageClass::Telemetry, at(day + 19 * HOUR)), Duration::ZERO);
+        assert_eq!(calendar.delay(MessageClass::Telemetry, at(day + 20 * HOUR)), 8 * HOUR);
+        assert_eq!(calendar.delay(MessageClass::Bulk, at(day + DAY + HOUR)), 3 * HOUR);
+        assert_eq!(calendar.delay(MessageClass::Control, at(day + 23 * HOUR)), Duration::ZERO);
+        assert_eq!(calendar.delay(MessageClass::Telemetry, at(day + DAY + 4 * HOUR)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_duty_cycles_combine() {
+        let second = Duration::from_secs(1);
+        // On for the first 10 s of every minute, and for 20 s from 5 s into every 30 s
+        let calendar = ShapingCalendar::new()
+            .with_rule(ShapingRule::duty_cycle(60 * second, 10 * second))
+            .with_rule(ShapingRule::duty_cycle(30 * second, 20 * second).with_phase(5 * second));
+        let minute = 1_000_000 * 60 * second;
+
+        assert_eq!(calendar.delay(MessageClass::Telemetry, at(minute + 7 * second)), Duration::ZERO);
+        // Held by the first rule until the next minute, then by the second until 5 s past it
+        assert_eq!(calendar.delay(MessageClass::Telemetry, at(minute + 12 * second)), 53 * second);
+        assert_eq!(calendar.delay(MessageClass::Telemetry, at(minute + 2 * second)), 3 * second);
+    }
+
+    #[async_std::test]
+    async fn test_held_sends_fail_but_emergencies_go_out() {
+        let receiver = TestReceiver::start().await.unwrap();
+        let calendar = ShapingCalendar::new().with_rule(ShapingRule::quiet_hours(Duration::ZERO, DAY - Duration::from_nanos(1)));
+        let mut sender = receiver.sender(6).await.unwrap().with_shaping_calendar(calendar);
+
+        let error = sender.send_data(b"routine").await.unwrap_err();
+        assert!(matches!(error, TransportError::Held { class: MessageClass::Telemetry, .. }));
+        sender.send_emergency(MessageType::Control, b"STOP").await.unwrap();
+        let received = receiver.wait_for(1, Duration::from_secs(2)).await;
+        assert_eq!(received.iter().map(|(_, payload, _)| payload.as_slice()).collect::<Vec<_>>(), [b"STOP"]);
+    }
+
+    #[async_std::test]
+    async fn test_shutdown_says_goodbye_during_quiet_hours() {
+        let receiver = TestReceiver::start().await.unwrap();
+        // Holds every class, Control included
+        let calendar = ShapingCalendar::new().with_rule(ShapingRule::quiet_hours(Duration::ZERO, DAY - Duration::from_nanos(1)));
+        let sender = receiver.sender(7).await.unwrap().with_shaping_calendar(calendar);
+
+        sender.shutdown().await.unwrap();
+        // Exactly one Goodbye: dropping the sender doesn't send another
+        let received = receiver.wait_for(2, Duration::from_millis(500)).await;
+        let types: Vec<MessageType> = received.iter().map(|(header, _, _)| header.message_type()).collect();
+        assert_eq!(types, [MessageType::Goodbye]);
+    }
+}
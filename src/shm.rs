@@ -0,0 +1,365 @@
+//! Shared-memory ring transport for co-located high-rate producers and
+//! consumers (perception feeding a planner, say), with microsecond latency and
+//! the same frames, message types and receive validation as multicast.
+//!
+//! A [`ShmSender`] creates a ring in a memory-mapped file, ideally under
+//! `/dev/shm`, and is its only writer. Any number of [`ShmReceiver`]s map the
+//! same file and read at their own pace. The writer never waits for readers: a
+//! reader that falls a whole ring behind loses the oldest messages and counts
+//! them in [`ShmReceiver::lost`].
+//!
+//! Each slot carries a stamp that is odd while the writer is filling it and
+//! `2 * (n + 1)` once message `n` is complete. Readers check the stamp before
+//! and after copying, and drop a message the writer lapped mid-copy.
+
+use memmap2::MmapRaw;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+use std::time::Duration;
+use zerocopy::AsBytes;
+
+use crate::protocol;
+use crate::receiver::{self, Delivery, ReceiverConfig};
+use crate::stats::TransportStats;
+use crate::transport::{FleetMsgHeader, MessageType};
+
+/// Deliveries from a shared-memory ring have no IP source; they carry this address instead
+pub const SHM_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 1);
+
+/// "FLSH", written last so readers never see a half-initialised ring
+const MAGIC: u32 = 0x4853_4C46;
+const LAYOUT_VERSION: u32 = 1;
+
+/// Magic, layout version, slot count, slot size, then the write index at 16
+const RING_HEADER_LEN: usize = 64;
+/// Stamp, then the frame length at 8
+const SLOT_HEADER_LEN: usize = 16;
+
+/// Empty polls a receiver spends yielding before it starts sleeping between polls
+const SPIN_POLLS: u32 = 1000;
+const IDLE_POLL: Duration = Duration::from_micros(50);
+
+/// Size of a ring: how many messages it holds and how large each may be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShmRing {
+    pub slots: u32,
+    /// Bytes per slot, including a 16-byte slot header; rounded up to a multiple of 8
+    pub slot_size: u32,
+}
+
+impl Default for ShmRing {
+    /// 1024 slots of 2 KiB, about 2 MiB
+    fn default() -> Self {
+        Self { slots: 1024, slot_size: 2048 }
+    }
+}
+
+impl ShmRing {
+    /// Largest frame, header included, one slot holds
+    pub fn max_frame_len(&self) -> usize {
+        self.slot_stride().saturating_sub(SLOT_HEADER_LEN)
+    }
+
+    fn slot_stride(&self) -> usize {
+        (self.slot_size as usize).next_multiple_of(8)
+    }
+
+    fn file_len(&self) -> usize {
+        RING_HEADER_LEN + self.slots as usize * self.slot_stride()
+    }
+}
+
+/// A mapped ring file; all access to the shared memory goes through here
+#[derive(Debug)]
+struct Mapping {
+    map: MmapRaw,
+    ring: ShmRing,
+}
+
+impl Mapping {
+    fn map(file: &File, ring: ShmRing) -> std::io::Result<Self> {
+        Ok(Self { map: MmapRaw::map_raw(file)?, ring })
+    }
+
+    /// Safety: `offset` must be 4-aligned and inside the mapping
+    unsafe fn u32_at(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.map.as_mut_ptr().add(offset) as *const AtomicU32) }
+    }
+
+    /// Safety: `offset` must be 8-aligned and inside the mapping
+    unsafe fn u64_at(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.map.as_mut_ptr().add(offset) as *const AtomicU64) }
+    }
+
+    fn write_index(&self) -> &AtomicU64 {
+        // Safety: the mapping is page-aligned and at least RING_HEADER_LEN long
+        unsafe { self.u64_at(16) }
+    }
+
+    fn slot_offset(&self, index: u64) -> usize {
+        RING_HEADER_LEN + (index % self.ring.slots as u64) as usize * self.ring.slot_stride()
+    }
+
+    fn stamp(&self, index: u64) -> &AtomicU64 {
+        // Safety: slots start 8-aligned and the mapping holds all of them
+        unsafe { self.u64_at(self.slot_offset(index)) }
+    }
+
+    fn frame_len(&self, index: u64) -> &AtomicU32 {
+        // Safety: as for `stamp`
+        unsafe { self.u32_at(self.slot_offset(index) + 8) }
+    }
+
+    fn frame_ptr(&self, index: u64) -> *mut u8 {
+        // Safety: the frame area is inside the slot
+        unsafe { self.map.as_mut_ptr().add(self.slot_offset(index) + SLOT_HEADER_LEN) }
+    }
+}
+
+/// Sole writer of a shared-memory ring
+#[derive(Debug)]
+pub struct ShmSender {
+    mapping: Mapping,
+    sender_id: u32,
+    sequence: u16,
+    stats: Arc<TransportStats>,
+}
+
+impl ShmSender {
+    /// Create (or replace) the ring at `path`. Receivers that had the old file
+    /// mapped keep reading it, so restart them along with the sender.
+    pub fn create(path: impl AsRef<Path>, ring: ShmRing, sender_id: u32) -> std::io::Result<Self> {
+        if ring.slots == 0 || ring.max_frame_len() < protocol::HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{:?} can't hold a single message", ring)));
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(ring.file_len() as u64)?;
+        let mapping = Mapping::map(&file, ring)?;
+        // Safety: all inside the freshly sized header
+        unsafe {
+            mapping.u32_at(4).store(LAYOUT_VERSION, Ordering::Relaxed);
+            mapping.u32_at(8).store(ring.slots, Ordering::Relaxed);
+            mapping.u32_at(12).store(ring.slot_stride() as u32, Ordering::Relaxed);
+            mapping.u32_at(0).store(MAGIC, Ordering::Release);
+        }
+        Ok(Self { mapping, sender_id, sequence: 0, stats: Arc::new(TransportStats::new()) })
+    }
+
+    /// Count sends in shared stats, e.g. the ones a multicast sender on the same node uses
+    pub fn with_stats(mut self, stats: Arc<TransportStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+
+    pub fn ring(&self) -> ShmRing {
+        self.mapping.ring
+    }
+
+    /// Publish one message. Never waits: the oldest message is overwritten
+    /// whether or not every receiver has read it.
+    pub async fn send_message(&mut self, msg_type: MessageType, payload: &[u8]) -> std::io::Result<()> {
+        let header = FleetMsgHeader::new(msg_type, self.sender_id, self.sequence, payload.len() as u16);
+        let frame_len = protocol::HEADER_LEN + payload.len();
+        if frame_len > self.mapping.ring.max_frame_len() || payload.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, format!(
+                "{} byte payload doesn't fit a {} byte slot", payload.len(), self.mapping.ring.slot_size
+            )));
+        }
+
+        let index = self.mapping.write_index().load(Ordering::Relaxed);
+        let stamp = self.mapping.stamp(index);
+        stamp.store(2 * index + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.mapping.frame_len(index).store(frame_len as u32, Ordering::Relaxed);
+        // Safety: the frame fits the slot, checked above
+        unsafe {
+            let dest = self.mapping.frame_ptr(index);
+            std::ptr::copy_nonoverlapping(header.as_bytes().as_ptr(), dest, protocol::HEADER_LEN);
+            std::ptr::copy_nonoverlapping(payload.as_ptr(), dest.add(protocol::HEADER_LEN), payload.len());
+        }
+        stamp.store(2 * index + 2, Ordering::Release);
+        self.mapping.write_index().store(index + 1, Ordering::Release);
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stats.record_sent(frame_len);
+        Ok(())
+    }
+
+    pub async fn send_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.send_message(MessageType::Data, data).await
+    }
+
+    pub async fn send_control(&mut self, command: &str) -> std::io::Result<()> {
+        self.send_message(MessageType::Control, command.as_bytes()).await
+    }
+}
+
+/// One reader of a shared-memory ring, starting at whatever is written after it opens
+#[derive(Debug)]
+pub struct ShmReceiver {
+    mapping: Mapping,
+    config: ReceiverConfig,
+    next: u64,
+    lost: u64,
+}
+
+impl ShmReceiver {
+    pub fn open(path: impl AsRef<Path>, config: ReceiverConfig) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let invalid = |what: String| Error::new(ErrorKind::InvalidData, what);
+        if file.metadata()?.len() < RING_HEADER_LEN as u64 {
+            return Err(invalid("file is too short for a shared-memory ring".to_string()));
+        }
+        let probe = Mapping::map(&file, ShmRing { slots: 1, slot_size: 0 })?;
+        // Safety: the file holds at least a ring header
+        let (magic, version, slots, slot_size) = unsafe {
+            (
+                probe.u32_at(0).load(Ordering::Acquire),
+                probe.u32_at(4).load(Ordering::Relaxed),
+                probe.u32_at(8).load(Ordering::Relaxed),
+                probe.u32_at(12).load(Ordering::Relaxed),
+            )
+        };
+        if magic != MAGIC {
+            return Err(invalid("not a FleetLink shared-memory ring".to_string()));
+        }
+        if version != LAYOUT_VERSION {
+            return Err(invalid(format!("ring layout version {} is not supported", version)));
+        }
+        let ring = ShmRing { slots, slot_size };
+        if slots == 0 || (file.metadata()?.len() as usize) < ring.file_len() {
+            return Err(invalid(format!("file is too short for {:?}", ring)));
+        }
+
+        let mapping = Mapping { map: probe.map, ring };
+        let next = mapping.write_index().load(Ordering::Acquire);
+        Ok(Self { mapping, config, next, lost: 0 })
+    }
+
+    /// Messages overwritten before this receiver got to them
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// The next message, or `None` if the receiver has caught up. Frames that
+    /// fail validation are reported and skipped.
+    pub fn try_recv(&mut self) -> Option<Delivery> {
+        loop {
+            let head = self.mapping.write_index().load(Ordering::Acquire);
+            if self.next >= head {
+                return None;
+            }
+            let slots = self.mapping.ring.slots as u64;
+            if head - self.next > slots {
+                self.lost += head - self.next - slots;
+                self.next = head - slots;
+            }
+
+            let index = self.next;
+            self.next += 1;
+            let Some(frame) = self.copy_frame(index) else {
+                self.lost += 1;
+                continue;
+            };
+            if !self.config.screen(&frame, SHM_ADDR) {
+                continue;
+            }
+            match receiver::inspect(&frame, SHM_ADDR, &self.config) {
+                Ok(delivery) => return Some(delivery),
+                Err(issues) => eprintln!("Dropped shared-memory frame: {}", receiver::describe(&issues)),
+            }
+        }
+    }
+
+    /// Copy out message `index`, or `None` if the writer has lapped it
+    fn copy_frame(&self, index: u64) -> Option<Vec<u8>> {
+        let stamp = self.mapping.stamp(index);
+        let complete = 2 * index + 2;
+        if stamp.load(Ordering::Acquire) != complete {
+            return None;
+        }
+        let len = (self.mapping.frame_len(index).load(Ordering::Relaxed) as usize).min(self.mapping.ring.max_frame_len());
+        let mut frame = vec![0u8; len];
+        // Safety: `len` is clamped to the slot. The writer may be overwriting
+        // these bytes right now; the stamp check below throws such a copy away.
+        unsafe { std::ptr::copy_nonoverlapping(self.mapping.frame_ptr(index), frame.as_mut_ptr(), len) };
+        fence(Ordering::Acquire);
+        (stamp.load(Ordering::Relaxed) == complete).then_some(frame)
+    }
+}
+
+/// Read the ring at `path` until the task is dropped, validating messages as
+/// `config` says. Polls without sleeping for a while after each message, so
+/// a busy stream is picked up within microseconds.
+pub async fn start_shm_rx(
+    path: impl AsRef<Path>,
+    config: ReceiverConfig,
+    mut message_handler: impl FnMut(Delivery)
+) -> std::io::Result<()> {
+    let mut receiver = ShmReceiver::open(path, config)?;
+    let mut idle_polls = 0;
+    loop {
+        if let Some(delivery) = receiver.try_recv() {
+            message_handler(delivery);
+            idle_polls = 0;
+        } else if idle_polls < SPIN_POLLS {
+            idle_polls += 1;
+            async_std::task::yield_now().await;
+        } else {
+            async_std::task::sleep(IDLE_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("fleetlink-{}-{}.ring", name, std::process::id()))
+    }
+
+    #[async_std::test]
+    async fn test_messages_pass_through_the_ring() {
+        let path = ring_path("pass");
+        let ring = ShmRing { slots: 8, slot_size: 64 };
+        let mut sender = ShmSender::create(&path, ring, 0x5E).unwrap();
+        let mut receiver = ShmReceiver::open(&path, ReceiverConfig::new()).unwrap();
+        assert!(receiver.try_recv().is_none());
+
+        sender.send_data(b"obstacle").await.unwrap();
+        sender.send_control("STOP").await.unwrap();
+        assert!(sender.send_data(&[0; 41]).await.is_err());
+
+        let first = receiver.try_recv().unwrap();
+        assert_eq!((first.header.sender_id.get(), first.payload.as_slice()), (0x5E, &b"obstacle"[..]));
+        let second = receiver.try_recv().unwrap();
+        assert_eq!((second.header.message_type(), second.header.sequence.get()), (MessageType::Control, 1));
+        assert!(receiver.try_recv().is_none());
+        assert_eq!(receiver.lost(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_slow_receiver_loses_oldest() {
+        let path = ring_path("lapped");
+        let mut sender = ShmSender::create(&path, ShmRing { slots: 4, slot_size: 64 }, 1).unwrap();
+        let mut receiver = ShmReceiver::open(&path, ReceiverConfig::new()).unwrap();
+        for i in 0..10u8 {
+            sender.send_data(&[i]).await.unwrap();
+        }
+
+        let received: Vec<u8> = std::iter::from_fn(|| receiver.try_recv()).map(|d| d.payload[0]).collect();
+        assert_eq!(received, [6, 7, 8, 9]);
+        assert_eq!(receiver.lost(), 6);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use rand::Rng;
+use rand::seq::index;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+use crate::rng::{self, FleetRng};
+
+use super::{
+    EventRecord, FIRST_SIM_SENDER_ID, FleetEvent, LinkModel, LossProfile, NodeGroup, NodeReport, SimInterval,
+    SimReport, TimedEvent,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug)]
+struct Node {
+    sender_id: u32,
+    group: usize,
+    active: bool,
+    /// On the far side of a partition
+    isolated: bool,
+    /// Bumped whenever the node leaves, so its pending sends are discarded
+    epoch: u32,
+    sent: u64,
+    delivered: u64,
+}
+
+/// Discrete-event model of a fleet sharing one channel
+struct Engine<'a> {
+    groups: &'a [NodeGroup],
+    link: &'a LinkModel,
+    loss: LossProfile,
+    in_bad_state: bool,
+    rng: FleetRng,
+    nodes: Vec<Node>,
+    due: BinaryHeap<Reverse<(u64, usize, u32)>>,
+    channel_free_at: u64,
+    busy_ns: u64,
+    latencies_us: Vec<u64>,
+    timeline: Vec<SimInterval>,
+    events: Vec<EventRecord>,
+    /// First second whose `active_nodes` hasn't been recorded yet
+    next_second: usize,
+}
+
+/// Run `groups` over `link` for `duration`, applying `events` in order.
+/// The same inputs and seed always give the same report.
+pub(super) fn simulate(
+    groups: &[NodeGroup],
+    link: &LinkModel,
+    loss: LossProfile,
+    events: &[TimedEvent],
+    duration: Duration,
+    seed: u64,
+) -> SimReport {
+    let end = duration.as_nanos() as u64;
+    let mut engine = Engine {
+        groups,
+        link,
+        loss,
+        in_bad_state: false,
+        rng: rng::seeded(seed),
+        nodes: Vec::new(),
+        due: BinaryHeap::new(),
+        channel_free_at: 0,
+        busy_ns: 0,
+        latencies_us: Vec::new(),
+        timeline: vec![SimInterval::default(); end.div_ceil(NANOS_PER_SEC) as usize],
+        events: Vec::new(),
+        next_second: 0,
+    };
+    for (group, spec) in groups.iter().enumerate() {
+        engine.add_nodes(group, spec.nodes, 0);
+    }
+
+    let mut pending = events.iter().peekable();
+    loop {
+        let next_send = engine.due.peek().map(|Reverse((at, _, _))| *at);
+        if let Some(event) = pending.next_if(|event| {
+            let at = event.at.as_nanos() as u64;
+            at < end && next_send.is_none_or(|send| at <= send)
+        }) {
+            let at = event.at.as_nanos() as u64;
+            engine.advance_to(at);
+            engine.apply(at, &event.event);
+            continue;
+        }
+
+        let Some(Reverse((now, node, epoch))) = engine.due.pop() else {
+            break;
+        };
+        if now >= end {
+            break;
+        }
+        if engine.nodes[node].epoch != epoch || !engine.nodes[node].active {
+            continue;
+        }
+        engine.advance_to(now);
+        engine.send(now, node);
+        let period = groups[engine.nodes[node].group].pattern.period().as_nanos() as u64;
+        engine.due.push(Reverse((now + period, node, epoch)));
+    }
+    engine.advance_to(end.saturating_sub(1));
+
+    let per_node = engine.nodes.iter()
+        .map(|node| NodeReport {
+            sender_id: node.sender_id,
+            group: groups[node.group].name.clone(),
+            sent: node.sent,
+            delivered: node.delivered,
+        })
+        .collect();
+    let mut report = SimReport::summarize(groups, link, duration, per_node, engine.latencies_us);
+    if link.bytes_per_sec > 0 {
+        report.channel_utilization = Some((engine.busy_ns as f64 / end.max(1) as f64).min(1.0));
+    }
+    report.seed = seed;
+    report.receptions = engine.timeline.iter().map(|s| s.receptions).sum();
+    report.expected_receptions = engine.timeline.iter().map(|s| s.expected_receptions).sum();
+    report.timeline = engine.timeline;
+    report.events = engine.events;
+    report
+}
+
+impl Engine<'_> {
+    /// Create `count` active nodes in `group`, spreading their first sends over one period from `now`
+    fn add_nodes(&mut self, group: usize, count: usize, now: u64) {
+        for i in 0..count {
+            let node = self.push_node(group);
+            self.schedule(node, now, i, count);
+        }
+    }
+
+    fn push_node(&mut self, group: usize) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            sender_id: FIRST_SIM_SENDER_ID + node as u32,
+            group,
+            active: true,
+            isolated: false,
+            epoch: 0,
+            sent: 0,
+            delivered: 0,
+        });
+        node
+    }
+
+    fn schedule(&mut self, node: usize, now: u64, index: usize, of: usize) {
+        let period = self.groups[self.nodes[node].group].pattern.period();
+        let phase = period.mul_f64(index as f64 / of.max(1) as f64).as_nanos() as u64;
+        self.due.push(Reverse((now + phase, node, self.nodes[node].epoch)));
+    }
+
+    /// Record the active node count for every second that has started by `now`
+    fn advance_to(&mut self, now: u64) {
+        let second = (now / NANOS_PER_SEC) as usize;
+        let active = self.nodes.iter().filter(|n| n.active).count();
+        while self.next_second <= second && self.next_second < self.timeline.len() {
+            self.timeline[self.next_second].active_nodes = active;
+            self.next_second += 1;
+        }
+    }
+
+    fn group_index(&self, name: &Option<String>) -> Option<usize> {
+        match name {
+            Some(name) => self.groups.iter().position(|g| &g.name == name),
+            None => Some(0),
+        }
+    }
+
+    /// Indices of nodes matching `filter`, restricted to `group` if one is named
+    fn candidates(&self, group: &Option<String>, filter: impl Fn(&Node) -> bool) -> Vec<usize> {
+        let group = group.as_ref().and_then(|_| self.group_index(group));
+        self.nodes.iter().enumerate()
+            .filter(|(_, node)| group.is_none_or(|g| node.group == g) && filter(node))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Pick `count` of `candidates` at random
+    fn pick(&mut self, candidates: Vec<usize>, count: usize) -> Vec<usize> {
+        let count = count.min(candidates.len());
+        index::sample(&mut self.rng, candidates.len(), count).into_iter().map(|i| candidates[i]).collect()
+    }
+
+    fn apply(&mut self, now: u64, event: &FleetEvent) {
+        let affected = match event {
+            FleetEvent::Leave { group, count } => {
+                let active = self.candidates(group, |n| n.active);
+                let leaving = self.pick(active, *count);
+                for &node in &leaving {
+                    self.nodes[node].active = false;
+                    self.nodes[node].isolated = false;
+                    self.nodes[node].epoch += 1;
+                }
+                leaving.len()
+            }
+            FleetEvent::Join { group, count } => match self.group_index(group) {
+                Some(group_index) => {
+                    let mut departed = self.candidates(&Some(self.groups[group_index].name.clone()), |n| !n.active)
+                        .into_iter();
+                    for i in 0..*count {
+                        let node = match departed.next() {
+                            Some(node) => {
+                                self.nodes[node].active = true;
+                                node
+                            }
+                            None => self.push_node(group_index),
+                        };
+                        self.schedule(node, now, i, *count);
+                    }
+                    *count
+                }
+                None => 0,
+            },
+            FleetEvent::Partition { group, fraction } => {
+                let active = self.candidates(group, |n| n.active);
+                let count = match fraction {
+                    Some(fraction) => (active.len() as f64 * fraction).round() as usize,
+                    None => active.len(),
+                };
+                let isolated = self.pick(active, count);
+                for &node in &isolated {
+                    self.nodes[node].isolated = true;
+                }
+                isolated.len()
+            }
+            FleetEvent::Heal => {
+                let healed = self.nodes.iter().filter(|n| n.isolated).count();
+                self.nodes.iter_mut().for_each(|n| n.isolated = false);
+                healed
+            }
+        };
+
+        self.events.push(EventRecord {
+            at_secs: now as f64 / NANOS_PER_SEC as f64,
+            event: event.clone(),
+            affected,
+        });
+    }
+
+    /// Whether the channel loses the next message, advancing the bursty model's state
+    fn lost(&mut self) -> bool {
+        match self.loss {
+            LossProfile::None => false,
+            LossProfile::Random { probability } => self.rng.random_bool(probability),
+            LossProfile::Bursty { good_loss, bad_loss, enter_bad, leave_bad } => {
+                let flip = if self.in_bad_state { leave_bad } else { enter_bad };
+                if self.rng.random_bool(flip) {
+                    self.in_bad_state = !self.in_bad_state;
+                }
+                self.rng.random_bool(if self.in_bad_state { bad_loss } else { good_loss })
+            }
+        }
+    }
+
+    fn send(&mut self, now: u64, node: usize) {
+        let spec = &self.groups[self.nodes[node].group];
+        let batch = spec.pattern.batch();
+        let wire = spec.wire_bytes(self.link) as u64;
+        // Zero capacity means an unlimited channel: no transmit time, no queueing
+        let tx_ns = (wire * NANOS_PER_SEC).checked_div(self.link.bytes_per_sec).unwrap_or(0);
+
+        // Everyone else active can hear it, unless a partition is in the way
+        let isolated = self.nodes[node].isolated;
+        let active = self.nodes.iter().filter(|n| n.active).count() as u64;
+        let same_side = self.nodes.iter().filter(|n| n.active && n.isolated == isolated).count() as u64;
+        let second = ((now / NANOS_PER_SEC) as usize).min(self.timeline.len() - 1);
+
+        for _ in 0..batch {
+            self.nodes[node].sent += 1;
+            self.timeline[second].sent += 1;
+            self.timeline[second].expected_receptions += active - 1;
+
+            if tx_ns > 0 {
+                let backlog = self.channel_free_at.saturating_sub(now) * self.link.bytes_per_sec / NANOS_PER_SEC;
+                if backlog + wire > self.link.queue_bytes as u64 {
+                    continue;
+                }
+                self.channel_free_at = self.channel_free_at.max(now) + tx_ns;
+                self.busy_ns += tx_ns;
+            }
+            // Lost messages still took their airtime
+            if self.lost() {
+                continue;
+            }
+
+            self.nodes[node].delivered += 1;
+            self.timeline[second].delivered += 1;
+            self.timeline[second].receptions += same_side - 1;
+            let queued = self.channel_free_at.saturating_sub(now);
+            self.latencies_us.push((queued + self.link.latency.as_nanos() as u64) / 1000);
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use async_std::net::UdpSocket;
+use async_std::task;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::protocol;
+use crate::rng;
+use crate::soak::{self, LatencySummary};
+use crate::transport::{FleetMsgHeader, MessageType};
+
+mod engine;
+pub mod scenario;
+
+pub use scenario::Scenario;
+
+/// IPv4 and UDP headers carried by every datagram
+pub const UDP_IP_OVERHEAD: usize = 28;
+
+/// Sender id of the first virtual node; the rest follow consecutively
+pub const FIRST_SIM_SENDER_ID: u32 = 1;
+
+/// How long the loopback collector keeps listening after the last node stops
+const LOOPBACK_DRAIN: Duration = Duration::from_millis(500);
+
+/// How often each virtual node transmits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SendPattern {
+    /// One message every `1 / rate_hz` seconds
+    Periodic { rate_hz: f64 },
+    /// `messages` back to back, every `every`
+    Burst { messages: u32, every: Duration },
+}
+
+impl SendPattern {
+    /// `burst` messages back to back, `rate_hz` times a second
+    pub fn at_rate(rate_hz: f64, burst: u32) -> Self {
+        match burst {
+            1 => SendPattern::Periodic { rate_hz },
+            messages => SendPattern::Burst { messages, every: Duration::from_secs_f64(1.0 / rate_hz) },
+        }
+    }
+
+    fn period(&self) -> Duration {
+        match *self {
+            SendPattern::Periodic { rate_hz } => Duration::from_secs_f64(1.0 / rate_hz),
+            SendPattern::Burst { every, .. } => every,
+        }
+    }
+
+    fn batch(&self) -> u32 {
+        match *self {
+            SendPattern::Periodic { .. } => 1,
+            SendPattern::Burst { messages, .. } => messages,
+        }
+    }
+
+    /// Average messages per second from one node
+    pub fn messages_per_sec(&self) -> f64 {
+        self.batch() as f64 / self.period().as_secs_f64()
+    }
+
+    fn is_valid(&self) -> bool {
+        match *self {
+            SendPattern::Periodic { rate_hz } => rate_hz.is_finite() && rate_hz > 0.0,
+            SendPattern::Burst { messages, every } => messages > 0 && !every.is_zero(),
+        }
+    }
+}
+
+/// Where the virtual nodes' messages go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimTransport {
+    /// A modelled shared channel, simulated in virtual time (fast and repeatable)
+    InMemory,
+    /// Real UDP sockets on 127.0.0.1 sending to one collecting receiver, in real time
+    Loopback,
+}
+
+/// Nodes that share a send pattern and payload size
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeGroup {
+    pub name: String,
+    pub nodes: usize,
+    pub pattern: SendPattern,
+    pub payload_size: usize,
+}
+
+impl NodeGroup {
+    fn wire_bytes(&self, link: &LinkModel) -> usize {
+        protocol::HEADER_LEN + self.payload_size + link.per_packet_overhead
+    }
+}
+
+/// How the in-memory channel loses messages that made it onto the air
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum LossProfile {
+    #[default]
+    None,
+    /// Each message is lost independently with `probability`
+    Random { probability: f64 },
+    /// Gilbert-Elliott: the channel flips between a good and a bad state, each
+    /// with its own loss rate, so losses come in bursts
+    Bursty { good_loss: f64, bad_loss: f64, enter_bad: f64, leave_bad: f64 },
+}
+
+impl LossProfile {
+    fn is_valid(&self) -> bool {
+        let probability = |p: f64| (0.0..=1.0).contains(&p);
+        match *self {
+            LossProfile::None => true,
+            LossProfile::Random { probability: p } => probability(p),
+            LossProfile::Bursty { good_loss, bad_loss, enter_bad, leave_bad } => {
+                [good_loss, bad_loss, enter_bad, leave_bad].into_iter().all(probability)
+            }
+        }
+    }
+}
+
+/// Something that happens to the fleet part-way through a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FleetEvent {
+    /// `count` active nodes, from `group` or anywhere, go silent
+    Leave { group: Option<String>, count: usize },
+    /// `count` nodes join `group` (default: the first), reusing departed nodes before adding new ones
+    Join { group: Option<String>, count: usize },
+    /// Cut `group`, or a random `fraction` of the active nodes, off from everyone else
+    Partition { group: Option<String>, fraction: Option<f64> },
+    /// Reconnect every partitioned node
+    Heal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedEvent {
+    pub at: Duration,
+    pub event: FleetEvent,
+}
+
+/// The shared channel every in-memory message has to cross
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkModel {
+    bytes_per_sec: u64,
+    queue_bytes: usize,
+    latency: Duration,
+    per_packet_overhead: usize,
+}
+
+impl LinkModel {
+    /// A channel carrying `bytes_per_sec` for the whole fleet; 0 means unlimited
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            queue_bytes: 64 * 1024,
+            latency: Duration::ZERO,
+            per_packet_overhead: UDP_IP_OVERHEAD,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0)
+    }
+
+    /// Bytes that may be waiting for (or on) the channel before new messages are dropped
+    pub fn with_queue_bytes(mut self, queue_bytes: usize) -> Self {
+        self.queue_bytes = queue_bytes;
+        self
+    }
+
+    /// Propagation delay added to every delivery
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Bytes each datagram costs on top of header and payload (default: IPv4 + UDP)
+    pub fn with_per_packet_overhead(mut self, bytes: usize) -> Self {
+        self.per_packet_overhead = bytes;
+        self
+    }
+}
+
+/// A fleet of identical virtual nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    nodes: usize,
+    pattern: SendPattern,
+    payload_size: usize,
+    duration: Duration,
+    transport: SimTransport,
+    link: LinkModel,
+    loss: LossProfile,
+    seed: u64,
+}
+
+impl SimConfig {
+    pub fn new(nodes: usize, pattern: SendPattern) -> std::io::Result<Self> {
+        if nodes == 0 || nodes > u32::MAX as usize || !pattern.is_valid() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "simulation needs at least one node and a positive send rate",
+            ));
+        }
+
+        Ok(Self {
+            nodes,
+            pattern,
+            payload_size: 64,
+            duration: Duration::from_secs(60),
+            transport: SimTransport::InMemory,
+            link: LinkModel::unlimited(),
+            loss: LossProfile::None,
+            seed: rng::random_seed(),
+        })
+    }
+
+    /// Payload bytes per message; at least the send timestamp is always carried
+    pub fn with_payload_size(mut self, payload_size: usize) -> Self {
+        self.payload_size = payload_size.max(soak::SOAK_TIMESTAMP_LEN);
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn with_transport(mut self, transport: SimTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Channel model for [`SimTransport::InMemory`]; loopback runs ignore it
+    pub fn with_link(mut self, link: LinkModel) -> Self {
+        self.link = link;
+        self
+    }
+
+    /// Loss applied by the in-memory channel; loopback runs ignore it
+    pub fn with_loss(mut self, loss: LossProfile) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Seed for every random choice in the run, so it can be repeated exactly.
+    /// Without one a random seed is used and recorded in the report.
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The whole fleet as a single group
+    fn group(&self) -> NodeGroup {
+        NodeGroup {
+            name: "nodes".to_string(),
+            nodes: self.nodes,
+            pattern: self.pattern,
+            payload_size: self.payload_size,
+        }
+    }
+
+    /// Spread node start times evenly over the first period so they don't all fire at once
+    fn phase(&self, node: usize) -> Duration {
+        self.pattern.period().mul_f64(node as f64 / self.nodes as f64)
+    }
+}
+
+/// Aggregate result of a simulation run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SimReport {
+    pub scenario: Option<String>,
+    pub seed: u64,
+    /// Every node that took part, including ones that joined later
+    pub nodes: usize,
+    pub duration_secs: f64,
+    pub messages_sent: u64,
+    pub messages_delivered: u64,
+    pub messages_dropped: u64,
+    /// Bytes per second the fleet tried to put on the channel, per-packet overhead included
+    pub offered_load: f64,
+    /// Fraction of the run the channel spent transmitting; only known for a limited in-memory link
+    pub channel_utilization: Option<f64>,
+    pub latency_us: LatencySummary,
+    /// Copies of delivered messages that reached another active node on the same side of any partition
+    pub receptions: u64,
+    /// Copies every other active node would have received on a loss-free, unpartitioned link
+    pub expected_receptions: u64,
+    pub per_node: Vec<NodeReport>,
+    /// Second-by-second counts (in-memory runs only)
+    pub timeline: Vec<SimInterval>,
+    /// Scripted events as they were applied
+    pub events: Vec<EventRecord>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeReport {
+    pub sender_id: u32,
+    pub group: String,
+    pub sent: u64,
+    pub delivered: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimInterval {
+    /// Active nodes at the start of the second
+    pub active_nodes: usize,
+    pub sent: u64,
+    pub delivered: u64,
+    pub receptions: u64,
+    pub expected_receptions: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub at_secs: f64,
+    #[serde(flatten)]
+    pub event: FleetEvent,
+    /// Nodes the event applied to
+    pub affected: usize,
+}
+
+impl SimReport {
+    pub fn delivery_ratio(&self) -> f64 {
+        if self.messages_sent == 0 {
+            return 1.0;
+        }
+        self.messages_delivered as f64 / self.messages_sent as f64
+    }
+
+    /// Totals from the per-node counts; offered load is for the fleet as configured at the start
+    fn summarize(
+        groups: &[NodeGroup],
+        link: &LinkModel,
+        duration: Duration,
+        per_node: Vec<NodeReport>,
+        mut latencies_us: Vec<u64>,
+    ) -> Self {
+        let messages_sent = per_node.iter().map(|n| n.sent).sum();
+        let messages_delivered = per_node.iter().map(|n| n.delivered).sum();
+        Self {
+            nodes: per_node.len(),
+            duration_secs: duration.as_secs_f64(),
+            messages_sent,
+            messages_delivered,
+            messages_dropped: messages_sent - messages_delivered,
+            offered_load: groups.iter()
+                .map(|g| g.nodes as f64 * g.pattern.messages_per_sec() * g.wire_bytes(link) as f64)
+                .sum(),
+            latency_us: LatencySummary::from_samples(&mut latencies_us),
+            per_node,
+            ..Self::default()
+        }
+    }
+}
+
+/// Run the simulation on the configured transport
+pub async fn run(config: &SimConfig) -> std::io::Result<SimReport> {
+    match config.transport {
+        SimTransport::InMemory => Ok(run_in_memory(config)),
+        SimTransport::Loopback => run_loopback(config).await,
+    }
+}
+
+/// Push every node's messages through the modelled channel, in virtual time.
+///
+/// The channel sends one message at a time in arrival order; a message that
+/// would overflow the queue is dropped.
+pub fn run_in_memory(config: &SimConfig) -> SimReport {
+    engine::simulate(&[config.group()], &config.link, config.loss, &[], config.duration, config.seed)
+}
+
+/// Send from one UDP socket per node to a single receiver on 127.0.0.1, in real time
+pub async fn run_loopback(config: &SimConfig) -> std::io::Result<SimReport> {
+    let collector = UdpSocket::bind("127.0.0.1:0").await?;
+    let target = collector.local_addr()?;
+    let start = Instant::now();
+
+    let receiver = task::spawn(collect(collector, start + config.duration + LOOPBACK_DRAIN));
+    let nodes: Vec<_> = (0..config.nodes)
+        .map(|node| {
+            let sender_id = FIRST_SIM_SENDER_ID + node as u32;
+            task::spawn(run_node(sender_id, config.clone(), start + config.phase(node), start + config.duration, target))
+        })
+        .collect();
+
+    let mut sent = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        sent.push(node.await?);
+    }
+    let (delivered, latencies_us) = receiver.await;
+
+    let per_node = sent.into_iter().enumerate()
+        .map(|(node, sent)| {
+            let sender_id = FIRST_SIM_SENDER_ID + node as u32;
+            NodeReport {
+                sender_id,
+                group: config.group().name,
+                sent,
+                delivered: delivered.get(&sender_id).copied().unwrap_or(0).min(sent),
+            }
+        })
+        .collect();
+
+    // The collector is the only receiver
+    let mut report = SimReport::summarize(&[config.group()], &config.link, config.duration, per_node, latencies_us);
+    report.seed = config.seed;
+    report.receptions = report.messages_delivered;
+    report.expected_receptions = report.messages_sent;
+    Ok(report)
+}
+
+async fn run_node(sender_id: u32, config: SimConfig, first: Instant, end: Instant, target: SocketAddr) -> std::io::Result<u64> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let period = config.pattern.period();
+    let mut sequence = 0u16;
+    let mut sent = 0u64;
+
+    let mut due = first;
+    while due < end {
+        let now = Instant::now();
+        if due > now {
+            task::sleep(due - now).await;
+        }
+        for _ in 0..config.pattern.batch() {
+            let payload = soak::soak_payload(config.payload_size);
+            let header = FleetMsgHeader::new(MessageType::Data, sender_id, sequence, payload.len() as u16);
+            let mut message = header.as_bytes().to_vec();
+            message.extend_from_slice(&payload);
+
+            // A failed send (e.g. ENOBUFS) is a drop like any other
+            let _ = socket.send_to(&message, target).await;
+            sequence = sequence.wrapping_add(1);
+            sent += 1;
+        }
+        due += period;
+    }
+    Ok(sent)
+}
+
+async fn collect(socket: UdpSocket, deadline: Instant) -> (BTreeMap<u32, u64>, Vec<u64>) {
+    let mut delivered = BTreeMap::new();
+    let mut latencies_us = Vec::new();
+    let mut buf = vec![0u8; 65536];
+    let header_size = protocol::HEADER_LEN;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Ok(received) = async_std::future::timeout(remaining, socket.recv_from(&mut buf)).await else {
+            break;
+        };
+        let Ok((len, _)) = received else {
+            continue;
+        };
+        let Some(header) = FleetMsgHeader::read_from_prefix(&buf[..len]) else {
+            continue;
+        };
+        if !header.is_valid() {
+            continue;
+        }
+        *delivered.entry(header.sender_id.get()).or_insert(0) += 1;
+        if let Some(sent_us) = soak::soak_timestamp(&buf[header_size..len]) {
+            latencies_us.push(soak::now_micros().saturating_sub(sent_us));
+        }
+    }
+    (delivered, latencies_us)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_link_delivers_everything() {
+        let config = SimConfig::new(10, SendPattern::Periodic { rate_hz: 10.0 }).unwrap()
+            .with_duration(Duration::from_secs(1))
+            .with_link(LinkModel::unlimited().with_latency(Duration::from_millis(2)));
+
+        let report = run_in_memory(&config);
+        assert_eq!(report.messages_sent, 100);
+        assert_eq!(report.messages_dropped, 0);
+        assert_eq!(report.per_node[9].sender_id, 10);
+        assert_eq!(report.latency_us.max, 2000);
+        assert_eq!(report.channel_utilization, None);
+        assert!(SimConfig::new(0, SendPattern::Periodic { rate_hz: 1.0 }).is_err());
+    }
+
+    #[test]
+    fn test_seed_makes_lossy_runs_repeatable() {
+        let run = |seed| {
+            let config = SimConfig::new(20, SendPattern::Periodic { rate_hz: 50.0 }).unwrap()
+                .with_duration(Duration::from_secs(2))
+                .with_loss(LossProfile::Random { probability: 0.1 })
+                .with_rng_seed(seed);
+            run_in_memory(&config)
+        };
+
+        let report = run(9);
+        assert_eq!(report.seed, 9);
+        assert!(report.messages_dropped > 0);
+        assert_eq!(run(9), report);
+        assert_ne!(run(10).per_node, report.per_node);
+    }
+
+    #[test]
+    fn test_saturated_link_queues_then_drops() {
+        // 300 nodes at 10 Hz offer ~350 kB/s of 116-byte datagrams to a 250 kB/s channel
+        let config = SimConfig::new(300, SendPattern::Periodic { rate_hz: 10.0 }).unwrap()
+            .with_duration(Duration::from_secs(10))
+            .with_link(LinkModel::new(250_000).with_queue_bytes(16 * 1024));
+
+        let report = run_in_memory(&config);
+        assert_eq!(report.offered_load, 300.0 * 10.0 * 116.0);
+        assert!(report.messages_dropped > 0);
+        assert!(report.delivery_ratio() < 0.75);
+        assert!(report.channel_utilization.unwrap() > 0.95);
+        // Nothing waits longer than a full queue takes to drain
+        assert!(report.latency_us.max <= 16 * 1024 * 1_000_000 / 250_000 + 1000);
+    }
+
+    #[async_std::test]
+    async fn test_loopback_run_counts_per_node() {
+        let config = SimConfig::new(4, SendPattern::Burst { messages: 2, every: Duration::from_millis(100) }).unwrap()
+            .with_duration(Duration::from_millis(300))
+            .with_transport(SimTransport::Loopback);
+
+        let report = run(&config).await.unwrap();
+        assert_eq!(report.messages_sent, 4 * 3 * 2);
+        assert!(report.messages_delivered > 0);
+        assert!(report.per_node.iter().all(|node| node.delivered <= node.sent));
+    }
+}
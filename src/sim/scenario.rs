@@ -0,0 +1,258 @@
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+
+use super::{engine, FleetEvent, LinkModel, LossProfile, NodeGroup, SendPattern, SimReport, TimedEvent};
+
+/// A scripted simulation: node groups, channel, loss and timed events.
+///
+/// Runs on the in-memory transport, so the same file and seed always
+/// produce the same report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: Option<String>,
+    pub seed: u64,
+    pub duration: Duration,
+    pub link: LinkModel,
+    pub loss: LossProfile,
+    pub groups: Vec<NodeGroup>,
+    /// Sorted by time
+    pub events: Vec<TimedEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    name: Option<String>,
+    #[serde(default)]
+    seed: u64,
+    duration_secs: f64,
+    #[serde(default)]
+    link: LinkFile,
+    #[serde(default)]
+    loss: LossProfile,
+    groups: Vec<GroupFile>,
+    #[serde(default)]
+    events: Vec<EventFile>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LinkFile {
+    bytes_per_sec: u64,
+    queue_bytes: Option<usize>,
+    latency_ms: f64,
+    per_packet_overhead: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupFile {
+    name: String,
+    nodes: usize,
+    rate_hz: f64,
+    #[serde(default = "GroupFile::default_burst")]
+    burst: u32,
+    #[serde(default = "GroupFile::default_payload_size")]
+    payload_size: usize,
+}
+
+impl GroupFile {
+    fn default_burst() -> u32 {
+        1
+    }
+
+    fn default_payload_size() -> usize {
+        64
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EventFile {
+    at_secs: f64,
+    #[serde(flatten)]
+    event: FleetEvent,
+}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Self::from_toml(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn from_toml(text: &str) -> std::io::Result<Self> {
+        let file: ScenarioFile = toml::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let invalid = |msg: String| Err(Error::new(ErrorKind::InvalidInput, msg));
+
+        if !(file.duration_secs.is_finite() && file.duration_secs > 0.0) {
+            return invalid("duration_secs must be positive".to_string());
+        }
+        if !file.loss.is_valid() {
+            return invalid("loss probabilities must be between 0 and 1".to_string());
+        }
+        if file.groups.is_empty() {
+            return invalid("a scenario needs at least one group".to_string());
+        }
+
+        let mut groups = Vec::with_capacity(file.groups.len());
+        for group in file.groups {
+            let pattern = SendPattern::at_rate(group.rate_hz, group.burst);
+            if !pattern.is_valid() {
+                return invalid(format!("group '{}' needs a positive rate_hz and burst", group.name));
+            }
+            if groups.iter().any(|g: &NodeGroup| g.name == group.name) {
+                return invalid(format!("group '{}' is defined twice", group.name));
+            }
+            groups.push(NodeGroup { name: group.name, nodes: group.nodes, pattern, payload_size: group.payload_size });
+        }
+
+        let mut events = Vec::with_capacity(file.events.len());
+        for event in file.events {
+            if !(event.at_secs.is_finite() && event.at_secs >= 0.0) {
+                return invalid(format!("event time {} is not a valid offset", event.at_secs));
+            }
+            let group = match &event.event {
+                FleetEvent::Leave { group, .. } | FleetEvent::Join { group, .. } => group,
+                FleetEvent::Partition { group, fraction } => {
+                    if group.is_none() && fraction.is_none() {
+                        return invalid(format!("partition at {}s needs a group or a fraction", event.at_secs));
+                    }
+                    if fraction.is_some_and(|f| !(0.0..=1.0).contains(&f)) {
+                        return invalid(format!("partition at {}s has a fraction outside 0..=1", event.at_secs));
+                    }
+                    group
+                }
+                FleetEvent::Heal => &None,
+            };
+            if let Some(name) = group
+                && !groups.iter().any(|g| &g.name == name)
+            {
+                return invalid(format!("event at {}s names unknown group '{}'", event.at_secs, name));
+            }
+            events.push(TimedEvent { at: Duration::from_secs_f64(event.at_secs), event: event.event });
+        }
+        events.sort_by_key(|event| event.at);
+
+        let mut link = LinkModel::new(file.link.bytes_per_sec)
+            .with_latency(Duration::from_secs_f64(file.link.latency_ms.max(0.0) / 1000.0));
+        if let Some(queue_bytes) = file.link.queue_bytes {
+            link = link.with_queue_bytes(queue_bytes);
+        }
+        if let Some(overhead) = file.link.per_packet_overhead {
+            link = link.with_per_packet_overhead(overhead);
+        }
+
+        Ok(Self {
+            name: file.name,
+            seed: file.seed,
+            duration: Duration::from_secs_f64(file.duration_secs),
+            link,
+            loss: file.loss,
+            groups,
+            events,
+        })
+    }
+
+    pub fn run(&self) -> SimReport {
+        let mut report = engine::simulate(&self.groups, &self.link, self.loss, &self.events, self.duration, self.seed);
+        report.scenario = self.name.clone();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+        name = "churn and partition"
+        seed = 7
+        duration_secs = 10
+
+        [link]
+        bytes_per_sec = 1000000
+        latency_ms = 2
+
+        [loss]
+        model = "bursty"
+        good_loss = 0.0
+        bad_loss = 0.5
+        enter_bad = 0.01
+        leave_bad = 0.2
+
+        [[groups]]
+        name = "vehicles"
+        nodes = 50
+        rate_hz = 10
+
+        [[groups]]
+        name = "base"
+        nodes = 2
+        rate_hz = 1
+        payload_size = 512
+
+        [[events]]
+        at_secs = 6
+        action = "heal"
+
+        [[events]]
+        at_secs = 2
+        action = "leave"
+        group = "vehicles"
+        count = 10
+
+        [[events]]
+        at_secs = 4
+        action = "partition"
+        group = "base"
+
+        [[events]]
+        at_secs = 8
+        action = "join"
+        group = "vehicles"
+        count = 15
+    "#;
+
+    #[test]
+    fn test_scenario_runs_events_in_order() {
+        let scenario = Scenario::from_toml(SCENARIO).unwrap();
+        assert_eq!(scenario.events[0].at, Duration::from_secs(2));
+
+        let report = scenario.run();
+        assert_eq!(report.scenario.as_deref(), Some("churn and partition"));
+        assert_eq!(report.timeline.len(), 10);
+        assert_eq!(report.timeline[1].active_nodes, 52);
+        assert_eq!(report.timeline[3].active_nodes, 42);
+        assert_eq!(report.timeline[9].active_nodes, 57);
+        // 10 returning nodes plus 5 new ones
+        assert_eq!(report.nodes, 57);
+        assert_eq!(report.events.iter().map(|e| e.affected).collect::<Vec<_>>(), vec![10, 2, 2, 15]);
+
+        // While the base stations are cut off nobody hears them, and they hear nobody
+        let partitioned = &report.timeline[5];
+        assert!(partitioned.receptions < partitioned.expected_receptions);
+        assert!(report.messages_dropped > 0);
+
+        // Same file, same seed: same report
+        assert_eq!(Scenario::from_toml(SCENARIO).unwrap().run(), report);
+    }
+
+    #[test]
+    fn test_shipped_scenarios_load() {
+        for entry in std::fs::read_dir("scenarios").unwrap() {
+            let path = entry.unwrap().path();
+            assert!(Scenario::load(&path).is_ok(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_scenarios() {
+        let unknown = SCENARIO.replace("group = \"base\"", "group = \"nowhere\"");
+        assert_eq!(Scenario::from_toml(&unknown).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let typo = SCENARIO.replace("rate_hz = 1\n", "rate = 1\n");
+        assert_eq!(Scenario::from_toml(&typo).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes at the start of every soak payload holding the send time
+pub const SOAK_TIMESTAMP_LEN: usize = 8;
+
+/// Result of one soak run, written as JSON by the `soak_benchmark` binary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoakReport {
+    pub target_rate: f64,
+    pub payload_size: usize,
+    pub duration_secs: f64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub messages_lost: u64,
+    pub achieved_send_rate: f64,
+    pub achieved_receive_rate: f64,
+    /// Receive-buffer overflows reported by the kernel, where it exposes them
+    pub kernel_drops: Option<u64>,
+    pub latency_us: LatencySummary,
+    /// Received messages per second of the run, for plotting
+    pub per_second: Vec<u64>,
+    /// Flamegraph of the send and receive paths, when run with `--profile`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flamegraph: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub min: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl LatencySummary {
+    pub fn from_samples(samples: &mut [u64]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+        Self {
+            samples: samples.len(),
+            min: samples[0],
+            mean: samples.iter().sum::<u64>() as f64 / samples.len() as f64,
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+/// Microseconds since the Unix epoch, as stamped into soak payloads
+pub fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+/// Build a payload of `size` bytes (at least the timestamp) carrying the current time
+pub fn soak_payload(size: usize) -> Vec<u8> {
+    let mut payload = vec![0u8; size.max(SOAK_TIMESTAMP_LEN)];
+    payload[..SOAK_TIMESTAMP_LEN].copy_from_slice(&now_micros().to_le_bytes());
+    payload
+}
+
+/// Read back the send time stamped by [`soak_payload`]
+pub fn soak_timestamp(payload: &[u8]) -> Option<u64> {
+    let bytes = payload.get(..SOAK_TIMESTAMP_LEN)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Sum the `drops` column of `/proc/net/udp` for every socket bound to `port`
+pub fn kernel_udp_drops(port: u16) -> Option<u64> {
+    let table = std::fs::read_to_string("/proc/net/udp").ok()?;
+    parse_udp_drops(&table, port)
+}
+
+fn parse_udp_drops(table: &str, port: u16) -> Option<u64> {
+    let wanted = format!("{:04X}", port);
+    let mut found = false;
+    let mut drops = 0;
+
+    for line in table.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(local) = fields.get(1) else { continue };
+        if local.rsplit(':').next() != Some(wanted.as_str()) {
+            continue;
+        }
+        if let Some(count) = fields.last().and_then(|d| d.parse::<u64>().ok()) {
+            drops += count;
+            found = true;
+        }
+    }
+
+    found.then_some(drops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut samples: Vec<u64> = (1..=1000).rev().collect();
+        let summary = LatencySummary::from_samples(&mut samples);
+
+        assert_eq!(summary.samples, 1000);
+        assert_eq!(summary.min, 1);
+        assert_eq!(summary.max, 1000);
+        assert_eq!(summary.p50, 501);
+        assert_eq!(summary.p99, 990);
+        assert_eq!(LatencySummary::from_samples(&mut []), LatencySummary::default());
+    }
+
+    #[test]
+    fn test_payload_carries_timestamp() {
+        let payload = soak_payload(64);
+        assert_eq!(payload.len(), 64);
+        assert!(soak_timestamp(&payload).unwrap() <= now_micros());
+        assert_eq!(soak_payload(0).len(), SOAK_TIMESTAMP_LEN);
+        assert_eq!(soak_timestamp(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn test_parse_udp_drops() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+            \x20 12: 00000000:3039 00000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 4242 2 0000000000000000 17\n\
+            \x20 13: 00000000:3039 00000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 4243 2 0000000000000000 3\n\
+            \x20 14: 0100007F:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 99 2 0000000000000000 5\n";
+
+        assert_eq!(parse_udp_drops(table, 12345), Some(20));
+        assert_eq!(parse_udp_drops(table, 53), Some(5));
+        assert_eq!(parse_udp_drops(table, 9), None);
+    }
+}
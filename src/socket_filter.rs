@@ -0,0 +1,432 @@
+//! Dropping unwanted datagrams in the kernel, before they are copied to the
+//! receiver or wake it up.
+//!
+//! [`SocketFilter::for_config`] compiles what a [`ReceiverConfig`] would drop
+//! anyway into a classic BPF program: datagrams too short for a header, with
+//! the wrong magic or an unsupported version (unless the validation policy is
+//! [`Lenient`](ValidationPolicy::Lenient) or
+//! [`Promiscuous`](ValidationPolicy::Promiscuous)), and those its
+//! [`ReceiverFilter`](crate::receiver::ReceiverFilter) turns away by type or
+//! sender. The kernel runs it as eBPF, but attaching it with
+//! `SO_ATTACH_FILTER` needs neither `CAP_BPF` nor a BPF toolchain, so any
+//! receiver can use it. A filter's predicate can't be compiled; it keeps
+//! running in userspace, as does the rest of the filter.
+//!
+//! Receivers attach it themselves when the config asks for it with
+//! [`ReceiverConfig::with_kernel_filter`]. Datagrams the kernel drops never
+//! show up in stats, taps or logs.
+//!
+//! With the `ebpf` feature, a receiver can load an eBPF program instead:
+//! `EbpfFilter` loads `c/fleetlink_filter.c`, built with clang, through aya
+//! and attaches it with `SO_ATTACH_BPF`. Rather than being compiled per
+//! config, the program reads the magic, versions, wanted types and one block
+//! of sender ids from a map, so it can be built once. It needs `CAP_BPF`, and
+//! covers less of the filter: sender lists and denied senders stay in
+//! userspace.
+
+use std::io;
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+use std::path::Path;
+
+use crate::protocol;
+use crate::receiver::{ReceiverConfig, ValidationPolicy};
+
+/// Longest program the kernel loads (`BPF_MAXINSNS`)
+pub const MAX_INSTRUCTIONS: usize = 4096;
+
+/// Where the FleetLink header starts: a UDP socket filter sees the UDP header first
+const UDP_HEADER_LEN: u32 = 8;
+const MAGIC_AT: u32 = UDP_HEADER_LEN;
+const VERSION_AT: u32 = UDP_HEADER_LEN + 4;
+const MSG_TYPE_AT: u32 = UDP_HEADER_LEN + 5;
+const SENDER_ID_AT: u32 = UDP_HEADER_LEN + 16;
+
+// Opcodes from linux/filter.h, for the instructions compiled here
+const LD_W_ABS: u16 = 0x20;
+const LD_B_ABS: u16 = 0x30;
+const LD_W_LEN: u16 = 0x80;
+const ALU_AND_K: u16 = 0x54;
+const ALU_OR_X: u16 = 0x4c;
+const ALU_LSH_K: u16 = 0x64;
+const MISC_TAX: u16 = 0x07;
+const JMP_JA: u16 = 0x05;
+const JMP_JEQ_K: u16 = 0x15;
+const JMP_JGT_K: u16 = 0x25;
+const JMP_JGE_K: u16 = 0x35;
+const RET_K: u16 = 0x06;
+
+const DROP: u32 = 0;
+const ACCEPT: u32 = u32::MAX;
+
+/// One classic BPF instruction, laid out as the kernel's `struct sock_filter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Instruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl Instruction {
+    const fn statement(code: u16, k: u32) -> Self {
+        Self { code, jt: 0, jf: 0, k }
+    }
+
+    const fn jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+/// A compiled socket filter for a receiver's socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketFilter {
+    program: Vec<Instruction>,
+}
+
+impl SocketFilter {
+    /// Compile what `config` drops by header into a program; fails if its
+    /// sender lists are too long for the kernel to load
+    pub fn for_config(config: &ReceiverConfig) -> io::Result<Self> {
+        let validating = matches!(config.validation(), ValidationPolicy::Strict | ValidationPolicy::Standard);
+        let mut program = vec![
+            Instruction::statement(LD_W_LEN, 0),
+            Instruction::jump(JMP_JGE_K, UDP_HEADER_LEN + protocol::HEADER_LEN as u32, 1, 0),
+            // Too short for a header: only validation would complain about it
+            Instruction::statement(RET_K, if validating { DROP } else { ACCEPT }),
+        ];
+        if validating {
+            // Loads are big-endian, the header is little-endian
+            program.extend([
+                Instruction::statement(LD_W_ABS, MAGIC_AT),
+                Instruction::jump(JMP_JEQ_K, protocol::MAGIC.swap_bytes(), 1, 0),
+                Instruction::statement(RET_K, DROP),
+                Instruction::statement(LD_B_ABS, VERSION_AT),
+                Instruction::jump(JMP_JGE_K, protocol::MIN_VERSION as u32, 1, 0),
+                Instruction::statement(RET_K, DROP),
+                Instruction::jump(JMP_JGT_K, protocol::MAX_VERSION as u32, 0, 1),
+                Instruction::statement(RET_K, DROP),
+            ]);
+        }
+        if let Some(filter) = config.filter() {
+            if let Some(types) = &filter.types {
+                let mut codes: Vec<u32> = types.iter().map(|msg_type| msg_type.code() as u32).collect();
+                codes.sort_unstable();
+                codes.dedup();
+                program.push(Instruction::statement(LD_B_ABS, MSG_TYPE_AT));
+                program.push(Instruction::statement(ALU_AND_K, protocol::MSG_TYPE_MASK as u32));
+                // At most 16 codes fit the mask, so the jumps past the drop stay short
+                for (i, code) in codes.iter().enumerate() {
+                    program.push(Instruction::jump(JMP_JEQ_K, *code, (codes.len() - i) as u8, 0));
+                }
+                program.push(Instruction::statement(RET_K, DROP));
+            }
+            let allowing = filter.allowed_senders.is_some() || !filter.allowed_ranges.is_empty();
+            if allowing || !filter.denied_senders.is_empty() {
+                load_sender_id(&mut program);
+                for sender_id in &filter.denied_senders {
+                    program.push(Instruction::jump(JMP_JEQ_K, *sender_id, 0, 1));
+                    program.push(Instruction::statement(RET_K, DROP));
+                }
+            }
+            if allowing {
+                // Jumps to the accept at the end, patched once its place is known
+                let mut to_accept = Vec::new();
+                for sender_id in filter.allowed_senders.iter().flatten() {
+                    program.push(Instruction::jump(JMP_JEQ_K, *sender_id, 0, 1));
+                    to_accept.push(program.len());
+                    program.push(Instruction::statement(JMP_JA, 0));
+                }
+                for range in &filter.allowed_ranges {
+                    program.push(Instruction::jump(JMP_JGE_K, *range.start(), 0, 2));
+                    program.push(Instruction::jump(JMP_JGT_K, *range.end(), 1, 0));
+                    to_accept.push(program.len());
+                    program.push(Instruction::statement(JMP_JA, 0));
+                }
+                program.push(Instruction::statement(RET_K, DROP));
+                for at in to_accept {
+                    program[at].k = (program.len() - at - 1) as u32;
+                }
+            }
+        }
+        program.push(Instruction::statement(RET_K, ACCEPT));
+        if program.len() > MAX_INSTRUCTIONS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("socket filter of {} instructions is over the kernel's {}", program.len(), MAX_INSTRUCTIONS)
+            ));
+        }
+        Ok(Self { program })
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.program
+    }
+
+    /// Whether the kernel would let `payload` through, run here as it would
+    /// run on the datagram; for tests and for checking a config without a socket
+    pub fn accepts(&self, payload: &[u8]) -> bool {
+        let mut datagram = vec![0u8; UDP_HEADER_LEN as usize];
+        datagram.extend_from_slice(payload);
+        self.run(&datagram) != DROP
+    }
+
+    /// Interpret the program over `packet`, which starts with the UDP header;
+    /// like the kernel, a load past the end drops the packet
+    fn run(&self, packet: &[u8]) -> u32 {
+        let (mut a, mut x, mut pc) = (0u32, 0u32, 0usize);
+        while let Some(instruction) = self.program.get(pc) {
+            let k = instruction.k;
+            pc += 1;
+            match instruction.code {
+                LD_W_LEN => a = packet.len() as u32,
+                LD_W_ABS => match packet.get(k as usize..k as usize + 4) {
+                    Some(word) => a = u32::from_be_bytes(word.try_into().unwrap()),
+                    None => return DROP,
+                },
+                LD_B_ABS => match packet.get(k as usize) {
+                    Some(byte) => a = *byte as u32,
+                    None => return DROP,
+                },
+                ALU_AND_K => a &= k,
+                ALU_OR_X => a |= x,
+                ALU_LSH_K => a <<= k,
+                MISC_TAX => x = a,
+                JMP_JA => pc += k as usize,
+                JMP_JEQ_K | JMP_JGT_K | JMP_JGE_K => {
+                    let taken = match instruction.code {
+                        JMP_JEQ_K => a == k,
+                        JMP_JGT_K => a > k,
+                        _ => a >= k,
+                    };
+                    pc += if taken { instruction.jt } else { instruction.jf } as usize;
+                }
+                RET_K => return k,
+                code => unreachable!("socket filters don't compile opcode {:#x}", code),
+            }
+        }
+        DROP
+    }
+
+    /// Attach the program to `socket`, replacing any filter it had
+    #[cfg(target_os = "linux")]
+    pub fn attach(&self, socket: &impl std::os::fd::AsRawFd) -> io::Result<()> {
+        let program = libc::sock_fprog { len: self.program.len() as u16, filter: self.program.as_ptr() as *mut libc::sock_filter };
+        // SAFETY: Instruction has sock_filter's layout, and the kernel copies
+        // the program during the call, while `self.program` is borrowed
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &program as *const libc::sock_fprog as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn attach<S>(&self, _socket: &S) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "socket filters are only attached on Linux"))
+    }
+}
+
+/// What the eBPF program checks, laid out as `struct fleetlink_settings` in
+/// `c/fleetlink_filter.c`
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct FilterSettings {
+    magic: u32,
+    min_sender: u32,
+    max_sender: u32,
+    /// Bit n set: message type code n is wanted
+    types: u16,
+    validate: u8,
+    min_version: u8,
+    max_version: u8,
+    pad: [u8; 3],
+}
+
+// SAFETY: plain integers with no padding the compiler adds, so every bit pattern is valid
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+unsafe impl aya::Pod for FilterSettings {}
+
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+impl FilterSettings {
+    fn for_config(config: &ReceiverConfig) -> Self {
+        let validating = matches!(config.validation(), ValidationPolicy::Strict | ValidationPolicy::Standard);
+        let mut settings = Self {
+            magic: protocol::MAGIC,
+            min_sender: 0,
+            max_sender: u32::MAX,
+            types: u16::MAX,
+            validate: validating as u8,
+            min_version: protocol::MIN_VERSION,
+            max_version: protocol::MAX_VERSION,
+            pad: [0; 3],
+        };
+        if let Some(filter) = config.filter() {
+            if let Some(types) = &filter.types {
+                settings.types = types.iter().fold(0, |mask, msg_type| mask | 1 << (msg_type.code() & protocol::MSG_TYPE_MASK));
+            }
+            // A single block fits the map; anything more is left to userspace
+            if let (None, [range]) = (&filter.allowed_senders, filter.allowed_ranges.as_slice()) {
+                (settings.min_sender, settings.max_sender) = (*range.start(), *range.end());
+            }
+        }
+        settings
+    }
+}
+
+/// The eBPF socket filter in `c/fleetlink_filter.c`, set up for one receiver
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+#[derive(Debug)]
+pub struct EbpfFilter {
+    ebpf: aya::Ebpf,
+}
+
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+impl EbpfFilter {
+    /// Load the object built from `c/fleetlink_filter.c` at `path` and set it
+    /// to drop what `config` would by header
+    pub fn load(path: impl AsRef<Path>, config: &ReceiverConfig) -> io::Result<Self> {
+        let mut ebpf = aya::Ebpf::load_file(path).map_err(io::Error::other)?;
+        let map = ebpf.map_mut("fleetlink_settings")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no fleetlink_settings map in the eBPF object"))?;
+        let mut settings: aya::maps::Array<_, FilterSettings> = aya::maps::Array::try_from(map).map_err(io::Error::other)?;
+        settings.set(0, FilterSettings::for_config(config), 0).map_err(io::Error::other)?;
+        let program: &mut aya::programs::SocketFilter = ebpf.program_mut("fleetlink_filter")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no fleetlink_filter program in the eBPF object"))?
+            .try_into()
+            .map_err(io::Error::other)?;
+        program.load().map_err(io::Error::other)?;
+        Ok(Self { ebpf })
+    }
+
+    /// Attach the program to `socket`, replacing any filter it had. The
+    /// socket keeps it after this `EbpfFilter` is dropped.
+    pub fn attach(&self, socket: &impl std::os::fd::AsRawFd) -> io::Result<()> {
+        let program: &aya::programs::SocketFilter = self.ebpf.program("fleetlink_filter")
+            .expect("checked when loaded")
+            .try_into()
+            .map_err(io::Error::other)?;
+        // SAFETY: the descriptor stays open while `socket` is borrowed, which outlasts the call
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(socket.as_raw_fd()) };
+        program.attach(fd).map_err(io::Error::other)
+    }
+}
+
+/// Put the header's sender id in A, assembled from its little-endian bytes
+fn load_sender_id(program: &mut Vec<Instruction>) {
+    program.extend([
+        Instruction::statement(LD_B_ABS, SENDER_ID_AT + 3),
+        Instruction::statement(ALU_LSH_K, 24),
+        Instruction::statement(MISC_TAX, 0),
+    ]);
+    for (byte, shift) in [(2, 16), (1, 8)] {
+        program.extend([
+            Instruction::statement(LD_B_ABS, SENDER_ID_AT + byte),
+            Instruction::statement(ALU_LSH_K, shift),
+            Instruction::statement(ALU_OR_X, 0),
+            Instruction::statement(MISC_TAX, 0),
+        ]);
+    }
+    program.extend([Instruction::statement(LD_B_ABS, SENDER_ID_AT), Instruction::statement(ALU_OR_X, 0)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::FleetMessage;
+    use crate::receiver::ReceiverFilter;
+    use crate::transport::{FleetMsgHeader, MessageType};
+
+    fn message(msg_type: MessageType, sender_id: u32) -> Vec<u8> {
+        FleetMessage::new(msg_type, sender_id, 1, b"reading".to_vec()).to_bytes()
+    }
+
+    #[test]
+    fn test_non_fleet_traffic_is_dropped_unless_lenient() {
+        let strict = SocketFilter::for_config(&ReceiverConfig::new()).unwrap();
+        let mut wrong_magic = message(MessageType::Data, 7);
+        wrong_magic[0] ^= 0xFF;
+        let mut future_version = message(MessageType::Data, 7);
+        future_version[4] = protocol::MAX_VERSION + 1;
+
+        assert!(strict.accepts(&message(MessageType::Data, 7)));
+        assert!(!strict.accepts(b"M-SEARCH * HTTP/1.1"));
+        assert!(!strict.accepts(&wrong_magic));
+        assert!(!strict.accepts(&future_version));
+
+        let lenient = SocketFilter::for_config(&ReceiverConfig::new().with_validation(ValidationPolicy::Lenient)).unwrap();
+        assert!(lenient.accepts(b"M-SEARCH * HTTP/1.1"));
+        assert!(lenient.accepts(&wrong_magic));
+    }
+
+    #[test]
+    fn test_compiled_filter_matches_the_receiver_filter() {
+        let filter = ReceiverFilter::new()
+            .with_message_types([MessageType::Data, MessageType::Control])
+            .with_allowed_senders([5, 0x0102_0304])
+            .with_allowed_sender_range(0x3B00..=0x3BFF)
+            .with_denied_senders([0x3B13]);
+        let compiled = SocketFilter::for_config(&ReceiverConfig::new().with_filter(filter.clone())).unwrap();
+
+        for msg_type in [MessageType::Heartbeat, MessageType::Data, MessageType::Control, MessageType::Goodbye] {
+            for sender_id in [4, 5, 0x0102_0304, 0x0403_0201, 0x3AFF, 0x3B00, 0x3B13, 0x3BFF, 0x3C00] {
+                let datagram = message(msg_type, sender_id);
+                let header = FleetMsgHeader::parse(&datagram).unwrap();
+                assert_eq!(compiled.accepts(&datagram), filter.accepts(&header), "{:?} from {:#x}", msg_type, sender_id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversized_allow_lists_are_refused() {
+        let filter = ReceiverFilter::new().with_allowed_senders(0..MAX_INSTRUCTIONS as u32);
+        assert!(SocketFilter::for_config(&ReceiverConfig::new().with_filter(filter)).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_attached_filter_drops_in_the_kernel() {
+        use std::net::UdpSocket;
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(std::time::Duration::from_millis(500))).unwrap();
+        let filter = ReceiverFilter::new().with_allowed_senders([7]);
+        SocketFilter::for_config(&ReceiverConfig::new().with_filter(filter)).unwrap().attach(&receiver).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to = receiver.local_addr().unwrap();
+        sender.send_to(b"not fleet traffic at all", to).unwrap();
+        sender.send_to(&message(MessageType::Data, 9), to).unwrap();
+        sender.send_to(&message(MessageType::Data, 7), to).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(FleetMsgHeader::parse(&buf[..len]).unwrap().sender_id.get(), 7);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "ebpf"))]
+    fn test_ebpf_settings_follow_the_config() {
+        let settings = FilterSettings::for_config(&ReceiverConfig::new());
+        assert_eq!((settings.types, settings.validate, settings.min_sender, settings.max_sender), (u16::MAX, 1, 0, u32::MAX));
+
+        let filter = ReceiverFilter::new().with_message_types([MessageType::Data, MessageType::Heartbeat]).with_allowed_sender_range(0x3B00..=0x3BFF);
+        let config = ReceiverConfig::new().with_validation(ValidationPolicy::Lenient).with_filter(filter);
+        let settings = FilterSettings::for_config(&config);
+        assert_eq!((settings.types, settings.validate, settings.min_sender, settings.max_sender), (0b110, 0, 0x3B00, 0x3BFF));
+
+        // Two blocks don't fit the map, so the kernel lets every sender through
+        let filter = ReceiverFilter::new().with_allowed_sender_range(1..=2).with_allowed_sender_range(5..=6);
+        let settings = FilterSettings::for_config(&ReceiverConfig::new().with_filter(filter));
+        assert_eq!((settings.min_sender, settings.max_sender), (0, u32::MAX));
+    }
+}
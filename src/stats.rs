@@ -0,0 +1,117 @@
+//! Transport counters, and the modules that report on the traffic behind them.
+
+pub use crate::alloc_counter;
+
+#[path = "sequence_stats.rs"]
+pub mod sequence_stats;
+#[path = "alerts.rs"]
+pub mod alerts;
+#[path = "usage.rs"]
+pub mod usage;
+
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::alloc_counter::SubsystemAllocations;
+use crate::sequence_stats::{SenderSequenceStats, SequenceAnalyzer};
+use crate::transport::FleetMsgHeader;
+
+/// Transport counters, shared between the sender, receive handlers and admin tooling
+#[derive(Debug, Default)]
+pub struct TransportStats {
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    invalid_received: AtomicU64,
+    padding_bytes_sent: AtomicU64,
+    sequences: Mutex<SequenceAnalyzer>,
+}
+
+/// Point-in-time copy of [`TransportStats`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    pub invalid_received: u64,
+    /// Bytes of padding added to encrypted messages (counted in `bytes_sent` too)
+    #[serde(default)]
+    pub padding_bytes_sent: u64,
+    /// Allocations by subsystem, when built with `alloc-count` and the counting allocator is installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocations: Option<SubsystemAllocations>,
+    /// Loss, duplication and reordering of each sender heard from, by id
+    #[serde(default)]
+    pub senders: Vec<SenderSequenceStats>,
+}
+
+impl TransportStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_received(&self, bytes: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Track a received message's sequence number for per-sender loss and reordering
+    pub fn record_sequence(&self, header: &FleetMsgHeader, payload: &[u8]) {
+        self.sequences.lock().unwrap().observe(header, payload);
+    }
+
+    /// Stop reporting a sender that has left
+    pub fn forget_sender(&self, sender_id: u32) {
+        self.sequences.lock().unwrap().remove(sender_id);
+    }
+
+    pub fn record_padding(&self, bytes: usize) {
+        self.padding_bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_invalid(&self) {
+        self.invalid_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            invalid_received: self.invalid_received.load(Ordering::Relaxed),
+            padding_bytes_sent: self.padding_bytes_sent.load(Ordering::Relaxed),
+            allocations: alloc_counter::by_subsystem(),
+            senders: self.sequences.lock().unwrap().senders().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_reflects_counters() {
+        let stats = TransportStats::new();
+        stats.record_sent(100);
+        stats.record_sent(50);
+        stats.record_received(24);
+        stats.record_invalid();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.messages_sent, 2);
+        assert_eq!(snapshot.bytes_sent, 150);
+        assert_eq!(snapshot.messages_received, 1);
+        assert_eq!(snapshot.bytes_received, 24);
+        assert_eq!(snapshot.invalid_received, 1);
+    }
+}
@@ -0,0 +1,310 @@
+//! Where the transport keeps state that must outlive the process: incarnation
+//! numbers, counters, journals and read cursors.
+//!
+//! Everything goes through the [`StateStore`] trait, so a deployment picks the
+//! storage that suits its flash: [`FileStore`] writes one small file per key
+//! and appends logs to plain files, [`MemoryStore`] keeps nothing across
+//! restarts, and `SledStore` (`--features store-sled`) puts everything in one
+//! embedded database. A store holds two kinds of data:
+//!
+//! - values under string keys, replaced whole on each `put`
+//! - append-only logs of records, read back from a position (a cursor)
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Storage for state that must survive restarts
+pub trait StateStore: Send + Sync {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>>;
+
+    /// Store `value` under `key`; once this returns, it survives a crash
+    fn put(&self, key: &str, value: &[u8]) -> std::io::Result<()>;
+
+    fn delete(&self, key: &str) -> std::io::Result<()>;
+
+    /// Add a record to the end of `log`; returns its position
+    fn append(&self, log: &str, record: &[u8]) -> std::io::Result<u64>;
+
+    /// Records of `log` from position `from` on, at most `limit` of them
+    fn read_log(&self, log: &str, from: u64, limit: usize) -> std::io::Result<Vec<(u64, Vec<u8>)>>;
+}
+
+impl std::fmt::Debug for dyn StateStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StateStore")
+    }
+}
+
+/// Keys and log names are used as file names, so they're kept to a safe alphabet
+fn check_name(name: &str) -> std::io::Result<()> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(safe) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("invalid state key {:?}", name)));
+    }
+    Ok(())
+}
+
+/// A store that keeps nothing across restarts, for tests, simulations and
+/// nodes with no writable storage
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    values: Mutex<BTreeMap<String, Vec<u8>>>,
+    logs: Mutex<BTreeMap<String, Vec<Vec<u8>>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        check_name(key)?;
+        self.values.lock().unwrap().insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn append(&self, log: &str, record: &[u8]) -> std::io::Result<u64> {
+        check_name(log)?;
+        let mut logs = self.logs.lock().unwrap();
+        let records = logs.entry(log.to_string()).or_default();
+        records.push(record.to_vec());
+        Ok(records.len() as u64 - 1)
+    }
+
+    fn read_log(&self, log: &str, from: u64, limit: usize) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        let logs = self.logs.lock().unwrap();
+        let records = logs.get(log).map(Vec::as_slice).unwrap_or_default();
+        Ok((from..).zip(records.iter().skip(from as usize).take(limit).cloned()).collect())
+    }
+}
+
+/// A directory holding one file per key (`<key>.state`) and per log
+/// (`<log>.log`, length-prefixed records).
+///
+/// Values are replaced by writing a temporary file and renaming it, so a crash
+/// leaves the old value or the new one. Appends are synced before returning.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+    /// Record count of each log opened so far
+    log_lengths: Mutex<BTreeMap<String, u64>>,
+}
+
+impl FileStore {
+    /// Use `dir`, creating it if needed
+    pub fn open(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self { dir: dir.as_ref().to_path_buf(), log_lengths: Mutex::new(BTreeMap::new()) })
+    }
+
+    fn value_path(&self, key: &str) -> std::io::Result<PathBuf> {
+        check_name(key)?;
+        Ok(self.dir.join(format!("{}.state", key)))
+    }
+
+    fn log_path(&self, log: &str) -> std::io::Result<PathBuf> {
+        check_name(log)?;
+        Ok(self.dir.join(format!("{}.log", log)))
+    }
+
+    /// Every record of the log at `path`; a torn record at the end (from a
+    /// crash mid-append) is ignored
+    fn read_records(path: &Path) -> std::io::Result<Vec<Vec<u8>>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+        let mut records = Vec::new();
+        let mut len = [0u8; 4];
+        while reader.read_exact(&mut len).is_ok() {
+            let mut record = vec![0u8; u32::from_le_bytes(len) as usize];
+            if reader.read_exact(&mut record).is_err() {
+                break;
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+impl StateStore for FileStore {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.value_path(key)?) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        let path = self.value_path(key)?;
+        let temp = path.with_extension("tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(value)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, &path)
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(self.value_path(key)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&self, log: &str, record: &[u8]) -> std::io::Result<u64> {
+        let path = self.log_path(log)?;
+        let len = u32::try_from(record.len()).map_err(|_| Error::new(ErrorKind::InvalidInput, "record too large"))?;
+        let mut lengths = self.log_lengths.lock().unwrap();
+        let position = match lengths.get(log) {
+            Some(count) => *count,
+            None => Self::read_records(&path)?.len() as u64,
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut framed = len.to_le_bytes().to_vec();
+        framed.extend_from_slice(record);
+        file.write_all(&framed)?;
+        file.sync_data()?;
+        lengths.insert(log.to_string(), position + 1);
+        Ok(position)
+    }
+
+    fn read_log(&self, log: &str, from: u64, limit: usize) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        let records = Self::read_records(&self.log_path(log)?)?;
+        Ok((from..).zip(records.into_iter().skip(from as usize).take(limit)).collect())
+    }
+}
+
+/// Everything in one sled database: values in its default tree, each log in a
+/// tree of its own keyed by big-endian position
+#[cfg(feature = "store-sled")]
+pub struct SledStore {
+    db: sled::Db,
+}
+
+#[cfg(feature = "store-sled")]
+impl SledStore {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self { db: sled::open(path).map_err(Error::other)? })
+    }
+
+    fn log_tree(&self, log: &str) -> std::io::Result<sled::Tree> {
+        check_name(log)?;
+        self.db.open_tree(format!("log/{}", log)).map_err(Error::other)
+    }
+}
+
+#[cfg(feature = "store-sled")]
+impl StateStore for SledStore {
+    fn get(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key).map_err(Error::other)?.map(|value| value.to_vec()))
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> std::io::Result<()> {
+        check_name(key)?;
+        self.db.insert(key, value).map_err(Error::other)?;
+        self.db.flush().map_err(Error::other).map(|_| ())
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()> {
+        self.db.remove(key).map_err(Error::other)?;
+        self.db.flush().map_err(Error::other).map(|_| ())
+    }
+
+    fn append(&self, log: &str, record: &[u8]) -> std::io::Result<u64> {
+        let tree = self.log_tree(log)?;
+        let position = match tree.last().map_err(Error::other)? {
+            Some((key, _)) => u64::from_be_bytes(key.as_ref().try_into().map_err(Error::other)?) + 1,
+            None => 0,
+        };
+        tree.insert(position.to_be_bytes(), record).map_err(Error::other)?;
+        tree.flush().map_err(Error::other)?;
+        Ok(position)
+    }
+
+    fn read_log(&self, log: &str, from: u64, limit: usize) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+        self.log_tree(log)?
+            .range(from.to_be_bytes()..)
+            .take(limit)
+            .map(|entry| {
+                let (key, value) = entry.map_err(Error::other)?;
+                Ok((u64::from_be_bytes(key.as_ref().try_into().map_err(Error::other)?), value.to_vec()))
+            })
+            .collect()
+    }
+}
+
+/// The incarnation for this run of the node: one more than the last run's,
+/// saved before it is returned. Give it to
+/// [`MulticastSender::with_incarnation`](crate::transport::MulticastSender::with_incarnation)
+/// so peers see restarts even when the clock can't be trusted.
+pub fn next_incarnation(store: &dyn StateStore) -> std::io::Result<u64> {
+    let last = match store.get("incarnation")? {
+        Some(bytes) => u64::from_le_bytes(bytes.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, "corrupt incarnation"))?),
+        None => 0,
+    };
+    let next = last + 1;
+    store.put("incarnation", &next.to_le_bytes())?;
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(store: &dyn StateStore) {
+        assert_eq!(store.get("cursor").unwrap(), None);
+        store.put("cursor", b"42").unwrap();
+        store.put("cursor", b"43").unwrap();
+        assert_eq!(store.get("cursor").unwrap().as_deref(), Some(&b"43"[..]));
+        store.delete("cursor").unwrap();
+        store.delete("cursor").unwrap();
+        assert_eq!(store.get("cursor").unwrap(), None);
+        assert_eq!(store.put("../escape", b"x").unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        for record in [&b"first"[..], b"", b"third\nline"] {
+            store.append("journal", record).unwrap();
+        }
+        assert_eq!(store.read_log("journal", 1, 10).unwrap(), vec![(1, b"".to_vec()), (2, b"third\nline".to_vec())]);
+        assert_eq!(store.read_log("journal", 0, 1).unwrap(), vec![(0, b"first".to_vec())]);
+        assert!(store.read_log("other", 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stores_behave_alike() {
+        exercise(&MemoryStore::new());
+
+        let dir = std::env::temp_dir().join(format!("fleetlink-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        exercise(&FileStore::open(&dir).unwrap());
+        // A reopened store continues the log, and a torn last record is dropped
+        let store = FileStore::open(&dir).unwrap();
+        assert_eq!((next_incarnation(&store).unwrap(), next_incarnation(&store).unwrap()), (1, 2));
+        OpenOptions::new().append(true).open(dir.join("journal.log")).unwrap().write_all(&[9, 0, 0, 0, 1]).unwrap();
+        assert_eq!(FileStore::open(&dir).unwrap().append("journal", b"fourth").unwrap(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        #[cfg(feature = "store-sled")]
+        {
+            let dir = std::env::temp_dir().join(format!("fleetlink-sled-{}", std::process::id()));
+            exercise(&SledStore::open(&dir).unwrap());
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
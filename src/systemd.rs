@@ -0,0 +1,148 @@
+//! Supervision by systemd through the `sd_notify` protocol, for units with
+//! `Type=notify` and, optionally, `WatchdogSec=`.
+//!
+//! A [`Notifier`] sends state lines to the socket systemd names in
+//! `NOTIFY_SOCKET`: `READY=1` once the service is up, `WATCHDOG=1` at least
+//! every half [`watchdog_timeout`](Notifier::watchdog_timeout) while it is
+//! healthy, `STATUS=...` for `systemctl status`, and `STOPPING=1` on the way
+//! out. Outside systemd the variable is unset and every call does nothing, so
+//! the same binary runs under any supervisor.
+
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Sends service state to systemd; does nothing when not run by it
+#[derive(Debug, Default)]
+pub struct Notifier {
+    socket: Option<UnixDatagram>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// A notifier that does nothing
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Connect to the socket in `NOTIFY_SOCKET`, if set, and read the watchdog
+    /// timeout from `WATCHDOG_USEC` if `WATCHDOG_PID` (when set) is this process
+    pub fn from_env() -> std::io::Result<Self> {
+        let watchdog_usec = std::env::var("WATCHDOG_USEC").ok();
+        let watchdog_pid = std::env::var("WATCHDOG_PID").ok();
+        Self::from_vars(std::env::var_os("NOTIFY_SOCKET"), watchdog_usec.as_deref(), watchdog_pid.as_deref())
+    }
+
+    fn from_vars(notify_socket: Option<OsString>, watchdog_usec: Option<&str>, watchdog_pid: Option<&str>) -> std::io::Result<Self> {
+        let Some(path) = notify_socket.filter(|path| !path.is_empty()) else {
+            return Ok(Self::disabled());
+        };
+        let ours = watchdog_pid.is_none_or(|pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = watchdog_usec
+            .filter(|_| ours)
+            .and_then(|usec| usec.parse().ok())
+            .filter(|usec| *usec > 0)
+            .map(Duration::from_micros);
+        Self::connect(&path, watchdog)
+    }
+
+    /// Send to the notification socket at `path`; one starting with `@` is in
+    /// the abstract namespace (Linux only)
+    pub fn connect(path: impl AsRef<std::ffi::OsStr>, watchdog: Option<Duration>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::unbound()?;
+        match path.as_encoded_bytes().strip_prefix(b"@") {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                socket.connect_addr(&std::os::unix::net::SocketAddr::from_abstract_name(name)?)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return Err(Error::new(ErrorKind::Unsupported, "abstract notification sockets need Linux")),
+            None => socket.connect(path)?,
+        }
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket: Some(socket), watchdog })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// How long systemd waits for a watchdog ping before restarting the
+    /// service; `None` if the unit has no watchdog
+    pub fn watchdog_timeout(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// Send state lines such as `READY=1`, separated by newlines
+    pub fn notify(&self, state: &str) -> std::io::Result<()> {
+        let Some(socket) = &self.socket else { return Ok(()) };
+        if state.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "empty notification"));
+        }
+        socket.send(state.as_bytes()).map(|_| ())
+    }
+
+    /// The service is up, with `status` to show in `systemctl status`
+    pub fn ready(&self, status: &str) -> std::io::Result<()> {
+        self.notify(&format!("READY=1\nSTATUS={}", one_line(status)))
+    }
+
+    pub fn status(&self, status: &str) -> std::io::Result<()> {
+        self.notify(&format!("STATUS={}", one_line(status)))
+    }
+
+    /// The service is healthy; a no-op without a watchdog
+    pub fn watchdog(&self) -> std::io::Result<()> {
+        if self.watchdog.is_none() {
+            return Ok(());
+        }
+        self.notify("WATCHDOG=1")
+    }
+
+    pub fn stopping(&self) -> std::io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+}
+
+/// A status line can't carry a newline, which would start another assignment
+fn one_line(status: &str) -> String {
+    status.replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_reaches_the_notification_socket() {
+        let dir = std::env::temp_dir().join(format!("fleetlink-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        systemd.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let received = || {
+            let mut buf = [0u8; 256];
+            let len = systemd.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+
+        let notifier = Notifier::from_vars(Some(path.clone().into()), Some("4000000"), None).unwrap();
+        assert_eq!(notifier.watchdog_timeout(), Some(Duration::from_secs(4)));
+        notifier.ready("3 peers\nheard").unwrap();
+        assert_eq!(received(), "READY=1\nSTATUS=3 peers heard");
+        notifier.watchdog().unwrap();
+        assert_eq!(received(), "WATCHDOG=1");
+
+        // The watchdog belongs to another process, e.g. a wrapper script
+        let notifier = Notifier::from_vars(Some(path.clone().into()), Some("4000000"), Some("0")).unwrap();
+        assert_eq!(notifier.watchdog_timeout(), None);
+        let outside = Notifier::from_vars(None, Some("4000000"), None).unwrap();
+        assert!(!outside.is_enabled());
+        outside.ready("up").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use rand::Rng;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::receiver::ValidationIssue;
+use crate::rng::{self, FleetRng};
+use crate::soak;
+
+/// One raw datagram as a receiver got it, with what became of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TappedFrame {
+    /// Receiver wall clock, microseconds since the Unix epoch
+    pub received_at_us: u64,
+    pub addr: SocketAddr,
+    /// The datagram exactly as received
+    pub datagram: Vec<u8>,
+    /// Whether it passed the receiver's filter and validation. Duplicates
+    /// and messages from outside the zone filter's area are dropped later,
+    /// so they still show as delivered.
+    pub delivered: bool,
+    /// What failed validation, whether or not the policy delivered it anyway
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// Which of the datagrams a [`FrameTap`] sees it copies
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Sampling {
+    #[default]
+    All,
+    /// Every `n`th datagram
+    OneIn(u64),
+    /// Each datagram with this probability, drawn from a generator seeded with `seed`
+    Random { probability: f64, seed: u64 },
+}
+
+#[derive(Debug)]
+struct Sampler {
+    sampling: Sampling,
+    rng: Mutex<FleetRng>,
+}
+
+impl Sampler {
+    fn new(sampling: Sampling) -> Self {
+        let seed = match sampling {
+            Sampling::Random { seed, .. } => seed,
+            _ => 0,
+        };
+        Self { sampling, rng: Mutex::new(rng::seeded(seed)) }
+    }
+
+    /// Whether the `seen`th datagram (counting from 1) is copied
+    fn takes(&self, seen: u64) -> bool {
+        match self.sampling {
+            Sampling::All => true,
+            Sampling::OneIn(n) => seen.is_multiple_of(n.max(1)),
+            Sampling::Random { probability, .. } => self.rng.lock().unwrap().random_bool(probability.clamp(0.0, 1.0)),
+        }
+    }
+}
+
+/// Copies every datagram a receiver gets, valid or not, to a [`TapSubscription`],
+/// alongside the main handler and without changing what it sees.
+///
+/// When the subscriber falls behind, frames are dropped (and counted) rather
+/// than slowing the receiver down. On high-rate streams, [`Sampling`] limits
+/// the copying to a share of the datagrams, so the tap can stay enabled in
+/// production; datagrams sampled out cost a counter increment.
+#[derive(Debug, Clone)]
+pub struct FrameTap {
+    frames: Sender<TappedFrame>,
+    dropped: Arc<AtomicU64>,
+    seen: Arc<AtomicU64>,
+    sampler: Arc<Sampler>,
+}
+
+impl FrameTap {
+    /// A tap buffering up to `capacity` frames for its subscriber
+    pub fn new(capacity: usize) -> (Self, TapSubscription) {
+        let (frames, receiver) = channel::bounded(capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let seen = Arc::new(AtomicU64::new(0));
+        let tap = Self { frames, dropped: dropped.clone(), seen: seen.clone(), sampler: Arc::new(Sampler::new(Sampling::All)) };
+        (tap, TapSubscription { frames: receiver, dropped, seen })
+    }
+
+    /// Copy only a sample of the datagrams
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampler = Arc::new(Sampler::new(sampling));
+        self
+    }
+
+    pub(crate) fn record(&self, datagram: &[u8], addr: SocketAddr, delivered: bool, issues: &[ValidationIssue]) {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.sampler.takes(seen) {
+            return;
+        }
+        let frame = TappedFrame {
+            received_at_us: soak::now_micros(),
+            addr,
+            datagram: datagram.to_vec(),
+            delivered,
+            issues: issues.to_vec(),
+        };
+        if let Err(TrySendError::Full(_)) = self.frames.try_send(frame) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The receiving end of a [`FrameTap`]
+#[derive(Debug)]
+pub struct TapSubscription {
+    frames: Receiver<TappedFrame>,
+    dropped: Arc<AtomicU64>,
+    seen: Arc<AtomicU64>,
+}
+
+impl TapSubscription {
+    /// The next frame; `None` once every receiver using the tap has stopped
+    pub async fn next(&self) -> Option<TappedFrame> {
+        self.frames.recv().await.ok()
+    }
+
+    /// The next frame if one is waiting
+    pub fn try_next(&self) -> Option<TappedFrame> {
+        self.frames.try_recv().ok()
+    }
+
+    /// Frames lost because the subscriber fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Datagrams the tap saw, whether or not they were sampled, e.g. to scale
+    /// counts made from a sample back up
+    pub fn seen(&self) -> u64 {
+        self.seen.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copied(sampling: Sampling, datagrams: usize) -> (usize, u64) {
+        let (tap, frames) = FrameTap::new(datagrams);
+        let tap = tap.with_sampling(sampling);
+        let addr = "10.0.0.1:5000".parse().unwrap();
+        for _ in 0..datagrams {
+            tap.record(b"frame", addr, true, &[]);
+        }
+        (std::iter::from_fn(|| frames.try_next()).count(), frames.seen())
+    }
+
+    #[test]
+    fn test_sampling_copies_a_share() {
+        assert_eq!(copied(Sampling::All, 9), (9, 9));
+        assert_eq!(copied(Sampling::OneIn(3), 9), (3, 9));
+        let random = Sampling::Random { probability: 0.25, seed: 7 };
+        let (sampled, seen) = copied(random, 2000);
+        assert!((400..600).contains(&sampled), "{} of {}", sampled, seen);
+        // The same seed samples the same datagrams
+        assert_eq!(copied(random, 2000).0, sampled);
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time-division transmit schedule shared by every node on a half-duplex link.
+///
+/// Each sender owns the slot `sender_id % slots_per_cycle`; all nodes must use
+/// the same slot width and cycle length and have synchronized clocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlotSchedule {
+    slot_width: Duration,
+    slots_per_cycle: u32,
+    guard_time: Duration,
+    clock_offset_us: i64,
+}
+
+impl SlotSchedule {
+    /// Slots are scheduled in whole microseconds, so `slot_width` must be at
+    /// least 1µs, and the cycle must fit in a `u64` of microseconds
+    pub fn new(slot_width: Duration, slots_per_cycle: u32) -> std::io::Result<Self> {
+        if slot_width.is_zero() || slots_per_cycle == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "slot width and slots per cycle must be non-zero",
+            ));
+        }
+        if slot_width < Duration::from_micros(1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "slot width must be at least 1µs"));
+        }
+        let cycle = slot_width.checked_mul(slots_per_cycle);
+        if cycle.is_none_or(|cycle| cycle.as_micros() > u64::MAX as u128) {
+            return Err(Error::new(ErrorKind::InvalidInput, "slot width times slots per cycle overflows the cycle length"));
+        }
+
+        Ok(Self {
+            slot_width,
+            slots_per_cycle,
+            guard_time: Duration::ZERO,
+            clock_offset_us: 0,
+        })
+    }
+
+    /// Stop starting transmissions this long before the end of the slot;
+    /// must leave some of the slot to transmit in
+    pub fn with_guard_time(mut self, guard_time: Duration) -> std::io::Result<Self> {
+        // Compared in whole microseconds, as the schedule works in them
+        if guard_time.as_micros() >= self.slot_width.as_micros() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "guard time must be shorter than the slot width",
+            ));
+        }
+        self.guard_time = guard_time;
+        Ok(self)
+    }
+
+    /// Correction (in microseconds) applied to the local wall clock, as
+    /// estimated by whatever time synchronization the fleet runs
+    pub fn with_clock_offset(mut self, offset_us: i64) -> Self {
+        self.clock_offset_us = offset_us;
+        self
+    }
+
+    pub fn slot_width(&self) -> Duration {
+        self.slot_width
+    }
+
+    pub fn slots_per_cycle(&self) -> u32 {
+        self.slots_per_cycle
+    }
+
+    pub fn cycle_length(&self) -> Duration {
+        // Checked in `new`
+        self.slot_width.checked_mul(self.slots_per_cycle).expect("cycle length fits a Duration")
+    }
+
+    pub fn slot_for(&self, sender_id: u32) -> u32 {
+        sender_id % self.slots_per_cycle
+    }
+
+    /// Current fleet time in microseconds since the Unix epoch
+    pub fn synchronized_now_us(&self) -> u64 {
+        let local = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        local.saturating_add(self.clock_offset_us).max(0) as u64
+    }
+
+    /// How long `sender_id` has to wait at fleet time `now_us` before it may transmit
+    pub fn delay_until_slot(&self, sender_id: u32, now_us: u64) -> Duration {
+        let slot_us = self.slot_width.as_micros() as u64;
+        let cycle_us = slot_us * self.slots_per_cycle as u64;
+        let usable_us = slot_us - self.guard_time.as_micros() as u64;
+
+        let slot_start = self.slot_for(sender_id) as u64 * slot_us;
+        let position = now_us % cycle_us;
+
+        if position >= slot_start && position < slot_start + usable_us {
+            return Duration::ZERO;
+        }
+
+        let wait_us = if position < slot_start {
+            slot_start - position
+        } else {
+            cycle_us - position + slot_start
+        };
+        Duration::from_micros(wait_us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> SlotSchedule {
+        SlotSchedule::new(Duration::from_millis(10), 4).unwrap()
+    }
+
+    #[test]
+    fn test_slot_assignment_wraps_by_sender_id() {
+        let s = schedule();
+        assert_eq!(s.slot_for(0), 0);
+        assert_eq!(s.slot_for(6), 2);
+        assert_eq!(s.cycle_length(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_delay_inside_and_outside_slot() {
+        let s = schedule();
+
+        // Sender 1 owns [10ms, 20ms) of every 40ms cycle
+        assert_eq!(s.delay_until_slot(1, 15_000), Duration::ZERO);
+        assert_eq!(s.delay_until_slot(1, 5_000), Duration::from_millis(5));
+        assert_eq!(s.delay_until_slot(1, 25_000), Duration::from_millis(25));
+        assert_eq!(s.delay_until_slot(1, 40_000 + 12_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_guard_time_closes_end_of_slot() {
+        let s = schedule().with_guard_time(Duration::from_millis(2)).unwrap();
+
+        assert_eq!(s.delay_until_slot(1, 17_000), Duration::ZERO);
+        assert_eq!(s.delay_until_slot(1, 18_500), Duration::from_micros(31_500));
+    }
+
+    #[test]
+    fn test_rejects_empty_schedule() {
+        assert!(SlotSchedule::new(Duration::ZERO, 4).is_err());
+        assert!(SlotSchedule::new(Duration::from_millis(1), 0).is_err());
+        assert!(schedule().with_guard_time(Duration::from_millis(10)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_sub_microsecond_and_overflowing_schedules() {
+        assert_eq!(SlotSchedule::new(Duration::from_nanos(500), 4).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(SlotSchedule::new(Duration::MAX / 2, 4).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(SlotSchedule::new(Duration::from_secs(u64::MAX / 1_000_000), 2).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let narrowest = SlotSchedule::new(Duration::from_micros(1), u32::MAX).unwrap();
+        assert_eq!(narrowest.cycle_length(), Duration::from_micros(u32::MAX as u64));
+        assert_eq!(narrowest.delay_until_slot(0, 0), Duration::ZERO);
+        // Shorter than the slot, but not by a whole microsecond
+        let uneven = SlotSchedule::new(Duration::from_nanos(1_500), 4).unwrap();
+        assert!(uneven.with_guard_time(Duration::from_nanos(1_200)).is_err());
+    }
+}
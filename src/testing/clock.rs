@@ -0,0 +1,37 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A clock that only moves when told to, for the APIs that take `now: Instant`
+/// (membership, peer tables, bandwidth budgets).
+///
+/// Clones share the same time, so one can be handed to each node under test.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self { start: Instant::now(), elapsed: Arc::default() }
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    /// Time since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
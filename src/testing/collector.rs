@@ -0,0 +1,143 @@
+use async_std::task;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::transport::{FleetMsgHeader, MessageType};
+
+use super::Received;
+
+/// Records received messages and checks them with assertions that explain
+/// what went wrong, instead of tests keeping their own counters.
+///
+/// Clones share the same record, so one can be moved into a handler while the
+/// test keeps another. Each assertion panics on failure and returns `self`, so
+/// they chain.
+#[derive(Debug, Clone, Default)]
+pub struct MessageCollector {
+    messages: Arc<Mutex<Vec<Received>>>,
+}
+
+impl MessageCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, header: FleetMsgHeader, payload: Vec<u8>, addr: SocketAddr) {
+        self.messages.lock().unwrap().push((header, payload, addr));
+    }
+
+    /// A handler for [`start_multicast_rx`](crate::start_multicast_rx) or
+    /// [`MemoryBus::subscribe`](super::MemoryBus::subscribe) that records into this collector
+    pub fn handler(&self) -> impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static {
+        let collector = self.clone();
+        move |header, payload, addr| collector.record(header, payload, addr)
+    }
+
+    /// Everything recorded so far, in arrival order
+    pub fn messages(&self) -> Vec<Received> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Recorded messages of one type
+    pub fn of_type(&self, msg_type: MessageType) -> Vec<Received> {
+        self.messages().into_iter().filter(|(header, _, _)| header.message_type() == msg_type).collect()
+    }
+
+    /// Wait until at least `count` messages have arrived or `timeout` passes
+    pub async fn wait_for(&self, count: usize, timeout: Duration) -> &Self {
+        let deadline = Instant::now() + timeout;
+        while self.len() < count && Instant::now() < deadline {
+            task::sleep(Duration::from_millis(10)).await;
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn received_at_least(&self, count: usize) -> &Self {
+        let len = self.len();
+        assert!(len >= count, "expected at least {} messages, received {}", count, len);
+        self
+    }
+
+    /// Each sender's sequence numbers only ever move forward (allowing for wrap-around)
+    #[track_caller]
+    pub fn in_order_per_sender(&self) -> &Self {
+        let mut last: HashMap<u32, u16> = HashMap::new();
+        for (header, _, _) in &self.messages() {
+            if let Some(previous) = last.insert(header.sender_id.get(), header.sequence.get()) {
+                assert!(
+                    (header.sequence.get().wrapping_sub(previous) as i16) > 0,
+                    "sender {}: sequence {} arrived after {}", header.sender_id, header.sequence, previous
+                );
+            }
+        }
+        self
+    }
+
+    /// No sender/sequence pair was delivered twice
+    #[track_caller]
+    pub fn no_duplicates(&self) -> &Self {
+        let mut seen = HashSet::new();
+        for (header, _, _) in &self.messages() {
+            assert!(
+                seen.insert((header.sender_id, header.sequence)),
+                "sender {}: sequence {} delivered more than once", header.sender_id, header.sequence
+            );
+        }
+        self
+    }
+
+    /// At least one message's payload satisfies `predicate`
+    #[track_caller]
+    pub fn payload_matching(&self, predicate: impl Fn(&[u8]) -> bool) -> &Self {
+        let messages = self.messages();
+        assert!(
+            messages.iter().any(|(_, payload, _)| predicate(payload)),
+            "none of {} payloads matched", messages.len()
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FaultInjector, MemoryBus};
+
+    #[test]
+    fn test_assertions_pass_on_clean_delivery() {
+        let collector = MessageCollector::new();
+        let mut bus = MemoryBus::new();
+        bus.subscribe(collector.handler());
+        for sender_id in [1, 2] {
+            bus.send(sender_id, MessageType::Heartbeat, b"");
+            bus.send(sender_id, MessageType::Data, b"position 1");
+        }
+
+        collector.received_at_least(4)
+            .in_order_per_sender()
+            .no_duplicates()
+            .payload_matching(|payload| payload.starts_with(b"position"));
+        assert_eq!(collector.of_type(MessageType::Data).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "delivered more than once")]
+    fn test_duplicates_are_reported() {
+        let collector = MessageCollector::new();
+        let mut bus = MemoryBus::new().with_faults(FaultInjector::new(3).with_duplication(1.0));
+        bus.subscribe(collector.handler());
+        bus.send(1, MessageType::Data, b"once");
+        collector.no_duplicates();
+    }
+}
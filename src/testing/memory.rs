@@ -0,0 +1,208 @@
+use rand::Rng;
+use std::collections::{BTreeSet, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use zerocopy::AsBytes;
+
+use crate::features::FeatureCodec;
+use crate::receiver::{self, ReceiverConfig};
+use crate::rng::{self, FleetRng};
+use crate::transport::{FleetMsgHeader, MessageType};
+
+type Handler = Box<dyn FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send>;
+
+/// Seeded, repeatable faults for a [`MemoryBus`]: loss, duplication,
+/// corruption and senders cut off from everyone else
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    rng: FleetRng,
+    loss: f64,
+    duplication: f64,
+    corruption: f64,
+    isolated: BTreeSet<u32>,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64) -> Self {
+        Self { rng: rng::seeded(seed), loss: 0.0, duplication: 0.0, corruption: 0.0, isolated: BTreeSet::new() }
+    }
+
+    /// Drop each datagram with `probability`
+    pub fn with_loss(mut self, probability: f64) -> Self {
+        self.loss = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Deliver each datagram twice with `probability`
+    pub fn with_duplication(mut self, probability: f64) -> Self {
+        self.duplication = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Flip one random bit of each datagram with `probability`
+    pub fn with_corruption(mut self, probability: f64) -> Self {
+        self.corruption = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Drop everything `sender_id` sends until it is healed
+    pub fn isolate(&mut self, sender_id: u32) {
+        self.isolated.insert(sender_id);
+    }
+
+    pub fn heal(&mut self, sender_id: u32) {
+        self.isolated.remove(&sender_id);
+    }
+
+    /// The copies of `datagram` that get through: none if it was lost, two if duplicated
+    pub fn apply(&mut self, sender_id: u32, datagram: &[u8]) -> Vec<Vec<u8>> {
+        if self.isolated.contains(&sender_id) || self.rng.random_bool(self.loss) {
+            return Vec::new();
+        }
+        let mut datagram = datagram.to_vec();
+        if !datagram.is_empty() && self.rng.random_bool(self.corruption) {
+            let bit = self.rng.random_range(0..datagram.len() * 8);
+            datagram[bit / 8] ^= 1 << (bit % 8);
+        }
+        match self.rng.random_bool(self.duplication) {
+            true => vec![datagram.clone(), datagram],
+            false => vec![datagram],
+        }
+    }
+}
+
+/// Delivers messages between in-process nodes synchronously, without sockets.
+///
+/// Datagrams are checked and decoded exactly as the UDP receiver does, so
+/// handlers written for [`start_multicast_rx`](crate::start_multicast_rx)
+/// work unchanged. Every subscriber gets every message, as on a multicast group.
+#[derive(Default)]
+pub struct MemoryBus {
+    subscribers: Vec<Handler>,
+    config: ReceiverConfig,
+    faults: Option<FaultInjector>,
+    sequences: HashMap<u32, u16>,
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Codec used to strip optional features before handing payloads over
+    pub fn with_codec(mut self, codec: FeatureCodec) -> Self {
+        self.config = self.config.with_codec(codec);
+        self
+    }
+
+    /// Decode, bound and validate datagrams as a receiver with `config` would
+    pub fn with_config(mut self, config: ReceiverConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// The fault injector, e.g. to isolate or heal a sender mid-test
+    pub fn faults_mut(&mut self) -> Option<&mut FaultInjector> {
+        self.faults.as_mut()
+    }
+
+    pub fn subscribe(&mut self, handler: impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static) {
+        self.subscribers.push(Box::new(handler));
+    }
+
+    /// The address messages from `sender_id` appear to come from (in 127.0.0.0/8)
+    pub fn address_of(sender_id: u32) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::from(0x7F00_0000 | (sender_id & 0x00FF_FFFF)).into(), 0)
+    }
+
+    /// Frame and send a message from `sender_id` with its next sequence number;
+    /// returns how many deliveries were made
+    pub fn send(&mut self, sender_id: u32, msg_type: MessageType, payload: &[u8]) -> usize {
+        let sequence = self.sequences.entry(sender_id).or_insert(0);
+        let header = FleetMsgHeader::new(msg_type, sender_id, *sequence, payload.len() as u16);
+        *sequence = sequence.wrapping_add(1);
+
+        let mut datagram = header.as_bytes().to_vec();
+        datagram.extend_from_slice(payload);
+        let copies = match &mut self.faults {
+            Some(faults) => faults.apply(sender_id, &datagram),
+            None => vec![datagram],
+        };
+        copies.iter().map(|copy| self.deliver(copy, Self::address_of(sender_id))).sum()
+    }
+
+    /// Forget `sender_id`'s sequence numbers, as if it restarted; its next
+    /// message starts again from 0
+    pub fn restart(&mut self, sender_id: u32) {
+        self.sequences.remove(&sender_id);
+    }
+
+    /// Hand a raw datagram to every subscriber, bypassing the fault injector
+    pub fn deliver(&mut self, datagram: &[u8], from: SocketAddr) -> usize {
+        if !self.config.screen(datagram, from) {
+            return 0;
+        }
+        match receiver::inspect(datagram, from, &self.config) {
+            Ok(delivery) => {
+                for handler in &mut self.subscribers {
+                    handler(delivery.header, delivery.payload.clone(), from);
+                }
+                self.subscribers.len()
+            }
+            Err(issues) => {
+                eprintln!("Dropped message from {}: {}", from, receiver::describe(&issues));
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membership::{Membership, MembershipEvent};
+    use crate::testing::MockClock;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_faults_are_repeatable() {
+        let run = |seed| {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let sink = received.clone();
+            let faults = FaultInjector::new(seed).with_loss(0.3).with_duplication(0.2).with_corruption(0.1);
+            let mut bus = MemoryBus::new().with_faults(faults);
+            bus.subscribe(move |header, _, _| sink.lock().unwrap().push(header.sequence));
+            for _ in 0..50 {
+                bus.send(1, MessageType::Data, b"telemetry");
+            }
+            received.lock().unwrap().clone()
+        };
+        assert_eq!(run(7), run(7));
+        assert!(run(7).len() < 50);
+    }
+
+    #[test]
+    fn test_isolated_node_goes_down_on_mock_clock() {
+        let clock = MockClock::new();
+        let membership = Arc::new(Mutex::new(Membership::new(1, Duration::from_secs(3), clock.now())));
+        let mut bus = MemoryBus::new().with_faults(FaultInjector::new(1));
+        let (observer, observed_clock) = (membership.clone(), clock.clone());
+        bus.subscribe(move |header, payload, _| {
+            observer.lock().unwrap().observe(&header, &payload, observed_clock.now());
+        });
+
+        bus.send(2, MessageType::Heartbeat, b"");
+        bus.faults_mut().unwrap().isolate(2);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(bus.send(2, MessageType::Heartbeat, b""), 0);
+        assert!(membership.lock().unwrap().tick(clock.now()).is_empty());
+
+        clock.advance(Duration::from_secs(2));
+        assert!(membership.lock().unwrap().tick(clock.now()).contains(&MembershipEvent::PeerDown { sender_id: 2 }));
+    }
+}
@@ -0,0 +1,130 @@
+use async_std::net::UdpSocket;
+use async_std::task;
+use futures::channel::oneshot;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::channels::{self, Channel};
+use crate::features::FeatureCodec;
+use crate::receiver::{Delivery, ReceiverConfig};
+use crate::transport::{self, FleetMsgHeader, MulticastSender};
+
+mod clock;
+mod collector;
+mod memory;
+
+pub use clock::MockClock;
+pub use collector::MessageCollector;
+pub use memory::{FaultInjector, MemoryBus};
+
+/// A message as a [`TestReceiver`] or [`MessageCollector`] saw it
+pub type Received = (FleetMsgHeader, Vec<u8>, SocketAddr);
+
+/// Bind and join a channel no other test in this process has, skipping ports
+/// that another process (e.g. a parallel test run) already holds
+pub async fn bind_free_channel() -> std::io::Result<(Channel, UdpSocket)> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let name = format!("test-{}", NEXT.fetch_add(1, Ordering::Relaxed));
+        let channel = channels::shared().lock().unwrap().allocate(&name)?;
+        match UdpSocket::bind(("0.0.0.0", channel.port)).await {
+            Ok(socket) => {
+                socket.join_multicast_v4(channel.group, Ipv4Addr::UNSPECIFIED)?;
+                return Ok((channel, socket));
+            }
+            // The busy channel stays allocated, so it isn't offered again
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Receiver on its own free channel, collecting everything it gets.
+///
+/// The socket is bound before `start` returns, so there is no need to sleep
+/// before sending; the receiver stops when the fixture is dropped.
+pub struct TestReceiver {
+    channel: Channel,
+    collector: MessageCollector,
+    stop: Option<oneshot::Sender<()>>,
+}
+
+impl TestReceiver {
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_with_codec(FeatureCodec::default()).await
+    }
+
+    pub async fn start_with_codec(codec: FeatureCodec) -> std::io::Result<Self> {
+        Self::start_with_config(ReceiverConfig::new().with_codec(codec)).await
+    }
+
+    pub async fn start_with_config(config: ReceiverConfig) -> std::io::Result<Self> {
+        let (channel, socket) = bind_free_channel().await?;
+        let collector = MessageCollector::new();
+        let sink = collector.clone();
+        let (stop, stopped) = oneshot::channel::<()>();
+
+        task::spawn(async move {
+            let handler = move |delivery: Delivery| {
+                sink.record(delivery.header, delivery.payload, delivery.addr);
+            };
+            futures::future::select(Box::pin(transport::receive_loop(socket, config, handler)), stopped).await;
+        });
+
+        Ok(Self { channel, collector, stop: Some(stop) })
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// A sender on this receiver's channel
+    pub async fn sender(&self, sender_id: u32) -> std::io::Result<MulticastSender> {
+        Ok(MulticastSender::new(self.channel.group, self.channel.port, sender_id).await?)
+    }
+
+    /// What this receiver has collected, for assertions
+    pub fn collector(&self) -> &MessageCollector {
+        &self.collector
+    }
+
+    /// Everything received so far
+    pub fn received(&self) -> Vec<Received> {
+        self.collector.messages()
+    }
+
+    /// Wait until at least `count` messages have arrived or `timeout` passes,
+    /// then return everything received
+    pub async fn wait_for(&self, count: usize, timeout: Duration) -> Vec<Received> {
+        self.collector.wait_for(count, timeout).await.messages()
+    }
+}
+
+impl Drop for TestReceiver {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_fixtures_get_distinct_free_channels() {
+        let first = TestReceiver::start().await.unwrap();
+        let second = TestReceiver::start().await.unwrap();
+        assert_ne!(first.channel().port, second.channel().port);
+        assert_ne!(first.channel().group, second.channel().group);
+
+        let mut sender = second.sender(5).await.unwrap();
+        sender.send_data(b"hello").await.unwrap();
+        let received = second.wait_for(1, Duration::from_secs(2)).await;
+        assert_eq!(received[0].1, b"hello");
+        assert!(first.received().is_empty());
+    }
+}
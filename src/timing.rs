@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::soak;
+
+/// Microseconds on this process's monotonic clock, which NTP steps don't move
+pub fn monotonic_micros() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+/// When a message was sent, by the sender's wall and monotonic clocks.
+///
+/// Carried as the [`SEND_TIME`](crate::extensions::SEND_TIME) extension: both
+/// values as little-endian u64 microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendTimestamps {
+    /// Microseconds since the Unix epoch
+    pub wall_us: u64,
+    /// Microseconds on the sender's monotonic clock, see [`monotonic_micros`]
+    pub monotonic_us: u64,
+}
+
+impl SendTimestamps {
+    pub fn now() -> Self {
+        Self { wall_us: soak::now_micros(), monotonic_us: monotonic_micros() }
+    }
+
+    pub fn encode(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.wall_us.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.monotonic_us.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; 16] = bytes.try_into().ok()?;
+        Some(Self {
+            wall_us: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            monotonic_us: u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Measures one-way delay on monotonic clocks, so an NTP step on either side
+/// mid-run doesn't show up as a latency spike or a negative delay.
+///
+/// Each sender's monotonic clock is tied to our own once, from the first
+/// message's wall-clock time and the time-sync offset; after that, only
+/// monotonic time is used. A sender whose monotonic clock goes backwards has
+/// restarted and is tied again.
+#[derive(Debug, Clone)]
+pub struct DelayEstimator {
+    /// Our wall clock minus our monotonic clock, fixed at creation
+    local_anchor_us: i64,
+    /// Per sender: our wall time at which its monotonic clock read zero, and its last reading
+    senders: HashMap<u32, (i64, u64)>,
+}
+
+impl Default for DelayEstimator {
+    fn default() -> Self {
+        let now = SendTimestamps::now();
+        Self { local_anchor_us: now.wall_us as i64 - now.monotonic_us as i64, senders: HashMap::new() }
+    }
+}
+
+impl DelayEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// One-way delay of a message from `sender_id` received at `received_monotonic_us`
+    /// (our [`monotonic_micros`]). `time_sync_offset_us` is the sender's wall
+    /// clock minus ours, as estimated by the fleet's time sync; it only matters
+    /// for the first message from each sender.
+    pub fn one_way_delay(
+        &mut self,
+        sender_id: u32,
+        sent: SendTimestamps,
+        time_sync_offset_us: i64,
+        received_monotonic_us: u64
+    ) -> Duration {
+        let fresh_anchor = sent.wall_us as i64 - time_sync_offset_us - sent.monotonic_us as i64;
+        let (anchor, last) = self.senders.entry(sender_id).or_insert((fresh_anchor, sent.monotonic_us));
+        if sent.monotonic_us < *last {
+            *anchor = fresh_anchor;
+        }
+        *last = sent.monotonic_us;
+
+        let sent_at = sent.monotonic_us as i64 + *anchor;
+        let received_at = received_monotonic_us as i64 + self.local_anchor_us;
+        Duration::from_micros(received_at.saturating_sub(sent_at).max(0) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_survives_wall_clock_steps() {
+        // Our monotonic clock started at wall 1_000_000; the sender's wall clock runs
+        // 500 ms ahead of ours, so its monotonic zero is our wall 1_500_000
+        let mut estimator = DelayEstimator { local_anchor_us: 1_000_000, senders: HashMap::new() };
+        let offset = 500_000;
+
+        let first = SendTimestamps { wall_us: 2_010_000, monotonic_us: 10_000 };
+        assert_eq!(estimator.one_way_delay(7, first, offset, 510_300), Duration::from_micros(300));
+
+        // The sender's wall clock then steps forward 2 s; only monotonic time counts now
+        let stepped = SendTimestamps { wall_us: 4_020_000, monotonic_us: 20_000 };
+        assert_eq!(estimator.one_way_delay(7, stepped, offset, 520_250), Duration::from_micros(250));
+
+        // After a restart its monotonic clock starts over and is tied again
+        let restarted = SendTimestamps { wall_us: 2_030_000, monotonic_us: 50 };
+        assert_eq!(estimator.one_way_delay(7, restarted, offset, 530_400), Duration::from_micros(400));
+    }
+}
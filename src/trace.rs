@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+/// Follows one operator action (or any other causal chain) across every
+/// message, node and service it touches.
+///
+/// Written as 32 lowercase hex digits in logs, journals and JSON.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TraceId(u128);
+
+impl TraceId {
+    /// A new random id, never zero
+    pub fn random() -> Self {
+        Self(rand::random::<u128>().max(1))
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(u128::from_be_bytes(bytes))
+    }
+
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl fmt::Debug for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TraceId({})", self)
+    }
+}
+
+impl FromStr for TraceId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        if s.len() != 32 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("trace id '{}' must be 32 hex digits", s)));
+        }
+        u128::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("trace id '{}' is not hex", s)))
+    }
+}
+
+impl From<TraceId> for String {
+    fn from(id: TraceId) -> Self {
+        id.to_string()
+    }
+}
+
+impl TryFrom<String> for TraceId {
+    type Error = Error;
+
+    fn try_from(s: String) -> std::io::Result<Self> {
+        s.parse()
+    }
+}
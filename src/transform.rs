@@ -0,0 +1,282 @@
+//! Rewriting outgoing payloads per topic, e.g. converting units for a
+//! consumer, or coarsening precise positions to zones before traffic is
+//! bridged to a less-trusted network.
+//!
+//! [`Transforms`] holds rules, each for one topic (as named by the
+//! [`usage`](crate::usage) classifier, `*` for every topic) and applied in the
+//! order added. A rule is either declarative, a [`TransformAction`] on the
+//! fields of a JSON payload that can be read from configuration files, or a
+//! hook: any closure from payload to payload. Declarative rules leave
+//! payloads that aren't JSON objects as they are; formats of your own need a hook.
+//!
+//! Transforms apply to everything a sender sends once set with
+//! [`MulticastSender::with_transforms`](crate::transport::MulticastSender::with_transforms),
+//! or only to the copies a [`Mirror`](crate::mirror::Mirror) or a broker
+//! bridge passes on. Copies are transformed after encoding, so those of
+//! messages that are compressed, encrypted or fragmented can't be read and
+//! are withheld rather than passed on untransformed.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use zerocopy::AsBytes;
+
+use crate::extensions::Extensions;
+use crate::features::ProtocolFeatures;
+use crate::protocol;
+use crate::transport::{FleetMsgHeader, MessageType};
+use crate::usage::{TopicClassifier, default_topic};
+
+/// Topic name matching every topic
+pub const ANY_TOPIC: &str = "*";
+
+/// A payload rewrite; `None` leaves the payload as it is
+pub type TransformHook = Arc<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// What a declarative rule does to a JSON payload's fields, named by dotted
+/// paths such as `position.lat`; fields that are missing or not numbers are skipped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TransformAction {
+    /// Round numbers to this many decimal places, e.g. 2 for coordinates to about a kilometre
+    Round { fields: Vec<String>, decimals: u32 },
+    /// Remove the fields
+    Redact { fields: Vec<String> },
+    /// Replace each number `x` with `x * factor + offset`, e.g. m/s to km/h with a factor of 3.6
+    Scale {
+        fields: Vec<String>,
+        factor: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+}
+
+/// A declarative rule, as read from configuration:
+///
+/// ```toml
+/// [[bridge.transforms]]
+/// topic = "Data"
+/// action = "round"
+/// fields = ["lat", "lon"]
+/// decimals = 2
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransformRule {
+    pub topic: String,
+    #[serde(flatten)]
+    pub action: TransformAction,
+}
+
+#[derive(Clone)]
+enum Rewrite {
+    Declared(TransformAction),
+    Hook(TransformHook),
+}
+
+/// Rules rewriting outgoing payloads by topic; see the [module docs](self)
+#[derive(Clone, Default)]
+pub struct Transforms {
+    classifier: Option<TopicClassifier>,
+    rules: Vec<(String, Rewrite)>,
+}
+
+impl fmt::Debug for Transforms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<(&str, Option<&TransformAction>)> = self.rules.iter()
+            .map(|(topic, rewrite)| match rewrite {
+                Rewrite::Declared(action) => (topic.as_str(), Some(action)),
+                Rewrite::Hook(_) => (topic.as_str(), None),
+            })
+            .collect();
+        f.debug_struct("Transforms").field("rules", &rules).finish_non_exhaustive()
+    }
+}
+
+impl Transforms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transforms made of declarative rules, e.g. from a configuration file
+    pub fn from_rules(rules: impl IntoIterator<Item = TransformRule>) -> Self {
+        rules.into_iter().fold(Self::new(), Self::with_rule)
+    }
+
+    pub fn with_rule(mut self, rule: TransformRule) -> Self {
+        self.rules.push((rule.topic, Rewrite::Declared(rule.action)));
+        self
+    }
+
+    /// Rewrite payloads of `topic` with `hook`, which returns `None` to leave one as it is
+    pub fn with_hook(mut self, topic: &str, hook: impl Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static) -> Self {
+        self.rules.push((topic.to_string(), Rewrite::Hook(Arc::new(hook))));
+        self
+    }
+
+    /// Name topics with `classifier` instead of [`default_topic`]; use the same
+    /// one as the sender's [`UsageAccounting`](crate::usage::UsageAccounting), if any
+    pub fn with_classifier(mut self, classifier: impl Fn(MessageType, &[u8]) -> String + Send + Sync + 'static) -> Self {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// `payload` after every rule for its topic, or `None` if none changed it
+    pub fn apply(&self, msg_type: MessageType, payload: &[u8]) -> Option<Vec<u8>> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let topic = match &self.classifier {
+            Some(classifier) => classifier(msg_type, payload),
+            None => default_topic(msg_type, payload),
+        };
+        let mut rewritten: Option<Vec<u8>> = None;
+        for (_, rewrite) in self.rules.iter().filter(|(rule_topic, _)| *rule_topic == topic || rule_topic == ANY_TOPIC) {
+            let current = rewritten.as_deref().unwrap_or(payload);
+            let output = match rewrite {
+                Rewrite::Declared(action) => action.apply(current),
+                Rewrite::Hook(hook) => hook(current),
+            };
+            if output.is_some() {
+                rewritten = output;
+            }
+        }
+        rewritten
+    }
+
+    /// `datagram` with its payload transformed, under the same header fields
+    /// and extensions. `None` if it has to be withheld: it isn't a valid fleet
+    /// message, or its payload is compressed, encrypted, padded or a fragment.
+    pub fn apply_to_datagram<'a>(&self, datagram: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let header = FleetMsgHeader::parse(datagram).ok()?;
+        let body = datagram.get(protocol::HEADER_LEN..)?;
+        let (extensions, payload) = match header.features() {
+            features if features.is_empty() => (None, body),
+            ProtocolFeatures::EXTENSIONS => {
+                let (extensions, payload) = Extensions::split(body).ok()?;
+                if extensions.fragment().is_some() || extensions.padding() > 0 {
+                    return None;
+                }
+                (Some(extensions), payload)
+            }
+            _ => return None,
+        };
+        let Some(payload) = self.apply(header.message_type(), payload) else {
+            return Some(Cow::Borrowed(datagram));
+        };
+        let body = match extensions {
+            Some(extensions) => extensions.prepend_to(&payload),
+            None => payload,
+        };
+        if body.len() > protocol::MAX_PAYLOAD_LEN {
+            return None;
+        }
+        let mut header = header;
+        header.payload_len = (body.len() as u16).into();
+        let header = header.seal(&body);
+        Some(Cow::Owned([header.as_bytes(), &body].concat()))
+    }
+}
+
+impl TransformAction {
+    /// The rewritten payload, if it is a JSON object and any of the fields was there
+    fn apply(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        let Ok(Value::Object(mut object)) = serde_json::from_slice::<Value>(payload) else {
+            return None;
+        };
+        let mut changed = false;
+        match self {
+            TransformAction::Round { fields, decimals } => {
+                let scale = 10f64.powi((*decimals).min(15) as i32);
+                for field in fields {
+                    changed |= map_number(&mut object, field, |x| (x * scale).round() / scale);
+                }
+            }
+            TransformAction::Redact { fields } => {
+                for field in fields {
+                    let (parent, name) = match field.rsplit_once('.') {
+                        Some((parent, name)) => (lookup(&mut object, parent).and_then(Value::as_object_mut), name),
+                        None => (Some(&mut object), field.as_str()),
+                    };
+                    changed |= parent.and_then(|parent| parent.remove(name)).is_some();
+                }
+            }
+            TransformAction::Scale { fields, factor, offset } => {
+                for field in fields {
+                    changed |= map_number(&mut object, field, |x| x * factor + offset);
+                }
+            }
+        }
+        changed.then(|| serde_json::to_vec(&object).expect("JSON values serialize"))
+    }
+}
+
+/// The value at a dotted `path` below `object`
+fn lookup<'a>(object: &'a mut Map<String, Value>, path: &str) -> Option<&'a mut Value> {
+    let mut names = path.split('.');
+    let mut value = object.get_mut(names.next()?)?;
+    for name in names {
+        value = value.as_object_mut()?.get_mut(name)?;
+    }
+    Some(value)
+}
+
+fn map_number(object: &mut Map<String, Value>, path: &str, f: impl Fn(f64) -> f64) -> bool {
+    let Some(value) = lookup(object, path) else { return false };
+    let Some(number) = value.as_f64().and_then(|x| serde_json::Number::from_f64(f(x))) else { return false };
+    *value = Value::Number(number);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::FleetMessage;
+
+    fn coarse_positions() -> Transforms {
+        Transforms::from_rules([
+            TransformRule {
+                topic: "Data".into(),
+                action: TransformAction::Round { fields: vec!["pos.lat".into(), "pos.lon".into()], decimals: 2 },
+            },
+            TransformRule { topic: "Data".into(), action: TransformAction::Redact { fields: vec!["vin".into()] } },
+            TransformRule {
+                topic: ANY_TOPIC.into(),
+                action: TransformAction::Scale { fields: vec!["speed".into()], factor: 3.6, offset: 0.0 },
+            },
+        ])
+    }
+
+    #[test]
+    fn test_declared_rules_rewrite_json_fields() {
+        let payload = br#"{"pos":{"lat":52.520008,"lon":13.404954},"speed":10,"vin":"WVW123"}"#;
+        let rewritten = coarse_positions().apply(MessageType::Data, payload).unwrap();
+        let value: Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(value, serde_json::json!({"pos": {"lat": 52.52, "lon": 13.4}, "speed": 36.0}));
+
+        assert_eq!(coarse_positions().apply(MessageType::Data, b"not json"), None);
+        let hooked = Transforms::new().with_hook("Control ROLLOUT", |payload| Some(payload.to_ascii_lowercase()));
+        assert_eq!(hooked.apply(MessageType::Control, b"ROLLOUT GO").unwrap(), b"rollout go");
+        assert_eq!(hooked.apply(MessageType::Control, b"STOP"), None);
+    }
+
+    #[test]
+    fn test_datagrams_are_reframed_or_withheld() {
+        let transforms = coarse_positions();
+        let plain = FleetMessage::data(9, 3, &br#"{"vin":"WVW123","odometer":1200}"#[..]);
+        let bytes = plain.to_bytes();
+        let copy = FleetMessage::parse(&transforms.apply_to_datagram(&bytes).unwrap()).unwrap();
+        assert_eq!((copy.sender_id(), copy.sequence(), copy.payload()), (9, 3, &br#"{"odometer":1200}"#[..]));
+        assert_eq!(copy.timestamp_micros(), plain.timestamp_micros());
+
+        let untouched = FleetMessage::data(9, 4, &b"raw"[..]).to_bytes();
+        assert!(matches!(transforms.apply_to_datagram(&untouched), Some(Cow::Borrowed(_))));
+        let compressed = FleetMessage::data(9, 5, &b"\x78\x9c"[..]).with_features(ProtocolFeatures::COMPRESSION).to_bytes();
+        assert_eq!(transforms.apply_to_datagram(&compressed), None);
+    }
+}
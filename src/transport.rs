@@ -1,15 +1,81 @@
 use async_std::net::{UdpSocket, SocketAddr};
-use zerocopy::{AsBytes, FromBytes, FromZeroes};
+use serde::{Deserialize, Serialize};
+use futures::FutureExt;
+use futures::channel::oneshot;
+use zerocopy::byteorder::little_endian::{U16, U32, U64};
+use zerocopy::{AsBytes, FromBytes, FromZeroes, Unaligned};
 use std::net::{Ipv4Addr, IpAddr};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::alloc_counter::{self, Subsystem};
+use crate::bandwidth::{BandwidthManager, MessageClass};
+use crate::capabilities::Capabilities;
+use crate::error::{self, TransportError};
+use crate::extensions::{Extensions, FRAGMENT};
+use crate::features::{FeatureCodec, ProtocolFeatures};
+use crate::fragment::Fragment;
+use crate::heartbeat::{HeartbeatHandle, HeartbeatLane, Liveness};
+use crate::message::FleetMessage;
+use crate::mirror::Mirror;
+use crate::padding::PaddingBuckets;
+use crate::power::Wake;
+use crate::peers::PeerTable;
+use crate::protocol;
+use crate::rpc::Correlation;
+use crate::receiver::{self, BorrowedDelivery, Delivery, ReceiverConfig, ReceiverFilter, ValidationIssue};
+use crate::scheduling::BusyPoll;
+use crate::socket_filter::SocketFilter;
+use crate::shaping::ShapingCalendar;
+use crate::stats::TransportStats;
+use crate::timing::SendTimestamps;
+use crate::trace::TraceId;
+use crate::transform::Transforms;
+use crate::tdma::SlotSchedule;
+use crate::usage::UsageAccounting;
+use crate::zones::Zone;
 
 /// Fleet message types
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MessageType {
-    Heartbeat = 1,
-    Data = 2,
-    Control = 3,
+    Heartbeat,
+    Data,
+    Control,
+    /// Sent on clean shutdown so peers can drop the node without waiting for a timeout
+    Goodbye,
+    /// A node's view of fleet membership, exchanged periodically so views converge
+    Digest,
+    /// A code in [`APPLICATION_TYPES`](protocol::APPLICATION_TYPES), whose
+    /// meaning the application defines; name them in a
+    /// [`MessageTypeRegistry`](crate::message_types::MessageTypeRegistry)
+    Application(u8),
+    /// A code this build doesn't know, e.g. from newer firmware
+    Unknown(u8),
+}
+
+impl MessageType {
+    /// The code sent in the low nibble of the header's `msg_type`
+    pub const fn code(self) -> u8 {
+        match self {
+            MessageType::Heartbeat => 1,
+            MessageType::Data => 2,
+            MessageType::Control => 3,
+            MessageType::Goodbye => 4,
+            MessageType::Digest => 5,
+            MessageType::Application(code) | MessageType::Unknown(code) => code,
+        }
+    }
+
+    /// The application-defined type `code`, if it is in [`APPLICATION_TYPES`](protocol::APPLICATION_TYPES)
+    pub fn application(code: u8) -> Option<Self> {
+        protocol::APPLICATION_TYPES.contains(&code).then_some(MessageType::Application(code))
+    }
+
+    /// Whether this build or the application gives the type a meaning
+    pub fn is_known(self) -> bool {
+        !matches!(self, MessageType::Unknown(_))
+    }
 }
 
 impl From<u8> for MessageType {
@@ -18,195 +84,1248 @@ impl From<u8> for MessageType {
             1 => MessageType::Heartbeat,
             2 => MessageType::Data,
             3 => MessageType::Control,
-            _ => MessageType::Heartbeat, // Default fallback
+            4 => MessageType::Goodbye,
+            5 => MessageType::Digest,
+            code if protocol::APPLICATION_TYPES.contains(&code) => MessageType::Application(code),
+            code => MessageType::Unknown(code),
+        }
+    }
+}
+
+impl From<MessageType> for u8 {
+    fn from(msg_type: MessageType) -> Self {
+        msg_type.code()
+    }
+}
+
+/// How the header checksum is computed, which follows from the header's version
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeaderChecksum {
+    /// Sum of the header bytes, truncated to 16 bits; what versions 1 and 2 use.
+    /// Misses swapped bytes and any corruption that cancels out in the sum.
+    #[default]
+    Sum,
+    /// CRC32C of the header bytes, its two halves XORed together; version 3
+    /// and later. Computed in hardware where the CPU has CRC instructions.
+    /// Receivers older than version 3 reject these headers.
+    Crc32c,
+    /// CRC32C of the header bytes followed by the payload as sent, folded the
+    /// same way; version 4. Catches corrupted payloads too, at the cost of a
+    /// pass over every payload on both ends.
+    Crc32cPayload,
+}
+
+impl HeaderChecksum {
+    pub fn for_version(version: u8) -> Self {
+        match version {
+            version if version >= protocol::PAYLOAD_CHECKSUM_VERSION => HeaderChecksum::Crc32cPayload,
+            version if version >= protocol::CRC32C_VERSION => HeaderChecksum::Crc32c,
+            _ => HeaderChecksum::Sum,
         }
     }
+
+    /// The version headers checksummed this way are sent as
+    pub fn version(self) -> u8 {
+        match self {
+            HeaderChecksum::Sum => protocol::VERSION,
+            HeaderChecksum::Crc32c => protocol::CRC32C_VERSION,
+            HeaderChecksum::Crc32cPayload => protocol::PAYLOAD_CHECKSUM_VERSION,
+        }
+    }
+
+    pub fn covers_payload(self) -> bool {
+        self == HeaderChecksum::Crc32cPayload
+    }
+
+    /// The checksum of a header's wire bytes, checksum field excluded, and of
+    /// `payload` if this kind covers it
+    pub fn compute(self, header: &[u8], payload: &[u8]) -> u16 {
+        let crc = match self {
+            HeaderChecksum::Sum => return header.iter().map(|&byte| byte as u32).sum::<u32>() as u16,
+            HeaderChecksum::Crc32c => crc32c::crc32c(header),
+            HeaderChecksum::Crc32cPayload => crc32c::crc32c_append(crc32c::crc32c(header), payload),
+        };
+        (crc ^ (crc >> 16)) as u16
+    }
 }
 
-/// Fleet message header with proper fields
+/// Fleet message header with proper fields. Multi-byte fields are
+/// little-endian on the wire whatever the host's byte order; read and set
+/// them with `get()` and `set()` (or `.into()`).
 #[repr(C)]
-#[derive(FromBytes, AsBytes, FromZeroes, Debug, Clone, Copy)]
+#[derive(FromBytes, AsBytes, FromZeroes, Unaligned, Debug, Clone, Copy)]
 pub struct FleetMsgHeader {
-    pub magic: u32,        // Magic number for validation (0xFEED)
+    pub magic: U32,        // Magic number for validation (0xFEED)
     pub version: u8,       // Protocol version
-    pub msg_type: u8,      // Message type (see MessageType enum)
-    pub sequence: u16,     // Sequence number
-    pub timestamp: u64,    // Unix timestamp in milliseconds
-    pub sender_id: u32,    // Unique sender identifier
-    pub payload_len: u16,  // Length of payload following header
-    pub checksum: u16,     // Simple checksum for integrity
+    pub msg_type: u8,      // Message type (see MessageType enum); high nibble flags ProtocolFeatures
+    pub sequence: U16,     // Sequence number
+    pub timestamp: U64,    // Unix timestamp in microseconds (milliseconds in version 1)
+    pub sender_id: U32,    // Unique sender identifier
+    pub payload_len: U16,  // Length of payload following header
+    pub checksum: U16,     // Header checksum, see HeaderChecksum
 }
 
 impl FleetMsgHeader {
-    const MAGIC: u32 = 0xFEED;
-    const VERSION: u8 = 1;
+    const MAGIC: u32 = protocol::MAGIC;
+    /// Protocol version this build sends
+    pub const VERSION: u8 = protocol::VERSION;
+    /// Oldest protocol version still accepted; version 1 timestamps are in milliseconds
+    pub const MIN_VERSION: u8 = protocol::MIN_VERSION;
+    /// Newest protocol version accepted; version 3 headers carry a CRC32C
+    /// checksum, version 4 headers one that covers the payload too
+    pub const MAX_VERSION: u8 = protocol::MAX_VERSION;
+    /// Bits of `msg_type` holding the `MessageType`; the rest flag optional features
+    pub const MSG_TYPE_MASK: u8 = protocol::MSG_TYPE_MASK;
 
     pub fn new(msg_type: MessageType, sender_id: u32, sequence: u16, payload_len: u16) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
-            .as_millis() as u64;
+            .as_micros() as u64;
 
         let mut header = Self {
-            magic: Self::MAGIC,
+            magic: Self::MAGIC.into(),
             version: Self::VERSION,
-            msg_type: msg_type as u8,
-            sequence,
-            timestamp,
-            sender_id,
-            payload_len,
-            checksum: 0,
+            msg_type: msg_type.code(),
+            sequence: sequence.into(),
+            timestamp: timestamp.into(),
+            sender_id: sender_id.into(),
+            payload_len: payload_len.into(),
+            checksum: U16::ZERO,
         };
 
-        // Calculate simple checksum (sum of all bytes except checksum field)
-        header.checksum = header.calculate_checksum();
+        header.checksum = header.calculate_checksum_without_field().into();
         header
     }
 
+    /// Magic, version and checksum check out. A checksum that covers the
+    /// payload can't be checked without it; see [`verify`](Self::verify).
     pub fn is_valid(&self) -> bool {
-        self.magic == Self::MAGIC &&
-        self.version == Self::VERSION &&
-        self.checksum == self.calculate_checksum_without_field()
+        self.magic.get() == Self::MAGIC &&
+        (Self::MIN_VERSION..=Self::MAX_VERSION).contains(&self.version) &&
+        (self.covers_payload() || self.checksum.get() == self.calculate_checksum_without_field())
     }
 
-    fn calculate_checksum(&self) -> u16 {
-        let bytes = self.as_bytes();
-        let mut sum: u32 = 0;
+    /// Whether the checksum matches this header and, if it covers it, `payload`
+    pub fn verify(&self, payload: &[u8]) -> bool {
+        self.checksum.get() == self.calculate_checksum_for(payload)
+    }
 
-        // Sum all bytes except the checksum field (last 2 bytes)
-        for &byte in &bytes[..bytes.len() - 2] {
-            sum += byte as u32;
-        }
+    /// Read and check the header at the start of `datagram`, and the payload
+    /// after it if the checksum covers it. A message type this build doesn't
+    /// know is no error, as under the standard validation policy.
+    pub fn parse(datagram: &[u8]) -> error::Result<Self> {
+        let Some(header) = Self::read_from_prefix(datagram) else {
+            return Err(TransportError::Invalid(vec![ValidationIssue::Truncated { len: datagram.len() }]));
+        };
+        let issues: Vec<ValidationIssue> = header.validation_issues_for(&datagram[protocol::HEADER_LEN..])
+            .into_iter()
+            .filter(ValidationIssue::is_malformed)
+            .collect();
+        if issues.is_empty() { Ok(header) } else { Err(TransportError::from_issues(issues)) }
+    }
 
-        (sum & 0xFFFF) as u16
+    /// Everything wrong with this header, including a message type this build
+    /// doesn't know. A checksum that covers the payload isn't checked; see
+    /// [`validation_issues_for`](Self::validation_issues_for).
+    pub fn validation_issues(&self) -> Vec<ValidationIssue> {
+        self.issues(None)
+    }
+
+    /// Everything wrong with this header and the checksum over it and `payload`
+    pub fn validation_issues_for(&self, payload: &[u8]) -> Vec<ValidationIssue> {
+        self.issues(Some(payload))
+    }
+
+    fn issues(&self, payload: Option<&[u8]>) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        if self.magic.get() != Self::MAGIC {
+            issues.push(ValidationIssue::BadMagic(self.magic.get()));
+        }
+        if !(Self::MIN_VERSION..=Self::MAX_VERSION).contains(&self.version) {
+            issues.push(ValidationIssue::UnsupportedVersion(self.version));
+        }
+        let checksum_ok = match payload {
+            Some(payload) => self.verify(payload),
+            None => self.covers_payload() || self.checksum.get() == self.calculate_checksum_without_field(),
+        };
+        if !checksum_ok {
+            issues.push(ValidationIssue::BadChecksum);
+        }
+        if let MessageType::Unknown(msg_type) = self.message_type() {
+            issues.push(ValidationIssue::UnknownMessageType(msg_type));
+        }
+        issues
     }
 
     fn calculate_checksum_without_field(&self) -> u16 {
-        let mut temp = self.clone();
-        temp.checksum = 0;
-        temp.calculate_checksum()
+        self.calculate_checksum_for(&[])
+    }
+
+    /// The checksum field is excluded (last 2 bytes); `payload` only counts if the checksum covers it
+    fn calculate_checksum_for(&self, payload: &[u8]) -> u16 {
+        let bytes = self.as_bytes();
+        self.checksum_kind().compute(&bytes[..bytes.len() - 2], payload)
+    }
+
+    /// Send time in microseconds since the Unix epoch, whichever version sent it
+    pub fn timestamp_micros(&self) -> u64 {
+        match self.version {
+            1 => self.timestamp.get().saturating_mul(1000),
+            _ => self.timestamp.get(),
+        }
+    }
+
+    /// Send time in milliseconds since the Unix epoch, whichever version sent it
+    pub fn timestamp_millis(&self) -> u64 {
+        match self.version {
+            1 => self.timestamp.get(),
+            _ => self.timestamp.get() / 1000,
+        }
     }
 
     pub fn message_type(&self) -> MessageType {
-        MessageType::from(self.msg_type)
+        MessageType::from(self.msg_type & Self::MSG_TYPE_MASK)
+    }
+
+    /// How this header's checksum is computed, by its version
+    pub fn checksum_kind(&self) -> HeaderChecksum {
+        HeaderChecksum::for_version(self.version)
     }
+
+    /// Whether the checksum covers the payload as well as the header
+    pub fn covers_payload(&self) -> bool {
+        self.checksum_kind().covers_payload()
+    }
+
+    /// Recompute the checksum for this header followed by `payload` as sent;
+    /// the payload only counts if the checksum covers it
+    pub fn seal(mut self, payload: &[u8]) -> Self {
+        self.checksum = self.calculate_checksum_for(payload).into();
+        self
+    }
+
+    /// The same header, sent as the version that uses `checksum`. One that
+    /// covers the payload is computed as if the payload were empty, so
+    /// [`seal`](Self::seal) the header once its payload is known.
+    pub fn with_checksum(mut self, checksum: HeaderChecksum) -> Self {
+        if self.checksum_kind() != checksum || self.version < protocol::VERSION {
+            return self.with_version(checksum.version());
+        }
+        self.checksum = self.calculate_checksum_without_field().into();
+        self
+    }
+
+    /// Protocol version the header was sent as, which decides the unit of its
+    /// timestamp and how its checksum is computed
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// The same header sent as `version`: the timestamp is converted to its
+    /// unit and the checksum computed its way, as for [`with_checksum`](Self::with_checksum)
+    pub fn with_version(mut self, version: u8) -> Self {
+        let micros = self.timestamp_micros();
+        let timestamp = match version {
+            1 => micros / 1000,
+            _ => micros,
+        };
+        self.version = version;
+        self.timestamp = timestamp.into();
+        self.checksum = self.calculate_checksum_without_field().into();
+        self
+    }
+
+    /// Optional features applied to this message's payload
+    pub fn features(&self) -> ProtocolFeatures {
+        ProtocolFeatures::from_bits(self.msg_type >> protocol::FEATURES_SHIFT)
+    }
+
+    /// Flag `features` as applied to the payload
+    pub fn with_features(mut self, features: ProtocolFeatures) -> Self {
+        self.msg_type = (self.msg_type & Self::MSG_TYPE_MASK) | (features.bits() << protocol::FEATURES_SHIFT);
+        self.checksum = self.calculate_checksum_without_field().into();
+        self
+    }
+}
+
+/// Heartbeat payload announcing the sender's incarnation
+pub fn heartbeat_payload(incarnation: u64) -> [u8; 8] {
+    incarnation.to_le_bytes()
+}
+
+/// Incarnation carried by a heartbeat payload; `None` for the empty heartbeats of older senders
+pub fn heartbeat_incarnation(payload: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(payload.get(..8)?.try_into().ok()?))
+}
+
+/// Roles and capabilities announced after the incarnation; `None` if the heartbeat carries none
+pub fn heartbeat_capabilities(payload: &[u8]) -> Option<Capabilities> {
+    Capabilities::decode(payload.get(8..).filter(|rest| !rest.is_empty())?)
+}
+
+/// Multicast group for traffic aimed at one role or capability, hashed (FNV-1a)
+/// into the organization-local 239.192.0.0/14 range. Distinct tags can collide,
+/// so keep the number of tags in use small or use unicast routing.
+pub fn tag_group(tag: &str) -> Ipv4Addr {
+    let hash = tag.bytes().fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+    Ipv4Addr::from(0xEFC0_0000 | (hash & 0x0003_FFFF))
+}
+
+/// How [`MulticastSender::send_to_tagged`] reaches the tagged peers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TagRouting {
+    /// One datagram per tagged peer in the peer table, with pairwise features
+    #[default]
+    Unicast,
+    /// One datagram to the tag's [`tag_group`], which tagged receivers join
+    Group,
 }
 
 /// Multicast receiver that processes incoming fleet messages
 pub async fn start_multicast_rx(
     group: Ipv4Addr,
     port: u16,
+    message_handler: impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static
+) -> error::Result<()> {
+    start_multicast_rx_with_codec(group, port, FeatureCodec::default(), message_handler).await
+}
+
+/// Multicast receiver that strips optional features (e.g. decrypts with the fleet key)
+/// before handing payloads to `message_handler`
+pub async fn start_multicast_rx_with_codec(
+    group: Ipv4Addr,
+    port: u16,
+    codec: FeatureCodec,
+    message_handler: impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static
+) -> error::Result<()> {
+    start_multicast_rx_groups(&[group], port, codec, message_handler).await
+}
+
+/// Multicast receiver that only hands `message_handler` the messages `filter`
+/// accepts, dropping the rest before they are validated or decoded
+pub async fn start_multicast_rx_filtered(
+    group: Ipv4Addr,
+    port: u16,
+    filter: ReceiverFilter,
+    mut message_handler: impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static
+) -> error::Result<()> {
+    let handler = move |delivery: Delivery| message_handler(delivery.header, delivery.payload, delivery.addr);
+    start_multicast_rx_extended(&[group], port, ReceiverConfig::new().with_filter(filter), handler).await
+}
+
+/// Multicast receiver joined to several groups on one port, e.g. the fleet
+/// group plus the [`tag_group`] of each role this node has
+pub async fn start_multicast_rx_groups(
+    groups: &[Ipv4Addr],
+    port: u16,
+    codec: FeatureCodec,
     mut message_handler: impl FnMut(FleetMsgHeader, Vec<u8>, SocketAddr) + Send + 'static
-) -> std::io::Result<()> {
+) -> error::Result<()> {
+    let handler = move |delivery: Delivery| message_handler(delivery.header, delivery.payload, delivery.addr);
+    start_multicast_rx_extended(groups, port, ReceiverConfig::new().with_codec(codec), handler).await
+}
+
+/// Like [`start_multicast_rx_groups`], handing over each message as a
+/// [`Delivery`] (with its extensions, e.g. the trace id, and any validation
+/// issues) and decoding, bounding and validating messages as `config` says
+pub async fn start_multicast_rx_extended(
+    groups: &[Ipv4Addr],
+    port: u16,
+    config: ReceiverConfig,
+    message_handler: impl FnMut(Delivery) + Send + 'static
+) -> error::Result<()> {
+    receive_loop(join(groups, port).await?, config, message_handler).await
+}
+
+/// Like [`start_multicast_rx_extended`], without copying payloads out of the
+/// receive buffer: `message_handler` gets each one borrowed (unless it had to
+/// be decrypted, decompressed or reassembled) and can only look at it until
+/// it returns. Handlers that keep messages take them with
+/// [`BorrowedDelivery::into_owned`].
+pub async fn start_multicast_rx_borrowed(
+    groups: &[Ipv4Addr],
+    port: u16,
+    config: ReceiverConfig,
+    message_handler: impl FnMut(BorrowedDelivery<'_>) + Send + 'static
+) -> error::Result<()> {
+    receive_loop_borrowed(join(groups, port).await?, config, message_handler).await
+}
+
+/// A socket bound to `port` and joined to `groups`
+pub(crate) async fn join(groups: &[Ipv4Addr], port: u16) -> error::Result<UdpSocket> {
     let socket = UdpSocket::bind(("0.0.0.0", port)).await?;
-    socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+    for group in groups {
+        socket.join_multicast_v4(*group, Ipv4Addr::UNSPECIFIED)?;
+    }
 
-    println!("Started multicast receiver on {}:{}", group, port);
+    println!("Started multicast receiver on {:?}:{}", groups, port);
+    Ok(socket)
+}
 
-    let mut buf = vec![0u8; 1500]; // Standard MTU size
+/// Messages a [`MulticastReceiver`] holds for its consumer by default
+pub const DEFAULT_RECEIVE_QUEUE_LEN: usize = 1024;
 
-    loop {
-        match socket.recv_from(&mut buf).await {
-            Ok((len, addr)) => {
-                if len < std::mem::size_of::<FleetMsgHeader>() {
-                    eprintln!("Received packet too small for header from {}", addr);
-                    continue;
+/// Multicast receiver handing out messages as a [`Stream`](futures::Stream)
+/// of [`Delivery`], instead of calling a handler.
+///
+/// Messages are received in a background task and queued for the consumer;
+/// when the consumer falls behind a full queue, further messages are dropped
+/// (and counted) rather than slowing the receive loop down. Dropping the
+/// receiver stops the task and closes the socket.
+#[derive(Debug)]
+pub struct MulticastReceiver {
+    deliveries: async_std::channel::Receiver<Delivery>,
+    dropped: Arc<AtomicU64>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl MulticastReceiver {
+    /// Join `groups` on `port`, receiving as `config` says
+    pub async fn bind(groups: &[Ipv4Addr], port: u16, config: ReceiverConfig) -> error::Result<Self> {
+        Ok(Self::from_socket(join(groups, port).await?, config, DEFAULT_RECEIVE_QUEUE_LEN))
+    }
+
+    /// Receive on a socket that is already bound and joined, queueing up to `queue_len` messages
+    pub fn from_socket(socket: UdpSocket, config: ReceiverConfig, queue_len: usize) -> Self {
+        let (queue, deliveries) = async_std::channel::bounded(queue_len.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let counter = dropped.clone();
+        let handler = move |delivery: Delivery| {
+            if let Err(async_std::channel::TrySendError::Full(_)) = queue.try_send(delivery) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        async_std::task::spawn(futures::future::select(Box::pin(receive_loop(socket, config, handler)), stopped));
+        Self { deliveries, dropped, _stop: stop }
+    }
+
+    /// The next message
+    pub async fn recv(&self) -> Option<Delivery> {
+        self.deliveries.recv().await.ok()
+    }
+
+    /// Every message queued right now, without waiting; e.g. to hand a
+    /// [`Conflator`](crate::compaction::Conflator) after a stall
+    pub fn backlog(&self) -> Vec<Delivery> {
+        std::iter::from_fn(|| self.deliveries.try_recv().ok()).collect()
+    }
+
+    /// Messages lost because the consumer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl futures::Stream for MulticastReceiver {
+    type Item = Delivery;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Delivery>> {
+        std::pin::Pin::new(&mut self.deliveries).poll_next(cx)
+    }
+}
+
+/// Receive on a socket that is already bound and joined
+pub(crate) async fn receive_loop(
+    socket: UdpSocket,
+    config: ReceiverConfig,
+    mut message_handler: impl FnMut(Delivery) + Send + 'static
+) -> error::Result<()> {
+    receive_loop_borrowed(socket, config, move |delivery: BorrowedDelivery<'_>| message_handler(delivery.into_owned())).await
+}
+
+/// [`receive_loop`], handing over payloads borrowed from the receive buffer.
+/// With [thread scheduling](ReceiverConfig::with_thread_scheduling) or
+/// [busy polling](ReceiverConfig::with_busy_poll) the loop runs on a thread of
+/// its own, which stops when this future is dropped.
+async fn receive_loop_borrowed(
+    socket: UdpSocket,
+    config: ReceiverConfig,
+    message_handler: impl FnMut(BorrowedDelivery<'_>) + Send + 'static
+) -> error::Result<()> {
+    if config.kernel_filter()
+        && let Err(e) = SocketFilter::for_config(&config).and_then(|filter| filter.attach(&socket))
+    {
+        eprintln!("Filtering in userspace only: {}", e);
+    }
+    #[cfg(all(target_os = "linux", feature = "ebpf"))]
+    if let Some(path) = config.ebpf_filter()
+        && let Err(e) = crate::socket_filter::EbpfFilter::load(path, &config).and_then(|filter| filter.attach(&socket))
+    {
+        eprintln!("Filtering without the eBPF program: {}", e);
+    }
+    let scheduling = config.thread_scheduling().cloned();
+    if scheduling.is_none() && config.busy_poll().is_none() {
+        return receive_here(socket, config, message_handler).await;
+    }
+    let (done, finished) = oneshot::channel();
+    let (_cancel, cancelled) = oneshot::channel::<()>();
+    std::thread::Builder::new().name("fleetlink-rx".to_string()).spawn(move || {
+        if let Some(Err(e)) = scheduling.map(|scheduling| scheduling.apply_to_current_thread()) {
+            eprintln!("Receiving without the configured thread scheduling: {}", e);
+        }
+        if let Some(busy) = config.busy_poll() {
+            let _ = done.send(busy_receive(socket, config, message_handler, busy, cancelled));
+            return;
+        }
+        let receiving = Box::pin(receive_here(socket, config, message_handler));
+        if let futures::future::Either::Left((result, _)) = async_std::task::block_on(futures::future::select(receiving, cancelled)) {
+            let _ = done.send(result);
+        }
+    })?;
+    finished.await.unwrap_or(Ok(()))
+}
+
+/// One byte over the configured length, to tell a datagram that didn't fit
+/// from one that just did
+fn receive_buffer(config: &ReceiverConfig) -> Vec<u8> {
+    let _scope = alloc_counter::scope(Subsystem::RxBuffers);
+    vec![0u8; config.buffer_len() + 1]
+}
+
+/// What every receive loop does with a datagram: filter it, validate it,
+/// reassemble fragments, count it and hand it over
+fn intake<'c>(
+    config: &'c ReceiverConfig,
+    mut message_handler: impl FnMut(BorrowedDelivery<'_>) + 'c
+) -> impl FnMut(&[u8], SocketAddr) + 'c {
+    let mut reassembler = config.reassembler();
+    move |datagram: &[u8], addr: SocketAddr| {
+        if !config.screen(datagram, addr) {
+            return;
+        }
+        let now = Instant::now();
+        let expired = reassembler.expire(now);
+        if expired > 0 {
+            eprintln!("Dropped {} incomplete fragmented messages", expired);
+        }
+        match receiver::inspect_borrowed(datagram, addr, config) {
+            Ok(delivery) if !config.admits(&delivery.header, &delivery.extensions, &delivery.payload, addr) => {}
+            Ok(delivery) => {
+                // Fragments are kept until the whole message is there, so they are copied
+                let delivery = match delivery.extensions.get(FRAGMENT) {
+                    Some(_) => match reassembler.push(delivery.into_owned(), now) {
+                        Some(delivery) => BorrowedDelivery::from(delivery),
+                        None => return,
+                    },
+                    None => delivery,
+                };
+                if let Some(usage) = config.usage() {
+                    let topic = usage.topic(delivery.header.message_type(), &delivery.payload);
+                    usage.record_received(&topic, protocol::HEADER_LEN + delivery.payload.len(), now);
                 }
+                message_handler(delivery);
+            }
+            Err(issues) => eprintln!("Dropped message from {}: {}", addr, receiver::describe(&issues)),
+        }
+    }
+}
 
-                if let Some(header) = FleetMsgHeader::read_from_prefix(&buf[..len]) {
-                    if header.is_valid() {
-                        let header_size = std::mem::size_of::<FleetMsgHeader>();
-                        let payload = if len > header_size {
-                            buf[header_size..len].to_vec()
-                        } else {
-                            Vec::new()
-                        };
-
-                        // Verify payload length matches header
-                        if payload.len() == header.payload_len as usize {
-                            message_handler(header.clone(), payload, addr);
-                        } else {
-                            eprintln!("Payload length mismatch from {}: expected {}, got {}",
-                                     addr, header.payload_len, payload.len());
-                        }
-                    } else {
-                        eprintln!("Invalid message header from {}", addr);
-                    }
+/// Spin on the socket, without ever waiting in the kernel, until `cancelled`
+/// fires; see [`BusyPoll`]
+fn busy_receive(
+    socket: UdpSocket,
+    config: ReceiverConfig,
+    message_handler: impl FnMut(BorrowedDelivery<'_>),
+    busy: BusyPoll,
+    mut cancelled: oneshot::Receiver<()>
+) -> error::Result<()> {
+    let socket = std::net::UdpSocket::try_from(socket)?;
+    socket.set_nonblocking(true)?;
+    if let Err(e) = busy.apply_to_socket(&socket) {
+        eprintln!("Busy polling without kernel busy polling: {}", e);
+    }
+    let mut buf = receive_buffer(&config);
+    let mut receive = intake(&config, message_handler);
+    let mut last_received = Instant::now();
+    let mut backoff = Duration::ZERO;
+    while let Ok(None) = cancelled.try_recv() {
+        match socket.recv_from(&mut buf) {
+            Ok((len, addr)) => {
+                receive(&buf[..len], addr);
+                last_received = Instant::now();
+                backoff = Duration::ZERO;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if last_received.elapsed() < busy.spin {
+                    std::hint::spin_loop();
                 } else {
-                    eprintln!("Failed to parse message header from {}", addr);
+                    backoff = (backoff * 2).clamp(Duration::from_micros(1), busy.max_backoff);
+                    std::thread::sleep(backoff);
                 }
             }
-            Err(e) => {
-                eprintln!("Error receiving multicast message: {}", e);
+            Err(e) => eprintln!("Error receiving multicast message: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// The receive loop, on whichever thread polls it
+async fn receive_here(
+    socket: UdpSocket,
+    config: ReceiverConfig,
+    message_handler: impl FnMut(BorrowedDelivery<'_>) + Send + 'static
+) -> error::Result<()> {
+    let mut buf = receive_buffer(&config);
+    let mut receive = intake(&config, message_handler);
+
+    loop {
+        let Some(policy) = config.power_policy() else {
+            match socket.recv_from(&mut buf).await {
+                Ok((len, addr)) => receive(&buf[..len], addr),
                 // Continue listening despite errors
+                Err(e) => eprintln!("Error receiving multicast message: {}", e),
+            }
+            continue;
+        };
+        match policy.next(SystemTime::now()) {
+            Wake::Listen(window) => match async_std::future::timeout(window, socket.recv_from(&mut buf)).await {
+                Ok(Ok((len, addr))) => receive(&buf[..len], addr),
+                Ok(Err(e)) => eprintln!("Error receiving multicast message: {}", e),
+                Err(_) => {}
+            },
+            Wake::Sleep(wait) => {
+                async_std::task::sleep(wait).await;
+                // Handle what queued while asleep, without waiting for more
+                for _ in 0..policy.max_batch() {
+                    match socket.recv_from(&mut buf).now_or_never() {
+                        Some(Ok((len, addr))) => receive(&buf[..len], addr),
+                        Some(Err(e)) => eprintln!("Error receiving multicast message: {}", e),
+                        None => break,
+                    }
+                }
             }
         }
     }
 }
 
-/// Multicast sender for broadcasting fleet messages
+/// Multicast sender for broadcasting fleet messages.
+///
+/// Announces a `Goodbye` when shut down or dropped.
 pub struct MulticastSender {
     socket: UdpSocket,
+    /// Blocking handle on the same socket, for the goodbye sent from `Drop`
+    goodbye_socket: std::net::UdpSocket,
+    departed: bool,
     group: Ipv4Addr,
     port: u16,
     sender_id: u32,
-    sequence: u16,
+    /// Shared with the heartbeat task, if one is running
+    sequence: Arc<AtomicU16>,
+    started: Instant,
+    /// Tells the heartbeat task the sender has said goodbye
+    stop_heartbeats: Arc<AtomicBool>,
+    /// Changes on every restart so receivers can tell a sequence reset from loss
+    incarnation: u64,
+    capabilities: Capabilities,
+    codec: FeatureCodec,
+    tag_routing: TagRouting,
+    /// Known peers, whose announced features decide what may be applied to a multicast
+    peers: Option<Arc<Mutex<PeerTable>>>,
+    slot_schedule: Option<SlotSchedule>,
+    bandwidth: Option<BandwidthManager>,
+    shaping: Option<ShapingCalendar>,
+    padding: Option<PaddingBuckets>,
+    mirrors: Vec<Mirror>,
+    /// Larger messages are sent as fragments
+    max_datagram_len: usize,
+    stats: Arc<TransportStats>,
+    usage: Option<Arc<UsageAccounting>>,
+    transforms: Option<Transforms>,
+    /// Stamped on every multicast; shared with the heartbeat task
+    zone: Arc<Mutex<Option<Zone>>>,
+    /// Protocol version headers are sent as
+    version: u8,
 }
 
 impl MulticastSender {
-    pub async fn new(group: Ipv4Addr, port: u16, sender_id: u32) -> std::io::Result<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    pub async fn new(group: Ipv4Addr, port: u16, sender_id: u32) -> error::Result<Self> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
         socket.set_multicast_ttl_v4(1)?; // Local network only
+        let goodbye_socket = socket.try_clone()?;
+        let socket = UdpSocket::from(socket);
 
         println!("Created multicast sender for {}:{} with ID {}", group, port, sender_id);
 
         Ok(Self {
             socket,
+            goodbye_socket,
+            departed: false,
             group,
             port,
             sender_id,
-            sequence: 0,
+            sequence: Arc::new(AtomicU16::new(0)),
+            started: Instant::now(),
+            stop_heartbeats: Arc::new(AtomicBool::new(false)),
+            incarnation: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64,
+            capabilities: Capabilities::default(),
+            codec: FeatureCodec::default(),
+            tag_routing: TagRouting::default(),
+            peers: None,
+            slot_schedule: None,
+            bandwidth: None,
+            shaping: None,
+            padding: None,
+            mirrors: Vec::new(),
+            max_datagram_len: protocol::MTU_DATAGRAM_LEN,
+            stats: Arc::new(TransportStats::new()),
+            usage: None,
+            transforms: None,
+            zone: Arc::default(),
+            version: protocol::VERSION,
         })
     }
 
+    /// Announce a specific incarnation (e.g. a persisted boot counter) instead of the start time
+    pub fn with_incarnation(mut self, incarnation: u64) -> Self {
+        self.incarnation = incarnation;
+        self
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    /// Roles and capabilities to announce in every heartbeat
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Stamp `zone` on every multicast, so receivers can filter by area; see
+    /// [`zones`](crate::zones). Version-1 headers can't carry it and leave it out.
+    pub fn with_zone(self, zone: Zone) -> Self {
+        self.set_zone(Some(zone));
+        self
+    }
+
+    /// Change the stamped zone, e.g. when a vehicle crosses into another;
+    /// the heartbeat task picks it up too
+    pub fn set_zone(&self, zone: Option<Zone>) {
+        *self.zone.lock().unwrap() = zone;
+    }
+
+    pub fn zone(&self) -> Option<Zone> {
+        self.zone.lock().unwrap().clone()
+    }
+
+    pub fn sender_id(&self) -> u32 {
+        self.sender_id
+    }
+
+    /// Optional features this node supports (e.g. with the fleet encryption key)
+    pub fn with_codec(mut self, codec: FeatureCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Apply optional features to Data and Control messages when every peer in
+    /// `peers` has announced support for them
+    pub fn with_peer_table(mut self, peers: Arc<Mutex<PeerTable>>) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    pub fn with_tag_routing(mut self, routing: TagRouting) -> Self {
+        self.tag_routing = routing;
+        self
+    }
+
+    /// Only transmit inside this sender's TDMA slot
+    pub fn with_slot_schedule(mut self, schedule: SlotSchedule) -> Self {
+        self.slot_schedule = Some(schedule);
+        self
+    }
+
+    /// Enforce per-class shares of the link budget on every send
+    pub fn with_bandwidth_manager(mut self, manager: BandwidthManager) -> Self {
+        self.bandwidth = Some(manager);
+        self
+    }
+
+    /// Hold back traffic in the calendar's windows; see [`send_emergency`](Self::send_emergency)
+    /// for messages that must go out regardless
+    pub fn with_shaping_calendar(mut self, calendar: ShapingCalendar) -> Self {
+        self.shaping = Some(calendar);
+        self
+    }
+
+    /// Pad encrypted messages to `buckets` so their lengths don't give away
+    /// what they are. Applies to targets that support extensions; their
+    /// payloads are not compressed.
+    pub fn with_padding(mut self, buckets: PaddingBuckets) -> Self {
+        self.padding = Some(buckets);
+        self
+    }
+
+    /// Also send a copy of every message to `mirror`; may be given more than once
+    pub fn with_mirror(mut self, mirror: Mirror) -> Self {
+        self.mirrors.push(mirror);
+        self
+    }
+
+    /// Split messages that would make datagrams longer than `len` (header
+    /// included) into fragments; the default fits a 1500-byte Ethernet MTU
+    pub fn with_max_datagram_len(mut self, len: usize) -> Self {
+        self.max_datagram_len = len.min(protocol::MAX_DATAGRAM_LEN);
+        self
+    }
+
+    /// Checksum headers with `checksum`, sending them as the protocol version
+    /// that uses it. Only switch to [`HeaderChecksum::Crc32c`] once every
+    /// receiver accepts version 3.
+    pub fn with_header_checksum(mut self, checksum: HeaderChecksum) -> Self {
+        self.version = checksum.version();
+        self
+    }
+
+    /// Send headers as `version`, e.g. 1 while part of the fleet still only
+    /// reads version 1, or the newest version once every receiver accepts it.
+    /// Fails with [`ValidationIssue::UnsupportedVersion`] for a version this
+    /// build can't send. Version 1 has no feature flags, so a sender on it
+    /// sends payloads without compression, encryption or extensions, and
+    /// can't fragment.
+    pub fn with_protocol_version(mut self, version: u8) -> error::Result<Self> {
+        if !(protocol::MIN_VERSION..=protocol::MAX_VERSION).contains(&version) {
+            return Err(TransportError::Invalid(vec![ValidationIssue::UnsupportedVersion(version)]));
+        }
+        self.version = version;
+        Ok(self)
+    }
+
+    pub fn protocol_version(&self) -> u8 {
+        self.version
+    }
+
+    /// Count sends into an existing (e.g. node-wide) stats instance
+    pub fn with_stats(mut self, stats: Arc<TransportStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+
+    /// Also count sends per topic, e.g. into the node's shared accounting
+    pub fn with_usage(mut self, usage: Arc<UsageAccounting>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// Rewrite payloads by topic before anything else is done to them, e.g.
+    /// on a sender whose group reaches a less-trusted network
+    pub fn with_transforms(mut self, transforms: Transforms) -> Self {
+        self.transforms = Some(transforms);
+        self
+    }
+
     pub async fn send_message(
         &mut self,
         msg_type: MessageType,
         payload: &[u8]
-    ) -> std::io::Result<()> {
-        let header = FleetMsgHeader::new(
-            msg_type,
-            self.sender_id,
-            self.sequence,
-            payload.len() as u16
-        );
+    ) -> error::Result<()> {
+        self.send_message_as(MessageClass::for_message_type(msg_type), msg_type, payload).await
+    }
+
+    /// Send a message, charging it to an explicit bandwidth class
+    pub async fn send_message_as(
+        &mut self,
+        class: MessageClass,
+        msg_type: MessageType,
+        payload: &[u8]
+    ) -> error::Result<()> {
+        self.send_traced_as(class, msg_type, payload, TraceId::random()).await
+    }
+
+    /// Send a message carrying `trace` instead of a fresh trace id, e.g. to
+    /// tie together every message of one operator action
+    pub async fn send_traced(
+        &mut self,
+        msg_type: MessageType,
+        payload: &[u8],
+        trace: TraceId
+    ) -> error::Result<()> {
+        self.send_traced_as(MessageClass::for_message_type(msg_type), msg_type, payload, trace).await
+    }
+
+    /// Reply to (or acknowledge) a received message under its trace id, or a
+    /// fresh one if it didn't carry any
+    pub async fn reply_to(
+        &mut self,
+        received: &Extensions,
+        msg_type: MessageType,
+        payload: &[u8]
+    ) -> error::Result<()> {
+        let trace = received.trace_id().unwrap_or_else(TraceId::random);
+        self.send_traced(msg_type, payload, trace).await
+    }
 
-        self.sequence = self.sequence.wrapping_add(1);
+    /// Trace ids are only stamped (as an extension) when every peer supports extensions
+    async fn send_traced_as(
+        &mut self,
+        class: MessageClass,
+        msg_type: MessageType,
+        payload: &[u8],
+        trace: TraceId
+    ) -> error::Result<()> {
+        self.shape(class).await?;
+        self.multicast(class, msg_type, payload, Extensions::traced(trace)).await
+    }
 
-        let mut message = Vec::new();
-        message.extend_from_slice(header.as_bytes());
-        message.extend_from_slice(payload);
+    /// Send a message past the shaping calendar, charged to the control budget
+    pub async fn send_emergency(&mut self, msg_type: MessageType, payload: &[u8]) -> error::Result<()> {
+        self.multicast(MessageClass::Control, msg_type, payload, Extensions::traced(TraceId::random())).await
+    }
+
+    /// Send the newest value of a latest-only `topic` (e.g. a position) as a
+    /// Data message, marked so receivers can drop older values of the topic
+    /// that are still queued; see [`compaction`](crate::compaction)
+    pub async fn send_latest(&mut self, topic: &str, payload: &[u8]) -> error::Result<()> {
+        let mut extensions = Extensions::traced(TraceId::random());
+        extensions.set_last_value(topic).map_err(|e| TransportError::Misconfigured(e.to_string()))?;
+        self.shape(MessageClass::Telemetry).await?;
+        self.multicast(MessageClass::Telemetry, MessageType::Data, payload, extensions).await
+    }
 
+    /// Send `payload` tagged as encoded in schema `version`, so receivers on
+    /// another version can migrate it; see [`schema`](crate::net::schema)
+    pub async fn send_versioned(&mut self, msg_type: MessageType, version: u16, payload: &[u8]) -> error::Result<()> {
+        let class = MessageClass::for_message_type(msg_type);
+        let mut extensions = Extensions::traced(TraceId::random());
+        extensions.set_schema_version(version);
+        self.shape(class).await?;
+        self.multicast(class, msg_type, payload, extensions).await
+    }
+
+    /// Send one half of a request/response exchange as a Control message;
+    /// see [`rpc`](crate::rpc)
+    pub(crate) async fn send_correlated(&mut self, correlation: Correlation, trace: TraceId, payload: &[u8]) -> error::Result<()> {
+        let mut extensions = Extensions::traced(trace);
+        extensions.set_correlation(correlation);
+        self.shape(MessageClass::Control).await?;
+        self.multicast(MessageClass::Control, MessageType::Control, payload, extensions).await
+    }
+
+    async fn multicast(
+        &mut self,
+        class: MessageClass,
+        msg_type: MessageType,
+        payload: &[u8],
+        mut extensions: Extensions
+    ) -> error::Result<()> {
+        if self.version >= protocol::FEATURES_VERSION
+            && let Some(zone) = &*self.zone.lock().unwrap()
+        {
+            extensions.set_zone(zone);
+        }
+        let mut features = match (&self.peers, msg_type) {
+            (Some(peers), MessageType::Data | MessageType::Control) => {
+                peers.lock().unwrap().common_features(self.codec.supported())
+            }
+            _ => ProtocolFeatures::NONE,
+        };
+        // Unlike the trace id, a last-value topic, schema version, zone or correlation has to reach the receiver
+        if extensions.last_value().is_some()
+            || extensions.schema_version().is_some()
+            || extensions.liveness().is_some()
+            || extensions.zone().is_some()
+            || extensions.correlation().is_some()
+        {
+            features = features | ProtocolFeatures::EXTENSIONS;
+        }
         let addr = SocketAddr::new(IpAddr::V4(self.group), self.port);
-        self.socket.send_to(&message, addr).await?;
+        self.transmit(class, msg_type, payload, extensions, &[(addr, features, None)]).await?;
+        Ok(())
+    }
 
-        println!("Sent {} message (seq: {}, {} bytes payload)",
-                 format!("{:?}", msg_type), header.sequence, payload.len());
+    /// Send only to peers that announced `tag` as a role or capability, routed
+    /// as configured with `with_tag_routing`. Returns how many datagrams went out.
+    pub async fn send_to_tagged(
+        &mut self,
+        tag: &str,
+        msg_type: MessageType,
+        payload: &[u8]
+    ) -> error::Result<usize> {
+        let local = self.codec.supported();
+        let targets: Vec<(SocketAddr, ProtocolFeatures, Option<u32>)> = match (self.tag_routing, &self.peers) {
+            (TagRouting::Group, peers) => {
+                let features = peers.as_ref().map_or(ProtocolFeatures::NONE, |peers| {
+                    let peers = peers.lock().unwrap();
+                    peers.tagged(tag).fold(local, |common, peer| common & peer.capabilities.features)
+                });
+                vec![(SocketAddr::new(IpAddr::V4(tag_group(tag)), self.port), features, None)]
+            }
+            (TagRouting::Unicast, Some(peers)) => peers.lock().unwrap().tagged(tag)
+                .map(|peer| (SocketAddr::new(peer.addr.ip(), self.port), peer.capabilities.features & local, Some(peer.sender_id)))
+                .collect(),
+            (TagRouting::Unicast, None) => {
+                return Err(TransportError::Misconfigured("unicast tag routing needs a peer table".into()));
+            }
+        };
+        self.shape(MessageClass::for_message_type(msg_type)).await?;
+        self.transmit(MessageClass::for_message_type(msg_type), msg_type, payload, Extensions::traced(TraceId::random()), &targets).await
+    }
 
+    /// Wait out the shaping calendar's hold on `class`, or fail with
+    /// [`TransportError::Held`] if it lasts longer than the calendar's `max_defer`
+    async fn shape(&self, class: MessageClass) -> error::Result<()> {
+        let Some(calendar) = &self.shaping else { return Ok(()) };
+        let delay = calendar.delay(class, SystemTime::now());
+        if delay.is_zero() {
+            return Ok(());
+        }
+        if delay > calendar.max_defer() {
+            return Err(TransportError::Held { class, delay });
+        }
+        async_std::task::sleep(delay).await;
         Ok(())
     }
 
-    pub async fn send_heartbeat(&mut self) -> std::io::Result<()> {
-        self.send_message(MessageType::Heartbeat, b"").await
+    /// Frame `payload` once per target (with that target's features, and its
+    /// session when the target is a single peer) under a single sequence
+    /// number, and send each copy in turn. Mirrors get the first copy. A
+    /// payload too large for one datagram goes out as fragments, each under
+    /// a sequence number of its own.
+    async fn transmit(
+        &mut self,
+        class: MessageClass,
+        msg_type: MessageType,
+        payload: &[u8],
+        mut extensions: Extensions,
+        targets: &[(SocketAddr, ProtocolFeatures, Option<u32>)]
+    ) -> error::Result<usize> {
+        if msg_type.code() > protocol::MSG_TYPE_MASK {
+            let reason = format!("message type {} doesn't fit the header's type bits", msg_type.code());
+            return Err(TransportError::Misconfigured(reason));
+        }
+        let transformed = self.transforms.as_ref().and_then(|transforms| transforms.apply(msg_type, payload));
+        let payload = transformed.as_deref().unwrap_or(payload);
+        extensions.set_send_timestamps(SendTimestamps::now());
+        // Version 1 headers can't flag how a payload was encoded; later ones
+        // carry at least what the codec requires, whatever peers announced
+        let required = self.codec.required_for(msg_type);
+        let targets: Vec<(SocketAddr, ProtocolFeatures, Option<u32>)> = match self.version < protocol::FEATURES_VERSION {
+            true => {
+                if extensions.last_value().is_some() || extensions.schema_version().is_some() || extensions.correlation().is_some() {
+                    let reason = format!("version {} headers can't carry extensions", self.version);
+                    return Err(TransportError::Misconfigured(reason));
+                }
+                if !required.is_empty() {
+                    let reason = format!("version {} headers can't flag the required {:?}", self.version, required);
+                    return Err(TransportError::Misconfigured(reason));
+                }
+                targets.iter().map(|&(addr, _, _)| (addr, ProtocolFeatures::NONE, None)).collect()
+            }
+            false => targets.iter().map(|&(addr, wanted, peer)| (addr, wanted | required, peer)).collect(),
+        };
+        let targets = &targets[..];
+        let chunks = self.fragments(payload, &extensions, targets)?;
+        if chunks.len() > 1 && self.version < protocol::FEATURES_VERSION {
+            let limit = self.max_datagram_len.saturating_sub(protocol::HEADER_LEN);
+            return Err(TransportError::PayloadTooLarge { len: payload.len(), limit });
+        }
+        let topic = self.usage.as_ref().map(|usage| usage.topic(msg_type, payload));
+        let sequence = self.sequence.fetch_add(chunks.len() as u16, Ordering::Relaxed);
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let fragmented = chunks.len() > 1;
+            let mut extensions = extensions.clone();
+            if fragmented {
+                extensions.set_fragment(Fragment { id: sequence, index: index as u16, count: chunks.len() as u16 });
+            }
+            let mut first = None;
+
+            for &(addr, mut wanted, peer) in targets {
+                if fragmented {
+                    wanted = wanted | ProtocolFeatures::EXTENSIONS;
+                }
+                let stamped = wanted.contains(ProtocolFeatures::EXTENSIONS);
+                let sealed_overhead = self.codec.sealed_overhead(peer, wanted);
+                let body = match (&self.padding, sealed_overhead) {
+                    (Some(buckets), Some(overhead)) if stamped => {
+                        wanted = ProtocolFeatures::from_bits(wanted.bits() & !ProtocolFeatures::COMPRESSION.bits());
+                        let (body, padding) = buckets.pad(&extensions, chunk, protocol::HEADER_LEN + overhead);
+                        self.stats.record_padding(padding);
+                        body
+                    }
+                    _ if stamped => extensions.prepend_to(chunk),
+                    _ => chunk.to_vec(),
+                };
+                let (mut features, encoded) = if wanted.is_empty() && peer.is_none() {
+                    (wanted, body)
+                } else {
+                    self.codec.encode_for(peer, wanted, &body).map_err(TransportError::Encode)?
+                };
+                if stamped {
+                    features = features | ProtocolFeatures::EXTENSIONS;
+                }
+                let message = self.frame(msg_type, sequence.wrapping_add(index as u16), features, encoded);
+
+                if let Some(bandwidth) = &self.bandwidth {
+                    bandwidth.acquire(class, message.len()).await?;
+                }
+
+                if let Some(schedule) = &self.slot_schedule {
+                    let wait = schedule.delay_until_slot(self.sender_id, schedule.synchronized_now_us());
+                    if !wait.is_zero() {
+                        async_std::task::sleep(wait).await;
+                    }
+                }
+
+                self.socket.send_to(&message, addr).await?;
+                self.stats.record_sent(message.len());
+                if let (Some(usage), Some(topic)) = (&self.usage, &topic) {
+                    usage.record_sent(topic, message.len(), Instant::now());
+                }
+                first.get_or_insert(message);
+            }
+            if let Some(message) = first {
+                for mirror in &self.mirrors {
+                    mirror.forward(class, msg_type, &message);
+                }
+            }
+        }
+
+        let traced = match (extensions.trace_id(), chunks.len() > 1 || targets.iter().any(|(_, wanted, _)| wanted.contains(ProtocolFeatures::EXTENSIONS))) {
+            (Some(trace), true) => format!(", trace {}", trace),
+            _ => String::new(),
+        };
+        let datagrams = targets.len() * chunks.len();
+        println!("Sent {:?} message (seq: {}, {} bytes payload, {} datagrams{})",
+                 msg_type, sequence, payload.len(), datagrams, traced);
+
+        Ok(datagrams)
     }
 
-    pub async fn send_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+    /// `payload` cut into pieces that fit `max_datagram_len` once framed and
+    /// encoded for any of `targets`; just the payload itself if it fits whole
+    fn fragments<'a>(
+        &self,
+        payload: &'a [u8],
+        extensions: &Extensions,
+        targets: &[(SocketAddr, ProtocolFeatures, Option<u32>)]
+    ) -> error::Result<Vec<&'a [u8]>> {
+        let mut extensions = extensions.clone();
+        if self.padding.is_some() {
+            extensions.set_padding(0);
+        }
+        let stamped = targets.iter().any(|(_, wanted, _)| wanted.contains(ProtocolFeatures::EXTENSIONS));
+        let overhead = |wanted: ProtocolFeatures| {
+            targets.iter().map(|&(_, _, peer)| self.codec.encoded_overhead(peer, wanted)).max().unwrap_or(0)
+        };
+        let whole_overhead = if stamped { extensions.prepend_to(&[]).len() } else { 0 };
+        let wanted = targets.iter().fold(ProtocolFeatures::NONE, |all, (_, wanted, _)| all | *wanted);
+        if protocol::HEADER_LEN + whole_overhead + overhead(wanted) + payload.len() <= self.max_datagram_len {
+            return Ok(vec![payload]);
+        }
+
+        extensions.set_fragment(Fragment { id: 0, index: 0, count: 0 });
+        let fragment_overhead = protocol::HEADER_LEN + extensions.prepend_to(&[]).len() + overhead(wanted | ProtocolFeatures::EXTENSIONS);
+        let chunk_len = self.max_datagram_len.saturating_sub(fragment_overhead);
+        if chunk_len == 0 || payload.len().div_ceil(chunk_len) > protocol::MAX_FRAGMENTS {
+            return Err(TransportError::PayloadTooLarge { len: payload.len(), limit: chunk_len * protocol::MAX_FRAGMENTS });
+        }
+        Ok(payload.chunks(chunk_len).collect())
+    }
+
+    fn frame(&self, msg_type: MessageType, sequence: u16, features: ProtocolFeatures, payload: Vec<u8>) -> Vec<u8> {
+        let mut message = FleetMessage::new(msg_type, self.sender_id, sequence, payload).with_version(self.version);
+        if !features.is_empty() {
+            message = message.with_features(features);
+        }
+        message.to_bytes()
+    }
+
+    /// Announce departure to the fleet and close the sender. The Goodbye goes
+    /// out past the shaping calendar, like [`send_emergency`](Self::send_emergency);
+    /// if it fails, dropping the sender still tries once more.
+    pub async fn shutdown(mut self) -> error::Result<()> {
+        self.stop_heartbeats.store(true, Ordering::Relaxed);
+        self.multicast(MessageClass::Control, MessageType::Goodbye, b"", Extensions::traced(TraceId::random())).await?;
+        self.departed = true;
+        Ok(())
+    }
+
+    /// Send a heartbeat now, carrying the incarnation, the announcement and,
+    /// from version 2 on, this sender's [`Liveness`]
+    pub async fn send_heartbeat(&mut self) -> error::Result<()> {
+        let payload = self.heartbeat_body();
+        let class = MessageClass::for_message_type(MessageType::Heartbeat);
+        let mut extensions = Extensions::traced(TraceId::random());
+        if self.version >= protocol::FEATURES_VERSION {
+            extensions.set_liveness(Liveness::of(self.started, &self.stats));
+        }
+        self.shape(class).await?;
+        self.multicast(class, MessageType::Heartbeat, &payload, extensions).await
+    }
+
+    /// Send heartbeats every `interval` from a background task until the
+    /// returned handle is dropped or this sender shuts down; see
+    /// [`heartbeat`](crate::heartbeat). Announcements made after this call
+    /// aren't picked up by the task; restart it to send them.
+    pub fn start_heartbeat(&self, interval: Duration) -> error::Result<HeartbeatHandle> {
+        let lane = HeartbeatLane {
+            socket: UdpSocket::from(self.goodbye_socket.try_clone()?),
+            target: SocketAddr::new(IpAddr::V4(self.group), self.port),
+            sender_id: self.sender_id,
+            sequence: self.sequence.clone(),
+            payload: self.heartbeat_body(),
+            version: self.version,
+            started: self.started,
+            stats: self.stats.clone(),
+            slot_schedule: self.slot_schedule,
+            zone: self.zone.clone(),
+            departed: self.stop_heartbeats.clone(),
+        };
+        Ok(HeartbeatHandle::spawn(lane, interval))
+    }
+
+    /// How long this sender has been up
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Incarnation, then the announcement if there is anything to announce
+    fn heartbeat_body(&self) -> Vec<u8> {
+        let mut payload = heartbeat_payload(self.incarnation).to_vec();
+        let mut announcement = self.capabilities.clone();
+        announcement.features = self.codec.supported();
+        if !announcement.is_empty() {
+            payload.extend_from_slice(&announcement.encode());
+        }
+        payload
+    }
+
+    pub async fn send_data(&mut self, data: &[u8]) -> error::Result<()> {
         self.send_message(MessageType::Data, data).await
     }
 
-    pub async fn send_control(&mut self, command: &str) -> std::io::Result<()> {
+    pub async fn send_control(&mut self, command: &str) -> error::Result<()> {
         self.send_message(MessageType::Control, command.as_bytes()).await
     }
+
+    /// Send a Data message accounted against the bulk (e.g. OTA) budget
+    pub async fn send_bulk(&mut self, data: &[u8]) -> error::Result<()> {
+        self.send_message_as(MessageClass::Bulk, MessageType::Data, data).await
+    }
+}
+
+impl Drop for MulticastSender {
+    /// Best-effort goodbye if `shutdown` wasn't called; skips the slot and budget waits
+    fn drop(&mut self) {
+        self.stop_heartbeats.store(true, Ordering::Relaxed);
+        if self.departed {
+            return;
+        }
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let message = FleetMessage::goodbye(self.sender_id, sequence).with_version(self.version).to_bytes();
+        if self.goodbye_socket.send_to(&message, (self.group, self.port)).is_ok() {
+            self.stats.record_sent(message.len());
+        }
+    }
 }
 
 #[cfg(test)]
@@ -215,21 +1334,77 @@ mod tests {
     use async_std::task;
     use std::sync::{Arc, Mutex};
     use std::time::Duration;
+    use crate::scheduling::ThreadScheduling;
+    use crate::testing::TestReceiver;
 
     #[async_std::test]
     async fn test_header_creation_and_validation() {
         let header = FleetMsgHeader::new(MessageType::Data, 12345, 100, 256);
 
-        assert_eq!(header.magic, 0xFEED);
-        assert_eq!(header.version, 1);
-        assert_eq!(header.msg_type, MessageType::Data as u8);
-        assert_eq!(header.sender_id, 12345);
-        assert_eq!(header.sequence, 100);
-        assert_eq!(header.payload_len, 256);
+        assert_eq!(header.magic.get(), 0xFEED);
+        assert_eq!(header.version, 2);
+        assert_eq!(header.msg_type, MessageType::Data.code());
+        assert_eq!(header.sender_id.get(), 12345);
+        assert_eq!(header.sequence.get(), 100);
+        assert_eq!(header.payload_len.get(), 256);
         assert!(header.is_valid());
         assert_eq!(header.message_type(), MessageType::Data);
     }
 
+    #[test]
+    fn test_version_1_millisecond_timestamps_still_accepted() {
+        let mut header = FleetMsgHeader::new(MessageType::Data, 1, 0, 0);
+        assert_eq!(header.timestamp_millis(), header.timestamp.get() / 1000);
+
+        header.version = 1;
+        header.timestamp = 1_700_000_000_123.into();
+        header.checksum = header.calculate_checksum_without_field().into();
+        assert!(header.validation_issues().is_empty());
+        assert_eq!(header.timestamp_micros(), 1_700_000_000_123_000);
+        assert_eq!(header.timestamp_millis(), 1_700_000_000_123);
+
+        header.version = FleetMsgHeader::MAX_VERSION + 1;
+        header.checksum = header.calculate_checksum_without_field().into();
+        assert!(!header.is_valid());
+    }
+
+    #[test]
+    fn test_crc32c_headers_catch_what_the_sum_misses() {
+        let header = FleetMsgHeader::new(MessageType::Data, 0x0102, 7, 64);
+        let crc = header.with_checksum(HeaderChecksum::Crc32c);
+        assert_eq!((crc.version, crc.checksum_kind()), (protocol::CRC32C_VERSION, HeaderChecksum::Crc32c));
+        assert!(crc.validation_issues().is_empty());
+        assert_eq!(crc.with_checksum(HeaderChecksum::Sum).as_bytes(), header.as_bytes());
+
+        // Swapping two bytes keeps the sum but not the CRC
+        let swapped = |header: FleetMsgHeader| {
+            let mut bytes = header.as_bytes().to_vec();
+            bytes.swap(16, 17);
+            FleetMsgHeader::read_from(bytes.as_slice()).unwrap()
+        };
+        assert!(swapped(header).is_valid());
+        assert_eq!(swapped(crc).validation_issues(), [ValidationIssue::BadChecksum]);
+
+        // Version 1 headers move to microsecond timestamps
+        let mut v1 = header;
+        v1.version = 1;
+        v1.timestamp = 1_700_000_000_123.into();
+        assert_eq!(v1.with_checksum(HeaderChecksum::Crc32c).timestamp.get(), 1_700_000_000_123_000);
+    }
+
+    #[test]
+    fn test_parse_tells_failures_apart() {
+        let mut header = FleetMsgHeader::new(MessageType::Data, 1, 0, 0);
+        assert_eq!(FleetMsgHeader::parse(header.as_bytes()).unwrap().sender_id.get(), 1);
+        assert!(matches!(FleetMsgHeader::parse(&header.as_bytes()[..8]), Err(TransportError::Invalid(_))));
+
+        header.sequence = 9.into();
+        assert!(matches!(FleetMsgHeader::parse(header.as_bytes()), Err(TransportError::ChecksumMismatch)));
+        header.magic = 0.into();
+        let Err(TransportError::Invalid(issues)) = FleetMsgHeader::parse(header.as_bytes()) else { panic!("accepted") };
+        assert_eq!(issues, [ValidationIssue::BadMagic(0), ValidationIssue::BadChecksum]);
+    }
+
     #[async_std::test]
     async fn test_header_serialization() {
         let original = FleetMsgHeader::new(MessageType::Heartbeat, 54321, 200, 0);
@@ -245,61 +1420,362 @@ mod tests {
 
     #[async_std::test]
     async fn test_multicast_send_receive() {
-        let group = Ipv4Addr::new(239, 1, 1, 1);
-        let port = 12345;
         let sender_id = 999;
-
-        // Shared state to capture received messages
-        let received_messages = Arc::new(Mutex::new(Vec::new()));
-        let received_clone = received_messages.clone();
-
-        // Start receiver in background
-        let receiver_task = task::spawn(async move {
-            let handler = move |header: FleetMsgHeader, payload: Vec<u8>, _addr: SocketAddr| {
-                received_clone.lock().unwrap().push((header, payload));
-            };
-
-            // Run receiver for a short time
-            let receiver_future = start_multicast_rx(group, port, handler);
-            let timeout_future = task::sleep(Duration::from_millis(500));
-
-            // Race between receiver and timeout
-            futures::future::select(
-                Box::pin(receiver_future),
-                Box::pin(timeout_future)
-            ).await;
-        });
-
-        // Give receiver time to start
-        task::sleep(Duration::from_millis(100)).await;
+        let receiver = TestReceiver::start().await.unwrap();
 
         // Create sender and send test messages
-        let mut sender = MulticastSender::new(group, port, sender_id).await.unwrap();
+        let mut sender = receiver.sender(sender_id).await.unwrap();
+        let incarnation = sender.incarnation();
 
         sender.send_heartbeat().await.unwrap();
         sender.send_data(b"test data").await.unwrap();
         sender.send_control("test command").await.unwrap();
-
-        // Wait a bit for messages to be received
-        task::sleep(Duration::from_millis(200)).await;
-
-        // Stop receiver
-        receiver_task.cancel().await;
+        sender.shutdown().await.unwrap();
 
         // Check received messages
-        let messages = received_messages.lock().unwrap();
-        assert!(messages.len() >= 1, "Should have received at least one message");
+        let messages = receiver.wait_for(4, Duration::from_millis(500)).await;
+        assert!(!messages.is_empty(), "Should have received at least one message");
 
         // Verify message types and content
-        for (header, payload) in messages.iter() {
-            assert_eq!(header.sender_id, sender_id);
+        for (header, payload, _) in messages.iter() {
+            assert_eq!(header.sender_id.get(), sender_id);
             assert!(header.is_valid());
 
             match header.message_type() {
-                MessageType::Heartbeat => assert_eq!(payload.len(), 0),
+                MessageType::Heartbeat => assert_eq!(heartbeat_incarnation(payload), Some(incarnation)),
                 MessageType::Data => assert_eq!(payload, b"test data"),
                 MessageType::Control => assert_eq!(payload, b"test command"),
+                MessageType::Goodbye => assert!(payload.is_empty()),
+                other => panic!("no {:?} was sent", other),
             }
         }
+        let (last, _, _) = messages.last().unwrap();
+        assert_eq!(last.message_type(), MessageType::Goodbye);
+    }
+
+    #[async_std::test]
+    async fn test_heartbeat_task_beats_until_stopped() {
+        let (tap, frames) = crate::tap::FrameTap::new(64);
+        let receiver = TestReceiver::start_with_config(ReceiverConfig::new().with_tap(tap)).await.unwrap();
+        let sender = receiver.sender(31).await.unwrap();
+        let heartbeats = sender.start_heartbeat(Duration::from_millis(20)).unwrap();
+
+        let received = receiver.wait_for(3, Duration::from_secs(2)).await;
+        let sequences: Vec<u16> = received.iter().take(3).map(|(header, payload, _)| {
+            assert_eq!(header.message_type(), MessageType::Heartbeat);
+            assert_eq!(heartbeat_incarnation(payload), Some(sender.incarnation()));
+            header.sequence.get()
+        }).collect();
+        assert_eq!(sequences, [0, 1, 2]);
+        let frame = frames.try_next().unwrap();
+        let liveness = ReceiverConfig::new().parse(&frame.datagram, frame.addr).unwrap().extensions.liveness().unwrap();
+        assert!(liveness.uptime <= sender.uptime());
+
+        heartbeats.set_interval(Duration::from_secs(3600));
+        assert_eq!(heartbeats.interval(), Duration::from_secs(3600));
+        task::sleep(Duration::from_millis(50)).await;
+        let sent = heartbeats.sent();
+        assert!(sent >= 3);
+        task::sleep(Duration::from_millis(100)).await;
+        assert_eq!(heartbeats.sent(), sent);
+        heartbeats.stop().await;
+    }
+
+    #[async_std::test]
+    async fn test_send_to_tagged_reaches_only_tagged_peers() {
+        let receiver = TestReceiver::start().await.unwrap();
+
+        // The forklift is "at" the loopback address; the charger is not. A
+        // second forklift went quiet and timed out, so it isn't addressed.
+        let peers = Arc::new(Mutex::new(PeerTable::new().with_timeout(Duration::from_secs(3))));
+        let start = std::time::Instant::now();
+        for (sender_id, role, heard) in [(1, "forklift", 10), (2, "charger", 10), (3, "forklift", 0)] {
+            let mut peers = peers.lock().unwrap();
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, sender_id as u8)), 40000);
+            peers.observe(&FleetMsgHeader::new(MessageType::Heartbeat, sender_id, 0, 0), addr, start + Duration::from_secs(heard));
+            peers.announce(sender_id, Capabilities::new([role], Vec::<String>::new()).unwrap());
+        }
+        assert_eq!(peers.lock().unwrap().tick(start + Duration::from_secs(10)), vec![3]);
+        let mut sender = receiver.sender(77).await.unwrap().with_peer_table(peers);
+        assert_eq!(sender.send_to_tagged("forklift", MessageType::Control, b"stop").await.unwrap(), 1);
+        assert_eq!(sender.send_to_tagged("crane", MessageType::Control, b"stop").await.unwrap(), 0);
+
+        task::sleep(Duration::from_millis(200)).await;
+        let received: Vec<_> = receiver.received().into_iter().map(|(header, payload, _)| (header.sender_id.get(), payload)).collect();
+        assert_eq!(received, vec![(77, b"stop".to_vec())]);
+    }
+
+    #[async_std::test]
+    async fn test_trace_id_propagates_into_reply() {
+        let (channel, socket) = crate::testing::bind_free_channel().await.unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let handler = move |delivery: Delivery| {
+            sink.lock().unwrap().push((delivery.header.sender_id.get(), delivery.extensions, delivery.payload));
+        };
+        let receiver_task = task::spawn(receive_loop(socket, ReceiverConfig::default(), handler));
+
+        // Extensions are only stamped once every known peer supports them
+        let peers = Arc::new(Mutex::new(PeerTable::new()));
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000);
+        peers.lock().unwrap().observe(&FleetMsgHeader::new(MessageType::Heartbeat, 2, 0, 0), addr, std::time::Instant::now());
+        peers.lock().unwrap().announce(2, Capabilities { features: FeatureCodec::default().supported(), ..Default::default() });
+        let mut operator = MulticastSender::new(channel.group, channel.port, 1).await.unwrap().with_peer_table(peers.clone());
+        let mut vehicle = MulticastSender::new(channel.group, channel.port, 2).await.unwrap().with_peer_table(peers);
+
+        let trace = TraceId::random();
+        operator.send_traced(MessageType::Control, b"stop", trace).await.unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+        let command = received.lock().unwrap()[0].1.clone();
+        vehicle.reply_to(&command, MessageType::Data, b"stopped").await.unwrap();
+        task::sleep(Duration::from_millis(100)).await;
+        receiver_task.cancel().await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!((received[0].0, received[0].1.trace_id(), received[0].2.as_slice()), (1, Some(trace), &b"stop"[..]));
+        assert_eq!((received[1].0, received[1].1.trace_id(), received[1].2.as_slice()), (2, Some(trace), &b"stopped"[..]));
+    }
+
+    #[async_std::test]
+    async fn test_padding_hides_command_lengths() {
+        let codec = FeatureCodec::new().with_encryption_key([5; 32]);
+        let receiver = TestReceiver::start_with_codec(codec.clone()).await.unwrap();
+        let peers = Arc::new(Mutex::new(PeerTable::new()));
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000);
+        peers.lock().unwrap().observe(&FleetMsgHeader::new(MessageType::Heartbeat, 2, 0, 0), addr, std::time::Instant::now());
+        peers.lock().unwrap().announce(2, Capabilities { features: codec.supported(), ..Default::default() });
+        let mut sender = receiver.sender(9).await.unwrap()
+            .with_codec(codec)
+            .with_peer_table(peers)
+            .with_padding(PaddingBuckets::default());
+
+        sender.send_control("STOP").await.unwrap();
+        sender.send_control("GOTO 12.5 40.1 heading 270").await.unwrap();
+        receiver.collector().wait_for(2, Duration::from_secs(2)).await;
+        let messages = receiver.received();
+        assert_eq!(messages.iter().map(|(_, payload, _)| payload.as_slice()).collect::<Vec<_>>(), [&b"STOP"[..], b"GOTO 12.5 40.1 heading 270"]);
+        assert!(messages.iter().all(|(header, _, _)| header.payload_len.get() == 128 - 24));
+        let stats = sender.stats().snapshot();
+        assert_eq!(stats.bytes_sent, 2 * 128);
+        assert!((1..128).contains(&stats.padding_bytes_sent));
+    }
+
+    #[async_std::test]
+    async fn test_forged_heartbeat_cant_strip_required_encryption() {
+        let codec = FeatureCodec::new().with_encryption_key([5; 32]).with_required(ProtocolFeatures::ENCRYPTION).unwrap();
+        let receiver = TestReceiver::start_with_codec(codec.clone()).await.unwrap();
+        let peers = Arc::new(Mutex::new(PeerTable::new()));
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 40000);
+        peers.lock().unwrap().observe(&FleetMsgHeader::new(MessageType::Heartbeat, 2, 0, 0), addr, std::time::Instant::now());
+        peers.lock().unwrap().announce(2, Capabilities { features: codec.supported(), ..Default::default() });
+        // A sender id never seen before announces that it supports nothing
+        peers.lock().unwrap().observe(&FleetMsgHeader::new(MessageType::Heartbeat, 66, 0, 0), addr, std::time::Instant::now());
+        peers.lock().unwrap().announce(66, Capabilities::default());
+        assert_eq!(peers.lock().unwrap().common_features(codec.supported()), ProtocolFeatures::NONE);
+
+        let mut sender = receiver.sender(9).await.unwrap().with_codec(codec).with_peer_table(peers);
+        sender.send_data(b"pallet 7 at dock 3").await.unwrap();
+        // Plaintext Data from a node without the floor is refused
+        let mut plain = receiver.sender(10).await.unwrap();
+        plain.send_data(b"pallet 7 at dock 4").await.unwrap();
+        sender.send_data(b"pallet 8 at dock 3").await.unwrap();
+
+        let messages = receiver.wait_for(2, Duration::from_secs(2)).await;
+        assert_eq!(messages.iter().map(|(_, payload, _)| payload.as_slice()).collect::<Vec<_>>(), [&b"pallet 7 at dock 3"[..], b"pallet 8 at dock 3"]);
+        assert!(messages.iter().all(|(header, _, _)| header.features().contains(ProtocolFeatures::ENCRYPTION)));
+    }
+
+    #[async_std::test]
+    async fn test_receiver_stream_composes_with_combinators() {
+        use futures::StreamExt;
+
+        let (channel, socket) = crate::testing::bind_free_channel().await.unwrap();
+        let receiver = MulticastReceiver::from_socket(socket, ReceiverConfig::new(), 16);
+        let mut sender = MulticastSender::new(channel.group, channel.port, 8).await.unwrap();
+        sender.send_heartbeat().await.unwrap();
+        for reading in ["12.5", "13.0"] {
+            sender.send_data(reading.as_bytes()).await.unwrap();
+        }
+
+        let readings: Vec<String> = receiver
+            .map(Delivery::into_message)
+            .filter(|message| std::future::ready(message.message_type() == MessageType::Data))
+            .map(|message| String::from_utf8(message.into_payload()).unwrap())
+            .take(2)
+            .collect()
+            .await;
+        assert_eq!(readings, ["12.5", "13.0"]);
+    }
+
+    #[async_std::test]
+    async fn test_large_payload_is_fragmented_and_reassembled() {
+        let receiver = TestReceiver::start().await.unwrap();
+        let usage = Arc::new(crate::usage::UsageAccounting::new());
+        let mut sender = receiver.sender(4).await.unwrap().with_usage(usage.clone());
+        let map: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+
+        sender.send_data(&map).await.unwrap();
+        sender.send_data(b"after").await.unwrap();
+        let messages = receiver.wait_for(2, Duration::from_secs(2)).await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].1, map);
+        // Each fragment took a sequence number of its own
+        assert_eq!((messages[0].0.sequence.get(), messages[1].0.sequence.get()), (0, 4));
+        assert!(sender.stats().snapshot().bytes_sent <= 4 * protocol::MTU_DATAGRAM_LEN as u64);
+        // The fragments are counted under the message's topic
+        let data = usage.report(std::time::Instant::now()).get("Data").unwrap().total;
+        assert_eq!((data.messages_sent, data.bytes_sent), (5, sender.stats().snapshot().bytes_sent));
+    }
+
+    #[async_std::test]
+    async fn test_sender_speaks_the_configured_version() {
+        let receiver = TestReceiver::start().await.unwrap();
+        assert!(receiver.sender(5).await.unwrap().with_protocol_version(FleetMsgHeader::MAX_VERSION + 1).is_err());
+        let mut sender = receiver.sender(5).await.unwrap().with_protocol_version(1).unwrap();
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        sender.send_data(b"old fleet").await.unwrap();
+        let big = vec![7u8; 5000];
+        assert!(matches!(sender.send_data(&big).await, Err(TransportError::PayloadTooLarge { .. })));
+        assert!(sender.send_versioned(MessageType::Data, 2, b"{}").await.is_err());
+        let mut sender = sender.with_protocol_version(FleetMsgHeader::MAX_VERSION).unwrap();
+        sender.send_data(b"new fleet").await.unwrap();
+
+        // After the goodbye of the sender that was turned down
+        let messages = receiver.wait_for(3, Duration::from_secs(2)).await;
+        let messages: Vec<_> = messages.into_iter().filter(|(header, _, _)| header.message_type() == MessageType::Data).collect();
+        let versions: Vec<(u8, &[u8])> = messages.iter().map(|(header, payload, _)| (header.version(), payload.as_slice())).collect();
+        assert_eq!(versions, [(1, &b"old fleet"[..]), (FleetMsgHeader::MAX_VERSION, &b"new fleet"[..])]);
+        // Version 1 sends milliseconds, read back in either unit
+        let old = &messages[0].0;
+        assert!(old.timestamp.get() >= before && old.timestamp.get() < before + 5_000);
+        assert_eq!(old.timestamp_micros(), old.timestamp.get() * 1000);
+    }
+
+    #[async_std::test]
+    async fn test_application_types_arrive_as_their_own() {
+        let receiver = TestReceiver::start().await.unwrap();
+        let mut sender = receiver.sender(5).await.unwrap();
+        let alert = MessageType::application(9).unwrap();
+        sender.send_message(alert, b"overheat").await.unwrap();
+        let too_large = sender.send_message(MessageType::Application(200), b"").await;
+        assert!(matches!(too_large, Err(TransportError::Misconfigured(_))));
+
+        let messages = receiver.wait_for(1, Duration::from_secs(2)).await;
+        assert_eq!((messages[0].0.message_type(), messages[0].1.as_slice()), (alert, &b"overheat"[..]));
+        assert!(messages[0].0.validation_issues().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_a_pinned_receiver_receives() {
+        let config = ReceiverConfig::new().with_thread_scheduling(ThreadScheduling::pinned([0]));
+        let receiver = TestReceiver::start_with_config(config).await.unwrap();
+        let mut sender = receiver.sender(5).await.unwrap();
+
+        sender.send_data(b"pinned").await.unwrap();
+        let messages = receiver.wait_for(1, Duration::from_secs(2)).await;
+        assert_eq!(messages[0].1, b"pinned");
+    }
+
+    #[async_std::test]
+    async fn test_a_busy_polling_receiver_receives_after_backing_off() {
+        let busy = BusyPoll::default().with_spin(Duration::from_millis(20)).with_max_backoff(Duration::from_millis(2));
+        let receiver = TestReceiver::start_with_config(ReceiverConfig::new().with_busy_poll(busy)).await.unwrap();
+        let mut sender = receiver.sender(5).await.unwrap();
+
+        sender.send_data(b"spinning").await.unwrap();
+        assert_eq!(receiver.wait_for(1, Duration::from_secs(2)).await[0].1, b"spinning");
+        task::sleep(Duration::from_millis(50)).await;
+        sender.send_data(b"backed off").await.unwrap();
+        assert_eq!(receiver.wait_for(2, Duration::from_secs(2)).await[1].1, b"backed off");
+    }
+
+    #[async_std::test]
+    async fn test_filtered_messages_never_reach_the_handler() {
+        let filter = ReceiverFilter::new()
+            .with_message_types([MessageType::Data])
+            .with_allowed_senders([5, 6, 7])
+            .with_denied_senders([6])
+            .with_predicate(|header| header.sequence.get() < 100);
+        let (tap, frames) = crate::tap::FrameTap::new(16);
+        let receiver = TestReceiver::start_with_config(ReceiverConfig::new().with_filter(filter).with_tap(tap)).await.unwrap();
+        for sender_id in 4..=7 {
+            let mut sender = receiver.sender(sender_id).await.unwrap();
+            sender.send_control("ping").await.unwrap();
+            sender.send_data(b"reading").await.unwrap();
+        }
+
+        let messages = receiver.wait_for(3, Duration::from_millis(500)).await;
+        let heard: Vec<(u32, MessageType)> = messages.iter().map(|(header, _, _)| (header.sender_id.get(), header.message_type())).collect();
+        assert_eq!(heard, [(5, MessageType::Data), (7, MessageType::Data)]);
+
+        // The tap still sees what the filter turned away (the senders'
+        // goodbyes too), as not delivered
+        let tapped: Vec<(u32, MessageType, bool)> = std::iter::from_fn(|| frames.try_next())
+            .map(|frame| {
+                let header = FleetMsgHeader::read_from_prefix(&frame.datagram).unwrap();
+                (header.sender_id.get(), header.message_type(), frame.delivered)
+            })
+            .filter(|(_, msg_type, _)| *msg_type != MessageType::Goodbye)
+            .collect();
+        assert_eq!(tapped.len(), 8);
+        let delivered: Vec<_> = tapped.iter().filter(|(_, _, delivered)| *delivered).collect();
+        assert_eq!(delivered, [&(5, MessageType::Data, true), &(7, MessageType::Data, true)]);
+    }
+
+    #[async_std::test]
+    async fn test_zone_filter_passes_only_its_area() {
+        use crate::zones::ZoneFilter;
+
+        let config = ReceiverConfig::new().with_zone_filter(ZoneFilter::new().with_zones([3]).with_geofences([30]));
+        let receiver = TestReceiver::start_with_config(config).await.unwrap();
+        let mut inside = receiver.sender(1).await.unwrap().with_zone(Zone::new(3));
+        let mut fenced = receiver.sender(2).await.unwrap().with_zone(Zone::new(4).with_geofences([30]).unwrap());
+        let mut outside = receiver.sender(3).await.unwrap().with_zone(Zone::new(4));
+        let mut unannotated = receiver.sender(4).await.unwrap();
+        for sender in [&mut inside, &mut fenced, &mut outside, &mut unannotated] {
+            sender.send_data(b"reading").await.unwrap();
+        }
+        // A vehicle driving into the zone is heard from then on
+        outside.set_zone(Some(Zone::new(3)));
+        outside.send_data(b"arrived").await.unwrap();
+
+        let messages = receiver.wait_for(4, Duration::from_millis(500)).await;
+        let heard: Vec<(u32, &[u8])> = messages.iter().map(|(header, payload, _)| (header.sender_id.get(), payload.as_slice())).collect();
+        assert_eq!(heard, [(1, &b"reading"[..]), (2, b"reading"), (4, b"reading"), (3, b"arrived")]);
+    }
+
+    #[async_std::test]
+    async fn test_kernel_filter_keeps_the_fleet_block() {
+        let filter = ReceiverFilter::new().with_message_types([MessageType::Data]).with_allowed_sender_range(0x3B00..=0x3BFF);
+        let config = ReceiverConfig::new().with_filter(filter).with_kernel_filter(true);
+        let receiver = TestReceiver::start_with_config(config).await.unwrap();
+        for sender_id in [0x3AFF, 0x3B01, 0x3C00] {
+            receiver.sender(sender_id).await.unwrap().send_data(b"reading").await.unwrap();
+        }
+
+        let messages = receiver.wait_for(2, Duration::from_millis(300)).await;
+        let heard: Vec<u32> = messages.iter().map(|(header, _, _)| header.sender_id.get()).collect();
+        assert_eq!(heard, [0x3B01]);
+    }
+
+    #[async_std::test]
+    async fn test_jumbo_datagrams_need_a_jumbo_buffer() {
+        let jumbo = ReceiverConfig::new().with_mtu(9000);
+        let receiver = TestReceiver::start_with_config(jumbo).await.unwrap();
+        let mut sender = receiver.sender(5).await.unwrap().with_max_datagram_len(8972);
+        let reading = vec![7u8; 8000];
+
+        sender.send_data(&reading).await.unwrap();
+        let messages = receiver.wait_for(1, Duration::from_secs(2)).await;
+        assert_eq!(messages[0].1, reading);
+        assert_eq!(sender.stats().snapshot().messages_sent, 1, "sent whole, not fragmented");
+
+        // A receiver with the default buffer drops it instead of misreading it
+        let standard = TestReceiver::start().await.unwrap();
+        let mut sender = standard.sender(5).await.unwrap().with_max_datagram_len(8972);
+        sender.send_data(&reading).await.unwrap();
+        assert!(standard.wait_for(1, Duration::from_millis(300)).await.is_empty());
     }
 }
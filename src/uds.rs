@@ -0,0 +1,264 @@
+//! Local transport over Unix datagram sockets, for processes on one vehicle
+//! computer that exchange fleet messages without touching the network stack.
+//!
+//! Each datagram carries one frame exactly as on multicast, and receivers
+//! validate it the same way. A [`UdsSender`] sends to one socket path: either a
+//! single receiver's, or a [`UdsHub`]'s, which fans every frame out to all the
+//! processes subscribed with [`start_uds_rx_hub`].
+
+use async_std::future::timeout;
+use async_std::os::unix::net::UnixDatagram;
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use zerocopy::AsBytes;
+
+use crate::receiver::{self, Delivery, ReceiverConfig};
+use crate::stats::TransportStats;
+use crate::transport::{FleetMsgHeader, MessageType};
+
+/// Deliveries over a Unix socket have no IP source; they carry this address instead
+pub const UDS_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Socket path the hub daemon binds unless told otherwise
+pub const DEFAULT_HUB_PATH: &str = "/tmp/fleetlink.sock";
+
+/// How often subscribers repeat their subscription, so they survive a hub restart
+pub const SUBSCRIBE_INTERVAL: Duration = Duration::from_secs(2);
+
+const MAX_DATAGRAM: usize = 65536;
+
+/// How long the hub waits on a subscriber whose receive queue is full before
+/// giving up on that frame, so one stuck process can't stall the others
+const FORWARD_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Bind `path`, replacing a socket file left behind by a process that exited
+async fn bind(path: &Path) -> std::io::Result<UnixDatagram> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    UnixDatagram::bind(path).await
+}
+
+/// Sends fleet messages to one socket path, a receiver's or a hub's
+#[derive(Debug)]
+pub struct UdsSender {
+    socket: UnixDatagram,
+    target: PathBuf,
+    sender_id: u32,
+    sequence: u16,
+    stats: Arc<TransportStats>,
+}
+
+impl UdsSender {
+    pub fn new(target: impl Into<PathBuf>, sender_id: u32) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            target: target.into(),
+            sender_id,
+            sequence: 0,
+            stats: Arc::new(TransportStats::new()),
+        })
+    }
+
+    /// Count sends in shared stats, e.g. the ones a multicast sender on the same node uses
+    pub fn with_stats(mut self, stats: Arc<TransportStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+
+    pub async fn send_message(&mut self, msg_type: MessageType, payload: &[u8]) -> std::io::Result<()> {
+        let len = u16::try_from(payload.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} byte payload is too large", payload.len())))?;
+        let header = FleetMsgHeader::new(msg_type, self.sender_id, self.sequence, len);
+        let frame = [header.as_bytes(), payload].concat();
+        self.socket.send_to(&frame, &self.target).await?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stats.record_sent(frame.len());
+        Ok(())
+    }
+
+    pub async fn send_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.send_message(MessageType::Data, data).await
+    }
+
+    pub async fn send_control(&mut self, command: &str) -> std::io::Result<()> {
+        self.send_message(MessageType::Control, command.as_bytes()).await
+    }
+}
+
+/// Bind `path` and receive fleet messages sent straight to it, validating them
+/// as `config` says, exactly like a multicast receiver
+pub async fn start_uds_rx(
+    path: impl AsRef<Path>,
+    config: ReceiverConfig,
+    mut message_handler: impl FnMut(Delivery)
+) -> std::io::Result<()> {
+    let socket = bind(path.as_ref()).await?;
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        deliver(&buf[..len], &config, &mut message_handler);
+    }
+}
+
+/// Bind `path`, subscribe it to the hub at `hub_path` and receive everything
+/// sent to the hub, including this process's own sends
+pub async fn start_uds_rx_hub(
+    path: impl AsRef<Path>,
+    hub_path: impl AsRef<Path>,
+    config: ReceiverConfig,
+    mut message_handler: impl FnMut(Delivery)
+) -> std::io::Result<()> {
+    let socket = bind(path.as_ref()).await?;
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        // An empty datagram subscribes; the hub may not be up yet, so keep trying
+        let _ = socket.send_to(&[], hub_path.as_ref()).await;
+        while let Ok(received) = timeout(SUBSCRIBE_INTERVAL, socket.recv(&mut buf)).await {
+            deliver(&buf[..received?], &config, &mut message_handler);
+        }
+    }
+}
+
+fn deliver(frame: &[u8], config: &ReceiverConfig, message_handler: &mut impl FnMut(Delivery)) {
+    if !config.screen(frame, UDS_ADDR) {
+        return;
+    }
+    match receiver::inspect(frame, UDS_ADDR, config) {
+        Ok(delivery) => message_handler(delivery),
+        Err(issues) => eprintln!("Dropped local frame: {}", receiver::describe(&issues)),
+    }
+}
+
+/// Local fan-out daemon: every frame sent to its socket goes to every subscriber
+#[derive(Debug)]
+pub struct UdsHub {
+    socket: UnixDatagram,
+    path: PathBuf,
+    subscribers: BTreeSet<PathBuf>,
+}
+
+impl UdsHub {
+    pub async fn bind(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        Ok(Self { socket: bind(&path).await?, path, subscribers: BTreeSet::new() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Forward frames until the socket fails. Frames that aren't fleet messages
+    /// are dropped, and subscribers whose socket is gone are forgotten.
+    pub async fn run(mut self) -> std::io::Result<()> {
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            if len == 0 {
+                if let Some(path) = from.as_pathname()
+                    && self.subscribers.insert(path.to_path_buf())
+                {
+                    println!("Subscriber joined: {}", path.display());
+                }
+                continue;
+            }
+            if let Err(issues) = receiver::inspect(&buf[..len], UDS_ADDR, &ReceiverConfig::new()) {
+                eprintln!("Hub dropped frame: {}", receiver::describe(&issues));
+                continue;
+            }
+
+            let mut gone = Vec::new();
+            for subscriber in &self.subscribers {
+                match timeout(FORWARD_TIMEOUT, self.socket.send_to(&buf[..len], subscriber)).await {
+                    Ok(Err(e)) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                        gone.push(subscriber.clone());
+                    }
+                    // A full receive queue loses the frame, like a busy multicast receiver would
+                    _ => {}
+                }
+            }
+            for path in gone {
+                println!("Subscriber left: {}", path.display());
+                self.subscribers.remove(&path);
+            }
+        }
+    }
+}
+
+impl Drop for UdsHub {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MessageCollector;
+    use async_std::task;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("fleetlink-{}-{}.sock", name, std::process::id()));
+        // Left over from an earlier run under the same pid, it would look like a receiver already up
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[async_std::test]
+    async fn test_direct_send() {
+        let path = socket_path("direct");
+        let collector = MessageCollector::new();
+        let mut handler = collector.handler();
+        task::spawn(start_uds_rx(path.clone(), ReceiverConfig::new(), move |d: Delivery| handler(d.header, d.payload, d.addr)));
+        while !path.exists() {
+            task::sleep(Duration::from_millis(5)).await;
+        }
+
+        let mut sender = UdsSender::new(&path, 0x10C).unwrap();
+        sender.send_data(b"pose").await.unwrap();
+        sender.send_control("STOP").await.unwrap();
+        collector.wait_for(2, Duration::from_secs(2)).await;
+        let messages = collector.messages();
+        assert_eq!((messages[0].0.sender_id.get(), messages[0].1.as_slice()), (0x10C, &b"pose"[..]));
+        assert_eq!(messages[1].0.message_type(), MessageType::Control);
+        assert_eq!(sender.stats().snapshot().messages_sent, 2);
+    }
+
+    #[async_std::test]
+    async fn test_hub_fans_out() {
+        let hub = UdsHub::bind(socket_path("hub")).await.unwrap();
+        let hub_path = hub.path().to_path_buf();
+        task::spawn(hub.run());
+
+        let collectors = [MessageCollector::new(), MessageCollector::new()];
+        for (i, collector) in collectors.iter().enumerate() {
+            let mut handler = collector.handler();
+            let path = socket_path(&format!("sub{}", i));
+            task::spawn(start_uds_rx_hub(path, hub_path.clone(), ReceiverConfig::new(), move |d: Delivery| {
+                handler(d.header, d.payload, d.addr)
+            }));
+        }
+        // Give both subscriptions time to reach the hub
+        task::sleep(Duration::from_millis(100)).await;
+
+        let mut sender = UdsSender::new(&hub_path, 7).unwrap();
+        sender.send_data(b"one").await.unwrap();
+        for collector in &collectors {
+            collector.wait_for(1, Duration::from_secs(2)).await;
+            assert_eq!(collector.messages()[0].1, b"one");
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Bandwidth used per application topic, so teams sharing the fleet link can
+//! be held to their budgets with numbers.
+//!
+//! [`UsageAccounting`] counts the messages and bytes sent and received under
+//! each topic in fixed windows (a minute by default), keeping the last few
+//! (an hour's worth by default). A topic is whatever the classifier makes of a
+//! message; the default is the message type, with Control messages split by
+//! the command's first word (`Control ROLLOUT`, `Control BACKFILL`, ...).
+//! Attach it to a sender with
+//! [`MulticastSender::with_usage`](crate::transport::MulticastSender::with_usage)
+//! and to a receiver with [`ReceiverConfig::with_usage`](crate::receiver::ReceiverConfig::with_usage);
+//! [`UsageAccounting::report`] then shows each topic's use against its budget.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::journal::message_type_name;
+use crate::transport::MessageType;
+
+/// Length of one accounting window by default
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Windows kept per topic by default: an hour of one-minute windows
+pub const DEFAULT_HISTORY: usize = 60;
+
+/// Names the topic of a message from its type and (decoded) payload
+pub type TopicClassifier = Arc<dyn Fn(MessageType, &[u8]) -> String + Send + Sync>;
+
+/// The default topic: the message type's name, plus the command's first word for Control messages
+pub fn default_topic(msg_type: MessageType, payload: &[u8]) -> String {
+    let name = message_type_name(msg_type.code());
+    if msg_type != MessageType::Control {
+        return name;
+    }
+    let command = payload.split(|byte| byte.is_ascii_whitespace()).next().unwrap_or_default();
+    match std::str::from_utf8(command) {
+        Ok(word) if !word.is_empty() => format!("{} {}", name, word),
+        _ => name,
+    }
+}
+
+/// Traffic counted in one window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowUsage {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+}
+
+impl WindowUsage {
+    /// Bytes both ways, i.e. the topic's share of the link
+    pub fn bytes(&self) -> u64 {
+        self.bytes_sent + self.bytes_received
+    }
+
+    fn add(&mut self, other: &WindowUsage) {
+        self.messages_sent += other.messages_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.messages_received += other.messages_received;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+/// One topic's use over the windows kept
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopicUsage {
+    pub topic: String,
+    pub total: WindowUsage,
+    /// Each window's traffic, oldest first, ending with the current (partial) one
+    pub windows: Vec<WindowUsage>,
+    /// Sent and received bytes per second over the busiest complete window
+    /// (the current window while it is the only one)
+    pub peak_bytes_per_sec: f64,
+    /// Bytes per second the topic is allowed, if a budget is set
+    pub budget: Option<u64>,
+    /// The busiest window went over the budget
+    pub over_budget: bool,
+}
+
+/// Usage of every topic heard, busiest first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub window_secs: u64,
+    pub topics: Vec<TopicUsage>,
+}
+
+impl UsageReport {
+    pub fn get(&self, topic: &str) -> Option<&TopicUsage> {
+        self.topics.iter().find(|usage| usage.topic == topic)
+    }
+
+    /// Topics whose busiest window went over their budget
+    pub fn over_budget(&self) -> impl Iterator<Item = &TopicUsage> {
+        self.topics.iter().filter(|usage| usage.over_budget)
+    }
+}
+
+/// Per-topic traffic counters in time windows; see the [module docs](self)
+pub struct UsageAccounting {
+    window: Duration,
+    history: usize,
+    classifier: TopicClassifier,
+    budgets: BTreeMap<String, u64>,
+    started: Instant,
+    /// Each topic's windows, by window number since `started`, oldest first
+    topics: Mutex<BTreeMap<String, VecDeque<(u64, WindowUsage)>>>,
+}
+
+impl fmt::Debug for UsageAccounting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsageAccounting")
+            .field("window", &self.window)
+            .field("history", &self.history)
+            .field("budgets", &self.budgets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for UsageAccounting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsageAccounting {
+    pub fn new() -> Self {
+        Self {
+            window: DEFAULT_WINDOW,
+            history: DEFAULT_HISTORY,
+            classifier: Arc::new(default_topic),
+            budgets: BTreeMap::new(),
+            started: Instant::now(),
+            topics: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Count in windows of this length, keeping `history` of them per topic
+    pub fn with_windows(mut self, window: Duration, history: usize) -> Self {
+        self.window = window.max(Duration::from_millis(1));
+        self.history = history.max(1);
+        self
+    }
+
+    /// Name topics with `classifier` instead of [`default_topic`], e.g. from an
+    /// application id at the start of Data payloads
+    pub fn with_classifier(mut self, classifier: impl Fn(MessageType, &[u8]) -> String + Send + Sync + 'static) -> Self {
+        self.classifier = Arc::new(classifier);
+        self
+    }
+
+    /// Allow `topic` this many bytes per second, sent and received together
+    pub fn with_budget(mut self, topic: impl Into<String>, bytes_per_sec: u64) -> Self {
+        self.budgets.insert(topic.into(), bytes_per_sec);
+        self
+    }
+
+    /// The topic a message is counted under
+    pub fn topic(&self, msg_type: MessageType, payload: &[u8]) -> String {
+        (self.classifier)(msg_type, payload)
+    }
+
+    pub fn record_sent(&self, topic: &str, bytes: usize, now: Instant) {
+        self.record(topic, now, |usage| {
+            usage.messages_sent += 1;
+            usage.bytes_sent += bytes as u64;
+        });
+    }
+
+    pub fn record_received(&self, topic: &str, bytes: usize, now: Instant) {
+        self.record(topic, now, |usage| {
+            usage.messages_received += 1;
+            usage.bytes_received += bytes as u64;
+        });
+    }
+
+    fn record(&self, topic: &str, now: Instant, count: impl FnOnce(&mut WindowUsage)) {
+        let window = self.window_at(now);
+        let mut topics = self.topics.lock().unwrap();
+        if !topics.contains_key(topic) {
+            topics.insert(topic.to_string(), VecDeque::new());
+        }
+        let windows = topics.get_mut(topic).expect("inserted above");
+        if windows.back().is_none_or(|(last, _)| *last < window) {
+            windows.push_back((window, WindowUsage::default()));
+        }
+        while windows.front().is_some_and(|(first, _)| first + (self.history as u64) <= window) {
+            windows.pop_front();
+        }
+        // A timestamp from before the latest window is counted in it
+        if let Some((_, usage)) = windows.back_mut() {
+            count(usage);
+        }
+    }
+
+    /// Each topic's traffic over the windows kept as of `now`, busiest first
+    pub fn report(&self, now: Instant) -> UsageReport {
+        let current = self.window_at(now);
+        let first = (current + 1).saturating_sub(self.history as u64);
+        let window_secs = self.window.as_secs_f64();
+        let topics = self.topics.lock().unwrap();
+        let mut report: Vec<TopicUsage> = topics.iter()
+            .map(|(topic, kept)| {
+                let mut windows = vec![WindowUsage::default(); (current - first + 1) as usize];
+                for (window, usage) in kept.iter().filter(|(window, _)| (first..=current).contains(window)) {
+                    windows[(window - first) as usize] = *usage;
+                }
+                let mut total = WindowUsage::default();
+                windows.iter().for_each(|usage| total.add(usage));
+                let complete = &windows[..windows.len() - 1];
+                let peak = match complete.is_empty() {
+                    true => windows[0].bytes(),
+                    false => complete.iter().map(WindowUsage::bytes).max().unwrap_or_default(),
+                } as f64 / window_secs;
+                let budget = self.budgets.get(topic).copied();
+                TopicUsage {
+                    topic: topic.clone(),
+                    total,
+                    windows,
+                    peak_bytes_per_sec: peak,
+                    budget,
+                    over_budget: budget.is_some_and(|budget| peak > budget as f64),
+                }
+            })
+            .filter(|usage| usage.total != WindowUsage::default())
+            .collect();
+        report.sort_by(|a, b| b.total.bytes().cmp(&a.total.bytes()).then_with(|| a.topic.cmp(&b.topic)));
+        UsageReport { window_secs: self.window.as_secs(), topics: report }
+    }
+
+    fn window_at(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.started).as_nanos() / self.window.as_nanos()) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_topics() {
+        assert_eq!(default_topic(MessageType::Control, b"ROLLOUT {\"step\":\"propose\"}"), "Control ROLLOUT");
+        assert_eq!(default_topic(MessageType::Control, b""), "Control");
+        assert_eq!(default_topic(MessageType::Data, b"ROLLOUT"), "Data");
+    }
+
+    #[test]
+    fn test_windows_roll_over_and_budgets_are_checked() {
+        let usage = UsageAccounting::new()
+            .with_windows(Duration::from_secs(10), 3)
+            .with_budget("Data", 50)
+            .with_budget("Control COUNTER", 50);
+        let start = usage.started;
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        usage.record_sent("Data", 900, at(1));
+        usage.record_received("Data", 100, at(2));
+        usage.record_sent("Control COUNTER", 200, at(12));
+        usage.record_sent("Data", 100, at(25));
+
+        let report = usage.report(at(25));
+        let data = report.get("Data").unwrap();
+        assert_eq!(data.windows.iter().map(WindowUsage::bytes).collect::<Vec<_>>(), vec![1000, 0, 100]);
+        assert_eq!((data.total.messages_sent, data.total.messages_received), (2, 1));
+        assert_eq!(data.peak_bytes_per_sec, 100.0);
+        assert_eq!(report.over_budget().map(|usage| usage.topic.as_str()).collect::<Vec<_>>(), vec!["Data"]);
+        assert_eq!(report.topics[1].topic, "Control COUNTER");
+
+        // The first window falls out of the history
+        let report = usage.report(at(31));
+        assert_eq!(report.get("Data").unwrap().total.bytes(), 100);
+        assert!(report.over_budget().next().is_none());
+        assert_eq!(usage.report(at(60)).topics, Vec::new());
+    }
+}
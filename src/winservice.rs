@@ -0,0 +1,211 @@
+//! Running as a Windows service, and reporting transport events to Event
+//! Tracing for Windows (ETW), so depot servers on Windows are supervised and
+//! monitored like their other services.
+//!
+//! [`run`] hands the process to the service control manager, which starts the
+//! service on a thread of its own. While it runs, [`current`] returns the
+//! [`Service`] to report through: [`ready`](Service::ready) once it is up,
+//! [`stopping`](Service::stopping) on the way out. A stop from the service
+//! control manager (`sc stop`, or the machine shutting down) resolves
+//! [`stop_requested`](Service::stop_requested). The service's result becomes
+//! its exit code. Outside a service [`current`] is `None`, so the same binary
+//! also runs from a console.
+//!
+//! An [`EventProvider`] writes text events under a provider GUID, by default
+//! [`PROVIDER_GUID`], for `logman`, `wpr` or an event collector to record.
+
+use std::io::{Error, ErrorKind};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::{define_windows_service, service_dispatcher};
+use windows_sys::Win32::System::Diagnostics::Etw::{EventRegister, EventUnregister, EventWriteString, REGHANDLE};
+use windows_sys::core::GUID;
+
+/// ETW provider of FleetLink events, {6b1f3c2e-9a4d-4e57-b8c1-2f7a9d0e4c35}
+pub const PROVIDER_GUID: u128 = 0x6b1f3c2e_9a4d_4e57_b8c1_2f7a9d0e4c35;
+
+/// How long the service control manager should wait for a pending start or stop
+const PENDING_WAIT: Duration = Duration::from_secs(10);
+
+/// Exit code of a service whose run failed
+const FAILED: ServiceExitCode = ServiceExitCode::ServiceSpecific(1);
+
+type ServiceMain = Box<dyn FnOnce() -> std::io::Result<()> + Send>;
+
+/// The service's name and what to run, for the dispatcher's callback
+static REGISTERED: OnceLock<(String, Mutex<Option<ServiceMain>>)> = OnceLock::new();
+
+static CURRENT: OnceLock<Service> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Run `main` as the service `name`, blocking until it returns. Fails if the
+/// process wasn't started by the service control manager, e.g. from a console.
+pub fn run(name: &str, main: impl FnOnce() -> std::io::Result<()> + Send + 'static) -> std::io::Result<()> {
+    let main: ServiceMain = Box::new(main);
+    if REGISTERED.set((name.to_string(), Mutex::new(Some(main)))).is_err() {
+        return Err(Error::new(ErrorKind::AlreadyExists, "a service already ran in this process"));
+    }
+    service_dispatcher::start(name, ffi_service_main).map_err(Error::other)
+}
+
+/// The service this process runs as, while [`run`] runs it
+pub fn current() -> Option<&'static Service> {
+    CURRENT.get()
+}
+
+fn service_main(_arguments: Vec<std::ffi::OsString>) {
+    let Some((name, main)) = REGISTERED.get() else { return };
+    let Some(main) = main.lock().unwrap().take() else { return };
+
+    let (stop, stop_requested) = oneshot::channel();
+    let stop = Mutex::new(Some(stop));
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Some(stop) = stop.lock().unwrap().take() {
+                let _ = stop.send(());
+            }
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let handle = match service_control_handler::register(name, handler) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Failed to register with the service control manager: {}", e);
+            return;
+        }
+    };
+    let service = CURRENT.get_or_init(|| Service { handle, stop_requested: Mutex::new(Some(stop_requested)) });
+    report(service.set(ServiceState::StartPending, ServiceControlAccept::empty(), ServiceExitCode::NO_ERROR), "start");
+
+    let exit_code = match main() {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(e) => {
+            eprintln!("Service failed: {}", e);
+            FAILED
+        }
+    };
+    report(service.set(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code), "stop");
+}
+
+/// A running Windows service; see the [module docs](self)
+#[derive(Debug)]
+pub struct Service {
+    handle: ServiceStatusHandle,
+    stop_requested: Mutex<Option<oneshot::Receiver<()>>>,
+}
+
+impl Service {
+    /// The service is up and accepts stops
+    pub fn ready(&self) -> std::io::Result<()> {
+        let accepted = ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN;
+        self.set(ServiceState::Running, accepted, ServiceExitCode::NO_ERROR)
+    }
+
+    pub fn stopping(&self) -> std::io::Result<()> {
+        self.set(ServiceState::StopPending, ServiceControlAccept::empty(), ServiceExitCode::NO_ERROR)
+    }
+
+    /// Resolves when the service control manager asks the service to stop;
+    /// only the first caller is told
+    pub async fn stop_requested(&self) {
+        let receiver = self.stop_requested.lock().unwrap().take();
+        match receiver {
+            Some(receiver) => {
+                let _ = receiver.await;
+            }
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn set(&self, state: ServiceState, accepted: ServiceControlAccept, exit_code: ServiceExitCode) -> std::io::Result<()> {
+        let pending = matches!(state, ServiceState::StartPending | ServiceState::StopPending);
+        let status = ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: if pending { PENDING_WAIT } else { Duration::ZERO },
+            process_id: None,
+        };
+        self.handle.set_service_status(status).map_err(Error::other)
+    }
+}
+
+fn report(result: std::io::Result<()>, what: &str) {
+    if let Err(e) = result {
+        eprintln!("Failed to report service {} to the service control manager: {}", what, e);
+    }
+}
+
+/// Severity of an event, as ETW consumers filter by it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventLevel {
+    Critical = 1,
+    Error = 2,
+    Warning = 3,
+    Info = 4,
+    Verbose = 5,
+}
+
+/// A registered ETW provider writing text events
+#[derive(Debug)]
+pub struct EventProvider {
+    handle: REGHANDLE,
+}
+
+impl EventProvider {
+    /// Register the provider with the GUID `guid`, e.g. [`PROVIDER_GUID`]
+    pub fn register(guid: u128) -> std::io::Result<Self> {
+        let guid = GUID::from_u128(guid);
+        let mut handle: REGHANDLE = 0;
+        // SAFETY: the GUID and the handle outlive the call; no enable callback is given
+        let status = unsafe { EventRegister(&guid, None, std::ptr::null(), &mut handle) };
+        if status != 0 {
+            return Err(Error::from_raw_os_error(status as i32));
+        }
+        Ok(Self { handle })
+    }
+
+    /// Write `message` as an event at `level`; cheap when no session listens
+    pub fn write(&self, level: EventLevel, message: &str) -> std::io::Result<()> {
+        let wide: Vec<u16> = message.encode_utf16().chain([0]).collect();
+        // SAFETY: `wide` is NUL-terminated and outlives the call
+        let status = unsafe { EventWriteString(self.handle, level as u8, 0, wide.as_ptr()) };
+        if status != 0 {
+            return Err(Error::from_raw_os_error(status as i32));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventProvider {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from EventRegister and is unregistered once
+        unsafe {
+            EventUnregister(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_written_without_a_listening_session() {
+        let provider = EventProvider::register(PROVIDER_GUID).unwrap();
+        provider.write(EventLevel::Info, "fleetlink test event").unwrap();
+        assert!(current().is_none());
+    }
+}
@@ -0,0 +1,787 @@
+//! Experimental AF_XDP backend (Linux, `--features af-xdp`) for gateway nodes
+//! that aggregate more fleet traffic than the socket path keeps up with.
+//!
+//! An AF_XDP socket takes frames straight off one receive queue of a network
+//! device into memory shared with this process (the UMEM), skipping the
+//! kernel's IP and UDP stack. Which frames it gets is decided by an XDP
+//! program on the device that redirects them into an `XSKMAP`;
+//! `c/fleetlink_xdp.c` is one that redirects UDP to the fleet port and passes
+//! everything else on. [`XdpConfig::with_xsk_map`] names where its map is
+//! pinned, and sockets register themselves there under their queue. Load the
+//! program first; see the comment at the top of the C file.
+//!
+//! Frames arrive as Ethernet, so the receiver checks the IPv4 and UDP headers
+//! itself, then validates the datagram like any multicast receiver. The
+//! [`XdpSender`] writes whole Ethernet frames to the multicast MAC of its
+//! group. Binding needs `CAP_NET_RAW`, and registering in the map `CAP_BPF`
+//! (or root). Copy mode works on every driver; zero-copy needs driver support.
+//!
+//! Only frames arriving on the bound queue are seen: on a multi-queue NIC,
+//! steer fleet traffic to it (`ethtool -N <dev> flow-type udp4 dst-port 12345
+//! action <queue>`) or run a receiver per queue.
+
+use std::ffi::CString;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use zerocopy::AsBytes;
+
+use crate::net::interfaces;
+use crate::receiver::{self, Delivery, ReceiverConfig};
+use crate::stats::TransportStats;
+use crate::transport::{FleetMsgHeader, MessageType};
+
+/// Where `c/fleetlink_xdp.c`'s socket map is pinned by the commands in its header comment
+pub const DEFAULT_XSK_MAP: &str = "/sys/fs/bpf/fleetlink/fleetlink_xsks";
+
+const ETH_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+/// Local network only, like the multicast sender
+const MULTICAST_TTL: u8 = 1;
+
+/// Largest number of frames taken off the receive ring in one go
+const RX_BATCH: u32 = 64;
+/// How often the receive thread looks up from `poll` to see whether it was stopped
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+const BPF_OBJ_GET: libc::c_long = 7;
+
+/// Where and how to open AF_XDP sockets; see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XdpConfig {
+    pub interface: String,
+    /// Receive (or transmit) queue of the device to bind
+    pub queue: u32,
+    /// Frames in the UMEM; half are for receiving and half for sending
+    pub frames: u32,
+    /// Bytes per frame: 2048 or 4096, and at least the interface's MTU plus Ethernet's 14
+    pub frame_size: u32,
+    /// Descriptors per ring, a power of two
+    pub ring_len: u32,
+    /// Ask the driver to DMA into the UMEM instead of copying; binding fails without driver support
+    pub zero_copy: bool,
+    /// Pinned `XSKMAP` of the redirecting XDP program; `None` when something else registers the socket
+    pub xsk_map: Option<PathBuf>,
+}
+
+impl XdpConfig {
+    /// Queue 0 of `interface`, 4096 frames of 2 KiB, 2048-entry rings, copy mode, the [`DEFAULT_XSK_MAP`]
+    pub fn new(interface: impl Into<String>) -> Self {
+        Self {
+            interface: interface.into(),
+            queue: 0,
+            frames: 4096,
+            frame_size: 2048,
+            ring_len: 2048,
+            zero_copy: false,
+            xsk_map: Some(PathBuf::from(DEFAULT_XSK_MAP)),
+        }
+    }
+
+    pub fn with_queue(mut self, queue: u32) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// `frames` frames of `frame_size` bytes
+    pub fn with_umem(mut self, frames: u32, frame_size: u32) -> Self {
+        self.frames = frames;
+        self.frame_size = frame_size;
+        self
+    }
+
+    pub fn with_ring_len(mut self, ring_len: u32) -> Self {
+        self.ring_len = ring_len;
+        self
+    }
+
+    pub fn with_zero_copy(mut self, zero_copy: bool) -> Self {
+        self.zero_copy = zero_copy;
+        self
+    }
+
+    pub fn with_xsk_map(mut self, path: Option<impl Into<PathBuf>>) -> Self {
+        self.xsk_map = path.map(Into::into);
+        self
+    }
+
+    fn validate(&self) -> std::io::Result<()> {
+        let invalid = |reason: String| Err(Error::new(ErrorKind::InvalidInput, reason));
+        if !matches!(self.frame_size, 2048 | 4096) {
+            return invalid(format!("frame size {} is neither 2048 nor 4096", self.frame_size));
+        }
+        if !self.ring_len.is_power_of_two() {
+            return invalid(format!("ring length {} is not a power of two", self.ring_len));
+        }
+        if self.frames < 2 || !self.frames.is_multiple_of(2) {
+            return invalid(format!("{} frames can't be split between receiving and sending", self.frames));
+        }
+        Ok(())
+    }
+}
+
+/// The multicast MAC address IPv4 `group` maps to: 01:00:5e and its low 23 bits
+pub fn multicast_mac(group: Ipv4Addr) -> [u8; 6] {
+    let [_, b, c, d] = group.octets();
+    [0x01, 0x00, 0x5e, b & 0x7f, c, d]
+}
+
+/// The fleet datagram in an Ethernet frame, if the frame is an unfragmented
+/// IPv4 UDP datagram to `port`, untagged or with one VLAN tag
+pub fn decode_frame(frame: &[u8], port: u16) -> Option<(SocketAddr, &[u8])> {
+    let mut offset = ETH_HEADER_LEN;
+    let mut ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+    if ethertype == ETHERTYPE_VLAN {
+        ethertype = u16::from_be_bytes(frame.get(16..18)?.try_into().ok()?);
+        offset += VLAN_TAG_LEN;
+    }
+    if ethertype != ETHERTYPE_IPV4 {
+        return None;
+    }
+
+    let ip = frame.get(offset..)?;
+    let ihl = usize::from(ip.first()? & 0x0f) * 4;
+    if ip[0] >> 4 != 4 || ihl < IPV4_HEADER_LEN || ip.len() < ihl + UDP_HEADER_LEN || ip[9] != IPPROTO_UDP {
+        return None;
+    }
+    // More fragments, or not the first: fleet messages are fragmented above IP
+    if u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0 {
+        return None;
+    }
+    let source = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+
+    let udp = &ip[ihl..];
+    if u16::from_be_bytes([udp[2], udp[3]]) != port {
+        return None;
+    }
+    let udp_len = usize::from(u16::from_be_bytes([udp[4], udp[5]]));
+    let payload = udp.get(UDP_HEADER_LEN..udp_len)?;
+    Some((SocketAddr::V4(SocketAddrV4::new(source, u16::from_be_bytes([udp[0], udp[1]]))), payload))
+}
+
+/// Write the Ethernet frame carrying `datagram` from `source` to the multicast
+/// `group` into `frame`, returning its length; the UDP checksum is left out,
+/// as IPv4 allows
+pub fn encode_frame(frame: &mut [u8], source_mac: [u8; 6], source: SocketAddrV4, group: SocketAddrV4, datagram: &[u8]) -> Option<usize> {
+    let udp_len = UDP_HEADER_LEN + datagram.len();
+    let ip_len = IPV4_HEADER_LEN + udp_len;
+    let len = ETH_HEADER_LEN + ip_len;
+    let frame = frame.get_mut(..len)?;
+    let ip_len = u16::try_from(ip_len).ok()?;
+
+    frame[..6].copy_from_slice(&multicast_mac(*group.ip()));
+    frame[6..12].copy_from_slice(&source_mac);
+    frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+
+    let ip = &mut frame[ETH_HEADER_LEN..];
+    ip[..IPV4_HEADER_LEN].copy_from_slice(&[0x45, 0, 0, 0, 0, 0, 0x40, 0, MULTICAST_TTL, IPPROTO_UDP, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    ip[2..4].copy_from_slice(&ip_len.to_be_bytes());
+    ip[12..16].copy_from_slice(&source.ip().octets());
+    ip[16..20].copy_from_slice(&group.ip().octets());
+    let checksum = ipv4_checksum(&ip[..IPV4_HEADER_LEN]);
+    ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    let udp = &mut ip[IPV4_HEADER_LEN..];
+    udp[..2].copy_from_slice(&source.port().to_be_bytes());
+    udp[2..4].copy_from_slice(&group.port().to_be_bytes());
+    udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    udp[6..8].fill(0);
+    udp[UDP_HEADER_LEN..].copy_from_slice(datagram);
+    Some(len)
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header.chunks(2).map(|word| u32::from(u16::from_be_bytes([word[0], word[1]]))).sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// One of the four rings shared with the kernel: the kernel produces into the
+/// receive and completion rings, this process into the fill and transmit rings
+struct Ring<T> {
+    map: *mut libc::c_void,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    descs: *mut T,
+    mask: u32,
+}
+
+impl<T: Copy> Ring<T> {
+    fn map(fd: RawFd, offsets: &libc::xdp_ring_offset, len: u32, page_offset: libc::off_t) -> std::io::Result<Self> {
+        let map_len = offsets.desc as usize + len as usize * std::mem::size_of::<T>();
+        // SAFETY: mapping the ring the kernel created for this socket, at the offset it names
+        let map = unsafe {
+            libc::mmap(std::ptr::null_mut(), map_len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED | libc::MAP_POPULATE, fd, page_offset)
+        };
+        if map == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: the offsets the kernel reported lie within the mapping
+        unsafe {
+            let at = |offset: u64| map.cast::<u8>().add(offset as usize);
+            Ok(Self {
+                map,
+                map_len,
+                producer: at(offsets.producer).cast(),
+                consumer: at(offsets.consumer).cast(),
+                flags: at(offsets.flags).cast(),
+                descs: at(offsets.desc).cast(),
+                mask: len - 1,
+            })
+        }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: points into the mapping, which lives as long as `self`
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: as for `producer`
+        unsafe { &*self.consumer }
+    }
+
+    fn needs_wakeup(&self) -> bool {
+        // SAFETY: as for `producer`
+        unsafe { &*self.flags }.load(Ordering::Relaxed) & libc::XDP_RING_NEED_WAKEUP != 0
+    }
+
+    fn read(&self, index: u32) -> T {
+        // SAFETY: masked into the ring; the kernel published it before moving the producer
+        unsafe { self.descs.add((index & self.mask) as usize).read() }
+    }
+
+    fn write(&mut self, index: u32, desc: T) {
+        // SAFETY: masked into the ring; the kernel won't read it before the producer moves past it
+        unsafe { self.descs.add((index & self.mask) as usize).write(desc) }
+    }
+
+    /// Entries the kernel produced that haven't been consumed, at most `max`
+    fn available(&self, max: u32) -> (u32, u32) {
+        let consumer = self.consumer().load(Ordering::Relaxed);
+        let producer = self.producer().load(Ordering::Acquire);
+        (consumer, producer.wrapping_sub(consumer).min(max))
+    }
+
+    fn release(&self, consumer: u32) {
+        self.consumer().store(consumer, Ordering::Release);
+    }
+
+    /// Room for this process to produce into
+    fn free(&self) -> u32 {
+        let producer = self.producer().load(Ordering::Relaxed);
+        self.mask + 1 - producer.wrapping_sub(self.consumer().load(Ordering::Acquire))
+    }
+
+    fn submit(&self, producer: u32) {
+        self.producer().store(producer, Ordering::Release);
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        // SAFETY: unmapping what `map` mapped; nothing borrows from it past `self`
+        unsafe { libc::munmap(self.map, self.map_len) };
+    }
+}
+
+/// An AF_XDP socket bound to one queue, with its UMEM and rings
+struct XdpSocket {
+    // Dropped before the rings and UMEM: the kernel stops using them once the socket closes
+    fd: OwnedFd,
+    fill: Ring<u64>,
+    completion: Ring<u64>,
+    rx: Option<Ring<libc::xdp_desc>>,
+    tx: Ring<libc::xdp_desc>,
+    umem: *mut u8,
+    umem_len: usize,
+    frame_size: u32,
+    /// UMEM frames neither on a ring nor in flight, for sending
+    free_tx: Vec<u64>,
+}
+
+// SAFETY: the mappings are owned by the socket and only touched through `&mut self`
+unsafe impl Send for XdpSocket {}
+
+impl XdpSocket {
+    fn bind(config: &XdpConfig, receive: bool) -> std::io::Result<Self> {
+        config.validate()?;
+        let interface = interfaces::select(Some(&config.interface))?;
+
+        // SAFETY: plain socket creation; the descriptor is owned right away
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: `fd` was just created and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let umem_len = config.frames as usize * config.frame_size as usize;
+        // SAFETY: a fresh anonymous mapping, unmapped in `Drop`
+        let umem = unsafe {
+            libc::mmap(std::ptr::null_mut(), umem_len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+        };
+        if umem == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        let unmap_on_error = |e: Error| {
+            // SAFETY: the UMEM mapping isn't owned by a socket yet
+            unsafe { libc::munmap(umem, umem_len) };
+            e
+        };
+
+        let registration = libc::xdp_umem_reg {
+            addr: umem as u64,
+            len: umem_len as u64,
+            chunk_size: config.frame_size,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        set_option(&fd, libc::XDP_UMEM_REG, &registration).map_err(unmap_on_error)?;
+        let half = config.frames / 2;
+        let completion_len = config.ring_len.max(half.next_power_of_two());
+        set_option(&fd, libc::XDP_UMEM_FILL_RING, &config.ring_len).map_err(unmap_on_error)?;
+        set_option(&fd, libc::XDP_UMEM_COMPLETION_RING, &completion_len).map_err(unmap_on_error)?;
+        if receive {
+            set_option(&fd, libc::XDP_RX_RING, &config.ring_len).map_err(unmap_on_error)?;
+        }
+        set_option(&fd, libc::XDP_TX_RING, &config.ring_len).map_err(unmap_on_error)?;
+        let offsets: libc::xdp_mmap_offsets = get_option(&fd, libc::XDP_MMAP_OFFSETS).map_err(unmap_on_error)?;
+
+        let raw = fd.as_raw_fd();
+        let rings = (|| {
+            let fill = Ring::map(raw, &offsets.fr, config.ring_len, libc::XDP_UMEM_PGOFF_FILL_RING as libc::off_t)?;
+            let completion = Ring::map(raw, &offsets.cr, completion_len, libc::XDP_UMEM_PGOFF_COMPLETION_RING as libc::off_t)?;
+            let rx = receive.then(|| Ring::map(raw, &offsets.rx, config.ring_len, libc::XDP_PGOFF_RX_RING)).transpose()?;
+            let tx = Ring::map(raw, &offsets.tx, config.ring_len, libc::XDP_PGOFF_TX_RING)?;
+            Ok((fill, completion, rx, tx))
+        })();
+        let (fill, completion, rx, tx) = rings.map_err(unmap_on_error)?;
+
+        // Receive into the first half of the frames, send from the second
+        let mut socket = Self {
+            fd,
+            fill,
+            completion,
+            rx,
+            tx,
+            umem: umem.cast(),
+            umem_len,
+            frame_size: config.frame_size,
+            free_tx: (half..config.frames).map(|frame| u64::from(frame) * u64::from(config.frame_size)).collect(),
+        };
+        if receive {
+            let frames = half.min(config.ring_len);
+            for frame in 0..frames {
+                socket.fill.write(frame, u64::from(frame) * u64::from(config.frame_size));
+            }
+            socket.fill.submit(frames);
+        }
+
+        let mode = if config.zero_copy { libc::XDP_ZEROCOPY } else { libc::XDP_COPY };
+        let address = libc::sockaddr_xdp {
+            sxdp_family: libc::AF_XDP as u16,
+            sxdp_flags: mode | libc::XDP_USE_NEED_WAKEUP,
+            sxdp_ifindex: interface.index,
+            sxdp_queue_id: config.queue,
+            sxdp_shared_umem_fd: 0,
+        };
+        // SAFETY: `address` is a valid sockaddr_xdp of the given length
+        let status = unsafe {
+            libc::bind(raw, (&address as *const libc::sockaddr_xdp).cast(), std::mem::size_of::<libc::sockaddr_xdp>() as libc::socklen_t)
+        };
+        if status != 0 {
+            let e = Error::last_os_error();
+            return Err(Error::new(e.kind(), format!("binding AF_XDP to {} queue {}: {}", interface.name, config.queue, e)));
+        }
+
+        if receive && let Some(map) = &config.xsk_map {
+            register(map, config.queue, raw)
+                .map_err(|e| Error::new(e.kind(), format!("registering in XSKMAP {}: {}", map.display(), e)))?;
+        }
+        Ok(socket)
+    }
+
+    /// Wait up to `timeout` for frames, then hand over up to a batch of them
+    /// and give their UMEM frames back to the kernel
+    fn receive(&mut self, timeout: Duration, mut handle: impl FnMut(&[u8])) -> std::io::Result<u32> {
+        let Some(rx) = &self.rx else { return Ok(0) };
+        let (mut consumer, mut count) = rx.available(RX_BATCH);
+        if count == 0 {
+            let mut poll = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            // SAFETY: one valid pollfd
+            if unsafe { libc::poll(&mut poll, 1, timeout.as_millis().min(i32::MAX as u128) as i32) } < 0 {
+                let e = Error::last_os_error();
+                return if e.kind() == ErrorKind::Interrupted { Ok(0) } else { Err(e) };
+            }
+            (consumer, count) = rx.available(RX_BATCH);
+        }
+        // Frames can only go back to the kernel as fast as the fill ring takes them
+        let count = count.min(self.fill.free());
+        let mut fill_producer = self.fill.producer().load(Ordering::Relaxed);
+        for _ in 0..count {
+            let desc = rx.read(consumer);
+            if desc.addr as usize + desc.len as usize <= self.umem_len {
+                // SAFETY: within the UMEM, checked above, and the kernel is done with the frame
+                handle(unsafe { std::slice::from_raw_parts(self.umem.add(desc.addr as usize), desc.len as usize) });
+            }
+            let frame = desc.addr - desc.addr % u64::from(self.frame_size);
+            self.fill.write(fill_producer, frame);
+            fill_producer = fill_producer.wrapping_add(1);
+            consumer = consumer.wrapping_add(1);
+        }
+        rx.release(consumer);
+        self.fill.submit(fill_producer);
+        if count > 0 && self.fill.needs_wakeup() {
+            self.wake(libc::POLLIN);
+        }
+        Ok(count)
+    }
+
+    /// Queue one Ethernet frame, written by `write` into a free UMEM frame, for transmission
+    fn send(&mut self, write: impl FnOnce(&mut [u8]) -> Option<usize>) -> std::io::Result<()> {
+        self.reclaim();
+        let Some(frame) = self.free_tx.pop() else {
+            return Err(Error::new(ErrorKind::WouldBlock, "every transmit frame is in flight"));
+        };
+        if self.tx.free() == 0 {
+            self.free_tx.push(frame);
+            return Err(Error::new(ErrorKind::WouldBlock, "transmit ring is full"));
+        }
+        // SAFETY: the frame is within the UMEM and owned by this process until submitted
+        let buf = unsafe { std::slice::from_raw_parts_mut(self.umem.add(frame as usize), self.frame_size as usize) };
+        let Some(len) = write(buf) else {
+            self.free_tx.push(frame);
+            return Err(Error::new(ErrorKind::InvalidInput, format!("frame over the {} byte UMEM frame size", self.frame_size)));
+        };
+        let producer = self.tx.producer().load(Ordering::Relaxed);
+        self.tx.write(producer, libc::xdp_desc { addr: frame, len: len as u32, options: 0 });
+        self.tx.submit(producer.wrapping_add(1));
+        if self.tx.needs_wakeup() {
+            self.kick()?;
+        }
+        Ok(())
+    }
+
+    /// Take back the frames the kernel finished sending
+    fn reclaim(&mut self) {
+        let (mut consumer, count) = self.completion.available(u32::MAX);
+        for _ in 0..count {
+            self.free_tx.push(self.completion.read(consumer));
+            consumer = consumer.wrapping_add(1);
+        }
+        self.completion.release(consumer);
+    }
+
+    /// Tell the kernel there is something to transmit
+    fn kick(&self) -> std::io::Result<()> {
+        // SAFETY: a zero-length send without buffers, which AF_XDP takes as a wake-up
+        let sent = unsafe { libc::sendto(self.fd.as_raw_fd(), std::ptr::null(), 0, libc::MSG_DONTWAIT, std::ptr::null(), 0) };
+        if sent < 0 {
+            let e = Error::last_os_error();
+            // The kernel is already busy with the ring, or short of buffers for a moment
+            if !matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EBUSY | libc::ENOBUFS | libc::ENETDOWN)) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn wake(&self, events: libc::c_short) {
+        let mut poll = libc::pollfd { fd: self.fd.as_raw_fd(), events, revents: 0 };
+        // SAFETY: one valid pollfd; a zero timeout only nudges the driver
+        unsafe { libc::poll(&mut poll, 1, 0) };
+    }
+
+    /// Frames the kernel dropped for this socket: no room on the receive ring,
+    /// no fill ring entries, or malformed descriptors
+    fn kernel_drops(&self) -> std::io::Result<u64> {
+        let stats: libc::xdp_statistics = get_option(&self.fd, libc::XDP_STATISTICS)?;
+        Ok(stats.rx_dropped + stats.rx_ring_full + stats.rx_fill_ring_empty_descs + stats.rx_invalid_descs)
+    }
+}
+
+impl Drop for XdpSocket {
+    fn drop(&mut self) {
+        // SAFETY: the UMEM outlives every slice handed out, which borrow `self`
+        unsafe { libc::munmap(self.umem.cast(), self.umem_len) };
+    }
+}
+
+fn set_option<T>(fd: &OwnedFd, name: libc::c_int, value: &T) -> std::io::Result<()> {
+    // SAFETY: `value` is a valid T of the given length for the call's duration
+    let status = unsafe {
+        libc::setsockopt(fd.as_raw_fd(), libc::SOL_XDP, name, (value as *const T).cast(), std::mem::size_of::<T>() as libc::socklen_t)
+    };
+    if status != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn get_option<T>(fd: &OwnedFd, name: libc::c_int) -> std::io::Result<T> {
+    let mut value = std::mem::MaybeUninit::<T>::zeroed();
+    let mut len = std::mem::size_of::<T>() as libc::socklen_t;
+    // SAFETY: the kernel writes at most `len` bytes into `value`
+    let status = unsafe { libc::getsockopt(fd.as_raw_fd(), libc::SOL_XDP, name, value.as_mut_ptr().cast(), &mut len) };
+    if status != 0 {
+        return Err(Error::last_os_error());
+    }
+    // SAFETY: zeroed, then filled by the kernel; every field is an integer
+    Ok(unsafe { value.assume_init() })
+}
+
+/// `bpf(2)` attributes for `BPF_OBJ_GET`
+#[repr(C)]
+struct ObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+/// `bpf(2)` attributes for `BPF_MAP_UPDATE_ELEM`
+#[repr(C)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+fn bpf<T>(command: libc::c_long, attr: &T) -> std::io::Result<libc::c_long> {
+    // SAFETY: `attr` is the attribute struct for `command`, valid for the call's duration
+    let result = unsafe { libc::syscall(libc::SYS_bpf, command, attr as *const T, std::mem::size_of::<T>()) };
+    if result < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(result)
+}
+
+/// Put `socket` into the pinned `XSKMAP` at `map`, under `queue`
+fn register(map: &Path, queue: u32, socket: RawFd) -> std::io::Result<()> {
+    let path = CString::new(map.as_os_str().as_encoded_bytes()).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let map_fd = bpf(BPF_OBJ_GET, &ObjGetAttr { pathname: path.as_ptr() as u64, bpf_fd: 0, file_flags: 0 })?;
+    // SAFETY: the kernel just handed over this descriptor
+    let map_fd = unsafe { OwnedFd::from_raw_fd(map_fd as RawFd) };
+    let value = socket as u32;
+    bpf(BPF_MAP_UPDATE_ELEM, &MapElemAttr {
+        map_fd: map_fd.as_raw_fd() as u32,
+        _pad: 0,
+        key: &queue as *const u32 as u64,
+        value: &value as *const u32 as u64,
+        flags: 0,
+    })?;
+    Ok(())
+}
+
+/// The MAC address of `interface`
+fn hardware_address(interface: &str) -> std::io::Result<[u8; 6]> {
+    let path = format!("/sys/class/net/{}/address", interface);
+    let text = std::fs::read_to_string(&path)?;
+    let bytes: Vec<u8> = text.trim().split(':').filter_map(|byte| u8::from_str_radix(byte, 16).ok()).collect();
+    bytes.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is not a MAC address", path)))
+}
+
+/// Receives fleet datagrams to one port from an AF_XDP socket
+pub struct XdpReceiver {
+    socket: XdpSocket,
+    port: u16,
+    config: ReceiverConfig,
+}
+
+impl XdpReceiver {
+    /// Bind to the queue `xdp` names and register in its `XSKMAP`
+    pub fn bind(xdp: &XdpConfig, port: u16, config: ReceiverConfig) -> std::io::Result<Self> {
+        Ok(Self { socket: XdpSocket::bind(xdp, true)?, port, config })
+    }
+
+    /// Hand over the messages that arrived, waiting up to `timeout` for the
+    /// first; returns how many frames were taken off the ring, fleet messages or not
+    pub fn poll(&mut self, timeout: Duration, message_handler: &mut impl FnMut(Delivery)) -> std::io::Result<u32> {
+        let (port, config) = (self.port, &self.config);
+        self.socket.receive(timeout, |frame| {
+            let Some((addr, datagram)) = decode_frame(frame, port) else { return };
+            if !config.screen(datagram, addr) {
+                return;
+            }
+            match receiver::inspect(datagram, addr, config) {
+                Ok(delivery) if !config.admits(&delivery.header, &delivery.extensions, &delivery.payload, addr) => {}
+                Ok(delivery) => message_handler(delivery),
+                Err(issues) => eprintln!("Dropped message from {}: {}", addr, receiver::describe(&issues)),
+            }
+        })
+    }
+
+    /// Frames the kernel dropped before this receiver saw them, e.g. because it fell behind
+    pub fn kernel_drops(&self) -> std::io::Result<u64> {
+        self.socket.kernel_drops()
+    }
+}
+
+/// Receive fleet messages to `port` over AF_XDP, validating them as `config`
+/// says. The loop runs on a thread of its own, with the config's
+/// [thread scheduling](ReceiverConfig::with_thread_scheduling) if it has one,
+/// and stops when this future is dropped.
+pub async fn start_xdp_rx(
+    xdp: XdpConfig,
+    port: u16,
+    config: ReceiverConfig,
+    mut message_handler: impl FnMut(Delivery) + Send + 'static
+) -> std::io::Result<()> {
+    let mut receiver = XdpReceiver::bind(&xdp, port, config)?;
+    let (done, finished) = futures::channel::oneshot::channel();
+    let (_cancel, mut cancelled) = futures::channel::oneshot::channel::<()>();
+    std::thread::Builder::new().name("fleetlink-xdp".to_string()).spawn(move || {
+        if let Some(Err(e)) = receiver.config.thread_scheduling().map(|scheduling| scheduling.apply_to_current_thread()) {
+            eprintln!("Receiving without the configured thread scheduling: {}", e);
+        }
+        while let Ok(None) = cancelled.try_recv() {
+            if let Err(e) = receiver.poll(STOP_CHECK, &mut message_handler) {
+                let _ = done.send(Err(e));
+                return;
+            }
+        }
+    })?;
+    finished.await.unwrap_or(Ok(()))
+}
+
+/// Sends fleet messages to a multicast group as raw Ethernet frames over AF_XDP
+pub struct XdpSender {
+    socket: XdpSocket,
+    source_mac: [u8; 6],
+    source: SocketAddrV4,
+    group: SocketAddrV4,
+    sender_id: u32,
+    sequence: u16,
+    stats: Arc<TransportStats>,
+}
+
+impl XdpSender {
+    /// Send from the interface and queue `xdp` names, from its first IPv4
+    /// address; use a queue no [`XdpReceiver`] on this host is bound to
+    pub fn new(xdp: &XdpConfig, group: Ipv4Addr, port: u16, sender_id: u32) -> std::io::Result<Self> {
+        let interface = interfaces::select(Some(&xdp.interface))?;
+        let Some(&address) = interface.ipv4.first() else {
+            return Err(Error::new(ErrorKind::AddrNotAvailable, format!("{} has no IPv4 address", interface.name)));
+        };
+        Ok(Self {
+            socket: XdpSocket::bind(xdp, false)?,
+            source_mac: hardware_address(&interface.name)?,
+            source: SocketAddrV4::new(address, port),
+            group: SocketAddrV4::new(group, port),
+            sender_id,
+            sequence: 0,
+            stats: Arc::new(TransportStats::new()),
+        })
+    }
+
+    /// Count sends in shared stats, e.g. the ones a multicast sender on the same node uses
+    pub fn with_stats(mut self, stats: Arc<TransportStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+
+    /// Queue a message for transmission; fails with `WouldBlock` while every
+    /// transmit frame is in flight
+    pub fn send_message(&mut self, msg_type: MessageType, payload: &[u8]) -> std::io::Result<()> {
+        let len = u16::try_from(payload.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} byte payload is too large", payload.len())))?;
+        let header = FleetMsgHeader::new(msg_type, self.sender_id, self.sequence, len);
+        let datagram = [header.as_bytes(), payload].concat();
+        let (source_mac, source, group) = (self.source_mac, self.source, self.group);
+        self.socket.send(|frame| encode_frame(frame, source_mac, source, group, &datagram))?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stats.record_sent(datagram.len());
+        Ok(())
+    }
+
+    pub fn send_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.send_message(MessageType::Data, data)
+    }
+
+    pub fn send_control(&mut self, command: &str) -> std::io::Result<()> {
+        self.send_message(MessageType::Control, command.as_bytes())
+    }
+
+    /// Where frames are addressed
+    pub fn group(&self) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(*self.group.ip()), self.group.port())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let header = FleetMsgHeader::new(MessageType::Data, 0xA1, 3, 4);
+        let datagram = [header.as_bytes(), b"pose"].concat();
+        let source = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 7), 12345);
+        let group = SocketAddrV4::new(Ipv4Addr::new(239, 129, 2, 3), 12345);
+        let mut frame = [0u8; 2048];
+        let len = encode_frame(&mut frame, [2, 0, 0, 0, 0, 7], source, group, &datagram).unwrap();
+        assert_eq!(len, 14 + 20 + 8 + datagram.len());
+        assert_eq!(frame[..6], [0x01, 0x00, 0x5e, 0x01, 2, 3]);
+        // A header with its checksum filled in sums to zero
+        assert_eq!(ipv4_checksum(&frame[14..34]), 0);
+
+        let (addr, decoded) = decode_frame(&frame[..len], 12345).unwrap();
+        assert_eq!(addr, SocketAddr::V4(source));
+        assert_eq!(decoded, datagram.as_slice());
+        assert!(decode_frame(&frame[..len], 12346).is_none());
+        assert!(decode_frame(&frame[..len - 1], 12345).is_none());
+        assert!(encode_frame(&mut frame[..40], [0; 6], source, group, &datagram).is_none());
+    }
+
+    #[test]
+    fn test_decode_skips_other_traffic() {
+        let source = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 7), 40000);
+        let group = SocketAddrV4::new(Ipv4Addr::new(239, 0, 0, 1), 12345);
+        let mut frame = [0u8; 128];
+        let len = encode_frame(&mut frame, [0; 6], source, group, b"x").unwrap();
+
+        let mut tagged = frame[..12].to_vec();
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x05]);
+        tagged.extend_from_slice(&frame[12..len]);
+        assert_eq!(decode_frame(&tagged, 12345).unwrap().1, b"x");
+
+        let mut tcp = frame;
+        tcp[14 + 9] = 6;
+        assert!(decode_frame(&tcp[..len], 12345).is_none());
+        let mut fragment = frame;
+        fragment[14 + 6] = 0x20;
+        assert!(decode_frame(&fragment[..len], 12345).is_none());
+        let mut ipv6 = frame;
+        ipv6[12..14].copy_from_slice(&0x86ddu16.to_be_bytes());
+        assert!(decode_frame(&ipv6[..len], 12345).is_none());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(XdpConfig::new("eth0").validate().is_ok());
+        assert!(XdpConfig::new("eth0").with_umem(4096, 1500).validate().is_err());
+        assert!(XdpConfig::new("eth0").with_ring_len(1000).validate().is_err());
+        assert!(XdpConfig::new("eth0").with_umem(1, 2048).validate().is_err());
+    }
+}
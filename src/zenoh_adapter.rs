@@ -0,0 +1,162 @@
+//! Exposes FleetLink channels as zenoh key expressions, so zenoh applications
+//! can join fleet messaging one component at a time instead of in a flag-day switch.
+//!
+//! Data and Control messages heard on a channel are put on
+//! `<prefix>/<channel>/<data|control>/<sender_id>` with the message payload as
+//! the value; subscribe to `fleetlink/<channel>/**` to follow a channel.
+//! Values put on `<prefix>/<channel>/<data|control>`, without a sender id, are
+//! sent on the channel as this adapter's sender id. Keys with a sender id are
+//! traffic another adapter mirrored from its own LAN and are never sent back,
+//! which together with ignoring our own sends on multicast keeps adapters from looping.
+
+use async_std::channel;
+use async_std::task;
+use futures::future::{Either, select, try_join_all};
+use std::net::SocketAddr;
+use std::pin::pin;
+use zenoh::sample::Locality;
+use zenoh::{Config, Session};
+
+use crate::channels::{Channel, ChannelRegistry};
+use crate::transport::{FleetMsgHeader, MessageType, MulticastSender, start_multicast_rx};
+
+/// First chunk of every key this adapter uses unless told otherwise
+pub const DEFAULT_PREFIX: &str = "fleetlink";
+
+/// Messages heard on multicast that may wait for zenoh before new ones are dropped
+const PUT_QUEUE_LEN: usize = 1024;
+
+/// The key chunk naming a message type; only Data and Control cross to zenoh
+pub fn type_chunk(msg_type: MessageType) -> Option<&'static str> {
+    match msg_type {
+        MessageType::Data => Some("data"),
+        MessageType::Control => Some("control"),
+        _ => None,
+    }
+}
+
+/// Key a message from `sender_id` on `channel` is put on
+pub fn key_for(prefix: &str, channel: &str, msg_type: MessageType, sender_id: u32) -> Option<String> {
+    Some(format!("{}/{}/{}/{}", prefix, channel, type_chunk(msg_type)?, sender_id))
+}
+
+/// The message type to send for a value put on `key` by a zenoh application,
+/// or `None` for anything else, including traffic mirrored by another adapter
+pub fn message_type_for(prefix: &str, channel: &str, key: &str) -> Option<MessageType> {
+    let kind = key.strip_prefix(prefix)?.strip_prefix('/')?.strip_prefix(channel)?.strip_prefix('/')?;
+    match kind {
+        "data" => Some(MessageType::Data),
+        "control" => Some(MessageType::Control),
+        _ => None,
+    }
+}
+
+/// Mirrors FleetLink channels to and from a zenoh session
+pub struct ZenohAdapter {
+    session: Session,
+    prefix: String,
+    sender_id: u32,
+}
+
+impl std::fmt::Debug for ZenohAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZenohAdapter")
+            .field("zid", &self.session.zid())
+            .field("prefix", &self.prefix)
+            .field("sender_id", &self.sender_id)
+            .finish()
+    }
+}
+
+impl ZenohAdapter {
+    /// Open a zenoh session with `config`; values from zenoh are sent on multicast as `sender_id`
+    pub async fn open(config: Config, sender_id: u32) -> std::io::Result<Self> {
+        let session = zenoh::open(config).await.map_err(std::io::Error::other)?;
+        Ok(Self::new(session, sender_id))
+    }
+
+    /// Use a session the application already has
+    pub fn new(session: Session, sender_id: u32) -> Self {
+        Self { session, prefix: DEFAULT_PREFIX.to_string(), sender_id }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Mirror every channel in `registry` until one of them fails
+    pub async fn run(&self, registry: &ChannelRegistry) -> std::io::Result<()> {
+        try_join_all(registry.channels().map(|(name, channel)| self.run_channel(name, channel))).await?;
+        Ok(())
+    }
+
+    /// Mirror one channel in both directions until either side fails
+    pub async fn run_channel(&self, name: &str, channel: Channel) -> std::io::Result<()> {
+        let to_zenoh = self.multicast_to_zenoh(name, channel);
+        let from_zenoh = self.zenoh_to_multicast(name, channel);
+        match select(pin!(to_zenoh), pin!(from_zenoh)).await {
+            Either::Left((result, _)) | Either::Right((result, _)) => result,
+        }
+    }
+
+    async fn multicast_to_zenoh(&self, name: &str, channel: Channel) -> std::io::Result<()> {
+        let (puts, queued) = channel::bounded(PUT_QUEUE_LEN);
+        let (prefix, own_id) = (self.prefix.clone(), self.sender_id);
+        let channel_name = name.to_string();
+        let handler = move |header: FleetMsgHeader, payload: Vec<u8>, _addr: SocketAddr| {
+            // Our own sends come back over multicast loopback
+            if header.sender_id.get() == own_id {
+                return;
+            }
+            if let Some(key) = key_for(&prefix, &channel_name, header.message_type(), header.sender_id.get()) {
+                // Drop rather than stall the receiver when zenoh can't keep up
+                let _ = puts.try_send((key, payload));
+            }
+        };
+        let receiver = task::spawn(start_multicast_rx(channel.group, channel.port, handler));
+
+        while let Ok((key, payload)) = queued.recv().await {
+            self.session.put(key, payload).await.map_err(std::io::Error::other)?;
+        }
+        // The handler, and so the sender half, only goes away when the receiver stops
+        receiver.await.map_err(std::io::Error::from)
+    }
+
+    async fn zenoh_to_multicast(&self, name: &str, channel: Channel) -> std::io::Result<()> {
+        let subscriber = self.session
+            .declare_subscriber(format!("{}/{}/*", self.prefix, name))
+            .allowed_origin(Locality::Remote)
+            .await
+            .map_err(std::io::Error::other)?;
+        let mut sender = MulticastSender::new(channel.group, channel.port, self.sender_id).await?;
+
+        loop {
+            let sample = subscriber.recv_async().await.map_err(std::io::Error::other)?;
+            if let Some(msg_type) = message_type_for(&self.prefix, name, sample.key_expr().as_str()) {
+                sender.send_message(msg_type, &sample.payload().to_bytes()).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_mapping() {
+        assert_eq!(key_for("fleetlink", "telemetry", MessageType::Data, 17).as_deref(), Some("fleetlink/telemetry/data/17"));
+        assert_eq!(key_for("fleetlink", "telemetry", MessageType::Heartbeat, 17), None);
+
+        assert_eq!(message_type_for("fleetlink", "telemetry", "fleetlink/telemetry/control"), Some(MessageType::Control));
+        // Mirrored by another adapter, another channel, another prefix
+        assert_eq!(message_type_for("fleetlink", "telemetry", "fleetlink/telemetry/data/17"), None);
+        assert_eq!(message_type_for("fleetlink", "telemetry", "fleetlink/telemetry2/data"), None);
+        assert_eq!(message_type_for("fleetlink", "telemetry", "site/telemetry/data"), None);
+    }
+}
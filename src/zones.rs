@@ -0,0 +1,149 @@
+//! Site zones and geofences a sender says it is in, so stationary
+//! infrastructure can listen only to the vehicles in its area.
+//!
+//! A sender given a [`Zone`] with
+//! [`MulticastSender::with_zone`](crate::transport::MulticastSender::with_zone)
+//! stamps it on every multicast, heartbeats included, as the [`ZONE`]
+//! extension; a moving vehicle updates it with `set_zone` as it crosses
+//! boundaries. A receiver with a [`ZoneFilter`] on its
+//! [`ReceiverConfig`](crate::receiver::ReceiverConfig) drops annotated
+//! messages from other areas before the handler sees them.
+//!
+//! [`ZONE`]: crate::extensions::ZONE
+
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+
+/// Most geofences one message can name, to fit one extension
+pub const MAX_GEOFENCES: usize = 62;
+
+/// Where a sender is: the site zone it is in (0 if none) and the geofences it is inside
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Zone {
+    site_zone: u32,
+    geofences: BTreeSet<u32>,
+}
+
+impl Zone {
+    pub fn new(site_zone: u32) -> Self {
+        Self { site_zone, geofences: BTreeSet::new() }
+    }
+
+    /// Also inside these geofences; at most [`MAX_GEOFENCES`]
+    pub fn with_geofences(mut self, geofences: impl IntoIterator<Item = u32>) -> std::io::Result<Self> {
+        self.geofences.extend(geofences);
+        if self.geofences.len() > MAX_GEOFENCES {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("at most {} geofences", MAX_GEOFENCES)));
+        }
+        Ok(self)
+    }
+
+    pub fn site_zone(&self) -> Option<u32> {
+        (self.site_zone != 0).then_some(self.site_zone)
+    }
+
+    pub fn geofences(&self) -> impl Iterator<Item = u32> + '_ {
+        self.geofences.iter().copied()
+    }
+
+    /// The site zone, then each geofence, as little-endian u32s
+    pub fn encode(&self) -> Vec<u8> {
+        std::iter::once(self.site_zone).chain(self.geofences()).flat_map(u32::to_le_bytes).collect()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(4) {
+            return None;
+        }
+        let mut ids = bytes.chunks_exact(4).map(|id| u32::from_le_bytes(id.try_into().unwrap()));
+        let site_zone = ids.next()?;
+        Some(Self { site_zone, geofences: ids.collect() })
+    }
+}
+
+/// Which zones a receiver wants messages from.
+///
+/// An annotated message passes if its site zone is one of the filter's zones
+/// or it is inside one of the filter's geofences. Messages without a zone
+/// (older firmware, infrastructure, a goodbye from a dropped sender) pass
+/// unless the filter is told to drop them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneFilter {
+    zones: BTreeSet<u32>,
+    geofences: BTreeSet<u32>,
+    unannotated: bool,
+}
+
+impl Default for ZoneFilter {
+    fn default() -> Self {
+        Self { zones: BTreeSet::new(), geofences: BTreeSet::new(), unannotated: true }
+    }
+}
+
+impl ZoneFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass messages from senders in these site zones
+    pub fn with_zones(mut self, zones: impl IntoIterator<Item = u32>) -> Self {
+        self.zones.extend(zones);
+        self
+    }
+
+    /// Pass messages from senders inside any of these geofences
+    pub fn with_geofences(mut self, geofences: impl IntoIterator<Item = u32>) -> Self {
+        self.geofences.extend(geofences);
+        self
+    }
+
+    /// Whether messages that carry no zone pass (the default)
+    pub fn with_unannotated(mut self, pass: bool) -> Self {
+        self.unannotated = pass;
+        self
+    }
+
+    pub fn accepts(&self, zone: Option<&Zone>) -> bool {
+        match zone {
+            None => self.unannotated,
+            Some(zone) => {
+                zone.site_zone().is_some_and(|site_zone| self.zones.contains(&site_zone))
+                    || zone.geofences().any(|geofence| self.geofences.contains(&geofence))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::Extensions;
+
+    #[test]
+    fn test_zone_round_trips_through_extensions() {
+        let zone = Zone::new(12).with_geofences([40, 41]).unwrap();
+        let mut extensions = Extensions::new();
+        extensions.set_zone(&zone);
+        let bytes = extensions.prepend_to(b"position");
+        let (parsed, payload) = Extensions::split(&bytes).unwrap();
+        assert_eq!(parsed.zone(), Some(zone));
+        assert_eq!(payload, b"position");
+
+        assert_eq!(Zone::decode(&[1, 2, 3]), None);
+        assert_eq!(Zone::new(0).site_zone(), None);
+        assert!(Zone::new(1).with_geofences(0..MAX_GEOFENCES as u32 + 1).is_err());
+    }
+
+    #[test]
+    fn test_filter_passes_its_area() {
+        let filter = ZoneFilter::new().with_zones([12]).with_geofences([40]);
+        assert!(filter.accepts(Some(&Zone::new(12))));
+        assert!(filter.accepts(Some(&Zone::new(7).with_geofences([40]).unwrap())));
+        assert!(!filter.accepts(Some(&Zone::new(7).with_geofences([41]).unwrap())));
+        // Zone 0 is no zone at all, not a zone of its own
+        assert!(!ZoneFilter::new().with_zones([0]).accepts(Some(&Zone::new(0))));
+
+        assert!(filter.accepts(None));
+        assert!(!filter.with_unannotated(false).accepts(None));
+    }
+}
@@ -0,0 +1,81 @@
+use fleetlink_transport::protocol::{self, FleetMsgHeader, MessageType};
+use fleetlink_transport::ProtocolFeatures;
+use fleetlink_transport::protocol::padding::PaddingBuckets;
+use zerocopy::AsBytes;
+
+#[test]
+fn test_constants_match_the_wire_format() {
+    let header = FleetMsgHeader::new(MessageType::Digest, 0x0102_0304, 7, protocol::MAX_PAYLOAD_LEN as u16)
+        .with_features(ProtocolFeatures::CRC32 | ProtocolFeatures::EXTENSIONS);
+    let bytes = header.as_bytes();
+
+    assert_eq!(bytes.len(), protocol::HEADER_LEN);
+    assert_eq!(&bytes[0..4], &protocol::MAGIC.to_le_bytes());
+    assert_eq!(bytes[4], protocol::VERSION);
+    assert_eq!(bytes[5] & protocol::MSG_TYPE_MASK, MessageType::Digest.code());
+    assert_eq!(bytes[5] >> protocol::FEATURES_SHIFT, 0xA);
+    assert_eq!(&bytes[16..20], &0x0102_0304u32.to_le_bytes());
+    assert_eq!(u16::from_le_bytes([bytes[20], bytes[21]]) as usize, protocol::MAX_PAYLOAD_LEN);
+
+    // Every code in the range parses to its own type, and nothing outside it validates
+    for code in protocol::MESSAGE_TYPES.chain(protocol::APPLICATION_TYPES) {
+        assert_eq!(MessageType::from(code).code(), code);
+        assert!(MessageType::from(code).is_known());
+    }
+    assert_eq!(MessageType::from(12), MessageType::Application(12));
+    assert_eq!(MessageType::from(0), MessageType::Unknown(0));
+    let mut unknown = header;
+    unknown.msg_type = protocol::MESSAGE_TYPES.end() + 1;
+    assert!(!unknown.validation_issues().is_empty());
+}
+
+#[test]
+fn test_versions_and_limits_are_enforced() {
+    let checksummed = |version: u8| {
+        let mut header = FleetMsgHeader::new(MessageType::Data, 1, 0, 0);
+        header.version = version;
+        header.checksum = 0.into();
+        let sum: u32 = header.as_bytes()[..protocol::HEADER_LEN - 2].iter().map(|byte| *byte as u32).sum();
+        header.checksum = (sum as u16).into();
+        header
+    };
+    assert!(checksummed(protocol::MIN_VERSION).is_valid());
+    assert!(checksummed(protocol::VERSION).is_valid());
+    assert!(!checksummed(protocol::MIN_VERSION - 1).is_valid());
+    let mut newer = FleetMsgHeader::new(MessageType::Data, 1, 0, 0);
+    newer.version = protocol::MAX_VERSION + 1;
+    assert!(!newer.with_checksum(newer.checksum_kind()).is_valid());
+    let crc = FleetMsgHeader::new(MessageType::Data, 1, 0, 0).with_checksum(protocol::HeaderChecksum::Crc32c);
+    assert_eq!(crc.version, protocol::CRC32C_VERSION);
+    assert!(crc.is_valid());
+
+    // Padding never rounds a datagram past one that fits the MTU
+    let buckets = PaddingBuckets::default();
+    assert_eq!(buckets.bucket_for(protocol::MTU_DATAGRAM_LEN), Some(protocol::MTU_DATAGRAM_LEN));
+    assert_eq!(buckets.bucket_for(protocol::MTU_DATAGRAM_LEN + 1), None);
+}
+
+#[test]
+fn test_header_fields_are_little_endian_on_the_wire() {
+    const WIRE: [u8; protocol::HEADER_LEN] = [
+        0xED, 0xFE, 0x00, 0x00, // magic
+        0x02, 0x02, // version, msg_type
+        0x02, 0x01, // sequence
+        0x6E, 0x5D, 0x4C, 0x3B, 0x2A, 0x1F, 0x06, 0x00, // timestamp
+        0x0D, 0x0C, 0x0B, 0x0A, // sender_id
+        0x05, 0x00, // payload_len
+        0xC6, 0x03, // checksum
+    ];
+
+    let mut header = FleetMsgHeader::new(MessageType::Data, 0x0A0B_0C0D, 0x0102, 5);
+    header.timestamp = 0x0006_1F2A_3B4C_5D6E.into();
+    let header = header.with_checksum(protocol::HeaderChecksum::Sum);
+    assert_eq!(header.as_bytes(), &WIRE);
+
+    let datagram = [&WIRE[..], b"hello"].concat();
+    let parsed = FleetMsgHeader::parse(&datagram).unwrap();
+    assert_eq!(parsed.magic.get(), protocol::MAGIC);
+    assert_eq!((parsed.sequence.get(), parsed.sender_id.get()), (0x0102, 0x0A0B_0C0D));
+    assert_eq!(parsed.timestamp_micros(), 0x0006_1F2A_3B4C_5D6E);
+    assert_eq!((parsed.payload_len.get(), parsed.checksum.get()), (5, 0x03C6));
+}
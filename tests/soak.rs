@@ -0,0 +1,115 @@
+//! Endurance run through the in-memory transport, checking that per-peer state
+//! and heap use level off instead of creeping up. Only built with `--features soak`.
+#![cfg(feature = "soak")]
+
+use fleetlink_transport::alloc_counter::{self, CountingAllocator};
+use fleetlink_transport::testing::{FaultInjector, MemoryBus, MockClock};
+use fleetlink_transport::net::transport::heartbeat_payload;
+use fleetlink_transport::{Membership, MembershipEvent, MessageType, PeerTable};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[global_allocator]
+static ALLOC: CountingAllocator = CountingAllocator;
+
+const MESSAGES: u64 = 10_000_000;
+/// Stop early (after the warm-up) rather than hold up CI on a slow machine
+const TIME_LIMIT: Duration = Duration::from_secs(300);
+const ACTIVE_SENDERS: u32 = 200;
+/// Simulated time per round, in which every active sender sends once
+const ROUND: Duration = Duration::from_millis(10);
+const ABSENCE_THRESHOLD: Duration = Duration::from_secs(5);
+/// Heap growth allowed between the end of the warm-up and the end of the run
+const HEAP_SLACK_BYTES: u64 = 64 * 1024;
+
+/// What a receiving node keeps per peer
+struct Node {
+    peers: PeerTable,
+    membership: Membership,
+    delivered: u64,
+}
+
+#[test]
+fn test_per_peer_state_stays_bounded() {
+    let clock = MockClock::new();
+    let node = Arc::new(Mutex::new(Node {
+        peers: PeerTable::new(),
+        membership: Membership::new(1, Duration::from_secs(3), clock.now()).with_absence_threshold(ABSENCE_THRESHOLD),
+        delivered: 0,
+    }));
+
+    // No corruption: each dropped frame is logged, and captured test output would count as heap growth
+    let faults = FaultInjector::new(2721).with_loss(0.01).with_duplication(0.01);
+    let mut bus = MemoryBus::new().with_faults(faults);
+    let (receiver, receiver_clock) = (node.clone(), clock.clone());
+    bus.subscribe(move |header, payload, addr| {
+        let mut node = receiver.lock().unwrap();
+        let now = receiver_clock.now();
+        node.delivered += 1;
+        node.membership.observe(&header, &payload, now);
+        // Not on the departure event: a duplicated goodbye would bring the peer back
+        if header.message_type() == MessageType::Goodbye {
+            node.peers.remove(header.sender_id.get());
+        } else {
+            node.peers.observe(&header, addr, now);
+        }
+    });
+
+    // Sender ids churn: each round the oldest sender leaves and a new one joins,
+    // and every 50th round one sender restarts with a new incarnation
+    let mut first_id = 2;
+    let mut incarnations: HashMap<u32, u64> = HashMap::new();
+    let (mut sent, mut round) = (0u64, 0u64);
+    let mut warm = None;
+    let started = Instant::now();
+
+    while sent < MESSAGES && (warm.is_none() || started.elapsed() < TIME_LIMIT) {
+        for sender_id in first_id..first_id + ACTIVE_SENDERS {
+            if (round + sender_id as u64).is_multiple_of(10) {
+                let incarnation = *incarnations.entry(sender_id).or_insert(round);
+                bus.send(sender_id, MessageType::Heartbeat, &heartbeat_payload(incarnation));
+            } else {
+                bus.send(sender_id, MessageType::Data, &round.to_le_bytes());
+            }
+        }
+        bus.send(first_id, MessageType::Goodbye, b"");
+        bus.restart(first_id);
+        incarnations.remove(&first_id);
+        first_id += 1;
+        if round.is_multiple_of(50) {
+            let restarted = first_id + (round / 50) as u32 % ACTIVE_SENDERS;
+            bus.restart(restarted);
+            incarnations.insert(restarted, round);
+        }
+        sent += ACTIVE_SENDERS as u64 + 1;
+        round += 1;
+
+        clock.advance(ROUND);
+        if round.is_multiple_of(100) {
+            let mut node = node.lock().unwrap();
+            let now = clock.now();
+            for event in node.membership.tick(now) {
+                if let MembershipEvent::PeerDown { sender_id } = event {
+                    node.peers.remove(sender_id);
+                }
+            }
+        }
+        if warm.is_none() && sent >= MESSAGES / 4 {
+            warm = Some(alloc_counter::live_bytes());
+        }
+    }
+
+    let node = node.lock().unwrap();
+    let grown = alloc_counter::live_bytes().saturating_sub(warm.unwrap());
+    println!("{} messages sent, {} delivered in {:?}; heap grew {} bytes after warm-up",
+             sent, node.delivered, started.elapsed(), grown);
+
+    // Everyone departed within the absence threshold may still be remembered, nobody older
+    let retained = (ABSENCE_THRESHOLD.as_millis() / ROUND.as_millis()) as usize + 100;
+    let bound = ACTIVE_SENDERS as usize + retained;
+    assert!(node.membership.members().count() <= bound,
+            "membership holds {} peers, expected at most {}", node.membership.members().count(), bound);
+    assert!(node.peers.len() <= bound, "peer table holds {} peers, expected at most {}", node.peers.len(), bound);
+    assert!(grown <= HEAP_SLACK_BYTES, "heap grew {} bytes after warm-up", grown);
+}